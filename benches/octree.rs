@@ -0,0 +1,114 @@
+use std::time::{Duration, Instant};
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rtvox::{
+    morton::MortonOctree,
+    octree::{Octree, VoxelTree},
+    world::scattered_positions,
+};
+
+const SIZES: [usize; 4] = [10_000, 100_000, 1_000_000, 10_000_000];
+const SEED: u64 = 0;
+
+fn build(positions: &[[i32; 3]]) -> Octree<i32> {
+    let mut tree = Octree::with_capacity(positions.len() * 2);
+    for &pos in positions {
+        tree.insert_leaf(1, pos);
+    }
+    tree
+}
+
+fn build_morton(positions: &[[i32; 3]]) -> MortonOctree<i32> {
+    let mut tree = MortonOctree::with_capacity(positions.len());
+    for &pos in positions {
+        tree.insert_leaf(1, pos);
+    }
+    tree
+}
+
+fn bulk_construction(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bulk_construction");
+    group.sample_size(10);
+    for size in SIZES {
+        let positions = scattered_positions(SEED, size);
+        group.bench_with_input(BenchmarkId::new("pointer", size), &positions, |b, p| {
+            b.iter(|| build(black_box(p)))
+        });
+        group.bench_with_input(BenchmarkId::new("morton", size), &positions, |b, p| {
+            b.iter(|| build_morton(black_box(p)))
+        });
+    }
+    group.finish();
+}
+
+fn insert_leaf(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert_leaf");
+    for size in SIZES {
+        let positions = scattered_positions(SEED, size * 2);
+        let (present, absent) = positions.split_at(size);
+        let mut tree = build(present);
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            // only the insert is timed, removing keeps the tree at `size`
+            b.iter_custom(|iters| {
+                let mut total = Duration::ZERO;
+                for i in 0..iters as usize {
+                    let pos = absent[i % absent.len()];
+                    let start = Instant::now();
+                    tree.insert_leaf(1, black_box(pos));
+                    total += start.elapsed();
+                    tree.remove_leaf(pos);
+                }
+                total
+            })
+        });
+    }
+    group.finish();
+}
+
+fn remove_leaf(c: &mut Criterion) {
+    let mut group = c.benchmark_group("remove_leaf");
+    for size in SIZES {
+        let positions = scattered_positions(SEED, size);
+        let mut tree = build(&positions);
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter_custom(|iters| {
+                let mut total = Duration::ZERO;
+                for i in 0..iters as usize {
+                    let pos = positions[i % positions.len()];
+                    let start = Instant::now();
+                    tree.remove_leaf(black_box(pos));
+                    total += start.elapsed();
+                    tree.insert_leaf(1, pos);
+                }
+                total
+            })
+        });
+    }
+    group.finish();
+}
+
+fn serialize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("serialize");
+    group.sample_size(10);
+    for size in SIZES {
+        let positions = scattered_positions(SEED, size);
+        let tree = build(&positions);
+        group.bench_with_input(BenchmarkId::new("pointer", size), &tree, |b, tree| {
+            b.iter(|| tree.serialize())
+        });
+        let tree = build_morton(&positions);
+        group.bench_with_input(BenchmarkId::new("morton", size), &tree, |b, tree| {
+            b.iter(|| tree.serialize())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bulk_construction,
+    insert_leaf,
+    remove_leaf,
+    serialize
+);
+criterion_main!(benches);
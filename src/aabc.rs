@@ -1,6 +1,6 @@
 use vecmath::Vector3;
 
-#[derive(PartialEq, Debug, Copy, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
 pub struct Aabc {
     pub origin: Vector3<i32>,
     pub size: u32,
@@ -31,6 +31,83 @@ impl Aabc {
         true
     }
 
+    pub fn intersects(&self, aabc: Aabc) -> bool {
+        for i in 0..3 {
+            if aabc.origin[i] >= self.origin[i] + self.size as i32
+                || aabc.origin[i] + aabc.size as i32 <= self.origin[i]
+            {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// The eighth of the cube at `idx`, in the order an octree's children
+    /// are serialized in.
+    pub fn octant(&self, idx: usize) -> Aabc {
+        const OFFSETS: [[i32; 3]; 8] = [
+            [1, 1, 1],
+            [1, 1, 0],
+            [0, 1, 0],
+            [0, 1, 1],
+            [1, 0, 1],
+            [1, 0, 0],
+            [0, 0, 0],
+            [0, 0, 1],
+        ];
+        let half = self.size / 2;
+        let mut origin = self.origin;
+        for i in 0..3 {
+            origin[i] += OFFSETS[idx][i] * half as i32;
+        }
+        Aabc::new(origin, half)
+    }
+
+    pub fn center(&self) -> Vector3<f32> {
+        let half = self.size as f32 / 2.0;
+        [0, 1, 2].map(|i| self.origin[i] as f32 + half)
+    }
+
+    /// Returns the 8 corners, the far ones being `origin + size` on their axes.
+    pub fn corners(&self) -> [Vector3<i32>; 8] {
+        let size = self.size as i32;
+        [0, 1, 2, 3, 4, 5, 6, 7].map(|i| {
+            [
+                self.origin[0] + (i & 1) * size,
+                self.origin[1] + (i >> 1 & 1) * size,
+                self.origin[2] + (i >> 2 & 1) * size,
+            ]
+        })
+    }
+
+    /// Intersects the ray `origin + t * dir` with the cube using the slab
+    /// method. Returns the distances along the ray at which it enters and exits
+    /// the cube, the entry being negative when the ray starts inside it, or
+    /// None if the cube is missed or behind the ray.
+    pub fn intersect_ray(&self, origin: Vector3<f32>, dir: Vector3<f32>) -> Option<(f32, f32)> {
+        let mut t_min = f32::NEG_INFINITY;
+        let mut t_max = f32::INFINITY;
+        for i in 0..3 {
+            let lo = self.origin[i] as f32;
+            let hi = lo + self.size as f32;
+            if dir[i] == 0.0 {
+                // parallel to the slab, so either always or never inside it
+                if origin[i] < lo || origin[i] > hi {
+                    return None;
+                }
+                continue;
+            }
+            let t1 = (lo - origin[i]) / dir[i];
+            let t2 = (hi - origin[i]) / dir[i];
+            t_min = t_min.max(t1.min(t2));
+            t_max = t_max.min(t1.max(t2));
+        }
+        if t_max < t_min || t_max < 0.0 {
+            return None;
+        }
+        Some((t_min, t_max))
+    }
+
     pub fn expand_towards(&self, target: Vector3<i32>) -> Aabc {
         if self.contains(target) {
             panic!(
@@ -38,16 +115,26 @@ impl Aabc {
                 target, self
             )
         }
-        let mut expanded = Aabc {
-            origin: self.origin,
-            size: self.size * 2,
-        };
+        let size = self.size as i64 * 2;
+        let mut origin = self.origin;
         for i in 0..3 {
-            if target[i] < expanded.origin[i] {
-                expanded.origin[i] -= self.size as i32;
+            let mut o = self.origin[i] as i64;
+            if (target[i] as i64) < o {
+                o -= self.size as i64;
             }
+            // the other methods compute origin + size as i32
+            if size > i32::MAX as i64 || o < i32::MIN as i64 || o + size > i32::MAX as i64 {
+                panic!(
+                    "cannot expand aabc: {:?} towards target: {:?} past the i32 range",
+                    self, target
+                )
+            }
+            origin[i] = o as i32;
+        }
+        Aabc {
+            origin,
+            size: size as u32,
         }
-        expanded
     }
 
     pub fn shrink_towards(&self, target: Vector3<i32>) -> Aabc {
@@ -74,6 +161,8 @@ impl Aabc {
 #[cfg(test)]
 
 mod tests {
+    use proptest::prelude::*;
+
     use super::Aabc;
 
     #[test]
@@ -137,6 +226,25 @@ mod tests {
         assert_eq!(expect, result)
     }
 
+    #[test]
+    fn expand_towards_large_coordinates() {
+        let mut aabc = Aabc::new([0, 0, 0], 1);
+        for target in [[1 << 20, 0, 0], [0, -(1 << 20), 0], [0, 0, (1 << 20) - 1]] {
+            while !aabc.contains(target) {
+                aabc = aabc.expand_towards(target);
+            }
+        }
+        assert_eq!(1 << 22, aabc.size);
+        assert!(aabc.contains([0, 0, 0]));
+    }
+
+    #[test]
+    #[should_panic]
+    fn expand_towards_past_i32_panics() {
+        let aabc = Aabc::new([-(1 << 29); 3], 1 << 30);
+        _ = aabc.expand_towards([i32::MAX, 0, 0]);
+    }
+
     #[test]
     #[should_panic]
     fn shrink_towards_panics() {
@@ -175,6 +283,14 @@ mod tests {
         assert_eq!(expect, result)
     }
 
+    #[test]
+    fn intersects_overlapping_and_touching() {
+        let aabc = Aabc::new([0, 0, 0], 4);
+        assert!(aabc.intersects(Aabc::new([3, 3, 3], 4)));
+        assert!(!aabc.intersects(Aabc::new([4, 0, 0], 4))); // touching faces
+        assert!(!aabc.intersects(Aabc::new([-2, 0, 0], 2)));
+    }
+
     #[test]
     fn contains_aabc_self() {
         let aabc = Aabc {
@@ -235,4 +351,94 @@ mod tests {
         };
         assert!(aabc.contains_aabc(target))
     }
+
+    #[test]
+    fn center_of_cube() {
+        assert_eq!([1.0, 2.0, -3.0], Aabc::new([0, 1, -4], 2).center());
+        assert_eq!([0.5, 0.5, 0.5], Aabc::new([0, 0, 0], 1).center());
+    }
+
+    #[test]
+    fn corners_span_cube() {
+        let corners = Aabc::new([1, 2, 3], 2).corners();
+        assert_eq!([1, 2, 3], corners[0]);
+        assert_eq!([3, 2, 3], corners[1]);
+        assert_eq!([3, 4, 5], corners[7]);
+        let unique: std::collections::HashSet<_> = corners.iter().collect();
+        assert_eq!(8, unique.len());
+    }
+
+    #[test]
+    fn intersect_ray_through_cube() {
+        let aabc = Aabc::new([0, 0, 0], 2);
+        assert_eq!(
+            Some((1.0, 3.0)),
+            aabc.intersect_ray([-1.0, 1.0, 1.0], [1.0, 0.0, 0.0])
+        );
+        // starting inside
+        assert_eq!(
+            Some((-1.0, 1.0)),
+            aabc.intersect_ray([1.0, 1.0, 1.0], [0.0, 0.0, 1.0])
+        );
+    }
+
+    #[test]
+    fn intersect_ray_misses() {
+        let aabc = Aabc::new([0, 0, 0], 2);
+        // parallel to a slab it's outside of
+        assert_eq!(None, aabc.intersect_ray([-1.0, 3.0, 1.0], [1.0, 0.0, 0.0]));
+        // pointing away
+        assert_eq!(None, aabc.intersect_ray([-1.0, 1.0, 1.0], [-1.0, 0.0, 0.0]));
+        assert_eq!(
+            None,
+            aabc.intersect_ray([-1.0, -1.0, 1.0], [1.0, -1.0, 0.0])
+        );
+    }
+
+    fn any_aabc() -> impl Strategy<Value = Aabc> {
+        (prop::array::uniform3(-64..64), 0..6u32).prop_map(|(origin, e)| Aabc::new(origin, 1 << e))
+    }
+
+    proptest! {
+        #[test]
+        fn expand_contains_original(
+            aabc in any_aabc(),
+            target in prop::array::uniform3(-256..256),
+        ) {
+            prop_assume!(!aabc.contains(target));
+            let expanded = aabc.expand_towards(target);
+            prop_assert_eq!(aabc.size * 2, expanded.size);
+            prop_assert!(expanded.contains_aabc(aabc));
+        }
+
+        #[test]
+        fn shrink_keeps_target(aabc in any_aabc(), offset in prop::array::uniform3(0..32)) {
+            prop_assume!(aabc.size >= 2);
+            let target = [0, 1, 2].map(|i| aabc.origin[i] + offset[i] % aabc.size as i32);
+            let shrunken = aabc.shrink_towards(target);
+            prop_assert_eq!(aabc.size / 2, shrunken.size);
+            prop_assert!(shrunken.contains(target));
+            prop_assert!(aabc.contains_aabc(shrunken));
+        }
+
+        #[test]
+        fn ray_towards_center_hits(
+            aabc in any_aabc(),
+            origin in prop::array::uniform3(-128.0f32..128.0),
+        ) {
+            let center = aabc.center();
+            let dir = [0, 1, 2].map(|i| center[i] - origin[i]);
+            prop_assume!(dir.iter().any(|&d| d != 0.0));
+            let (t_min, t_max) = aabc.intersect_ray(origin, dir).unwrap();
+            prop_assert!(t_min <= 1.0 && 1.0 <= t_max);
+        }
+
+        #[test]
+        fn intersects_is_symmetric(a in any_aabc(), b in any_aabc()) {
+            prop_assert_eq!(a.intersects(b), b.intersects(a));
+            if a.contains_aabc(b) {
+                prop_assert!(a.intersects(b));
+            }
+        }
+    }
 }
@@ -0,0 +1,72 @@
+/// Counts the path traced samples averaged into every pixel. Each frame adds
+/// one until `max_samples`, starting over whenever anything seen changes.
+#[derive(Clone, Debug)]
+pub struct Accumulation {
+    samples: u32,
+    max_samples: u32,
+    // what the samples so far were traced with
+    view: Vec<u8>,
+}
+
+impl Accumulation {
+    pub fn new(max_samples: u32) -> Self {
+        Accumulation {
+            samples: 0,
+            max_samples,
+            view: Vec::new(),
+        }
+    }
+
+    /// Starts over with the next frame, for changes `view` doesn't cover.
+    pub fn reset(&mut self) {
+        self.samples = 0;
+    }
+
+    /// Returns the index of the sample the next frame adds, starting over
+    /// when `view`, the bytes of everything affecting the image, differs from
+    /// the last frame's. None once the image has converged, when the average
+    /// is only shown.
+    pub fn next_sample(&mut self, view: &[u8]) -> Option<u32> {
+        if self.view != view {
+            self.view = view.to_vec();
+            self.samples = 0;
+        }
+        if self.samples == self.max_samples {
+            return None;
+        }
+        self.samples += 1;
+        Some(self.samples - 1)
+    }
+
+    /// Samples in each pixel after the last `next_sample`.
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_add_up_until_converged() {
+        let mut accumulation = Accumulation::new(3);
+        assert_eq!(Some(0), accumulation.next_sample(&[1]));
+        assert_eq!(Some(1), accumulation.next_sample(&[1]));
+        assert_eq!(Some(2), accumulation.next_sample(&[1]));
+        assert_eq!(None, accumulation.next_sample(&[1]));
+        assert_eq!(3, accumulation.samples());
+    }
+
+    #[test]
+    fn changes_start_over() {
+        let mut accumulation = Accumulation::new(8);
+        accumulation.next_sample(&[1]);
+        accumulation.next_sample(&[1]);
+        assert_eq!(Some(0), accumulation.next_sample(&[2]));
+        accumulation.next_sample(&[2]);
+        accumulation.reset();
+        assert_eq!(0, accumulation.samples());
+        assert_eq!(Some(0), accumulation.next_sample(&[2]));
+    }
+}
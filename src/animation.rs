@@ -0,0 +1,202 @@
+use std::{fs, io, path::Path, time::Duration};
+
+use vecmath::Vector3;
+
+use crate::{
+    io::{import, region::invalid},
+    materials::MaterialId,
+    octree::{MergePolicy, Octree},
+    prefab::VoxelPrefab,
+};
+
+pub const DEFAULT_FPS: f32 = 10.0;
+
+/// Frames of voxels played one after the other over the world, which is left
+/// as it is. Only the scene, the world with the current frame in it, changes:
+/// from one frame to the next it's patched with the voxels that differ.
+pub struct Animation {
+    frames: Vec<Octree<MaterialId>>,
+    fps: f32,
+    current: usize,
+    // seconds since the current frame was shown
+    elapsed: f32,
+    scene: Octree<MaterialId>,
+}
+
+impl Animation {
+    /// Places the frames with their minimum corner at `origin`. None without
+    /// frames.
+    pub fn new(
+        frames: Vec<VoxelPrefab>,
+        origin: Vector3<i32>,
+        fps: f32,
+        world: &Octree<MaterialId>,
+    ) -> Option<Self> {
+        if frames.is_empty() {
+            return None;
+        }
+        let frames = frames
+            .iter()
+            .map(|frame| {
+                let mut tree = Octree::new();
+                for &(pos, material) in &frame.voxels {
+                    tree.insert_leaf(material, vecmath::vec3_add(origin, pos));
+                }
+                tree
+            })
+            .collect();
+        let mut animation = Animation {
+            frames,
+            fps,
+            current: 0,
+            elapsed: 0.0,
+            scene: Octree::new(),
+        };
+        animation.rebuild(world);
+        Some(animation)
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn fps(&self) -> f32 {
+        self.fps
+    }
+
+    /// Frames shown per second, 0 pauses on the current one.
+    pub fn set_fps(&mut self, fps: f32) {
+        self.fps = fps.max(0.0);
+    }
+
+    /// The world with the current frame in it, to draw instead of the world.
+    pub fn scene(&self) -> &Octree<MaterialId> {
+        &self.scene
+    }
+
+    /// Puts the current frame into a copy of `world`, after the world changed.
+    pub fn rebuild(&mut self, world: &Octree<MaterialId>) {
+        self.scene = world.clone();
+        self.scene
+            .merge(&self.frames[self.current], MergePolicy::Overwrite);
+    }
+
+    /// Moves time on by `dt`, skipping frames if it's more than a frame long.
+    /// Returns whether another frame is shown, in which case the scene was
+    /// patched with it. Voxels of the world under the frame before show again.
+    pub fn advance(&mut self, dt: Duration, world: &Octree<MaterialId>) -> bool {
+        if self.fps <= 0.0 {
+            return false;
+        }
+        self.elapsed += dt.as_secs_f32();
+        let steps = (self.elapsed * self.fps) as usize;
+        if steps == 0 {
+            return false;
+        }
+        self.elapsed -= steps as f32 / self.fps;
+        let next = (self.current + steps) % self.frames.len();
+        if next == self.current {
+            return false;
+        }
+        for change in self.frames[self.current].diff(&self.frames[next]) {
+            let (pos, value) = change.result();
+            if self.scene.get_leaf(pos).is_some() {
+                self.scene.remove_leaf(pos);
+            }
+            if let Some(value) = value.or_else(|| world.get_leaf(pos)) {
+                self.scene.insert_leaf(value, pos);
+            }
+        }
+        self.current = next;
+        true
+    }
+}
+
+/// Reads the frames of an animation: the models of a .vox file, or those of
+/// every .vox file in a directory, ordered by file name.
+pub fn load_frames(path: &Path) -> io::Result<Vec<VoxelPrefab>> {
+    if !path.is_dir() {
+        return import::read_vox(&fs::read(path)?);
+    }
+    let mut files = fs::read_dir(path)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    files.retain(|file| file.extension().is_some_and(|ext| ext == "vox"));
+    files.sort();
+    if files.is_empty() {
+        return Err(invalid("no .vox files"));
+    }
+    let mut frames = Vec::new();
+    for file in files {
+        frames.extend(import::read_vox(&fs::read(file)?)?);
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames() -> Vec<VoxelPrefab> {
+        vec![
+            VoxelPrefab::new([0; 3], [2, 1, 1], [([0, 0, 0], 1)]),
+            VoxelPrefab::new([0; 3], [2, 1, 1], [([1, 0, 0], 2)]),
+        ]
+    }
+
+    #[test]
+    fn no_frames_no_animation() {
+        assert!(Animation::new(Vec::new(), [0; 3], DEFAULT_FPS, &Octree::new()).is_none());
+    }
+
+    #[test]
+    fn frames_are_placed_over_the_world() {
+        let mut world = Octree::new();
+        world.insert_leaf(7, [10, 0, 0]);
+        world.insert_leaf(7, [0, 5, 0]);
+        let animation = Animation::new(frames(), [10, 0, 0], DEFAULT_FPS, &world).unwrap();
+        assert_eq!(Some(1), animation.scene().get_leaf([10, 0, 0]));
+        assert_eq!(Some(7), animation.scene().get_leaf([0, 5, 0]));
+        assert_eq!(None, animation.scene().get_leaf([11, 0, 0]));
+    }
+
+    #[test]
+    fn advance_patches_the_scene() {
+        let mut world = Octree::new();
+        world.insert_leaf(7, [0, 0, 0]);
+        let mut animation = Animation::new(frames(), [0; 3], 10.0, &world).unwrap();
+        assert!(!animation.advance(Duration::from_millis(50), &world));
+        assert!(animation.advance(Duration::from_millis(60), &world));
+        assert_eq!(1, animation.current());
+        // the world's voxel is back where the first frame was
+        assert_eq!(Some(7), animation.scene().get_leaf([0, 0, 0]));
+        assert_eq!(Some(2), animation.scene().get_leaf([1, 0, 0]));
+        // and the first frame loops back
+        assert!(animation.advance(Duration::from_millis(100), &world));
+        assert_eq!(Some(1), animation.scene().get_leaf([0, 0, 0]));
+        assert_eq!(None, animation.scene().get_leaf([1, 0, 0]));
+    }
+
+    #[test]
+    fn slow_frames_skip_ahead() {
+        let three = [frames(), frames()[..1].to_vec()].concat();
+        let mut animation = Animation::new(three, [0; 3], 10.0, &Octree::new()).unwrap();
+        // 2.5 frames in
+        assert!(animation.advance(Duration::from_millis(250), &Octree::new()));
+        assert_eq!(2, animation.current());
+        assert_eq!(Some(1), animation.scene().get_leaf([0, 0, 0]));
+        assert_eq!(None, animation.scene().get_leaf([1, 0, 0]));
+    }
+
+    #[test]
+    fn zero_fps_pauses() {
+        let mut animation = Animation::new(frames(), [0; 3], 10.0, &Octree::new()).unwrap();
+        animation.set_fps(0.0);
+        assert!(!animation.advance(Duration::from_secs(1), &Octree::new()));
+        assert_eq!(0, animation.current());
+    }
+}
@@ -0,0 +1,77 @@
+/// What the event loop does with input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AppState {
+    /// Input moves the camera and edits the world.
+    Running,
+    /// Nothing reacts to input, entered when the window loses focus.
+    Paused,
+    /// Only settings can be changed.
+    Menu,
+    /// Typed text goes to the console.
+    Console,
+}
+
+impl AppState {
+    /// State after Escape is pressed: it opens the menu, or closes it and
+    /// goes back to running.
+    pub fn escape(self) -> Self {
+        match self {
+            AppState::Running | AppState::Paused => AppState::Menu,
+            AppState::Menu | AppState::Console => AppState::Running,
+        }
+    }
+
+    /// State after the console key is pressed: it opens the console, or
+    /// closes it and goes back to running.
+    pub fn toggle_console(self) -> Self {
+        match self {
+            AppState::Console => AppState::Running,
+            _ => AppState::Console,
+        }
+    }
+
+    /// State after the window gained or lost focus. The menu and console stay
+    /// open.
+    pub fn focus_changed(self, focused: bool) -> Self {
+        match (self, focused) {
+            (AppState::Running, false) => AppState::Paused,
+            (AppState::Paused, true) => AppState::Running,
+            (state, _) => state,
+        }
+    }
+
+    /// Whether the camera moves and the world can be edited.
+    pub fn is_running(self) -> bool {
+        self == AppState::Running
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_toggles_menu() {
+        assert_eq!(AppState::Menu, AppState::Running.escape());
+        assert_eq!(AppState::Running, AppState::Menu.escape());
+        assert_eq!(AppState::Menu, AppState::Paused.escape());
+        assert_eq!(AppState::Running, AppState::Console.escape());
+    }
+
+    #[test]
+    fn console_key_toggles_console() {
+        assert_eq!(AppState::Console, AppState::Running.toggle_console());
+        assert_eq!(AppState::Console, AppState::Menu.toggle_console());
+        assert_eq!(AppState::Running, AppState::Console.toggle_console());
+        assert_eq!(AppState::Console, AppState::Console.focus_changed(false));
+    }
+
+    #[test]
+    fn focus_pauses_only_running() {
+        assert_eq!(AppState::Paused, AppState::Running.focus_changed(false));
+        assert_eq!(AppState::Running, AppState::Paused.focus_changed(true));
+        assert_eq!(AppState::Menu, AppState::Menu.focus_changed(false));
+        assert_eq!(AppState::Menu, AppState::Menu.focus_changed(true));
+        assert_eq!(AppState::Running, AppState::Running.focus_changed(true));
+    }
+}
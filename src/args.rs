@@ -0,0 +1,436 @@
+use std::{path::PathBuf, str::FromStr};
+
+use crate::{
+    benchmark::BenchmarkConfig,
+    demo_world::DemoWorld,
+    net::server::ServeConfig,
+    softrender::SnapshotConfig,
+    stress::StressConfig,
+    worldgen::{caves::CaveConfig, WorldBounds},
+};
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run(Backend),
+    Benchmark(Backend, BenchmarkConfig),
+    Stress(StressConfig),
+    /// Runs the server of a shared world without a window.
+    Serve(ServeConfig),
+    /// Draws the benchmark world to a PNG on the CPU, without Vulkan.
+    Snapshot(SnapshotConfig),
+}
+
+/// Which renderer draws the world.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    /// The compute shader ray tracer, `Graphics`.
+    RayTrace,
+    /// Greedy meshed chunks drawn with a graphics pipeline, `RasterRenderer`.
+    Raster,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Args {
+    pub command: Command,
+    /// Directory to record the rendered frames to from the start.
+    pub record: Option<PathBuf>,
+    /// Seed of the generated world, random when not given. The benchmark
+    /// also takes it from `BenchmarkConfig::seed`.
+    pub seed: Option<u64>,
+    pub caves: CaveConfig,
+    pub bounds: WorldBounds,
+    /// Directory edited chunks are saved to and loaded from. A seed saved
+    /// there takes precedence over `seed`.
+    pub world: Option<PathBuf>,
+    /// Address of the server of a shared world to join instead of generating
+    /// one.
+    pub connect: Option<String>,
+    /// Frame rate the ray tracer lowers or raises its quality to hold.
+    pub target_fps: Option<f32>,
+    /// Frames the ray tracer records ahead of the GPU, see `FramesInFlight`.
+    pub frames_in_flight: Option<usize>,
+    /// Device memory in MiB to stay under, by evicting chunks or streaming
+    /// less of the world, see `gpu_memory`.
+    pub memory_budget: Option<u64>,
+    /// Hand made scene to start in instead of generating a world.
+    pub demo_world: Option<DemoWorld>,
+    /// Enables the Vulkan validation layer, logging what it finds.
+    pub validation: bool,
+    /// File to write the log to as well as stderr.
+    pub log_file: Option<PathBuf>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ArgsError {
+    UnknownArgument(String),
+    MissingValue(String),
+    InvalidValue(String, String),
+}
+
+impl Args {
+    pub fn parse() -> Result<Self, ArgsError> {
+        Self::parse_from(std::env::args().skip(1))
+    }
+
+    pub fn parse_from<I: IntoIterator<Item = String>>(args: I) -> Result<Self, ArgsError> {
+        let mut args = args.into_iter().peekable();
+        let mut record = None;
+        let mut seed = None;
+        let mut caves = CaveConfig::default();
+        let mut bounds = WorldBounds::default();
+        let mut world = None;
+        let mut connect = None;
+        let mut target_fps = None;
+        let mut frames_in_flight = None;
+        let mut memory_budget = None;
+        let mut demo_world = None;
+        let mut validation = false;
+        let mut log_file = None;
+        let command = match args.peek().map(String::as_str) {
+            Some("stress") => {
+                args.next();
+                let mut config = StressConfig::default();
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--iterations" => config.iterations = parse_value(&arg, args.next())?,
+                        "--edits" => config.edits_per_iteration = parse_value(&arg, args.next())?,
+                        "--fill-size" => config.fill_size = parse_value(&arg, args.next())?,
+                        "--seed" => config.seed = parse_value(&arg, args.next())?,
+                        _ => return Err(ArgsError::UnknownArgument(arg)),
+                    }
+                }
+                Command::Stress(config)
+            }
+            Some("serve") => {
+                args.next();
+                let mut config = ServeConfig::default();
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--port" => config.port = parse_value(&arg, args.next())?,
+                        "--log-file" => log_file = Some(parse_value(&arg, args.next())?),
+                        "--seed" => config.seed = Some(parse_value(&arg, args.next())?),
+                        "--cave-density" => caves.density = parse_value(&arg, args.next())?,
+                        "--cave-scale" => caves.scale = parse_value(&arg, args.next())?,
+                        _ => return Err(ArgsError::UnknownArgument(arg)),
+                    }
+                }
+                Command::Serve(config)
+            }
+            Some("snapshot") => {
+                args.next();
+                let mut config = SnapshotConfig::default();
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--out" => config.out = parse_value(&arg, args.next())?,
+                        "--width" => config.width = parse_value(&arg, args.next())?,
+                        "--height" => config.height = parse_value(&arg, args.next())?,
+                        "--seed" => config.seed = parse_value(&arg, args.next())?,
+                        "--demo-world" => config.demo_world = Some(parse_value(&arg, args.next())?),
+                        _ => return Err(ArgsError::UnknownArgument(arg)),
+                    }
+                }
+                Command::Snapshot(config)
+            }
+            _ => {
+                let mut backend = Backend::RayTrace;
+                let mut benchmark = false;
+                let mut config = BenchmarkConfig::default();
+                // benchmark options given without --benchmark
+                let mut stray = None;
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--raster" => backend = Backend::Raster,
+                        "--benchmark" => benchmark = true,
+                        "--validation" => validation = true,
+                        "--frames" => config.frames = parse_value(&arg, args.next())?,
+                        "--seed" => {
+                            config.seed = parse_value(&arg, args.next())?;
+                            seed = Some(config.seed)
+                        }
+                        "--record" => record = Some(parse_value(&arg, args.next())?),
+                        "--log-file" => log_file = Some(parse_value(&arg, args.next())?),
+                        "--world" => world = Some(parse_value(&arg, args.next())?),
+                        "--connect" => connect = Some(parse_value(&arg, args.next())?),
+                        "--target-fps" => target_fps = Some(parse_value(&arg, args.next())?),
+                        "--frames-in-flight" => {
+                            frames_in_flight = Some(parse_value(&arg, args.next())?)
+                        }
+                        "--memory-budget" => memory_budget = Some(parse_value(&arg, args.next())?),
+                        "--demo-world" => demo_world = Some(parse_value(&arg, args.next())?),
+                        "--cave-density" => caves.density = parse_value(&arg, args.next())?,
+                        "--cave-scale" => caves.scale = parse_value(&arg, args.next())?,
+                        "--world-radius" => bounds.radius = Some(parse_value(&arg, args.next())?),
+                        "--world-bottom" => bounds.bottom = Some(parse_value(&arg, args.next())?),
+                        "--world-top" => bounds.top = Some(parse_value(&arg, args.next())?),
+                        _ => return Err(ArgsError::UnknownArgument(arg)),
+                    }
+                    if arg == "--frames" {
+                        stray.get_or_insert(arg);
+                    }
+                }
+                if benchmark {
+                    Command::Benchmark(backend, config)
+                } else if let Some(arg) = stray {
+                    return Err(ArgsError::UnknownArgument(arg));
+                } else {
+                    Command::Run(backend)
+                }
+            }
+        };
+        Ok(Args {
+            command,
+            record,
+            seed,
+            caves,
+            bounds,
+            world,
+            connect,
+            target_fps,
+            frames_in_flight,
+            memory_budget,
+            demo_world,
+            validation,
+            log_file,
+        })
+    }
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: Option<String>) -> Result<T, ArgsError> {
+    match value {
+        None => Err(ArgsError::MissingValue(flag.to_string())),
+        Some(v) => v
+            .parse()
+            .map_err(|_| ArgsError::InvalidValue(flag.to_string(), v)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, ArgsError> {
+        Args::parse_from(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn no_args_runs() {
+        assert_eq!(Command::Run(Backend::RayTrace), parse(&[]).unwrap().command);
+    }
+
+    #[test]
+    fn raster_flag_picks_backend() {
+        assert_eq!(
+            Command::Run(Backend::Raster),
+            parse(&["--raster"]).unwrap().command
+        );
+    }
+
+    #[test]
+    fn benchmark_with_options() {
+        let expected = BenchmarkConfig {
+            frames: 50,
+            seed: 7,
+        };
+        assert_eq!(
+            Command::Benchmark(Backend::Raster, expected),
+            parse(&["--frames", "50", "--benchmark", "--raster", "--seed", "7"])
+                .unwrap()
+                .command
+        );
+    }
+
+    #[test]
+    fn benchmark_options_need_benchmark() {
+        assert_eq!(
+            Err(ArgsError::UnknownArgument("--frames".to_string())),
+            parse(&["--frames", "50"])
+        );
+    }
+
+    #[test]
+    fn seed_picks_world() {
+        let args = parse(&["--seed", "123"]).unwrap();
+        assert_eq!(Command::Run(Backend::RayTrace), args.command);
+        assert_eq!(Some(123), args.seed);
+        assert_eq!(None, parse(&[]).unwrap().seed);
+    }
+
+    #[test]
+    fn cave_options() {
+        let args = parse(&["--cave-density", "0.5", "--cave-scale", "12"]).unwrap();
+        let expected = CaveConfig {
+            density: 0.5,
+            scale: 12.0,
+            ..Default::default()
+        };
+        assert_eq!(expected, args.caves);
+        assert_eq!(CaveConfig::default(), parse(&[]).unwrap().caves);
+    }
+
+    #[test]
+    fn world_bounds() {
+        let args = parse(&["--world-radius", "8", "--world-bottom", "-2"]).unwrap();
+        let expected = WorldBounds {
+            radius: Some(8),
+            bottom: Some(-2),
+            top: None,
+        };
+        assert_eq!(expected, args.bounds);
+    }
+
+    #[test]
+    fn record_takes_directory() {
+        let args = parse(&["--record", "frames/"]).unwrap();
+        assert_eq!(Command::Run(Backend::RayTrace), args.command);
+        assert_eq!(Some(PathBuf::from("frames/")), args.record);
+    }
+
+    #[test]
+    fn world_takes_directory() {
+        let args = parse(&["--world", "saves/a"]).unwrap();
+        assert_eq!(Some(PathBuf::from("saves/a")), args.world);
+        assert_eq!(None, parse(&[]).unwrap().world);
+    }
+
+    #[test]
+    fn serve_with_options() {
+        let args = parse(&["serve", "--port", "9000", "--cave-scale", "4"]).unwrap();
+        let expected = ServeConfig {
+            port: 9000,
+            seed: None,
+        };
+        assert_eq!(Command::Serve(expected), args.command);
+        assert_eq!(4.0, args.caves.scale);
+        assert_eq!(
+            Err(ArgsError::UnknownArgument("--raster".to_string())),
+            parse(&["serve", "--raster"])
+        );
+    }
+
+    #[test]
+    fn target_fps_takes_rate() {
+        assert_eq!(
+            Some(30.0),
+            parse(&["--target-fps", "30"]).unwrap().target_fps
+        );
+        assert_eq!(None, parse(&[]).unwrap().target_fps);
+    }
+
+    #[test]
+    fn frames_in_flight_takes_count() {
+        assert_eq!(
+            Some(3),
+            parse(&["--frames-in-flight", "3"])
+                .unwrap()
+                .frames_in_flight
+        );
+        assert_eq!(None, parse(&[]).unwrap().frames_in_flight);
+    }
+
+    #[test]
+    fn demo_world_takes_name() {
+        let args = parse(&["--demo-world", "sphere", "--benchmark"]).unwrap();
+        assert_eq!(Some(DemoWorld::Sphere), args.demo_world);
+        assert_eq!(
+            Err(ArgsError::InvalidValue(
+                "--demo-world".to_string(),
+                "noise".to_string()
+            )),
+            parse(&["--demo-world", "noise"])
+        );
+    }
+
+    #[test]
+    fn memory_budget_takes_mebibytes() {
+        assert_eq!(
+            Some(2048),
+            parse(&["--memory-budget", "2048"]).unwrap().memory_budget
+        );
+        assert_eq!(None, parse(&[]).unwrap().memory_budget);
+    }
+
+    #[test]
+    fn validation_flag() {
+        assert!(parse(&["--validation", "--raster"]).unwrap().validation);
+        assert!(!parse(&[]).unwrap().validation);
+    }
+
+    #[test]
+    fn log_file_takes_path() {
+        let args = parse(&["--log-file", "rtvox.log"]).unwrap();
+        assert_eq!(Some(PathBuf::from("rtvox.log")), args.log_file);
+        let args = parse(&["serve", "--log-file", "server.log"]).unwrap();
+        assert_eq!(Some(PathBuf::from("server.log")), args.log_file);
+    }
+
+    #[test]
+    fn connect_takes_address() {
+        let args = parse(&["--connect", "localhost:7878"]).unwrap();
+        assert_eq!(Some("localhost:7878".to_string()), args.connect);
+    }
+
+    #[test]
+    fn stress_defaults() {
+        assert_eq!(
+            Command::Stress(StressConfig::default()),
+            parse(&["stress"]).unwrap().command
+        );
+    }
+
+    #[test]
+    fn snapshot_with_options() {
+        let expected = SnapshotConfig {
+            out: PathBuf::from("view.png"),
+            width: 64,
+            ..Default::default()
+        };
+        assert_eq!(
+            Command::Snapshot(expected),
+            parse(&["snapshot", "--width", "64", "--out", "view.png"])
+                .unwrap()
+                .command
+        );
+    }
+
+    #[test]
+    fn stress_with_options() {
+        let expected = StressConfig {
+            iterations: 10,
+            seed: 42,
+            ..Default::default()
+        };
+        assert_eq!(
+            Command::Stress(expected),
+            parse(&["stress", "--iterations", "10", "--seed", "42"])
+                .unwrap()
+                .command
+        );
+    }
+
+    #[test]
+    fn missing_value_errors() {
+        assert_eq!(
+            Err(ArgsError::MissingValue("--seed".to_string())),
+            parse(&["stress", "--seed"])
+        );
+    }
+
+    #[test]
+    fn invalid_value_errors() {
+        assert_eq!(
+            Err(ArgsError::InvalidValue(
+                "--iterations".to_string(),
+                "many".to_string()
+            )),
+            parse(&["stress", "--iterations", "many"])
+        );
+    }
+
+    #[test]
+    fn unknown_argument_errors() {
+        assert_eq!(
+            Err(ArgsError::UnknownArgument("--bogus".to_string())),
+            parse(&["--bogus"])
+        );
+    }
+}
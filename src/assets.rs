@@ -0,0 +1,236 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fs::File,
+    hash::{Hash, Hasher},
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+};
+
+use crate::io::{frames, region::invalid};
+
+/// Index of a texture in the texture array, each taking `FACES` layers.
+pub type TextureId = u32;
+
+/// Faces of a cube map texture, side by side in its image: +x, -x, +y, -y,
+/// +z and -z.
+pub const FACES: u32 = 6;
+
+/// The cube map textures of the materials. Textures keep the index they're
+/// given for good, and loading the same file or the same pixels again gives
+/// back the texture already there rather than a copy.
+#[derive(Default)]
+pub struct TextureManager {
+    // every texture's faces are this many texels square
+    face_size: u32,
+    // the faces of each texture one after the other, row by row
+    textures: Vec<Vec<u8>>,
+    by_hash: HashMap<u64, Vec<TextureId>>,
+    by_path: HashMap<PathBuf, TextureId>,
+    // the first textures, which the GPU has
+    resident: usize,
+}
+
+impl TextureManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Texels across each face, 0 until there's a texture.
+    pub fn face_size(&self) -> u32 {
+        self.face_size
+    }
+
+    pub fn len(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.textures.is_empty()
+    }
+
+    /// Loads the cube map in the PNG at `path`, its faces side by side in a
+    /// row, unless it was loaded before.
+    pub fn load(&mut self, path: &Path) -> io::Result<TextureId> {
+        if let Some(&id) = self.by_path.get(path) {
+            return Ok(id);
+        }
+        let file = BufReader::new(File::open(path)?);
+        let (size, pixels) = frames::read_png(file).map_err(|e| invalid(&e.to_string()))?;
+        // checked first, add_atlas would keep every row
+        if size[0] != size[1].saturating_mul(FACES) {
+            return Err(invalid("expected the six faces of one cube map in a row"));
+        }
+        let id = self.add_atlas(size, &pixels)?[0];
+        self.by_path.insert(path.to_path_buf(), id);
+        Ok(id)
+    }
+
+    /// Adds the cube maps in the rows of an RGBA8 image `size` texels across
+    /// and down, each of their faces as wide as a sixth of the image.
+    pub fn add_atlas(&mut self, size: [u32; 2], pixels: &[u8]) -> io::Result<Vec<TextureId>> {
+        let [width, height] = size;
+        let len = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|texels| texels.checked_mul(4));
+        if len != Some(pixels.len()) {
+            return Err(invalid("pixels don't fill the atlas"));
+        }
+        let face_size = width / FACES;
+        if face_size == 0 || width % FACES != 0 || height % face_size != 0 {
+            return Err(invalid("cube maps need six square faces in a row"));
+        }
+        let row_bytes = (width * 4) as usize;
+        let face_bytes = (face_size * 4) as usize;
+        let mut ids = Vec::new();
+        for cube in pixels.chunks(row_bytes * face_size as usize) {
+            let mut faces = Vec::with_capacity(cube.len());
+            for face in 0..FACES as usize {
+                for row in cube.chunks(row_bytes) {
+                    faces.extend_from_slice(&row[face * face_bytes..(face + 1) * face_bytes]);
+                }
+            }
+            ids.push(self.add(face_size, faces)?);
+        }
+        Ok(ids)
+    }
+
+    /// Adds a cube map from its RGBA8 faces one after the other, or finds the
+    /// same one added before. Every texture has faces of the same size.
+    pub fn add(&mut self, face_size: u32, faces: Vec<u8>) -> io::Result<TextureId> {
+        if faces.len() != (FACES * face_size * face_size * 4) as usize {
+            return Err(invalid("cube map faces of the wrong size"));
+        }
+        if !self.is_empty() && face_size != self.face_size {
+            return Err(invalid(&format!(
+                "cube map faces are {} texels across, not {}",
+                face_size, self.face_size
+            )));
+        }
+        let mut hasher = DefaultHasher::new();
+        faces.hash(&mut hasher);
+        let same = self.by_hash.entry(hasher.finish()).or_default();
+        // a hash could be shared by different faces
+        if let Some(&id) = same.iter().find(|&&id| self.textures[id as usize] == faces) {
+            return Ok(id);
+        }
+        let id = self.textures.len() as TextureId;
+        same.push(id);
+        self.face_size = face_size;
+        self.textures.push(faces);
+        Ok(id)
+    }
+
+    pub fn is_resident(&self, id: TextureId) -> bool {
+        (id as usize) < self.resident
+    }
+
+    /// The faces of every texture to upload as the layers of the texture
+    /// array, when some aren't on the GPU yet. They're taken to be from then
+    /// on.
+    pub fn take_upload(&mut self) -> Option<Vec<u8>> {
+        if self.resident == self.textures.len() {
+            return None;
+        }
+        self.resident = self.textures.len();
+        Some(self.textures.concat())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an atlas of cube maps 2 texels across whose faces are each one gray
+    // level
+    fn atlas(levels: &[[u8; 6]]) -> ([u32; 2], Vec<u8>) {
+        let mut pixels = Vec::new();
+        for cube in levels {
+            for _ in 0..2 {
+                for level in cube {
+                    pixels.extend([*level, *level, *level, 255].repeat(2));
+                }
+            }
+        }
+        ([12, 2 * levels.len() as u32], pixels)
+    }
+
+    #[test]
+    fn atlas_rows_become_cube_maps() {
+        let mut textures = TextureManager::new();
+        let (size, pixels) = atlas(&[[0, 1, 2, 3, 4, 5], [6, 7, 8, 9, 10, 11]]);
+        assert_eq!(vec![0, 1], textures.add_atlas(size, &pixels).unwrap());
+        assert_eq!(2, textures.face_size());
+        let faces = textures.take_upload().unwrap();
+        assert_eq!(2 * 6 * 2 * 2 * 4, faces.len());
+        // each face is whole before the next
+        let face_levels: Vec<u8> = faces.chunks(2 * 2 * 4).map(|face| face[0]).collect();
+        assert_eq!((0..12).collect::<Vec<u8>>(), face_levels);
+        assert!(textures.add_atlas([12, 3], &pixels[..12 * 3 * 4]).is_err());
+    }
+
+    #[test]
+    fn same_pixels_share_a_texture() {
+        let mut textures = TextureManager::new();
+        let (size, pixels) = atlas(&[[1; 6], [2; 6], [1; 6]]);
+        assert_eq!(vec![0, 1, 0], textures.add_atlas(size, &pixels).unwrap());
+        assert_eq!(2, textures.len());
+        let (size, pixels) = atlas(&[[2; 6], [3; 6]]);
+        assert_eq!(vec![1, 2], textures.add_atlas(size, &pixels).unwrap());
+        // faces of another size can't join the array
+        assert!(textures.add(1, vec![0; 6 * 4]).is_err());
+    }
+
+    #[test]
+    fn uploads_track_what_the_gpu_has() {
+        let mut textures = TextureManager::new();
+        let (size, pixels) = atlas(&[[1; 6]]);
+        textures.add_atlas(size, &pixels).unwrap();
+        assert!(!textures.is_resident(0));
+        assert!(textures.take_upload().is_some());
+        assert!(textures.is_resident(0));
+        assert_eq!(None, textures.take_upload());
+        // nothing new to upload for a texture already there
+        textures.add_atlas(size, &pixels).unwrap();
+        assert_eq!(None, textures.take_upload());
+        let (size, pixels) = atlas(&[[2; 6]]);
+        let id = textures.add_atlas(size, &pixels).unwrap()[0];
+        assert!(!textures.is_resident(id));
+        assert_eq!(2 * 6 * 2 * 2 * 4, textures.take_upload().unwrap().len());
+    }
+
+    #[test]
+    fn atlases_must_match_their_pixels() {
+        let mut textures = TextureManager::new();
+        let (size, pixels) = atlas(&[[1; 6]]);
+        assert!(textures.add_atlas([12, 4], &pixels).is_err());
+        assert!(textures.add_atlas([u32::MAX, u32::MAX], &pixels).is_err());
+        assert!(textures.add_atlas(size, &pixels).is_ok());
+    }
+
+    #[test]
+    fn files_are_loaded_once() {
+        let dir = std::env::temp_dir().join(format!("rtvox-assets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (size, pixels) = atlas(&[[5; 6]]);
+        let [a, b] = ["a.png", "b.png"].map(|name| {
+            let path = dir.join(name);
+            frames::write_png(File::create(&path).unwrap(), size, &pixels).unwrap();
+            path
+        });
+        let mut textures = TextureManager::new();
+        assert_eq!(0, textures.load(&a).unwrap());
+        std::fs::remove_file(&a).unwrap();
+        // from the paths already loaded, then by the pixels
+        assert_eq!(0, textures.load(&a).unwrap());
+        assert_eq!(0, textures.load(&b).unwrap());
+        assert_eq!(1, textures.len());
+        assert!(textures.load(&dir.join("missing.png")).is_err());
+        // a file of several cube maps adds none of them
+        let rows = dir.join("rows.png");
+        let (size, pixels) = atlas(&[[6; 6], [7; 6]]);
+        frames::write_png(File::create(&rows).unwrap(), size, &pixels).unwrap();
+        assert!(textures.load(&rows).is_err());
+        assert_eq!(1, textures.len());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
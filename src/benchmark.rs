@@ -0,0 +1,92 @@
+use std::{f32::consts::PI, time::Duration};
+
+use crate::{camera::Projection, graphics::cs::ty::CameraInfo, stats::FrameStats};
+
+/// Half the edge length of the world the benchmark flies around.
+pub const WORLD_EXTENT: i32 = 5;
+// frames drawn before measuring, while pipelines and uploads settle
+pub const WARMUP_FRAMES: u32 = 30;
+const ORBIT_RADIUS: f32 = 15.0;
+const ORBIT_HEIGHT: f32 = 4.0;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct BenchmarkConfig {
+    /// Number of measured frames.
+    pub frames: u32,
+    pub seed: u64,
+}
+
+impl Default for BenchmarkConfig {
+    fn default() -> Self {
+        BenchmarkConfig {
+            frames: 1000,
+            seed: 0,
+        }
+    }
+}
+
+/// Camera of the given frame of the flythrough: one orbit around the world
+/// over `frames` frames, bobbing up and down so the view isn't only ever
+/// level.
+pub fn camera_path(frame: u32, frames: u32) -> CameraInfo {
+    let t = frame as f32 / frames.max(1) as f32;
+    let angle = 2.0 * PI * t;
+    let eye = [
+        ORBIT_RADIUS * angle.sin(),
+        ORBIT_HEIGHT * (2.0 * angle).sin(),
+        ORBIT_RADIUS * angle.cos(),
+    ];
+    Projection::Perspective { fov: PI / 2.0 }.camera_info(eye, [0.0, 0.0, 0.0])
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+/// Frame time summary as a single line of JSON, times in milliseconds.
+pub fn report_json(stats: &FrameStats) -> String {
+    format!(
+        "{{\"frames\":{},\"avg_ms\":{:.3},\"p50_ms\":{:.3},\"p95_ms\":{:.3},\"p99_ms\":{:.3},\"max_ms\":{:.3}}}",
+        stats.len(),
+        millis(stats.average()),
+        millis(stats.percentile(50.0)),
+        millis(stats.percentile(95.0)),
+        millis(stats.percentile(99.0)),
+        millis(stats.max()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_is_deterministic_and_closed() {
+        assert_eq!(camera_path(17, 100).eye, camera_path(17, 100).eye);
+        let start = camera_path(0, 100).eye;
+        let end = camera_path(100, 100).eye;
+        for i in 0..3 {
+            assert!((start[i] - end[i]).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn path_stays_outside_world() {
+        for frame in 0..100 {
+            let eye = camera_path(frame, 100).eye;
+            let horizontal = (eye[0] * eye[0] + eye[2] * eye[2]).sqrt();
+            assert!(horizontal > WORLD_EXTENT as f32 * 2.0);
+        }
+    }
+
+    #[test]
+    fn json_report() {
+        let mut stats = FrameStats::new(4);
+        stats.record(Duration::from_millis(2));
+        stats.record(Duration::from_millis(4));
+        assert_eq!(
+            "{\"frames\":2,\"avg_ms\":3.000,\"p50_ms\":4.000,\"p95_ms\":4.000,\"p99_ms\":4.000,\"max_ms\":4.000}",
+            report_json(&stats)
+        );
+    }
+}
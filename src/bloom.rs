@@ -0,0 +1,200 @@
+use std::sync::Arc;
+
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
+    descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet},
+    device::Queue,
+    image::{view::ImageView, ImageAccess, StorageImage},
+    memory::pool::StdMemoryPool,
+    pipeline::{ComputePipeline, Pipeline, PipelineBindPoint},
+    shader::ShaderModule,
+};
+
+use crate::{
+    graphics::{Graphics, COMPUTE_GROUP_SIZE},
+    workgroups::group_count,
+};
+
+/// Number of blurred mips, each half the size of the previous one. Must match
+/// the bindings of bloom_composite.comp.
+pub const BLOOM_LEVELS: usize = 4;
+// HDR brightness above which light spills onto the neighboring pixels
+const THRESHOLD: f32 = 1.0;
+const INTENSITY: f32 = 0.6;
+
+type Image = Arc<StorageImage<Arc<StdMemoryPool>>>;
+
+/// Makes the HDR pixels brighter than white glow: the bright parts are
+/// downsampled into a chain of mips, each blurred, and added back on top of
+/// the image while converting it to 8 bits per channel.
+pub struct Bloom {
+    downsample: Arc<ComputePipeline>,
+    blur: Arc<ComputePipeline>,
+    composite: Arc<ComputePipeline>,
+    // per level, the blurred result and the intermediate of the separable blur
+    levels: Vec<[Image; 2]>,
+    output: Image,
+}
+
+impl Bloom {
+    pub fn new(queue: &Arc<Queue>, size: [u32; 2]) -> Self {
+        let device = queue.device();
+        let pipeline = |shader: Arc<ShaderModule>| {
+            ComputePipeline::new(
+                device.clone(),
+                shader.entry_point("main").unwrap(),
+                &(),
+                None,
+                |_| {},
+            )
+            .unwrap()
+        };
+        Bloom {
+            downsample: pipeline(downsample_cs::load(device.clone()).unwrap()),
+            blur: pipeline(blur_cs::load(device.clone()).unwrap()),
+            composite: pipeline(composite_cs::load(device.clone()).unwrap()),
+            levels: Self::create_levels(queue, size),
+            output: Graphics::create_storage_image(queue, size),
+        }
+    }
+
+    fn create_levels(queue: &Arc<Queue>, size: [u32; 2]) -> Vec<[Image; 2]> {
+        (1..=BLOOM_LEVELS)
+            .map(|i| {
+                let level_size = size.map(|s| (s >> i).max(1));
+                [
+                    Graphics::create_hdr_image(queue, level_size),
+                    Graphics::create_hdr_image(queue, level_size),
+                ]
+            })
+            .collect()
+    }
+
+    /// Resizes the images when the render resolution changed.
+    /// Bytes of device memory its images hold.
+    pub fn memory(&self) -> u64 {
+        self.levels
+            .iter()
+            .flatten()
+            .chain([&self.output])
+            .map(|image| Graphics::image_bytes(&**image))
+            .sum()
+    }
+
+    pub fn resize(&mut self, queue: &Arc<Queue>, size: [u32; 2]) {
+        if self.output.dimensions().width_height() != size {
+            self.levels = Self::create_levels(queue, size);
+            self.output = Graphics::create_storage_image(queue, size);
+        }
+    }
+
+    /// Records the bloom of the HDR `input` and returns the 8 bit output.
+    /// When disabled only the conversion is recorded.
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        input: Image,
+        enabled: bool,
+    ) -> Image {
+        if enabled {
+            let mut src = input.clone();
+            for (i, [level, scratch]) in self.levels.iter().enumerate() {
+                let size = level.dimensions().width_height();
+                let threshold = if i == 0 { THRESHOLD } else { 0.0 };
+                builder
+                    .bind_pipeline_compute(self.downsample.clone())
+                    .bind_descriptor_sets(
+                        PipelineBindPoint::Compute,
+                        self.downsample.layout().clone(),
+                        0,
+                        Self::descriptor_set(&self.downsample, [src, level.clone()]),
+                    )
+                    .push_constants(
+                        self.downsample.layout().clone(),
+                        0,
+                        downsample_cs::ty::Params { threshold },
+                    )
+                    .dispatch(group_count(size, [COMPUTE_GROUP_SIZE; 2]))
+                    .unwrap();
+                for (from, to, direction) in [(level, scratch, [1, 0]), (scratch, level, [0, 1])] {
+                    builder
+                        .bind_pipeline_compute(self.blur.clone())
+                        .bind_descriptor_sets(
+                            PipelineBindPoint::Compute,
+                            self.blur.layout().clone(),
+                            0,
+                            Self::descriptor_set(&self.blur, [from.clone(), to.clone()]),
+                        )
+                        .push_constants(
+                            self.blur.layout().clone(),
+                            0,
+                            blur_cs::ty::Params { direction },
+                        )
+                        .dispatch(group_count(size, [COMPUTE_GROUP_SIZE; 2]))
+                        .unwrap();
+                }
+                src = level.clone();
+            }
+        }
+
+        let mut images = vec![input];
+        images.extend(self.levels.iter().map(|[level, _]| level.clone()));
+        images.push(self.output.clone());
+        builder
+            .bind_pipeline_compute(self.composite.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.composite.layout().clone(),
+                0,
+                Self::descriptor_set(&self.composite, images),
+            )
+            .push_constants(
+                self.composite.layout().clone(),
+                0,
+                composite_cs::ty::Params {
+                    intensity: if enabled { INTENSITY } else { 0.0 },
+                },
+            )
+            .dispatch(group_count(
+                self.output.dimensions().width_height(),
+                [COMPUTE_GROUP_SIZE; 2],
+            ))
+            .unwrap();
+        self.output.clone()
+    }
+
+    // binds the images in order, starting at binding 0
+    fn descriptor_set(
+        pipeline: &Arc<ComputePipeline>,
+        images: impl IntoIterator<Item = Image>,
+    ) -> Arc<PersistentDescriptorSet> {
+        PersistentDescriptorSet::new(
+            pipeline.layout().set_layouts().get(0).unwrap().clone(),
+            images.into_iter().enumerate().map(|(i, image)| {
+                WriteDescriptorSet::image_view(i as u32, ImageView::new_default(image).unwrap())
+            }),
+        )
+        .unwrap()
+    }
+}
+
+pub mod downsample_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/bloom_downsample.comp",
+    }
+}
+
+pub mod blur_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/bloom_blur.comp",
+    }
+}
+
+pub mod composite_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/bloom_composite.comp",
+    }
+}
@@ -0,0 +1,171 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+// how far the energy of a point reaches, as in Ulichney's paper
+const SIGMA: f32 = 1.5;
+// pixels from a point beyond which its energy is too small to matter
+const REACH: usize = 6;
+
+/// Ranks the pixels of a `size` by `size` tile so that the pixels below any
+/// rank are spread out evenly, without the patterns of an ordered dither.
+/// This is Ulichney's void and cluster method, which wraps around the edges
+/// so the tile repeats seamlessly. It takes time quadratic in the pixels,
+/// fine for the small tiles the shader repeats over the screen.
+pub fn ranks(size: usize, seed: u64) -> Vec<u32> {
+    let n = size * size;
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut pattern = vec![false; n];
+    let mut energy = Energy::new(size);
+    let initial = (n / 10).max(1);
+    let mut placed = 0;
+    while placed < initial {
+        let p = rng.gen_range(0..n);
+        if !pattern[p] {
+            pattern[p] = true;
+            energy.add(p, 1.0);
+            placed += 1;
+        }
+    }
+    // move the point in the tightest cluster to the largest void until it
+    // would land where it came from
+    loop {
+        let cluster = energy.tightest_cluster(&pattern);
+        pattern[cluster] = false;
+        energy.add(cluster, -1.0);
+        let void = energy.largest_void(&pattern);
+        pattern[void] = true;
+        energy.add(void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; n];
+    // the initial points are ranked by taking them away again, and the rest
+    // by filling in the voids
+    let (mut removing, mut removed) = (pattern.clone(), energy.clone());
+    for rank in (0..initial).rev() {
+        let cluster = removed.tightest_cluster(&removing);
+        removing[cluster] = false;
+        removed.add(cluster, -1.0);
+        ranks[cluster] = rank as u32;
+    }
+    for rank in initial..n {
+        let void = energy.largest_void(&pattern);
+        pattern[void] = true;
+        energy.add(void, 1.0);
+        ranks[void] = rank as u32;
+    }
+    ranks
+}
+
+/// Four independent tiles of blue noise as RGBA8 pixels, each value being
+/// equally common.
+pub fn rgba(size: usize, seed: u64) -> Vec<u8> {
+    let n = size * size;
+    let channels: Vec<_> = (0..4).map(|c| ranks(size, seed + c)).collect();
+    let mut pixels = Vec::with_capacity(4 * n);
+    for i in 0..n {
+        for channel in &channels {
+            pixels.push((channel[i] as usize * 256 / n) as u8);
+        }
+    }
+    pixels
+}
+
+// the sum of a gaussian around each point at every pixel
+#[derive(Clone)]
+struct Energy {
+    size: usize,
+    // the gaussian within REACH of a point, or the whole tile if smaller
+    reach: usize,
+    kernel: Vec<f32>,
+    energy: Vec<f32>,
+}
+
+impl Energy {
+    fn new(size: usize) -> Self {
+        let reach = REACH.min((size - 1) / 2);
+        let width = 2 * reach + 1;
+        let mut kernel = Vec::with_capacity(width * width);
+        for y in 0..width {
+            for x in 0..width {
+                let dx = x as f32 - reach as f32;
+                let dy = y as f32 - reach as f32;
+                kernel.push((-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp());
+            }
+        }
+        Energy {
+            size,
+            reach,
+            kernel,
+            energy: vec![0.0; size * size],
+        }
+    }
+
+    // adds or with -1 removes the point at index p
+    fn add(&mut self, p: usize, sign: f32) {
+        let (size, width) = (self.size, 2 * self.reach + 1);
+        // offset so the kernel's corner wraps around to the right pixel
+        let (left, top) = (p % size + size - self.reach, p / size + size - self.reach);
+        for (ky, row) in self.kernel.chunks(width).enumerate() {
+            let y = (top + ky) % size;
+            for (kx, k) in row.iter().enumerate() {
+                self.energy[y * size + (left + kx) % size] += sign * k;
+            }
+        }
+    }
+
+    fn tightest_cluster(&self, pattern: &[bool]) -> usize {
+        self.extreme(pattern, true, |a, b| a > b)
+    }
+
+    fn largest_void(&self, pattern: &[bool]) -> usize {
+        self.extreme(pattern, false, |a, b| a < b)
+    }
+
+    // the first pixel set to `set` whose energy beats all others
+    fn extreme(&self, pattern: &[bool], set: bool, beats: impl Fn(f32, f32) -> bool) -> usize {
+        let mut best = None;
+        for (i, &e) in self.energy.iter().enumerate() {
+            match best {
+                _ if pattern[i] != set => (),
+                Some((_, b)) if !beats(e, b) => (),
+                _ => best = Some((i, e)),
+            }
+        }
+        best.unwrap().0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_value_is_equally_common() {
+        let pixels = rgba(16, 7);
+        for channel in 0..4 {
+            let mut values: Vec<_> = pixels.iter().skip(channel).step_by(4).collect();
+            values.sort();
+            assert!(values.iter().enumerate().all(|(i, &&v)| v as usize == i));
+        }
+        assert_ne!(pixels, rgba(16, 8));
+    }
+
+    #[test]
+    fn low_ranks_are_spread_out() {
+        let size = 32;
+        let ranks = ranks(size, 1);
+        let points: Vec<_> = (0..size * size)
+            .filter(|&i| ranks[i] < (size * size / 8) as u32)
+            .map(|i| (i % size, i / size))
+            .collect();
+        for (i, &(ax, ay)) in points.iter().enumerate() {
+            for &(bx, by) in &points[i + 1..] {
+                let dx = ax.abs_diff(bx).min(size - ax.abs_diff(bx));
+                let dy = ay.abs_diff(by).min(size - ay.abs_diff(by));
+                assert!(dx * dx + dy * dy >= 4, "{:?} and {:?}", (ax, ay), (bx, by));
+            }
+        }
+    }
+}
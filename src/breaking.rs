@@ -0,0 +1,252 @@
+use std::{collections::HashMap, time::Duration};
+
+use vecmath::Vector3;
+
+use crate::decals::{DecalKind, DecalList, CRACK_STAGES};
+
+/// Seconds a damaged voxel is left alone before it starts healing.
+pub const HEAL_DELAY: f32 = 3.0;
+
+/// Seconds of damage healed per second once healing.
+pub const HEAL_RATE: f32 = 0.5;
+
+// damage done to a voxel that isn't broken yet
+#[derive(Clone, Copy)]
+struct Damage {
+    // seconds spent breaking it, less what healed
+    elapsed: f32,
+    hardness: f32,
+    // seconds since it was last worked on
+    idle: f32,
+}
+
+impl Damage {
+    fn progress(&self) -> f32 {
+        if self.hardness <= 0.0 {
+            return 1.0;
+        }
+        (self.elapsed / self.hardness).min(1.0)
+    }
+}
+
+/// Tracks the damage done to voxels by holding the break button on them. A
+/// voxel is only reported as broken once it was held for its material's
+/// hardness. Damage stays when the button is released or another voxel is
+/// targeted, showing as cracks, and heals after `HEAL_DELAY`.
+#[derive(Default)]
+pub struct BlockBreaker {
+    target: Option<Vector3<i32>>,
+    damaged: HashMap<Vector3<i32>, Damage>,
+}
+
+impl BlockBreaker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn target(&self) -> Option<Vector3<i32>> {
+        self.target
+    }
+
+    /// Returns the breaking progress of the current target in [0, 1].
+    pub fn progress(&self) -> f32 {
+        self.target.map_or(0.0, |pos| self.damage(pos))
+    }
+
+    /// Returns how damaged the voxel at `pos` is in [0, 1], 1 being broken.
+    pub fn damage(&self, pos: Vector3<i32>) -> f32 {
+        self.damaged.get(&pos).map_or(0.0, |d| d.progress())
+    }
+
+    pub fn crack_stage(&self) -> Option<u32> {
+        self.target.and_then(|pos| self.stage(pos))
+    }
+
+    fn stage(&self, pos: Vector3<i32>) -> Option<u32> {
+        let progress = self.damage(pos);
+        if progress <= 0.0 {
+            return None;
+        }
+        Some(((progress * CRACK_STAGES as f32).ceil() as u32).clamp(1, CRACK_STAGES))
+    }
+
+    /// Forgets the damage of a voxel that changed, like one broken another
+    /// way or replaced.
+    pub fn forget(&mut self, pos: Vector3<i32>, decals: &mut DecalList) {
+        if self.damaged.remove(&pos).is_some() {
+            decals.remove(pos);
+        }
+    }
+
+    /// Advances breaking by `dt`, keeping the crack decals of damaged voxels
+    /// in `decals` up to date. A target starts taking damage the update after
+    /// it's first held on. Returns the position of the voxel if it finished
+    /// breaking.
+    pub fn update(
+        &mut self,
+        target: Option<(Vector3<i32>, f32)>,
+        held: bool,
+        dt: Duration,
+        decals: &mut DecalList,
+    ) -> Option<Vector3<i32>> {
+        let dt = dt.as_secs_f32();
+        let target = if held { target } else { None };
+        let previous = std::mem::replace(&mut self.target, target.map(|(pos, _)| pos));
+        for (pos, damage) in &mut self.damaged {
+            if Some(*pos) != self.target {
+                damage.idle += dt;
+                if damage.idle > HEAL_DELAY {
+                    damage.elapsed -= dt * HEAL_RATE;
+                }
+            }
+        }
+        let healed: Vec<_> = self
+            .damaged
+            .iter()
+            .filter(|(pos, damage)| damage.elapsed <= 0.0 && Some(**pos) != self.target)
+            .map(|(pos, _)| *pos)
+            .collect();
+        for pos in healed {
+            self.forget(pos, decals);
+        }
+
+        if let Some((pos, hardness)) = target {
+            let damage = self.damaged.entry(pos).or_insert(Damage {
+                elapsed: 0.0,
+                hardness,
+                idle: 0.0,
+            });
+            damage.idle = 0.0;
+            if previous == Some(pos) {
+                damage.elapsed += dt;
+            }
+            if damage.progress() >= 1.0 {
+                self.forget(pos, decals);
+                self.target = None;
+                return Some(pos);
+            }
+        }
+        let stages: Vec<_> = self
+            .damaged
+            .keys()
+            .map(|pos| (*pos, self.stage(*pos)))
+            .collect();
+        for (pos, stage) in stages {
+            match stage {
+                Some(stage) => {
+                    decals.set(pos, DecalKind::Crack(stage));
+                }
+                None => {
+                    decals.remove(pos);
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POS: Vector3<i32> = [1, 2, 3];
+
+    #[test]
+    fn breaks_after_hardness() {
+        let mut breaker = BlockBreaker::new();
+        let mut decals = DecalList::new();
+        let step = Duration::from_millis(250);
+        assert_eq!(
+            None,
+            breaker.update(Some((POS, 1.0)), true, step, &mut decals)
+        );
+        for _ in 0..3 {
+            assert_eq!(
+                None,
+                breaker.update(Some((POS, 1.0)), true, step, &mut decals)
+            );
+        }
+        assert_eq!(Some(DecalKind::Crack(8)), decals.get(POS));
+        assert_eq!(
+            Some(POS),
+            breaker.update(Some((POS, 1.0)), true, step, &mut decals)
+        );
+        assert!(decals.is_empty());
+    }
+
+    #[test]
+    fn releasing_keeps_the_damage() {
+        let mut breaker = BlockBreaker::new();
+        let mut decals = DecalList::new();
+        let step = Duration::from_millis(500);
+        breaker.update(Some((POS, 1.0)), true, step, &mut decals);
+        breaker.update(Some((POS, 1.0)), true, step, &mut decals);
+        breaker.update(Some((POS, 1.0)), false, step, &mut decals);
+        assert_eq!(0.0, breaker.progress());
+        assert_eq!(0.5, breaker.damage(POS));
+        assert_eq!(Some(DecalKind::Crack(5)), decals.get(POS));
+        // and picks up where it was
+        breaker.update(Some((POS, 1.0)), true, step, &mut decals);
+        assert_eq!(
+            Some(POS),
+            breaker.update(Some((POS, 1.0)), true, step, &mut decals)
+        );
+    }
+
+    #[test]
+    fn changing_target_keeps_the_damage() {
+        let mut breaker = BlockBreaker::new();
+        let mut decals = DecalList::new();
+        let step = Duration::from_millis(500);
+        breaker.update(Some((POS, 1.0)), true, step, &mut decals);
+        breaker.update(Some((POS, 1.0)), true, step, &mut decals);
+        breaker.update(Some(([0, 0, 0], 1.0)), true, step, &mut decals);
+        assert_eq!(Some([0, 0, 0]), breaker.target());
+        assert_eq!(0.0, breaker.progress());
+        assert_eq!(0.5, breaker.damage(POS));
+        assert_eq!(None, decals.get([0, 0, 0]));
+    }
+
+    #[test]
+    fn damage_heals_when_left_alone() {
+        let mut breaker = BlockBreaker::new();
+        let mut decals = DecalList::new();
+        let step = Duration::from_millis(500);
+        breaker.update(Some((POS, 1.0)), true, step, &mut decals);
+        breaker.update(Some((POS, 1.0)), true, step, &mut decals);
+        breaker.update(
+            None,
+            false,
+            Duration::from_secs_f32(HEAL_DELAY),
+            &mut decals,
+        );
+        assert_eq!(0.5, breaker.damage(POS));
+        breaker.update(None, false, Duration::from_millis(500), &mut decals);
+        assert_eq!(0.25, breaker.damage(POS));
+        breaker.update(None, false, Duration::from_secs(1), &mut decals);
+        assert_eq!(0.0, breaker.damage(POS));
+        assert!(decals.is_empty());
+    }
+
+    #[test]
+    fn changed_voxels_are_forgotten() {
+        let mut breaker = BlockBreaker::new();
+        let mut decals = DecalList::new();
+        let step = Duration::from_millis(500);
+        breaker.update(Some((POS, 1.0)), true, step, &mut decals);
+        breaker.update(Some((POS, 1.0)), true, step, &mut decals);
+        breaker.forget(POS, &mut decals);
+        assert_eq!(0.0, breaker.damage(POS));
+        assert!(decals.is_empty());
+    }
+
+    #[test]
+    fn zero_hardness_breaks_immediately() {
+        let mut breaker = BlockBreaker::new();
+        let mut decals = DecalList::new();
+        assert_eq!(
+            Some(POS),
+            breaker.update(Some((POS, 0.0)), true, Duration::ZERO, &mut decals)
+        );
+    }
+}
@@ -1,8 +1,8 @@
 use quaternion::Quaternion;
 use std::time::Duration;
-use vecmath::Vector3;
+use vecmath::{Matrix4, Vector3};
 
-use crate::graphics::cs::ty::CameraInfo;
+use crate::{aabc::Aabc, graphics::cs::ty::CameraInfo};
 
 const FORWARD: Vector3<f32> = [0.0, 0.0, -1.0];
 const BACKWARD: Vector3<f32> = [0.0, 0.0, 1.0];
@@ -16,10 +16,58 @@ const MOVEMENT_RATE: f32 = 3.0;
 pub struct Camera {
     pos: Vector3<f32>,
     quat: Quaternion<f32>,
-    fov: f32,
+    projection: Projection,
     pub move_state: MoveState,
 }
 
+/// The whole of the view, as the `window` of `CameraInfo`.
+pub const FULL_WINDOW: [f32; 4] = [-1.0, -1.0, 1.0, 1.0];
+
+/// How the rays of the image leave the camera.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    /// Rays spread out from the eye, `fov` radians apart across the image.
+    Perspective { fov: f32 },
+    /// Parallel rays, starting `width` voxels apart across the image.
+    Orthographic { width: f32 },
+    /// Part of a perspective view, `window` being its left, top, right and
+    /// bottom edges between -1 and 1. The image of each tile of a view put
+    /// together gives the image of the whole view.
+    OffCenter { fov: f32, window: [f32; 4] },
+}
+
+impl Projection {
+    /// Tile `[column, row]` of a perspective view split into `tiles` columns
+    /// and rows, counted from the top left.
+    pub fn tile(fov: f32, tile: [u32; 2], tiles: [u32; 2]) -> Self {
+        let edge = |i: u32, n: u32| 2.0 * i as f32 / n as f32 - 1.0;
+        Projection::OffCenter {
+            fov,
+            window: [
+                edge(tile[0], tiles[0]),
+                edge(tile[1], tiles[1]),
+                edge(tile[0] + 1, tiles[0]),
+                edge(tile[1] + 1, tiles[1]),
+            ],
+        }
+    }
+
+    pub fn camera_info(self, eye: Vector3<f32>, target: Vector3<f32>) -> CameraInfo {
+        let (fov, orthographic, window) = match self {
+            Projection::Perspective { fov } => (fov, 0, FULL_WINDOW),
+            Projection::Orthographic { width } => (width, 1, FULL_WINDOW),
+            Projection::OffCenter { fov, window } => (fov, 0, window),
+        };
+        CameraInfo {
+            eye,
+            fov,
+            target,
+            orthographic,
+            window,
+        }
+    }
+}
+
 // right and down are angles in radians
 pub struct LookEvent {
     pub right: f32,
@@ -75,11 +123,32 @@ impl Camera {
         Camera {
             pos: pos,
             quat: (1.0, [0.0, 0.0, 0.0]),
-            fov,
+            projection: Projection::Perspective { fov },
             move_state: MoveState::default(),
         }
     }
 
+    pub fn set_position(&mut self, pos: Vector3<f32>) {
+        self.pos = pos;
+    }
+
+    /// Turn from looking down -z.
+    pub fn orientation(&self) -> Quaternion<f32> {
+        self.quat
+    }
+
+    pub fn set_orientation(&mut self, orientation: Quaternion<f32>) {
+        self.quat = orientation;
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
     pub fn apply_look_event(&mut self, look_evt: LookEvent) {
         let quat_x = quaternion::axis_angle(DOWN, look_evt.right);
         self.quat = quaternion::mul(quat_x, self.quat);
@@ -128,11 +197,7 @@ impl Camera {
     pub fn get_camera_info(&self) -> CameraInfo {
         let dir = quaternion::rotate_vector(self.quat, FORWARD);
         let target = vecmath::vec3_add(self.pos, dir);
-        CameraInfo {
-            target,
-            fov: self.fov,
-            eye: self.pos,
-        }
+        self.projection.camera_info(self.pos, target)
     }
 
     pub fn is_moving(&self) -> bool {
@@ -154,6 +219,159 @@ impl Camera {
     }
 }
 
+/// The volume visible from a camera, bounded by a near plane through the eye
+/// and the 4 side planes, which are parallel when the projection is
+/// orthographic. There is no far plane.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frustum {
+    // inward facing normals and offsets, p is inside a plane when
+    // dot(normal, p) + offset >= 0
+    planes: [(Vector3<f32>, f32); 5],
+}
+
+impl Frustum {
+    /// Uses the same projection as the ray tracing shader. `aspect` is the
+    /// render width over its height.
+    pub fn from_camera_info(camera: &CameraInfo, aspect: f32) -> Self {
+        let [t_n, b_n, v_n] = basis(camera);
+        let [left, top, right, bottom] = image_extents(camera, aspect);
+        // the plane the edge at `extent` along `axis` is on, facing the image
+        let side = |axis: Vector3<f32>, extent: f32| {
+            if camera.orthographic != 0 {
+                let p = vecmath::vec3_add(camera.eye, vecmath::vec3_scale(axis, extent));
+                (axis, -vecmath::vec3_dot(axis, p))
+            } else {
+                let n = vecmath::vec3_sub(axis, vecmath::vec3_scale(t_n, extent));
+                (n, -vecmath::vec3_dot(n, camera.eye))
+            }
+        };
+        Frustum {
+            planes: [
+                (t_n, -vecmath::vec3_dot(t_n, camera.eye)),
+                side(b_n, left),
+                side(vecmath::vec3_neg(b_n), -right),
+                side(v_n, top),
+                side(vecmath::vec3_neg(v_n), -bottom),
+            ],
+        }
+    }
+
+    pub fn contains(&self, p: Vector3<f32>) -> bool {
+        self.planes
+            .iter()
+            .all(|&(n, d)| vecmath::vec3_dot(n, p) + d >= 0.0)
+    }
+
+    /// Conservative test, may return true for cubes just outside a corner of
+    /// the frustum.
+    pub fn intersects_aabc(&self, aabc: Aabc) -> bool {
+        let min = aabc.origin.map(|c| c as f32);
+        let max = min.map(|c| c + aabc.size as f32);
+        self.planes.iter().all(|&(n, d)| {
+            // the corner furthest along the normal
+            let p = [0, 1, 2].map(|i| if n[i] >= 0.0 { max[i] } else { min[i] });
+            vecmath::vec3_dot(n, p) + d >= 0.0
+        })
+    }
+}
+
+/// Corners of the frustum's cross section `distance` in front of the eye,
+/// going around the edge of the image.
+pub fn frustum_corners(camera: &CameraInfo, aspect: f32, distance: f32) -> [Vector3<f32>; 4] {
+    let [t_n, b_n, v_n] = basis(camera);
+    let [left, top, right, bottom] = image_extents(camera, aspect);
+    [(left, top), (right, top), (right, bottom), (left, bottom)].map(|(x, y)| {
+        let across = vecmath::vec3_add(vecmath::vec3_scale(b_n, x), vecmath::vec3_scale(v_n, y));
+        let offset = if camera.orthographic != 0 {
+            vecmath::vec3_add(vecmath::vec3_scale(t_n, distance), across)
+        } else {
+            vecmath::vec3_scale(vecmath::vec3_add(t_n, across), distance)
+        };
+        vecmath::vec3_add(camera.eye, offset)
+    })
+}
+
+/// Height above the player the minimap is traced from, over the terrain
+/// around them.
+pub const MINIMAP_HEIGHT: f32 = 512.0;
+
+/// Orthographic camera looking down on `eye` from `MINIMAP_HEIGHT` above,
+/// seeing `width` voxels across.
+pub fn minimap_camera(eye: Vector3<f32>, width: f32) -> CameraInfo {
+    let [x, y, z] = eye;
+    let above = y + MINIMAP_HEIGHT;
+    // the rays of graphics.comp need a view that isn't exactly vertical
+    Projection::Orthographic { width }.camera_info([x, above, z], [x, above - 1.0, z - 1e-3])
+}
+
+/// Column major matrix from world space to Vulkan clip space, matching the
+/// rays of the ray tracer. Depth goes from 0 at `near` to 1 at `far`.
+pub fn view_projection(camera: &CameraInfo, aspect: f32, near: f32, far: f32) -> Matrix4<f32> {
+    let [t_n, b_n, v_n] = basis(camera);
+    let [left, top, right, bottom] = image_extents(camera, aspect);
+    let (center_x, half_x) = ((left + right) / 2.0, (right - left) / 2.0);
+    let (center_y, half_y) = ((top + bottom) / 2.0, (bottom - top) / 2.0);
+    let row = |axis: Vector3<f32>, scale: f32, offset: f32| {
+        let d = -vecmath::vec3_dot(axis, camera.eye);
+        [
+            axis[0] * scale,
+            axis[1] * scale,
+            axis[2] * scale,
+            d * scale + offset,
+        ]
+    };
+    let rows = if camera.orthographic != 0 {
+        [
+            row(b_n, 1.0 / half_x, -center_x / half_x),
+            row(v_n, 1.0 / half_y, -center_y / half_y),
+            row(t_n, 1.0 / (far - near), -near / (far - near)),
+            [0.0, 0.0, 0.0, 1.0],
+        ]
+    } else {
+        let depth = far / (far - near);
+        // off center, the image's center is away from the view direction
+        let skew = |axis: Vector3<f32>, center: f32| {
+            vecmath::vec3_sub(axis, vecmath::vec3_scale(t_n, center))
+        };
+        [
+            row(skew(b_n, center_x), 1.0 / half_x, 0.0),
+            row(skew(v_n, center_y), 1.0 / half_y, 0.0),
+            row(t_n, depth, -depth * near),
+            row(t_n, 1.0, 0.0),
+        ]
+    };
+    vecmath::mat4_transposed(rows)
+}
+
+// The view direction, then the right and down directions of the image.
+pub(crate) fn basis(camera: &CameraInfo) -> [Vector3<f32>; 3] {
+    let t_n = vecmath::vec3_normalized(vecmath::vec3_sub(camera.target, camera.eye));
+    let b_n = vecmath::vec3_normalized(vecmath::vec3_cross(t_n, UP));
+    // points down, like the y axis of clip space
+    let v_n = vecmath::vec3_cross(t_n, b_n);
+    [t_n, b_n, v_n]
+}
+
+// Left, top, right and bottom edges of the image along the right and down
+// directions of `basis`, one unit in front of the eye, or around the eye when
+// the projection is orthographic.
+fn image_extents(camera: &CameraInfo, aspect: f32) -> [f32; 4] {
+    let [left, top, right, bottom] = camera.window;
+    let g_x = if camera.orthographic != 0 {
+        camera.fov / 2.0
+    } else {
+        (camera.fov / 2.0).tan()
+    };
+    // pixels stay square whatever part of the view the image covers
+    let g_y = g_x / aspect * (right - left) / (bottom - top);
+    [left * g_x, top * g_y, right * g_x, bottom * g_y]
+}
+
+/// Whether the camera sees the whole of a perspective view.
+pub fn is_full_perspective(camera: &CameraInfo) -> bool {
+    camera.orthographic == 0 && camera.window == FULL_WINDOW
+}
+
 #[cfg(test)]
 mod tests {
     use std::f32::consts::PI;
@@ -341,4 +559,123 @@ mod tests {
             assert_eq!(info.eye, *expect);
         });
     }
+
+    #[test]
+    fn test_frustum_contains() {
+        let camera = Camera::new([0.0, 0.0, 0.0], PI / 2.0);
+        let frustum = Frustum::from_camera_info(&camera.get_camera_info(), 1.0);
+
+        assert!(frustum.contains([0.0, 0.0, -5.0]));
+        assert!(frustum.contains([4.0, -4.0, -5.0]));
+        assert!(!frustum.contains([0.0, 0.0, 5.0]));
+        assert!(!frustum.contains([6.0, 0.0, -5.0]));
+        assert!(!frustum.contains([0.0, 6.0, -5.0]));
+    }
+
+    #[test]
+    fn test_frustum_intersects_aabc() {
+        let camera = Camera::new([0.0, 0.0, 0.0], PI / 2.0);
+        let frustum = Frustum::from_camera_info(&camera.get_camera_info(), 2.0);
+
+        assert!(frustum.intersects_aabc(Aabc::new([-1, -1, -10], 2)));
+        assert!(frustum.intersects_aabc(Aabc::new([-1, -1, -1], 2)));
+        assert!(!frustum.intersects_aabc(Aabc::new([-1, -1, 5], 2)));
+        assert!(!frustum.intersects_aabc(Aabc::new([-50, 0, -5], 2)));
+        // the vertical field of view is half the horizontal one
+        assert!(frustum.intersects_aabc(Aabc::new([7, 0, -10], 2)));
+        assert!(!frustum.intersects_aabc(Aabc::new([0, 7, -10], 2)));
+    }
+
+    #[test]
+    fn test_view_projection() {
+        let camera = Camera::new([0.0, 0.0, 0.0], PI / 2.0);
+        let m = view_projection(&camera.get_camera_info(), 2.0, 0.1, 100.0);
+        let ndc = |p: Vector3<f32>| {
+            let c = vecmath::col_mat4_transform(m, [p[0], p[1], p[2], 1.0]);
+            [c[0] / c[3], c[1] / c[3], c[2] / c[3]]
+        };
+        let close = |a: Vector3<f32>, b: Vector3<f32>| (0..3).all(|i| (a[i] - b[i]).abs() < 1e-4);
+
+        assert!(close([0.0, 0.0, 1.0], ndc([0.0, 0.0, -100.0])));
+        assert!(close([0.0, 0.0, 0.0], ndc([0.0, 0.0, -0.1])));
+        // clip space y points down
+        assert!(close(
+            [1.0, -1.0, ndc([0.0, 0.0, -5.0])[2]],
+            ndc([5.0, 2.5, -5.0])
+        ));
+    }
+
+    #[test]
+    fn test_frustum_corners() {
+        let camera = Camera::new([1.0, 2.0, 3.0], PI / 2.0).get_camera_info();
+        let m = view_projection(&camera, 2.0, 0.1, 100.0);
+        let mut corners = Vec::new();
+        for p in frustum_corners(&camera, 2.0, 10.0) {
+            let c = vecmath::col_mat4_transform(m, [p[0], p[1], p[2], 1.0]);
+            corners.push([c[0] / c[3], c[1] / c[3]].map(|c| c.round() as i32));
+            assert!((c[3] - 10.0).abs() < 1e-4);
+        }
+        assert_eq!(vec![[-1, -1], [1, -1], [1, 1], [-1, 1]], corners);
+    }
+
+    #[test]
+    fn test_minimap_camera() {
+        let camera = minimap_camera([10.0, 5.0, -20.0], 128.0);
+        let m = view_projection(&camera, 1.0, 0.1, 1000.0);
+        let project = |p: Vector3<f32>| {
+            let c = vecmath::col_mat4_transform(m, [p[0], p[1], p[2], 1.0]);
+            [c[0] / c[3], c[1] / c[3]]
+        };
+        // within a pixel of a small image, the view being slightly tilted
+        let [x, y] = project([10.0, 5.0, -20.0]);
+        assert!(x.abs() < 0.01 && y.abs() < 0.01);
+        // half the width away is at the edge, east to the right
+        let [x, y] = project([74.0, 5.0, -20.0]);
+        assert!((x - 1.0).abs() < 0.01 && y.abs() < 0.01);
+        // at any depth
+        let [x, _] = project([74.0, -100.0, -20.0]);
+        assert!((x - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_orthographic_frustum() {
+        let camera =
+            Projection::Orthographic { width: 8.0 }.camera_info([0.0, 0.0, 0.0], [0.0, 0.0, -1.0]);
+        let frustum = Frustum::from_camera_info(&camera, 2.0);
+
+        assert!(frustum.contains([3.9, 1.9, -100.0]));
+        assert!(!frustum.contains([4.1, 0.0, -100.0]));
+        assert!(!frustum.contains([0.0, 2.1, -1.0]));
+        assert!(!frustum.contains([0.0, 0.0, 1.0]));
+        let corners = frustum_corners(&camera, 2.0, 10.0);
+        assert_about_eq([-4.0, 2.0, -10.0], corners[0]);
+        assert_about_eq([4.0, -2.0, -10.0], corners[2]);
+    }
+
+    #[test]
+    fn test_tiles_cover_the_view() {
+        let eye = [1.0, 2.0, 3.0];
+        let target = [1.0, 2.0, 2.0];
+        let full = Projection::Perspective { fov: PI / 2.0 }.camera_info(eye, target);
+        let full_corners = frustum_corners(&full, 2.0, 10.0);
+        // each tile is half as wide and tall, with the same aspect
+        let top_left = Projection::tile(PI / 2.0, [0, 0], [2, 2]).camera_info(eye, target);
+        let bottom_right = Projection::tile(PI / 2.0, [1, 1], [2, 2]).camera_info(eye, target);
+        assert_about_eq(full_corners[0], frustum_corners(&top_left, 2.0, 10.0)[0]);
+        assert_about_eq(
+            full_corners[2],
+            frustum_corners(&bottom_right, 2.0, 10.0)[2],
+        );
+        assert_about_eq(
+            frustum_corners(&top_left, 2.0, 10.0)[2],
+            frustum_corners(&bottom_right, 2.0, 10.0)[0],
+        );
+
+        // the center of the view is the bottom right corner of the top left tile
+        let m = view_projection(&top_left, 2.0, 0.1, 100.0);
+        let c = vecmath::col_mat4_transform(m, [1.0, 2.0, -7.0, 1.0]);
+        assert_about_eq([1.0, 1.0, 0.0], [c[0] / c[3], c[1] / c[3], 0.0]);
+        assert!(Frustum::from_camera_info(&top_left, 2.0).contains([0.0, 2.5, -7.0]));
+        assert!(!Frustum::from_camera_info(&top_left, 2.0).contains([2.0, 2.5, -7.0]));
+    }
 }
@@ -0,0 +1,299 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    str::FromStr,
+};
+
+/// Runs a command on the context with the words after its name, returning
+/// what to show or why it failed.
+pub type Handler<C> = Box<dyn Fn(&mut C, &[&str]) -> Result<String, String>>;
+
+struct Command<C> {
+    usage: &'static str,
+    help: &'static str,
+    handler: Handler<C>,
+}
+
+/// Commands typed as `/name arg...`, which any part of the program can add
+/// handlers to. `/help` lists them.
+pub struct CommandRegistry<C> {
+    commands: BTreeMap<&'static str, Command<C>>,
+}
+
+impl<C> Default for CommandRegistry<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C> CommandRegistry<C> {
+    pub fn new() -> Self {
+        CommandRegistry {
+            commands: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a command, replacing one with the same name. `usage` describes
+    /// the arguments, like "<x> <y> <z>".
+    pub fn register(
+        &mut self,
+        name: &'static str,
+        usage: &'static str,
+        help: &'static str,
+        handler: impl Fn(&mut C, &[&str]) -> Result<String, String> + 'static,
+    ) {
+        let handler = Box::new(handler);
+        self.commands.insert(
+            name,
+            Command {
+                usage,
+                help,
+                handler,
+            },
+        );
+    }
+
+    /// One line per command, sorted by name.
+    pub fn help(&self) -> String {
+        let lines: Vec<_> = self
+            .commands
+            .iter()
+            .map(|(name, command)| match command.usage {
+                "" => format!("/{} - {}", name, command.help),
+                usage => format!("/{} {} - {}", name, usage, command.help),
+            })
+            .collect();
+        lines.join("\n")
+    }
+
+    /// Runs a line like "/tp 1 2 3", the slash being optional. A failing
+    /// command's error is followed by its usage.
+    pub fn run(&self, context: &mut C, line: &str) -> Result<String, String> {
+        let line = line.trim();
+        let mut words = line.strip_prefix('/').unwrap_or(line).split_whitespace();
+        let name = words.next().ok_or("No command given")?;
+        let args: Vec<_> = words.collect();
+        if name == "help" && !self.commands.contains_key("help") {
+            return Ok(self.help());
+        }
+        let command = self
+            .commands
+            .get(name)
+            .ok_or_else(|| format!("Unknown command /{}, /help lists them", name))?;
+        (command.handler)(context, &args)
+            .map_err(|e| format!("{}, usage: /{} {}", e, name, command.usage))
+    }
+}
+
+/// Parses every argument, requiring exactly `N` of them.
+pub fn parse_args<T: FromStr, const N: usize>(args: &[&str]) -> Result<[T; N], String> {
+    if args.len() != N {
+        return Err(format!("Expected {} arguments, got {}", N, args.len()));
+    }
+    let parsed: Vec<T> = args
+        .iter()
+        .map(|arg| arg.parse().map_err(|_| format!("Invalid argument {}", arg)))
+        .collect::<Result<_, _>>()?;
+    Ok(parsed.try_into().ok().unwrap())
+}
+
+/// Lines of output kept for the console to show.
+pub const SCROLLBACK: usize = 200;
+
+/// The line being typed, the lines entered before which Up and Down go back
+/// through, and what was printed to the console.
+#[derive(Default)]
+pub struct Console {
+    input: String,
+    history: Vec<String>,
+    // index into history while going through it
+    browsing: Option<usize>,
+    output: VecDeque<String>,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    /// Adds a typed character. Control characters and the backtick, which
+    /// opens and closes the console, are left out.
+    pub fn type_char(&mut self, c: char) {
+        if !c.is_control() && c != '`' {
+            self.input.push(c);
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        self.input.pop();
+    }
+
+    /// Adds the lines of `text` to the output, forgetting the oldest past
+    /// `SCROLLBACK`.
+    pub fn print(&mut self, text: &str) {
+        for line in text.lines() {
+            if self.output.len() == SCROLLBACK {
+                self.output.pop_front();
+            }
+            self.output.push_back(line.to_string());
+        }
+    }
+
+    /// What the open console shows in `rows` rows: the latest output and the
+    /// input being typed below it.
+    pub fn rows(&self, rows: usize) -> Vec<String> {
+        let shown = rows.saturating_sub(1).min(self.output.len());
+        let mut lines: Vec<_> = self
+            .output
+            .iter()
+            .skip(self.output.len() - shown)
+            .cloned()
+            .collect();
+        lines.push(format!("> {}_", self.input));
+        lines
+    }
+
+    /// Clears the input, returning it unless it was blank.
+    pub fn submit(&mut self) -> Option<String> {
+        self.browsing = None;
+        let line = std::mem::take(&mut self.input).trim().to_string();
+        if line.is_empty() {
+            return None;
+        }
+        if self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+        }
+        Some(line)
+    }
+
+    /// Replaces the input with the line entered before the one shown.
+    pub fn previous(&mut self) {
+        let index = match self.browsing {
+            Some(index) => index.saturating_sub(1),
+            None if self.history.is_empty() => return,
+            None => self.history.len() - 1,
+        };
+        self.browsing = Some(index);
+        self.input = self.history[index].clone();
+    }
+
+    /// Replaces the input with the line entered after the one shown, or
+    /// clears it after the latest.
+    pub fn next(&mut self) {
+        match self.browsing {
+            Some(index) if index + 1 < self.history.len() => {
+                self.browsing = Some(index + 1);
+                self.input = self.history[index + 1].clone();
+            }
+            Some(_) => {
+                self.browsing = None;
+                self.input.clear();
+            }
+            None => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> CommandRegistry<Vec<i32>> {
+        let mut commands: CommandRegistry<Vec<i32>> = CommandRegistry::new();
+        commands.register("push", "<n>", "adds a number", |numbers, args| {
+            let [n] = parse_args(args)?;
+            numbers.push(n);
+            Ok(format!("Pushed {}", n))
+        });
+        commands.register("clear", "", "removes every number", |numbers, _| {
+            numbers.clear();
+            Ok(String::new())
+        });
+        commands
+    }
+
+    #[test]
+    fn commands_run_their_handler() {
+        let commands = registry();
+        let mut numbers = Vec::new();
+        assert_eq!(
+            Ok("Pushed 3".to_string()),
+            commands.run(&mut numbers, "/push 3")
+        );
+        assert_eq!(
+            Ok("Pushed -1".to_string()),
+            commands.run(&mut numbers, " push  -1 ")
+        );
+        assert_eq!(vec![3, -1], numbers);
+        commands.run(&mut numbers, "/clear").unwrap();
+        assert!(numbers.is_empty());
+    }
+
+    #[test]
+    fn errors_show_usage() {
+        let commands = registry();
+        let mut numbers = Vec::new();
+        assert_eq!(
+            Err("Invalid argument x, usage: /push <n>".to_string()),
+            commands.run(&mut numbers, "/push x")
+        );
+        assert_eq!(
+            Err("Expected 1 arguments, got 2, usage: /push <n>".to_string()),
+            commands.run(&mut numbers, "/push 1 2")
+        );
+        assert!(commands.run(&mut numbers, "/pop").is_err());
+        assert!(commands.run(&mut numbers, "/").is_err());
+        assert!(numbers.is_empty());
+    }
+
+    #[test]
+    fn help_lists_commands() {
+        let commands = registry();
+        let help = "/clear - removes every number\n/push <n> - adds a number";
+        assert_eq!(help, commands.help());
+        assert_eq!(Ok(help.to_string()), commands.run(&mut Vec::new(), "/help"));
+    }
+
+    #[test]
+    fn typing_and_history() {
+        let mut console = Console::new();
+        for c in "`/seed\u{8}".chars() {
+            console.type_char(c);
+        }
+        assert_eq!("/seed", console.input());
+        console.backspace();
+        assert_eq!(Some("/see".to_string()), console.submit());
+        assert_eq!("", console.input());
+        assert_eq!(None, console.submit());
+        console.type_char('a');
+        console.submit();
+
+        console.previous();
+        assert_eq!("a", console.input());
+        console.previous();
+        console.previous();
+        assert_eq!("/see", console.input());
+        console.next();
+        assert_eq!("a", console.input());
+        console.next();
+        assert_eq!("", console.input());
+    }
+
+    #[test]
+    fn output_scrolls_back() {
+        let mut console = Console::new();
+        console.type_char('a');
+        assert_eq!(vec!["> a_"], console.rows(4));
+        console.print("one\ntwo");
+        for i in 0..SCROLLBACK {
+            console.print(&i.to_string());
+        }
+        let last = (SCROLLBACK - 1).to_string();
+        assert_eq!(vec![last, "> a_".to_string()], console.rows(2));
+        assert_eq!(SCROLLBACK + 1, console.rows(usize::MAX).len());
+        assert_eq!(1, console.rows(0).len());
+    }
+}
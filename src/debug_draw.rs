@@ -0,0 +1,92 @@
+use std::cell::RefCell;
+
+use vecmath::Vector3;
+
+use crate::aabc::Aabc;
+
+/// A line drawn over the next frame, see `line`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Line {
+    pub a: Vector3<f32>,
+    pub b: Vector3<f32>,
+    pub color: [f32; 3],
+}
+
+thread_local! {
+    static LINES: RefCell<Vec<Line>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Draws a line from `a` to `b` over the next frame only, so whatever wants
+/// a line to stay draws it again every frame. Lines are drawn on top of the
+/// world, which makes them handy for debugging what can't be seen.
+pub fn line(a: Vector3<f32>, b: Vector3<f32>, color: [f32; 3]) {
+    LINES.with(|lines| lines.borrow_mut().push(Line { a, b, color }))
+}
+
+/// Draws the 12 edges of a cube, see `line`.
+pub fn aabb(aabc: Aabc, color: [f32; 3]) {
+    let lo = aabc.origin.map(|c| c as f32);
+    cuboid(lo, lo.map(|c| c + aabc.size as f32), color)
+}
+
+/// Draws the 12 edges of the box from `lo` to `hi`, see `line`.
+pub fn cuboid(lo: Vector3<f32>, hi: Vector3<f32>, color: [f32; 3]) {
+    let corner = |i: usize| {
+        [0, 1, 2].map(|axis| {
+            if i >> axis & 1 == 0 {
+                lo[axis]
+            } else {
+                hi[axis]
+            }
+        })
+    };
+    for i in 0..8 {
+        // every edge once, from the corner with the lower coordinate
+        for axis in 0..3 {
+            if i >> axis & 1 == 0 {
+                line(corner(i), corner(i | 1 << axis), color);
+            }
+        }
+    }
+}
+
+/// Takes the lines drawn since the last call, which the renderer draws.
+pub fn take() -> Vec<Line> {
+    LINES.with(|lines| std::mem::take(&mut *lines.borrow_mut()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_taken_once() {
+        line([0.0; 3], [1.0, 2.0, 3.0], [1.0, 0.0, 0.0]);
+        assert_eq!(
+            vec![Line {
+                a: [0.0; 3],
+                b: [1.0, 2.0, 3.0],
+                color: [1.0, 0.0, 0.0],
+            }],
+            take()
+        );
+        assert!(take().is_empty());
+    }
+
+    #[test]
+    fn aabb_draws_every_edge() {
+        aabb(Aabc::new([1, 2, 3], 2), [0.0, 1.0, 0.0]);
+        let lines = take();
+        assert_eq!(12, lines.len());
+        for line in &lines {
+            let d: Vec<_> = (0..3).map(|i| (line.b[i] - line.a[i]).abs()).collect();
+            // along a single axis, the length of the cube
+            assert_eq!(2.0, d.iter().sum::<f32>());
+            assert_eq!(1, d.iter().filter(|&&d| d > 0.0).count());
+            assert!([1.0, 3.0].contains(&line.a[0]) && [2.0, 4.0].contains(&line.a[1]));
+        }
+        for (i, line) in lines.iter().enumerate() {
+            assert!(!lines[i + 1..].contains(line));
+        }
+    }
+}
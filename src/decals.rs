@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use vecmath::Vector3;
+
+/// Maximum number of decals uploaded to the GPU. The shader scans the list
+/// linearly for every hit, so it is kept small.
+pub const MAX_DECALS: usize = 64;
+pub const CRACK_STAGES: u32 = 10;
+
+// must match the DECAL_* defines in graphics.comp
+const DECAL_CRACK: i32 = 0;
+const DECAL_MARKER: i32 = 1;
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum DecalKind {
+    /// Breaking progress, from 1 to `CRACK_STAGES`.
+    Crack(u32),
+    /// Solid tint with the given RGB color.
+    Marker([u8; 3]),
+}
+
+impl DecalKind {
+    // kind in the top byte, payload in the lower 24 bits
+    fn pack(&self) -> i32 {
+        match *self {
+            DecalKind::Crack(stage) => (DECAL_CRACK << 24) | stage.clamp(1, CRACK_STAGES) as i32,
+            DecalKind::Marker([r, g, b]) => {
+                (DECAL_MARKER << 24) | (r as i32) << 16 | (g as i32) << 8 | b as i32
+            }
+        }
+    }
+}
+
+/// Decals applied to all faces of the voxels they are keyed by.
+#[derive(Default)]
+pub struct DecalList {
+    decals: HashMap<Vector3<i32>, DecalKind>,
+}
+
+impl DecalList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds or replaces the decal on the voxel at `pos`. Returns false if the
+    /// list is full and `pos` doesn't already have a decal.
+    pub fn set(&mut self, pos: Vector3<i32>, kind: DecalKind) -> bool {
+        if self.decals.len() >= MAX_DECALS && !self.decals.contains_key(&pos) {
+            return false;
+        }
+        self.decals.insert(pos, kind);
+        true
+    }
+
+    pub fn get(&self, pos: Vector3<i32>) -> Option<DecalKind> {
+        self.decals.get(&pos).copied()
+    }
+
+    pub fn remove(&mut self, pos: Vector3<i32>) -> Option<DecalKind> {
+        self.decals.remove(&pos)
+    }
+
+    pub fn clear(&mut self) {
+        self.decals.clear()
+    }
+
+    pub fn len(&self) -> usize {
+        self.decals.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decals.is_empty()
+    }
+
+    /// Serializes the list as the decal count followed by x, y, z and the
+    /// packed kind of each decal.
+    pub fn serialize(&self) -> Vec<i32> {
+        let mut arr = Vec::with_capacity(1 + 4 * self.decals.len());
+        arr.push(self.decals.len() as i32);
+        for (pos, kind) in &self.decals {
+            arr.extend_from_slice(pos);
+            arr.push(kind.pack());
+        }
+        arr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_empty() {
+        assert_eq!(vec![0], DecalList::new().serialize());
+    }
+
+    #[test]
+    fn serialize_crack() {
+        let mut decals = DecalList::new();
+        decals.set([1, -2, 3], DecalKind::Crack(4));
+        assert_eq!(vec![1, 1, -2, 3, 4], decals.serialize());
+    }
+
+    #[test]
+    fn serialize_marker() {
+        let mut decals = DecalList::new();
+        decals.set([0, 0, 0], DecalKind::Marker([0xff, 0x80, 0x01]));
+        assert_eq!(vec![1, 0, 0, 0, 0x01ff8001], decals.serialize());
+    }
+
+    #[test]
+    fn crack_stage_is_clamped() {
+        assert_eq!(CRACK_STAGES as i32, DecalKind::Crack(100).pack());
+        assert_eq!(1, DecalKind::Crack(0).pack());
+    }
+
+    #[test]
+    fn set_replaces_existing() {
+        let mut decals = DecalList::new();
+        decals.set([0, 0, 0], DecalKind::Crack(1));
+        decals.set([0, 0, 0], DecalKind::Crack(2));
+        assert_eq!(1, decals.len());
+        assert_eq!(Some(DecalKind::Crack(2)), decals.get([0, 0, 0]));
+    }
+
+    #[test]
+    fn full_list_rejects_new_decals() {
+        let mut decals = DecalList::new();
+        for i in 0..MAX_DECALS as i32 {
+            assert!(decals.set([i, 0, 0], DecalKind::Crack(1)));
+        }
+        assert!(!decals.set([-1, 0, 0], DecalKind::Crack(1)));
+        assert!(decals.set([0, 0, 0], DecalKind::Crack(3)));
+    }
+}
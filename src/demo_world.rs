@@ -0,0 +1,183 @@
+use std::str::FromStr;
+
+use vecmath::Vector3;
+
+use crate::{
+    materials::{MaterialId, MaterialRegistry},
+    octree::Octree,
+    world::World,
+};
+
+const CHECKERBOARD_EXTENT: i32 = 32;
+const SPHERE_RADIUS: i32 = 12;
+const TERRAIN_EXTENT: i32 = 48;
+
+/// Hand made scenes that are always the same, to debug, test and benchmark
+/// with instead of a generated world.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DemoWorld {
+    /// A flat floor of two alternating materials.
+    Checkerboard,
+    /// A ball of stone on a snow floor.
+    Sphere,
+    /// Rolling hills of grass over dirt and stone, cut off at their edges.
+    TerrainSlice,
+    /// Only the voxel at the origin.
+    SingleVoxel,
+}
+
+impl DemoWorld {
+    pub const ALL: [DemoWorld; 4] = [
+        DemoWorld::Checkerboard,
+        DemoWorld::Sphere,
+        DemoWorld::TerrainSlice,
+        DemoWorld::SingleVoxel,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DemoWorld::Checkerboard => "checkerboard",
+            DemoWorld::Sphere => "sphere",
+            DemoWorld::TerrainSlice => "terrain",
+            DemoWorld::SingleVoxel => "voxel",
+        }
+    }
+
+    /// Where the camera starts, looking down -z at the scene.
+    pub fn spawn(self) -> Vector3<f32> {
+        match self {
+            DemoWorld::Checkerboard => [0.0, 8.0, 40.0],
+            DemoWorld::Sphere => [0.0, 4.0, 36.0],
+            DemoWorld::TerrainSlice => [0.0, 16.0, 64.0],
+            DemoWorld::SingleVoxel => [0.5, 0.5, 4.0],
+        }
+    }
+
+    pub fn tree(self, materials: &MaterialRegistry) -> Octree<MaterialId> {
+        let id = |name| materials.id(name).unwrap();
+        let mut tree = Octree::new();
+        match self {
+            DemoWorld::Checkerboard => {
+                let (light, dark) = (id("snow"), id("cobblestone"));
+                let e = CHECKERBOARD_EXTENT;
+                for x in -e..e {
+                    for z in -e..e {
+                        let material = if (x + z).rem_euclid(2) == 0 {
+                            light
+                        } else {
+                            dark
+                        };
+                        tree.insert_leaf(material, [x, -1, z]);
+                    }
+                }
+            }
+            DemoWorld::Sphere => {
+                let (stone, floor) = (id("stone"), id("snow"));
+                let r = SPHERE_RADIUS;
+                for x in -r..r {
+                    for y in -r..r {
+                        for z in -r..r {
+                            // distance from the center of the voxel
+                            let d = [x, y, z].map(|c| c as f32 + 0.5);
+                            if d.iter().map(|c| c * c).sum::<f32>() <= (r * r) as f32 {
+                                tree.insert_leaf(stone, [x, y, z]);
+                            }
+                        }
+                    }
+                }
+                for x in -2 * r..2 * r {
+                    for z in -2 * r..2 * r {
+                        tree.insert_leaf(floor, [x, -r - 1, z]);
+                    }
+                }
+            }
+            DemoWorld::TerrainSlice => {
+                let (grass, dirt, stone) = (id("grass"), id("dirt"), id("stone"));
+                let e = TERRAIN_EXTENT;
+                for x in -e..e {
+                    for z in -e..e {
+                        let height = terrain_height(x, z);
+                        for y in -e / 2..=height {
+                            let material = match height - y {
+                                0 => grass,
+                                1..=3 => dirt,
+                                _ => stone,
+                            };
+                            tree.insert_leaf(material, [x, y, z]);
+                        }
+                    }
+                }
+            }
+            DemoWorld::SingleVoxel => tree.insert_leaf(id("debug"), [0, 0, 0]),
+        }
+        tree
+    }
+
+    pub fn build(self, materials: &MaterialRegistry) -> World {
+        let mut world = World::new();
+        world.insert_chunk(&self.tree(materials));
+        world
+    }
+}
+
+// waves along both axes rather than noise, so the hills are the same
+// everywhere the scene is built
+fn terrain_height(x: i32, z: i32) -> i32 {
+    let (x, z) = (x as f32, z as f32);
+    let height = 6.0 * (x / 9.0).sin() + 4.0 * (z / 7.0).cos() + 2.0 * ((x + z) / 4.0).sin();
+    height.round() as i32
+}
+
+impl FromStr for DemoWorld {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        DemoWorld::ALL
+            .into_iter()
+            .find(|demo| demo.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = DemoWorld::ALL.iter().map(|d| d.name()).collect();
+                format!("unknown demo world {}, one of {}", s, names.join(", "))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_parse() {
+        for demo in DemoWorld::ALL {
+            assert_eq!(Ok(demo), demo.name().parse());
+        }
+        assert!("noise".parse::<DemoWorld>().is_err());
+    }
+
+    #[test]
+    fn scenes_are_always_the_same() {
+        let materials = MaterialRegistry::default();
+        for demo in DemoWorld::ALL {
+            let tree = demo.tree(&materials);
+            assert_eq!(tree.serialize(), demo.tree(&materials).serialize());
+            assert!(tree.validate().is_ok());
+        }
+    }
+
+    #[test]
+    fn scenes_have_what_they_say() {
+        let materials = MaterialRegistry::default();
+        let voxel = DemoWorld::SingleVoxel.build(&materials);
+        assert_eq!(1, voxel.tree().count_leaves());
+        assert_eq!(materials.id("debug"), voxel.get([0, 0, 0]));
+        let board = DemoWorld::Checkerboard.tree(&materials);
+        assert_ne!(board.get_leaf([0, -1, 0]), board.get_leaf([1, -1, 0]));
+        assert_eq!(board.get_leaf([0, -1, 0]), board.get_leaf([1, -1, 1]));
+        let sphere = DemoWorld::Sphere.tree(&materials);
+        assert_eq!(
+            materials.id("stone"),
+            sphere.get_leaf([0, SPHERE_RADIUS - 1, 0])
+        );
+        assert_eq!(None, sphere.get_leaf([SPHERE_RADIUS, SPHERE_RADIUS, 0]));
+    }
+}
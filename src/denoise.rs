@@ -0,0 +1,138 @@
+use std::sync::Arc;
+
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
+    descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet},
+    device::Queue,
+    image::{view::ImageView, ImageAccess, StorageImage},
+    memory::pool::StdMemoryPool,
+    pipeline::{ComputePipeline, Pipeline, PipelineBindPoint},
+};
+
+use crate::{
+    gbuffer::GBuffer,
+    graphics::{Graphics, COMPUTE_GROUP_SIZE},
+    workgroups::group_count,
+};
+
+// iterations of the filter, which reaches 2^ITERATIONS pixels around each
+// pixel
+const ITERATIONS: usize = 5;
+// how far apart the colors blurred together may be in the first iteration
+const COLOR_PHI: f32 = 0.5;
+
+type Image = Arc<StorageImage<Arc<StdMemoryPool>>>;
+
+/// Smooths the noise of soft shadows and path tracing out of the HDR image
+/// without blurring across edges, which the G-buffer shows. Each iteration
+/// spreads its taps twice as far apart, so a wide area is covered in a few
+/// cheap passes.
+pub struct Denoiser {
+    pipeline: Arc<ComputePipeline>,
+    // the iterations go back and forth between these
+    images: [Image; 2],
+}
+
+impl Denoiser {
+    pub fn new(queue: &Arc<Queue>, size: [u32; 2]) -> Self {
+        let shader = denoise_cs::load(queue.device().clone()).unwrap();
+        let pipeline = ComputePipeline::new(
+            queue.device().clone(),
+            shader.entry_point("main").unwrap(),
+            &(),
+            None,
+            |_| {},
+        )
+        .unwrap();
+        Denoiser {
+            pipeline,
+            images: Self::create_images(queue, size),
+        }
+    }
+
+    fn create_images(queue: &Arc<Queue>, size: [u32; 2]) -> [Image; 2] {
+        [
+            Graphics::create_hdr_image(queue, size),
+            Graphics::create_hdr_image(queue, size),
+        ]
+    }
+
+    /// Resizes the images when the render resolution changed.
+    /// Bytes of device memory its images hold.
+    pub fn memory(&self) -> u64 {
+        self.images
+            .iter()
+            .map(|image| Graphics::image_bytes(&**image))
+            .sum()
+    }
+
+    pub fn resize(&mut self, queue: &Arc<Queue>, size: [u32; 2]) {
+        if self.images[0].dimensions().width_height() != size {
+            self.images = Self::create_images(queue, size);
+        }
+    }
+
+    /// Records the iterations over the HDR `input`, which must be the size
+    /// of the G-buffer, and returns the denoised image.
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        input: Image,
+        gbuffer: &GBuffer,
+    ) -> Image {
+        let size = gbuffer.size();
+        let mut src = input;
+        for i in 0..ITERATIONS {
+            let dst = self.images[i % 2].clone();
+            let desc_set = PersistentDescriptorSet::new(
+                self.pipeline.layout().set_layouts().get(0).unwrap().clone(),
+                [
+                    WriteDescriptorSet::image_view(0, ImageView::new_default(src).unwrap()),
+                    WriteDescriptorSet::image_view(1, ImageView::new_default(dst.clone()).unwrap()),
+                    WriteDescriptorSet::image_view(
+                        2,
+                        ImageView::new_default(gbuffer.depth.clone()).unwrap(),
+                    ),
+                    WriteDescriptorSet::image_view(
+                        3,
+                        ImageView::new_default(gbuffer.normal.clone()).unwrap(),
+                    ),
+                ],
+            )
+            .unwrap();
+            builder
+                .bind_pipeline_compute(self.pipeline.clone())
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Compute,
+                    self.pipeline.layout().clone(),
+                    0,
+                    desc_set,
+                )
+                .push_constants(
+                    self.pipeline.layout().clone(),
+                    0,
+                    denoise_cs::ty::Params {
+                        step: 1 << i,
+                        color_phi: color_phi(i),
+                    },
+                )
+                .dispatch(group_count(size, [COMPUTE_GROUP_SIZE; 2]))
+                .unwrap();
+            src = dst;
+        }
+        src
+    }
+}
+
+// colors have to be closer in later iterations, whose taps are further away
+// and more likely on something else
+fn color_phi(iteration: usize) -> f32 {
+    COLOR_PHI / (1 << iteration) as f32
+}
+
+pub mod denoise_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/denoise.comp",
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::BTreeMap;
+
+use vecmath::Vector3;
+
+use crate::materials::MaterialId;
+
+/// Maximum number of entities uploaded to the GPU. Every primary ray is tested
+/// against all of them, so it is kept small.
+pub const MAX_ENTITIES: usize = 64;
+
+pub type EntityId = u32;
+
+/// A box that isn't part of the octree, drawn with the texture of its
+/// material. Entities can be placed anywhere, not only on the voxel grid.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Entity {
+    /// Minimum corner of the box.
+    pub position: Vector3<f32>,
+    pub size: Vector3<f32>,
+    pub material: MaterialId,
+}
+
+/// Entities drawn by the ray tracer after the octree.
+#[derive(Default)]
+pub struct EntityList {
+    // ordered so the serialization doesn't change between frames
+    entities: BTreeMap<EntityId, Entity>,
+    next_id: EntityId,
+}
+
+impl EntityList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an entity and returns its id, or None if the list is full.
+    pub fn spawn(&mut self, entity: Entity) -> Option<EntityId> {
+        if self.entities.len() >= MAX_ENTITIES {
+            return None;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entities.insert(id, entity);
+        Some(id)
+    }
+
+    pub fn get(&self, id: EntityId) -> Option<&Entity> {
+        self.entities.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: EntityId) -> Option<&mut Entity> {
+        self.entities.get_mut(&id)
+    }
+
+    pub fn remove(&mut self, id: EntityId) -> Option<Entity> {
+        self.entities.remove(&id)
+    }
+
+    pub fn clear(&mut self) {
+        self.entities.clear()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (EntityId, &Entity)> {
+        self.entities.iter().map(|(id, entity)| (*id, entity))
+    }
+
+    /// Serializes the list as vec4s: the entity count padded to 4 floats,
+    /// then the position and material followed by the size of each entity.
+    pub fn serialize(&self) -> Vec<f32> {
+        let mut arr = Vec::with_capacity(4 + 8 * self.entities.len());
+        arr.extend_from_slice(&[self.entities.len() as f32, 0.0, 0.0, 0.0]);
+        for entity in self.entities.values() {
+            arr.extend_from_slice(&entity.position);
+            arr.push(entity.material as f32);
+            arr.extend_from_slice(&entity.size);
+            arr.push(0.0);
+        }
+        arr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube(x: f32, material: MaterialId) -> Entity {
+        Entity {
+            position: [x, 0.5, -1.0],
+            size: [0.25, 0.5, 1.0],
+            material,
+        }
+    }
+
+    #[test]
+    fn serialize_empty() {
+        assert_eq!(vec![0.0; 4], EntityList::new().serialize());
+    }
+
+    #[test]
+    fn serialize_entity() {
+        let mut entities = EntityList::new();
+        entities.spawn(cube(2.0, 3));
+        assert_eq!(
+            vec![1.0, 0.0, 0.0, 0.0, 2.0, 0.5, -1.0, 3.0, 0.25, 0.5, 1.0, 0.0],
+            entities.serialize()
+        );
+    }
+
+    #[test]
+    fn spawn_and_remove() {
+        let mut entities = EntityList::new();
+        let a = entities.spawn(cube(0.0, 1)).unwrap();
+        let b = entities.spawn(cube(1.0, 2)).unwrap();
+        assert_ne!(a, b);
+        assert_eq!(Some(cube(0.0, 1)), entities.remove(a));
+        assert_eq!(None, entities.get(a));
+        assert_eq!(Some(&cube(1.0, 2)), entities.get(b));
+        assert_eq!(1, entities.len());
+    }
+
+    #[test]
+    fn full_list_rejects_new_entities() {
+        let mut entities = EntityList::new();
+        for i in 0..MAX_ENTITIES {
+            assert!(entities.spawn(cube(i as f32, 1)).is_some());
+        }
+        assert_eq!(None, entities.spawn(cube(0.0, 1)));
+    }
+}
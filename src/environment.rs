@@ -0,0 +1,258 @@
+use std::{
+    f32::consts::PI,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use vecmath::{vec3_normalized, Vector3};
+
+use crate::io::{
+    exr,
+    hdr::{self, HdrImage},
+};
+
+/// Where environment maps are looked for by name.
+pub const DEFAULT_DIR: &str = "environments";
+/// Faces are at most this many texels across, about what an 8K wide map
+/// resolves.
+pub const MAX_FACE_SIZE: u32 = 1024;
+
+/// The six faces of a cube map in the order Vulkan keeps them: +x, -x, +y,
+/// -y, +z and -z, each `face_size` texels square, row by row.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CubeMap {
+    pub face_size: u32,
+    pub texels: Vec<[f32; 3]>,
+}
+
+impl CubeMap {
+    /// Samples `image`, an equirectangular map with -z at its center and up
+    /// at its top, in the direction of each texel. Values are kept as they
+    /// are, so the sun of a map can be far brighter than 1.
+    pub fn from_equirectangular(image: &HdrImage, face_size: u32) -> Self {
+        let mut texels = Vec::with_capacity(6 * (face_size * face_size) as usize);
+        for face in 0..6 {
+            for y in 0..face_size {
+                for x in 0..face_size {
+                    let dir = texel_direction(face, [x, y], face_size);
+                    texels.push(sample_equirectangular(image, dir));
+                }
+            }
+        }
+        CubeMap { face_size, texels }
+    }
+
+    /// RGBA16F texels as uploaded to the GPU, a format every device can
+    /// filter.
+    pub fn rgba_bytes(&self) -> Vec<u8> {
+        let rgba: Vec<[u16; 4]> = self
+            .texels
+            .iter()
+            .map(|&[r, g, b]| [r, g, b, 1.0].map(f16_bits))
+            .collect();
+        bytemuck::cast_slice(&rgba).to_vec()
+    }
+}
+
+/// File extensions of the environment maps `load` reads, Radiance .hdr and
+/// OpenEXR files.
+pub const EXTENSIONS: [&str; 2] = ["hdr", "exr"];
+
+/// Reads an environment map and turns it into a cube map with faces a
+/// quarter of its width across.
+pub fn load(path: &Path) -> io::Result<CubeMap> {
+    let image = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("hdr") => hdr::read_hdr(&fs::read(path)?)?,
+        Some("exr") => exr::read_exr(&fs::read(path)?)?,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "environment maps have to be .hdr or .exr files",
+            ))
+        }
+    };
+    let face_size = (image.width / 4).clamp(1, MAX_FACE_SIZE);
+    Ok(CubeMap::from_equirectangular(&image, face_size))
+}
+
+/// The environment maps of `dir` by file name, sorted. A missing directory
+/// has none.
+pub fn list(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut files = entries
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    files.retain(|file| {
+        file.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| EXTENSIONS.contains(&ext))
+    });
+    files.sort();
+    Ok(files)
+}
+
+/// Direction through the center of a texel of a cube map face, following
+/// the face selection of Vulkan that cube_texel inverts.
+pub fn texel_direction(face: u32, texel: [u32; 2], face_size: u32) -> Vector3<f32> {
+    let [s, t] = texel.map(|c| 2.0 * (c as f32 + 0.5) / face_size as f32 - 1.0);
+    let dir = match face {
+        0 => [1.0, -t, -s],
+        1 => [-1.0, -t, s],
+        2 => [s, 1.0, t],
+        3 => [s, -1.0, -t],
+        4 => [s, -t, 1.0],
+        _ => [-s, -t, -1.0],
+    };
+    vec3_normalized(dir)
+}
+
+/// Face and texel of a cube map `dir` points at, picked the way Vulkan picks
+/// them when sampling.
+pub fn cube_texel(dir: Vector3<f32>, face_size: u32) -> (u32, [u32; 2]) {
+    let [x, y, z] = dir;
+    let (face, s, t, major) = if x.abs() >= y.abs() && x.abs() >= z.abs() {
+        if x > 0.0 {
+            (0, -z, -y, x)
+        } else {
+            (1, z, -y, -x)
+        }
+    } else if y.abs() >= z.abs() {
+        if y > 0.0 {
+            (2, x, z, y)
+        } else {
+            (3, x, -z, -y)
+        }
+    } else if z > 0.0 {
+        (4, x, -y, z)
+    } else {
+        (5, -x, -y, -z)
+    };
+    let texel = [s, t].map(|c| {
+        let c = ((c / major + 1.0) / 2.0 * face_size as f32) as u32;
+        c.min(face_size - 1)
+    });
+    (face, texel)
+}
+
+/// Bits of the half precision float nearest `value`. Values too large for
+/// one become the largest, which keeps a bright sun from turning infinite,
+/// and those too small become 0.
+pub fn f16_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    if value.is_nan() {
+        return sign | 0x7e00;
+    }
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    if exponent <= 0 {
+        return sign;
+    }
+    let mantissa = bits & 0x7f_ffff;
+    // rounding to nearest may carry into the exponent, as it should
+    let magnitude = ((exponent as u32) << 10 | mantissa >> 13) + ((mantissa >> 12) & 1);
+    sign | magnitude.min(0x7bff) as u16
+}
+
+// bilinear, wrapping around horizontally
+fn sample_equirectangular(image: &HdrImage, dir: Vector3<f32>) -> [f32; 3] {
+    let u = 0.5 + dir[0].atan2(-dir[2]) / (2.0 * PI);
+    let v = dir[1].clamp(-1.0, 1.0).acos() / PI;
+    let x = u * image.width as f32 - 0.5;
+    let y = (v * image.height as f32 - 0.5).clamp(0.0, image.height as f32 - 1.0);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let texel = |x: i64, y: i64| {
+        let x = x.rem_euclid(image.width as i64) as u32;
+        let y = y.clamp(0, image.height as i64 - 1) as u32;
+        image.pixels[(y * image.width + x) as usize]
+    };
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let [a, b, c, d] = [
+        texel(x0, y0),
+        texel(x0 + 1, y0),
+        texel(x0, y0 + 1),
+        texel(x0 + 1, y0 + 1),
+    ];
+    [0, 1, 2].map(|i| {
+        let top = a[i] + (b[i] - a[i]) * fx;
+        let bottom = c[i] + (d[i] - c[i]) * fx;
+        top + (bottom - top) * fy
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn texels_point_back_at_themselves() {
+        for face in 0..6 {
+            for texel in [[0, 0], [3, 1], [7, 7], [2, 6]] {
+                let dir = texel_direction(face, texel, 8);
+                assert_eq!((face, texel), cube_texel(dir, 8));
+            }
+        }
+    }
+
+    #[test]
+    fn faces_are_in_vulkan_order() {
+        let axes = [
+            [1.0, 0.0, 0.0],
+            [-1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, -1.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [0.0, 0.0, -1.0],
+        ];
+        for (face, axis) in axes.into_iter().enumerate() {
+            assert_eq!(face as u32, cube_texel(axis, 4).0);
+        }
+    }
+
+    #[test]
+    fn equirectangular_map_keeps_bright_values() {
+        // a bright upper half over a dark lower one
+        let pixels = (0..16 * 8)
+            .map(|i| {
+                if i < 16 * 4 {
+                    [8.0, 6.0, 4.0]
+                } else {
+                    [0.1; 3]
+                }
+            })
+            .collect();
+        let image = HdrImage {
+            width: 16,
+            height: 8,
+            pixels,
+        };
+        let cube = CubeMap::from_equirectangular(&image, 4);
+        assert_eq!(6 * 16, cube.texels.len());
+        let face = |f: usize| &cube.texels[f * 16..(f + 1) * 16];
+        assert!(face(2).iter().all(|&t| t == [8.0, 6.0, 4.0]));
+        assert!(face(3).iter().all(|&t| t == [0.1; 3]));
+        assert_eq!(6 * 16 * 8, cube.rgba_bytes().len());
+    }
+
+    #[test]
+    fn halves_round_and_saturate() {
+        assert_eq!(0x3c00, f16_bits(1.0));
+        assert_eq!(0x3800, f16_bits(0.5));
+        assert_eq!(0xc000, f16_bits(-2.0));
+        assert_eq!(0x3555, f16_bits(1.0 / 3.0));
+        assert_eq!(0x7bff, f16_bits(65504.0));
+        assert_eq!(0x7bff, f16_bits(1e9));
+        assert_eq!(0, f16_bits(1e-9));
+    }
+
+    #[test]
+    fn only_hdr_and_exr_files_load() {
+        let error = load(Path::new("sky.png")).unwrap_err();
+        assert_eq!(io::ErrorKind::Unsupported, error.kind());
+        assert!(list(Path::new("no such directory")).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,199 @@
+use std::time::Duration;
+
+// frame times within this fraction of the budget keep the level
+const DEAD_BAND: f32 = 0.1;
+// fraction of the budget that has to be left over before raising the level,
+// which costs more than the headroom of the dead band alone
+const HEADROOM: f32 = 0.3;
+// weight of the newest frame time in the moving average
+const SMOOTHING: f32 = 0.1;
+// frames after a change before the next one, letting the average catch up
+const SETTLE_FRAMES: u32 = 30;
+
+/// What the frame budget trades for speed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quality {
+    pub render_scale: f32,
+    /// Most shadow rays traced towards the sun, see `Shadows`.
+    pub shadow_samples: u32,
+    /// Most bounces of a path traced sample.
+    pub bounces: u32,
+}
+
+/// From the fastest to the best looking.
+pub const LEVELS: [Quality; 6] = [
+    Quality {
+        render_scale: 0.5,
+        shadow_samples: 1,
+        bounces: 1,
+    },
+    Quality {
+        render_scale: 0.75,
+        shadow_samples: 1,
+        bounces: 2,
+    },
+    Quality {
+        render_scale: 1.0,
+        shadow_samples: 2,
+        bounces: 2,
+    },
+    Quality {
+        render_scale: 1.0,
+        shadow_samples: 4,
+        bounces: 4,
+    },
+    Quality {
+        render_scale: 1.0,
+        shadow_samples: 8,
+        bounces: 6,
+    },
+    Quality {
+        render_scale: 1.25,
+        shadow_samples: 16,
+        bounces: 6,
+    },
+];
+// where a new target starts from
+const START_LEVEL: usize = 3;
+
+/// Steps through the quality `LEVELS` to hold a target frame rate, given
+/// the time each frame took.
+pub struct FrameBudget {
+    target_fps: Option<f32>,
+    level: usize,
+    smoothed_frame_time: Option<f32>,
+    settling: u32,
+}
+
+impl Default for FrameBudget {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameBudget {
+    pub fn new() -> Self {
+        FrameBudget {
+            target_fps: None,
+            level: START_LEVEL,
+            smoothed_frame_time: None,
+            settling: 0,
+        }
+    }
+
+    pub fn target_fps(&self) -> Option<f32> {
+        self.target_fps
+    }
+
+    /// Starts holding `target` frames per second from the middle level, or
+    /// stops with `None`.
+    pub fn set_target_fps(&mut self, target: Option<f32>) {
+        self.target_fps = target.filter(|&fps| fps > 0.0);
+        self.level = START_LEVEL;
+        self.smoothed_frame_time = None;
+        self.settling = 0;
+    }
+
+    /// The quality to render with, None without a target.
+    pub fn quality(&self) -> Option<Quality> {
+        self.target_fps.map(|_| LEVELS[self.level])
+    }
+
+    /// Feeds a measured frame time in. Returns true if the quality changed.
+    pub fn update(&mut self, frame_time: Duration) -> bool {
+        let budget = match self.target_fps {
+            Some(fps) => 1.0 / fps,
+            None => return false,
+        };
+        let frame_time = frame_time.as_secs_f32();
+        let smoothed = match self.smoothed_frame_time {
+            Some(s) => s + SMOOTHING * (frame_time - s),
+            None => frame_time,
+        };
+        self.smoothed_frame_time = Some(smoothed);
+        if self.settling > 0 {
+            self.settling -= 1;
+            return false;
+        }
+        let level = if smoothed > budget * (1.0 + DEAD_BAND) {
+            self.level.saturating_sub(1)
+        } else if smoothed < budget * (1.0 - HEADROOM) {
+            (self.level + 1).min(LEVELS.len() - 1)
+        } else {
+            self.level
+        };
+        if level == self.level {
+            return false;
+        }
+        self.level = level;
+        self.settling = SETTLE_FRAMES;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(fps: f32) -> FrameBudget {
+        let mut budget = FrameBudget::new();
+        budget.set_target_fps(Some(fps));
+        budget
+    }
+
+    #[test]
+    fn no_target_never_changes() {
+        let mut budget = FrameBudget::new();
+        assert!(!budget.update(Duration::from_secs(1)));
+        assert_eq!(None, budget.quality());
+        budget.set_target_fps(Some(0.0));
+        assert_eq!(None, budget.target_fps());
+    }
+
+    #[test]
+    fn slow_frames_lower_quality() {
+        let mut budget = budget(60.0);
+        assert!(budget.update(Duration::from_millis(40)));
+        assert_eq!(Some(LEVELS[START_LEVEL - 1]), budget.quality());
+    }
+
+    #[test]
+    fn fast_frames_raise_quality() {
+        let mut budget = budget(60.0);
+        assert!(budget.update(Duration::from_millis(5)));
+        assert_eq!(Some(LEVELS[START_LEVEL + 1]), budget.quality());
+    }
+
+    #[test]
+    fn frames_near_budget_keep_quality() {
+        let mut budget = budget(60.0);
+        // under budget, but not by enough to afford the next level
+        assert!(!budget.update(Duration::from_millis(15)));
+        assert!(!budget.update(Duration::from_millis(18)));
+        assert_eq!(Some(LEVELS[START_LEVEL]), budget.quality());
+    }
+
+    #[test]
+    fn changes_settle_before_the_next() {
+        let mut budget = budget(60.0);
+        assert!(budget.update(Duration::from_millis(40)));
+        for _ in 0..SETTLE_FRAMES {
+            assert!(!budget.update(Duration::from_millis(40)));
+        }
+        assert!(budget.update(Duration::from_millis(40)));
+        assert_eq!(Some(LEVELS[START_LEVEL - 2]), budget.quality());
+    }
+
+    #[test]
+    fn quality_stays_in_range() {
+        let mut budget = budget(60.0);
+        for _ in 0..1000 {
+            budget.update(Duration::from_secs(1));
+        }
+        assert_eq!(Some(LEVELS[0]), budget.quality());
+        for _ in 0..1000 {
+            budget.update(Duration::ZERO);
+        }
+        assert_eq!(Some(LEVELS[LEVELS.len() - 1]), budget.quality());
+    }
+}
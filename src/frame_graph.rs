@@ -0,0 +1,181 @@
+use std::fmt;
+
+/// The passes of a frame and the resources each reads and writes, from which
+/// the order they're recorded in follows. The writers of a resource run in
+/// the order they were added, and its readers after all of them. Passes that
+/// don't depend on each other keep the order they were added in.
+///
+/// Only the order is worked out here. Vulkano's command buffer builder puts
+/// the barriers between commands that use the same resource, so a pass that
+/// runs after the ones it depends on sees their writes.
+pub struct FrameGraph<'a, R, C> {
+    passes: Vec<Pass<'a, R, C>>,
+}
+
+struct Pass<'a, R, C> {
+    name: &'static str,
+    reads: Vec<R>,
+    writes: Vec<R>,
+    record: Box<dyn FnOnce(&mut C) + 'a>,
+}
+
+/// Passes that wait on each other, so none of them can be recorded.
+#[derive(Debug, PartialEq)]
+pub struct Cycle(pub Vec<&'static str>);
+
+impl fmt::Display for Cycle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "frame graph passes wait on each other: {}",
+            self.0.join(", ")
+        )
+    }
+}
+
+impl<'a, R: Copy + PartialEq, C> Default for FrameGraph<'a, R, C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, R: Copy + PartialEq, C> FrameGraph<'a, R, C> {
+    pub fn new() -> Self {
+        FrameGraph { passes: Vec::new() }
+    }
+
+    /// Adds a pass that records its commands with `record`. A pass that
+    /// updates a resource in place both reads and writes it, but only needs
+    /// to list it in `writes`.
+    pub fn add_pass(
+        &mut self,
+        name: &'static str,
+        reads: &[R],
+        writes: &[R],
+        record: impl FnOnce(&mut C) + 'a,
+    ) {
+        self.passes.push(Pass {
+            name,
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+            record: Box::new(record),
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.passes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passes.is_empty()
+    }
+
+    /// Names of the passes in the order they'd be recorded.
+    pub fn order(&self) -> Result<Vec<&'static str>, Cycle> {
+        Ok(self
+            .sorted()?
+            .into_iter()
+            .map(|i| self.passes[i].name)
+            .collect())
+    }
+
+    /// Records every pass into `context`, see `order`.
+    pub fn record(mut self, context: &mut C) -> Result<(), Cycle> {
+        let order = self.sorted()?;
+        let mut records: Vec<_> = self.passes.drain(..).map(|p| Some(p.record)).collect();
+        for i in order {
+            records[i].take().unwrap()(context);
+        }
+        Ok(())
+    }
+
+    // whether pass `j` has to wait for pass `i`
+    fn waits_for(&self, j: usize, i: usize) -> bool {
+        let (a, b) = (&self.passes[i], &self.passes[j]);
+        a.writes.iter().any(|r| {
+            let reads = b.reads.contains(r) && !b.writes.contains(r);
+            reads || (i < j && b.writes.contains(r))
+        })
+    }
+
+    // indices of the passes in dependency order, the earliest added first
+    // among those that are ready
+    fn sorted(&self) -> Result<Vec<usize>, Cycle> {
+        let n = self.passes.len();
+        let mut waiting: Vec<usize> = (0..n)
+            .map(|j| (0..n).filter(|&i| i != j && self.waits_for(j, i)).count())
+            .collect();
+        let mut done = vec![false; n];
+        let mut order = Vec::with_capacity(n);
+        while let Some(i) = (0..n).find(|&i| !done[i] && waiting[i] == 0) {
+            done[i] = true;
+            order.push(i);
+            for j in (0..n).filter(|&j| j != i && !done[j]) {
+                if self.waits_for(j, i) {
+                    waiting[j] -= 1;
+                }
+            }
+        }
+        if order.len() < n {
+            let stuck = (0..n).filter(|&i| !done[i]);
+            return Err(Cycle(stuck.map(|i| self.passes[i].name).collect()));
+        }
+        Ok(order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum Image {
+        Color,
+        Depth,
+        Screen,
+    }
+    use Image::*;
+
+    type Graph<'a> = FrameGraph<'a, Image, Vec<&'static str>>;
+
+    fn add(graph: &mut Graph, name: &'static str, reads: &[Image], writes: &[Image]) {
+        graph.add_pass(name, reads, writes, move |log| log.push(name));
+    }
+
+    #[test]
+    fn readers_wait_for_every_writer() {
+        let mut graph = Graph::new();
+        add(&mut graph, "blit", &[Color], &[Screen]);
+        add(&mut graph, "record", &[Color], &[]);
+        add(&mut graph, "clear", &[], &[Color]);
+        add(&mut graph, "trace", &[], &[Color, Depth]);
+        add(&mut graph, "denoise", &[Depth], &[Color]);
+        add(&mut graph, "lines", &[Depth], &[Screen]);
+        let order = vec!["clear", "trace", "denoise", "blit", "record", "lines"];
+        assert_eq!(Ok(order.clone()), graph.order());
+        let mut log = Vec::new();
+        graph.record(&mut log).unwrap();
+        assert_eq!(order, log);
+    }
+
+    #[test]
+    fn independent_passes_keep_their_order() {
+        let mut graph = Graph::new();
+        add(&mut graph, "b", &[], &[Depth]);
+        add(&mut graph, "a", &[], &[Color]);
+        add(&mut graph, "c", &[Color], &[Screen]);
+        assert_eq!(Ok(vec!["b", "a", "c"]), graph.order());
+    }
+
+    #[test]
+    fn cycles_are_refused() {
+        let mut graph = Graph::new();
+        add(&mut graph, "clear", &[], &[Screen]);
+        add(&mut graph, "a", &[Depth], &[Color]);
+        add(&mut graph, "b", &[Color], &[Depth]);
+        assert_eq!(Err(Cycle(vec!["a", "b"])), graph.order());
+        let mut log = Vec::new();
+        assert!(graph.record(&mut log).is_err());
+        assert!(log.is_empty());
+    }
+}
@@ -0,0 +1,157 @@
+/// Frames the CPU may record while the GPU is still busy with earlier ones.
+pub const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
+pub const MAX_FRAMES_IN_FLIGHT: usize = 3;
+
+/// One slot per frame that may be in flight, used in turn. Each holds what
+/// signals the end of the frame last submitted from it, which has to be
+/// waited for before the slot is used again. That bounds how far the CPU runs
+/// ahead of the GPU, and so the latency of a frame.
+pub struct FramesInFlight<T> {
+    slots: Vec<Option<T>>,
+    current: usize,
+}
+
+impl<T> FramesInFlight<T> {
+    /// `count` is clamped between 1 and `MAX_FRAMES_IN_FLIGHT`.
+    pub fn new(count: usize) -> Self {
+        FramesInFlight {
+            slots: (0..count.clamp(1, MAX_FRAMES_IN_FLIGHT))
+                .map(|_| None)
+                .collect(),
+            current: 0,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// The end of the frame that used the slot of the next one, to wait for
+    /// before recording it.
+    pub fn next(&self) -> Option<&T> {
+        self.slots[(self.current + 1) % self.slots.len()].as_ref()
+    }
+
+    /// Moves on to the slot of the next frame, keeping what signals its end,
+    /// None if it couldn't be submitted.
+    pub fn submit(&mut self, end: Option<T>) {
+        self.current = (self.current + 1) % self.slots.len();
+        self.slots[self.current] = end;
+    }
+
+    /// The end of the frame submitted last, which the next one starts after.
+    pub fn last(&self) -> Option<&T> {
+        self.slots[self.current].as_ref()
+    }
+
+    /// The end of the frame submitted before the last, None with a single
+    /// slot, where `next` is the last frame already.
+    pub fn before_last(&self) -> Option<&T> {
+        let count = self.slots.len();
+        if count < 2 {
+            return None;
+        }
+        self.slots[(self.current + count - 1) % count].as_ref()
+    }
+
+    /// Forgets every frame, once they're known to have finished.
+    pub fn clear(&mut self) {
+        self.slots.iter_mut().for_each(|slot| *slot = None);
+    }
+
+    /// Takes the ends of every frame still kept, oldest first.
+    pub fn take_all(&mut self) -> Vec<T> {
+        let count = self.slots.len();
+        (1..=count)
+            .filter_map(|i| self.slots[(self.current + i) % count].take())
+            .collect()
+    }
+}
+
+/// Swapchain images to ask for: one more than the frames in flight, so an
+/// image can be acquired while the others wait to be presented, within what
+/// the surface supports.
+pub fn swapchain_image_count(frames_in_flight: usize, min: u32, max: Option<u32>) -> u32 {
+    let count = (frames_in_flight as u32 + 1).max(min);
+    match max {
+        Some(max) => count.min(max),
+        None => count,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots_are_reused_in_turn() {
+        let mut frames = FramesInFlight::new(2);
+        assert_eq!(None, frames.next());
+        frames.submit(Some(1));
+        assert_eq!(None, frames.next());
+        frames.submit(Some(2));
+        assert_eq!(Some(&2), frames.last());
+        // the third frame waits for the first
+        assert_eq!(Some(&1), frames.next());
+        frames.submit(Some(3));
+        assert_eq!(Some(&3), frames.last());
+        assert_eq!(Some(&2), frames.next());
+    }
+
+    #[test]
+    fn failed_frames_leave_empty_slots() {
+        let mut frames = FramesInFlight::new(2);
+        frames.submit(Some(1));
+        frames.submit(None);
+        assert_eq!(None, frames.last());
+        assert_eq!(Some(&1), frames.next());
+    }
+
+    #[test]
+    fn before_last_is_one_behind() {
+        let mut frames = FramesInFlight::new(3);
+        frames.submit(Some(1));
+        assert_eq!(None, frames.before_last());
+        frames.submit(Some(2));
+        assert_eq!(Some(&1), frames.before_last());
+        frames.submit(Some(3));
+        assert_eq!(Some(&2), frames.before_last());
+        let mut single = FramesInFlight::new(1);
+        single.submit(Some(1));
+        single.submit(Some(2));
+        assert_eq!(None, single.before_last());
+    }
+
+    #[test]
+    fn count_is_clamped() {
+        assert_eq!(1, FramesInFlight::<()>::new(0).count());
+        assert_eq!(MAX_FRAMES_IN_FLIGHT, FramesInFlight::<()>::new(8).count());
+    }
+
+    #[test]
+    fn clear_forgets_frames() {
+        let mut frames = FramesInFlight::new(3);
+        frames.submit(Some(1));
+        frames.submit(Some(2));
+        frames.clear();
+        assert_eq!(None, frames.last());
+        assert_eq!(None, frames.next());
+    }
+
+    #[test]
+    fn take_all_oldest_first() {
+        let mut frames = FramesInFlight::new(3);
+        for i in 1..=4 {
+            frames.submit(Some(i));
+        }
+        assert_eq!(vec![2, 3, 4], frames.take_all());
+        assert_eq!(None, frames.last());
+    }
+
+    #[test]
+    fn an_image_more_than_frames() {
+        assert_eq!(3, swapchain_image_count(2, 2, None));
+        assert_eq!(4, swapchain_image_count(2, 4, Some(8)));
+        assert_eq!(3, swapchain_image_count(3, 2, Some(3)));
+    }
+}
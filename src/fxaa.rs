@@ -0,0 +1,91 @@
+use std::sync::Arc;
+
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
+    descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet},
+    device::Queue,
+    image::{view::ImageView, ImageAccess, StorageImage},
+    memory::pool::StdMemoryPool,
+    pipeline::{ComputePipeline, Pipeline, PipelineBindPoint},
+};
+
+use crate::{
+    graphics::{Graphics, COMPUTE_GROUP_SIZE},
+    workgroups::group_count,
+};
+
+/// Fast approximate anti-aliasing: blurs across the high contrast edges found
+/// in the ray traced image, which hides the stair stepping of voxel edges.
+pub struct Fxaa {
+    pipeline: Arc<ComputePipeline>,
+    output: Arc<StorageImage<Arc<StdMemoryPool>>>,
+}
+
+impl Fxaa {
+    pub fn new(queue: &Arc<Queue>, size: [u32; 2]) -> Self {
+        let shader = fxaa_cs::load(queue.device().clone()).unwrap();
+        let pipeline = ComputePipeline::new(
+            queue.device().clone(),
+            shader.entry_point("main").unwrap(),
+            &(),
+            None,
+            |_| {},
+        )
+        .unwrap();
+        Fxaa {
+            pipeline,
+            output: Graphics::create_storage_image(queue, size),
+        }
+    }
+
+    /// Resizes the output image when the render resolution changed.
+    /// Bytes of device memory its image holds.
+    pub fn memory(&self) -> u64 {
+        Graphics::image_bytes(&*self.output)
+    }
+
+    pub fn resize(&mut self, queue: &Arc<Queue>, size: [u32; 2]) {
+        if self.output.dimensions().width_height() != size {
+            self.output = Graphics::create_storage_image(queue, size);
+        }
+    }
+
+    /// Records the pass over `input`, which must be the size of the output,
+    /// and returns the output image.
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        input: Arc<StorageImage<Arc<StdMemoryPool>>>,
+    ) -> Arc<StorageImage<Arc<StdMemoryPool>>> {
+        let size = self.output.dimensions().width_height();
+        let desc_set = PersistentDescriptorSet::new(
+            self.pipeline.layout().set_layouts().get(0).unwrap().clone(),
+            [
+                WriteDescriptorSet::image_view(0, ImageView::new_default(input).unwrap()),
+                WriteDescriptorSet::image_view(
+                    1,
+                    ImageView::new_default(self.output.clone()).unwrap(),
+                ),
+            ],
+        )
+        .unwrap();
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipeline.layout().clone(),
+                0,
+                desc_set,
+            )
+            .dispatch(group_count(size, [COMPUTE_GROUP_SIZE; 2]))
+            .unwrap();
+        self.output.clone()
+    }
+}
+
+pub mod fxaa_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/fxaa.comp",
+    }
+}
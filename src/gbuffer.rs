@@ -0,0 +1,37 @@
+use std::sync::Arc;
+
+use vulkano::{
+    device::Queue,
+    format::Format,
+    image::{ImageAccess, StorageImage},
+    memory::pool::StdMemoryPool,
+};
+
+use crate::graphics::Graphics;
+
+/// Per pixel data of the first surface hit by the primary rays, written by the
+/// ray tracer alongside the color for passes that need the geometry.
+/// Translucent surfaces count as hits. Pixels seeing the sky have a distance
+/// of 0 and material 0.
+pub struct GBuffer {
+    /// Distance from the eye to the hit along the ray, in R32_SFLOAT.
+    pub depth: Arc<StorageImage<Arc<StdMemoryPool>>>,
+    /// Unit face normal in the rgb channels of R16G16B16A16_SFLOAT.
+    pub normal: Arc<StorageImage<Arc<StdMemoryPool>>>,
+    /// Material id of the voxel hit, in R32_SINT.
+    pub material: Arc<StorageImage<Arc<StdMemoryPool>>>,
+}
+
+impl GBuffer {
+    pub fn new(queue: &Arc<Queue>, size: [u32; 2]) -> Self {
+        GBuffer {
+            depth: Graphics::create_image(queue, size, Format::R32_SFLOAT),
+            normal: Graphics::create_image(queue, size, Format::R16G16B16A16_SFLOAT),
+            material: Graphics::create_image(queue, size, Format::R32_SINT),
+        }
+    }
+
+    pub fn size(&self) -> [u32; 2] {
+        self.depth.dimensions().width_height()
+    }
+}
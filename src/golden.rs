@@ -0,0 +1,176 @@
+use std::{
+    env,
+    fs::{self, File},
+    io::BufWriter,
+    path::Path,
+};
+
+use crate::io::frames;
+
+/// Set to write the images rendered by the image tests as their references
+/// instead of comparing against them.
+pub const UPDATE_VAR: &str = "UPDATE_GOLDEN";
+
+/// How far a rendered image may drift from its reference before the test
+/// fails.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tolerance {
+    /// Perceptual difference, 0 to 1, a pixel may have before it counts as
+    /// changed.
+    pub pixel: f32,
+    /// Fraction of the pixels that may change.
+    pub changed: f32,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Tolerance {
+            pixel: 0.04,
+            changed: 0.002,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Comparison {
+    pub pixels: usize,
+    /// Pixels further apart than the tolerance allows.
+    pub changed: usize,
+    /// The largest difference of any pixel.
+    pub worst: f32,
+}
+
+impl Comparison {
+    pub fn passes(&self, tolerance: Tolerance) -> bool {
+        self.changed as f32 <= tolerance.changed * self.pixels as f32
+    }
+}
+
+/// How different two colors look, from 0 to 1. Weighs the channels by how
+/// sensitive the eye is to them, more so for green and for red in bright
+/// colors ("redmean").
+pub fn color_difference(a: [u8; 3], b: [u8; 3]) -> f32 {
+    let mean_red = (a[0] as f32 + b[0] as f32) / 2.0 / 255.0;
+    let [r, g, b] = [0, 1, 2].map(|i| (a[i] as f32 - b[i] as f32) / 255.0);
+    let weights = [2.0 + mean_red, 4.0, 3.0 - mean_red];
+    let distance = weights[0] * r * r + weights[1] * g * g + weights[2] * b * b;
+    // the weights add up to 9
+    (distance / 9.0).sqrt()
+}
+
+/// Compares two RGBA8 images of the same size, ignoring alpha.
+pub fn compare(expected: &[u8], actual: &[u8], tolerance: Tolerance) -> Comparison {
+    assert_eq!(expected.len(), actual.len());
+    let mut comparison = Comparison {
+        pixels: expected.len() / 4,
+        changed: 0,
+        worst: 0.0,
+    };
+    for (e, a) in expected.chunks(4).zip(actual.chunks(4)) {
+        let difference = color_difference([e[0], e[1], e[2]], [a[0], a[1], a[2]]);
+        comparison.worst = comparison.worst.max(difference);
+        if difference > tolerance.pixel {
+            comparison.changed += 1;
+        }
+    }
+    comparison
+}
+
+/// Checks RGBA8 `pixels` of the given size against the reference PNG
+/// `name` in `dir`. When they drifted apart, the rendered image is written
+/// to `failures` to look at. With `UPDATE_VAR` set, the reference is
+/// written instead.
+pub fn check(
+    dir: &Path,
+    failures: &Path,
+    name: &str,
+    size: [u32; 2],
+    pixels: &[u8],
+    tolerance: Tolerance,
+) -> Result<(), String> {
+    let reference = dir.join(format!("{}.png", name));
+    let write = |path: &Path| {
+        let file = File::create(path).unwrap();
+        frames::write_png(BufWriter::new(file), size, pixels).unwrap();
+    };
+    if env::var_os(UPDATE_VAR).is_some() {
+        fs::create_dir_all(dir).unwrap();
+        write(&reference);
+        return Ok(());
+    }
+    let (expected_size, expected) = match File::open(&reference) {
+        Ok(file) => frames::read_png(file).unwrap(),
+        Err(e) => {
+            return Err(format!(
+                "{}: {}, run with {}=1 to create it",
+                reference.display(),
+                e,
+                UPDATE_VAR
+            ))
+        }
+    };
+    let actual = failures.join(format!("{}.png", name));
+    if expected_size != size {
+        fs::create_dir_all(failures).unwrap();
+        write(&actual);
+        return Err(format!(
+            "{}: rendered {:?} but the reference is {:?}",
+            name, size, expected_size
+        ));
+    }
+    let comparison = compare(&expected, pixels, tolerance);
+    if !comparison.passes(tolerance) {
+        fs::create_dir_all(failures).unwrap();
+        write(&actual);
+        return Err(format!(
+            "{}: {} of {} pixels changed, by up to {:.3}, see {}",
+            name,
+            comparison.changed,
+            comparison.pixels,
+            comparison.worst,
+            actual.display()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(colors: &[[u8; 3]]) -> Vec<u8> {
+        colors
+            .iter()
+            .flat_map(|&[r, g, b]| [r, g, b, 255])
+            .collect()
+    }
+
+    #[test]
+    fn difference_is_weighted_by_channel() {
+        assert_eq!(0.0, color_difference([10, 20, 30], [10, 20, 30]));
+        assert!((color_difference([0; 3], [255; 3]) - 1.0).abs() < 1e-6);
+        let green = color_difference([0, 0, 0], [0, 40, 0]);
+        let blue = color_difference([0, 0, 0], [0, 0, 40]);
+        assert!(green > blue);
+    }
+
+    #[test]
+    fn small_drift_passes() {
+        let expected = image(&[[100, 100, 100]; 1000]);
+        let mut drifted = vec![[101, 99, 100]; 1000];
+        drifted[0] = [255, 0, 0];
+        let comparison = compare(&expected, &image(&drifted), Tolerance::default());
+        assert_eq!((1000, 1), (comparison.pixels, comparison.changed));
+        assert!(comparison.passes(Tolerance::default()));
+    }
+
+    #[test]
+    fn changed_pixels_fail() {
+        let expected = image(&[[100, 100, 100]; 100]);
+        let mut changed = vec![[100, 100, 100]; 100];
+        changed[..10].fill([100, 160, 100]);
+        let comparison = compare(&expected, &image(&changed), Tolerance::default());
+        assert_eq!(10, comparison.changed);
+        assert!(!comparison.passes(Tolerance::default()));
+    }
+}
@@ -0,0 +1,134 @@
+/// What device memory is held for, counted separately.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryKind {
+    /// The serialized octree the ray tracer walks.
+    Octree,
+    /// The meshed chunks the raster renderer draws.
+    Chunks,
+    /// Textures read by the shaders, like the cube map and blue noise.
+    Textures,
+    /// Images rendered into, sized after the window.
+    Images,
+}
+
+impl MemoryKind {
+    pub const ALL: [MemoryKind; 4] = [
+        MemoryKind::Octree,
+        MemoryKind::Chunks,
+        MemoryKind::Textures,
+        MemoryKind::Images,
+    ];
+}
+
+/// Bytes of device memory a renderer holds, by kind.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    bytes: [u64; MemoryKind::ALL.len()],
+}
+
+impl MemoryUsage {
+    pub fn add(&mut self, kind: MemoryKind, bytes: u64) {
+        self.bytes[kind as usize] += bytes;
+    }
+
+    pub fn get(&self, kind: MemoryKind) -> u64 {
+        self.bytes[kind as usize]
+    }
+
+    pub fn total(&self) -> u64 {
+        self.bytes.iter().sum()
+    }
+}
+
+/// Formats a size with binary units, like "1.5 GiB".
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Something held in device memory that can be dropped and made again when
+/// it's needed.
+pub struct Resident<K> {
+    pub key: K,
+    pub bytes: u64,
+    /// The last frame it was visible in.
+    pub last_visible: u64,
+}
+
+/// Picks what to evict of `resident` to free `excess` bytes, least recently
+/// visible first. What's visible in `frame` is kept, so less is freed when
+/// that's all there is.
+pub fn evictions<K: Copy>(resident: &[Resident<K>], excess: u64, frame: u64) -> Vec<K> {
+    let mut candidates: Vec<_> = resident.iter().filter(|r| r.last_visible < frame).collect();
+    candidates.sort_by_key(|r| r.last_visible);
+    let mut freed = 0;
+    candidates
+        .into_iter()
+        .take_while(|r| {
+            let more = freed < excess;
+            freed += r.bytes;
+            more
+        })
+        .map(|r| r.key)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resident(key: u32, bytes: u64, last_visible: u64) -> Resident<u32> {
+        Resident {
+            key,
+            bytes,
+            last_visible,
+        }
+    }
+
+    #[test]
+    fn usage_adds_up_by_kind() {
+        let mut usage = MemoryUsage::default();
+        usage.add(MemoryKind::Chunks, 100);
+        usage.add(MemoryKind::Chunks, 50);
+        usage.add(MemoryKind::Images, 1000);
+        assert_eq!(150, usage.get(MemoryKind::Chunks));
+        assert_eq!(0, usage.get(MemoryKind::Octree));
+        assert_eq!(1150, usage.total());
+    }
+
+    #[test]
+    fn bytes_are_formatted_with_binary_units() {
+        assert_eq!("512 B", format_bytes(512));
+        assert_eq!("1.5 KiB", format_bytes(1536));
+        assert_eq!("300.0 MiB", format_bytes(300 << 20));
+        assert_eq!("2.0 GiB", format_bytes(2 << 30));
+    }
+
+    #[test]
+    fn least_recently_visible_are_evicted_first() {
+        let resident = [
+            resident(0, 100, 7),
+            resident(1, 100, 3),
+            resident(2, 100, 5),
+            resident(3, 100, 1),
+        ];
+        assert_eq!(vec![3, 1], evictions(&resident, 150, 10));
+        assert_eq!(vec![3], evictions(&resident, 100, 10));
+        assert!(evictions(&resident, 0, 10).is_empty());
+    }
+
+    #[test]
+    fn visible_are_kept() {
+        let resident = [resident(0, 100, 10), resident(1, 100, 4)];
+        assert_eq!(vec![1], evictions(&resident, 1000, 10));
+    }
+}
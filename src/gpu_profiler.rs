@@ -0,0 +1,265 @@
+use std::{sync::Arc, time::Duration};
+
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
+    device::Queue,
+    query::{QueryPool, QueryPoolCreateInfo, QueryResultFlags, QueryType},
+    sync::PipelineStage,
+};
+
+use crate::stats::FrameStats;
+
+// frames that may be in flight at once, each gets its own range of queries
+const FRAME_SLOTS: u32 = 4;
+// number of frames the reported timings are averaged over
+const HISTORY: usize = 120;
+
+/// Sections of a frame's command buffer that are timed separately.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GpuZone {
+    Clear,
+    Raytrace,
+    Denoise,
+    Bloom,
+    Taa,
+    Fxaa,
+    Upscale,
+    /// The crosshair, hotbar and break progress, see hud.rs.
+    Hud,
+    Blit,
+    /// The lines of `debug_draw`, when there are any.
+    Lines,
+    /// Tracing the minimap, on the frames it's updated.
+    Minimap,
+}
+
+impl GpuZone {
+    pub const ALL: [GpuZone; 11] = [
+        GpuZone::Clear,
+        GpuZone::Raytrace,
+        GpuZone::Denoise,
+        GpuZone::Bloom,
+        GpuZone::Taa,
+        GpuZone::Fxaa,
+        GpuZone::Upscale,
+        GpuZone::Hud,
+        GpuZone::Blit,
+        GpuZone::Lines,
+        GpuZone::Minimap,
+    ];
+
+    fn index(self) -> u32 {
+        self as u32
+    }
+
+    // zones of the trace command buffer, the others post-process and present
+    fn traces(self) -> bool {
+        matches!(self, GpuZone::Clear | GpuZone::Raytrace | GpuZone::Minimap)
+    }
+}
+
+const ZONE_COUNT: u32 = GpuZone::ALL.len() as u32;
+
+/// Times zones of the frame command buffer with timestamp queries. Results
+/// are read back `FRAME_SLOTS` frames later so the CPU never waits on them.
+pub struct GpuProfiler {
+    // None if the queue doesn't support timestamps
+    pool: Option<Arc<QueryPool>>,
+    // nanoseconds per timestamp tick
+    period: f32,
+    slot: u32,
+    // bit mask of the zones written in each slot
+    written: [u32; FRAME_SLOTS as usize],
+    stats: Vec<FrameStats>,
+    // sum of the zones of the latest frame read back
+    last_frame_time: Option<Duration>,
+    // first and last tick of the post-processing of the latest frame read back
+    last_post: Option<[u64; 2]>,
+    overlap: FrameStats,
+}
+
+impl GpuProfiler {
+    pub fn new(queue: &Arc<Queue>) -> Self {
+        let device = queue.device();
+        let pool = queue.family().timestamp_valid_bits().map(|_| {
+            QueryPool::new(
+                device.clone(),
+                QueryPoolCreateInfo {
+                    query_count: FRAME_SLOTS * ZONE_COUNT * 2,
+                    ..QueryPoolCreateInfo::query_type(QueryType::Timestamp)
+                },
+            )
+            .unwrap()
+        });
+        GpuProfiler {
+            pool,
+            period: device.physical_device().properties().timestamp_period,
+            slot: 0,
+            written: [0; FRAME_SLOTS as usize],
+            stats: GpuZone::ALL
+                .iter()
+                .map(|_| FrameStats::new(HISTORY))
+                .collect(),
+            last_frame_time: None,
+            last_post: None,
+            overlap: FrameStats::new(HISTORY),
+        }
+    }
+
+    /// Moves to the next slot, collecting the timings it holds from an earlier
+    /// frame, and resets its queries. Must be recorded before any zone.
+    pub fn begin_frame(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) {
+        let pool = match &self.pool {
+            Some(pool) => pool.clone(),
+            None => return,
+        };
+        self.slot = (self.slot + 1) % FRAME_SLOTS;
+        let mut frame_time = None;
+        let mut trace = None;
+        let mut post = None;
+        for zone in GpuZone::ALL {
+            if self.written[self.slot as usize] & (1 << zone.index()) == 0 {
+                continue;
+            }
+            let start = self.query(zone);
+            let mut ticks = [0u64; 2];
+            let available = pool
+                .queries_range(start..start + 2)
+                .unwrap()
+                .get_results(&mut ticks, QueryResultFlags::default());
+            if let Ok(true) = available {
+                let time = ticks_to_duration(ticks, self.period);
+                self.stats[zone.index() as usize].record(time);
+                *frame_time.get_or_insert(Duration::ZERO) += time;
+                let span = if zone.traces() { &mut trace } else { &mut post };
+                *span = Some(span_union(*span, ticks));
+            }
+        }
+        if frame_time.is_some() {
+            self.last_frame_time = frame_time;
+        }
+        if let (Some(trace), Some(last_post)) = (trace, self.last_post) {
+            self.overlap.record(ticks_to_duration(
+                span_intersection(trace, last_post),
+                self.period,
+            ));
+        }
+        self.last_post = post;
+        self.written[self.slot as usize] = 0;
+        let base = self.slot * ZONE_COUNT * 2;
+        unsafe {
+            builder
+                .reset_query_pool(pool, base..base + ZONE_COUNT * 2)
+                .unwrap();
+        }
+    }
+
+    pub fn begin(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        zone: GpuZone,
+    ) {
+        self.write(builder, self.query(zone), PipelineStage::TopOfPipe);
+    }
+
+    pub fn end(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        zone: GpuZone,
+    ) {
+        self.write(builder, self.query(zone) + 1, PipelineStage::BottomOfPipe);
+        self.written[self.slot as usize] |= 1 << zone.index();
+    }
+
+    /// Average GPU time of each zone over the last `HISTORY` frames it was
+    /// recorded in. Zones that were never recorded are left out.
+    pub fn timings(&self) -> Vec<(GpuZone, Duration)> {
+        GpuZone::ALL
+            .into_iter()
+            .zip(&self.stats)
+            .filter(|(_, stats)| !stats.is_empty())
+            .map(|(zone, stats)| (zone, stats.average()))
+            .collect()
+    }
+
+    /// Average time a frame's tracing ran alongside the post-processing of the
+    /// frame before, measured from the timestamps of both queues. Zero when
+    /// they're submitted to one queue, None without timestamps.
+    pub fn overlap(&self) -> Option<Duration> {
+        if self.overlap.is_empty() {
+            None
+        } else {
+            Some(self.overlap.average())
+        }
+    }
+
+    /// GPU time of the latest frame whose timings were read back, None
+    /// without timestamps.
+    pub fn last_frame_time(&self) -> Option<Duration> {
+        self.last_frame_time
+    }
+
+    fn query(&self, zone: GpuZone) -> u32 {
+        (self.slot * ZONE_COUNT + zone.index()) * 2
+    }
+
+    fn write(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        query: u32,
+        stage: PipelineStage,
+    ) {
+        if let Some(pool) = &self.pool {
+            unsafe {
+                builder.write_timestamp(pool.clone(), query, stage).unwrap();
+            }
+        }
+    }
+}
+
+fn ticks_to_duration(ticks: [u64; 2], period: f32) -> Duration {
+    Duration::from_nanos((ticks[1].saturating_sub(ticks[0]) as f64 * period as f64) as u64)
+}
+
+fn span_union(span: Option<[u64; 2]>, ticks: [u64; 2]) -> [u64; 2] {
+    match span {
+        Some(span) => [span[0].min(ticks[0]), span[1].max(ticks[1])],
+        None => ticks,
+    }
+}
+
+// as a [start, end] pair of ticks, empty if they don't intersect
+fn span_intersection(a: [u64; 2], b: [u64; 2]) -> [u64; 2] {
+    [a[0].max(b[0]), a[1].min(b[1])]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticks_scale_by_period() {
+        assert_eq!(
+            Duration::from_micros(2),
+            ticks_to_duration([1000, 1500], 4.0)
+        );
+    }
+
+    #[test]
+    fn reversed_ticks_are_zero() {
+        assert_eq!(Duration::ZERO, ticks_to_duration([10, 5], 1.0));
+    }
+
+    #[test]
+    fn overlap_is_the_shared_ticks() {
+        let trace = span_union(Some([100, 200]), [150, 300]);
+        assert_eq!([100, 300], trace);
+        let overlap = span_intersection(trace, [50, 180]);
+        assert_eq!(Duration::from_nanos(80), ticks_to_duration(overlap, 1.0));
+        let apart = span_intersection(trace, [400, 500]);
+        assert_eq!(Duration::ZERO, ticks_to_duration(apart, 1.0));
+    }
+}
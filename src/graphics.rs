@@ -1,320 +1,678 @@
-use rand::{self, Rng};
-use std::{io::Cursor, sync::Arc};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Cursor},
+    mem,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use vulkano::{
-    buffer::{BufferUsage, CpuAccessibleBuffer},
+    buffer::{
+        BufferAccess, BufferUsage, CpuAccessibleBuffer, DeviceLocalBuffer, TypedBufferAccess,
+    },
     command_buffer::{
-        AutoCommandBufferBuilder, BlitImageInfo, ClearColorImageInfo, CommandBufferUsage,
-        CopyBufferToImageInfo, PrimaryCommandBuffer,
+        AutoCommandBufferBuilder, BlitImageInfo, BufferCopy, ClearColorImageInfo,
+        CommandBufferUsage, CopyBufferInfo, CopyBufferInfoTyped, CopyImageToBufferInfo, ImageBlit,
+        PrimaryAutoCommandBuffer, PrimaryCommandBuffer,
     },
     descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet},
     device::{
-        physical::{PhysicalDevice, PhysicalDeviceType},
-        Device, DeviceCreateInfo, DeviceExtensions, Features, Queue, QueueCreateInfo,
+        physical::{PhysicalDevice, PhysicalDeviceType, QueueFamily},
+        Device, DeviceCreateInfo, DeviceCreationError, DeviceExtensions, Features, Queue,
+        QueueCreateInfo,
     },
     format::Format,
     image::{
         view::{ImageView, ImageViewCreateInfo, ImageViewType},
-        ImageAccess, ImageCreateFlags, ImageDimensions, ImageLayout, ImageUsage, StorageImage,
-        SwapchainImage,
+        ImageAccess, ImageCreateFlags, ImageDimensions, ImageLayout, ImageUsage, ImmutableImage,
+        MipmapsCount, StorageImage, SwapchainImage,
     },
     memory::pool::StdMemoryPool,
     pipeline::{ComputePipeline, Pipeline, PipelineBindPoint},
+    sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode},
     swapchain::{
-        acquire_next_image, AcquireError, Surface, SurfaceInfo, Swapchain, SwapchainCreateInfo,
-        SwapchainCreationError,
+        acquire_next_image, AcquireError, PresentMode, Surface, SurfaceInfo, Swapchain,
+        SwapchainCreateInfo, SwapchainCreationError,
     },
-    sync::{self, FlushError, GpuFuture},
+    sync::{self, FenceSignalFuture, FlushError, GpuFuture},
 };
 
+use tracing::{debug_span, error, info, warn};
+use vecmath::Vector3;
 use winit::window::Window;
 
-use crate::octree::Octree;
+use crate::{
+    aabc::Aabc,
+    accumulation::Accumulation,
+    assets::{TextureId, TextureManager, FACES},
+    bloom::Bloom,
+    blue_noise, camera,
+    debug_draw::Line,
+    decals::DecalList,
+    denoise::Denoiser,
+    entity::EntityList,
+    environment::CubeMap,
+    frame_budget::{FrameBudget, Quality},
+    frame_graph::FrameGraph,
+    frames_in_flight::{self, FramesInFlight, DEFAULT_FRAMES_IN_FLIGHT},
+    fxaa::Fxaa,
+    gbuffer::GBuffer,
+    gpu_memory::{MemoryKind, MemoryUsage},
+    gpu_profiler::{GpuProfiler, GpuZone},
+    hud::Hud,
+    io::frames,
+    light::LightVolume,
+    line_overlay::LineOverlay,
+    materials::{MaterialId, MaterialRegistry},
+    mipmaps,
+    octree::Octree,
+    octree_layout::{OctreeLayout, Patch},
+    pipelines::{PermutationCache, ShaderFeatures},
+    recorder::{FrameRecorder, RecordingSummary},
+    render_mode::RenderMode,
+    render_scale::RenderScale,
+    scheduler::{DirtyChunks, Workers},
+    shadows::Shadows,
+    status::Status,
+    taa::Taa,
+    tiles::{self, Tile},
+    transfer::Uploader,
+    upscale::Upscaler,
+    view_distance::ViewDistance,
+    workgroups,
+};
+
+#[cfg(feature = "hot-reload")]
+use crate::shader_reload::ShaderReloader;
 
-use self::cs::ty::CameraInfo;
+use self::cs::ty::{CameraInfo, FrameInfo, HudInfo, LightInfo, Lighting};
 
 pub const COMPUTE_GROUP_SIZE: u32 = 8;
+// number of frames traced per workgroup size when autotuning
+const AUTOTUNE_FRAMES: u32 = 8;
+// path traced samples after which the image counts as converged
+const MAX_PATH_SAMPLES: u32 = 4096;
+// bounces of a path traced sample without a target frame rate, see
+// MAX_BOUNCES in graphics.comp
+const MAX_BOUNCES: i32 = 6;
+// width and height of the blue noise tile, which is generated at startup
+const BLUE_NOISE_SIZE: u32 = 32;
+// texels sampled along the longer axis of a grazing footprint
+const MAX_ANISOTROPY: f32 = 8.0;
+// the minimap in the top right corner, traced again every few frames
+const MINIMAP_SIZE: u32 = 192;
+const MINIMAP_MARGIN: u32 = 16;
+const MINIMAP_INTERVAL: u32 = 10;
+// voxels across the minimap
+const MINIMAP_WIDTH: f32 = 256.0;
+// how far the outline of a frozen cull camera reaches
+const FROZEN_FRUSTUM_LENGTH: f32 = 64.0;
+const FROZEN_FRUSTUM_COLOR: [f32; 3] = [0.2, 1.0, 1.0];
+// dirty chunks whose octree nodes are serialized again per frame, see
+// update_chunks
+const MAX_CHUNKS_PER_FRAME: usize = 2;
+const PATCH_THREADS: usize = 2;
+
+// order in which present modes are cycled through
+const PRESENT_MODE_CYCLE: [PresentMode; 3] = [
+    PresentMode::Fifo,
+    PresentMode::Mailbox,
+    PresentMode::Immediate,
+];
+
+// signaled when a submitted frame is done on the GPU
+type FrameEnd = Arc<FenceSignalFuture<Box<dyn GpuFuture>>>;
+
 pub struct Graphics {
     surface: Arc<Surface<Window>>,
     pub recreate_swapchain: bool,
-    previous_frame_end: Option<Box<dyn GpuFuture>>,
+    // see Renderer::is_lost
+    lost: bool,
+    present_mode: PresentMode,
+    supported_present_modes: Vec<PresentMode>,
+    frames: FramesInFlight<FrameEnd>,
+    // with a compute queue, the ends of the last two frames' tracing. The
+    // next trace starts after the last one rather than after its whole frame
+    traces: FramesInFlight<FrameEnd>,
     swapchain: Arc<Swapchain<Window>>,
     swapchain_images: Vec<Arc<SwapchainImage<Window>>>,
+    // HDR, converted to 8 bits by the bloom composite
     storage_image: Arc<StorageImage<Arc<StdMemoryPool>>>,
+    gbuffer: GBuffer,
+    // with a compute queue, what the next frame traces into, swapped with the
+    // two above every frame so a frame doesn't overwrite the one before while
+    // it's still post-processed
+    spare_targets: Option<(Arc<StorageImage<Arc<StdMemoryPool>>>, GBuffer)>,
+    // path traced samples, see graphics.comp
+    accumulation_image: Arc<StorageImage<Arc<StdMemoryPool>>>,
+    accumulation: Accumulation,
+    render_scale: RenderScale,
+    frame_budget: FrameBudget,
+    render_mode: RenderMode,
+    last_frame: Option<Instant>,
     queue: Arc<Queue>,
-    compute_pipeline: Arc<ComputePipeline>,
+    // a second queue of the graphics family post-processing and presenting
+    // frames while the graphics queue traces the next, so images need no
+    // sharing between families
+    compute_queue: Option<Arc<Queue>>,
+    pipelines: PermutationCache,
+    #[cfg(feature = "hot-reload")]
+    shader_reloader: ShaderReloader,
+    shader_features: ShaderFeatures,
+    camera: CameraInfo,
+    // outlined while frozen, see update_cull_camera
+    cull_camera: Option<CameraInfo>,
     camera_info: Arc<CpuAccessibleBuffer<cs::ty::CameraInfo>>,
-    cube_map_array: Arc<ImageView<StorageImage>>,
-    octree_buffer: Arc<CpuAccessibleBuffer<[i32]>>,
+    // the six faces of each material's texture with their mip chains, see
+    // create_material_textures
+    material_textures: Arc<ImageView<ImmutableImage>>,
+    // every texture in material_textures, which grows with the files
+    // materials name
+    textures: TextureManager,
+    // the texture of each row of cubemap.png, which is what materials refer
+    // to, see TextureManager
+    cubemap_textures: Vec<TextureId>,
+    texture_sampler: Arc<Sampler>,
+    blue_noise: Arc<ImageView<StorageImage>>,
+    light_volume: Arc<ImageView<StorageImage>>,
+    // a black placeholder while the sky is a plain color, see set_environment
+    environment: Arc<ImageView<StorageImage>>,
+    environment_sampler: Arc<Sampler>,
+    environment_enabled: bool,
+    light_info: Arc<CpuAccessibleBuffer<LightInfo>>,
+    // counts the frames, seeding the noise in graphics.comp
+    frame_seed: u32,
+    octree_buffer: Arc<DeviceLocalBuffer<[i32]>>,
+    // swapped in for octree_buffer when the next frame starts
+    next_octree_buffer: Option<Arc<DeviceLocalBuffer<[i32]>>>,
+    // where the nodes are in octree_buffer, None until the first tree is
+    // uploaded with room to patch, see update_chunks
+    octree_layout: Option<OctreeLayout>,
+    octree_patcher: Workers<PatchJob, (u64, Aabc, Vec<i32>)>,
+    // the id of the latest job for each node being serialized again
+    patching: HashMap<Aabc, u64>,
+    next_patch: u64,
+    // written into a copy of octree_buffer by the next frame
+    octree_patches: Vec<Patch>,
+    uploader: Uploader,
+    decal_buffer: Arc<CpuAccessibleBuffer<[i32]>>,
+    entity_buffer: Arc<CpuAccessibleBuffer<[f32]>>,
+    material_buffer: Arc<CpuAccessibleBuffer<[f32]>>,
+    // for the frames of animated textures, see texture_frame_buffer
+    materials: MaterialRegistry,
+    hud_info: Arc<CpuAccessibleBuffer<HudInfo>>,
+    lighting: Arc<CpuAccessibleBuffer<Lighting>>,
+    // what the lighting and decal buffers hold, which path tracing starts
+    // over when they change
+    lighting_values: Lighting,
+    decal_values: Vec<i32>,
+    // animates the wobble behind translucent voxels
+    started: Instant,
+    denoiser: Denoiser,
+    denoise_enabled: bool,
+    bloom: Bloom,
+    bloom_enabled: bool,
+    taa: Taa,
+    taa_enabled: bool,
+    fxaa: Fxaa,
+    fxaa_enabled: bool,
+    shadows: Shadows,
+    chunk_borders: bool,
+    // voxels rays go before giving up, see ViewDistance
+    view_distance: f32,
+    // see set_max_depth
+    max_depth: Option<u32>,
+    minimap_enabled: bool,
+    minimap_image: Arc<StorageImage<Arc<StdMemoryPool>>>,
+    // with a compute queue, what the minimap is traced into next while frames
+    // still presenting show the one above
+    spare_minimap: Option<Arc<StorageImage<Arc<StdMemoryPool>>>>,
+    // frames until the minimap is traced again
+    minimap_countdown: u32,
+    upscaler: Upscaler,
+    hud: Hud,
+    line_overlay: LineOverlay,
+    // drawn over the next frames until replaced, see debug_draw
+    debug_lines: Vec<Line>,
+    profiler: GpuProfiler,
+    recorder: Option<FrameRecorder>,
 }
 
 #[derive(Debug)]
 pub enum GraphicsCreationError {
-    CubeMapImageNotRGBA,
+    NoSuitableDevice,
+    /// The device was found but couldn't be opened, e.g. right after it was
+    /// lost.
+    Device(DeviceCreationError),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct UnsupportedPresentMode(pub PresentMode);
+
+/// What can be changed about a ray tracer while it runs, carried over to the
+/// one replacing it after its device was lost, see `Graphics::take_settings`.
+pub struct RenderSettings {
+    target_fps: Option<f32>,
+    target_frame_time: Option<Duration>,
+    render_scale: f32,
+    frames_in_flight: usize,
+    present_mode: PresentMode,
+    shader_features: ShaderFeatures,
+    render_mode: RenderMode,
+    shadows: Shadows,
+    taa_enabled: bool,
+    denoise_enabled: bool,
+    bloom_enabled: bool,
+    fxaa_enabled: bool,
+    minimap_enabled: bool,
+    chunk_borders: bool,
+    max_depth: Option<u32>,
+    recorder: Option<FrameRecorder>,
+}
+
+// what the passes of a frame read and write, see `FrameGraph`
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FrameResource {
+    // the frame's color, whichever image the last pass left it in
+    Color,
+    GBuffer,
+    Minimap,
+    Octree,
+    Swapchain,
+}
+
+// a node of the octree to serialize again on a worker
+struct PatchJob {
+    id: u64,
+    node: Aabc,
+    leaves: Vec<(Vector3<i32>, MaterialId)>,
+}
+
+// what the passes of a frame record into
+struct Frame<'a> {
+    builder: AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    profiler: &'a mut GpuProfiler,
+    // the image the color passes take and replace, the HDR image at first
+    color: Arc<StorageImage<Arc<StdMemoryPool>>>,
+}
+
+impl<'a> Frame<'a> {
+    fn new(
+        queue: &Arc<Queue>,
+        profiler: &'a mut GpuProfiler,
+        color: Arc<StorageImage<Arc<StdMemoryPool>>>,
+    ) -> Self {
+        let builder = AutoCommandBufferBuilder::primary(
+            queue.device().clone(),
+            queue.family(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        Frame {
+            builder,
+            profiler,
+            color,
+        }
+    }
+
+    // ends the commands recorded so far, going on in a new command buffer
+    // for `queue`
+    fn next(self, queue: &Arc<Queue>) -> (PrimaryAutoCommandBuffer, Self) {
+        let commands = self.builder.build().unwrap();
+        (commands, Frame::new(queue, self.profiler, self.color))
+    }
+
+    // records the commands of `f` timed as `zone`
+    fn timed<T>(
+        &mut self,
+        zone: GpuZone,
+        f: impl FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) -> T,
+    ) -> T {
+        self.profiler.begin(&mut self.builder, zone);
+        let result = f(&mut self.builder);
+        self.profiler.end(&mut self.builder, zone);
+        result
+    }
 }
 
 impl Graphics {
+    /// Draws `tree` into the window of `surface`. The renderer makes no world
+    /// of its own, whatever is in `tree` is drawn, see `update_octree` and
+    /// `update_serialized_octree` to change it.
     pub fn new(
         surface: Arc<Surface<Window>>,
         camera_info: CameraInfo,
+        tree: &Octree<MaterialId>,
+        materials: &MaterialRegistry,
+        mut lighting: Lighting,
     ) -> Result<Self, GraphicsCreationError> {
+        let shadows = Shadows::default();
+        shadows.apply_to_sun(&mut lighting);
         let device_extensions = DeviceExtensions {
             khr_swapchain: true,
             ..DeviceExtensions::none()
         };
+        let (physical_device, queue_family) =
+            select_physical_device(&surface, &device_extensions, &Features::none())
+                .ok_or(GraphicsCreationError::NoSuitableDevice)?;
+        // sharpens textures seen at a grazing angle where supported
         let features = Features {
-            image_cube_array: true,
+            sampler_anisotropy: physical_device.supported_features().sampler_anisotropy,
             ..Features::none()
         };
-        let (physical_device, queue_family) = PhysicalDevice::enumerate(surface.instance())
-            .filter(|&p| p.supported_extensions().is_superset_of(&device_extensions))
-            .filter(|p| p.supported_features().is_superset_of(&features))
-            .filter_map(|p| {
-                p.queue_families()
-                    .find(|&q| {
-                        q.supports_graphics() && q.supports_surface(&surface).unwrap_or(false)
-                    })
-                    .map(|q| (p, q))
-            })
-            .min_by_key(|(p, _)| match p.properties().device_type {
-                PhysicalDeviceType::DiscreteGpu => 0,
-                PhysicalDeviceType::IntegratedGpu => 1,
-                PhysicalDeviceType::VirtualGpu => 2,
-                PhysicalDeviceType::Cpu => 3,
-                PhysicalDeviceType::Other => 4,
-            })
-            .expect("No suitable physical device found");
 
-        println!(
-            "Using device: {} (type: {:?})",
-            physical_device.properties().device_name,
-            physical_device.properties().device_type,
+        info!(
+            device = %physical_device.properties().device_name,
+            kind = ?physical_device.properties().device_type,
+            "Ray tracing"
         );
 
+        // a transfer-only family usually maps to a dedicated copy engine
+        let transfer_family = physical_device.queue_families().find(|&q| {
+            q.explicitly_supports_transfers() && !q.supports_graphics() && !q.supports_compute()
+        });
+        // post-processes a frame while the graphics queue traces the next
+        let async_compute = queue_family.queues_count() > 1;
+        let mut queue_create_infos = vec![QueueCreateInfo {
+            queues: if async_compute {
+                vec![0.5, 0.5]
+            } else {
+                vec![0.5]
+            },
+            ..QueueCreateInfo::family(queue_family)
+        }];
+        if let Some(family) = transfer_family {
+            queue_create_infos.push(QueueCreateInfo::family(family));
+        }
+
         // TODO [Rust Question] Why can't we add explicit type annotations here?
         let (device, mut queues) = Device::new(
             physical_device,
             DeviceCreateInfo {
                 enabled_extensions: device_extensions,
                 enabled_features: features,
-                queue_create_infos: vec![QueueCreateInfo::family(queue_family)],
+                queue_create_infos,
 
                 ..DeviceCreateInfo::default()
             },
         )
-        .unwrap();
+        .map_err(GraphicsCreationError::Device)?;
 
         let queue = queues.next().unwrap();
+        let compute_queue = async_compute.then(|| queues.next().unwrap());
+        if compute_queue.is_some() {
+            info!("Post-processing on a separate compute queue");
+        }
+        let transfer_queue = queues.next().unwrap_or_else(|| queue.clone());
+        let mut uploader = Uploader::new(queue.clone(), transfer_queue);
 
-        let image_format = Some(
-            physical_device
-                .surface_formats(&surface, SurfaceInfo::default())
-                .unwrap()[0]
-                .0,
-        );
-
-        let (swapchain, swapchain_images) = {
-            let surface_capabilities = physical_device
-                .surface_capabilities(&surface, SurfaceInfo::default())
-                .unwrap();
-            Swapchain::new(
-                device.clone(),
-                surface.clone(),
-                SwapchainCreateInfo {
-                    min_image_count: surface_capabilities.min_image_count,
-                    image_format,
-                    image_extent: surface.window().inner_size().into(),
-                    image_usage: ImageUsage {
-                        transfer_dst: true,
-                        ..ImageUsage::color_attachment()
-                    },
-                    composite_alpha: surface_capabilities
-                        .supported_composite_alpha
-                        .iter()
-                        .next()
-                        .unwrap(),
-                    ..SwapchainCreateInfo::default()
-                },
-            )
+        let supported_present_modes: Vec<PresentMode> = physical_device
+            .surface_present_modes(&surface)
             .unwrap()
-        };
-
-        let size = swapchain_images[0].dimensions().width_height();
-
-        let storage_image = StorageImage::new(
+            .collect();
+        // Fifo is the only mode guaranteed to be supported
+        let present_mode = PresentMode::Fifo;
+        let (swapchain, swapchain_images) = create_swapchain(
             device.clone(),
-            ImageDimensions::Dim2d {
-                width: size[0],
-                height: size[1],
-                array_layers: 1,
-            },
-            Format::R8G8B8A8_UNORM,
-            [queue.family()],
-        )
-        .unwrap();
-
-        let cs = cs::load(device.clone()).unwrap();
+            surface.clone(),
+            present_mode,
+            DEFAULT_FRAMES_IN_FLIGHT,
+        );
 
-        let compute_pipeline = ComputePipeline::new(
-            device.clone(),
-            cs.entry_point("main").unwrap(),
-            &(),
-            None,
-            |_| {},
-        )
-        .unwrap();
+        let size = swapchain_images[0].dimensions().width_height();
 
-        let png_bytes = include_bytes!("cubemap.png").to_vec();
-        let cursor = Cursor::new(png_bytes.clone());
-        let mut decoder = png::Decoder::new(cursor);
-        if decoder.read_header_info().unwrap().color_type != png::ColorType::Rgba {
-            return Err(GraphicsCreationError::CubeMapImageNotRGBA);
-        }
-        let mut reader = decoder.read_info().unwrap();
-        let info = reader.info();
-        let (width, height) = (info.width, info.height);
-        let mut image_data = Vec::new();
-        image_data.resize((width * height * 4) as usize, 0);
-        reader.next_frame(&mut image_data).unwrap();
-        let face_size = width / 6;
-        let dimensions = ImageDimensions::Dim2d {
-            width: face_size,
-            height: face_size,
-            array_layers: (36 * height) / width,
-        };
+        let render_scale = RenderScale::new(1.0);
+        let storage_image = Self::create_hdr_image(&queue, render_scale.apply(size));
+        let gbuffer = GBuffer::new(&queue, render_scale.apply(size));
+        let spare_targets = compute_queue.as_ref().map(|_| {
+            (
+                Self::create_hdr_image(&queue, render_scale.apply(size)),
+                GBuffer::new(&queue, render_scale.apply(size)),
+            )
+        });
+        let accumulation_image = Self::create_accumulation_image(&queue, render_scale.apply(size));
+        let minimap_image = Self::create_hdr_image(&queue, [MINIMAP_SIZE; 2]);
+        let spare_minimap = compute_queue
+            .as_ref()
+            .map(|_| Self::create_hdr_image(&queue, [MINIMAP_SIZE; 2]));
+        let denoiser = Denoiser::new(&queue, render_scale.apply(size));
+        let bloom = Bloom::new(&queue, render_scale.apply(size));
+        let taa = Taa::new(&queue, render_scale.apply(size));
+        let fxaa = Fxaa::new(&queue, render_scale.apply(size));
+        let upscaler = Upscaler::new(&queue, size);
+        let hud = Hud::new(&queue, size);
+        let line_overlay = LineOverlay::new(&queue, &swapchain_images);
+        let profiler = GpuProfiler::new(&queue);
 
-        let data = image_data.as_slice();
-        let mut reshaped_image_data = Vec::new();
-        let n_cubemaps = height / face_size;
-        for l in 0..n_cubemaps {
-            for i in 0..6 {
-                for j in 0..face_size {
-                    let start = (j * 6 + i + l * 6 * face_size) * 4 * face_size;
-                    let end = start + face_size * 4;
-                    let mut part = data[start as usize..end as usize].to_vec();
-                    reshaped_image_data.append(&mut part);
-                }
-            }
-        }
+        let shader_features = ShaderFeatures::default();
+        let mut pipelines = PermutationCache::new(device.clone());
+        pipelines.get(shader_features);
+        pipelines.prefetch_neighbors(shader_features);
 
-        let tex_image = StorageImage::with_usage(
+        // converted to RGBA whatever its color type
+        let cursor = Cursor::new(include_bytes!("cubemap.png").as_slice());
+        let (size, pixels) = frames::read_png(cursor).unwrap();
+        let mut textures = TextureManager::new();
+        let cubemap_textures = textures.add_atlas(size, &pixels).unwrap();
+        let texture_ids = Self::material_texture_ids(&mut textures, &cubemap_textures, materials);
+        let material_textures = Self::create_material_textures(
             device.clone(),
-            dimensions,
-            Format::R8G8B8A8_UNORM,
-            ImageUsage {
-                transfer_dst: true,
-                storage: true,
-                ..ImageUsage::none()
-            },
-            ImageCreateFlags {
-                cube_compatible: true,
-                ..Default::default()
-            },
-            Some(queue_family),
-        )
-        .unwrap();
-        let mut cbb = AutoCommandBufferBuilder::primary(
+            &mut uploader,
+            textures.face_size(),
+            textures.take_upload().unwrap(),
+        );
+        let texture_sampler = Self::create_texture_sampler(device.clone());
+        let blue_noise = Self::create_blue_noise(device.clone(), &mut uploader);
+        // fully sky lit until the first volume arrives
+        let light_volume = Self::create_light_volume(
             device.clone(),
-            queue.family(),
-            CommandBufferUsage::OneTimeSubmit,
-        )
-        .unwrap();
-        let image_data_buf = CpuAccessibleBuffer::from_iter(
-            queue.device().clone(),
-            BufferUsage::transfer_src(),
-            false,
-            reshaped_image_data,
-        )
-        .unwrap();
-
-        cbb.copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(
-            image_data_buf.clone(),
-            tex_image.clone(),
-        ))
-        .unwrap();
-        let cb = cbb.build().unwrap();
-        let tex_future = match cb.execute(queue.clone()) {
-            Ok(f) => f,
-            Err(e) => unreachable!("{:?}", e),
-        };
-        let cube_map_array = ImageView::new(
-            tex_image.clone(),
-            ImageViewCreateInfo {
-                view_type: ImageViewType::CubeArray,
-                ..ImageViewCreateInfo::from_image(&tex_image)
+            &mut uploader,
+            &LightVolume {
+                origin: [0; 3],
+                size: 1,
+                levels: vec![0],
             },
-        )
-        .unwrap();
-        let mut tree = Octree::new();
-        for i in -5..5 {
-            for j in -5..5 {
-                for k in -5..5 {
-                    let place_block = rand::thread_rng().gen_range(0..12);
-                    if place_block == 0 {
-                        tree.insert_leaf(5, [i, j, k]);
-                    }
-                }
-            }
-        }
-
-        let octree_buffer = CpuAccessibleBuffer::from_iter(
+        );
+        let environment = Self::create_environment(
             device.clone(),
-            BufferUsage {
-                storage_buffer: true,
-                ..BufferUsage::none()
+            &mut uploader,
+            &CubeMap {
+                face_size: 1,
+                texels: vec![[0.0; 3]; 6],
             },
-            false,
-            tree.serialize(),
-        )
-        .unwrap();
+        );
+        let octree_buffer = Self::create_octree_buffer(&mut uploader, tree.serialize());
 
-        Ok(Self {
+        let mut graphics = Self {
             surface,
             recreate_swapchain: false,
-            previous_frame_end: Some(tex_future.boxed()),
+            lost: false,
+            present_mode,
+            supported_present_modes,
+            frames: FramesInFlight::new(DEFAULT_FRAMES_IN_FLIGHT),
+            traces: FramesInFlight::new(2),
             swapchain,
             swapchain_images,
             storage_image,
+            gbuffer,
+            spare_targets,
+            accumulation_image,
+            accumulation: Accumulation::new(MAX_PATH_SAMPLES),
+            render_scale,
+            frame_budget: FrameBudget::new(),
+            render_mode: RenderMode::Shaded,
+            last_frame: None,
             queue,
-            compute_pipeline,
-            camera_info: Self::create_camera_info_buffer(device, camera_info),
-            cube_map_array,
+            compute_queue,
+            pipelines,
+            #[cfg(feature = "hot-reload")]
+            shader_reloader: ShaderReloader::new(),
+            shader_features,
+            camera: camera_info,
+            cull_camera: None,
+            camera_info: Self::create_camera_info_buffer(device.clone(), camera_info),
+            material_textures,
+            material_buffer: Self::create_material_buffer(device.clone(), materials, &texture_ids),
+            textures,
+            cubemap_textures,
+            materials: materials.clone(),
+            texture_sampler,
+            blue_noise,
+            light_volume,
+            environment,
+            environment_sampler: Self::create_environment_sampler(device.clone()),
+            environment_enabled: false,
+            light_info: Self::create_light_info_buffer(
+                device.clone(),
+                LightInfo {
+                    origin: [0; 3],
+                    size: 0,
+                },
+            ),
+            frame_seed: 0,
             octree_buffer,
-        })
+            next_octree_buffer: None,
+            octree_layout: None,
+            octree_patcher: Workers::new(PATCH_THREADS, |job: PatchJob| {
+                let words = Octree::serialize_node(job.node, job.leaves);
+                (job.id, job.node, words)
+            }),
+            patching: HashMap::new(),
+            next_patch: 0,
+            octree_patches: Vec::new(),
+            uploader,
+            decal_buffer: Self::create_decal_buffer(device.clone(), DecalList::new().serialize()),
+            entity_buffer: Self::create_entity_buffer(device.clone(), &EntityList::new()),
+            lighting: Self::create_lighting_buffer(device.clone(), lighting),
+            lighting_values: lighting,
+            decal_values: Vec::new(),
+            hud_info: Self::create_hud_info_buffer(
+                device,
+                HudInfo {
+                    preview_pos: [0; 3],
+                    break_progress: 0.0,
+                    preview_material: 0,
+                    preview_rotation: 0,
+                    hotbar_len: 0,
+                    hotbar_selected: 0,
+                    hotbar: [[0; 4]; 3],
+                },
+            ),
+            started: Instant::now(),
+            denoiser,
+            denoise_enabled: false,
+            bloom,
+            bloom_enabled: true,
+            taa,
+            taa_enabled: true,
+            fxaa,
+            fxaa_enabled: true,
+            shadows,
+            chunk_borders: false,
+            view_distance: ViewDistance::default().voxels(),
+            max_depth: None,
+            minimap_enabled: false,
+            minimap_image,
+            spare_minimap,
+            minimap_countdown: 0,
+            upscaler,
+            hud,
+            line_overlay,
+            debug_lines: Vec::new(),
+            profiler,
+            recorder: None,
+        };
+        graphics.autotune_workgroup_size();
+        Ok(graphics)
     }
 
     pub fn redraw(&mut self) {
+        let _span = debug_span!("redraw").entered();
         let dimensions = self.surface.window().inner_size();
-        if dimensions.width == 0 || dimensions.height == 0 {
+        if self.lost || dimensions.width == 0 || dimensions.height == 0 {
             return;
         }
 
-        self.previous_frame_end.as_mut().unwrap().cleanup_finished();
+        // the CPU doesn't get more frames ahead of the GPU than are in flight,
+        // and the spare targets were post-processed by the frame before the last
+        let spare_targets_end = self.spare_targets.as_ref().and(self.frames.before_last());
+        let waited = [self.frames.next(), spare_targets_end]
+            .into_iter()
+            .flatten()
+            .try_for_each(|end| end.wait(None));
+        match waited {
+            Ok(()) => (),
+            Err(FlushError::DeviceLost) => return self.set_lost(),
+            Err(e) => panic!("Failed to wait for a frame: {:?}", e),
+        }
         let mut size = self.swapchain_images[0].dimensions().width_height();
 
         if self.recreate_swapchain {
+            let capabilities = self
+                .queue
+                .device()
+                .physical_device()
+                .surface_capabilities(&self.surface, SurfaceInfo::default())
+                .unwrap();
             let (new_swapchain, new_images) = match self.swapchain.recreate(SwapchainCreateInfo {
+                min_image_count: frames_in_flight::swapchain_image_count(
+                    self.frames.count(),
+                    capabilities.min_image_count,
+                    capabilities.max_image_count,
+                ),
                 image_extent: dimensions.into(),
+                present_mode: self.present_mode,
                 ..self.swapchain.create_info()
             }) {
                 Ok(r) => r,
                 Err(SwapchainCreationError::ImageExtentNotSupported { .. }) => return,
+                Err(SwapchainCreationError::DeviceLost | SwapchainCreationError::SurfaceLost) => {
+                    return self.set_lost()
+                }
                 Err(e) => panic!("Failed to recreate swapchain: {:?}", e),
             };
             self.swapchain_images = new_images;
             self.swapchain = new_swapchain;
             self.recreate_swapchain = false;
             size = self.swapchain_images[0].dimensions().width_height();
-            self.storage_image = StorageImage::new(
-                self.queue.device().clone(),
-                ImageDimensions::Dim2d {
-                    width: size[0],
-                    height: size[1],
-                    array_layers: 1,
-                },
-                Format::R8G8B8A8_UNORM,
-                [self.queue.family()],
-            )
-            .unwrap();
+            self.upscaler.resize(&self.queue, size);
+            self.hud.resize(&self.queue, size);
+            self.line_overlay.resize(&self.swapchain_images);
+        }
+
+        if let Some(last_frame) = self.last_frame {
+            self.render_scale.update(last_frame.elapsed());
+            // the GPU's time when known, which isn't capped by the present mode
+            let frame_time = self
+                .profiler
+                .last_frame_time()
+                .unwrap_or_else(|| last_frame.elapsed());
+            if self.frame_budget.update(frame_time) {
+                self.apply_quality();
+            }
+        }
+        self.last_frame = Some(Instant::now());
+        let render_size = self.render_scale.apply(size);
+        if self.storage_image.dimensions().width_height() != render_size {
+            self.storage_image = Self::create_hdr_image(&self.queue, render_size);
+            self.gbuffer = GBuffer::new(&self.queue, render_size);
+            if let Some(targets) = &mut self.spare_targets {
+                *targets = (
+                    Self::create_hdr_image(&self.queue, render_size),
+                    GBuffer::new(&self.queue, render_size),
+                );
+            }
+            self.accumulation_image = Self::create_accumulation_image(&self.queue, render_size);
+            self.accumulation.reset();
+            self.denoiser.resize(&self.queue, render_size);
+            self.bloom.resize(&self.queue, render_size);
+            self.taa.reset(&self.queue, render_size);
+            self.fxaa.resize(&self.queue, render_size);
+        }
+        if let Some((image, gbuffer)) = &mut self.spare_targets {
+            mem::swap(&mut self.storage_image, image);
+            mem::swap(&mut self.gbuffer, gbuffer);
         }
 
         // This function can block if no image is available. The parameter is an optional timeout
@@ -326,6 +684,9 @@ impl Graphics {
                     self.recreate_swapchain = true;
                     return;
                 }
+                Err(AcquireError::DeviceLost | AcquireError::SurfaceLost) => {
+                    return self.set_lost()
+                }
                 Err(e) => panic!("Failed to acquire next image: {:?}", e),
             };
 
@@ -333,98 +694,1775 @@ impl Graphics {
             self.recreate_swapchain = true;
         }
 
-        let future = self.previous_frame_end.take().unwrap().join(acquire_future);
+        if let Some(buffer) = self.next_octree_buffer.take() {
+            // frames in flight keep the buffer they were recorded with alive
+            self.octree_buffer = buffer;
+        }
+        // edits go into a copy, frames in flight still read the buffer
+        let octree_patch = (!self.octree_patches.is_empty()).then(|| self.patch_octree());
 
-        let mut builder = AutoCommandBufferBuilder::primary(
-            self.queue.device().clone(),
-            self.queue.family(),
-            CommandBufferUsage::OneTimeSubmit,
-        )
-        .unwrap();
-        let pipeline_layout = self.compute_pipeline.layout();
-        let desc_layout = pipeline_layout.set_layouts().get(0).unwrap();
-        let compute_desc_set = PersistentDescriptorSet::new(
-            desc_layout.clone(),
-            [
-                WriteDescriptorSet::image_view(
-                    0,
-                    ImageView::new_default(self.storage_image.clone()).unwrap(),
-                ),
-                WriteDescriptorSet::buffer(1, self.camera_info.clone()),
-                WriteDescriptorSet::image_view(2, self.cube_map_array.clone()),
-                WriteDescriptorSet::buffer(3, self.octree_buffer.clone()),
-            ],
-        )
-        .unwrap();
+        #[cfg(feature = "hot-reload")]
+        if let Some(shader) = self.shader_reloader.poll(self.queue.device()) {
+            self.pipelines.replace_shader(shader);
+            self.accumulation.reset();
+        }
+        // the path tracer jitters its own samples and has no use for TAA
+        let path_traced = self.render_mode == RenderMode::PathTraced;
+        let taa_enabled = self.taa_enabled && !path_traced;
+        let jitter = if taa_enabled {
+            self.taa.jitter()
+        } else {
+            [0.0, 0.0]
+        };
+        let sample = if path_traced {
+            let mut view = bytemuck::bytes_of(&self.camera).to_vec();
+            view.extend_from_slice(bytemuck::bytes_of(&self.lighting_values));
+            view.extend_from_slice(bytemuck::cast_slice(&self.decal_values));
+            self.accumulation
+                .next_sample(&view)
+                .map_or(-1, |s| s as i32)
+        } else {
+            self.accumulation.reset();
+            0
+        };
+        self.frame_seed = self.frame_seed.wrapping_add(1);
+        let compute_pipeline = self.pipelines.get(self.shader_features);
+        let compute_desc_set = self.trace_descriptor_set(&compute_pipeline, jitter, sample);
 
-        builder
-            .clear_color_image(ClearColorImageInfo::image(self.storage_image.clone()))
-            .unwrap()
-            .bind_pipeline_compute(self.compute_pipeline.clone())
-            .bind_descriptor_sets(
-                PipelineBindPoint::Compute,
-                self.compute_pipeline.layout().clone(),
-                0,
-                compute_desc_set,
-            )
-            .dispatch([
-                size[0] / COMPUTE_GROUP_SIZE,
-                size[1] / COMPUTE_GROUP_SIZE,
+        // what the passes need from self, taken before the frame borrows the
+        // profiler
+        let workgroup_size = self.shader_features.workgroup_size;
+        let minimap_desc_set = if self.minimap_enabled {
+            let traced = self.minimap_countdown == 0;
+            if traced {
+                self.minimap_countdown = MINIMAP_INTERVAL;
+                if let Some(image) = &mut self.spare_minimap {
+                    mem::swap(&mut self.minimap_image, image);
+                }
+            }
+            self.minimap_countdown -= 1;
+            traced.then(|| self.minimap_descriptor_set(&compute_pipeline))
+        } else {
+            None
+        };
+        let draw_minimap =
+            self.minimap_enabled && size.iter().all(|&s| s >= MINIMAP_SIZE + 2 * MINIMAP_MARGIN);
+        let lines = match &self.cull_camera {
+            Some(camera) => [self.debug_lines.clone(), frustum_outline(camera, size)].concat(),
+            None => self.debug_lines.clone(),
+        };
+        // the HUD is drawn after post-processing, which would blur it
+        let hud_inputs = [
+            WriteDescriptorSet::image_view_sampler(
                 1,
-            ])
-            .unwrap()
-            .blit_image(BlitImageInfo {
-                src_image_layout: ImageLayout::General,
-                dst_image_layout: ImageLayout::General,
-                ..BlitImageInfo::images(
-                    self.storage_image.clone(),
-                    self.swapchain_images[next_image_idx].clone(),
-                )
-            })
-            .unwrap();
+                self.material_textures.clone(),
+                self.texture_sampler.clone(),
+            ),
+            WriteDescriptorSet::buffer(2, self.hud_info.clone()),
+            WriteDescriptorSet::buffer(3, self.material_buffer.clone()),
+            WriteDescriptorSet::buffer(
+                4,
+                self.texture_frame_buffer(self.started.elapsed().as_secs_f32()),
+            ),
+        ];
+        let swapchain_image = self.swapchain_images[next_image_idx].clone();
+        let minimap_image = self.minimap_image.clone();
+        let camera = self.camera;
 
-        let command_buffer = builder.build().unwrap();
+        // traced on the graphics queue, post-processed and presented on the
+        // compute queue when there is one
+        let mut trace: FrameGraph<FrameResource, Frame> = FrameGraph::new();
+        let mut post: FrameGraph<FrameResource, Frame> = FrameGraph::new();
+        let mut present: FrameGraph<FrameResource, Frame> = FrameGraph::new();
+        if let Some((old, staging, regions)) = octree_patch {
+            let new = self.octree_buffer.clone();
+            trace.add_pass(
+                "patch octree",
+                &[],
+                &[FrameResource::Octree],
+                move |frame| {
+                    frame
+                        .builder
+                        .copy_buffer(CopyBufferInfo::buffers(old, new.clone()))
+                        .unwrap()
+                        .copy_buffer(CopyBufferInfoTyped {
+                            regions: regions.into_iter().collect(),
+                            ..CopyBufferInfoTyped::buffers(staging, new)
+                        })
+                        .unwrap();
+                },
+            );
+        }
+        if let Some(desc_set) = minimap_desc_set {
+            let pipeline = compute_pipeline.clone();
+            trace.add_pass(
+                "minimap",
+                &[FrameResource::Octree],
+                &[FrameResource::Minimap],
+                move |frame| {
+                    frame.timed(GpuZone::Minimap, |builder| {
+                        builder
+                            .bind_pipeline_compute(pipeline.clone())
+                            .bind_descriptor_sets(
+                                PipelineBindPoint::Compute,
+                                pipeline.layout().clone(),
+                                0,
+                                desc_set,
+                            )
+                            .dispatch(workgroups::group_count([MINIMAP_SIZE; 2], workgroup_size))
+                            .unwrap();
+                    })
+                },
+            );
+        }
+        trace.add_pass("clear", &[], &[FrameResource::Color], |frame| {
+            let image = frame.color.clone();
+            frame.timed(GpuZone::Clear, |builder| {
+                builder
+                    .clear_color_image(ClearColorImageInfo::image(image))
+                    .unwrap();
+            })
+        });
+        trace.add_pass(
+            "raytrace",
+            &[FrameResource::Octree],
+            &[FrameResource::Color, FrameResource::GBuffer],
+            move |frame| {
+                frame.timed(GpuZone::Raytrace, |builder| {
+                    builder
+                        .bind_pipeline_compute(compute_pipeline.clone())
+                        .bind_descriptor_sets(
+                            PipelineBindPoint::Compute,
+                            compute_pipeline.layout().clone(),
+                            0,
+                            compute_desc_set,
+                        )
+                        .dispatch(workgroups::group_count(render_size, workgroup_size))
+                        .unwrap();
+                })
+            },
+        );
+        if self.denoise_enabled {
+            let (denoiser, gbuffer) = (&self.denoiser, &self.gbuffer);
+            post.add_pass(
+                "denoise",
+                &[FrameResource::GBuffer],
+                &[FrameResource::Color],
+                move |frame| {
+                    let input = frame.color.clone();
+                    frame.color = frame.timed(GpuZone::Denoise, |builder| {
+                        denoiser.record(builder, input, gbuffer)
+                    });
+                },
+            );
+        }
+        let (bloom, bloom_enabled) = (&self.bloom, self.bloom_enabled);
+        post.add_pass("bloom", &[], &[FrameResource::Color], move |frame| {
+            let input = frame.color.clone();
+            frame.color = frame.timed(GpuZone::Bloom, |builder| {
+                bloom.record(builder, input, bloom_enabled)
+            });
+        });
+        if taa_enabled {
+            let taa = &mut self.taa;
+            post.add_pass("taa", &[], &[FrameResource::Color], move |frame| {
+                let input = frame.color.clone();
+                frame.color =
+                    frame.timed(GpuZone::Taa, |builder| taa.record(builder, input, camera));
+            });
+        }
+        if self.fxaa_enabled {
+            let fxaa = &self.fxaa;
+            post.add_pass("fxaa", &[], &[FrameResource::Color], move |frame| {
+                let input = frame.color.clone();
+                frame.color = frame.timed(GpuZone::Fxaa, |builder| fxaa.record(builder, input));
+            });
+        }
+        let upscaler = &self.upscaler;
+        post.add_pass("upscale", &[], &[FrameResource::Color], move |frame| {
+            let input = frame.color.clone();
+            frame.color = frame.timed(GpuZone::Upscale, |builder| upscaler.record(builder, input));
+        });
+        let hud = &self.hud;
+        post.add_pass("hud", &[], &[FrameResource::Color], move |frame| {
+            let input = frame.color.clone();
+            frame.color = frame.timed(GpuZone::Hud, |builder| {
+                hud.record(builder, input, hud_inputs)
+            });
+        });
+        if let Some(recorder) = &mut self.recorder {
+            present.add_pass("record", &[FrameResource::Color], &[], move |frame| {
+                recorder.record(&mut frame.builder, frame.color.clone())
+            });
+        }
+        present.add_pass(
+            "blit",
+            &[FrameResource::Color, FrameResource::Minimap],
+            &[FrameResource::Swapchain],
+            move |frame| {
+                let output = frame.color.clone();
+                frame.timed(GpuZone::Blit, |builder| {
+                    builder
+                        .blit_image(BlitImageInfo {
+                            src_image_layout: ImageLayout::General,
+                            dst_image_layout: ImageLayout::General,
+                            filter: Filter::Nearest,
+                            ..BlitImageInfo::images(output, swapchain_image.clone())
+                        })
+                        .unwrap();
+                    if draw_minimap {
+                        let left = size[0] - MINIMAP_MARGIN - MINIMAP_SIZE;
+                        builder
+                            .blit_image(BlitImageInfo {
+                                src_image_layout: ImageLayout::General,
+                                dst_image_layout: ImageLayout::General,
+                                regions: [ImageBlit {
+                                    src_subresource: minimap_image.subresource_layers(),
+                                    src_offsets: [[0, 0, 0], [MINIMAP_SIZE, MINIMAP_SIZE, 1]],
+                                    dst_subresource: swapchain_image.subresource_layers(),
+                                    dst_offsets: [
+                                        [left, MINIMAP_MARGIN, 0],
+                                        [left + MINIMAP_SIZE, MINIMAP_MARGIN + MINIMAP_SIZE, 1],
+                                    ],
+                                }]
+                                .into(),
+                                filter: Filter::Nearest,
+                                ..BlitImageInfo::images(minimap_image, swapchain_image)
+                            })
+                            .unwrap();
+                    }
+                })
+            },
+        );
+        if !lines.is_empty() {
+            let line_overlay = &self.line_overlay;
+            present.add_pass("lines", &[], &[FrameResource::Swapchain], move |frame| {
+                frame.timed(GpuZone::Lines, |builder| {
+                    line_overlay.record(builder, next_image_idx, &lines, &camera)
+                })
+            });
+        }
+        let compute_queue = self.compute_queue.clone();
+        let post_queue = compute_queue.clone().unwrap_or_else(|| self.queue.clone());
+        let mut frame = Frame::new(&self.queue, &mut self.profiler, self.storage_image.clone());
+        frame.profiler.begin_frame(&mut frame.builder);
+        trace.record(&mut frame).unwrap();
+        let (trace_commands, mut frame) = frame.next(&post_queue);
+        post.record(&mut frame).unwrap();
+        let (post_commands, mut frame) = frame.next(&post_queue);
+        present.record(&mut frame).unwrap();
+        let present_commands = frame.builder.build().unwrap();
 
-        let render_future = future
-            .then_execute(self.queue.clone(), command_buffer)
+        let post_start = match compute_queue {
+            // the trace is flushed on its own so the next one can start while
+            // this frame is post-processed, GpuProfiler::overlap tells by how
+            // much they overlap
+            Some(_) => {
+                let traced = match self
+                    .trace_start_future()
+                    .then_execute(self.queue.clone(), trace_commands)
+                    .unwrap()
+                    .boxed()
+                    .then_signal_fence_and_flush()
+                {
+                    Ok(future) => Arc::new(future),
+                    Err(e) => {
+                        self.traces.submit(None);
+                        return self.submit_frame(Err(e));
+                    }
+                };
+                self.traces.submit(Some(traced.clone()));
+                self.previous_frame_end()
+                    .join(traced.then_signal_semaphore())
+                    .join(acquire_future)
+                    .boxed()
+            }
+            None => self
+                .frame_start_future()
+                .join(acquire_future)
+                .then_execute(self.queue.clone(), trace_commands)
+                .unwrap()
+                .boxed(),
+        };
+        let render_future = post_start
+            .then_execute(post_queue.clone(), post_commands)
             .unwrap()
-            .then_swapchain_present(self.queue.clone(), self.swapchain.clone(), next_image_idx)
-            .then_signal_fence_and_flush();
+            .then_execute(post_queue.clone(), present_commands)
+            .unwrap()
+            .then_swapchain_present(post_queue, self.swapchain.clone(), next_image_idx)
+            .boxed()
+            .then_signal_fence_and_flush()
+            .map(Arc::new);
+        self.submit_frame(render_future);
+    }
 
-        match render_future {
-            Ok(future) => {
-                self.previous_frame_end = Some(future.boxed());
-            }
+    fn submit_frame(&mut self, end: Result<FrameEnd, FlushError>) {
+        match end {
+            Ok(end) => self.frames.submit(Some(end)),
             Err(FlushError::OutOfDate) => {
                 self.recreate_swapchain = true;
-                self.previous_frame_end = Some(sync::now(self.queue.device().clone()).boxed());
+                self.frames.submit(None);
+            }
+            Err(FlushError::DeviceLost | FlushError::SurfaceLost) => {
+                self.frames.submit(None);
+                self.set_lost();
             }
             Err(e) => {
-                println!("Failed to flush future: {:?}", e);
-                self.previous_frame_end = Some(sync::now(self.queue.device().clone()).boxed());
+                error!(error = ?e, "Failed to flush future");
+                self.frames.submit(None);
             }
         }
     }
 
-    fn create_camera_info_buffer(
-        device: Arc<Device>,
-        camera_info: CameraInfo,
-    ) -> Arc<CpuAccessibleBuffer<CameraInfo>> {
-        CpuAccessibleBuffer::from_data(
-            device.clone(),
+    pub fn is_lost(&self) -> bool {
+        self.lost
+    }
+
+    fn set_lost(&mut self) {
+        self.lost = true;
+        // the frames in flight never finish on a lost device, and dropping
+        // their fences would wait for them
+        for end in self
+            .frames
+            .take_all()
+            .into_iter()
+            .chain(self.traces.take_all())
+        {
+            mem::forget(end);
+        }
+    }
+
+    /// Returns the end of the previous frame joined with any uploads the next
+    /// frame has to wait for.
+    fn frame_start_future(&mut self) -> Box<dyn GpuFuture> {
+        let previous_frame_end = self.previous_frame_end();
+        self.with_uploads(previous_frame_end)
+    }
+
+    /// Returns the end of the previous trace joined with the uploads, for
+    /// tracing apart from the rest of the frame on the graphics queue.
+    fn trace_start_future(&mut self) -> Box<dyn GpuFuture> {
+        // done since the frame before the last was waited for, waiting cleans
+        // up the chain of traces behind it. A lost device shows once flushed
+        if let Some(end) = self.traces.next() {
+            let _ = end.wait(None);
+        }
+        let previous_trace_end = match self.traces.last() {
+            Some(end) => end.clone().boxed(),
+            None => sync::now(self.queue.device().clone()).boxed(),
+        };
+        self.with_uploads(previous_trace_end)
+    }
+
+    fn previous_frame_end(&self) -> Box<dyn GpuFuture> {
+        match self.frames.last() {
+            Some(end) => end.clone().boxed(),
+            None => sync::now(self.queue.device().clone()).boxed(),
+        }
+    }
+
+    fn with_uploads(&mut self, future: Box<dyn GpuFuture>) -> Box<dyn GpuFuture> {
+        match self.uploader.take_pending() {
+            Some(uploads) => future.join(uploads).boxed(),
+            None => future,
+        }
+    }
+
+    /// Waits for every frame in flight and the pending uploads.
+    fn wait_for_frames(&mut self) {
+        self.frame_start_future()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+        self.frames.clear();
+        self.traces.clear();
+    }
+
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames.count()
+    }
+
+    /// Takes the settings to carry over to another ray tracer along with the
+    /// recording in progress, which stops here.
+    pub fn take_settings(&mut self) -> RenderSettings {
+        RenderSettings {
+            target_fps: self.target_fps(),
+            target_frame_time: self.target_frame_time(),
+            render_scale: self.render_scale(),
+            frames_in_flight: self.frames_in_flight(),
+            present_mode: self.present_mode,
+            shader_features: self.shader_features,
+            render_mode: self.render_mode,
+            shadows: self.shadows,
+            taa_enabled: self.taa_enabled,
+            denoise_enabled: self.denoise_enabled,
+            bloom_enabled: self.bloom_enabled,
+            fxaa_enabled: self.fxaa_enabled,
+            minimap_enabled: self.minimap_enabled,
+            chunk_borders: self.chunk_borders,
+            max_depth: self.max_depth,
+            recorder: self.recorder.take(),
+        }
+    }
+
+    /// Applies the settings taken from another ray tracer, keeping the present
+    /// mode this one started with if its surface doesn't support the old one.
+    pub fn apply_settings(&mut self, settings: RenderSettings) {
+        self.set_render_scale(settings.render_scale);
+        self.set_target_frame_time(settings.target_frame_time);
+        self.set_target_fps(settings.target_fps);
+        self.set_frames_in_flight(settings.frames_in_flight);
+        if let Err(UnsupportedPresentMode(mode)) = self.set_present_mode(settings.present_mode) {
+            warn!(?mode, "The new device can't present as before");
+        }
+        self.set_shader_features(settings.shader_features);
+        self.set_render_mode(settings.render_mode);
+        self.set_shadows(settings.shadows);
+        self.set_taa_enabled(settings.taa_enabled);
+        self.set_denoise_enabled(settings.denoise_enabled);
+        self.set_bloom_enabled(settings.bloom_enabled);
+        self.set_fxaa_enabled(settings.fxaa_enabled);
+        self.set_minimap_enabled(settings.minimap_enabled);
+        self.set_chunk_borders(settings.chunk_borders);
+        self.set_max_depth(settings.max_depth);
+        self.recorder = settings.recorder.map(|mut recorder| {
+            recorder.move_to(self.queue.device().clone());
+            recorder
+        });
+    }
+
+    /// Lets the CPU record up to `count` frames before the GPU has finished
+    /// the first, clamped by `FramesInFlight`. More keep the GPU busier but
+    /// show input later. The swapchain is recreated with an image more.
+    pub fn set_frames_in_flight(&mut self, count: usize) {
+        self.wait_for_frames();
+        self.frames = FramesInFlight::new(count);
+        self.recreate_swapchain = true;
+    }
+
+    fn trace_descriptor_set(
+        &self,
+        pipeline: &Arc<ComputePipeline>,
+        jitter: [f32; 2],
+        sample: i32,
+    ) -> Arc<PersistentDescriptorSet> {
+        self.descriptor_set(
+            pipeline,
+            self.storage_image.clone(),
+            self.camera_info.clone(),
+            FrameInfo {
+                jitter,
+                time: self.started.elapsed().as_secs_f32(),
+                render_mode: self.render_mode.index(),
+                sample,
+                seed: self.frame_seed,
+                max_bounces: self.max_bounces(),
+                chunk_borders: self.chunk_borders as i32,
+                minimap: 0,
+                offscreen: 0,
+                view_distance: self.view_distance,
+                max_depth: self.max_depth_uniform(),
+                environment: self.environment_enabled as i32,
+            },
+        )
+    }
+
+    // traces the world seen from above the camera into the minimap
+    fn minimap_descriptor_set(
+        &self,
+        pipeline: &Arc<ComputePipeline>,
+    ) -> Arc<PersistentDescriptorSet> {
+        let camera = camera::minimap_camera(self.camera.eye, MINIMAP_WIDTH);
+        self.descriptor_set(
+            pipeline,
+            self.minimap_image.clone(),
+            Self::create_camera_info_buffer(self.queue.device().clone(), camera),
+            FrameInfo {
+                jitter: [0.0, 0.0],
+                time: self.started.elapsed().as_secs_f32(),
+                render_mode: RenderMode::Shaded.index(),
+                sample: 0,
+                seed: self.frame_seed,
+                max_bounces: self.max_bounces(),
+                chunk_borders: 0,
+                minimap: 1,
+                offscreen: 1,
+                // traced from high above, down to the ground
+                view_distance: f32::MAX,
+                max_depth: self.max_depth_uniform(),
+                environment: self.environment_enabled as i32,
+            },
+        )
+    }
+
+    fn descriptor_set(
+        &self,
+        pipeline: &Arc<ComputePipeline>,
+        target: Arc<StorageImage<Arc<StdMemoryPool>>>,
+        camera_info: Arc<CpuAccessibleBuffer<CameraInfo>>,
+        frame_info: FrameInfo,
+    ) -> Arc<PersistentDescriptorSet> {
+        let time = frame_info.time;
+        let frame_info = CpuAccessibleBuffer::from_data(
+            self.queue.device().clone(),
             BufferUsage {
                 uniform_buffer: true,
                 ..BufferUsage::none()
             },
             false,
-            camera_info,
+            frame_info,
+        )
+        .unwrap();
+        let texture_frames = self.texture_frame_buffer(time);
+        let desc_layout = pipeline.layout().set_layouts().get(0).unwrap();
+        PersistentDescriptorSet::new(
+            desc_layout.clone(),
+            [
+                WriteDescriptorSet::image_view(0, ImageView::new_default(target).unwrap()),
+                WriteDescriptorSet::buffer(1, camera_info),
+                WriteDescriptorSet::image_view_sampler(
+                    2,
+                    self.material_textures.clone(),
+                    self.texture_sampler.clone(),
+                ),
+                WriteDescriptorSet::buffer(3, self.octree_buffer.clone()),
+                WriteDescriptorSet::buffer(4, self.decal_buffer.clone()),
+                WriteDescriptorSet::buffer(5, self.hud_info.clone()),
+                WriteDescriptorSet::buffer(6, frame_info),
+                WriteDescriptorSet::buffer(7, self.material_buffer.clone()),
+                WriteDescriptorSet::buffer(8, self.lighting.clone()),
+                WriteDescriptorSet::image_view(
+                    9,
+                    ImageView::new_default(self.gbuffer.depth.clone()).unwrap(),
+                ),
+                WriteDescriptorSet::image_view(
+                    10,
+                    ImageView::new_default(self.gbuffer.normal.clone()).unwrap(),
+                ),
+                WriteDescriptorSet::image_view(
+                    11,
+                    ImageView::new_default(self.gbuffer.material.clone()).unwrap(),
+                ),
+                WriteDescriptorSet::buffer(12, self.entity_buffer.clone()),
+                WriteDescriptorSet::image_view(
+                    13,
+                    ImageView::new_default(self.accumulation_image.clone()).unwrap(),
+                ),
+                WriteDescriptorSet::image_view(14, self.blue_noise.clone()),
+                WriteDescriptorSet::image_view(15, self.light_volume.clone()),
+                WriteDescriptorSet::buffer(16, self.light_info.clone()),
+                WriteDescriptorSet::image_view_sampler(
+                    17,
+                    self.environment.clone(),
+                    self.environment_sampler.clone(),
+                ),
+                WriteDescriptorSet::buffer(18, texture_frames),
+            ],
         )
         .unwrap()
     }
 
-    pub fn update_camera(&mut self, camera_info: CameraInfo) {
-        self.camera_info = Self::create_camera_info_buffer(self.queue.device().clone(), camera_info)
-    }
+    /// Traces a few frames with each workgroup size the device supports and
+    /// switches to the fastest one.
+    fn autotune_workgroup_size(&mut self) {
+        // make sure the texture upload isn't part of the first measurement
+        self.wait_for_frames();
+
+        let properties = self.queue.device().physical_device().properties();
+        let candidates = workgroups::supported_candidates(
+            properties.max_compute_work_group_invocations,
+            properties.max_compute_work_group_size,
+        );
+        let size = self.storage_image.dimensions().width_height();
+        let mut timings = Vec::new();
+        for workgroup_size in candidates {
+            let pipeline = self.pipelines.get(ShaderFeatures {
+                workgroup_size,
+                ..self.shader_features
+            });
+            let mut builder = AutoCommandBufferBuilder::primary(
+                self.queue.device().clone(),
+                self.queue.family(),
+                CommandBufferUsage::OneTimeSubmit,
+            )
+            .unwrap();
+            for _ in 0..AUTOTUNE_FRAMES {
+                builder
+                    .bind_pipeline_compute(pipeline.clone())
+                    .bind_descriptor_sets(
+                        PipelineBindPoint::Compute,
+                        pipeline.layout().clone(),
+                        0,
+                        self.trace_descriptor_set(&pipeline, [0.0, 0.0], 0),
+                    )
+                    .dispatch(workgroups::group_count(size, workgroup_size))
+                    .unwrap();
+            }
+            let command_buffer = builder.build().unwrap();
+            let start = Instant::now();
+            command_buffer
+                .execute(self.queue.clone())
+                .unwrap()
+                .then_signal_fence_and_flush()
+                .unwrap()
+                .wait(None)
+                .unwrap();
+            timings.push((workgroup_size, start.elapsed()));
+        }
+        if let Some(workgroup_size) = workgroups::fastest(&timings) {
+            info!(?workgroup_size, "Tuned the workgroup size");
+            self.set_shader_features(ShaderFeatures {
+                workgroup_size,
+                ..self.shader_features
+            });
+        }
+    }
+
+    /// Depth, normal and material of the last traced frame, at the render
+    /// resolution. Recreated when the render scale changes.
+    pub fn gbuffer(&self) -> &GBuffer {
+        &self.gbuffer
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// Starts writing every frame to `dir` as numbered PNGs, replacing the
+    /// recording in progress if any.
+    pub fn start_recording(&mut self, dir: PathBuf) -> io::Result<()> {
+        self.stop_recording();
+        self.recorder = Some(FrameRecorder::new(self.queue.device().clone(), dir)?);
+        Ok(())
+    }
+
+    /// Waits for the frames in flight and the queued PNGs to be written.
+    /// Returns None if nothing was being recorded.
+    pub fn stop_recording(&mut self) -> Option<RecordingSummary> {
+        let recorder = self.recorder.take()?;
+        self.wait_for_frames();
+        Some(recorder.finish())
+    }
+
+    /// Renders the current view at `size` into a PNG at `path`, in tiles at
+    /// most `tile_size` pixels across, for images larger than the GPU can
+    /// hold at once. The tiles are shaded whatever the render mode, without
+    /// the HUD or bloom.
+    pub fn render_tiled(&mut self, size: [u32; 2], tile_size: u32, path: &Path) -> io::Result<()> {
+        if size.iter().any(|&s| s < 2) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The image must be at least 2 pixels across",
+            ));
+        }
+        let properties = self.queue.device().physical_device().properties();
+        let tiles = tiles::tiles(size, tile_size.min(properties.max_image_dimension2_d));
+        let mut image = vec![0; size[0] as usize * size[1] as usize * 4];
+        let pipeline = self.pipelines.get(self.shader_features);
+        self.wait_for_frames();
+        for (i, tile) in tiles.iter().enumerate() {
+            let _span = debug_span!("tile", i).entered();
+            let pixels = self.render_tile(&pipeline, tile, size);
+            tile.copy_into(&pixels, &mut image, size);
+        }
+        info!(?size, tiles = tiles.len(), path = %path.display(), "Rendered tiles");
+        let file = File::create(path)?;
+        Ok(crate::io::frames::write_png(
+            BufWriter::new(file),
+            size,
+            &image,
+        )?)
+    }
+
+    // traces `tile` of an image of `image_size` and reads it back as RGBA8
+    fn render_tile(
+        &self,
+        pipeline: &Arc<ComputePipeline>,
+        tile: &Tile,
+        image_size: [u32; 2],
+    ) -> Vec<u8> {
+        let device = self.queue.device().clone();
+        let hdr = Self::create_hdr_image(&self.queue, tile.size);
+        let ldr = Self::create_storage_image(&self.queue, tile.size);
+        let camera = CameraInfo {
+            window: tile.window(image_size, self.camera.window),
+            ..self.camera
+        };
+        let len = tile.size[0] as usize * tile.size[1] as usize * 4;
+        let buffer = CpuAccessibleBuffer::from_iter(
+            device.clone(),
+            BufferUsage::transfer_dst(),
+            true,
+            (0..len).map(|_| 0u8),
+        )
+        .unwrap();
+        let desc_set = self.descriptor_set(
+            pipeline,
+            hdr.clone(),
+            Self::create_camera_info_buffer(device.clone(), camera),
+            FrameInfo {
+                jitter: [0.0, 0.0],
+                time: self.started.elapsed().as_secs_f32(),
+                render_mode: RenderMode::Shaded.index(),
+                sample: 0,
+                seed: self.frame_seed,
+                max_bounces: self.max_bounces(),
+                chunk_borders: 0,
+                minimap: 0,
+                offscreen: 1,
+                view_distance: self.view_distance,
+                max_depth: self.max_depth_uniform(),
+                environment: self.environment_enabled as i32,
+            },
+        );
+        let mut builder = AutoCommandBufferBuilder::primary(
+            device,
+            self.queue.family(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        builder
+            .bind_pipeline_compute(pipeline.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                pipeline.layout().clone(),
+                0,
+                desc_set,
+            )
+            .dispatch(workgroups::group_count(
+                tile.size,
+                self.shader_features.workgroup_size,
+            ))
+            .unwrap()
+            // clamps the HDR values into what a PNG holds
+            .blit_image(BlitImageInfo {
+                src_image_layout: ImageLayout::General,
+                dst_image_layout: ImageLayout::General,
+                filter: Filter::Nearest,
+                ..BlitImageInfo::images(hdr, ldr.clone())
+            })
+            .unwrap()
+            .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(ldr, buffer.clone()))
+            .unwrap();
+        builder
+            .build()
+            .unwrap()
+            .execute(self.queue.clone())
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+        let pixels = buffer.read().unwrap().to_vec();
+        pixels
+    }
+
+    /// Average GPU time spent in each section of a frame.
+    pub fn gpu_timings(&self) -> Vec<(GpuZone, Duration)> {
+        self.profiler.timings()
+    }
+
+    /// Average GPU time a frame's tracing overlapped the post-processing of
+    /// the frame before, see `GpuProfiler::overlap`.
+    pub fn gpu_overlap(&self) -> Option<Duration> {
+        self.profiler.overlap()
+    }
+
+    pub fn present_mode(&self) -> PresentMode {
+        self.present_mode
+    }
+
+    /// Switches the swapchain to the given present mode. The swapchain is
+    /// recreated on the next redraw.
+    pub fn set_present_mode(&mut self, mode: PresentMode) -> Result<(), UnsupportedPresentMode> {
+        if !self.supported_present_modes.contains(&mode) {
+            return Err(UnsupportedPresentMode(mode));
+        }
+        if mode != self.present_mode {
+            self.present_mode = mode;
+            self.recreate_swapchain = true;
+        }
+        Ok(())
+    }
+
+    /// Switches to the next supported present mode in `PRESENT_MODE_CYCLE`
+    /// and returns it.
+    pub fn cycle_present_mode(&mut self) -> PresentMode {
+        let start = PRESENT_MODE_CYCLE
+            .iter()
+            .position(|&m| m == self.present_mode)
+            .unwrap_or(0);
+        for i in 1..=PRESENT_MODE_CYCLE.len() {
+            let mode = PRESENT_MODE_CYCLE[(start + i) % PRESENT_MODE_CYCLE.len()];
+            if self.set_present_mode(mode).is_ok() {
+                break;
+            }
+        }
+        self.present_mode
+    }
+
+    pub fn shader_features(&self) -> ShaderFeatures {
+        self.shader_features
+    }
+
+    /// Switches to the pipeline permutation for `features` and starts compiling
+    /// the permutations reachable from it with a single toggle.
+    pub fn set_shader_features(&mut self, features: ShaderFeatures) {
+        self.shader_features = features;
+        self.pipelines.prefetch_neighbors(features);
+    }
+
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale.scale()
+    }
+
+    /// Sets the ratio of the ray tracing resolution to the window resolution.
+    /// The scale is clamped to the range supported by `RenderScale`.
+    pub fn set_render_scale(&mut self, scale: f32) {
+        self.render_scale.set_scale(scale)
+    }
+
+    pub fn target_frame_time(&self) -> Option<Duration> {
+        self.render_scale.target_frame_time()
+    }
+
+    /// Enables automatic render scaling towards a frame time budget, or
+    /// disables it with `None`. Replaces a target frame rate.
+    pub fn set_target_frame_time(&mut self, target: Option<Duration>) {
+        if target.is_some() {
+            self.frame_budget.set_target_fps(None);
+        }
+        self.render_scale.set_target_frame_time(target)
+    }
+
+    pub fn target_fps(&self) -> Option<f32> {
+        self.frame_budget.target_fps()
+    }
+
+    /// Holds a frame rate by trading render scale, shadow rays and path
+    /// tracing bounces for speed, or stops with `None`. Replaces a target
+    /// frame time.
+    pub fn set_target_fps(&mut self, target: Option<f32>) {
+        self.frame_budget.set_target_fps(target);
+        if self.frame_budget.target_fps().is_some() {
+            self.render_scale.set_target_frame_time(None);
+            self.apply_quality();
+        }
+    }
+
+    pub fn quality(&self) -> Option<Quality> {
+        self.frame_budget.quality()
+    }
+
+    // the shadows are capped from the next lighting update
+    fn apply_quality(&mut self) {
+        if let Some(quality) = self.frame_budget.quality() {
+            self.render_scale.set_scale(quality.render_scale);
+            self.accumulation.reset();
+        }
+    }
+
+    fn max_bounces(&self) -> i32 {
+        match self.frame_budget.quality() {
+            Some(quality) => quality.bounces as i32,
+            None => MAX_BOUNCES,
+        }
+    }
+
+    pub fn taa_enabled(&self) -> bool {
+        self.taa_enabled
+    }
+
+    /// Enables temporal anti-aliasing. The history is discarded so re-enabling
+    /// does not blend in a stale frame.
+    pub fn set_taa_enabled(&mut self, enabled: bool) {
+        if enabled && !self.taa_enabled {
+            self.taa
+                .reset(&self.queue, self.storage_image.dimensions().width_height());
+        }
+        self.taa_enabled = enabled;
+    }
+
+    pub fn render_mode(&self) -> RenderMode {
+        self.render_mode
+    }
+
+    pub fn set_render_mode(&mut self, mode: RenderMode) {
+        self.render_mode = mode;
+    }
+
+    /// Samples in each pixel so far while path tracing.
+    pub fn path_samples(&self) -> Option<u32> {
+        (self.render_mode == RenderMode::PathTraced).then(|| self.accumulation.samples())
+    }
+
+    pub fn denoise_enabled(&self) -> bool {
+        self.denoise_enabled
+    }
+
+    /// Enables smoothing the noise of soft shadows and path tracing.
+    pub fn set_denoise_enabled(&mut self, enabled: bool) {
+        self.denoise_enabled = enabled;
+    }
+
+    pub fn minimap_enabled(&self) -> bool {
+        self.minimap_enabled
+    }
+
+    /// Shows the world from above in the top right corner, traced again
+    /// every few frames.
+    pub fn set_minimap_enabled(&mut self, enabled: bool) {
+        self.minimap_enabled = enabled;
+        self.minimap_countdown = 0;
+    }
+
+    pub fn chunk_borders(&self) -> bool {
+        self.chunk_borders
+    }
+
+    /// Draws the edges of the world's chunks over the image, to see where
+    /// chunks are loaded, generated and saved.
+    pub fn set_chunk_borders(&mut self, enabled: bool) {
+        self.chunk_borders = enabled;
+    }
+
+    pub fn max_depth(&self) -> Option<u32> {
+        self.max_depth
+    }
+
+    /// Stops rays `depth` levels below the root of the octree, drawing the
+    /// nodes there whole in the material of their first voxel, so far and
+    /// sparse worlds take fewer steps. None descends down to the voxels.
+    pub fn set_max_depth(&mut self, depth: Option<u32>) {
+        self.max_depth = depth;
+        self.accumulation.reset();
+    }
+
+    fn max_depth_uniform(&self) -> i32 {
+        self.max_depth.map_or(i32::MAX, |depth| depth as i32)
+    }
+
+    pub fn bloom_enabled(&self) -> bool {
+        self.bloom_enabled
+    }
+
+    /// Enables the glow around emissive voxels. When disabled the HDR image is
+    /// only clamped.
+    pub fn set_bloom_enabled(&mut self, enabled: bool) {
+        self.bloom_enabled = enabled;
+    }
+
+    pub fn shadows(&self) -> Shadows {
+        self.shadows
+    }
+
+    /// Sets how the sun's shadows are traced, from the next lighting update.
+    pub fn set_shadows(&mut self, shadows: Shadows) {
+        self.shadows = shadows;
+    }
+
+    pub fn fxaa_enabled(&self) -> bool {
+        self.fxaa_enabled
+    }
+
+    /// Enables the FXAA pass, which runs after TAA on the ray traced image.
+    pub fn set_fxaa_enabled(&mut self, enabled: bool) {
+        self.fxaa_enabled = enabled;
+    }
+
+    pub(crate) fn create_storage_image(
+        queue: &Arc<Queue>,
+        size: [u32; 2],
+    ) -> Arc<StorageImage<Arc<StdMemoryPool>>> {
+        Self::create_image(queue, size, Format::R8G8B8A8_UNORM)
+    }
+
+    /// Creates a storage image holding values above 1, for the ray traced
+    /// image and the bloom mips.
+    pub(crate) fn create_hdr_image(
+        queue: &Arc<Queue>,
+        size: [u32; 2],
+    ) -> Arc<StorageImage<Arc<StdMemoryPool>>> {
+        Self::create_image(queue, size, Format::R16G16B16A16_SFLOAT)
+    }
+
+    // `data` holds the faces of each material one after the other, which are
+    // read as the layers of one array with their mip chains so distant
+    // voxels don't shimmer
+    fn create_material_textures(
+        device: Arc<Device>,
+        uploader: &mut Uploader,
+        face_size: u32,
+        data: Vec<u8>,
+    ) -> Arc<ImageView<ImmutableImage>> {
+        let layers = data.len() as u32 / (face_size * face_size * 4);
+        let levels = mipmaps::mip_chain(face_size, layers, data);
+        let (image, init) = ImmutableImage::uninitialized(
+            device,
+            ImageDimensions::Dim2d {
+                width: face_size,
+                height: face_size,
+                array_layers: layers,
+            },
+            Format::R8G8B8A8_UNORM,
+            MipmapsCount::Specific(levels.len() as u32),
+            ImageUsage {
+                transfer_dst: true,
+                sampled: true,
+                ..ImageUsage::none()
+            },
+            ImageCreateFlags::default(),
+            ImageLayout::ShaderReadOnlyOptimal,
+            uploader.queue_families(),
+        )
+        .unwrap();
+        uploader.upload_image_levels(levels, init);
+        ImageView::new(
+            image.clone(),
+            ImageViewCreateInfo {
+                view_type: ImageViewType::Dim2dArray,
+                ..ImageViewCreateInfo::from_image(&image)
+            },
+        )
+        .unwrap()
+    }
+
+    // blends between mip levels, but keeps the texels of close voxels sharp
+    fn create_texture_sampler(device: Arc<Device>) -> Arc<Sampler> {
+        let anisotropy = device.enabled_features().sampler_anisotropy.then(|| {
+            let max = device.physical_device().properties().max_sampler_anisotropy;
+            max.min(MAX_ANISOTROPY)
+        });
+        Sampler::new(
+            device,
+            SamplerCreateInfo {
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Linear,
+                mipmap_mode: SamplerMipmapMode::Linear,
+                address_mode: [SamplerAddressMode::Repeat; 3],
+                anisotropy,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    }
+
+    fn create_blue_noise(
+        device: Arc<Device>,
+        uploader: &mut Uploader,
+    ) -> Arc<ImageView<StorageImage>> {
+        let image = StorageImage::with_usage(
+            device,
+            ImageDimensions::Dim2d {
+                width: BLUE_NOISE_SIZE,
+                height: BLUE_NOISE_SIZE,
+                array_layers: 1,
+            },
+            Format::R8G8B8A8_UNORM,
+            ImageUsage {
+                transfer_dst: true,
+                storage: true,
+                ..ImageUsage::none()
+            },
+            ImageCreateFlags::default(),
+            uploader.queue_families(),
+        )
+        .unwrap();
+        uploader.upload_image(blue_noise::rgba(BLUE_NOISE_SIZE as usize, 0), image.clone());
+        ImageView::new_default(image).unwrap()
+    }
+
+    fn create_light_volume(
+        device: Arc<Device>,
+        uploader: &mut Uploader,
+        volume: &LightVolume,
+    ) -> Arc<ImageView<StorageImage>> {
+        let image = StorageImage::with_usage(
+            device,
+            ImageDimensions::Dim3d {
+                width: volume.size,
+                height: volume.size,
+                depth: volume.size,
+            },
+            Format::R8_UINT,
+            ImageUsage {
+                transfer_dst: true,
+                storage: true,
+                ..ImageUsage::none()
+            },
+            ImageCreateFlags::default(),
+            uploader.queue_families(),
+        )
+        .unwrap();
+        uploader.upload_image(volume.levels.clone(), image.clone());
+        ImageView::new_default(image).unwrap()
+    }
+
+    fn create_environment(
+        device: Arc<Device>,
+        uploader: &mut Uploader,
+        cube_map: &CubeMap,
+    ) -> Arc<ImageView<StorageImage>> {
+        let image = StorageImage::with_usage(
+            device,
+            ImageDimensions::Dim2d {
+                width: cube_map.face_size,
+                height: cube_map.face_size,
+                array_layers: 6,
+            },
+            Format::R16G16B16A16_SFLOAT,
+            ImageUsage {
+                transfer_dst: true,
+                sampled: true,
+                ..ImageUsage::none()
+            },
+            ImageCreateFlags {
+                cube_compatible: true,
+                ..Default::default()
+            },
+            uploader.queue_families(),
+        )
+        .unwrap();
+        uploader.upload_image(cube_map.rgba_bytes(), image.clone());
+        ImageView::new(
+            image.clone(),
+            ImageViewCreateInfo {
+                view_type: ImageViewType::Cube,
+                ..ImageViewCreateInfo::from_image(&image)
+            },
+        )
+        .unwrap()
+    }
+
+    // bilinear within a face and across to its neighbors
+    fn create_environment_sampler(device: Arc<Device>) -> Arc<Sampler> {
+        Sampler::new(
+            device,
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    }
+
+    /// Lights the world with an environment map in place of the sky color,
+    /// or goes back to the color with None. The sun is still drawn on top.
+    pub fn set_environment(&mut self, cube_map: Option<&CubeMap>) {
+        if let Some(cube_map) = cube_map {
+            self.environment =
+                Self::create_environment(self.queue.device().clone(), &mut self.uploader, cube_map);
+        }
+        self.environment_enabled = cube_map.is_some();
+        self.accumulation.reset();
+    }
+
+    fn create_light_info_buffer(
+        device: Arc<Device>,
+        light_info: LightInfo,
+    ) -> Arc<CpuAccessibleBuffer<LightInfo>> {
+        CpuAccessibleBuffer::from_data(
+            device,
+            BufferUsage {
+                uniform_buffer: true,
+                ..BufferUsage::none()
+            },
+            false,
+            light_info,
+        )
+        .unwrap()
+    }
+
+    /// Uploads the light levels around the camera on the transfer queue,
+    /// which the next frame shades with.
+    pub fn update_light(&mut self, volume: &LightVolume) {
+        self.light_volume =
+            Self::create_light_volume(self.queue.device().clone(), &mut self.uploader, volume);
+        self.light_info = Self::create_light_info_buffer(
+            self.queue.device().clone(),
+            LightInfo {
+                origin: volume.origin,
+                size: volume.size as i32,
+            },
+        );
+    }
+
+    fn create_accumulation_image(
+        queue: &Arc<Queue>,
+        size: [u32; 2],
+    ) -> Arc<StorageImage<Arc<StdMemoryPool>>> {
+        Self::create_image(queue, size, Format::R32G32B32A32_SFLOAT)
+    }
+
+    pub(crate) fn create_image(
+        queue: &Arc<Queue>,
+        size: [u32; 2],
+        format: Format,
+    ) -> Arc<StorageImage<Arc<StdMemoryPool>>> {
+        StorageImage::new(
+            queue.device().clone(),
+            ImageDimensions::Dim2d {
+                width: size[0],
+                height: size[1],
+                array_layers: 1,
+            },
+            format,
+            [queue.family()],
+        )
+        .unwrap()
+    }
+
+    // mip levels aside, which nothing here has
+    pub(crate) fn image_bytes(image: &dyn ImageAccess) -> u64 {
+        let dimensions = image.dimensions();
+        let texels: u64 = (0..image.mip_levels())
+            .filter_map(|level| dimensions.mip_level_dimensions(level))
+            .map(|level| level.num_texels())
+            .sum();
+        texels * image.format().block_size().unwrap_or(0)
+    }
+
+    fn create_camera_info_buffer(
+        device: Arc<Device>,
+        camera_info: CameraInfo,
+    ) -> Arc<CpuAccessibleBuffer<CameraInfo>> {
+        CpuAccessibleBuffer::from_data(
+            device.clone(),
+            BufferUsage {
+                uniform_buffer: true,
+                ..BufferUsage::none()
+            },
+            false,
+            camera_info,
+        )
+        .unwrap()
+    }
+
+    pub fn update_camera(&mut self, camera_info: CameraInfo) {
+        self.camera = camera_info;
+        self.camera_info = Self::create_camera_info_buffer(self.queue.device().clone(), camera_info)
+    }
+
+    /// The ray tracer culls nothing, it outlines the frozen camera's view
+    /// instead so what the raster renderer would cull can be seen.
+    pub fn update_cull_camera(&mut self, camera: Option<CameraInfo>) {
+        self.cull_camera = camera
+    }
+
+    fn create_octree_buffer(
+        uploader: &mut Uploader,
+        mut data: Vec<i32>,
+    ) -> Arc<DeviceLocalBuffer<[i32]>> {
+        if data.is_empty() {
+            // buffers can't be empty, a root size of 0 marks an empty tree
+            data.push(0);
+        }
+        uploader.upload_buffer(
+            data,
+            BufferUsage {
+                storage_buffer: true,
+                // copied into the buffers edits are patched into
+                transfer_src: true,
+                ..BufferUsage::none()
+            },
+        )
+    }
+
+    // takes the pending patches, replacing octree_buffer with a buffer for
+    // them to be copied into along with the old one and the regions to copy
+    fn patch_octree(
+        &mut self,
+    ) -> (
+        Arc<DeviceLocalBuffer<[i32]>>,
+        Arc<CpuAccessibleBuffer<[i32]>>,
+        Vec<BufferCopy>,
+    ) {
+        let patches = mem::take(&mut self.octree_patches);
+        let mut regions = Vec::new();
+        let mut words = Vec::new();
+        for patch in patches {
+            regions.push(BufferCopy {
+                src_offset: words.len() as u64,
+                dst_offset: patch.start as u64,
+                size: patch.words.len() as u64,
+                ..Default::default()
+            });
+            words.extend(patch.words);
+        }
+        let device = self.queue.device().clone();
+        let staging = CpuAccessibleBuffer::from_iter(
+            device.clone(),
+            BufferUsage::transfer_src(),
+            false,
+            words,
+        )
+        .unwrap();
+        let new = DeviceLocalBuffer::array(
+            device,
+            self.octree_buffer.len(),
+            BufferUsage {
+                storage_buffer: true,
+                transfer_src: true,
+                transfer_dst: true,
+                ..BufferUsage::none()
+            },
+            [self.queue.family()],
+        )
+        .unwrap();
+        let old = mem::replace(&mut self.octree_buffer, new);
+        self.accumulation.reset();
+        (old, staging, regions)
+    }
+
+    /// Uploads the octree on the transfer queue. The next frame waits for the
+    /// upload before tracing. The buffer gets room for the nodes edits grow,
+    /// see `update_chunks`.
+    pub fn update_octree(&mut self, tree: &Octree<MaterialId>) {
+        let mut words = tree.serialize();
+        let capacity = OctreeLayout::capacity_for(words.len());
+        let layout = OctreeLayout::new(&words, capacity);
+        if layout.is_some() {
+            words.resize(capacity, 0);
+        }
+        let buffer = Self::create_octree_buffer(&mut self.uploader, words);
+        self.replace_octree(buffer);
+        self.octree_layout = layout;
+    }
+
+    /// Serializes the octree's nodes around the chunks of `dirty` that fit in
+    /// this frame again on worker threads, and patches the finished ones into
+    /// the next frame's copy of the octree's buffer. Nodes already being
+    /// serialized again hold back the chunks inside or around them. The whole
+    /// tree is uploaded instead when it grew past its root or out of its
+    /// buffer.
+    pub fn update_chunks(&mut self, tree: &Octree<MaterialId>, dirty: &mut DirtyChunks) {
+        for (id, node, words) in self.octree_patcher.poll() {
+            if self.patching.get(&node) != Some(&id) {
+                continue;
+            }
+            self.patching.remove(&node);
+            let patches = self
+                .octree_layout
+                .as_mut()
+                .and_then(|layout| layout.place(node, words));
+            match patches {
+                Some(patches) => self.octree_patches.extend(patches),
+                None => {
+                    dirty.clear();
+                    return self.update_octree(tree);
+                }
+            }
+        }
+        if dirty.is_empty() {
+            return;
+        }
+        let layout = match (&self.octree_layout, tree.bounds()) {
+            (Some(layout), Some(bounds)) if layout.covers(bounds) => layout,
+            _ => {
+                dirty.clear();
+                return self.update_octree(tree);
+            }
+        };
+        for chunk in dirty.take(MAX_CHUNKS_PER_FRAME) {
+            let nodes = layout.patch_nodes(chunk);
+            let nested =
+                |a: &Aabc, b: &Aabc| a != b && (a.contains_aabc(*b) || b.contains_aabc(*a));
+            if nodes
+                .iter()
+                .any(|node| self.patching.keys().any(|p| nested(node, p)))
+            {
+                dirty.requeue(chunk);
+                continue;
+            }
+            for node in nodes {
+                let id = self.next_patch;
+                self.next_patch += 1;
+                self.patching.insert(node, id);
+                self.octree_patcher.submit(PatchJob {
+                    id,
+                    node,
+                    leaves: tree.leaves_in(node),
+                });
+            }
+        }
+    }
+
+    /// Starts uploading `tree` without changing the world being drawn, so a
+    /// new world can be prepared while the current one is still shown.
+    pub fn upload_octree(&mut self, tree: &Octree<MaterialId>) -> Arc<DeviceLocalBuffer<[i32]>> {
+        Self::create_octree_buffer(&mut self.uploader, tree.serialize())
+    }
+
+    /// Same as `update_octree` for a world already serialized the way
+    /// `Octree::serialize` does it, with leaves of `MaterialId::WORDS` words,
+    /// so voxels kept in something other than an `Octree` can be drawn too.
+    /// Data the shader couldn't walk safely is refused, see
+    /// `Octree::check_serialized`.
+    pub fn update_serialized_octree(&mut self, data: Vec<i32>) -> Result<(), String> {
+        Octree::<MaterialId>::check_serialized(&data)?;
+        let buffer = Self::create_octree_buffer(&mut self.uploader, data);
+        self.replace_octree(buffer);
+        Ok(())
+    }
+
+    /// Draws the world in `buffer`, from `upload_octree`, starting with the
+    /// next frame. Frames already submitted finish with the previous world.
+    /// Edits of the previous world still being patched in are dropped.
+    pub fn replace_octree(&mut self, buffer: Arc<DeviceLocalBuffer<[i32]>>) {
+        self.next_octree_buffer = Some(buffer);
+        self.octree_layout = None;
+        self.patching.clear();
+        self.octree_patches.clear();
+        self.accumulation.reset();
+    }
+
+    /// Device memory held by the octree, the textures and the images rendered
+    /// into, leaving out the swapchain's.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let mut usage = MemoryUsage::default();
+        for buffer in [Some(&self.octree_buffer), self.next_octree_buffer.as_ref()]
+            .into_iter()
+            .flatten()
+        {
+            usage.add(MemoryKind::Octree, buffer.size());
+        }
+        let textures: [&dyn ImageAccess; 4] = [
+            &**self.material_textures.image(),
+            &**self.blue_noise.image(),
+            &**self.light_volume.image(),
+            &**self.environment.image(),
+        ];
+        for texture in textures {
+            usage.add(MemoryKind::Textures, Self::image_bytes(texture));
+        }
+        for image in [
+            &self.storage_image,
+            &self.accumulation_image,
+            &self.minimap_image,
+            &self.gbuffer.depth,
+            &self.gbuffer.normal,
+            &self.gbuffer.material,
+        ] {
+            usage.add(MemoryKind::Images, Self::image_bytes(&**image));
+        }
+        if let Some((image, gbuffer)) = &self.spare_targets {
+            for image in [image, &gbuffer.depth, &gbuffer.normal, &gbuffer.material] {
+                usage.add(MemoryKind::Images, Self::image_bytes(&**image));
+            }
+        }
+        if let Some(image) = &self.spare_minimap {
+            usage.add(MemoryKind::Images, Self::image_bytes(&**image));
+        }
+        let effects = self.denoiser.memory()
+            + self.bloom.memory()
+            + self.taa.memory()
+            + self.fxaa.memory()
+            + self.upscaler.memory()
+            + self.hud.memory();
+        usage.add(MemoryKind::Images, effects);
+        usage
+    }
+
+    fn create_hud_info_buffer(
+        device: Arc<Device>,
+        hud_info: HudInfo,
+    ) -> Arc<CpuAccessibleBuffer<HudInfo>> {
+        CpuAccessibleBuffer::from_data(
+            device,
+            BufferUsage {
+                uniform_buffer: true,
+                ..BufferUsage::none()
+            },
+            false,
+            hud_info,
+        )
+        .unwrap()
+    }
+
+    pub fn update_hud(&mut self, hud_info: HudInfo) {
+        self.hud_info = Self::create_hud_info_buffer(self.queue.device().clone(), hud_info)
+    }
+
+    fn create_lighting_buffer(
+        device: Arc<Device>,
+        lighting: Lighting,
+    ) -> Arc<CpuAccessibleBuffer<Lighting>> {
+        CpuAccessibleBuffer::from_data(
+            device,
+            BufferUsage {
+                uniform_buffer: true,
+                ..BufferUsage::none()
+            },
+            false,
+            lighting,
+        )
+        .unwrap()
+    }
+
+    pub fn update_lighting(&mut self, mut lighting: Lighting) {
+        let shadows = match self.frame_budget.quality() {
+            Some(quality) => Shadows {
+                samples: self.shadows.samples.min(quality.shadow_samples),
+                ..self.shadows
+            },
+            None => self.shadows,
+        };
+        shadows.apply_to_sun(&mut lighting);
+        if shadows.is_enabled() != self.shader_features.shadows {
+            self.set_shader_features(ShaderFeatures {
+                shadows: shadows.is_enabled(),
+                ..self.shader_features
+            });
+        }
+        self.lighting_values = lighting;
+        self.lighting = Self::create_lighting_buffer(self.queue.device().clone(), lighting)
+    }
+
+    /// Stops rays `voxels` away, where the fog also ends.
+    pub fn update_view_distance(&mut self, voxels: f32) {
+        self.view_distance = voxels;
+        self.accumulation.reset();
+    }
+
+    /// Shows the status in the window title.
+    pub fn update_status(&mut self, status: &Status) {
+        self.surface.window().set_title(&status.title())
+    }
+
+    fn create_decal_buffer(
+        device: Arc<Device>,
+        decals: Vec<i32>,
+    ) -> Arc<CpuAccessibleBuffer<[i32]>> {
+        CpuAccessibleBuffer::from_iter(
+            device,
+            BufferUsage {
+                storage_buffer: true,
+                ..BufferUsage::none()
+            },
+            false,
+            decals,
+        )
+        .unwrap()
+    }
+
+    /// Replaces the lines drawn over the image, see `debug_draw`.
+    pub fn update_debug_lines(&mut self, lines: Vec<Line>) {
+        self.debug_lines = lines;
+    }
+
+    pub fn update_decals(&mut self, decals: &DecalList) {
+        self.decal_values = decals.serialize();
+        self.decal_buffer =
+            Self::create_decal_buffer(self.queue.device().clone(), self.decal_values.clone())
+    }
+
+    fn create_entity_buffer(
+        device: Arc<Device>,
+        entities: &EntityList,
+    ) -> Arc<CpuAccessibleBuffer<[f32]>> {
+        CpuAccessibleBuffer::from_iter(
+            device,
+            BufferUsage {
+                storage_buffer: true,
+                ..BufferUsage::none()
+            },
+            false,
+            entities.serialize(),
+        )
+        .unwrap()
+    }
+
+    pub fn update_entities(&mut self, entities: &EntityList) {
+        self.entity_buffer = Self::create_entity_buffer(self.queue.device().clone(), entities)
+    }
+
+    // the texture of every id of `materials`, from the file it names or its
+    // row of cubemap.png, which rows the texture manager found the same share
+    fn material_texture_ids(
+        textures: &mut TextureManager,
+        cubemap_textures: &[TextureId],
+        materials: &MaterialRegistry,
+    ) -> Vec<TextureId> {
+        (0..materials.len() as MaterialId)
+            .map(|id| {
+                if let Some(path) = materials.texture_file(id) {
+                    match textures.load(path) {
+                        Ok(texture) => return texture,
+                        Err(e) => {
+                            warn!(path = %path.display(), error = %e, "Could not load texture")
+                        }
+                    }
+                }
+                let row = materials.texture(id);
+                cubemap_textures
+                    .get(row as usize)
+                    .map_or(row as TextureId, |&id| id)
+            })
+            .collect()
+    }
+
+    fn create_material_buffer(
+        device: Arc<Device>,
+        materials: &MaterialRegistry,
+        texture_ids: &[TextureId],
+    ) -> Arc<CpuAccessibleBuffer<[f32]>> {
+        let mut data = materials.serialize();
+        for (material, &id) in data.chunks_mut(4).zip(texture_ids) {
+            material[3] = id as f32;
+        }
+        CpuAccessibleBuffer::from_iter(
+            device,
+            BufferUsage {
+                storage_buffer: true,
+                ..BufferUsage::none()
+            },
+            false,
+            data,
+        )
+        .unwrap()
+    }
+
+    // array layers from the texture of each material to the frame of its
+    // animation shown `time` seconds in, see TextureAnimation
+    fn texture_frame_buffer(&self, time: f32) -> Arc<CpuAccessibleBuffer<[i32]>> {
+        let texture = |row: MaterialId| {
+            self.cubemap_textures
+                .get(row as usize)
+                .map_or(row, |&id| id as MaterialId)
+        };
+        let layers: Vec<i32> = self
+            .materials
+            .textures_at(time)
+            .into_iter()
+            .enumerate()
+            .map(|(id, row)| {
+                let shown = texture(row) - texture(self.materials.texture(id as MaterialId));
+                shown * FACES as i32
+            })
+            .collect();
+        CpuAccessibleBuffer::from_iter(
+            self.queue.device().clone(),
+            BufferUsage {
+                storage_buffer: true,
+                ..BufferUsage::none()
+            },
+            false,
+            layers,
+        )
+        .unwrap()
+    }
+
+    /// Loads the textures `materials` name that weren't before, recreating
+    /// the texture array with them.
+    pub fn update_materials(&mut self, materials: &MaterialRegistry) {
+        let texture_ids =
+            Self::material_texture_ids(&mut self.textures, &self.cubemap_textures, materials);
+        // frames in flight keep the array they were recorded with
+        if let Some(faces) = self.textures.take_upload() {
+            self.material_textures = Self::create_material_textures(
+                self.queue.device().clone(),
+                &mut self.uploader,
+                self.textures.face_size(),
+                faces,
+            );
+        }
+        self.material_buffer =
+            Self::create_material_buffer(self.queue.device().clone(), materials, &texture_ids);
+        self.materials = materials.clone();
+        self.accumulation.reset();
+    }
+}
+
+// the edges of camera's view out to FROZEN_FRUSTUM_LENGTH on a window of size
+fn frustum_outline(camera: &CameraInfo, size: [u32; 2]) -> Vec<Line> {
+    let aspect = size[0] as f32 / size[1] as f32;
+    let corners = camera::frustum_corners(camera, aspect, FROZEN_FRUSTUM_LENGTH);
+    let line = |a, b| Line {
+        a,
+        b,
+        color: FROZEN_FRUSTUM_COLOR,
+    };
+    (0..4)
+        .flat_map(|i| {
+            [
+                line(camera.eye, corners[i]),
+                line(corners[i], corners[(i + 1) % 4]),
+            ]
+        })
+        .collect()
+}
+
+/// Picks the device that can present to `surface` with the given extensions
+/// and features, preferring discrete GPUs, along with its graphics queue
+/// family.
+pub(crate) fn select_physical_device<'a>(
+    surface: &'a Arc<Surface<Window>>,
+    device_extensions: &DeviceExtensions,
+    features: &Features,
+) -> Option<(PhysicalDevice<'a>, QueueFamily<'a>)> {
+    PhysicalDevice::enumerate(surface.instance())
+        .filter(|&p| p.supported_extensions().is_superset_of(device_extensions))
+        .filter(|p| p.supported_features().is_superset_of(features))
+        .filter_map(|p| {
+            p.queue_families()
+                .find(|&q| q.supports_graphics() && q.supports_surface(surface).unwrap_or(false))
+                .map(|q| (p, q))
+        })
+        .min_by_key(|(p, _)| match p.properties().device_type {
+            PhysicalDeviceType::DiscreteGpu => 0,
+            PhysicalDeviceType::IntegratedGpu => 1,
+            PhysicalDeviceType::VirtualGpu => 2,
+            PhysicalDeviceType::Cpu => 3,
+            PhysicalDeviceType::Other => 4,
+        })
+}
+
+/// Creates a swapchain the size of the window whose images can be rendered
+/// to or blitted to, with enough images for `frames_in_flight`.
+pub(crate) fn create_swapchain(
+    device: Arc<Device>,
+    surface: Arc<Surface<Window>>,
+    present_mode: PresentMode,
+    frames_in_flight: usize,
+) -> (Arc<Swapchain<Window>>, Vec<Arc<SwapchainImage<Window>>>) {
+    let physical_device = device.physical_device();
+    let surface_capabilities = physical_device
+        .surface_capabilities(&surface, SurfaceInfo::default())
+        .unwrap();
+    let image_format = Some(
+        physical_device
+            .surface_formats(&surface, SurfaceInfo::default())
+            .unwrap()[0]
+            .0,
+    );
+    Swapchain::new(
+        device,
+        surface.clone(),
+        SwapchainCreateInfo {
+            min_image_count: frames_in_flight::swapchain_image_count(
+                frames_in_flight,
+                surface_capabilities.min_image_count,
+                surface_capabilities.max_image_count,
+            ),
+            image_format,
+            image_extent: surface.window().inner_size().into(),
+            image_usage: ImageUsage {
+                transfer_dst: true,
+                ..ImageUsage::color_attachment()
+            },
+            composite_alpha: surface_capabilities
+                .supported_composite_alpha
+                .iter()
+                .next()
+                .unwrap(),
+            present_mode,
+            ..SwapchainCreateInfo::default()
+        },
+    )
+    .unwrap()
 }
 
 pub mod cs {
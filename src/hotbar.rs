@@ -0,0 +1,100 @@
+use crate::materials::{MaterialId, MaterialRegistry};
+
+/// Number of slots, selected with the number keys 1 to 9.
+pub const HOTBAR_SLOTS: usize = 9;
+
+/// The materials at hand for placing blocks, one of which is selected.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hotbar {
+    slots: Vec<MaterialId>,
+    selected: usize,
+}
+
+impl Hotbar {
+    /// Keeps the first `HOTBAR_SLOTS` of `slots`, which must not be empty.
+    pub fn new(mut slots: Vec<MaterialId>) -> Self {
+        assert!(!slots.is_empty());
+        slots.truncate(HOTBAR_SLOTS);
+        Hotbar { slots, selected: 0 }
+    }
+
+    /// The first materials of the registry, after air.
+    pub fn from_registry(materials: &MaterialRegistry) -> Self {
+        let count = materials.len().min(HOTBAR_SLOTS + 1) as MaterialId;
+        Self::new((1..count).collect())
+    }
+
+    pub fn slots(&self) -> &[MaterialId] {
+        &self.slots
+    }
+
+    pub fn selected_slot(&self) -> usize {
+        self.selected
+    }
+
+    pub fn selected(&self) -> MaterialId {
+        self.slots[self.selected]
+    }
+
+    /// Selects a slot, ignoring slots past the end.
+    pub fn select(&mut self, slot: usize) {
+        if slot < self.slots.len() {
+            self.selected = slot
+        }
+    }
+
+    /// Selects the next slot, or the previous one, wrapping around.
+    pub fn scroll(&mut self, forward: bool) {
+        let len = self.slots.len();
+        let step = if forward { 1 } else { len - 1 };
+        self.selected = (self.selected + step) % len
+    }
+
+    /// The slots packed 4 per ivec4 for the HUD, empty slots are 0.
+    pub fn serialize(&self) -> [[i32; 4]; 3] {
+        let mut packed = [[0; 4]; 3];
+        for (i, material) in self.slots.iter().enumerate() {
+            packed[i / 4][i % 4] = *material;
+        }
+        packed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_registry_skips_air() {
+        let hotbar = Hotbar::from_registry(&MaterialRegistry::default());
+        assert_eq!(HOTBAR_SLOTS, hotbar.slots().len());
+        assert_eq!(1, hotbar.selected());
+    }
+
+    #[test]
+    fn select_ignores_missing_slots() {
+        let mut hotbar = Hotbar::new(vec![4, 5]);
+        hotbar.select(1);
+        assert_eq!(5, hotbar.selected());
+        hotbar.select(7);
+        assert_eq!(5, hotbar.selected());
+    }
+
+    #[test]
+    fn scroll_wraps() {
+        let mut hotbar = Hotbar::new(vec![4, 5, 6]);
+        hotbar.scroll(false);
+        assert_eq!(6, hotbar.selected());
+        hotbar.scroll(true);
+        assert_eq!(4, hotbar.selected());
+    }
+
+    #[test]
+    fn serialize_packs_slots() {
+        let hotbar = Hotbar::new((1..=20).collect());
+        assert_eq!(
+            [[1, 2, 3, 4], [5, 6, 7, 8], [9, 0, 0, 0]],
+            hotbar.serialize()
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
+    descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet},
+    device::Queue,
+    image::{view::ImageView, ImageAccess, StorageImage},
+    memory::pool::StdMemoryPool,
+    pipeline::{ComputePipeline, Pipeline, PipelineBindPoint},
+};
+
+use crate::{
+    graphics::{Graphics, COMPUTE_GROUP_SIZE},
+    workgroups::group_count,
+};
+
+/// Draws the crosshair, hotbar and break progress over the post-processed
+/// image, so the denoiser and TAA never blur them. The image is copied rather
+/// than drawn over, as it may be the history of TAA.
+pub struct Hud {
+    pipeline: Arc<ComputePipeline>,
+    output: Arc<StorageImage<Arc<StdMemoryPool>>>,
+}
+
+impl Hud {
+    pub fn new(queue: &Arc<Queue>, size: [u32; 2]) -> Self {
+        let shader = hud_cs::load(queue.device().clone()).unwrap();
+        let pipeline = ComputePipeline::new(
+            queue.device().clone(),
+            shader.entry_point("main").unwrap(),
+            &(),
+            None,
+            |_| {},
+        )
+        .unwrap();
+        Hud {
+            pipeline,
+            output: Graphics::create_storage_image(queue, size),
+        }
+    }
+
+    /// Bytes of device memory its image holds.
+    pub fn memory(&self) -> u64 {
+        Graphics::image_bytes(&*self.output)
+    }
+
+    /// Resizes the output image, e.g. after the swapchain was recreated.
+    pub fn resize(&mut self, queue: &Arc<Queue>, size: [u32; 2]) {
+        if self.output.dimensions().width_height() != size {
+            self.output = Graphics::create_storage_image(queue, size);
+        }
+    }
+
+    /// Records the HUD drawn over `input`, which must be the size of the
+    /// output, and returns the output image. `inputs` are bindings 1 to 4 of
+    /// hud.comp.
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        input: Arc<StorageImage<Arc<StdMemoryPool>>>,
+        inputs: impl IntoIterator<Item = WriteDescriptorSet>,
+    ) -> Arc<StorageImage<Arc<StdMemoryPool>>> {
+        let size = self.output.dimensions().width_height();
+        let mut writes = vec![
+            WriteDescriptorSet::image_view(0, ImageView::new_default(input).unwrap()),
+            WriteDescriptorSet::image_view(5, ImageView::new_default(self.output.clone()).unwrap()),
+        ];
+        writes.extend(inputs);
+        let desc_set = PersistentDescriptorSet::new(
+            self.pipeline.layout().set_layouts().get(0).unwrap().clone(),
+            writes,
+        )
+        .unwrap();
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipeline.layout().clone(),
+                0,
+                desc_set,
+            )
+            .dispatch(group_count(size, [COMPUTE_GROUP_SIZE; 2]))
+            .unwrap();
+        self.output.clone()
+    }
+}
+
+pub mod hud_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/hud.comp",
+    }
+}
@@ -0,0 +1,8 @@
+pub mod bookmarks;
+pub mod export;
+pub mod exr;
+pub mod frames;
+pub mod hdr;
+pub mod import;
+pub mod metadata;
+pub mod region;
@@ -0,0 +1,174 @@
+use std::{fs, io, path::Path};
+
+use quaternion::Quaternion;
+use vecmath::Vector3;
+
+use super::region::{self, invalid};
+
+const FILE: &str = "bookmarks";
+
+/// A named camera pose to come back to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bookmark {
+    pub name: String,
+    pub position: Vector3<f32>,
+    pub orientation: Quaternion<f32>,
+}
+
+/// Bookmarks in the order they were made, each name at most once.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Bookmarks {
+    bookmarks: Vec<Bookmark>,
+}
+
+impl Bookmarks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `bookmark`, replacing the one of the same name where it was.
+    pub fn set(&mut self, bookmark: Bookmark) {
+        match self.bookmarks.iter_mut().find(|b| b.name == bookmark.name) {
+            Some(existing) => *existing = bookmark,
+            None => self.bookmarks.push(bookmark),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Bookmark> {
+        self.bookmarks.iter().find(|b| b.name == name)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Bookmark> {
+        let i = self.bookmarks.iter().position(|b| b.name == name)?;
+        Some(self.bookmarks.remove(i))
+    }
+
+    pub fn len(&self) -> usize {
+        self.bookmarks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bookmarks.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Bookmark> {
+        self.bookmarks.iter()
+    }
+
+    /// The bookmark after the one named `current`, going around to the first
+    /// after the last or when there's no such bookmark.
+    pub fn next_after(&self, current: Option<&str>) -> Option<&Bookmark> {
+        let next = current
+            .and_then(|name| self.bookmarks.iter().position(|b| b.name == name))
+            .map_or(0, |i| i + 1);
+        self.bookmarks.get(next).or_else(|| self.bookmarks.first())
+    }
+
+    /// A name no bookmark has yet, for bookmarks made without one.
+    pub fn unused_name(&self) -> String {
+        (1..)
+            .map(|i| format!("bookmark{}", i))
+            .find(|name| self.get(name).is_none())
+            .unwrap()
+    }
+
+    // a line of the position, orientation and name of each bookmark, the
+    // name last since it may have spaces
+    fn to_text(&self) -> String {
+        self.bookmarks
+            .iter()
+            .map(|b| {
+                let [x, y, z] = b.position;
+                let (w, [i, j, k]) = b.orientation;
+                format!("{} {} {} {} {} {} {} {}\n", x, y, z, w, i, j, k, b.name)
+            })
+            .collect()
+    }
+
+    fn parse(text: &str) -> io::Result<Self> {
+        let mut bookmarks = Bookmarks::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let bad = || invalid(&format!("bad bookmark: {}", line));
+            let mut fields = line.splitn(8, ' ');
+            let mut number = || -> io::Result<f32> {
+                fields.next().and_then(|f| f.parse().ok()).ok_or_else(bad)
+            };
+            let position = [number()?, number()?, number()?];
+            let orientation = (number()?, [number()?, number()?, number()?]);
+            let name = fields
+                .next()
+                .filter(|name| !name.is_empty())
+                .ok_or_else(bad)?;
+            bookmarks.set(Bookmark {
+                name: name.to_string(),
+                position,
+                orientation,
+            });
+        }
+        Ok(bookmarks)
+    }
+}
+
+/// Reads the bookmarks saved in `dir`, none if nothing was saved there.
+pub fn load(dir: &Path) -> io::Result<Bookmarks> {
+    match fs::read_to_string(dir.join(FILE)) {
+        Ok(text) => Bookmarks::parse(&text),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Bookmarks::new()),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn save(dir: &Path, bookmarks: &Bookmarks) -> io::Result<()> {
+    region::write_atomically(&dir.join(FILE), bookmarks.to_text().as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bookmark(name: &str, x: f32) -> Bookmark {
+        Bookmark {
+            name: name.to_string(),
+            position: [x, 64.5, -3.25],
+            orientation: (0.5, [0.5, -0.5, 0.5]),
+        }
+    }
+
+    #[test]
+    fn bookmarks_round_trip() {
+        let mut bookmarks = Bookmarks::new();
+        bookmarks.set(bookmark("spawn", 0.0));
+        bookmarks.set(bookmark("the big cave", -1e6));
+        assert_eq!(bookmarks, Bookmarks::parse(&bookmarks.to_text()).unwrap());
+        assert!(Bookmarks::parse("1 2 3 1 0 0 0\n").is_err());
+        assert!(Bookmarks::parse("1 2 x 1 0 0 0 name\n").is_err());
+    }
+
+    #[test]
+    fn names_are_kept_once() {
+        let mut bookmarks = Bookmarks::new();
+        bookmarks.set(bookmark("a", 1.0));
+        bookmarks.set(bookmark("b", 2.0));
+        bookmarks.set(bookmark("a", 3.0));
+        assert_eq!(2, bookmarks.len());
+        assert_eq!(3.0, bookmarks.get("a").unwrap().position[0]);
+        assert_eq!("bookmark1", bookmarks.unused_name());
+        bookmarks.set(bookmark("bookmark1", 0.0));
+        assert_eq!("bookmark2", bookmarks.unused_name());
+        assert_eq!("b", bookmarks.remove("b").unwrap().name);
+        assert_eq!(None, bookmarks.remove("b"));
+    }
+
+    #[test]
+    fn next_goes_around() {
+        let mut bookmarks = Bookmarks::new();
+        assert_eq!(None, bookmarks.next_after(None));
+        bookmarks.set(bookmark("a", 1.0));
+        bookmarks.set(bookmark("b", 2.0));
+        let next = |current| bookmarks.next_after(current).unwrap().name.as_str();
+        assert_eq!("a", next(None));
+        assert_eq!("b", next(Some("a")));
+        assert_eq!("a", next(Some("b")));
+        assert_eq!("a", next(Some("removed")));
+    }
+}
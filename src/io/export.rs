@@ -0,0 +1,198 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+use vecmath::Vector3;
+
+use crate::{
+    materials::{MaterialId, MaterialRegistry},
+    mesh,
+    prefab::VoxelPrefab,
+};
+
+// order of the normals written to .obj files
+const NORMALS: [Vector3<i32>; 6] = [
+    [1, 0, 0],
+    [-1, 0, 0],
+    [0, 1, 0],
+    [0, -1, 0],
+    [0, 0, 1],
+    [0, 0, -1],
+];
+
+/// Writes a greedy mesh of the prefab as a y up Wavefront .obj, with one
+/// material per voxel material described in the .mtl file named `mtl_name`.
+pub fn write_obj<W: Write, M: Write>(
+    prefab: &VoxelPrefab,
+    materials: &MaterialRegistry,
+    mtl_name: &str,
+    obj: &mut W,
+    mtl: &mut M,
+) -> io::Result<()> {
+    let voxels: HashMap<_, _> = prefab.voxels.iter().copied().collect();
+    let mut quads = mesh::greedy_quads([0; 3], prefab.extent, materials, |pos| {
+        voxels.get(&pos).copied()
+    });
+    quads.sort_by_key(|q| q.material);
+    writeln!(obj, "mtllib {}", mtl_name)?;
+    for quad in &quads {
+        for c in quad.corners {
+            writeln!(obj, "v {} {} {}", c[0], c[1], c[2])?;
+        }
+    }
+    for n in NORMALS {
+        writeln!(obj, "vn {} {} {}", n[0], n[1], n[2])?;
+    }
+    let mut current = 0;
+    for (i, quad) in quads.iter().enumerate() {
+        if quad.material != current {
+            current = quad.material;
+            writeln!(obj, "usemtl {}", material_name(materials, current))?;
+        }
+        let n = NORMALS.iter().position(|&n| n == quad.normal).unwrap() + 1;
+        let v = 4 * i + 1;
+        writeln!(
+            obj,
+            "f {}//{n} {}//{n} {}//{n} {}//{n}",
+            v,
+            v + 1,
+            v + 2,
+            v + 3,
+            n = n
+        )?;
+    }
+
+    let mut used: Vec<_> = quads.iter().map(|q| q.material).collect();
+    used.dedup();
+    for id in used {
+        writeln!(mtl, "newmtl {}", material_name(materials, id))?;
+        let (color, opacity) = materials
+            .get(id)
+            .map_or(([255; 3], 1.0), |m| (m.color, m.opacity));
+        let [r, g, b] = color.map(|c| c as f32 / 255.0);
+        writeln!(mtl, "Kd {:.3} {:.3} {:.3}", r, g, b)?;
+        if opacity < 1.0 {
+            writeln!(mtl, "d {:.3}", opacity)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the prefab as a single model MagicaVoxel .vox file. The palette
+/// index of each voxel is its material id, and y up becomes z up.
+pub fn write_vox<W: Write>(
+    prefab: &VoxelPrefab,
+    materials: &MaterialRegistry,
+    out: &mut W,
+) -> io::Result<()> {
+    if prefab.extent.iter().any(|&e| e > 256) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} is too large for a .vox model", prefab.extent),
+        ));
+    }
+    let [width, height, depth] = prefab.extent;
+    let mut size = Vec::new();
+    for e in [width, depth, height] {
+        size.extend_from_slice(&e.to_le_bytes());
+    }
+
+    let mut xyzi = (prefab.voxels.len() as u32).to_le_bytes().to_vec();
+    for ([x, y, z], material) in &prefab.voxels {
+        if !(1..256).contains(material) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("material {} has no palette index", material),
+            ));
+        }
+        // rotating about x keeps the model right handed
+        xyzi.extend_from_slice(&[*x as u8, (depth - 1 - z) as u8, *y as u8, *material as u8]);
+    }
+
+    // entry i holds the color of palette index i + 1
+    let mut rgba = Vec::with_capacity(256 * 4);
+    for index in 1..=256 {
+        let (color, opacity) = materials
+            .get(index)
+            .map_or(([0; 3], 1.0), |m| (m.color, m.opacity));
+        rgba.extend_from_slice(&color);
+        rgba.push((opacity * 255.0).round() as u8);
+    }
+
+    let mut children = Vec::new();
+    write_chunk(&mut children, b"SIZE", &size, &[])?;
+    write_chunk(&mut children, b"XYZI", &xyzi, &[])?;
+    write_chunk(&mut children, b"RGBA", &rgba, &[])?;
+    out.write_all(b"VOX ")?;
+    out.write_all(&150u32.to_le_bytes())?;
+    write_chunk(out, b"MAIN", &[], &children)
+}
+
+fn write_chunk<W: Write>(
+    out: &mut W,
+    id: &[u8; 4],
+    content: &[u8],
+    children: &[u8],
+) -> io::Result<()> {
+    out.write_all(id)?;
+    out.write_all(&(content.len() as u32).to_le_bytes())?;
+    out.write_all(&(children.len() as u32).to_le_bytes())?;
+    out.write_all(content)?;
+    out.write_all(children)
+}
+
+fn material_name(materials: &MaterialRegistry, id: MaterialId) -> String {
+    materials
+        .get(id)
+        .map_or(format!("material_{}", id), |m| m.name.replace(' ', "_"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn obj_references_materials() {
+        let prefab = VoxelPrefab::new([0; 3], [2, 1, 1], [([0, 0, 0], 1), ([1, 0, 0], 3)]);
+        let (mut obj, mut mtl) = (Vec::new(), Vec::new());
+        write_obj(
+            &prefab,
+            &MaterialRegistry::default(),
+            "a.mtl",
+            &mut obj,
+            &mut mtl,
+        )
+        .unwrap();
+        let obj = String::from_utf8(obj).unwrap();
+        let mtl = String::from_utf8(mtl).unwrap();
+        assert!(obj.starts_with("mtllib a.mtl\n"));
+        assert_eq!(40, obj.lines().filter(|l| l.starts_with("v ")).count());
+        assert_eq!(10, obj.lines().filter(|l| l.starts_with("f ")).count());
+        assert!(obj.contains("usemtl dark_grass\n"));
+        assert!(mtl.contains("newmtl dirt\n"));
+        assert_eq!(2, mtl.lines().filter(|l| l.starts_with("Kd ")).count());
+    }
+
+    #[test]
+    fn vox_layout() {
+        let prefab = VoxelPrefab::new([0; 3], [2, 3, 4], [([1, 2, 0], 5)]);
+        let mut out = Vec::new();
+        write_vox(&prefab, &MaterialRegistry::default(), &mut out).unwrap();
+        assert_eq!(b"VOX ", &out[0..4]);
+        assert_eq!(b"MAIN", &out[8..12]);
+        // SIZE chunk is first, z up
+        assert_eq!(b"SIZE", &out[20..24]);
+        assert_eq!([2, 0, 0, 0, 4, 0, 0, 0, 3, 0, 0, 0], out[32..44]);
+        assert_eq!(b"XYZI", &out[44..48]);
+        assert_eq!([1, 0, 0, 0, 1, 3, 2, 5], out[56..64]);
+        assert_eq!(b"RGBA", &out[64..68]);
+        assert_eq!(76 + 256 * 4, out.len());
+    }
+
+    #[test]
+    fn vox_rejects_large_models() {
+        let prefab = VoxelPrefab::new([0; 3], [300, 1, 1], []);
+        assert!(write_vox(&prefab, &MaterialRegistry::default(), &mut Vec::new()).is_err());
+    }
+}
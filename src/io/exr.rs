@@ -0,0 +1,409 @@
+use std::io::{self, Read};
+
+use flate2::read::ZlibDecoder;
+
+use crate::io::{
+    hdr::{check_size, unsupported, HdrImage},
+    region::invalid,
+};
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+// version flags of tiled, deep and multi-part files
+const TILED: u32 = 0x200;
+const NON_IMAGE: u32 = 0x800;
+const MULTI_PART: u32 = 0x1000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PixelType {
+    Uint,
+    Half,
+    Float,
+}
+
+impl PixelType {
+    fn size(self) -> usize {
+        match self {
+            PixelType::Half => 2,
+            PixelType::Uint | PixelType::Float => 4,
+        }
+    }
+
+    fn value(self, bytes: &[u8]) -> f32 {
+        match self {
+            PixelType::Uint => u32::from_le_bytes(bytes[..4].try_into().unwrap()) as f32,
+            PixelType::Half => half_to_f32(u16::from_le_bytes([bytes[0], bytes[1]])),
+            PixelType::Float => f32::from_le_bytes(bytes[..4].try_into().unwrap()),
+        }
+    }
+}
+
+struct Channel {
+    name: String,
+    pixel_type: PixelType,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Compression {
+    None,
+    Rle,
+    Zips,
+    Zip,
+}
+
+impl Compression {
+    // scanlines compressed together
+    fn lines(self) -> usize {
+        match self {
+            Compression::Zip => 16,
+            Compression::None | Compression::Rle | Compression::Zips => 1,
+        }
+    }
+}
+
+/// Reads the R, G and B channels of an OpenEXR file's scanlines,
+/// uncompressed or compressed with RLE, ZIPS or ZIP, the compressions
+/// environment maps are usually saved with. Tiled, deep and multi-part files
+/// and the other compressions are unsupported.
+pub fn read_exr(bytes: &[u8]) -> io::Result<HdrImage> {
+    if !bytes.starts_with(&MAGIC) {
+        return Err(invalid("not an OpenEXR file"));
+    }
+    let mut rest = &bytes[4..];
+    let version = read_u32(&mut rest)?;
+    if version & (TILED | NON_IMAGE | MULTI_PART) != 0 {
+        return Err(unsupported(
+            "only single part scanline images are supported",
+        ));
+    }
+    let (mut channels, mut compression, mut window) = (None, None, None);
+    // the header is a list of attributes ending with an empty name
+    loop {
+        let name = next_str(&mut rest)?;
+        if name.is_empty() {
+            break;
+        }
+        next_str(&mut rest)?;
+        let len = read_u32(&mut rest)? as usize;
+        let mut value = take(&mut rest, len)?;
+        match name {
+            "channels" => channels = Some(read_channels(&mut value)?),
+            "compression" => compression = Some(read_compression(value)?),
+            "dataWindow" => window = Some(read_box(&mut value)?),
+            _ => (),
+        }
+    }
+    let channels = channels.ok_or_else(|| invalid("no channels"))?;
+    let compression = compression.ok_or_else(|| invalid("no compression"))?;
+    let [x_min, y_min, x_max, y_max] = window.ok_or_else(|| invalid("no data window"))?;
+    let size = |min: i32, max: i32| u32::try_from(max as i64 - min as i64 + 1).unwrap_or(0);
+    let (width, height) = (size(x_min, x_max), size(y_min, y_max));
+    check_size(width, height)?;
+    let rgb = ["R", "G", "B"].map(|name| channels.iter().position(|c| c.name == name));
+    if rgb.contains(&None) {
+        return Err(unsupported(
+            "only images with R, G and B channels are supported",
+        ));
+    }
+
+    let (width, height) = (width as usize, height as usize);
+    let lines = compression.lines();
+    let line_len: usize = channels.iter().map(|c| c.pixel_type.size() * width).sum();
+    // a chunk of scanlines each, the table has to be there before anything
+    // is allocated
+    let offsets = take(&mut rest, (height + lines - 1) / lines * 8)?;
+    let mut pixels = vec![[0.0; 3]; width * height];
+    for offset in offsets.chunks(8) {
+        let offset = u64::from_le_bytes(offset.try_into().unwrap());
+        let mut chunk = usize::try_from(offset)
+            .ok()
+            .and_then(|offset| bytes.get(offset..))
+            .ok_or_else(|| invalid("chunk past the end of the file"))?;
+        let y = read_u32(&mut chunk)? as i32;
+        let len = read_u32(&mut chunk)? as usize;
+        let data = take(&mut chunk, len)?;
+        let first = y as i64 - y_min as i64;
+        if first < 0 || first >= height as i64 || first as usize % lines != 0 {
+            return Err(invalid("chunk outside of the image"));
+        }
+        let first = first as usize;
+        let count = lines.min(height - first);
+        let unpacked;
+        let data = if data.len() == count * line_len {
+            // stored as is when compressing didn't make it smaller
+            data
+        } else {
+            unpacked = decompress(compression, data, count * line_len)?;
+            &unpacked[..]
+        };
+        for (line, scanline) in data.chunks(line_len).enumerate() {
+            let row = &mut pixels[(first + line) * width..][..width];
+            let mut start = 0;
+            for (i, channel) in channels.iter().enumerate() {
+                let size = channel.pixel_type.size();
+                if let Some(c) = rgb.iter().position(|&index| index == Some(i)) {
+                    for (pixel, value) in row.iter_mut().zip(scanline[start..].chunks(size)) {
+                        pixel[c] = channel.pixel_type.value(value);
+                    }
+                }
+                start += size * width;
+            }
+        }
+    }
+    Ok(HdrImage {
+        width: width as u32,
+        height: height as u32,
+        pixels,
+    })
+}
+
+fn take<'a>(rest: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    if rest.len() < len {
+        return Err(invalid("truncated file"));
+    }
+    let (taken, left) = rest.split_at(len);
+    *rest = left;
+    Ok(taken)
+}
+
+fn read_u32(rest: &mut &[u8]) -> io::Result<u32> {
+    Ok(u32::from_le_bytes(take(rest, 4)?.try_into().unwrap()))
+}
+
+fn next_str<'a>(rest: &mut &'a [u8]) -> io::Result<&'a str> {
+    let end = rest
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| invalid("truncated header"))?;
+    let name = std::str::from_utf8(&rest[..end]).map_err(|_| invalid("invalid header"))?;
+    *rest = &rest[end + 1..];
+    Ok(name)
+}
+
+// the first and last pixel of the box, inclusive
+fn read_box(value: &mut &[u8]) -> io::Result<[i32; 4]> {
+    let mut corners = [0; 4];
+    for c in &mut corners {
+        *c = read_u32(value)? as i32;
+    }
+    Ok(corners)
+}
+
+// sorted by name, which is the order their values have in each scanline
+fn read_channels(value: &mut &[u8]) -> io::Result<Vec<Channel>> {
+    let mut channels = Vec::new();
+    loop {
+        let name = next_str(value)?;
+        if name.is_empty() {
+            return Ok(channels);
+        }
+        let pixel_type = match read_u32(value)? {
+            0 => PixelType::Uint,
+            1 => PixelType::Half,
+            2 => PixelType::Float,
+            _ => return Err(invalid("invalid pixel type")),
+        };
+        // linear flag and reserved bytes
+        take(value, 4)?;
+        let sampling = [read_u32(value)?, read_u32(value)?];
+        if sampling != [1, 1] {
+            return Err(unsupported("subsampled channels aren't supported"));
+        }
+        channels.push(Channel {
+            name: name.to_string(),
+            pixel_type,
+        });
+    }
+}
+
+fn read_compression(value: &[u8]) -> io::Result<Compression> {
+    match value.first() {
+        Some(0) => Ok(Compression::None),
+        Some(1) => Ok(Compression::Rle),
+        Some(2) => Ok(Compression::Zips),
+        Some(3) => Ok(Compression::Zip),
+        Some(_) => Err(unsupported(
+            "only uncompressed, RLE and ZIP compressed images are supported",
+        )),
+        None => Err(invalid("invalid compression")),
+    }
+}
+
+// RLE and ZIP both compress the bytes as differences to the previous one,
+// with the first halves of the values before their second halves
+fn decompress(compression: Compression, data: &[u8], len: usize) -> io::Result<Vec<u8>> {
+    let mut packed = Vec::with_capacity(len);
+    match compression {
+        Compression::None => (),
+        Compression::Rle => {
+            let mut rest = data;
+            while !rest.is_empty() && packed.len() <= len {
+                let count = take(&mut rest, 1)?[0] as i8;
+                if count < 0 {
+                    packed.extend_from_slice(take(&mut rest, -(count as isize) as usize)?);
+                } else {
+                    let value = take(&mut rest, 1)?[0];
+                    packed.extend(std::iter::repeat(value).take(count as usize + 1));
+                }
+            }
+        }
+        Compression::Zips | Compression::Zip => {
+            ZlibDecoder::new(data)
+                .take(len as u64 + 1)
+                .read_to_end(&mut packed)
+                .map_err(|_| invalid("invalid ZIP data"))?;
+        }
+    }
+    if packed.len() != len {
+        return Err(invalid("chunk doesn't hold its scanlines"));
+    }
+    for i in 1..len {
+        packed[i] = packed[i - 1].wrapping_add(packed[i]).wrapping_sub(128);
+    }
+    let (first, second) = packed.split_at((len + 1) / 2);
+    Ok((0..len)
+        .map(|i| {
+            if i % 2 == 0 {
+                first[i / 2]
+            } else {
+                second[i / 2]
+            }
+        })
+        .collect())
+}
+
+fn half_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 == 0 { 1.0 } else { -1.0 };
+    let exponent = (bits >> 10) & 0x1f;
+    let mantissa = (bits & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => f32::INFINITY,
+        31 => f32::NAN,
+        e => (1.0 + mantissa / 1024.0) * 2f32.powi(e as i32 - 15),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression as Level};
+
+    use super::*;
+
+    const ONE: u16 = 0x3c00;
+    const HALF: u16 = 0x3800;
+    const TWO: u16 = 0x4000;
+
+    fn attribute(bytes: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+        for s in [name, kind] {
+            bytes.extend_from_slice(s.as_bytes());
+            bytes.push(0);
+        }
+        bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(value);
+    }
+
+    // a file of half B, G and R channels with `chunks` of the scanlines, each
+    // starting at the y they're paired with
+    fn file(version: u32, compression: u8, size: [u32; 2], chunks: &[(i32, Vec<u8>)]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&version.to_le_bytes());
+        let mut channels = Vec::new();
+        for name in ["B", "G", "R"] {
+            channels.extend_from_slice(name.as_bytes());
+            channels.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0]);
+        }
+        channels.push(0);
+        attribute(&mut bytes, "channels", "chlist", &channels);
+        attribute(&mut bytes, "compression", "compression", &[compression]);
+        let window: Vec<u8> = [0, 0, size[0] - 1, size[1] - 1]
+            .iter()
+            .flat_map(|c| c.to_le_bytes())
+            .collect();
+        attribute(&mut bytes, "dataWindow", "box2i", &window);
+        attribute(&mut bytes, "lineOrder", "lineOrder", &[0]);
+        bytes.push(0);
+        let mut offset = bytes.len() + chunks.len() * 8;
+        for (_, data) in chunks {
+            bytes.extend_from_slice(&(offset as u64).to_le_bytes());
+            offset += 8 + data.len();
+        }
+        for (y, data) in chunks {
+            bytes.extend_from_slice(&y.to_le_bytes());
+            bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(data);
+        }
+        bytes
+    }
+
+    fn halves(values: &[u16]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    // what decompress undoes
+    fn zip(data: &[u8]) -> Vec<u8> {
+        let (even, odd): (Vec<_>, Vec<_>) = data.iter().enumerate().partition(|(i, _)| i % 2 == 0);
+        let mut packed: Vec<u8> = even.into_iter().chain(odd).map(|(_, &b)| b).collect();
+        for i in (1..packed.len()).rev() {
+            packed[i] = packed[i].wrapping_sub(packed[i - 1]).wrapping_add(128);
+        }
+        let mut encoder = ZlibEncoder::new(Vec::new(), Level::default());
+        encoder.write_all(&packed).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn uncompressed_scanlines_are_read() {
+        // B, G and R of both pixels in turn
+        let line = halves(&[0, 0, 0, HALF, ONE, TWO]);
+        let image = read_exr(&file(2, 0, [2, 1], &[(0, line)])).unwrap();
+        assert_eq!((2, 1), (image.width, image.height));
+        assert_eq!(vec![[1.0, 0.0, 0.0], [2.0, 0.5, 0.0]], image.pixels);
+    }
+
+    #[test]
+    fn zip_compressed_scanlines_are_read() {
+        let lines: Vec<u16> = (0..2 * 3 * 4).map(|i| 0x3c00 + i).collect();
+        let raw = read_exr(&file(
+            2,
+            0,
+            [4, 2],
+            &[(0, halves(&lines[..12])), (1, halves(&lines[12..]))],
+        ))
+        .unwrap();
+        let zipped = read_exr(&file(2, 3, [4, 2], &[(0, zip(&halves(&lines)))])).unwrap();
+        assert_eq!(raw, zipped);
+        assert_eq!([1.0 + 8.0 / 1024.0, 1.0 + 4.0 / 1024.0, 1.0], raw.pixels[0]);
+    }
+
+    #[test]
+    fn halves_are_widened() {
+        assert_eq!(1.0, half_to_f32(ONE));
+        assert_eq!(-2.0, half_to_f32(0xc000));
+        assert_eq!(65504.0, half_to_f32(0x7bff));
+        assert_eq!(2f32.powi(-24), half_to_f32(1));
+        assert_eq!(f32::INFINITY, half_to_f32(0x7c00));
+    }
+
+    #[test]
+    fn bad_files_are_errors() {
+        assert!(read_exr(b"#?RADIANCE\n").is_err());
+        let line = halves(&[0; 6]);
+        // truncated, too large and with chunks outside of the image
+        assert!(read_exr(&file(2, 0, [2, 2], &[(0, line.clone())])).is_err());
+        assert!(read_exr(&file(2, 0, [1 << 16, 1], &[])).is_err());
+        assert!(read_exr(&file(2, 0, [2, 1], &[(5, line.clone())])).is_err());
+        assert!(read_exr(&file(2, 3, [2, 1], &[(0, vec![1, 2, 3])])).is_err());
+        let tiled = file(2 | TILED, 0, [2, 1], &[(0, line.clone())]);
+        assert_eq!(
+            io::ErrorKind::Unsupported,
+            read_exr(&tiled).unwrap_err().kind()
+        );
+        let piz = file(2, 4, [2, 1], &[(0, line)]);
+        assert_eq!(
+            io::ErrorKind::Unsupported,
+            read_exr(&piz).unwrap_err().kind()
+        );
+    }
+}
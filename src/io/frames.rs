@@ -0,0 +1,204 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, SyncSender, TrySendError},
+    thread::{self, JoinHandle},
+};
+
+use tracing::error;
+
+/// A rendered RGBA8 frame waiting to be written.
+pub struct Frame {
+    pub index: u32,
+    pub size: [u32; 2],
+    pub pixels: Vec<u8>,
+}
+
+pub fn frame_path(dir: &Path, index: u32) -> PathBuf {
+    dir.join(format!("frame_{:06}.png", index))
+}
+
+pub fn write_png<W: Write>(
+    out: W,
+    size: [u32; 2],
+    pixels: &[u8],
+) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(out, size[0], size[1]);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    // encoding speed matters more than size when recording
+    encoder.set_compression(png::Compression::Fast);
+    encoder.write_header()?.write_image_data(pixels)
+}
+
+/// Reads a PNG as its size and RGBA8 pixels, whatever its color type and bit
+/// depth. Grayscale and RGB images are opaque unless they have a transparent
+/// color, and 16 bit channels lose their low bits.
+pub fn read_png<R: Read>(input: R) -> Result<([u32; 2], Vec<u8>), png::DecodingError> {
+    let mut decoder = png::Decoder::new(input);
+    // palettes, bits below 8 and transparent colors are expanded into 8 bit
+    // channels, so only the four color types below remain
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels)?;
+    pixels.truncate(info.buffer_size());
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => pixels,
+        png::ColorType::Rgb => pixels
+            .chunks(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => pixels
+            .chunks(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        png::ColorType::Grayscale => pixels.iter().flat_map(|&v| [v, v, v, 255]).collect(),
+        png::ColorType::Indexed => unreachable!("palettes are expanded"),
+    };
+    Ok(([info.width, info.height], pixels))
+}
+
+/// Writes frames as numbered PNGs on a background thread so encoding doesn't
+/// hold up rendering.
+pub struct FrameWriter {
+    sender: Option<SyncSender<Frame>>,
+    thread: Option<JoinHandle<u32>>,
+}
+
+impl FrameWriter {
+    /// Creates `dir` if needed. At most `queue_len` frames wait to be written.
+    pub fn new(dir: PathBuf, queue_len: usize) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let (sender, receiver) = mpsc::sync_channel(queue_len);
+        Ok(FrameWriter {
+            sender: Some(sender),
+            thread: Some(thread::spawn(move || write_frames(&dir, receiver))),
+        })
+    }
+
+    /// Queues a frame, or drops it and returns false if the writer is behind.
+    pub fn send(&self, frame: Frame) -> bool {
+        match self.sender.as_ref().unwrap().try_send(frame) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => false,
+            Err(TrySendError::Disconnected(_)) => panic!("frame writer stopped"),
+        }
+    }
+
+    /// Waits for the queued frames and returns how many were written.
+    pub fn finish(mut self) -> u32 {
+        self.sender = None;
+        self.thread.take().unwrap().join().unwrap()
+    }
+}
+
+fn write_frames(dir: &Path, frames: Receiver<Frame>) -> u32 {
+    let mut written = 0;
+    for frame in frames {
+        let path = frame_path(dir, frame.index);
+        let result = File::create(&path)
+            .map_err(png::EncodingError::from)
+            .and_then(|file| write_png(BufWriter::new(file), frame.size, &frame.pixels));
+        match result {
+            Ok(()) => written += 1,
+            Err(e) => error!(path = %path.display(), "Could not write frame: {}", e),
+        }
+    }
+    written
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn decode(data: Vec<u8>) -> (png::OutputInfo, Vec<u8>) {
+        let mut reader = png::Decoder::new(Cursor::new(data)).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        pixels.truncate(info.buffer_size());
+        (info, pixels)
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let pixels: Vec<u8> = (0..2 * 3 * 4).collect();
+        let mut out = Vec::new();
+        write_png(&mut out, [2, 3], &pixels).unwrap();
+        let (info, decoded) = decode(out);
+        assert_eq!((2, 3), (info.width, info.height));
+        assert_eq!(png::ColorType::Rgba, info.color_type);
+        assert_eq!(pixels, decoded);
+    }
+
+    #[test]
+    fn png_is_read_as_rgba() {
+        let pixels: Vec<u8> = (0..3 * 2 * 4).collect();
+        let mut out = Vec::new();
+        write_png(&mut out, [3, 2], &pixels).unwrap();
+        assert_eq!(([3, 2], pixels), read_png(Cursor::new(out)).unwrap());
+    }
+
+    // encodes `data` as a 2x1 image of the given format
+    fn encode(
+        color: png::ColorType,
+        depth: png::BitDepth,
+        data: &[u8],
+        setup: impl FnOnce(&mut png::Encoder<&mut Vec<u8>>),
+    ) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, 2, 1);
+        encoder.set_color(color);
+        encoder.set_depth(depth);
+        setup(&mut encoder);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(data)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn other_formats_are_read_as_rgba() {
+        use png::{BitDepth::*, ColorType::*};
+        let read = |png: Vec<u8>| read_png(Cursor::new(png)).unwrap().1;
+        let rgba = vec![10, 20, 30, 255, 40, 50, 60, 255];
+        let rgb = encode(Rgb, Eight, &[10, 20, 30, 40, 50, 60], |_| ());
+        assert_eq!(rgba, read(rgb));
+        let gray = vec![7, 7, 7, 255, 200, 200, 200, 255];
+        assert_eq!(gray, read(encode(Grayscale, Eight, &[7, 200], |_| ())));
+        let gray_alpha = encode(GrayscaleAlpha, Eight, &[7, 100, 200, 0], |_| ());
+        assert_eq!(vec![7, 7, 7, 100, 200, 200, 200, 0], read(gray_alpha));
+        // big endian, the high byte kept
+        let wide = encode(Grayscale, Sixteen, &[7, 1, 200, 2], |_| ());
+        assert_eq!(gray, read(wide));
+        // the second pixel is the first palette color, made half transparent
+        let indexed = encode(Indexed, Eight, &[1, 0], |e| {
+            e.set_palette(vec![10, 20, 30, 40, 50, 60]);
+            e.set_trns(vec![128]);
+        });
+        assert_eq!(vec![40, 50, 60, 255, 10, 20, 30, 128], read(indexed));
+    }
+
+    #[test]
+    fn writer_numbers_frames() {
+        let dir = std::env::temp_dir().join(format!("rtvox-frames-{}", std::process::id()));
+        let writer = FrameWriter::new(dir.clone(), 4).unwrap();
+        for index in [0, 7] {
+            assert!(writer.send(Frame {
+                index,
+                size: [1, 1],
+                pixels: vec![255, 0, 0, 255],
+            }));
+        }
+        assert_eq!(2, writer.finish());
+        let (_, pixels) = decode(fs::read(frame_path(&dir, 7)).unwrap());
+        assert_eq!(vec![255, 0, 0, 255], pixels);
+        assert!(frame_path(&dir, 0).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+use std::io;
+
+use crate::io::region::invalid;
+
+/// Widths and heights past this are refused as corrupt, a 16K map is half as
+/// wide.
+pub const MAX_SIZE: u32 = 1 << 15;
+/// Pixels past this are refused too, those of a 16K map.
+pub const MAX_PIXELS: u64 = 1 << 27;
+
+/// An image of linear RGB values that may go above 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    /// Row by row from the top.
+    pub pixels: Vec<[f32; 3]>,
+}
+
+/// Reads a Radiance .hdr (RGBE) file, run length encoded or not. Only the
+/// usual top to bottom, left to right orientation is supported.
+pub fn read_hdr(bytes: &[u8]) -> io::Result<HdrImage> {
+    if !bytes.starts_with(b"#?") {
+        return Err(invalid("not a Radiance .hdr file"));
+    }
+    let mut rest = bytes;
+    // the header ends with an empty line
+    loop {
+        let line = next_line(&mut rest)?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(unsupported(&format!("unsupported format {}", format)));
+            }
+        }
+    }
+    let resolution = next_line(&mut rest)?;
+    let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", height, "+X", width] => (
+            height.parse().map_err(|_| invalid("invalid height"))?,
+            width.parse().map_err(|_| invalid("invalid width"))?,
+        ),
+        _ => {
+            return Err(unsupported(&format!(
+                "unsupported orientation {}",
+                resolution
+            )))
+        }
+    };
+    check_size(width, height)?;
+    if rest.len() < height as usize * min_scanline_len(width as usize) {
+        return Err(invalid("truncated pixels"));
+    }
+    let mut pixels = Vec::with_capacity(width as usize * height as usize);
+    let mut scanline = vec![[0; 4]; width as usize];
+    for _ in 0..height {
+        read_scanline(&mut rest, &mut scanline)?;
+        pixels.extend(scanline.iter().map(|&rgbe| to_rgb(rgbe)));
+    }
+    Ok(HdrImage {
+        width,
+        height,
+        pixels,
+    })
+}
+
+/// Refuses empty images and those larger than `MAX_SIZE` or `MAX_PIXELS`,
+/// before anything is allocated for them.
+pub(crate) fn check_size(width: u32, height: u32) -> io::Result<()> {
+    let pixels = width as u64 * height as u64;
+    if pixels == 0 || pixels > MAX_PIXELS || width > MAX_SIZE || height > MAX_SIZE {
+        return Err(invalid(&format!("invalid size {}x{}", width, height)));
+    }
+    Ok(())
+}
+
+// the fewest bytes a scanline of `width` pixels can take, in runs of 127 when
+// it's run length encoded
+fn min_scanline_len(width: usize) -> usize {
+    if (8..0x8000).contains(&width) {
+        4 + 4 * 2 * ((width + 126) / 127)
+    } else {
+        4 * width
+    }
+}
+
+pub(crate) fn unsupported(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, message)
+}
+
+fn next_line<'a>(rest: &mut &'a [u8]) -> io::Result<&'a str> {
+    let end = rest
+        .iter()
+        .position(|&b| b == b'\n')
+        .ok_or_else(|| invalid("truncated header"))?;
+    let line = std::str::from_utf8(&rest[..end]).map_err(|_| invalid("invalid header"))?;
+    *rest = &rest[end + 1..];
+    Ok(line.trim_end_matches('\r'))
+}
+
+fn take<'a>(rest: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    if rest.len() < len {
+        return Err(invalid("truncated pixels"));
+    }
+    let (taken, left) = rest.split_at(len);
+    *rest = left;
+    Ok(taken)
+}
+
+// Scanlines are either flat RGBE pixels or, starting with 2, 2 and the width,
+// each of the four channels in turn as runs and literal spans.
+fn read_scanline(rest: &mut &[u8], scanline: &mut [[u8; 4]]) -> io::Result<()> {
+    let width = scanline.len();
+    let start = take(rest, 4)?;
+    let encoded =
+        (8..0x8000).contains(&width) && start[0] == 2 && start[1] == 2 && start[2] & 0x80 == 0;
+    if !encoded {
+        scanline[0].copy_from_slice(start);
+        for pixel in &mut scanline[1..] {
+            pixel.copy_from_slice(take(rest, 4)?);
+        }
+        return Ok(());
+    }
+    if (start[2] as usize) << 8 | start[3] as usize != width {
+        return Err(invalid("scanline width doesn't match the image"));
+    }
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = take(rest, 1)?[0] as usize;
+            let (run, len) = if count > 128 {
+                (true, count - 128)
+            } else {
+                (false, count)
+            };
+            if len == 0 || x + len > width {
+                return Err(invalid("run past the end of the scanline"));
+            }
+            if run {
+                let value = take(rest, 1)?[0];
+                for pixel in &mut scanline[x..x + len] {
+                    pixel[channel] = value;
+                }
+            } else {
+                for (pixel, &value) in scanline[x..x + len].iter_mut().zip(take(rest, len)?) {
+                    pixel[channel] = value;
+                }
+            }
+            x += len;
+        }
+    }
+    Ok(())
+}
+
+// the mantissas share an exponent biased by 128, and are 8 bit fractions
+fn to_rgb([r, g, b, e]: [u8; 4]) -> [f32; 3] {
+    if e == 0 {
+        return [0.0; 3];
+    }
+    let scale = 2f32.powi(e as i32 - 136);
+    [r, g, b].map(|c| c as f32 * scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+        let mut bytes = format!(
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+            height, width
+        )
+        .into_bytes();
+        bytes.extend_from_slice(pixels);
+        bytes
+    }
+
+    #[test]
+    fn flat_pixels_are_read() {
+        let image = read_hdr(&file(2, 1, &[128, 64, 32, 129, 128, 128, 128, 131])).unwrap();
+        assert_eq!((2, 1), (image.width, image.height));
+        assert_eq!(vec![[1.0, 0.5, 0.25], [4.0, 4.0, 4.0]], image.pixels);
+    }
+
+    #[test]
+    fn run_length_encoded_pixels_are_read() {
+        let mut pixels = vec![2, 2, 0, 8];
+        // red in one run, green in literals, blue and the exponent in runs
+        pixels.extend_from_slice(&[128 + 8, 128]);
+        pixels.extend_from_slice(&[8, 0, 16, 32, 48, 64, 80, 96, 112]);
+        pixels.extend_from_slice(&[128 + 8, 0]);
+        pixels.extend_from_slice(&[128 + 4, 129, 128 + 4, 0]);
+        let image = read_hdr(&file(8, 1, &pixels)).unwrap();
+        assert_eq!([1.0, 0.0, 0.0], image.pixels[0]);
+        assert_eq!([1.0, 0.125, 0.0], image.pixels[1]);
+        assert_eq!([0.0; 3], image.pixels[7]);
+    }
+
+    #[test]
+    fn bad_files_are_errors() {
+        assert!(read_hdr(b"P6\n").is_err());
+        assert!(read_hdr(&file(2, 2, &[128, 64, 32, 129])).is_err());
+        // sizes are checked against the limit and the bytes there are
+        assert!(read_hdr(&file(0, 1, &[])).is_err());
+        assert!(read_hdr(&file(MAX_SIZE + 1, 1, &[0; 4])).is_err());
+        assert!(read_hdr(&file(4096, 4096, &[2, 2, 16, 0])).is_err());
+        let flipped = b"#?RADIANCE\n\n+Y 1 +X 1\n\x80\x80\x80\x80";
+        assert_eq!(
+            io::ErrorKind::Unsupported,
+            read_hdr(flipped).unwrap_err().kind()
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use std::io;
+
+use crate::{io::region::invalid, prefab::VoxelPrefab};
+
+/// Reads every model of a MagicaVoxel .vox file, in the order they're stored,
+/// as `export::write_vox` writes them: material ids are palette indices and z
+/// up becomes y up. Files holding several models are how MagicaVoxel keeps
+/// animations.
+pub fn read_vox(bytes: &[u8]) -> io::Result<Vec<VoxelPrefab>> {
+    if bytes.len() < 8 || &bytes[0..4] != b"VOX " {
+        return Err(invalid("not a .vox file"));
+    }
+    let (main, _) = read_chunk(&bytes[8..])?;
+    if main.id != b"MAIN" || !main.content.is_empty() {
+        return Err(invalid("expected a MAIN chunk"));
+    }
+
+    let mut models = Vec::new();
+    let mut extent = None;
+    let mut rest = main.children;
+    while !rest.is_empty() {
+        let (chunk, next) = read_chunk(rest)?;
+        let content = chunk.content;
+        rest = next;
+        match chunk.id {
+            b"SIZE" => {
+                if content.len() < 12 {
+                    return Err(invalid("truncated SIZE chunk"));
+                }
+                let [width, depth, height] = [0, 4, 8].map(|i| u32_at(content, i) as i32);
+                extent = Some([width, height, depth]);
+            }
+            b"XYZI" => {
+                let [width, height, depth] = extent
+                    .take()
+                    .ok_or_else(|| invalid("XYZI chunk without a SIZE chunk"))?;
+                if content.len() < 4 {
+                    return Err(invalid("truncated XYZI chunk"));
+                }
+                let count = u32_at(content, 0) as usize;
+                let voxels = content[4..]
+                    .chunks_exact(4)
+                    .take(count)
+                    .map(|v| {
+                        let [x, y, z, index] = [v[0], v[1], v[2], v[3]].map(|b| b as i32);
+                        // rotating back about x
+                        ([x, z, depth - 1 - y], index)
+                    })
+                    .collect::<Vec<_>>();
+                if voxels.len() != count {
+                    return Err(invalid("truncated XYZI chunk"));
+                }
+                models.push(VoxelPrefab::new([0; 3], [width, height, depth], voxels));
+            }
+            // palette, materials and the scene graph
+            _ => (),
+        }
+    }
+    if models.is_empty() {
+        return Err(invalid("no models"));
+    }
+    Ok(models)
+}
+
+struct Chunk<'a> {
+    id: &'a [u8],
+    content: &'a [u8],
+    children: &'a [u8],
+}
+
+// the chunk at the start of `bytes`, and what follows it
+fn read_chunk(bytes: &[u8]) -> io::Result<(Chunk<'_>, &[u8])> {
+    if bytes.len() < 12 {
+        return Err(invalid("truncated chunk header"));
+    }
+    let content_len = u32_at(bytes, 4) as usize;
+    let children_len = u32_at(bytes, 8) as usize;
+    let end = 12 + content_len + children_len;
+    if bytes.len() < end {
+        return Err(invalid("truncated chunk"));
+    }
+    let chunk = Chunk {
+        id: &bytes[0..4],
+        content: &bytes[12..12 + content_len],
+        children: &bytes[12 + content_len..end],
+    };
+    Ok((chunk, &bytes[end..]))
+}
+
+fn u32_at(bytes: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{io::export::write_vox, materials::MaterialRegistry};
+
+    fn vox(prefab: &VoxelPrefab) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_vox(prefab, &MaterialRegistry::default(), &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn vox_round_trip() {
+        let prefab = VoxelPrefab::new([0; 3], [2, 3, 4], [([1, 2, 0], 5), ([0, 0, 3], 1)]);
+        assert_eq!(vec![prefab.clone()], read_vox(&vox(&prefab)).unwrap());
+    }
+
+    #[test]
+    fn every_model_is_read() {
+        let first = VoxelPrefab::new([0; 3], [1, 1, 1], [([0, 0, 0], 1)]);
+        let second = VoxelPrefab::new([0; 3], [2, 1, 1], [([1, 0, 0], 2)]);
+        // a second SIZE and XYZI pair after the first model's, in MAIN
+        let mut bytes = vox(&first);
+        let model = vox(&second)[20..64].to_vec();
+        bytes.splice(64..64, model);
+        let children_len = u32_at(&bytes, 16) + 44;
+        bytes[16..20].copy_from_slice(&children_len.to_le_bytes());
+        assert_eq!(vec![first, second], read_vox(&bytes).unwrap());
+    }
+
+    #[test]
+    fn garbage_is_an_error() {
+        assert!(read_vox(b"VOX ").is_err());
+        assert!(read_vox(b"not a vox file at all").is_err());
+        let mut truncated = vox(&VoxelPrefab::new([0; 3], [1, 1, 1], [([0, 0, 0], 1)]));
+        truncated.truncate(60);
+        assert!(read_vox(&truncated).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use std::{fs, io, path::Path};
+
+use vecmath::Vector3;
+
+use super::region::{self, invalid};
+use crate::worldgen;
+
+/// Version of the saves written, bumped whenever the format changes with a
+/// migration from the previous version added to `MIGRATIONS`.
+pub const FORMAT_VERSION: u32 = 2;
+// migrations[i] upgrades a save from version i + 1 to i + 2
+const MIGRATIONS: [fn(&Path) -> io::Result<()>; FORMAT_VERSION as usize - 1] = [region::migrate_v1];
+
+const FILE: &str = "world";
+// version 1 saves only stored the seed, in a file of its own
+const SEED_FILE: &str = "seed";
+
+/// What a saved world was generated from, kept next to its chunks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorldMetadata {
+    /// Version of the format the world was saved in.
+    pub format: u32,
+    pub seed: u64,
+    /// `worldgen::GENERATOR` when the world was saved, chunks that weren't
+    /// saved are generated again with the current one.
+    pub generator: String,
+    /// Where the camera was when the world was last saved.
+    pub spawn: Option<Vector3<f32>>,
+}
+
+impl WorldMetadata {
+    /// Metadata of a world saved now.
+    pub fn new(seed: u64, spawn: Vector3<f32>) -> Self {
+        WorldMetadata {
+            format: FORMAT_VERSION,
+            seed,
+            generator: worldgen::GENERATOR.to_string(),
+            spawn: Some(spawn),
+        }
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = format!(
+            "format {}\nseed {}\ngenerator {}\n",
+            self.format, self.seed, self.generator
+        );
+        if let Some([x, y, z]) = self.spawn {
+            text += &format!("spawn {} {} {}\n", x, y, z);
+        }
+        text
+    }
+
+    // one "key value" per line, unknown keys are skipped
+    fn parse(text: &str) -> io::Result<Self> {
+        let mut format = None;
+        let mut seed = None;
+        let mut generator = None;
+        let mut spawn = None;
+        for line in text.lines() {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let bad = || invalid(&format!("bad world metadata: {}", line));
+            match key {
+                "format" => format = Some(value.parse().map_err(|_| bad())?),
+                "seed" => seed = Some(value.parse().map_err(|_| bad())?),
+                "generator" => generator = Some(value.to_string()),
+                "spawn" => {
+                    let coords: Vec<f32> = value
+                        .split(' ')
+                        .map(str::parse)
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| bad())?;
+                    spawn = Some(coords.try_into().map_err(|_| bad())?);
+                }
+                _ => {}
+            }
+        }
+        match (format, seed, generator) {
+            (Some(format), Some(seed), Some(generator)) => Ok(WorldMetadata {
+                format,
+                seed,
+                generator,
+                spawn,
+            }),
+            _ => Err(invalid("incomplete world metadata")),
+        }
+    }
+}
+
+/// Reads the metadata saved in `dir`, None if nothing was saved there.
+pub fn load(dir: &Path) -> io::Result<Option<WorldMetadata>> {
+    match fs::read_to_string(dir.join(FILE)) {
+        Ok(text) => return WorldMetadata::parse(&text).map(Some),
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        Err(_) => {}
+    }
+    match fs::read_to_string(dir.join(SEED_FILE)) {
+        Ok(seed) => Ok(Some(WorldMetadata {
+            format: 1,
+            seed: seed.trim().parse().map_err(|_| invalid("bad seed"))?,
+            generator: worldgen::GENERATOR.to_string(),
+            spawn: None,
+        })),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn save(dir: &Path, metadata: &WorldMetadata) -> io::Result<()> {
+    region::write_atomically(&dir.join(FILE), metadata.to_text().as_bytes())
+}
+
+/// Upgrades the save in `dir` to `FORMAT_VERSION` one version at a time.
+/// The version reached is saved after each step, so an interrupted migration
+/// carries on where it stopped.
+pub fn migrate(dir: &Path, metadata: &mut WorldMetadata) -> io::Result<()> {
+    if metadata.format > FORMAT_VERSION {
+        return Err(invalid(&format!(
+            "saved by a newer version, format {}",
+            metadata.format
+        )));
+    }
+    while metadata.format < FORMAT_VERSION {
+        MIGRATIONS[metadata.format as usize - 1](dir)?;
+        metadata.format += 1;
+        save(dir, metadata)?;
+    }
+    match fs::remove_file(dir.join(SEED_FILE)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_round_trips() {
+        let metadata = WorldMetadata::new(u64::MAX, [1.5, -20.0, 0.25]);
+        let parsed = WorldMetadata::parse(&metadata.to_text()).unwrap();
+        assert_eq!(metadata, parsed);
+        let mut no_spawn = metadata;
+        no_spawn.spawn = None;
+        assert_eq!(no_spawn, WorldMetadata::parse(&no_spawn.to_text()).unwrap());
+    }
+
+    #[test]
+    fn bad_metadata_is_an_error() {
+        assert!(WorldMetadata::parse("format 2\nseed 1\n").is_err());
+        assert!(WorldMetadata::parse("format 2\nseed x\ngenerator a\n").is_err());
+        assert!(WorldMetadata::parse("format 2\nseed 1\ngenerator a\nspawn 1 2\n").is_err());
+        let extra = WorldMetadata::parse("format 2\nseed 1\ngenerator a\nfuture 3\n");
+        assert_eq!(1, extra.unwrap().seed);
+    }
+
+    #[test]
+    fn newer_formats_are_not_migrated() {
+        let mut metadata = WorldMetadata::new(1, [0.0; 3]);
+        metadata.format = FORMAT_VERSION + 1;
+        assert!(migrate(Path::new("unused"), &mut metadata).is_err());
+    }
+}
@@ -0,0 +1,621 @@
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use tracing::error;
+use vecmath::Vector3;
+
+use super::{
+    bookmarks::{self, Bookmarks},
+    metadata::{self, WorldMetadata, FORMAT_VERSION},
+};
+use crate::{
+    materials::MaterialId,
+    worldgen::{ChunkPos, CHUNK_SIZE},
+};
+
+/// Chunks along each axis of a region file.
+pub const REGION_CHUNKS: i32 = 8;
+const SLOTS: usize = (REGION_CHUNKS * REGION_CHUNKS * REGION_CHUNKS) as usize;
+const MAGIC: &[u8; 4] = b"RTVR";
+// magic, version and an (offset, length) pair per slot
+const HEADER_LEN: u64 = 8 + 8 * SLOTS as u64;
+const COMPRESSION_LEVEL: i32 = 3;
+
+// tags of the serialized octree nodes. Since version 2 a split node is
+// followed by a mask of its non-empty children and only those are stored,
+// before that all 8 were.
+const EMPTY: u8 = 0;
+const SOLID: u8 = 1;
+const SPLIT: u8 = 2;
+
+/// The voxels of a chunk, in world coordinates.
+pub type ChunkVoxels = Vec<(Vector3<i32>, MaterialId)>;
+
+pub fn region_of(chunk: ChunkPos) -> Vector3<i32> {
+    chunk.map(|c| c.div_euclid(REGION_CHUNKS))
+}
+
+// index of the chunk in the header of its region file
+fn slot(chunk: ChunkPos) -> usize {
+    let [x, y, z] = chunk.map(|c| c.rem_euclid(REGION_CHUNKS) as usize);
+    x + REGION_CHUNKS as usize * (y + REGION_CHUNKS as usize * z)
+}
+
+/// Serializes the voxels of a chunk as a preorder octree over the chunk, so
+/// uniform parts take a single node. Voxels outside the chunk are ignored.
+pub fn encode_chunk(voxels: &[(Vector3<i32>, MaterialId)], chunk: ChunkPos) -> Vec<u8> {
+    let size = CHUNK_SIZE as usize;
+    let origin = chunk.map(|c| c * CHUNK_SIZE);
+    let mut dense = vec![None; size * size * size];
+    for (pos, material) in voxels {
+        let local = vecmath::vec3_sub(*pos, origin);
+        if local.iter().all(|c| (0..CHUNK_SIZE).contains(c)) {
+            dense[dense_index(local)] = Some(*material);
+        }
+    }
+    let mut out = Vec::new();
+    encode_node(&dense, [0; 3], CHUNK_SIZE, &mut out);
+    out
+}
+
+fn dense_index(local: Vector3<i32>) -> usize {
+    let size = CHUNK_SIZE as usize;
+    let [x, y, z] = local.map(|c| c as usize);
+    x + size * (y + size * z)
+}
+
+fn child_origin(origin: Vector3<i32>, half: i32, child: i32) -> Vector3<i32> {
+    [
+        origin[0] + half * (child & 1),
+        origin[1] + half * (child >> 1 & 1),
+        origin[2] + half * (child >> 2 & 1),
+    ]
+}
+
+fn encode_node(dense: &[Option<MaterialId>], origin: Vector3<i32>, size: i32, out: &mut Vec<u8>) {
+    let first = dense[dense_index(origin)];
+    let uniform = (0..size).all(|x| {
+        (0..size).all(|y| {
+            (0..size).all(|z| dense[dense_index(vecmath::vec3_add(origin, [x, y, z]))] == first)
+        })
+    });
+    match first {
+        None if uniform => out.push(EMPTY),
+        Some(material) if uniform => {
+            out.push(SOLID);
+            out.extend_from_slice(&material.to_le_bytes());
+        }
+        _ => {
+            let half = size / 2;
+            let children: Vec<_> = (0..8)
+                .map(|child| {
+                    let mut encoded = Vec::new();
+                    encode_node(dense, child_origin(origin, half, child), half, &mut encoded);
+                    encoded
+                })
+                .collect();
+            let mask = (0..8)
+                .filter(|&child| children[child] != [EMPTY])
+                .fold(0, |mask, child| mask | 1 << child);
+            out.extend_from_slice(&[SPLIT, mask]);
+            for child in children.iter().filter(|c| c[..] != [EMPTY]) {
+                out.extend_from_slice(child);
+            }
+        }
+    }
+}
+
+/// Reads back the voxels of a chunk written by `encode_chunk`.
+pub fn decode_chunk(bytes: &[u8], chunk: ChunkPos) -> io::Result<ChunkVoxels> {
+    decode_chunk_version(bytes, chunk, FORMAT_VERSION)
+}
+
+fn decode_chunk_version(bytes: &[u8], chunk: ChunkPos, version: u32) -> io::Result<ChunkVoxels> {
+    let mut voxels = Vec::new();
+    let mut rest = bytes;
+    let origin = chunk.map(|c| c * CHUNK_SIZE);
+    decode_node(&mut rest, origin, CHUNK_SIZE, version, &mut voxels)?;
+    if !rest.is_empty() {
+        return Err(invalid("trailing bytes after chunk"));
+    }
+    Ok(voxels)
+}
+
+fn decode_node(
+    bytes: &mut &[u8],
+    origin: Vector3<i32>,
+    size: i32,
+    version: u32,
+    voxels: &mut ChunkVoxels,
+) -> io::Result<()> {
+    let mut tag = [0];
+    bytes.read_exact(&mut tag)?;
+    match tag[0] {
+        EMPTY => {}
+        SOLID => {
+            let mut material = [0; 4];
+            bytes.read_exact(&mut material)?;
+            let material = MaterialId::from_le_bytes(material);
+            for x in 0..size {
+                for y in 0..size {
+                    for z in 0..size {
+                        voxels.push((vecmath::vec3_add(origin, [x, y, z]), material));
+                    }
+                }
+            }
+        }
+        SPLIT if size > 1 => {
+            let mut mask = [u8::MAX];
+            if version >= 2 {
+                bytes.read_exact(&mut mask)?;
+            }
+            let half = size / 2;
+            for child in (0..8).filter(|child| mask[0] & 1 << child != 0) {
+                decode_node(
+                    bytes,
+                    child_origin(origin, half, child),
+                    half,
+                    version,
+                    voxels,
+                )?;
+            }
+        }
+        tag => return Err(invalid(&format!("bad node tag {}", tag))),
+    }
+    Ok(())
+}
+
+pub(crate) fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Saved chunks of a world, stored in a directory of region files that each
+/// hold a cube of `REGION_CHUNKS` chunks. A region file starts with an index
+/// of where each chunk's compressed octree is in it, so a chunk is read
+/// without reading the rest of the region. Saving rewrites only the region
+/// files of the saved chunks. The world's `WorldMetadata` is kept alongside,
+/// and saves of older formats are migrated when opened.
+pub struct RegionStore {
+    dir: PathBuf,
+    // chunks are read by the generator workers while the event loop saves
+    files: Mutex<()>,
+}
+
+type Index = [(u32, u32); SLOTS];
+
+impl RegionStore {
+    /// Creates `dir` if needed, or migrates the save in it to the current
+    /// format.
+    pub fn open(dir: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        if let Some(mut saved) = metadata::load(&dir)? {
+            metadata::migrate(&dir, &mut saved)?;
+        }
+        Ok(RegionStore {
+            dir,
+            files: Mutex::new(()),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The metadata of the saved world, None if nothing was saved yet.
+    pub fn metadata(&self) -> io::Result<Option<WorldMetadata>> {
+        metadata::load(&self.dir)
+    }
+
+    pub fn set_metadata(&self, metadata: &WorldMetadata) -> io::Result<()> {
+        metadata::save(&self.dir, metadata)
+    }
+
+    pub fn bookmarks(&self) -> io::Result<Bookmarks> {
+        bookmarks::load(&self.dir)
+    }
+
+    pub fn set_bookmarks(&self, bookmarks: &Bookmarks) -> io::Result<()> {
+        bookmarks::save(&self.dir, bookmarks)
+    }
+
+    fn region_path(&self, region: Vector3<i32>) -> PathBuf {
+        self.dir
+            .join(format!("r.{}.{}.{}.rtvr", region[0], region[1], region[2]))
+    }
+
+    /// Returns the voxels of a saved chunk, or None if it was never saved.
+    pub fn read_chunk(&self, chunk: ChunkPos) -> io::Result<Option<ChunkVoxels>> {
+        let _lock = self.files.lock().unwrap();
+        let mut file = match File::open(self.region_path(region_of(chunk))) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let (offset, len) = read_current_index(&mut file)?[slot(chunk)];
+        if len == 0 {
+            return Ok(None);
+        }
+        let blob = read_blob(&mut file, offset, len)?;
+        decode_chunk(&zstd::decode_all(&blob[..])?, chunk).map(Some)
+    }
+
+    /// Saves chunks, replacing earlier saves of them. Only the region files
+    /// of the given chunks are touched.
+    pub fn write_chunks(&self, chunks: &[(ChunkPos, ChunkVoxels)]) -> io::Result<()> {
+        let mut regions: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for (chunk, voxels) in chunks {
+            let blob = zstd::encode_all(&encode_chunk(voxels, *chunk)[..], COMPRESSION_LEVEL)?;
+            regions
+                .entry(region_of(*chunk))
+                .or_default()
+                .push((slot(*chunk), blob));
+        }
+        let _lock = self.files.lock().unwrap();
+        for (region, blobs) in regions {
+            self.write_region(region, blobs)?;
+        }
+        Ok(())
+    }
+
+    // writes the whole region to a temporary file and renames it over the
+    // old one, so a crash while saving leaves the last complete save
+    fn write_region(&self, region: Vector3<i32>, blobs: Vec<(usize, Vec<u8>)>) -> io::Result<()> {
+        let path = self.region_path(region);
+        let mut chunks = vec![None; SLOTS];
+        match File::open(&path) {
+            Ok(mut old) => {
+                let index = read_current_index(&mut old)?;
+                for (slot, (offset, len)) in index.into_iter().enumerate() {
+                    if len > 0 {
+                        chunks[slot] = Some(read_blob(&mut old, offset, len)?);
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        for (slot, blob) in blobs {
+            chunks[slot] = Some(blob);
+        }
+        write_atomically(&path, &region_bytes(&chunks))
+    }
+}
+
+// a region file holding the compressed chunks in their slots
+fn region_bytes(chunks: &[Option<Vec<u8>>]) -> Vec<u8> {
+    let mut index = [(0, 0); SLOTS];
+    let mut contents = Vec::new();
+    for (slot, blob) in chunks.iter().enumerate() {
+        if let Some(blob) = blob {
+            index[slot] = (
+                (HEADER_LEN + contents.len() as u64) as u32,
+                blob.len() as u32,
+            );
+            contents.extend_from_slice(blob);
+        }
+    }
+    let mut bytes = header(&index);
+    bytes.extend(contents);
+    bytes
+}
+
+/// Rewrites the version 1 region files in `dir` with the child masks of
+/// version 2.
+pub(crate) fn migrate_v1(dir: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension() != Some(OsStr::new("rtvr")) {
+            continue;
+        }
+        let mut file = File::open(&path)?;
+        let (version, index) = read_index(&mut file)?;
+        // files already migrated by an interrupted migration
+        if version != 1 {
+            continue;
+        }
+        let mut chunks = vec![None; SLOTS];
+        for (slot, (offset, len)) in index.into_iter().enumerate() {
+            if len > 0 {
+                let blob = zstd::decode_all(&read_blob(&mut file, offset, len)?[..])?;
+                // positions are only needed relative to the chunk
+                let voxels = decode_chunk_version(&blob, [0; 3], version)?;
+                let encoded = encode_chunk(&voxels, [0; 3]);
+                chunks[slot] = Some(zstd::encode_all(&encoded[..], COMPRESSION_LEVEL)?);
+            }
+        }
+        write_atomically(&path, &region_bytes(&chunks))?;
+    }
+    Ok(())
+}
+
+/// Replaces the file at `path` with `bytes`, leaving it unchanged if writing
+/// fails part way.
+pub fn write_atomically(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let temp = path.with_extension("tmp");
+    let mut file = File::create(&temp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(temp, path)
+}
+
+/// Saves chunks to a store on a background thread so saving doesn't hold up
+/// rendering. The chunks that couldn't be saved are handed back to be saved
+/// again, see `take_failed`.
+pub struct ChunkWriter {
+    store: Arc<RegionStore>,
+    sender: Option<Sender<Vec<(ChunkPos, ChunkVoxels)>>>,
+    failed: Receiver<ChunkPos>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ChunkWriter {
+    pub fn new(store: Arc<RegionStore>) -> Self {
+        let (sender, receiver) = mpsc::channel::<Vec<(ChunkPos, ChunkVoxels)>>();
+        let (failed_sender, failed) = mpsc::channel();
+        let writing = store.clone();
+        let thread = thread::spawn(move || {
+            for chunks in receiver {
+                if let Err(e) = writing.write_chunks(&chunks) {
+                    error!(dir = %writing.dir().display(), "Could not save chunks: {}", e);
+                    for (chunk, _) in chunks {
+                        failed_sender.send(chunk).unwrap();
+                    }
+                }
+            }
+        });
+        ChunkWriter {
+            store,
+            sender: Some(sender),
+            failed,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn store(&self) -> &Arc<RegionStore> {
+        &self.store
+    }
+
+    /// Queues chunks to be saved after those queued before.
+    pub fn send(&self, chunks: Vec<(ChunkPos, ChunkVoxels)>) {
+        self.sender.as_ref().unwrap().send(chunks).unwrap()
+    }
+
+    /// Chunks that failed to save since the last call, which still need
+    /// saving.
+    pub fn take_failed(&self) -> Vec<ChunkPos> {
+        self.failed.try_iter().collect()
+    }
+
+    /// Waits for the queued chunks to be saved and returns those that
+    /// couldn't be.
+    pub fn finish(mut self) -> Vec<ChunkPos> {
+        self.sender = None;
+        self.thread.take().unwrap().join().unwrap();
+        self.take_failed()
+    }
+}
+
+fn read_current_index(file: &mut File) -> io::Result<Index> {
+    match read_index(file)? {
+        (FORMAT_VERSION, index) => Ok(index),
+        (version, _) => Err(invalid(&format!(
+            "region version {} needs migrating",
+            version
+        ))),
+    }
+}
+
+// returns the version of the region file along with its index
+fn read_index(file: &mut File) -> io::Result<(u32, Index)> {
+    let mut header = vec![0; HEADER_LEN as usize];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut header)?;
+    if &header[..4] != MAGIC {
+        return Err(invalid("not a region file"));
+    }
+    let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    if !(1..=FORMAT_VERSION).contains(&version) {
+        return Err(invalid(&format!("unsupported region version {}", version)));
+    }
+    let word = |i: usize| u32::from_le_bytes(header[8 + 4 * i..12 + 4 * i].try_into().unwrap());
+    let mut index = [(0, 0); SLOTS];
+    for (slot, entry) in index.iter_mut().enumerate() {
+        *entry = (word(2 * slot), word(2 * slot + 1));
+    }
+    Ok((version, index))
+}
+
+fn header(index: &Index) -> Vec<u8> {
+    let mut header = Vec::with_capacity(HEADER_LEN as usize);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    for (offset, len) in index {
+        header.extend_from_slice(&offset.to_le_bytes());
+        header.extend_from_slice(&len.to_le_bytes());
+    }
+    header
+}
+
+fn read_blob(file: &mut File, offset: u32, len: u32) -> io::Result<Vec<u8>> {
+    let mut blob = vec![0; len as usize];
+    file.seek(SeekFrom::Start(offset as u64))?;
+    file.read_exact(&mut blob)?;
+    Ok(blob)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("rtvox_region_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn sorted(mut voxels: ChunkVoxels) -> ChunkVoxels {
+        voxels.sort();
+        voxels
+    }
+
+    #[test]
+    fn chunk_round_trips() {
+        let chunk = [-1, 0, 2];
+        let origin = chunk.map(|c| c * CHUNK_SIZE);
+        let mut voxels = Vec::new();
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                for y in 0..(x + z) % 5 {
+                    voxels.push((vecmath::vec3_add(origin, [x, y, z]), 1 + y % 2));
+                }
+            }
+        }
+        let bytes = encode_chunk(&voxels, chunk);
+        assert_eq!(sorted(voxels), sorted(decode_chunk(&bytes, chunk).unwrap()));
+    }
+
+    #[test]
+    fn uniform_chunks_are_one_node() {
+        assert_eq!(vec![EMPTY], encode_chunk(&[], [0; 3]));
+        let mut full = Vec::new();
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    full.push(([x, y, z], 4));
+                }
+            }
+        }
+        assert_eq!(5, encode_chunk(&full, [0; 3]).len());
+    }
+
+    #[test]
+    fn truncated_chunk_is_an_error() {
+        let bytes = encode_chunk(&[([1, 2, 3], 7)], [0; 3]);
+        assert!(decode_chunk(&bytes[..bytes.len() - 1], [0; 3]).is_err());
+        assert!(decode_chunk(&[9], [0; 3]).is_err());
+    }
+
+    #[test]
+    fn store_saves_and_replaces_chunks() {
+        let dir = temp_dir("replace");
+        let store = RegionStore::open(dir.clone()).unwrap();
+        assert_eq!(None, store.read_chunk([0, 0, 0]).unwrap());
+
+        let a = ([0, 0, 0], vec![([1, 1, 1], 3)]);
+        let b = ([-9, 3, 0], vec![([-140, 50, 2], 5)]);
+        store.write_chunks(&[a.clone(), b.clone()]).unwrap();
+        assert_eq!(Some(a.1), store.read_chunk(a.0).unwrap());
+        assert_eq!(Some(b.1), store.read_chunk(b.0).unwrap());
+        assert_eq!(None, store.read_chunk([1, 0, 0]).unwrap());
+
+        // a saved empty chunk is not the same as one never saved
+        store.write_chunks(&[(a.0, Vec::new())]).unwrap();
+        assert_eq!(Some(Vec::new()), store.read_chunk(a.0).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rewritten_regions_stay_compact() {
+        let dir = temp_dir("compact");
+        let store = RegionStore::open(dir.clone()).unwrap();
+        store
+            .write_chunks(&[([1, 0, 0], vec![([20, 0, 0], 1)])])
+            .unwrap();
+        for i in 0..50 {
+            store
+                .write_chunks(&[([0; 3], vec![([i % 16, 0, 0], i)])])
+                .unwrap();
+        }
+        let path = store.region_path([0; 3]);
+        let len = fs::metadata(&path).unwrap().len();
+        assert!(len < HEADER_LEN + 200, "region is {} bytes", len);
+        assert_eq!(
+            Some(vec![([1, 0, 0], 49)]),
+            store.read_chunk([0; 3]).unwrap()
+        );
+        assert_eq!(
+            Some(vec![([20, 0, 0], 1)]),
+            store.read_chunk([1, 0, 0]).unwrap()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn writer_saves_in_order() {
+        let dir = temp_dir("writer");
+        let writer = ChunkWriter::new(Arc::new(RegionStore::open(dir.clone()).unwrap()));
+        for material in 1..=3 {
+            writer.send(vec![([0; 3], vec![([0, 0, 0], material)])]);
+        }
+        let store = writer.store().clone();
+        assert!(writer.finish().is_empty());
+        assert_eq!(
+            Some(vec![([0, 0, 0], 3)]),
+            store.read_chunk([0; 3]).unwrap()
+        );
+        assert!(!dir.join("r.0.0.0.tmp").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn writer_hands_back_failed_chunks() {
+        let dir = temp_dir("writer_failed");
+        let writer = ChunkWriter::new(Arc::new(RegionStore::open(dir.clone()).unwrap()));
+        // a file where the region files would go
+        fs::remove_dir_all(&dir).unwrap();
+        fs::write(&dir, "").unwrap();
+        writer.send(vec![
+            ([0; 3], vec![([0, 0, 0], 1)]),
+            ([9, 0, 0], Vec::new()),
+        ]);
+        let mut failed = writer.finish();
+        failed.sort();
+        assert_eq!(vec![[0; 3], [9, 0, 0]], failed);
+        fs::remove_file(dir).unwrap();
+    }
+
+    #[test]
+    fn version_1_saves_are_migrated() {
+        let dir = temp_dir("migrate");
+        fs::create_dir_all(&dir).unwrap();
+        // a solid 8 voxel cube in the first child, with all 8 children stored
+        let mut v1 = vec![SPLIT, SOLID, 5, 0, 0, 0];
+        v1.extend([EMPTY; 7]);
+        let mut chunks = vec![None; SLOTS];
+        chunks[slot([1, 0, 0])] = Some(zstd::encode_all(&v1[..], COMPRESSION_LEVEL).unwrap());
+        let mut region = region_bytes(&chunks);
+        region[4..8].copy_from_slice(&1u32.to_le_bytes());
+        fs::write(dir.join("r.0.0.0.rtvr"), region).unwrap();
+        fs::write(dir.join("seed"), "7").unwrap();
+
+        let store = RegionStore::open(dir.clone()).unwrap();
+        let metadata = store.metadata().unwrap().unwrap();
+        assert_eq!((FORMAT_VERSION, 7), (metadata.format, metadata.seed));
+        assert!(!dir.join("seed").exists());
+        let voxels = store.read_chunk([1, 0, 0]).unwrap().unwrap();
+        assert_eq!(512, voxels.len());
+        assert!(voxels.contains(&([23, 7, 7], 5)));
+        // opening again finds nothing to migrate
+        RegionStore::open(dir.clone()).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn split_nodes_store_only_non_empty_children() {
+        let bytes = encode_chunk(&[([0, 0, 0], 3)], [0; 3]);
+        // a path of single children down to the voxel
+        assert_eq!([SPLIT, 1], bytes[..2]);
+        assert_eq!(4 * 2 + 5, bytes.len());
+    }
+}
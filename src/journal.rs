@@ -0,0 +1,151 @@
+use std::collections::VecDeque;
+
+use crate::{materials::MaterialId, octree::VoxelChange};
+
+/// The voxel changes made by a single edit, in the order they were made.
+pub type Edit = Vec<VoxelChange<MaterialId>>;
+
+/// Undo and redo history of world edits. Memory is bounded by the number of
+/// voxel changes stored, the oldest edits are forgotten first.
+pub struct EditJournal {
+    undo: VecDeque<Edit>,
+    redo: Vec<Edit>,
+    max_changes: usize,
+    // changes stored across both stacks
+    stored: usize,
+}
+
+impl EditJournal {
+    pub fn new(max_changes: usize) -> Self {
+        EditJournal {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            max_changes,
+            stored: 0,
+        }
+    }
+
+    /// Adds an edit to the history, discarding everything that could be redone.
+    pub fn record(&mut self, edit: Edit) {
+        if edit.is_empty() {
+            return;
+        }
+        self.stored -= self.redo.drain(..).map(|e| e.len()).sum::<usize>();
+        self.stored += edit.len();
+        self.undo.push_back(edit);
+        while self.stored > self.max_changes {
+            match self.undo.pop_front() {
+                Some(oldest) => self.stored -= oldest.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Adds changes following from the latest edit to it, so they're undone
+    /// and redone with it. They're an edit of their own if there is none.
+    pub fn amend(&mut self, changes: Edit) {
+        if changes.is_empty() {
+            return;
+        }
+        match self.undo.pop_back() {
+            Some(mut latest) => {
+                self.stored -= latest.len();
+                latest.extend(changes);
+                self.record(latest);
+            }
+            None => self.record(changes),
+        }
+    }
+
+    /// Returns the changes that revert the latest edit.
+    pub fn undo(&mut self) -> Option<Edit> {
+        let edit = self.undo.pop_back()?;
+        let inverse = edit.iter().rev().map(|c| c.inverse()).collect();
+        self.redo.push(edit);
+        Some(inverse)
+    }
+
+    /// Returns the changes of the latest undone edit.
+    pub fn redo(&mut self) -> Option<Edit> {
+        let edit = self.redo.pop()?;
+        self.undo.push_back(edit.clone());
+        Some(edit)
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.stored = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undo_then_redo() {
+        let mut journal = EditJournal::new(100);
+        journal.record(vec![
+            VoxelChange::Added([0, 0, 0], 1),
+            VoxelChange::Changed([0, 0, 0], 1, 2),
+        ]);
+        assert_eq!(
+            Some(vec![
+                VoxelChange::Changed([0, 0, 0], 2, 1),
+                VoxelChange::Removed([0, 0, 0], 1),
+            ]),
+            journal.undo()
+        );
+        assert_eq!(None, journal.undo());
+        assert_eq!(
+            Some(VoxelChange::Added([0, 0, 0], 1)),
+            journal.redo().map(|e| e[0])
+        );
+        assert_eq!(None, journal.redo());
+    }
+
+    #[test]
+    fn record_clears_redo() {
+        let mut journal = EditJournal::new(100);
+        journal.record(vec![VoxelChange::Added([0, 0, 0], 1)]);
+        journal.undo();
+        journal.record(vec![VoxelChange::Added([1, 0, 0], 1)]);
+        assert_eq!(None, journal.redo());
+        assert_eq!(1, journal.stored);
+    }
+
+    #[test]
+    fn amend_undoes_with_the_edit() {
+        let mut journal = EditJournal::new(100);
+        journal.amend(vec![VoxelChange::Added([0, 0, 0], 1)]);
+        journal.amend(vec![
+            VoxelChange::Removed([0, 0, 0], 1),
+            VoxelChange::Added([0, -1, 0], 1),
+        ]);
+        assert_eq!(3, journal.stored);
+        assert_eq!(
+            Some(vec![
+                VoxelChange::Removed([0, -1, 0], 1),
+                VoxelChange::Added([0, 0, 0], 1),
+                VoxelChange::Removed([0, 0, 0], 1),
+            ]),
+            journal.undo()
+        );
+        assert_eq!(None, journal.undo());
+    }
+
+    #[test]
+    fn oldest_edits_are_dropped() {
+        let mut journal = EditJournal::new(3);
+        for x in 0..3 {
+            journal.record(vec![
+                VoxelChange::Added([x, 0, 0], 1),
+                VoxelChange::Added([x, 1, 0], 1),
+            ]);
+        }
+        assert_eq!(2, journal.stored);
+        assert!(journal.undo().is_some());
+        assert!(journal.undo().is_none());
+    }
+}
@@ -0,0 +1,369 @@
+use std::collections::BTreeMap;
+
+use vecmath::{vec3_dot, vec3_sub, Vector3};
+
+use crate::{
+    camera,
+    debug_draw::{self, Line},
+    graphics::cs::ty::CameraInfo,
+};
+
+/// Height of the text as a part of the width of the view, whatever the
+/// distance of the label.
+pub const TEXT_HEIGHT: f32 = 0.02;
+/// Labels further from the camera aren't drawn.
+pub const MAX_DISTANCE: f32 = 256.0;
+// nor those closer, which would fill the view
+const MIN_DISTANCE: f32 = 0.5;
+// the strokes of a glyph lie on a grid this many points across and down,
+// with a gap between glyphs
+const GLYPH_WIDTH: f32 = 4.0;
+const GLYPH_HEIGHT: f32 = 6.0;
+const GLYPH_ADVANCE: f32 = 6.0;
+// gap between the edges of the view and the text of a panel, as a part of
+// the width of the view, and the height of its rows relative to the text's
+const PANEL_MARGIN: f32 = 0.02;
+const ROW_HEIGHT: f32 = 1.5;
+
+pub type LabelId = u32;
+
+/// Text floating over a point of the world, facing the camera.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Label {
+    /// Where the middle of the bottom of the text is.
+    pub position: Vector3<f32>,
+    pub text: String,
+    pub color: [f32; 3],
+}
+
+/// Labels that stay until removed, like waypoints.
+#[derive(Default)]
+pub struct LabelList {
+    labels: BTreeMap<LabelId, Label>,
+    next_id: LabelId,
+}
+
+impl LabelList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, label: Label) -> LabelId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.labels.insert(id, label);
+        id
+    }
+
+    pub fn get_mut(&mut self, id: LabelId) -> Option<&mut Label> {
+        self.labels.get_mut(&id)
+    }
+
+    pub fn remove(&mut self, id: LabelId) -> Option<Label> {
+        self.labels.remove(&id)
+    }
+
+    pub fn clear(&mut self) {
+        self.labels.clear()
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (LabelId, &Label)> {
+        self.labels.iter().map(|(id, label)| (*id, label))
+    }
+
+    /// Draws every label over the next frame, see `draw`.
+    pub fn draw(&self, camera: &CameraInfo) {
+        for label in self.labels.values() {
+            draw(label, camera);
+        }
+    }
+}
+
+/// Draws `label` over the next frame as debug lines, so like them it has to
+/// be drawn again every frame to stay, see `debug_draw::line`.
+pub fn draw(label: &Label, camera: &CameraInfo) {
+    for line in lines(label, camera) {
+        debug_draw::line(line.a, line.b, line.color);
+    }
+}
+
+/// The strokes of `label` in the plane facing the camera, sized to keep the
+/// same height on screen. Nothing when the label is behind the camera or too
+/// close or too far to be drawn.
+pub fn lines(label: &Label, camera: &CameraInfo) -> Vec<Line> {
+    let [forward, right, down] = camera::basis(camera);
+    let offset = vec3_sub(label.position, camera.eye);
+    let distance = vec3_dot(offset, forward);
+    if !(MIN_DISTANCE..=MAX_DISTANCE).contains(&distance) {
+        return Vec::new();
+    }
+    let view_width = if camera.orthographic != 0 {
+        camera.fov
+    } else {
+        2.0 * distance * (camera.fov / 2.0).tan()
+    };
+    let unit = TEXT_HEIGHT * view_width / GLYPH_HEIGHT;
+    let chars = label.text.chars().count() as f32;
+    let left = -(chars * GLYPH_ADVANCE - (GLYPH_ADVANCE - GLYPH_WIDTH)) / 2.0;
+    // the text's grid runs right and down from its top left corner
+    let point = |column: f32, [x, y]: [u8; 2]| {
+        let across = (left + column * GLYPH_ADVANCE + x as f32) * unit;
+        let below = (y as f32 - GLYPH_HEIGHT) * unit;
+        [0, 1, 2].map(|i| label.position[i] + right[i] * across + down[i] * below)
+    };
+    let mut lines = Vec::new();
+    for (column, c) in label.text.chars().enumerate() {
+        for [a, b] in segments(c) {
+            lines.push(Line {
+                a: point(column as f32, a),
+                b: point(column as f32, b),
+                color: label.color,
+            });
+        }
+    }
+    lines
+}
+
+/// Draws `rows` of text from the top left corner of the view over the next
+/// frame, like the console, see `panel_lines`.
+pub fn draw_panel(rows: &[String], camera: &CameraInfo, aspect: f32, color: [f32; 3]) {
+    for line in panel_lines(rows, camera, aspect, color) {
+        debug_draw::line(line.a, line.b, line.color);
+    }
+}
+
+/// The strokes of `rows` of text pinned to the top left corner of the view
+/// rather than to the world, one after the other downwards. `aspect` is the
+/// width of the view over its height.
+pub fn panel_lines(
+    rows: &[String],
+    camera: &CameraInfo,
+    aspect: f32,
+    color: [f32; 3],
+) -> Vec<Line> {
+    let [forward, right, down] = camera::basis(camera);
+    // the text lies a unit in front of the eye, where the view is this wide
+    let view_width = if camera.orthographic != 0 {
+        camera.fov
+    } else {
+        2.0 * (camera.fov / 2.0).tan()
+    };
+    let text_height = TEXT_HEIGHT * view_width;
+    let unit = text_height / GLYPH_HEIGHT;
+    let margin = PANEL_MARGIN * view_width;
+    let left = margin - view_width / 2.0;
+    let top = margin - view_width / aspect / 2.0;
+    let mut lines = Vec::new();
+    for (row, text) in rows.iter().enumerate() {
+        let chars = text.chars().count() as f32;
+        let width = (chars * GLYPH_ADVANCE - (GLYPH_ADVANCE - GLYPH_WIDTH)).max(0.0) * unit;
+        // labels are placed by the middle of their bottom
+        let across = left + width / 2.0;
+        let below = top + text_height + row as f32 * ROW_HEIGHT * text_height;
+        let position =
+            [0, 1, 2].map(|i| camera.eye[i] + forward[i] + right[i] * across + down[i] * below);
+        let label = Label {
+            position,
+            text: text.clone(),
+            color,
+        };
+        lines.extend(self::lines(&label, camera));
+    }
+    lines
+}
+
+// The segments of a glyph between points of its grid, from its top left.
+fn segments(c: char) -> impl Iterator<Item = [[u8; 2]; 2]> {
+    strokes(c).split_whitespace().flat_map(|stroke| {
+        let points: Vec<[u8; 2]> = stroke
+            .as_bytes()
+            .chunks(2)
+            .map(|p| [p[0] - b'0', p[1] - b'0'])
+            .collect();
+        points
+            .windows(2)
+            .map(|pair| [pair[0], pair[1]])
+            .collect::<Vec<_>>()
+    })
+}
+
+// Strokes through the x and y digits of their points, separated by spaces.
+// Lower case letters are drawn in upper case, and characters without a glyph
+// as a box.
+fn strokes(c: char) -> &'static str {
+    match c.to_ascii_uppercase() {
+        ' ' => "",
+        'A' => "0602204246 0444",
+        'B' => "06003041423303 3344453606",
+        'C' => "4130100105163645",
+        'D' => "00304145360600",
+        'E' => "40000646 0333",
+        'F' => "400006 0333",
+        'G' => "41301001051636454323",
+        'H' => "0006 4046 0343",
+        'I' => "1030 2026 1636",
+        'J' => "4045361605",
+        'K' => "0006 4004 1346",
+        'L' => "000646",
+        'M' => "0600234046",
+        'N' => "06004640",
+        'O' => "103041453616050110",
+        'P' => "06003041423303",
+        'Q' => "103041453616050110 2446",
+        'R' => "06003041423303 2346",
+        'S' => "413010010213334445361605",
+        'T' => "0040 2026",
+        'U' => "000516364540",
+        'V' => "002640",
+        'W' => "0016233640",
+        'X' => "0046 4006",
+        'Y' => "002340 2326",
+        'Z' => "00400646",
+        '0' => "103041453616050110 4105",
+        '1' => "112026 1636",
+        '2' => "01103041420646",
+        '3' => "0110304142334445361605 1333",
+        '4' => "36300444",
+        '5' => "4000033344453606",
+        '6' => "30100105163645443303",
+        '7' => "004016",
+        '8' => "103041423313020110 1304051636454433",
+        '9' => "43130201103041453616",
+        '-' => "1333",
+        '+' => "2125 0343",
+        '=' => "0242 0444",
+        '.' => "2526",
+        ',' => "2516",
+        ':' => "2122 2425",
+        '/' => "4006",
+        '_' => "0646",
+        '#' => "1016 3036 0242 0444",
+        '(' => "30121436",
+        ')' => "10323416",
+        '!' => "2024 2526",
+        '?' => "011030414224 2526",
+        _ => "0040460600",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    use crate::camera::Projection;
+
+    fn label(position: Vector3<f32>, text: &str) -> Label {
+        Label {
+            position,
+            text: text.to_string(),
+            color: [1.0; 3],
+        }
+    }
+
+    fn points(lines: &[Line]) -> Vec<Vector3<f32>> {
+        lines.iter().flat_map(|line| [line.a, line.b]).collect()
+    }
+
+    #[test]
+    fn glyphs_stay_on_their_grid() {
+        for c in (' '..='~').chain(['é']) {
+            let segments: Vec<_> = segments(c).collect();
+            assert_eq!(c == ' ', segments.is_empty(), "{:?}", c);
+            for [x, y] in segments.into_iter().flatten() {
+                assert!(
+                    x as f32 <= GLYPH_WIDTH && y as f32 <= GLYPH_HEIGHT,
+                    "{:?}",
+                    c
+                );
+            }
+        }
+        assert_eq!(strokes('a'), strokes('A'));
+    }
+
+    #[test]
+    fn text_faces_the_camera_above_its_position() {
+        let camera =
+            Projection::Perspective { fov: PI / 2.0 }.camera_info([0.0, 0.0, 10.0], [0.0; 3]);
+        let lines = lines(&label([1.0, 2.0, 0.0], "HI 42"), &camera);
+        assert!(!lines.is_empty());
+        let points = points(&lines);
+        for p in &points {
+            // in the plane through the position facing the camera, above it
+            assert!(p[2].abs() < 1e-5);
+            assert!(p[1] >= 2.0 - 1e-5);
+        }
+        let min = points.iter().map(|p| p[0]).fold(f32::MAX, f32::min);
+        let max = points.iter().map(|p| p[0]).fold(f32::MIN, f32::max);
+        assert!((min + max - 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn text_keeps_its_size_on_screen() {
+        let camera =
+            Projection::Perspective { fov: PI / 2.0 }.camera_info([0.0; 3], [0.0, 0.0, -1.0]);
+        let height = |distance: f32| {
+            let points = points(&lines(&label([0.0, 0.0, -distance], "8"), &camera));
+            points.iter().map(|p| p[1]).fold(f32::MIN, f32::max)
+        };
+        assert!((height(20.0) - 2.0 * height(10.0)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn labels_out_of_range_are_not_drawn() {
+        let camera =
+            Projection::Perspective { fov: PI / 2.0 }.camera_info([0.0; 3], [0.0, 0.0, -1.0]);
+        assert!(lines(&label([0.0, 0.0, 5.0], "behind"), &camera).is_empty());
+        let far = label([0.0, 0.0, -2.0 * MAX_DISTANCE], "far");
+        assert!(lines(&far, &camera).is_empty());
+    }
+
+    #[test]
+    fn panels_stay_in_the_top_left_corner() {
+        let camera =
+            Projection::Perspective { fov: PI / 2.0 }.camera_info([3.0, 4.0, 5.0], [3.0, 4.0, 4.0]);
+        let rows = ["first".to_string(), "second row".to_string()];
+        let lines = panel_lines(&rows, &camera, 2.0, [1.0; 3]);
+        assert!(!lines.is_empty());
+        // a unit in front of the eye the view spans -1 to 1 across and -0.5
+        // to 0.5 down, y pointing up
+        for [x, y, z] in points(&lines) {
+            assert!((-1.0..-0.5).contains(&(x - 3.0)), "{}", x);
+            assert!((0.0..0.5).contains(&(y - 4.0)), "{}", y);
+            assert!((z - 4.0).abs() < 1e-4);
+        }
+        let first = panel_lines(&rows[..1], &camera, 2.0, [1.0; 3]);
+        let lowest = |lines: &[Line]| {
+            points(lines)
+                .into_iter()
+                .map(|p| p[1])
+                .fold(f32::MAX, f32::min)
+        };
+        assert!(lowest(&lines) < lowest(&first));
+    }
+
+    #[test]
+    fn list_keeps_labels_until_removed() {
+        let mut labels = LabelList::new();
+        let a = labels.add(label([0.0; 3], "a"));
+        let b = labels.add(label([1.0; 3], "b"));
+        assert_ne!(a, b);
+        labels.get_mut(b).unwrap().text = "c".to_string();
+        assert_eq!("a", labels.remove(a).unwrap().text);
+        assert_eq!(
+            vec![(b, "c")],
+            labels
+                .iter()
+                .map(|(id, l)| (id, l.text.as_str()))
+                .collect::<Vec<_>>()
+        );
+    }
+}
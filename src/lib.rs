@@ -0,0 +1,108 @@
+pub mod aabc;
+pub mod accumulation;
+pub mod animation;
+pub mod app_state;
+pub mod args;
+pub mod assets;
+pub mod benchmark;
+pub mod bloom;
+pub mod blue_noise;
+pub mod breaking;
+pub mod camera;
+pub mod console;
+pub mod debug_draw;
+pub mod decals;
+pub mod demo_world;
+pub mod denoise;
+pub mod entity;
+pub mod environment;
+pub mod frame_budget;
+pub mod frame_graph;
+pub mod frames_in_flight;
+pub mod fxaa;
+pub mod gbuffer;
+pub mod golden;
+pub mod gpu_memory;
+pub mod gpu_profiler;
+pub mod graphics;
+pub mod hotbar;
+pub mod hud;
+pub mod io;
+pub mod journal;
+pub mod labels;
+pub mod library;
+pub mod light;
+pub mod line_overlay;
+pub mod logging;
+pub mod materials;
+pub mod mesh;
+pub mod mipmaps;
+pub mod morton;
+pub mod net;
+pub mod octree;
+pub mod octree_layout;
+pub mod physics;
+pub mod pipelines;
+pub mod placement;
+pub mod plugin;
+pub mod prefab;
+pub mod raster;
+pub mod raycast;
+pub mod recorder;
+pub mod render_mode;
+pub mod render_scale;
+pub mod renderer;
+pub mod scheduler;
+pub mod script;
+pub mod selection;
+#[cfg(feature = "hot-reload")]
+pub mod shader_reload;
+pub mod shadows;
+pub mod softrender;
+pub mod stats;
+pub mod status;
+pub mod stress;
+pub mod taa;
+pub mod tiles;
+pub mod time_of_day;
+pub mod transfer;
+pub mod upscale;
+pub mod validation;
+pub mod view_distance;
+pub mod voxel;
+pub mod workgroups;
+pub mod world;
+pub mod worldgen;
+
+use graphics::cs::ty::CameraInfo;
+use materials::{MaterialId, MaterialRegistry};
+use octree::Octree;
+
+/// The size of an image too small for `render_scene`.
+#[derive(Debug, PartialEq)]
+pub struct ImageTooSmall(pub [u32; 2]);
+
+/// Renders `tree` seen by `camera` into an RGBA8 image of `size`, at least 2
+/// by 2, on the CPU without a window, see `softrender::render`.
+///
+/// ```
+/// use rtvox::{camera::Projection, octree::Octree, ImageTooSmall};
+///
+/// let mut tree = Octree::new();
+/// tree.insert_leaf(1, [0, 0, 0]);
+/// let camera = Projection::Perspective { fov: 1.5 }.camera_info([0.5, 0.5, 4.0], [0.5; 3]);
+/// let pixels = rtvox::render_scene(&tree, &camera, [64, 48]).unwrap();
+/// assert_eq!(64 * 48 * 4, pixels.len());
+/// assert_eq!(Err(ImageTooSmall([1, 48])), rtvox::render_scene(&tree, &camera, [1, 48]));
+/// ```
+pub fn render_scene(
+    tree: &Octree<MaterialId>,
+    camera: &CameraInfo,
+    size: [u32; 2],
+) -> Result<Vec<u8>, ImageTooSmall> {
+    if size[0] < 2 || size[1] < 2 {
+        return Err(ImageTooSmall(size));
+    }
+    let materials = MaterialRegistry::default();
+    Ok(softrender::render(tree, &materials, camera, size))
+}
@@ -0,0 +1,183 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{io::import, prefab::VoxelPrefab};
+
+/// Where the prefab library is read from when nothing else is given.
+pub const DEFAULT_DIR: &str = "prefabs";
+
+/// A prefab of the library, named after its file.
+pub struct Blueprint {
+    pub name: String,
+    pub prefab: VoxelPrefab,
+}
+
+/// The .vox files of a directory, to stamp into the world. One of them can
+/// be selected, none at first.
+pub struct PrefabLibrary {
+    dir: PathBuf,
+    blueprints: Vec<Blueprint>,
+    selected: Option<usize>,
+}
+
+impl PrefabLibrary {
+    /// An empty library reading from `dir` once scanned.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        PrefabLibrary {
+            dir: dir.into(),
+            blueprints: Vec::new(),
+            selected: None,
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Reads every .vox file of the directory again, ordered by name. Only
+    /// the first model of a file is kept. Files that can't be read are
+    /// skipped and returned with why, a missing directory is an empty
+    /// library. The selection is kept if its name is still there.
+    pub fn scan(&mut self) -> io::Result<Vec<(PathBuf, io::Error)>> {
+        let selected = self.selected().map(|b| b.name.clone());
+        self.blueprints.clear();
+        self.selected = None;
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut files = entries
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        files.retain(|file| file.extension().is_some_and(|ext| ext == "vox"));
+        files.sort();
+        let mut failed = Vec::new();
+        for file in files {
+            let name = file.file_stem().unwrap().to_string_lossy().into_owned();
+            match fs::read(&file).and_then(|bytes| import::read_vox(&bytes)) {
+                Ok(mut models) => self.blueprints.push(Blueprint {
+                    name,
+                    prefab: models.swap_remove(0),
+                }),
+                Err(e) => failed.push((file, e)),
+            }
+        }
+        if let Some(name) = selected {
+            self.select(&name);
+        }
+        Ok(failed)
+    }
+
+    pub fn blueprints(&self) -> &[Blueprint] {
+        &self.blueprints
+    }
+
+    pub fn selected(&self) -> Option<&Blueprint> {
+        self.selected.map(|i| &self.blueprints[i])
+    }
+
+    /// Selects the blueprint called `name`, returning whether there is one.
+    pub fn select(&mut self, name: &str) -> bool {
+        match self.blueprints.iter().position(|b| b.name == name) {
+            Some(i) => {
+                self.selected = Some(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn deselect(&mut self) {
+        self.selected = None;
+    }
+
+    /// Selects the next blueprint, after the last one none, then the first.
+    pub fn select_next(&mut self) {
+        self.selected = match self.selected {
+            None if self.blueprints.is_empty() => None,
+            None => Some(0),
+            Some(i) if i + 1 < self.blueprints.len() => Some(i + 1),
+            Some(_) => None,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{io::export::write_vox, materials::MaterialRegistry};
+
+    fn write(dir: &Path, name: &str, prefab: &VoxelPrefab) {
+        let mut bytes = Vec::new();
+        write_vox(prefab, &MaterialRegistry::default(), &mut bytes).unwrap();
+        fs::write(dir.join(name), bytes).unwrap();
+    }
+
+    fn library_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("rtvox_library_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn scan_reads_vox_files_by_name() {
+        let dir = library_dir("scan");
+        let tree = VoxelPrefab::new([0; 3], [1, 2, 1], [([0, 0, 0], 1), ([0, 1, 0], 14)]);
+        let rock = VoxelPrefab::new([0; 3], [1, 1, 1], [([0, 0, 0], 13)]);
+        write(&dir, "tree.vox", &tree);
+        write(&dir, "rock.vox", &rock);
+        fs::write(dir.join("notes.txt"), "not a prefab").unwrap();
+        fs::write(dir.join("broken.vox"), "not a vox file").unwrap();
+        let mut library = PrefabLibrary::new(&dir);
+        let failed = library.scan().unwrap();
+        assert_eq!(
+            vec![dir.join("broken.vox")],
+            failed.into_iter().map(|f| f.0).collect::<Vec<_>>()
+        );
+        let names: Vec<_> = library
+            .blueprints()
+            .iter()
+            .map(|b| b.name.as_str())
+            .collect();
+        assert_eq!(vec!["rock", "tree"], names);
+        assert_eq!(tree, library.blueprints()[1].prefab);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_directory_is_empty() {
+        let mut library = PrefabLibrary::new(std::env::temp_dir().join("rtvox_library_missing"));
+        assert!(library.scan().unwrap().is_empty());
+        assert!(library.blueprints().is_empty());
+        library.select_next();
+        assert!(library.selected().is_none());
+    }
+
+    #[test]
+    fn selection_cycles_through_none() {
+        let dir = library_dir("select");
+        let prefab = VoxelPrefab::new([0; 3], [1, 1, 1], [([0, 0, 0], 1)]);
+        write(&dir, "a.vox", &prefab);
+        write(&dir, "b.vox", &prefab);
+        let mut library = PrefabLibrary::new(&dir);
+        library.scan().unwrap();
+        assert!(library.selected().is_none());
+        library.select_next();
+        library.select_next();
+        assert_eq!("b", library.selected().unwrap().name);
+        // kept through a scan
+        library.scan().unwrap();
+        assert_eq!("b", library.selected().unwrap().name);
+        library.select_next();
+        assert!(library.selected().is_none());
+        assert!(library.select("a"));
+        assert!(!library.select("c"));
+        assert_eq!("a", library.selected().unwrap().name);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
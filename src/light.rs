@@ -0,0 +1,476 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use vecmath::Vector3;
+
+use crate::{
+    materials::{MaterialId, MaterialRegistry},
+    voxel::MAX_LIGHT,
+    world::World,
+    worldgen::{chunk_containing, ChunkPos, CHUNK_SIZE},
+};
+
+const CHUNK_VOLUME: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+// what the light volume holds outside the loaded chunks, which are open sky
+const UNLOADED: u8 = MAX_LIGHT << 4;
+
+const NEIGHBORS: [Vector3<i32>; 6] = [
+    [1, 0, 0],
+    [-1, 0, 0],
+    [0, 1, 0],
+    [0, -1, 0],
+    [0, 0, 1],
+    [0, 0, -1],
+];
+const DOWN: Vector3<i32> = [0, -1, 0];
+
+/// Voxels along each axis of the volume around the camera the shader samples.
+pub const VOLUME_SIZE: u32 = 128;
+
+/// The two kinds of light, which spread separately.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    /// Light from above the loaded chunks, which goes straight down without
+    /// getting dimmer.
+    Sky,
+    /// Light given off by emissive voxels.
+    Block,
+}
+
+impl Channel {
+    const ALL: [Channel; 2] = [Channel::Sky, Channel::Block];
+
+    // bits of the packed levels it takes up
+    fn shift(self) -> u8 {
+        match self {
+            Channel::Sky => 4,
+            Channel::Block => 0,
+        }
+    }
+}
+
+/// Light levels around the camera, one byte per voxel with the sky light in
+/// the high four bits and the block light in the low ones. X varies fastest,
+/// then y, then z.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LightVolume {
+    /// Voxel at the first corner.
+    pub origin: Vector3<i32>,
+    /// Voxels along each axis.
+    pub size: u32,
+    pub levels: Vec<u8>,
+}
+
+struct ChunkLight {
+    levels: [u8; CHUNK_VOLUME],
+    // the world's voxels, 0 for air, kept so spreading doesn't look each one
+    // up in the octree
+    materials: [MaterialId; CHUNK_VOLUME],
+}
+
+/// Light levels from 0 to `MAX_LIGHT` of every voxel of the loaded chunks,
+/// flooded through the air and translucent voxels like in Minecraft: light
+/// loses a level with every voxel it passes, except sky light going down.
+/// Opaque voxels are dark unless they glow. Chunks and voxel changes are
+/// lit incrementally, only what they affect being recomputed.
+pub struct LightMap {
+    chunks: HashMap<ChunkPos, Box<ChunkLight>>,
+    // by material id
+    emission: Vec<u8>,
+    translucent: Vec<bool>,
+    changed: bool,
+}
+
+impl LightMap {
+    pub fn new(materials: &MaterialRegistry) -> Self {
+        let (mut emission, mut translucent) = (vec![0], vec![true]);
+        for id in 1..materials.len() as MaterialId {
+            let material = materials.get(id).unwrap();
+            emission.push(if material.emission > 0.0 {
+                MAX_LIGHT
+            } else {
+                0
+            });
+            translucent.push(material.is_translucent());
+        }
+        LightMap {
+            chunks: HashMap::new(),
+            emission,
+            translucent,
+            changed: false,
+        }
+    }
+
+    /// The light level at a voxel, None outside the loaded chunks.
+    pub fn get(&self, pos: Vector3<i32>, channel: Channel) -> Option<u8> {
+        let (chunk, i) = locate(pos);
+        let packed = self.chunks.get(&chunk)?.levels[i];
+        Some(packed >> channel.shift() & MAX_LIGHT)
+    }
+
+    fn set(&mut self, pos: Vector3<i32>, channel: Channel, level: u8) {
+        let (chunk, i) = locate(pos);
+        let packed = &mut self.chunks.get_mut(&chunk).unwrap().levels[i];
+        let mask = MAX_LIGHT << channel.shift();
+        *packed = *packed & !mask | level << channel.shift();
+        self.changed = true;
+    }
+
+    /// Lights a chunk that was added to the world, and what it shades or
+    /// lets light into around it.
+    pub fn add_chunk(&mut self, world: &World, chunk: ChunkPos) {
+        if self.chunks.contains_key(&chunk) {
+            return;
+        }
+        let corner = chunk.map(|c| c * CHUNK_SIZE);
+        let cells: Vec<_> = (0..CHUNK_VOLUME)
+            .map(|i| add(corner, local_pos(i)))
+            .collect();
+        let mut light = Box::new(ChunkLight {
+            levels: [0; CHUNK_VOLUME],
+            materials: [0; CHUNK_VOLUME],
+        });
+        for (i, &pos) in cells.iter().enumerate() {
+            light.materials[i] = world.get(pos).unwrap_or(0);
+        }
+        self.chunks.insert(chunk, light);
+        let cells = cells.into_iter().collect();
+        for channel in Channel::ALL {
+            self.relight(channel, &cells);
+        }
+        // the top of the chunk below was open sky until now, which only
+        // changes where the chunk doesn't pass full sky light on
+        let below: HashSet<_> = (0..CHUNK_SIZE.pow(2))
+            .map(|i| {
+                [
+                    corner[0] + i % CHUNK_SIZE,
+                    corner[1] - 1,
+                    corner[2] + i / CHUNK_SIZE,
+                ]
+            })
+            .filter(|&pos| {
+                self.get(pos, Channel::Sky) == Some(MAX_LIGHT)
+                    && self.get(add(pos, [0, 1, 0]), Channel::Sky) != Some(MAX_LIGHT)
+            })
+            .collect();
+        self.relight(Channel::Sky, &below);
+    }
+
+    /// Relights the voxels that changed in the world, which must be in loaded
+    /// chunks to be lit.
+    pub fn update(&mut self, world: &World, cells: impl IntoIterator<Item = Vector3<i32>>) {
+        let cells: HashSet<_> = cells
+            .into_iter()
+            .filter(|&pos| self.get(pos, Channel::Sky).is_some())
+            .collect();
+        for &pos in &cells {
+            let (chunk, i) = locate(pos);
+            self.chunks.get_mut(&chunk).unwrap().materials[i] = world.get(pos).unwrap_or(0);
+        }
+        for channel in Channel::ALL {
+            self.relight(channel, &cells);
+        }
+    }
+
+    /// Returns whether any level changed since the last call.
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::replace(&mut self.changed, false)
+    }
+
+    /// Copies out the levels of a cube of voxels.
+    pub fn volume(&self, origin: Vector3<i32>, size: u32) -> LightVolume {
+        let n = size as usize;
+        let mut levels = vec![UNLOADED; n * n * n];
+        let first = chunk_containing(origin);
+        let last = chunk_containing(origin.map(|c| c + size as i32 - 1));
+        for cz in first[2]..=last[2] {
+            for cy in first[1]..=last[1] {
+                for cx in first[0]..=last[0] {
+                    let chunk = match self.chunks.get(&[cx, cy, cz]) {
+                        Some(chunk) => chunk,
+                        None => continue,
+                    };
+                    let corner = [cx * CHUNK_SIZE, cy * CHUNK_SIZE, cz * CHUNK_SIZE];
+                    for (i, &packed) in chunk.levels.iter().enumerate() {
+                        let local = local_pos(i);
+                        let p = [0, 1, 2].map(|a| corner[a] + local[a] - origin[a]);
+                        if p.iter().all(|&c| (0..size as i32).contains(&c)) {
+                            let [x, y, z] = p.map(|c| c as usize);
+                            levels[x + n * (y + n * z)] = packed;
+                        }
+                    }
+                }
+            }
+        }
+        LightVolume {
+            origin,
+            size,
+            levels,
+        }
+    }
+
+    // whether light passes through the voxel at pos, not for materials
+    // outside the registry
+    fn passes(&self, pos: Vector3<i32>) -> bool {
+        let id = self.material(pos) as usize;
+        self.translucent.get(id).copied().unwrap_or(false)
+    }
+
+    fn material(&self, pos: Vector3<i32>) -> MaterialId {
+        let (chunk, i) = locate(pos);
+        self.chunks
+            .get(&chunk)
+            .map_or(0, |chunk| chunk.materials[i])
+    }
+
+    // the level a voxel has by itself, not counting light from its neighbors
+    fn source(&self, channel: Channel, pos: Vector3<i32>) -> u8 {
+        match channel {
+            Channel::Sky => {
+                let above = [pos[0], pos[1] + 1, pos[2]];
+                if self.get(above, channel).is_none() && self.passes(pos) {
+                    MAX_LIGHT
+                } else {
+                    0
+                }
+            }
+            Channel::Block => {
+                let id = self.material(pos) as usize;
+                self.emission.get(id).copied().unwrap_or(0)
+            }
+        }
+    }
+
+    // Takes away the light of the cells and everything lit through them, then
+    // floods back in from what's left and from the sources among them.
+    fn relight(&mut self, channel: Channel, cells: &HashSet<Vector3<i32>>) {
+        let mut darkened = Vec::new();
+        let mut queue = VecDeque::new();
+        for &pos in cells {
+            let level = self.get(pos, channel).unwrap();
+            self.set(pos, channel, 0);
+            queue.push_back((pos, level));
+            darkened.push(pos);
+        }
+        let mut sources = Vec::new();
+        while let Some((pos, level)) = queue.pop_front() {
+            for dir in NEIGHBORS {
+                let next = add(pos, dir);
+                let next_level = match self.get(next, channel) {
+                    Some(l) if l > 0 => l,
+                    _ => continue,
+                };
+                let lit_through = next_level < level
+                    || channel == Channel::Sky && dir == DOWN && level == MAX_LIGHT;
+                if lit_through {
+                    self.set(next, channel, 0);
+                    queue.push_back((next, next_level));
+                    darkened.push(next);
+                } else {
+                    sources.push(next);
+                }
+            }
+        }
+        for pos in darkened {
+            let level = self.source(channel, pos);
+            if level > 0 {
+                self.set(pos, channel, level);
+                sources.push(pos);
+            }
+        }
+        // the neighbors shine into cells that let light through now, those
+        // among the cells are already sources if they're lit
+        for &pos in cells {
+            let neighbors = NEIGHBORS.iter().map(|&dir| add(pos, dir));
+            sources.extend(neighbors.filter(|next| !cells.contains(next)));
+        }
+        self.spread(channel, sources);
+    }
+
+    fn spread(&mut self, channel: Channel, sources: Vec<Vector3<i32>>) {
+        let mut queue = VecDeque::from(sources);
+        while let Some(pos) = queue.pop_front() {
+            let level = match self.get(pos, channel) {
+                Some(l) if l > 0 => l,
+                _ => continue,
+            };
+            for dir in NEIGHBORS {
+                let next = add(pos, dir);
+                let next_level = match self.get(next, channel) {
+                    Some(l) => l,
+                    None => continue,
+                };
+                let level = if channel == Channel::Sky && dir == DOWN && level == MAX_LIGHT {
+                    MAX_LIGHT
+                } else {
+                    level - 1
+                };
+                if level > next_level && self.passes(next) {
+                    self.set(next, channel, level);
+                    queue.push_back(next);
+                }
+            }
+        }
+    }
+}
+
+/// The first corner of the volume around `center`, which moves a chunk at a
+/// time so the volume only has to be copied out again after crossing chunks.
+pub fn volume_origin(center: Vector3<f32>) -> Vector3<i32> {
+    let chunk = chunk_containing(center.map(|c| c.floor() as i32));
+    let half = VOLUME_SIZE as i32 / CHUNK_SIZE / 2;
+    chunk.map(|c| (c - half) * CHUNK_SIZE)
+}
+
+fn add(a: Vector3<i32>, b: Vector3<i32>) -> Vector3<i32> {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+// the chunk of a voxel and its index in the chunk's levels
+fn locate(pos: Vector3<i32>) -> (ChunkPos, usize) {
+    let [x, y, z] = pos.map(|c| c.rem_euclid(CHUNK_SIZE) as usize);
+    let size = CHUNK_SIZE as usize;
+    (chunk_containing(pos), x + size * (y + size * z))
+}
+
+fn local_pos(i: usize) -> Vector3<i32> {
+    let size = CHUNK_SIZE as usize;
+    [i % size, i / size % size, i / (size * size)].map(|c| c as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lit(world: &World, chunks: &[ChunkPos]) -> LightMap {
+        let mut light = LightMap::new(&MaterialRegistry::default());
+        for &chunk in chunks {
+            light.add_chunk(world, chunk);
+        }
+        light
+    }
+
+    fn floor(world: &mut World, y: i32) {
+        let stone = MaterialRegistry::default().id("stone").unwrap();
+        world.fill_box([0, y, 0], [CHUNK_SIZE - 1, y, CHUNK_SIZE - 1], Some(stone));
+    }
+
+    #[test]
+    fn sky_light_falls_to_the_ground() {
+        let mut world = World::new();
+        floor(&mut world, 3);
+        let light = lit(&world, &[[0, 0, 0]]);
+        assert_eq!(Some(MAX_LIGHT), light.get([5, 4, 5], Channel::Sky));
+        assert_eq!(Some(MAX_LIGHT), light.get([5, 15, 5], Channel::Sky));
+        assert_eq!(Some(0), light.get([5, 3, 5], Channel::Sky));
+        assert_eq!(Some(0), light.get([5, 2, 5], Channel::Sky));
+        assert_eq!(None, light.get([5, 2, 16], Channel::Sky));
+    }
+
+    #[test]
+    fn light_fades_under_an_overhang() {
+        let mut world = World::new();
+        let stone = MaterialRegistry::default().id("stone").unwrap();
+        // a roof over x < 8, open beyond it
+        world.fill_box([0, 10, 0], [7, 10, CHUNK_SIZE - 1], Some(stone));
+        let light = lit(&world, &[[0, 0, 0]]);
+        assert_eq!(Some(MAX_LIGHT), light.get([8, 5, 5], Channel::Sky));
+        assert_eq!(Some(MAX_LIGHT - 1), light.get([7, 5, 5], Channel::Sky));
+        assert_eq!(Some(MAX_LIGHT - 3), light.get([5, 5, 5], Channel::Sky));
+    }
+
+    #[test]
+    fn chunks_above_shade_those_below() {
+        let mut world = World::new();
+        floor(&mut world, CHUNK_SIZE);
+        let mut light = lit(&world, &[[0, 0, 0]]);
+        assert_eq!(Some(MAX_LIGHT), light.get([5, 0, 5], Channel::Sky));
+        light.add_chunk(&world, [0, 1, 0]);
+        assert_eq!(Some(0), light.get([5, 0, 5], Channel::Sky));
+        assert_eq!(
+            Some(MAX_LIGHT),
+            light.get([5, CHUNK_SIZE + 1, 5], Channel::Sky)
+        );
+        // and chunks next to each other light each other
+        light.add_chunk(&world, [1, 0, 0]);
+        assert_eq!(
+            Some(MAX_LIGHT - 1),
+            light.get([CHUNK_SIZE - 1, 0, 5], Channel::Sky)
+        );
+    }
+
+    #[test]
+    fn lamps_light_their_surroundings() {
+        let mut world = World::new();
+        let materials = MaterialRegistry::default();
+        floor(&mut world, 12);
+        let mut light = lit(&world, &[[0, 0, 0]]);
+        assert_eq!(Some(0), light.get([4, 4, 4], Channel::Block));
+
+        world.set([4, 4, 4], materials.id("lamp").unwrap());
+        light.take_changed();
+        light.update(&world, [[4, 4, 4]]);
+        assert!(light.take_changed());
+        assert_eq!(Some(MAX_LIGHT), light.get([4, 4, 4], Channel::Block));
+        assert_eq!(Some(MAX_LIGHT - 1), light.get([4, 5, 4], Channel::Block));
+        assert_eq!(Some(MAX_LIGHT - 3), light.get([6, 5, 4], Channel::Block));
+
+        world.remove([4, 4, 4]);
+        light.update(&world, [[4, 4, 4]]);
+        assert_eq!(Some(0), light.get([6, 5, 4], Channel::Block));
+    }
+
+    #[test]
+    fn unknown_materials_block_light() {
+        let mut world = World::new();
+        floor(&mut world, 3);
+        let mut light = lit(&world, &[[0, 0, 0]]);
+        world.remove([5, 3, 5]);
+        world.remove([8, 3, 8]);
+        light.update(&world, [[5, 3, 5], [8, 3, 8]]);
+        let unknown = MaterialRegistry::default().len() as MaterialId;
+        world.set([5, 3, 5], unknown);
+        world.set([8, 3, 8], -5);
+        light.update(&world, [[5, 3, 5], [8, 3, 8]]);
+        assert_eq!(Some(0), light.get([5, 0, 5], Channel::Sky));
+        assert_eq!(Some(0), light.get([8, 3, 8], Channel::Block));
+    }
+
+    #[test]
+    fn edits_update_the_sky_light() {
+        let mut world = World::new();
+        floor(&mut world, 3);
+        let mut light = lit(&world, &[[0, 0, 0]]);
+        // a hole lets the sky in below the floor
+        world.remove([5, 3, 5]);
+        light.update(&world, [[5, 3, 5]]);
+        assert_eq!(Some(MAX_LIGHT), light.get([5, 0, 5], Channel::Sky));
+        assert_eq!(Some(MAX_LIGHT - 1), light.get([6, 0, 5], Channel::Sky));
+        // and closing it puts the room back into the dark
+        world.set([5, 3, 5], MaterialRegistry::default().id("stone").unwrap());
+        light.update(&world, [[5, 3, 5]]);
+        assert_eq!(Some(0), light.get([5, 0, 5], Channel::Sky));
+        assert_eq!(Some(0), light.get([6, 0, 5], Channel::Sky));
+    }
+
+    #[test]
+    fn volume_copies_levels() {
+        let mut world = World::new();
+        floor(&mut world, 3);
+        let light = lit(&world, &[[0, 0, 0]]);
+        let volume = light.volume([4, 3, 4], 2);
+        let sky = MAX_LIGHT << 4;
+        assert_eq!(vec![0, 0, sky, sky, 0, 0, sky, sky], volume.levels);
+        let outside = light.volume([-1, 0, 0], 1);
+        assert_eq!(vec![UNLOADED], outside.levels);
+    }
+
+    #[test]
+    fn volume_follows_the_camera_chunk() {
+        let half = VOLUME_SIZE as i32 / 2;
+        assert_eq!([-half; 3], volume_origin([0.5, 3.0, 15.9]));
+        assert_eq!(
+            [-half, -half - CHUNK_SIZE, CHUNK_SIZE - half],
+            volume_origin([0.0, -0.1, 16.0])
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use std::sync::Arc;
+
+use bytemuck::{Pod, Zeroable};
+use vulkano::{
+    buffer::CpuBufferPool,
+    command_buffer::{
+        AutoCommandBufferBuilder, PrimaryAutoCommandBuffer, RenderPassBeginInfo, SubpassContents,
+    },
+    device::Queue,
+    image::{view::ImageView, ImageAccess, SwapchainImage},
+    impl_vertex,
+    pipeline::{
+        graphics::{
+            input_assembly::{InputAssemblyState, PrimitiveTopology},
+            vertex_input::BuffersDefinition,
+            viewport::{Viewport, ViewportState},
+        },
+        GraphicsPipeline, Pipeline,
+    },
+    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
+};
+use winit::window::Window;
+
+use crate::{camera, debug_draw::Line, graphics::cs::ty::CameraInfo};
+
+const NEAR: f32 = 0.05;
+const FAR: f32 = 1000.0;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+struct LineVertex {
+    position: [f32; 3],
+    color: [f32; 3],
+}
+
+impl_vertex!(LineVertex, position, color);
+
+/// Draws the lines of `debug_draw` straight onto the swapchain image after
+/// the ray traced image was blitted there, on top of everything.
+pub struct LineOverlay {
+    render_pass: Arc<RenderPass>,
+    pipeline: Arc<GraphicsPipeline>,
+    framebuffers: Vec<Arc<Framebuffer>>,
+    // hands out a vertex buffer every frame, reusing those of finished frames
+    vertices: CpuBufferPool<LineVertex>,
+}
+
+impl LineOverlay {
+    pub fn new(queue: &Arc<Queue>, images: &[Arc<SwapchainImage<Window>>]) -> Self {
+        let device = queue.device().clone();
+        let render_pass = vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                color: {
+                    load: Load,
+                    store: Store,
+                    format: images[0].format(),
+                    samples: 1,
+                }
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {}
+            }
+        )
+        .unwrap();
+        let vs = line_vs::load(device.clone()).unwrap();
+        let fs = line_fs::load(device.clone()).unwrap();
+        let pipeline = GraphicsPipeline::start()
+            .vertex_input_state(BuffersDefinition::new().vertex::<LineVertex>())
+            .vertex_shader(vs.entry_point("main").unwrap(), ())
+            .input_assembly_state(InputAssemblyState::new().topology(PrimitiveTopology::LineList))
+            .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+            .fragment_shader(fs.entry_point("main").unwrap(), ())
+            .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+            .build(device.clone())
+            .unwrap();
+        LineOverlay {
+            framebuffers: Self::create_framebuffers(images, &render_pass),
+            render_pass,
+            pipeline,
+            vertices: CpuBufferPool::vertex_buffer(device),
+        }
+    }
+
+    fn create_framebuffers(
+        images: &[Arc<SwapchainImage<Window>>],
+        render_pass: &Arc<RenderPass>,
+    ) -> Vec<Arc<Framebuffer>> {
+        images
+            .iter()
+            .map(|image| {
+                Framebuffer::new(
+                    render_pass.clone(),
+                    FramebufferCreateInfo {
+                        attachments: vec![ImageView::new_default(image.clone()).unwrap()],
+                        ..Default::default()
+                    },
+                )
+                .unwrap()
+            })
+            .collect()
+    }
+
+    /// Draws into the new images after the swapchain was recreated.
+    pub fn resize(&mut self, images: &[Arc<SwapchainImage<Window>>]) {
+        self.framebuffers = Self::create_framebuffers(images, &self.render_pass);
+    }
+
+    /// Records drawing `lines` seen from `camera` over the swapchain image
+    /// `image_idx`.
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        image_idx: usize,
+        lines: &[Line],
+        camera: &CameraInfo,
+    ) {
+        if lines.is_empty() {
+            return;
+        }
+        let vertices = lines.iter().flat_map(|line| {
+            [
+                LineVertex {
+                    position: line.a,
+                    color: line.color,
+                },
+                LineVertex {
+                    position: line.b,
+                    color: line.color,
+                },
+            ]
+        });
+        let vertex_buffer = self.vertices.chunk(vertices).unwrap();
+        let framebuffer = self.framebuffers[image_idx].clone();
+        let [width, height] = framebuffer.extent();
+        let aspect = width as f32 / height as f32;
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values: vec![None],
+                    ..RenderPassBeginInfo::framebuffer(framebuffer)
+                },
+                SubpassContents::Inline,
+            )
+            .unwrap()
+            .set_viewport(
+                0,
+                [Viewport {
+                    origin: [0.0, 0.0],
+                    dimensions: [width as f32, height as f32],
+                    depth_range: 0.0..1.0,
+                }],
+            )
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                line_vs::ty::PushConstants {
+                    view_projection: camera::view_projection(camera, aspect, NEAR, FAR),
+                },
+            )
+            .bind_vertex_buffers(0, vertex_buffer)
+            .draw(2 * lines.len() as u32, 1, 0, 0)
+            .unwrap()
+            .end_render_pass()
+            .unwrap();
+    }
+}
+
+mod line_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/line.vert",
+    }
+}
+
+mod line_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/line.frag",
+    }
+}
@@ -0,0 +1,20 @@
+use std::{fs::File, io, sync::Mutex};
+
+use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+
+// this crate's info and everyone's warnings, unless RUST_LOG says otherwise
+const DEFAULT_FILTER: &str = "warn,rtvox=info";
+
+/// Logs to stderr, and to `file` too when given, at the levels `RUST_LOG`
+/// sets, e.g. `RUST_LOG=rtvox::net=debug`. Messages of crates logging through
+/// `log`, like the Vulkan validation layer's, are included.
+pub fn init(file: Option<File>) {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+    let file = file.map(|file| fmt::layer().with_ansi(false).with_writer(Mutex::new(file)));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().with_writer(io::stderr))
+        .with(file)
+        .init();
+}
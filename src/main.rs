@@ -1,31 +1,204 @@
-use std::{f32::consts::PI, time::Instant};
+use std::{
+    collections::HashSet,
+    f32::consts::PI,
+    fs::File,
+    io::{self, BufWriter, Write},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process,
+    rc::Rc,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
 
-use camera::{Camera, LookEvent, MoveX, MoveY, MoveZ};
-use graphics::Graphics;
-use vulkano::instance::{Instance, InstanceCreateInfo};
+use rtvox::{
+    aabc::Aabc,
+    animation::{self, Animation},
+    app_state::AppState,
+    args::{Args, Backend, Command},
+    benchmark::{self, BenchmarkConfig, WARMUP_FRAMES},
+    breaking::BlockBreaker,
+    camera::{Camera, LookEvent, MoveState, MoveX, MoveY, MoveZ, Projection},
+    console::{self, CommandRegistry, Console},
+    debug_draw,
+    decals::DecalList,
+    demo_world::DemoWorld,
+    entity::{Entity, EntityList},
+    environment::{self, CubeMap},
+    gpu_memory::{self, MemoryKind},
+    graphics::{
+        self,
+        cs::ty::{CameraInfo, HudInfo, Lighting},
+        Graphics, GraphicsCreationError,
+    },
+    hotbar::Hotbar,
+    io::{
+        bookmarks::{Bookmark, Bookmarks},
+        export, frames,
+        metadata::WorldMetadata,
+        region::{ChunkVoxels, ChunkWriter, RegionStore},
+    },
+    labels::{self, Label, LabelList},
+    library::{self, PrefabLibrary},
+    light::{self, LightMap},
+    logging,
+    materials::{MaterialId, MaterialRegistry},
+    mesh,
+    net::{client::Client, protocol::VoxelEdit, server::Server},
+    octree::Octree,
+    physics::Physics,
+    pipelines::{Fog, ShaderFeatures},
+    placement::{self, Placement},
+    plugin::{AppEvent, EventBus, Plugin},
+    prefab::VoxelPrefab,
+    raster::RasterRenderer,
+    raycast::RaycastHit,
+    renderer::{NoRenderer, Renderer},
+    scheduler::DirtyChunks,
+    script::{Script, ScriptCommand},
+    selection::{self, Direction, SelectionBox},
+    softrender,
+    stats::FrameStats,
+    status::Status,
+    stress, tiles,
+    time_of_day::TimeOfDay,
+    validation,
+    view_distance::ViewDistance,
+    world::World,
+    worldgen::{
+        self, caves::CaveConfig, ChunkGenerator, ChunkPos, Palette, WorldBounds, CHUNK_SIZE,
+    },
+};
+use tracing::{error, info, warn};
+use vecmath::Vector3;
+use vulkano::{
+    instance::{Instance, InstanceCreateInfo},
+    swapchain::{PresentMode, Surface},
+};
 use vulkano_win::VkSurfaceBuild;
 use winit::{
     dpi::PhysicalSize,
     event::KeyboardInput,
-    event::{DeviceEvent, ElementState, Event, MouseButton, VirtualKeyCode, WindowEvent},
+    event::{
+        DeviceEvent, ElementState, Event, ModifiersState, MouseButton, MouseScrollDelta,
+        VirtualKeyCode, WindowEvent,
+    },
     event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    window::{Window, WindowBuilder},
 };
 
-mod aabc;
-mod camera;
-mod graphics;
-mod octree;
+// frame time budget used when automatic render scaling is enabled
+const TARGET_FRAME_TIME: Duration = Duration::from_micros(16_667);
+// how far away voxels can be edited from
+const REACH: f32 = 32.0;
+const EXPLOSION_RADIUS: f32 = 4.0;
+// how often the window title is updated
+const STATUS_INTERVAL: Duration = Duration::from_millis(500);
+// where frames are recorded to when recording is started with a key
+const RECORDING_DIR: &str = "recording";
+// real time a full day/night cycle takes at normal speed
+const DAY_LENGTH: Duration = Duration::from_secs(240);
+// what the other players of a shared world are drawn with
+const AVATAR_MATERIALS: [&str; 4] = ["debug", "lamp", "water", "sand"];
+// how often edits are saved when a world directory is given
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
+// most voxels /fill changes at once
+const MAX_FILL_VOLUME: i64 = 1 << 20;
+// how long cutting the view distance gets to free memory before the next cut
+const BUDGET_SETTLE: Duration = Duration::from_secs(2);
+// outline of what the next click copies
+const SELECTION_COLOR: [f32; 3] = [1.0, 1.0, 1.0];
+// outline of the selection box being edited
+const SELECTION_BOX_COLOR: [f32; 3] = [1.0, 0.8, 0.2];
+// outline of where the selected prefab would be stamped
+const STAMP_COLOR: [f32; 3] = [0.3, 0.8, 1.0];
+// labels of waypoints placed with /label
+const WAYPOINT_COLOR: [f32; 3] = [0.4, 1.0, 0.4];
+// chunks around the camera labelled with their position by /chunkids
+const CHUNK_LABEL_RADIUS: i32 = 1;
+const CHUNK_LABEL_COLOR: [f32; 3] = [1.0, 0.5, 0.5];
+const BOOKMARK_COLOR: [f32; 3] = [0.5, 0.7, 1.0];
+// rows of output and input the open console shows over the top of the view
+const CONSOLE_ROWS: usize = 12;
+const CONSOLE_COLOR: [f32; 3] = [0.9, 0.9, 0.9];
+// how long a list like the prefabs stays over the view once shown
+const PANEL_TIME: Duration = Duration::from_secs(4);
+const PANEL_COLOR: [f32; 3] = [1.0, 1.0, 0.6];
 
 fn main() {
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("Invalid arguments: {:?}", e);
+            process::exit(2);
+        }
+    };
+    let log_file = args.log_file.as_ref().map(|path| match File::create(path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Could not create {}: {}", path.display(), e);
+            process::exit(2);
+        }
+    });
+    logging::init(log_file);
+    let (backend, benchmark) = match args.command {
+        Command::Run(backend) => (backend, None),
+        Command::Benchmark(backend, config) => (backend, Some(config)),
+        Command::Stress(config) => {
+            let report = stress::run(&config);
+            stress::print_report(&report);
+            return;
+        }
+        Command::Serve(config) => {
+            let seed = config.seed.unwrap_or_else(rand::random);
+            let generator = create_generator(seed, &MaterialRegistry::default(), args.caves, None);
+            let server = Server::bind(("0.0.0.0", config.port), generator).unwrap();
+            info!(seed, addr = %server.local_addr(), "Serving world");
+            server.run()
+        }
+        Command::Snapshot(config) => {
+            let pixels = softrender::snapshot(&config);
+            let file = File::create(&config.out).unwrap();
+            frames::write_png(BufWriter::new(file), config.size(), &pixels).unwrap();
+            info!(path = %config.out.display(), "Wrote snapshot");
+            return;
+        }
+    };
+    if args.connect.is_some() && args.world.is_some() {
+        error!("Shared worlds are saved by their server, --world can't be used with --connect");
+        process::exit(2);
+    }
+    if args.demo_world.is_some() && (args.connect.is_some() || args.world.is_some()) {
+        error!("Demo worlds aren't saved or shared, --demo-world can't be used with --world or --connect");
+        process::exit(2);
+    }
+    let remote = args.connect.map(|addr| match Client::connect(&addr) {
+        Ok(client) => client,
+        Err(e) => {
+            error!(addr = %addr, "Could not connect: {}", e);
+            process::exit(1);
+        }
+    });
+
     let required_extensions = vulkano_win::required_extensions();
+    let (enabled_extensions, enabled_layers) = if args.validation {
+        (
+            validation::extensions(required_extensions),
+            vec![validation::LAYER.to_string()],
+        )
+    } else {
+        (required_extensions, Vec::new())
+    };
     let instance = Instance::new(InstanceCreateInfo {
-        enabled_extensions: required_extensions,
+        enabled_extensions,
         enumerate_portability: true,
-        enabled_layers: vec![String::from("VK_LAYER_LUNARG_monitor")],
+        enabled_layers,
         ..Default::default()
     })
     .unwrap();
+    // never dropped, the event loop ends the process
+    let _messenger = args.validation.then(|| validation::messenger(&instance));
     let event_loop = EventLoop::new();
     let surface = WindowBuilder::new()
         .with_min_inner_size(PhysicalSize {
@@ -35,126 +208,2333 @@ fn main() {
         .build_vk_surface(&event_loop, instance.clone())
         .unwrap();
 
-    let mut camera = Camera::new([0.0, 0.0, 15.0], PI / 2.0);
-    let mut graphics = Graphics::new(surface, camera.get_camera_info()).unwrap();
-    let mut mouse_1_held = false;
-    let mut started_moving: Option<Instant> = None;
+    let store = args
+        .world
+        .map(|dir| Arc::new(RegionStore::open(dir).unwrap()));
+    let saved = store.as_ref().and_then(|store| store.metadata().unwrap());
+    if let Some(saved) = &saved {
+        if saved.generator != worldgen::GENERATOR {
+            warn!(
+                generator = %saved.generator,
+                "World was generated by another generator, new chunks won't match it"
+            );
+        }
+    }
+    let spawn = saved.as_ref().and_then(|saved| saved.spawn);
+    let spawn = spawn.or(args.demo_world.map(DemoWorld::spawn));
+    let camera = Camera::new(spawn.unwrap_or([0.0, 0.0, 15.0]), PI / 2.0);
+    let materials = MaterialRegistry::default();
+    // the benchmark needs the whole world from the first frame, otherwise it
+    // is generated around the camera in the background
+    let mut world = match (args.demo_world, benchmark) {
+        (Some(demo), _) => demo.build(&materials),
+        (None, Some(config)) => World::seeded(config.seed, benchmark::WORLD_EXTENT, 5),
+        (None, None) => World::new(),
+    };
+    let time_of_day = TimeOfDay::new(DAY_LENGTH);
+    let mut renderer = create_renderer(
+        backend,
+        surface.clone(),
+        camera.get_camera_info(),
+        world.tree(),
+        &materials,
+        time_of_day.lighting(),
+    );
+    if let Some(dir) = args.record {
+        match renderer.ray_tracer() {
+            Some(graphics) => {
+                if let Err(e) = graphics.start_recording(dir.clone()) {
+                    eprintln!("Could not record to {}: {}", dir.display(), e);
+                    process::exit(2);
+                }
+            }
+            None => warn!("Only the ray tracer can record frames"),
+        }
+    }
+    if let Some(fps) = args.target_fps {
+        match renderer.ray_tracer() {
+            Some(graphics) => graphics.set_target_fps(Some(fps)),
+            None => warn!("Only the ray tracer can hold a frame rate"),
+        }
+    }
+    // given in MiB
+    let memory_budget = args.memory_budget.map(|mib| mib << 20);
+    renderer.set_memory_budget(memory_budget);
+    if let Some(count) = args.frames_in_flight {
+        match renderer.ray_tracer() {
+            Some(graphics) => graphics.set_frames_in_flight(count),
+            None => warn!("Only the ray tracer has several frames in flight"),
+        }
+    }
+    if let Some(config) = benchmark {
+        run_benchmark(event_loop, renderer, config)
+    }
+    // a saved world keeps the seed it was generated from, a shared one the
+    // server's
+    let seed = remote
+        .as_ref()
+        .map(Client::seed)
+        .or(saved.map(|saved| saved.seed))
+        .or(args.seed)
+        .unwrap_or_else(rand::random);
+    if let Some(store) = &store {
+        let spawn = camera.get_camera_info().eye;
+        store
+            .set_metadata(&WorldMetadata::new(seed, spawn))
+            .unwrap();
+    }
+    // printed so the world can be generated again with --seed
+    info!(seed, "World seed");
+    let bookmarks = match store.as_ref().map(|store| store.bookmarks()) {
+        Some(Ok(bookmarks)) => bookmarks,
+        Some(Err(e)) => {
+            error!("Could not read bookmarks, starting without them: {}", e);
+            Bookmarks::new()
+        }
+        None => Bookmarks::new(),
+    };
+    let hotbar = Hotbar::from_registry(&materials);
+    let mut library = PrefabLibrary::new(library::DEFAULT_DIR);
+    scan_library(&mut library);
+    // plugins get the edits as events
+    world.share_changes();
+    let source = match remote {
+        Some(client) => ChunkSource::Remote(client),
+        None => ChunkSource::Local(create_generator(
+            seed,
+            &materials,
+            args.caves,
+            store.clone(),
+        )),
+    };
+    let mut app = App {
+        state: AppState::Running,
+        renderer,
+        backend,
+        surface,
+        camera,
+        world,
+        source,
+        saver: store.map(ChunkWriter::new),
+        caves: args.caves,
+        bounds: args.bounds,
+        generated: HashSet::new(),
+        light: LightMap::new(&materials),
+        materials,
+        time_of_day,
+        breaker: BlockBreaker::new(),
+        decals: DecalList::new(),
+        entities: EntityList::new(),
+        labels: LabelList::new(),
+        chunk_labels: false,
+        bookmarks,
+        last_bookmark: None,
+        mouse_1_held: false,
+        mouse_2_held: false,
+        selection: None,
+        editing: false,
+        selection_box: None,
+        cull_camera: None,
+        memory_budget,
+        budget_cut: None,
+        view_distance: ViewDistance::default(),
+        demo_world: args.demo_world,
+        environment: None,
+        clipboard: None,
+        library,
+        placement: Placement::new(),
+        hotbar,
+        dragged: false,
+        modifiers: ModifiersState::empty(),
+        started_moving: None,
+        last_frame: Instant::now(),
+        hidden: false,
+        console: Console::new(),
+        panel: None,
+        commands: Rc::new(commands()),
+        script: None,
+        animation: None,
+        physics: Physics::new(),
+        dirty_chunks: DirtyChunks::new(mesh::CHUNK_SIZE),
+        octree_dirty: false,
+        bus: plugins(),
+    };
+    event_loop.run(move |event, _, control_flow| {
+        let handled =
+            panic::catch_unwind(AssertUnwindSafe(|| app.handle_event(event, control_flow)));
+        // keep the edits when something like losing the device panics
+        if let Err(panic) = handled {
+            app.save_and_wait();
+            panic::resume_unwind(panic)
+        }
+    });
+}
+
+/// Everything the event loop works on. Input is handled depending on the
+/// state: Escape opens a menu where only settings can be changed, the
+/// backtick key opens the console, and losing focus pauses until the window
+/// is focused again.
+struct App {
+    state: AppState,
+    renderer: Box<dyn Renderer>,
+    // what the renderer is created again from when the device is lost
+    backend: Backend,
+    surface: Arc<Surface<Window>>,
+    camera: Camera,
+    world: World,
+    source: ChunkSource,
+    // saves edited chunks, if a world directory was given
+    saver: Option<ChunkWriter>,
+    caves: CaveConfig,
+    bounds: WorldBounds,
+    // chunks added to the world
+    generated: HashSet<ChunkPos>,
+    light: LightMap,
+    materials: MaterialRegistry,
+    time_of_day: TimeOfDay,
+    breaker: BlockBreaker,
+    decals: DecalList,
+    entities: EntityList,
+    // waypoints, drawn every frame with the nametags of other players
+    labels: LabelList,
+    // see /chunkids
+    chunk_labels: bool,
+    // camera poses to go back to, saved with the world
+    bookmarks: Bookmarks,
+    // the bookmark I went to last, I goes to the next one
+    last_bookmark: Option<String>,
+    mouse_1_held: bool,
+    mouse_2_held: bool,
+    selection: Option<Vector3<i32>>,
+    // with the editor tool, middle clicks pick a box to nudge and resize
+    // with the keyboard, then fill, clear or copy
+    editing: bool,
+    selection_box: Option<SelectionBox>,
+    // the camera culling is frozen at, see Renderer::update_cull_camera
+    cull_camera: Option<CameraInfo>,
+    // bytes of device memory, see Renderer::set_memory_budget and
+    // keep_to_budget
+    memory_budget: Option<u64>,
+    // when keep_to_budget last cut the view distance
+    budget_cut: Option<Instant>,
+    // how far chunks are generated and the world is drawn
+    view_distance: ViewDistance,
+    // the scene started in, nothing is generated around it
+    demo_world: Option<DemoWorld>,
+    // lighting the world in place of the sky color, see Graphics::set_environment
+    environment: Option<CubeMap>,
+    clipboard: Option<VoxelPrefab>,
+    // prefabs to stamp where you look, see stamp_prefab
+    library: PrefabLibrary,
+    placement: Placement,
+    hotbar: Hotbar,
+    // whether the mouse moved since the left button was pressed, a click
+    // without moving places a block
+    dragged: bool,
+    modifiers: ModifiersState,
+    started_moving: Option<Instant>,
+    last_frame: Instant,
+    // while minimized the loop waits for events instead of drawing
+    hidden: bool,
+    console: Console,
+    // rows shown in the top left corner for a while, see show_panel
+    panel: Option<(Vec<String>, Instant)>,
+    // shared so a command can be run with the app it belongs to
+    commands: Rc<CommandRegistry<App>>,
+    // the script ticking every frame and when it was loaded
+    script: Option<(Script, Instant)>,
+    // voxel frames playing over the world, see AnimationPlugin
+    animation: Option<Animation>,
+    // moves sand and water after edits, see PhysicsPlugin
+    physics: Physics,
+    // what the renderer still has to get of the world, sent with the next
+    // frames, see flush_octree
+    dirty_chunks: DirtyChunks,
+    octree_dirty: bool,
+    bus: EventBus<App>,
+}
+
+impl AsMut<EventBus<App>> for App {
+    fn as_mut(&mut self) -> &mut EventBus<App> {
+        &mut self.bus
+    }
+}
+
+// where chunks come from: generated here, or sent by the server of a shared
+// world
+enum ChunkSource {
+    Local(ChunkGenerator),
+    Remote(Client),
+}
+
+impl ChunkSource {
+    fn seed(&self) -> u64 {
+        match self {
+            ChunkSource::Local(generator) => generator.seed(),
+            ChunkSource::Remote(client) => client.seed(),
+        }
+    }
+}
+
+impl App {
+    fn handle_event(&mut self, event: Event<()>, control_flow: &mut ControlFlow) {
+        match event {
+            Event::WindowEvent {
+                event: WindowEvent::CloseRequested,
+                ..
+            } => {
+                finish_recording(self.renderer.as_mut());
+                *control_flow = ControlFlow::Exit
+            }
+
+            // the loop ends after any exit
+            Event::LoopDestroyed => self.save_and_wait(),
+
+            Event::WindowEvent {
+                event: WindowEvent::Resized(size),
+                ..
+            } => {
+                // minimizing resizes the window to nothing
+                self.set_hidden(size.width == 0 || size.height == 0, control_flow);
+                self.renderer.resize()
+            }
+
+            Event::WindowEvent {
+                event: WindowEvent::ModifiersChanged(state),
+                ..
+            } => self.modifiers = state,
+
+            Event::WindowEvent {
+                event: WindowEvent::Focused(focused),
+                ..
+            } => self.set_state(self.state.focus_changed(focused)),
+
+            Event::RedrawEventsCleared if !self.hidden => self.redraw(),
+
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::Escape),
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => self.set_state(self.state.escape()),
+
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::Grave),
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => self.set_state(self.state.toggle_console()),
+
+            Event::WindowEvent {
+                event: WindowEvent::ReceivedCharacter(c),
+                ..
+            } if self.state == AppState::Console => self.console.type_char(c),
+
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state,
+                                virtual_keycode: Some(key),
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => match (self.state, state) {
+                (AppState::Running, ElementState::Pressed) => self.key_pressed(key),
+                (AppState::Running, ElementState::Released) => self.key_released(key),
+                (AppState::Menu, ElementState::Pressed) => {
+                    self.settings_key(key);
+                    self.print_menu()
+                }
+                (AppState::Console, ElementState::Pressed) => self.console_key(key),
+                _ => (),
+            },
+
+            _ if !self.state.is_running() => (),
+
+            Event::DeviceEvent {
+                // dx and dy are in "unspecified units"
+                event: DeviceEvent::MouseMotion { delta: (dx, dy) },
+                ..
+            } if self.mouse_1_held => {
+                let look_evt = LookEvent {
+                    right: dx as f32 / 500.0,
+                    down: dy as f32 / 500.0,
+                };
+                self.camera.apply_look_event(look_evt);
+                if dx.abs() + dy.abs() > 1.0 {
+                    self.dragged = true
+                }
+            }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::MouseInput {
+                        state,
+                        button: MouseButton::Left,
+                        ..
+                    },
+                ..
+            } => {
+                self.mouse_1_held = state == ElementState::Pressed;
+                match state {
+                    ElementState::Pressed => self.dragged = false,
+                    ElementState::Released if !self.dragged => self.place_block(),
+                    ElementState::Released => (),
+                }
+            }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::MouseInput {
+                        state,
+                        button: MouseButton::Right,
+                        ..
+                    },
+                ..
+            } => self.mouse_2_held = state == ElementState::Pressed,
+            Event::WindowEvent {
+                event:
+                    WindowEvent::MouseInput {
+                        state: ElementState::Pressed,
+                        button: MouseButton::Middle,
+                        ..
+                    },
+                ..
+            } => self.select(),
+            // pick the hotbar slot, scrolling down moves right
+            Event::WindowEvent {
+                event:
+                    WindowEvent::MouseWheel {
+                        delta: MouseScrollDelta::LineDelta(_, y),
+                        ..
+                    },
+                ..
+            } if y != 0.0 => self.hotbar.scroll(y < 0.0),
+            _ => (),
+        }
+        let changes = self.world.take_changes();
+        if !changes.is_empty() {
+            for change in &changes {
+                self.dirty_chunks.mark(change.result().0);
+            }
+            self.bus.publish(AppEvent::VoxelChanged(changes));
+        }
+        EventBus::dispatch(self);
+    }
+
+    fn set_hidden(&mut self, hidden: bool, control_flow: &mut ControlFlow) {
+        if hidden == self.hidden {
+            return;
+        }
+        self.hidden = hidden;
+        if hidden {
+            info!("Window hidden, stopped drawing");
+            *control_flow = ControlFlow::Wait;
+        } else {
+            info!("Window restored");
+            // the time spent hidden isn't caught up on
+            self.last_frame = Instant::now();
+            *control_flow = ControlFlow::Poll;
+        }
+    }
+
+    fn set_state(&mut self, state: AppState) {
+        if state == self.state {
+            return;
+        }
+        self.state = state;
+        if !state.is_running() {
+            // keys and buttons released while not running are never seen
+            self.camera.move_state = MoveState::default();
+            self.started_moving = None;
+            self.mouse_1_held = false;
+            self.mouse_2_held = false;
+        }
+        match state {
+            AppState::Running => info!("Resumed"),
+            AppState::Paused => info!("Paused"),
+            AppState::Menu => self.print_menu(),
+            AppState::Console => info!("Console (Enter runs a line, /help lists the commands)"),
+        }
+    }
+
+    fn redraw(&mut self) {
+        let now = Instant::now();
+        let dt = now - self.last_frame;
+        self.last_frame = now;
+        if self.state.is_running() {
+            if let Some(dur) = self.started_moving {
+                self.camera.update_position(dur.elapsed());
+                self.started_moving = Some(Instant::now());
+            }
+            let camera_info = self.camera.get_camera_info();
+            let look_dir = vecmath::vec3_sub(camera_info.target, camera_info.eye);
+            let (world, materials) = (&self.world, &self.materials);
+            let target = world
+                .raycast(camera_info.eye, look_dir, REACH)
+                .map(|hit| (hit.pos, materials.hardness(world.get(hit.pos).unwrap())));
+            if let Some(pos) = self
+                .breaker
+                .update(target, self.mouse_2_held, dt, &mut self.decals)
+            {
+                self.world.remove(pos);
+            }
+            self.time_of_day.advance(dt);
+        }
+        self.generate_chunks();
+        self.keep_to_budget();
+        let camera_info = self.camera.get_camera_info();
+        self.renderer.update_lighting(self.time_of_day.lighting());
+        self.renderer.update_decals(&self.decals);
+        // the cell the next block would be placed in
+        let preview = if self.state.is_running() {
+            look_target(&self.camera, &self.world).and_then(|hit| placement::target(&hit))
+        } else {
+            None
+        };
+        self.renderer.update_hud(HudInfo {
+            preview_pos: preview.unwrap_or([0; 3]),
+            break_progress: self.breaker.progress(),
+            preview_material: preview.map_or(0, |_| self.hotbar.selected()),
+            preview_rotation: self.placement.rotation as i32,
+            hotbar_len: self.hotbar.slots().len() as i32,
+            hotbar_selected: self.hotbar.selected_slot() as i32,
+            hotbar: self.hotbar.serialize(),
+        });
+        if let Some(corner) = self.selection {
+            let pos = look_target(&self.camera, &self.world).map_or(corner, |hit| hit.pos);
+            let color = if self.editing {
+                SELECTION_BOX_COLOR
+            } else {
+                SELECTION_COLOR
+            };
+            SelectionBox::new(corner, pos).draw(color);
+        }
+        if let Some(selection) = self.selection_box {
+            selection.draw(SELECTION_BOX_COLOR);
+        }
+        if let Some(selection) = self.stamp_box() {
+            selection.draw(STAMP_COLOR);
+        }
+        self.draw_labels(&camera_info);
+        if self.state == AppState::Console {
+            let size = self.surface.window().inner_size();
+            let aspect = size.width as f32 / size.height.max(1) as f32;
+            let rows = self.console.rows(CONSOLE_ROWS);
+            labels::draw_panel(&rows, &camera_info, aspect, CONSOLE_COLOR);
+        } else if let Some((rows, shown)) = &self.panel {
+            if shown.elapsed() < PANEL_TIME {
+                let size = self.surface.window().inner_size();
+                let aspect = size.width as f32 / size.height.max(1) as f32;
+                labels::draw_panel(rows, &camera_info, aspect, PANEL_COLOR);
+            }
+        }
+        self.renderer.update_debug_lines(debug_draw::take());
+        self.renderer.update_camera(camera_info);
+        self.flush_octree();
+        self.renderer.redraw();
+        if self.renderer.is_lost() {
+            self.recreate_renderer();
+        }
+        self.bus.publish(AppEvent::FrameRendered(dt));
+    }
+
+    // a driver reset or a laptop switching GPUs loses everything on the GPU,
+    // so the renderer is made again from what the app keeps. A lost surface
+    // is tried again too, which only works when the window's is still usable.
+    fn recreate_renderer(&mut self) {
+        warn!("Lost the graphics device, recreating the renderer");
+        let settings = self.renderer.ray_tracer().map(Graphics::take_settings);
+        self.renderer = Box::new(NoRenderer);
+        let tree = self
+            .animation
+            .as_ref()
+            .map_or(self.world.tree(), |animation| animation.scene());
+        self.renderer = create_renderer(
+            self.backend,
+            self.surface.clone(),
+            self.camera.get_camera_info(),
+            tree,
+            &self.materials,
+            self.time_of_day.lighting(),
+        );
+        // it starts with the whole world
+        self.octree_dirty = false;
+        self.dirty_chunks.clear();
+        self.renderer.update_entities(&self.entities);
+        self.renderer.update_cull_camera(self.cull_camera);
+        self.renderer.set_memory_budget(self.memory_budget);
+        self.renderer
+            .update_view_distance(self.view_distance.voxels());
+        match (self.renderer.ray_tracer(), settings) {
+            (Some(graphics), settings) => {
+                graphics.set_environment(self.environment.as_ref());
+                if let Some(settings) = settings {
+                    graphics.apply_settings(settings);
+                }
+            }
+            (None, Some(_)) => {
+                warn!("The ray tracer's settings don't carry over to its replacement")
+            }
+            (None, None) => (),
+        }
+        self.bus.publish(AppEvent::RendererRecreated);
+    }
+
+    fn draw_labels(&self, camera_info: &CameraInfo) {
+        self.labels.draw(camera_info);
+        for bookmark in self.bookmarks.iter() {
+            let label = Label {
+                position: bookmark.position,
+                text: bookmark.name.clone(),
+                color: BOOKMARK_COLOR,
+            };
+            labels::draw(&label, camera_info);
+        }
+        if self.chunk_labels {
+            for chunk in worldgen::chunks_around(camera_info.eye, CHUNK_LABEL_RADIUS) {
+                let label = Label {
+                    position: chunk.map(|c| (c as f32 + 0.5) * CHUNK_SIZE as f32),
+                    text: format!("{} {} {}", chunk[0], chunk[1], chunk[2]),
+                    color: CHUNK_LABEL_COLOR,
+                };
+                labels::draw(&label, camera_info);
+            }
+        }
+        if let ChunkSource::Remote(client) = &self.source {
+            for tag in client.players().nametags(Instant::now()) {
+                labels::draw(&tag, camera_info);
+            }
+        }
+    }
+
+    // sends the whole world to the renderer with the next frame, for changes
+    // that aren't edits, which only send the chunks they touched
+    fn update_octree(&mut self) {
+        self.octree_dirty = true;
+    }
+
+    // sends what changed of the world since the last frame, all of it at once
+    // or the dirty chunks the renderer has time for. The scene of an
+    // animation is rebuilt whole either way.
+    fn flush_octree(&mut self) {
+        if self.octree_dirty || (self.animation.is_some() && !self.dirty_chunks.is_empty()) {
+            self.octree_dirty = false;
+            self.dirty_chunks.clear();
+            upload_world(&mut *self.renderer, &self.world, self.animation.as_mut());
+        } else {
+            self.renderer
+                .update_chunks(self.world.tree(), &mut self.dirty_chunks);
+        }
+    }
+
+    // plays the frames at `path` on top of the face being looked at
+    fn start_animation(&mut self, path: &str, fps: f32) -> Result<String, String> {
+        let hit = look_target(&self.camera, &self.world).ok_or("Look at where it goes")?;
+        let frames = animation::load_frames(Path::new(path))
+            .map_err(|e| format!("Could not load {}: {}", path, e))?;
+        let origin = vecmath::vec3_add(hit.pos, hit.normal);
+        self.animation = Animation::new(frames, origin, fps, self.world.tree());
+        self.update_octree();
+        let count = self.animation.as_ref().map_or(0, Animation::frame_count);
+        Ok(format!("Playing {} frames", count))
+    }
+
+    // requests the missing chunks around the camera, cancels those the camera
+    // moved away from and adds the finished ones to the world
+    fn generate_chunks(&mut self) {
+        if self.demo_world.is_some() {
+            return;
+        }
+        let mut wanted = worldgen::chunks_around(
+            self.camera.get_camera_info().eye,
+            self.view_distance.chunks(),
+        );
+        wanted.retain(|chunk| self.bounds.contains(*chunk));
+        let generated = &self.generated;
+        let finished = match &mut self.source {
+            ChunkSource::Local(generator) => {
+                let wanted_set: HashSet<_> = wanted.iter().copied().collect();
+                generator.retain(|chunk| wanted_set.contains(&chunk));
+                for chunk in wanted {
+                    if !generated.contains(&chunk) {
+                        generator.request(chunk);
+                    }
+                }
+                generator.poll()
+            }
+            // requests to the server can't be cancelled
+            ChunkSource::Remote(client) => {
+                for chunk in wanted {
+                    if !generated.contains(&chunk) {
+                        client.request(chunk);
+                    }
+                }
+                client.poll()
+            }
+        };
+        self.sync_edits();
+        if finished.is_empty() {
+            return;
+        }
+        for (chunk, tree) in finished {
+            self.world.insert_chunk(&tree);
+            self.generated.insert(chunk);
+            let origin = chunk.map(|c| c * CHUNK_SIZE);
+            let max = origin.map(|c| c + CHUNK_SIZE - 1);
+            self.dirty_chunks.mark_box(origin, max);
+            self.bus.publish(AppEvent::ChunkLoaded(chunk));
+        }
+    }
+
+    // streams less of the world while the renderer holds more device memory
+    // than the budget, for renderers that can't evict chunks themselves.
+    // Chunks past the nearer view distance are dropped unless they have
+    // unsaved edits, and the world is sent again to free their memory.
+    fn keep_to_budget(&mut self) {
+        let budget = match self.memory_budget {
+            Some(budget) if self.demo_world.is_none() => budget,
+            _ => return,
+        };
+        if self
+            .budget_cut
+            .is_some_and(|cut| cut.elapsed() < BUDGET_SETTLE)
+        {
+            return;
+        }
+        let usage = self.renderer.memory_usage();
+        if usage.total() <= budget {
+            return;
+        }
+        let world = usage.get(MemoryKind::Octree) + usage.get(MemoryKind::Chunks);
+        let allowance = budget.saturating_sub(usage.total() - world);
+        let distance = self.view_distance.within(world, allowance);
+        if distance == self.view_distance {
+            return;
+        }
+        self.view_distance = distance;
+        self.renderer.update_view_distance(distance.voxels());
+        self.budget_cut = Some(Instant::now());
+        let center = worldgen::chunk_of(self.camera.get_camera_info().eye);
+        let far: Vec<_> = self
+            .generated
+            .iter()
+            .copied()
+            .filter(|chunk| (0..3).any(|i| (chunk[i] - center[i]).abs() > distance.chunks()))
+            .collect();
+        let mut unloaded = 0;
+        for chunk in far {
+            if self.world.unload_chunk(chunk) {
+                self.generated.remove(&chunk);
+                unloaded += 1;
+            }
+        }
+        self.update_octree();
+        warn!(
+            used = %gpu_memory::format_bytes(usage.total()),
+            chunks = distance.chunks(),
+            unloaded,
+            "Over the memory budget, cutting the view distance"
+        );
+    }
+
+    // sends the camera position to the server of a shared world, applies
+    // the edits of everyone and moves the avatars of the other players,
+    // falling back to generating chunks here if the server is gone
+    fn sync_edits(&mut self) {
+        let client = match &mut self.source {
+            ChunkSource::Remote(client) => client,
+            ChunkSource::Local(_) => return,
+        };
+        let edits: Vec<_> = client
+            .take_edits()
+            .iter()
+            .map(|edit| (edit.pos, edit.material))
+            .collect();
+        if !edits.is_empty() {
+            self.world.apply_remote(&edits);
+            self.light
+                .update(&self.world, edits.iter().map(|&(pos, _)| pos));
+            for &(pos, _) in &edits {
+                self.dirty_chunks.mark(pos);
+            }
+        }
+        for (id, text) in client.take_chat() {
+            self.console.print(&format!("<player {}> {}", id, text))
+        }
+        client.send_position(self.camera.get_camera_info().eye);
+        if !client.is_connected() {
+            client.players_mut().clear();
+        }
+        let avatars: Vec<_> = AVATAR_MATERIALS
+            .iter()
+            .map(|name| self.materials.id(name).unwrap())
+            .collect();
+        if client
+            .players_mut()
+            .update_entities(&mut self.entities, &avatars, Instant::now())
+        {
+            self.renderer.update_entities(&self.entities);
+        }
+        if !client.is_connected() {
+            warn!("Lost the connection to the server, continuing alone");
+            let seed = client.seed();
+            self.source =
+                ChunkSource::Local(create_generator(seed, &self.materials, self.caves, None));
+        }
+    }
+
+    fn key_pressed(&mut self, key: VirtualKeyCode) {
+        self.bus.publish(AppEvent::KeyAction(key));
+        let camera = &mut self.camera;
+        match key {
+            VirtualKeyCode::W => {
+                pressed_event!(MoveZ, Forward, Backward, camera.move_state.z)
+            }
+            VirtualKeyCode::A => {
+                pressed_event!(MoveX, Left, Right, camera.move_state.x)
+            }
+            VirtualKeyCode::S => {
+                pressed_event!(MoveZ, Backward, Forward, camera.move_state.z)
+            }
+            VirtualKeyCode::D => {
+                pressed_event!(MoveX, Right, Left, camera.move_state.x)
+            }
+            VirtualKeyCode::LShift => {
+                pressed_event!(MoveY, Down, Up, camera.move_state.y)
+            }
+            VirtualKeyCode::Space => {
+                pressed_event!(MoveY, Up, Down, camera.move_state.y)
+            }
+            VirtualKeyCode::X => {
+                if let Some(hit) = look_target(&self.camera, &self.world) {
+                    let center = hit.pos.map(|c| c as f32 + 0.5);
+                    self.world.carve_sphere(center, EXPLOSION_RADIUS);
+                }
+            }
+            VirtualKeyCode::R => {
+                if let Some(prefab) = &self.clipboard {
+                    self.clipboard = Some(prefab.rotated(1))
+                }
+            }
+            VirtualKeyCode::Q => self.placement.rotate(),
+            VirtualKeyCode::E => {
+                self.editing = !self.editing;
+                self.selection = None;
+                self.selection_box = None;
+                info!(enabled = self.editing, "Editor tool");
+            }
+            VirtualKeyCode::Up
+            | VirtualKeyCode::Down
+            | VirtualKeyCode::Left
+            | VirtualKeyCode::Right
+            | VirtualKeyCode::PageUp
+            | VirtualKeyCode::PageDown => self.move_selection(key),
+            VirtualKeyCode::C | VirtualKeyCode::L | VirtualKeyCode::Delete => {
+                self.edit_selection(key)
+            }
+            VirtualKeyCode::J => {
+                self.library.select_next();
+                let mut rows = self.prefab_rows();
+                if rows.is_empty() {
+                    rows.push(format!("No prefabs in {}", self.library.dir().display()));
+                }
+                self.show_panel(rows);
+            }
+            VirtualKeyCode::K => self.stamp_prefab(),
+            VirtualKeyCode::I if self.modifiers.ctrl() => {
+                self.add_bookmark(None);
+                self.show_panel(self.bookmark_rows());
+            }
+            VirtualKeyCode::I => {
+                let next = self
+                    .bookmarks
+                    .next_after(self.last_bookmark.as_deref())
+                    .map(|bookmark| bookmark.name.clone());
+                match next {
+                    Some(name) => {
+                        self.go_to_bookmark(&name);
+                        self.show_panel(self.bookmark_rows());
+                    }
+                    None => self.show_panel(vec!["No bookmarks, Ctrl+I makes one".to_string()]),
+                }
+            }
+            VirtualKeyCode::Key1
+            | VirtualKeyCode::Key2
+            | VirtualKeyCode::Key3
+            | VirtualKeyCode::Key4
+            | VirtualKeyCode::Key5
+            | VirtualKeyCode::Key6
+            | VirtualKeyCode::Key7
+            | VirtualKeyCode::Key8
+            | VirtualKeyCode::Key9 => self
+                .hotbar
+                .select(key as usize - VirtualKeyCode::Key1 as usize),
+            // paste on top of the face being looked at
+            VirtualKeyCode::Return => {
+                if let (Some(prefab), Some(hit)) =
+                    (&self.clipboard, look_target(&self.camera, &self.world))
+                {
+                    self.world
+                        .paste(prefab, vecmath::vec3_add(hit.pos, hit.normal));
+                }
+            }
+            VirtualKeyCode::Z | VirtualKeyCode::Y if self.modifiers.ctrl() => {
+                match key {
+                    VirtualKeyCode::Z => self.world.undo(),
+                    _ => self.world.redo(),
+                };
+            }
+            // export the clipboard, or the whole world when it's empty
+            VirtualKeyCode::F6 => {
+                let prefab = self
+                    .clipboard
+                    .clone()
+                    .unwrap_or_else(|| self.world.copy_all());
+                match export_prefab(&prefab, &self.materials) {
+                    Ok(()) => info!(voxels = prefab.voxels.len(), "Exported .vox and .obj"),
+                    Err(e) => error!("Could not export: {}", e),
+                }
+            }
+            // drop a small box of the material being looked at in front of it
+            VirtualKeyCode::G => {
+                if let Some(hit) = look_target(&self.camera, &self.world) {
+                    let entity = Entity {
+                        position: vecmath::vec3_add(hit.pos, hit.normal).map(|c| c as f32 + 0.3),
+                        size: [0.4; 3],
+                        material: self.world.get(hit.pos).unwrap(),
+                    };
+                    if self.entities.spawn(entity).is_some() {
+                        self.renderer.update_entities(&self.entities);
+                    }
+                }
+            }
+            VirtualKeyCode::F9 if matches!(self.source, ChunkSource::Remote(_)) => {
+                warn!("A shared world can't be replaced")
+            }
+            // replace the world with a newly generated one, which isn't saved
+            // over the current one
+            VirtualKeyCode::F9 => {
+                let seed = rand::random();
+                info!(seed, "World seed");
+                self.save();
+                if let Some(saver) = self.saver.take() {
+                    info!(dir = %saver.store().dir().display(), "No longer saving");
+                    finish_saving(saver);
+                }
+                self.source =
+                    ChunkSource::Local(create_generator(seed, &self.materials, self.caves, None));
+                self.generated.clear();
+                self.demo_world = None;
+                self.bookmarks = Bookmarks::new();
+                self.last_bookmark = None;
+                self.light = LightMap::new(&self.materials);
+                self.world = World::new();
+                self.world.share_changes();
+                self.update_octree();
+            }
+            VirtualKeyCode::O => {
+                info!(stats = ?self.world.tree().stats(), "Octree");
+                if let Err(e) = self.world.tree().validate() {
+                    error!("Invalid octree: {}", e)
+                }
+            }
+            _ => self.settings_key(key),
+        }
+        if self.started_moving.is_none() && self.camera.is_moving() {
+            self.started_moving = Some(Instant::now())
+        }
+    }
+
+    fn console_key(&mut self, key: VirtualKeyCode) {
+        match key {
+            VirtualKeyCode::Return => {
+                if let Some(line) = self.console.submit() {
+                    self.console.print(&format!("> {}", line));
+                    self.run_line(&line)
+                }
+            }
+            VirtualKeyCode::Back => self.console.backspace(),
+            VirtualKeyCode::Up => self.console.previous(),
+            VirtualKeyCode::Down => self.console.next(),
+            _ => (),
+        }
+    }
+
+    // lines starting with a slash are commands, the rest is chat
+    fn run_line(&mut self, line: &str) {
+        if !line.starts_with('/') {
+            match &mut self.source {
+                ChunkSource::Remote(client) => client.say(line),
+                ChunkSource::Local(_) => self
+                    .console
+                    .print("Nobody else is here, commands start with /"),
+            }
+            return;
+        }
+        let commands = self.commands.clone();
+        match commands.run(self, line) {
+            Ok(output) if output.is_empty() => (),
+            Ok(output) => self.console.print(&output),
+            Err(e) => self.console.print(&e),
+        }
+    }
+
+    fn run_script(&mut self, path: &str) -> Result<String, String> {
+        let mut script = Script::load(Path::new(path), &self.materials)?;
+        let commands = script.run(self.camera.get_camera_info().eye, &mut self.world)?;
+        self.apply_script(commands)?;
+        if !script.ticks() {
+            return Ok(format!("Ran {}", path));
+        }
+        self.script = Some((script, Instant::now()));
+        Ok(format!(
+            "Running {} every frame, /script stop stops it",
+            path
+        ))
+    }
+
+    // stops the script when it fails
+    fn tick_script(&mut self) {
+        let eye = self.camera.get_camera_info().eye;
+        let result = match &mut self.script {
+            Some((script, started)) => {
+                script.tick(started.elapsed().as_secs_f32(), eye, &mut self.world)
+            }
+            None => return,
+        };
+        let result = result.and_then(|commands| self.apply_script(commands));
+        if let Err(e) = result {
+            warn!("Script stopped: {}", e);
+            self.script = None;
+        }
+    }
+
+    fn apply_script(&mut self, commands: Vec<ScriptCommand>) -> Result<(), String> {
+        for command in commands {
+            match command {
+                ScriptCommand::SetVoxel(pos, Some(material)) => {
+                    self.world.set(pos, material);
+                }
+                ScriptCommand::SetVoxel(pos, None) => {
+                    self.world.remove(pos);
+                }
+                ScriptCommand::Fill(a, b, material) => {
+                    self.fill(a, b, material)?;
+                }
+                ScriptCommand::MoveCamera(pos) => self.camera.set_position(pos),
+            }
+        }
+        Ok(())
+    }
+
+    // fills the box between two corners, refusing large ones
+    fn fill(
+        &mut self,
+        a: Vector3<i32>,
+        b: Vector3<i32>,
+        material: Option<MaterialId>,
+    ) -> Result<usize, String> {
+        let min = [0, 1, 2].map(|i| a[i].min(b[i]));
+        let max = [0, 1, 2].map(|i| a[i].max(b[i]));
+        let volume: i64 = (0..3).map(|i| max[i] as i64 - min[i] as i64 + 1).product();
+        if volume > MAX_FILL_VOLUME {
+            return Err(format!("Can't fill more than {} voxels", MAX_FILL_VOLUME));
+        }
+        Ok(self.world.fill_box(min, max, material))
+    }
+
+    fn key_released(&mut self, key: VirtualKeyCode) {
+        let camera = &mut self.camera;
+        match key {
+            VirtualKeyCode::W => {
+                released_event!(MoveZ, Forward, Backward, camera.move_state.z)
+            }
+            VirtualKeyCode::A => {
+                released_event!(MoveX, Left, Right, camera.move_state.x)
+            }
+            VirtualKeyCode::S => {
+                released_event!(MoveZ, Backward, Forward, camera.move_state.z)
+            }
+            VirtualKeyCode::D => {
+                released_event!(MoveX, Right, Left, camera.move_state.x)
+            }
+            VirtualKeyCode::LShift => {
+                released_event!(MoveY, Down, Up, camera.move_state.y)
+            }
+            VirtualKeyCode::Space => {
+                released_event!(MoveY, Up, Down, camera.move_state.y)
+            }
+            _ => (),
+        }
+        if self.started_moving.is_some() && !self.camera.is_moving() {
+            self.started_moving = None
+        }
+    }
+
+    // keys that work both while running and in the menu
+    fn settings_key(&mut self, key: VirtualKeyCode) {
+        match key {
+            // halve or double the speed of the day/night cycle
+            VirtualKeyCode::LBracket => self
+                .time_of_day
+                .set_day_length(self.time_of_day.day_length() * 2),
+            VirtualKeyCode::RBracket => self
+                .time_of_day
+                .set_day_length(self.time_of_day.day_length() / 2),
+            VirtualKeyCode::Comma | VirtualKeyCode::Period => {
+                self.view_distance = match key {
+                    VirtualKeyCode::Comma => self.view_distance.nearer(),
+                    _ => self.view_distance.farther(),
+                };
+                self.renderer
+                    .update_view_distance(self.view_distance.voxels());
+                info!(chunks = self.view_distance.chunks(), "View distance")
+            }
+            // keep culling against where the camera is now, while flying
+            // around to check what's culled
+            VirtualKeyCode::F8 => {
+                self.cull_camera = match self.cull_camera {
+                    Some(_) => None,
+                    None => Some(self.camera.get_camera_info()),
+                };
+                self.renderer.update_cull_camera(self.cull_camera);
+                info!(frozen = self.cull_camera.is_some(), "Culling")
+            }
+            _ => {
+                if let Some(graphics) = self.renderer.ray_tracer() {
+                    ray_tracer_key(graphics, key)
+                }
+            }
+        }
+    }
+
+    fn print_menu(&mut self) {
+        println!("Settings (Escape to resume)");
+        println!(
+            "  [ ]  day length: {}s",
+            self.time_of_day.day_length().as_secs()
+        );
+        println!(
+            "  , .  view distance: {} chunks",
+            self.view_distance.chunks()
+        );
+        println!("  F8   culling frozen: {}", self.cull_camera.is_some());
+        println!("  E    editor tool: {}", self.editing);
+        println!(
+            "  J    prefab: {}",
+            self.library.selected().map_or("none", |b| b.name.as_str())
+        );
+        println!("       /projection: {:?}", self.camera.projection());
+        println!("       /physics: {}", self.physics.enabled());
+        println!(
+            "       /memory budget: {}",
+            self.memory_budget
+                .map_or("off".to_string(), gpu_memory::format_bytes)
+        );
+        if let Some(graphics) = self.renderer.ray_tracer() {
+            println!("  - =  render scale: {:.2}", graphics.render_scale());
+            println!(
+                "  F4   automatic scale: {}",
+                graphics.target_frame_time().is_some()
+            );
+            match graphics.target_fps() {
+                Some(fps) => println!("       /fps target: {} ({:?})", fps, graphics.quality()),
+                None => println!("       /fps target: off"),
+            }
+            match graphics.max_depth() {
+                Some(depth) => println!("       /depth: {}", depth),
+                None => println!("       /depth: off"),
+            }
+            println!("  T    TAA: {}", graphics.taa_enabled());
+            println!("  F    FXAA: {}", graphics.fxaa_enabled());
+            println!("  N    denoise: {}", graphics.denoise_enabled());
+            println!("  B    bloom: {}", graphics.bloom_enabled());
+            println!("  H    shadows: {:?}", graphics.shadows());
+            let features = graphics.shader_features();
+            println!("       /fog: {:?}", features.fog);
+            println!("       /steps: {}", features.max_steps);
+            println!("  M    render mode: {:?}", graphics.render_mode());
+            println!("  U    minimap: {}", graphics.minimap_enabled());
+            println!("  F7   chunk borders: {}", graphics.chunk_borders());
+            println!("  F10  recording: {}", graphics.is_recording());
+            println!("       frames in flight: {}", graphics.frames_in_flight());
+        }
+    }
+
+    // queues the edited chunks to be saved and returns how many there were,
+    // leaving those not generated yet dirty since saving them would hide the
+    // rest of their voxels
+    fn save(&mut self) -> usize {
+        let saver = match &self.saver {
+            Some(saver) => saver,
+            None => return 0,
+        };
+        let metadata = WorldMetadata::new(self.source.seed(), self.camera.get_camera_info().eye);
+        if let Err(e) = saver.store().set_metadata(&metadata) {
+            error!("Could not save world metadata: {}", e)
+        }
+        // saved again along with the newly edited ones
+        self.world.mark_dirty(saver.take_failed());
+        let generated = &self.generated;
+        let chunks: Vec<(ChunkPos, ChunkVoxels)> = self
+            .world
+            .take_dirty_chunks(|chunk| generated.contains(&chunk))
+            .into_iter()
+            .map(|chunk| {
+                let region = Aabc::new(chunk.map(|c| c * CHUNK_SIZE), CHUNK_SIZE as u32);
+                (chunk, self.world.tree().leaves_in(region))
+            })
+            .collect();
+        let count = chunks.len();
+        if count > 0 {
+            saver.send(chunks);
+        }
+        count
+    }
+
+    fn save_and_wait(&mut self) {
+        self.save();
+        if let Some(saver) = self.saver.take() {
+            finish_saving(saver)
+        }
+    }
+
+    // places the selected material against the face being looked at
+    fn place_block(&mut self) {
+        let target = look_target(&self.camera, &self.world).and_then(|hit| placement::target(&hit));
+        if let Some(pos) = target {
+            self.world.set(pos, self.hotbar.selected());
+        }
+    }
+
+    // the first click marks a corner of the selection, the second copies it,
+    // or with the editor tool makes it the selection box
+    fn select(&mut self) {
+        match (self.selection, look_target(&self.camera, &self.world)) {
+            (_, None) => (),
+            (None, Some(hit)) => self.selection = Some(hit.pos),
+            (Some(corner), Some(hit)) if self.editing => {
+                self.selection_box = Some(SelectionBox::new(corner, hit.pos));
+                self.selection = None;
+            }
+            (Some(corner), Some(hit)) => {
+                let selection = SelectionBox::new(corner, hit.pos);
+                let prefab = self.world.copy_box(selection.min, selection.max);
+                info!(voxels = prefab.voxels.len(), "Copied");
+                self.clipboard = Some(prefab);
+                self.selection = None;
+            }
+        }
+    }
+
+    // where the selected prefab goes, on top of the face being looked at and
+    // turned like the next placed block
+    fn stamp_box(&self) -> Option<SelectionBox> {
+        let blueprint = self.library.selected()?;
+        let hit = look_target(&self.camera, &self.world)?;
+        let corner = placement::target(&hit)?;
+        let [w, h, d] = blueprint.prefab.extent;
+        let extent = match self.placement.rotation % 2 {
+            0 => [w, h, d],
+            _ => [d, h, w],
+        };
+        let far = [0, 1, 2].map(|i| corner[i] + extent[i].max(1) - 1);
+        Some(SelectionBox::new(corner, far))
+    }
+
+    // bookmarks where the camera is and how it's turned, under `name` or one
+    // of its own
+    fn add_bookmark(&mut self, name: Option<String>) -> String {
+        let name = name.unwrap_or_else(|| self.bookmarks.unused_name());
+        self.bookmarks.set(Bookmark {
+            name: name.clone(),
+            position: self.camera.get_camera_info().eye,
+            orientation: self.camera.orientation(),
+        });
+        self.last_bookmark = Some(name.clone());
+        self.save_bookmarks();
+        name
+    }
+
+    fn go_to_bookmark(&mut self, name: &str) -> bool {
+        match self.bookmarks.get(name) {
+            Some(bookmark) => {
+                self.camera.set_position(bookmark.position);
+                self.camera.set_orientation(bookmark.orientation);
+                self.last_bookmark = Some(name.to_string());
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn save_bookmarks(&self) {
+        if let Some(saver) = &self.saver {
+            if let Err(e) = saver.store().set_bookmarks(&self.bookmarks) {
+                error!("Could not save bookmarks: {}", e)
+            }
+        }
+    }
+
+    // shows rows in the top left corner for PANEL_TIME, while the console
+    // is closed
+    fn show_panel(&mut self, rows: Vec<String>) {
+        self.panel = Some((rows, Instant::now()));
+    }
+
+    // a row for each bookmark and where it is, the last one gone to or made
+    // marked
+    fn bookmark_rows(&self) -> Vec<String> {
+        self.bookmarks
+            .iter()
+            .map(|b| {
+                let [x, y, z] = b.position;
+                let marker = if Some(&b.name) == self.last_bookmark.as_ref() {
+                    "*"
+                } else {
+                    " "
+                };
+                format!("{} {} ({:.0} {:.0} {:.0})", marker, b.name, x, y, z)
+            })
+            .collect()
+    }
+
+    // a row for each prefab in the library, the selected one marked
+    fn prefab_rows(&self) -> Vec<String> {
+        let selected = self.library.selected().map(|b| b.name.clone());
+        self.library
+            .blueprints()
+            .iter()
+            .map(|b| {
+                let [w, h, d] = b.prefab.extent;
+                let marker = if Some(&b.name) == selected.as_ref() {
+                    "*"
+                } else {
+                    " "
+                };
+                format!("{} {} ({}x{}x{})", marker, b.name, w, h, d)
+            })
+            .collect()
+    }
+
+    // pastes the selected prefab at the outline of stamp_box
+    fn stamp_prefab(&mut self) {
+        let corner = match self.stamp_box() {
+            Some(stamp) => stamp.min,
+            None => return,
+        };
+        let blueprint = self.library.selected().unwrap();
+        let prefab = blueprint.prefab.rotated(self.placement.rotation as i32);
+        info!(prefab = %blueprint.name, voxels = prefab.voxels.len(), "Stamped");
+        self.world.paste(&prefab, corner);
+    }
+
+    // arrows and page up and down nudge the selection box relative to the
+    // camera, growing it with ctrl held and shrinking it with alt
+    fn move_selection(&mut self, key: VirtualKeyCode) {
+        let selection_box = match &mut self.selection_box {
+            Some(selection_box) => selection_box,
+            None => return,
+        };
+        let direction = match key {
+            VirtualKeyCode::Up => Direction::Forward,
+            VirtualKeyCode::Down => Direction::Back,
+            VirtualKeyCode::Left => Direction::Left,
+            VirtualKeyCode::Right => Direction::Right,
+            VirtualKeyCode::PageUp => Direction::Up,
+            _ => Direction::Down,
+        };
+        let camera_info = self.camera.get_camera_info();
+        let step = selection::step(
+            direction,
+            vecmath::vec3_sub(camera_info.target, camera_info.eye),
+        );
+        if self.modifiers.ctrl() {
+            selection_box.resize(step, true);
+        } else if self.modifiers.alt() {
+            selection_box.resize(step, false);
+        } else {
+            selection_box.nudge(step);
+        }
+    }
+
+    // C copies the selection box, L fills it with the selected material and
+    // delete clears it
+    fn edit_selection(&mut self, key: VirtualKeyCode) {
+        let selection = match self.selection_box {
+            Some(selection) => selection,
+            None => return,
+        };
+        let material = match key {
+            VirtualKeyCode::C => {
+                let prefab = self.world.copy_box(selection.min, selection.max);
+                info!(voxels = prefab.voxels.len(), "Copied");
+                self.clipboard = Some(prefab);
+                return;
+            }
+            VirtualKeyCode::L => Some(self.hotbar.selected()),
+            _ => None,
+        };
+        match self.fill(selection.min, selection.max, material) {
+            Ok(changed) => info!(voxels = changed, "Filled the selection"),
+            Err(e) => warn!("{}", e),
+        }
+    }
+}
+
+// reads the prefabs again, logging the files that can't be read
+fn scan_library(library: &mut PrefabLibrary) {
+    match library.scan() {
+        Ok(failed) => {
+            for (path, e) in failed {
+                warn!(path = %path.display(), "Could not read prefab: {}", e);
+            }
+            info!(
+                prefabs = library.blueprints().len(),
+                dir = %library.dir().display(),
+                "Scanned the prefab library"
+            );
+        }
+        Err(e) => warn!(dir = %library.dir().display(), "Could not scan prefabs: {}", e),
+    }
+}
+
+// waits for the chunks queued in saver, telling of those it couldn't save
+fn finish_saving(saver: ChunkWriter) {
+    let failed = saver.finish();
+    if !failed.is_empty() {
+        error!(
+            chunks = failed.len(),
+            "Edits were lost, their chunks could not be saved"
+        );
+    }
+}
+
+// writes prefab to export.vox, and to export.obj with its materials in
+// export.mtl
+fn export_prefab(prefab: &VoxelPrefab, materials: &MaterialRegistry) -> io::Result<()> {
+    let mut vox = BufWriter::new(File::create("export.vox")?);
+    export::write_vox(prefab, materials, &mut vox)?;
+    vox.flush()?;
+    let mut obj = BufWriter::new(File::create("export.obj")?);
+    let mut mtl = BufWriter::new(File::create("export.mtl")?);
+    export::write_obj(prefab, materials, "export.mtl", &mut obj, &mut mtl)?;
+    obj.flush()?;
+    mtl.flush()
+}
+
+// what reacts to the events of the app
+fn plugins() -> EventBus<App> {
+    let mut bus = EventBus::new();
+    bus.add(StatusPlugin {
+        frame_stats: FrameStats::new(60),
+        last_status: Instant::now(),
+    });
+    bus.add(AutosavePlugin {
+        last_save: Instant::now(),
+    });
+    bus.add(ScriptPlugin);
+    bus.add(ShareEditsPlugin);
+    bus.add(LightPlugin { origin: None });
+    bus.add(AnimationPlugin);
+    bus.add(PhysicsPlugin);
+    bus.add(DamagePlugin);
+    bus
+}
+
+// keeps the frame rate and the window title up to date
+struct StatusPlugin {
+    frame_stats: FrameStats,
+    last_status: Instant,
+}
+
+impl Plugin<App> for StatusPlugin {
+    fn handle(&mut self, app: &mut App, event: &AppEvent) {
+        let dt = match event {
+            AppEvent::FrameRendered(dt) => *dt,
+            _ => return,
+        };
+        self.frame_stats.record(dt);
+        if self.last_status.elapsed() >= STATUS_INTERVAL {
+            let samples = app.renderer.ray_tracer().and_then(|g| g.path_samples());
+            app.renderer.update_status(&Status {
+                fps: self.frame_stats.fps(),
+                position: app.camera.get_camera_info().eye,
+                world: format!("seed {}", app.source.seed()),
+                samples,
+                memory: app.renderer.memory_usage().total(),
+            });
+            self.last_status = Instant::now();
+        }
+    }
+}
+
+// saves every `AUTOSAVE_INTERVAL` and when F5 is pressed, if a world
+// directory was given
+struct AutosavePlugin {
+    last_save: Instant,
+}
+
+impl Plugin<App> for AutosavePlugin {
+    fn handle(&mut self, app: &mut App, event: &AppEvent) {
+        match event {
+            AppEvent::FrameRendered(_) if self.last_save.elapsed() >= AUTOSAVE_INTERVAL => {
+                app.save();
+            }
+            AppEvent::KeyAction(VirtualKeyCode::F5) if app.saver.is_some() => {
+                info!(chunks = app.save(), "Saving")
+            }
+            _ => return,
+        }
+        self.last_save = Instant::now();
+    }
+}
+
+// ticks the script started from the console
+struct ScriptPlugin;
+
+impl Plugin<App> for ScriptPlugin {
+    fn handle(&mut self, app: &mut App, event: &AppEvent) {
+        if matches!(event, AppEvent::FrameRendered(_)) && app.state.is_running() {
+            app.tick_script()
+        }
+    }
+}
+
+// sends the edits made here to the server of a shared world
+struct ShareEditsPlugin;
+
+impl Plugin<App> for ShareEditsPlugin {
+    fn handle(&mut self, app: &mut App, event: &AppEvent) {
+        if let (AppEvent::VoxelChanged(changes), ChunkSource::Remote(client)) =
+            (event, &mut app.source)
+        {
+            client.send_edits(changes.iter().map(VoxelEdit::from_change).collect());
+        }
+    }
+}
+
+// lights chunks as they load and voxels as they change, and sends the
+// light around the camera to the ray tracer when it changed
+struct LightPlugin {
+    // of the volume last sent
+    origin: Option<Vector3<i32>>,
+}
+
+impl Plugin<App> for LightPlugin {
+    fn handle(&mut self, app: &mut App, event: &AppEvent) {
+        match event {
+            AppEvent::ChunkLoaded(chunk) => app.light.add_chunk(&app.world, *chunk),
+            AppEvent::VoxelChanged(changes) => app
+                .light
+                .update(&app.world, changes.iter().map(|change| change.result().0)),
+            AppEvent::FrameRendered(_) => {
+                let origin = light::volume_origin(app.camera.get_camera_info().eye);
+                let changed = app.light.take_changed();
+                if !changed && self.origin == Some(origin) {
+                    return;
+                }
+                if let Some(graphics) = app.renderer.ray_tracer() {
+                    graphics.update_light(&app.light.volume(origin, light::VOLUME_SIZE));
+                }
+                self.origin = Some(origin);
+            }
+            AppEvent::RendererRecreated => self.origin = None,
+            _ => (),
+        }
+    }
+}
+
+// moves the animation started from the console on to its next frame
+struct AnimationPlugin;
+
+impl Plugin<App> for AnimationPlugin {
+    fn handle(&mut self, app: &mut App, event: &AppEvent) {
+        let dt = match event {
+            AppEvent::FrameRendered(dt) if app.state.is_running() => *dt,
+            _ => return,
+        };
+        if let Some(animation) = &mut app.animation {
+            if animation.advance(dt, app.world.tree()) {
+                app.renderer.update_octree(animation.scene());
+            }
+        }
+    }
+}
+
+// wakes the voxels around those that changed, moves the sand and spreads
+// the water among them, only into loaded chunks
+struct PhysicsPlugin;
+
+impl Plugin<App> for PhysicsPlugin {
+    fn handle(&mut self, app: &mut App, event: &AppEvent) {
+        let dt = match event {
+            AppEvent::VoxelChanged(changes) => {
+                app.physics
+                    .wake(changes.iter().map(|change| change.result().0));
+                return;
+            }
+            AppEvent::FrameRendered(dt) if app.state.is_running() => *dt,
+            _ => return,
+        };
+        let generated = &app.generated;
+        app.physics
+            .update(dt, &mut app.world, &app.materials, |pos| {
+                generated.contains(&worldgen::chunk_containing(pos))
+            });
+    }
+}
+
+// forgets the damage of voxels that changed, so what replaces a cracked
+// voxel starts whole
+struct DamagePlugin;
+
+impl Plugin<App> for DamagePlugin {
+    fn handle(&mut self, app: &mut App, event: &AppEvent) {
+        if let AppEvent::VoxelChanged(changes) = event {
+            for change in changes {
+                app.breaker.forget(change.result().0, &mut app.decals);
+            }
+        }
+    }
+}
+
+// sends the world to the renderer, or the scene of the animation playing in
+// it, which has to be rebuilt from the world first
+fn upload_world(renderer: &mut dyn Renderer, world: &World, animation: Option<&mut Animation>) {
+    match animation {
+        Some(animation) => {
+            animation.rebuild(world.tree());
+            renderer.update_octree(animation.scene());
+        }
+        None => renderer.update_octree(world.tree()),
+    }
+}
+
+/// Draws the benchmark flythrough as fast as possible, then prints the frame
+/// times as JSON and exits.
+fn run_benchmark(
+    event_loop: EventLoop<()>,
+    mut renderer: Box<dyn Renderer>,
+    config: BenchmarkConfig,
+) -> ! {
+    let uncapped = match renderer.ray_tracer() {
+        Some(graphics) => [PresentMode::Immediate, PresentMode::Mailbox]
+            .into_iter()
+            .any(|mode| graphics.set_present_mode(mode).is_ok()),
+        None => false,
+    };
+    if !uncapped {
+        warn!("Frame times are limited by vsync");
+    }
+    let mut stats = FrameStats::new(config.frames as usize);
+    let mut frame = 0;
+    let mut last_frame = Instant::now();
     event_loop.run(move |event, _, control_flow| match event {
         Event::WindowEvent {
             event: WindowEvent::CloseRequested,
             ..
-        } => *control_flow = ControlFlow::Exit,
+        } => {
+            finish_recording(renderer.as_mut());
+            *control_flow = ControlFlow::Exit
+        }
 
         Event::WindowEvent {
             event: WindowEvent::Resized(_),
             ..
-        } => graphics.recreate_swapchain = true,
+        } => renderer.resize(),
 
         Event::RedrawEventsCleared => {
-            match started_moving {
-                None => (),
-                Some(dur) => {
-                    camera.update_position(dur.elapsed());
-                    started_moving = Some(Instant::now());
-                }
+            let now = Instant::now();
+            if frame > WARMUP_FRAMES {
+                stats.record(now - last_frame);
+            }
+            last_frame = now;
+            if frame == WARMUP_FRAMES + config.frames {
+                finish_recording(renderer.as_mut());
+                println!("{}", benchmark::report_json(&stats));
+                *control_flow = ControlFlow::Exit;
+                return;
             }
-            graphics.update_camera(camera.get_camera_info());
-            graphics.redraw();
+            let path_frame = frame.saturating_sub(WARMUP_FRAMES);
+            renderer.update_camera(benchmark::camera_path(path_frame, config.frames));
+            renderer.redraw();
+            frame += 1;
         }
+        _ => (),
+    })
+}
 
-        Event::WindowEvent {
-            event:
-                WindowEvent::KeyboardInput {
-                    input:
-                        KeyboardInput {
-                            state,
-                            virtual_keycode: Some(key),
-                            ..
-                        },
-                    ..
+// what can be run from the console
+fn commands() -> CommandRegistry<App> {
+    let mut commands: CommandRegistry<App> = CommandRegistry::new();
+    commands.register("tp", "<x> <y> <z>", "moves the camera", |app, args| {
+        app.camera.set_position(console::parse_args(args)?);
+        Ok(String::new())
+    });
+    commands.register(
+        "bookmark",
+        "[<name>]",
+        "bookmarks where the camera is and where it looks, Ctrl+I does too",
+        |app, args| {
+            let name = (!args.is_empty()).then(|| args.join(" "));
+            Ok(format!("Bookmarked {}", app.add_bookmark(name)))
+        },
+    );
+    commands.register(
+        "bookmarks",
+        "[remove <name>]",
+        "lists the bookmarks, I goes to the next one",
+        |app, args| match args {
+            [] if app.bookmarks.is_empty() => Ok("No bookmarks".to_string()),
+            [] => Ok(app.bookmark_rows().join("\n")),
+            ["remove", name @ ..] if !name.is_empty() => {
+                let name = name.join(" ");
+                app.bookmarks
+                    .remove(&name)
+                    .ok_or(format!("No bookmark {}", name))?;
+                app.save_bookmarks();
+                Ok(String::new())
+            }
+            _ => Err("Expected nothing or remove and a name".to_string()),
+        },
+    );
+    commands.register(
+        "goto",
+        "<name>",
+        "moves the camera to a bookmark",
+        |app, args| {
+            let name = args.join(" ");
+            if app.go_to_bookmark(&name) {
+                Ok(String::new())
+            } else {
+                Err(format!("No bookmark {}", name))
+            }
+        },
+    );
+    commands.register(
+        "trace",
+        "[<x> <y> <z> <dx> <dy> <dz>]",
+        "shows the voxel looked at, or the first one along a ray",
+        |app, args| {
+            let hit = match args {
+                [] => look_target(&app.camera, &app.world),
+                _ => {
+                    let [x, y, z, dx, dy, dz]: [f32; 6] = console::parse_args(args)?;
+                    app.world.raycast([x, y, z], [dx, dy, dz], REACH)
+                }
+            };
+            let hit = hit.ok_or(format!("Nothing within {} voxels", REACH))?;
+            let material = app.world.get(hit.pos).and_then(|id| app.materials.get(id));
+            let [x, y, z] = hit.pos;
+            let [nx, ny, nz] = hit.normal;
+            Ok(format!(
+                "{} at ({} {} {}), face ({} {} {}), {:.2} away",
+                material.map_or("unknown", |m| m.name),
+                x,
+                y,
+                z,
+                nx,
+                ny,
+                nz,
+                hit.distance
+            ))
+        },
+    );
+    commands.register(
+        "voxel",
+        "<x> <y> <z>",
+        "shows the material of a voxel",
+        |app, args| {
+            let pos = console::parse_args(args)?;
+            let material = app.world.get(pos).and_then(|id| app.materials.get(id));
+            Ok(material.map_or("air", |m| m.name).to_string())
+        },
+    );
+    commands.register("seed", "", "shows the world seed", |app, _| {
+        Ok(format!("World seed: {}", app.source.seed()))
+    });
+    commands.register(
+        "fill",
+        "<x1> <y1> <z1> <x2> <y2> <z2> [material]",
+        "fills a box with a material or the selected one, air removes voxels",
+        |app, args| {
+            let (corners, material) = match args {
+                [corners @ .., name] if args.len() == 7 => (corners, Some(*name)),
+                _ => (args, None),
+            };
+            let [x1, y1, z1, x2, y2, z2]: [i32; 6] = console::parse_args(corners)?;
+            let material = match material {
+                None => Some(app.hotbar.selected()),
+                Some("air") => None,
+                Some(name) => Some(
+                    app.materials
+                        .id(name)
+                        .ok_or_else(|| format!("Unknown material {}", name))?,
+                ),
+            };
+            let changed = app.fill([x1, y1, z1], [x2, y2, z2], material)?;
+            Ok(format!("Changed {} voxels", changed))
+        },
+    );
+    commands.register(
+        "time",
+        "set <0-1|day|night>|stop|start",
+        "sets the time of day, 0 is midnight and 0.5 noon, or stops it passing",
+        |app, args| {
+            let time = match args {
+                // a still sun lets path tracing converge
+                ["stop"] => {
+                    app.time_of_day.set_day_length(Duration::ZERO);
+                    return Ok("Stopped the time".to_string());
+                }
+                ["start"] => {
+                    app.time_of_day.set_day_length(DAY_LENGTH);
+                    return Ok("Started the time".to_string());
+                }
+                ["set", "day"] => 0.5,
+                ["set", "night"] => 0.0,
+                ["set", time] => time.parse().map_err(|_| format!("Invalid time {}", time))?,
+                _ => return Err("Expected set and a time".to_string()),
+            };
+            app.time_of_day.set_time(time);
+            Ok(String::new())
+        },
+    );
+    commands.register(
+        "fps",
+        "<target>|off",
+        "lowers or raises the ray tracer's quality to hold a frame rate",
+        |app, args| {
+            let target = match args {
+                ["off"] => None,
+                [fps] => match fps.parse::<f32>() {
+                    Ok(fps) if fps > 0.0 => Some(fps),
+                    _ => return Err(format!("Invalid frame rate {}", fps)),
                 },
-            ..
-        } => match state {
-            ElementState::Pressed => {
-                match key {
-                    VirtualKeyCode::W => {
-                        pressed_event!(MoveZ, Forward, Backward, camera.move_state.z)
-                    }
-                    VirtualKeyCode::A => {
-                        pressed_event!(MoveX, Left, Right, camera.move_state.x)
-                    }
-                    VirtualKeyCode::S => {
-                        pressed_event!(MoveZ, Backward, Forward, camera.move_state.z)
+                _ => return Err("Expected a frame rate".to_string()),
+            };
+            let graphics = app
+                .renderer
+                .ray_tracer()
+                .ok_or("Only the ray tracer can hold a frame rate")?;
+            graphics.set_target_fps(target);
+            Ok(String::new())
+        },
+    );
+    commands.register(
+        "fog",
+        "off|linear|exp",
+        "fades distant voxels into the sky",
+        |app, args| {
+            let fog = match args {
+                [name] => Fog::parse(name).ok_or(format!("Unknown fog {}", name))?,
+                _ => return Err("Expected a fog".to_string()),
+            };
+            let graphics = app
+                .renderer
+                .ray_tracer()
+                .ok_or("Only the ray tracer has fog")?;
+            graphics.set_shader_features(ShaderFeatures {
+                fog,
+                ..graphics.shader_features()
+            });
+            Ok(String::new())
+        },
+    );
+    commands.register(
+        "projection",
+        "perspective [degrees]|ortho <width>|tile <column> <row> <columns> <rows>",
+        "sets how the camera projects the world, a tile being part of a perspective view",
+        |app, args| {
+            let projection = match args {
+                ["perspective"] => Projection::Perspective { fov: PI / 2.0 },
+                ["perspective", degrees] => {
+                    let [degrees]: [f32; 1] = console::parse_args(&[*degrees])?;
+                    if !(1.0..180.0).contains(&degrees) {
+                        return Err(format!("Invalid field of view {}", degrees));
                     }
-                    VirtualKeyCode::D => {
-                        pressed_event!(MoveX, Right, Left, camera.move_state.x)
+                    Projection::Perspective {
+                        fov: degrees.to_radians(),
                     }
-                    VirtualKeyCode::LShift => {
-                        pressed_event!(MoveY, Down, Up, camera.move_state.y)
+                }
+                ["ortho", width] => {
+                    let [width]: [f32; 1] = console::parse_args(&[*width])?;
+                    if width <= 0.0 {
+                        return Err(format!("Invalid width {}", width));
                     }
-                    VirtualKeyCode::Space => {
-                        pressed_event!(MoveY, Up, Down, camera.move_state.y)
+                    Projection::Orthographic { width }
+                }
+                ["tile", tile @ ..] => {
+                    let [column, row, columns, rows]: [u32; 4] = console::parse_args(tile)?;
+                    if column >= columns || row >= rows {
+                        return Err(format!(
+                            "No tile {} {} in {}x{}",
+                            column, row, columns, rows
+                        ));
                     }
-                    _ => (),
+                    Projection::tile(PI / 2.0, [column, row], [columns, rows])
                 }
-                match started_moving {
-                    None if camera.is_moving() => started_moving = Some(Instant::now()),
-                    _ => (),
+                _ => return Err("Expected a projection".to_string()),
+            };
+            app.camera.set_projection(projection);
+            Ok(String::new())
+        },
+    );
+    commands.register(
+        "render",
+        "<width> <height> <path> [tile size]",
+        "renders the view into a PNG, tile by tile, for images larger than the GPU holds",
+        |app, args| {
+            let (size, path, tile_size) = match args {
+                [width, height, path] => (
+                    console::parse_args(&[*width, *height])?,
+                    path,
+                    tiles::DEFAULT_TILE_SIZE,
+                ),
+                [width, height, path, tile_size] => {
+                    let [tile_size] = console::parse_args(&[*tile_size])?;
+                    (console::parse_args(&[*width, *height])?, path, tile_size)
                 }
+                _ => return Err("Expected a size and a path".to_string()),
+            };
+            let [width, height]: [u32; 2] = size;
+            app.renderer
+                .render_tiled(width, height, tile_size, Path::new(path))
+                .map_err(|e| format!("Could not render {}: {}", path, e))?;
+            Ok(format!("Rendered {}x{} to {}", width, height, path))
+        },
+    );
+    commands.register(
+        "animate",
+        "<path> [fps]|fps <n>|stop",
+        "plays the models of a .vox file, or of a directory of them, where you look",
+        |app, args| match args {
+            ["stop"] => {
+                app.animation = None;
+                app.update_octree();
+                Ok(String::new())
             }
-            ElementState::Released => {
-                match key {
-                    VirtualKeyCode::W => {
-                        released_event!(MoveZ, Forward, Backward, camera.move_state.z)
-                    }
-                    VirtualKeyCode::A => {
-                        released_event!(MoveX, Left, Right, camera.move_state.x)
-                    }
-                    VirtualKeyCode::S => {
-                        released_event!(MoveZ, Backward, Forward, camera.move_state.z)
-                    }
-                    VirtualKeyCode::D => {
-                        released_event!(MoveX, Right, Left, camera.move_state.x)
-                    }
-                    VirtualKeyCode::LShift => {
-                        released_event!(MoveY, Down, Up, camera.move_state.y)
+            ["fps", fps] => {
+                let fps = parse_fps(fps)?;
+                let animation = app.animation.as_mut().ok_or("No animation is playing")?;
+                animation.set_fps(fps);
+                Ok(String::new())
+            }
+            [path] => app.start_animation(path, animation::DEFAULT_FPS),
+            [path, fps] => app.start_animation(path, parse_fps(fps)?),
+            _ => Err("Expected a path".to_string()),
+        },
+    );
+    commands.register(
+        "memory",
+        "[<MiB>|off]",
+        "shows the device memory used, or sets the budget chunks are evicted or streamed less to stay under",
+        |app, args| {
+            let budget = match args {
+                [] => app.memory_budget,
+                ["off"] => None,
+                [mib] => {
+                    let mib: u64 = mib.parse().map_err(|_| format!("Invalid size {}", mib))?;
+                    Some(mib << 20)
+                }
+                _ => return Err("Expected a size in MiB or off".to_string()),
+            };
+            app.renderer.set_memory_budget(budget);
+            app.memory_budget = budget;
+            let usage = app.renderer.memory_usage();
+            let mut lines: Vec<_> = MemoryKind::ALL
+                .iter()
+                .map(|&kind| format!("{:?}: {}", kind, gpu_memory::format_bytes(usage.get(kind))))
+                .collect();
+            lines.push(format!(
+                "Total: {} of {}",
+                gpu_memory::format_bytes(usage.total()),
+                app.memory_budget
+                    .map_or("no budget".to_string(), gpu_memory::format_bytes)
+            ));
+            Ok(lines.join("\n"))
+        },
+    );
+    commands.register(
+        "physics",
+        "on|off",
+        "makes sand and water fall and flow after edits, or stay put",
+        |app, args| {
+            let enabled = match args {
+                ["on"] => true,
+                ["off"] => false,
+                _ => return Err("Expected on or off".to_string()),
+            };
+            app.physics.set_enabled(enabled);
+            Ok(String::new())
+        },
+    );
+    commands.register(
+        "prefabs",
+        "[rescan]",
+        "lists the prefabs of the library, after reading them again with rescan",
+        |app, args| {
+            match args {
+                [] => (),
+                ["rescan"] => scan_library(&mut app.library),
+                _ => return Err("Expected nothing or rescan".to_string()),
+            }
+            let lines = app.prefab_rows();
+            if lines.is_empty() {
+                return Ok(format!("No prefabs in {}", app.library.dir().display()));
+            }
+            Ok(lines.join("\n"))
+        },
+    );
+    commands.register(
+        "prefab",
+        "<name>|none",
+        "selects the prefab K stamps where you look, Q turns it",
+        |app, args| match args {
+            ["none"] => {
+                app.library.deselect();
+                Ok(String::new())
+            }
+            [name] if app.library.select(name) => Ok(String::new()),
+            [name] => Err(format!("No prefab {}", name)),
+            _ => Err("Expected a name".to_string()),
+        },
+    );
+    commands.register(
+        "depth",
+        "<n>|off",
+        "draws octree nodes n levels below the root whole instead of their voxels",
+        |app, args| {
+            let depth = match args {
+                ["off"] => None,
+                [n] => match n.parse::<u32>() {
+                    Ok(n) if n > 0 => Some(n),
+                    _ => return Err(format!("Invalid depth {}", n)),
+                },
+                _ => return Err("Expected a depth or off".to_string()),
+            };
+            app.renderer
+                .ray_tracer()
+                .ok_or("Only the ray tracer traces an octree")?
+                .set_max_depth(depth);
+            Ok(String::new())
+        },
+    );
+    commands.register(
+        "label",
+        "<text>|clear",
+        "leaves a waypoint with the text where you look, or removes them all",
+        |app, args| match args {
+            [] => Err("Expected some text or clear".to_string()),
+            ["clear"] => {
+                app.labels.clear();
+                Ok(String::new())
+            }
+            words => {
+                let camera_info = app.camera.get_camera_info();
+                let position = look_target(&app.camera, &app.world)
+                    .map_or(camera_info.eye, |hit| {
+                        [0, 1, 2].map(|i| (hit.pos[i] + hit.normal[i]) as f32 + 0.5)
+                    });
+                app.labels.add(Label {
+                    position,
+                    text: words.join(" "),
+                    color: WAYPOINT_COLOR,
+                });
+                Ok(String::new())
+            }
+        },
+    );
+    commands.register(
+        "chunkids",
+        "on|off",
+        "labels the chunks around the camera with their positions",
+        |app, args| {
+            app.chunk_labels = match args {
+                ["on"] => true,
+                ["off"] => false,
+                _ => return Err("Expected on or off".to_string()),
+            };
+            Ok(String::new())
+        },
+    );
+    commands.register(
+        "sky",
+        "[<name>|off]",
+        "lights the world with an .hdr or .exr environment map, lists them without a name",
+        |app, args| {
+            let dir = Path::new(environment::DEFAULT_DIR);
+            let environment = match args {
+                [] => {
+                    let files = environment::list(dir)
+                        .map_err(|e| format!("Could not list {}: {}", dir.display(), e))?;
+                    if files.is_empty() {
+                        return Ok(format!("No environment maps in {}", dir.display()));
                     }
-                    VirtualKeyCode::Space => {
-                        released_event!(MoveY, Up, Down, camera.move_state.y)
+                    let names: Vec<_> = files
+                        .iter()
+                        .filter_map(|file| file.file_stem()?.to_str())
+                        .collect();
+                    return Ok(names.join("\n"));
+                }
+                ["off"] => None,
+                [name] => {
+                    // a bare name is looked for in the environment directory
+                    let mut path = PathBuf::from(name);
+                    if path.extension().is_none() {
+                        let found = environment::EXTENSIONS
+                            .iter()
+                            .map(|ext| dir.join(name).with_extension(ext))
+                            .find(|path| path.exists());
+                        path = found.unwrap_or_else(|| dir.join(name).with_extension("hdr"));
                     }
-                    _ => (),
+                    let cube_map = environment::load(&path)
+                        .map_err(|e| format!("Could not load {}: {}", path.display(), e))?;
+                    Some(cube_map)
                 }
-                match started_moving {
-                    Some(_) if !camera.is_moving() => started_moving = None,
-                    _ => (),
+                _ => return Err("Expected a name or off".to_string()),
+            };
+            app.renderer
+                .ray_tracer()
+                .ok_or("Only the ray tracer draws environment maps")?
+                .set_environment(environment.as_ref());
+            app.environment = environment;
+            Ok(String::new())
+        },
+    );
+    commands.register(
+        "texture",
+        "<material> <path>",
+        "shows a material with the cube map of a PNG, its six faces in a row",
+        |app, args| match args {
+            [name @ .., path] if !name.is_empty() => {
+                let name = name.join(" ");
+                let id = app
+                    .materials
+                    .id(&name)
+                    .ok_or_else(|| format!("Unknown material {}", name))?;
+                if !Path::new(path).is_file() {
+                    return Err(format!("No file {}", path));
                 }
+                app.materials.set_texture_file(id, Path::new(path));
+                app.renderer.update_materials(&app.materials);
+                Ok(String::new())
             }
+            _ => Err("Expected a material and a path".to_string()),
         },
-        Event::DeviceEvent {
-            // dx and dy are in "unspecified units"
-            event: DeviceEvent::MouseMotion { delta: (dx, dy) },
-            ..
-        } if mouse_1_held => {
-            let look_evt = LookEvent {
-                right: dx as f32 / 500.0,
-                down: dy as f32 / 500.0,
+    );
+    commands.register(
+        "steps",
+        "<n>|unlimited",
+        "sets how many octree nodes a ray visits before giving up",
+        |app, args| {
+            let max_steps = match args {
+                ["unlimited"] => i32::MAX,
+                [n] => match n.parse::<i32>() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("Invalid step count {}", n)),
+                },
+                _ => return Err("Expected a step count".to_string()),
             };
-            camera.apply_look_event(look_evt);
+            let graphics = app
+                .renderer
+                .ray_tracer()
+                .ok_or("Only the ray tracer traces an octree")?;
+            graphics.set_shader_features(ShaderFeatures {
+                max_steps,
+                ..graphics.shader_features()
+            });
+            Ok(String::new())
+        },
+    );
+    commands.register(
+        "script",
+        "<path>|stop",
+        "runs a Rhai script, which keeps running if it defines tick(time)",
+        |app, args| match args {
+            ["stop"] => match app.script.take() {
+                Some(_) => Ok("Stopped the script".to_string()),
+                None => Err("No script is running".to_string()),
+            },
+            [path] => app.run_script(path),
+            _ => Err("Expected a path".to_string()),
+        },
+    );
+    commands
+}
+
+/// Creates the renderer `backend` asks for, rasterizing instead when no
+/// device can run the ray tracer and drawing nothing when rasterizing fails
+/// too.
+fn create_renderer(
+    backend: Backend,
+    surface: Arc<Surface<Window>>,
+    camera_info: CameraInfo,
+    tree: &Octree<MaterialId>,
+    materials: &MaterialRegistry,
+    lighting: Lighting,
+) -> Box<dyn Renderer> {
+    // with no renderer left the window stays black rather than the game
+    // closing
+    let raster = |surface| -> Box<dyn Renderer> {
+        match RasterRenderer::new(surface, camera_info, tree, materials, lighting) {
+            Ok(raster) => Box::new(raster),
+            Err(e) => {
+                error!(error = ?e, "Could not create a renderer, drawing nothing");
+                Box::new(NoRenderer)
+            }
         }
-        Event::WindowEvent {
-            event:
-                WindowEvent::MouseInput {
-                    state,
-                    button: MouseButton::Left,
-                    ..
-                },
-            ..
-        } => match state {
-            ElementState::Pressed => mouse_1_held = true,
-            ElementState::Released => mouse_1_held = false,
+    };
+    match backend {
+        Backend::RayTrace => {
+            match Graphics::new(surface.clone(), camera_info, tree, materials, lighting) {
+                Ok(graphics) => Box::new(graphics),
+                Err(GraphicsCreationError::NoSuitableDevice) => {
+                    warn!("No device can run the ray tracer, rasterizing instead");
+                    raster(surface)
+                }
+                Err(GraphicsCreationError::Device(e)) => {
+                    error!(error = ?e, "Could not open the device, rasterizing instead");
+                    raster(surface)
+                }
+            }
+        }
+        Backend::Raster => raster(surface),
+    }
+}
+
+fn create_generator(
+    seed: u64,
+    materials: &MaterialRegistry,
+    caves: CaveConfig,
+    store: Option<Arc<RegionStore>>,
+) -> ChunkGenerator {
+    // leave a core for the event loop
+    let threads = thread::available_parallelism().map_or(1, |n| n.get().saturating_sub(1));
+    ChunkGenerator::new(
+        seed,
+        Palette::from_registry(materials),
+        caves,
+        store,
+        threads,
+    )
+}
+
+fn finish_recording(renderer: &mut dyn Renderer) {
+    if let Some(summary) = renderer.ray_tracer().and_then(Graphics::stop_recording) {
+        info!(
+            written = summary.written,
+            dropped = summary.dropped,
+            "Recorded frames"
+        )
+    }
+}
+
+// settings only the ray tracer has
+fn ray_tracer_key(graphics: &mut Graphics, key: VirtualKeyCode) {
+    match key {
+        VirtualKeyCode::F3 => {
+            let features = graphics.shader_features();
+            graphics.set_shader_features(ShaderFeatures {
+                debug_octree: !features.debug_octree,
+                ..features
+            })
+        }
+        VirtualKeyCode::F4 => match graphics.target_frame_time() {
+            Some(_) => graphics.set_target_frame_time(None),
+            None => graphics.set_target_frame_time(Some(TARGET_FRAME_TIME)),
         },
+        VirtualKeyCode::Minus => {
+            graphics.set_render_scale(graphics.render_scale() - 0.1);
+            info!(scale = graphics.render_scale(), "Render scale")
+        }
+        VirtualKeyCode::Equals => {
+            graphics.set_render_scale(graphics.render_scale() + 0.1);
+            info!(scale = graphics.render_scale(), "Render scale")
+        }
+        VirtualKeyCode::V => {
+            info!(mode = ?graphics.cycle_present_mode(), "Present mode")
+        }
+        VirtualKeyCode::T => graphics.set_taa_enabled(!graphics.taa_enabled()),
+        VirtualKeyCode::F => {
+            graphics.set_fxaa_enabled(!graphics.fxaa_enabled());
+            info!(enabled = graphics.fxaa_enabled(), "FXAA")
+        }
+        VirtualKeyCode::M => {
+            graphics.set_render_mode(graphics.render_mode().next());
+            info!(mode = ?graphics.render_mode(), "Render mode")
+        }
+        VirtualKeyCode::U => {
+            graphics.set_minimap_enabled(!graphics.minimap_enabled());
+            info!(enabled = graphics.minimap_enabled(), "Minimap")
+        }
+        VirtualKeyCode::F7 => {
+            graphics.set_chunk_borders(!graphics.chunk_borders());
+            info!(enabled = graphics.chunk_borders(), "Chunk borders")
+        }
+        VirtualKeyCode::N => {
+            graphics.set_denoise_enabled(!graphics.denoise_enabled());
+            info!(enabled = graphics.denoise_enabled(), "Denoise")
+        }
+        VirtualKeyCode::B => {
+            graphics.set_bloom_enabled(!graphics.bloom_enabled());
+            info!(enabled = graphics.bloom_enabled(), "Bloom")
+        }
+        VirtualKeyCode::H => {
+            graphics.set_shadows(graphics.shadows().next());
+            info!(shadows = ?graphics.shadows(), "Shadows")
+        }
+        VirtualKeyCode::F10 => {
+            if graphics.is_recording() {
+                finish_recording(graphics)
+            } else {
+                match graphics.start_recording(PathBuf::from(RECORDING_DIR)) {
+                    Ok(()) => info!(dir = RECORDING_DIR, "Recording"),
+                    Err(e) => error!("Could not record: {}", e),
+                }
+            }
+        }
+        VirtualKeyCode::P => {
+            for (zone, time) in graphics.gpu_timings() {
+                info!(?zone, ?time, "GPU time")
+            }
+            if let Some(time) = graphics.gpu_overlap() {
+                info!(?time, "Tracing overlapped post-processing")
+            }
+        }
         _ => (),
-    });
+    }
+}
+
+fn parse_fps(arg: &str) -> Result<f32, String> {
+    match arg.parse::<f32>() {
+        Ok(fps) if fps.is_finite() && fps >= 0.0 => Ok(fps),
+        _ => Err(format!("Invalid frame rate {}", arg)),
+    }
+}
+
+fn look_target(camera: &Camera, world: &World) -> Option<RaycastHit> {
+    let info = camera.get_camera_info();
+    let look_dir = vecmath::vec3_sub(info.target, info.eye);
+    world.raycast(info.eye, look_dir, REACH)
 }
 
 use paste::paste;
@@ -0,0 +1,390 @@
+use std::path::{Path, PathBuf};
+
+/// Index of a material in the registry. 0 is reserved for empty space.
+pub type MaterialId = i32;
+
+/// Level of a liquid's sources, which stay put. Liquid flowing from them is a
+/// level lower with every voxel it spreads sideways, down to 1.
+pub const MAX_LEVEL: u8 = 8;
+
+/// How voxels of a material move on their own, see `physics::Physics`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flow {
+    /// Stays where it's placed.
+    Solid,
+    /// Falls, and slides down the sides of piles.
+    Powder,
+    /// Flows down and spreads out from sources, see `MAX_LEVEL`.
+    Liquid,
+}
+
+/// Textures a material shows one after the other, like flowing water.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureAnimation {
+    /// Cube map index of the first frame, the others right after it.
+    pub first: MaterialId,
+    pub frames: u32,
+    /// Seconds each frame is shown for.
+    pub frame_time: f32,
+}
+
+impl TextureAnimation {
+    /// Cube map index of the frame shown `time` seconds in, looping.
+    pub fn texture(&self, time: f32) -> MaterialId {
+        let frame = (time.max(0.0) / self.frame_time) as u64 % self.frames.max(1) as u64;
+        self.first + frame as MaterialId
+    }
+}
+
+#[derive(Clone)]
+pub struct Material {
+    pub name: &'static str,
+    /// Seconds needed to break a voxel of this material.
+    pub hardness: f32,
+    /// How much of what's behind a voxel is hidden by it, 1 for opaque
+    /// materials.
+    pub opacity: f32,
+    /// Amplitude of the wobble applied to what's seen through a translucent
+    /// voxel, as a fraction of the screen height.
+    pub distortion: f32,
+    /// Average color of the texture, used when exporting meshes.
+    pub color: [u8; 3],
+    /// Light given off, relative to the texture color. Above 0 the voxel
+    /// glows, and bright enough emission blooms.
+    pub emission: f32,
+    pub flow: Flow,
+    /// Level of a liquid, `MAX_LEVEL` for its source, 0 for other materials.
+    pub level: u8,
+    /// Cube map index in the texture array. The levels of a liquid share the
+    /// texture of its source.
+    pub texture: MaterialId,
+    /// Shown instead of `texture` where the shader draws the material.
+    pub animation: Option<TextureAnimation>,
+    /// PNG of a cube map shown instead of `texture`, see
+    /// `MaterialRegistry::set_texture_file`.
+    pub texture_file: Option<PathBuf>,
+}
+
+impl Material {
+    pub fn is_translucent(&self) -> bool {
+        self.opacity < 1.0
+    }
+}
+
+#[derive(Clone)]
+pub struct MaterialRegistry {
+    materials: Vec<Material>,
+}
+
+impl Default for MaterialRegistry {
+    fn default() -> Self {
+        // name, hardness and color, in the order of the rows of cubemap.png
+        let materials = vec![
+            ("air", 0.0, [0, 0, 0]),
+            ("dirt", 0.5, [121, 85, 58]),
+            ("grass", 0.6, [96, 144, 56]),
+            ("dark grass", 0.6, [64, 104, 44]),
+            ("debug", 0.2, [255, 0, 255]),
+            ("gravel", 0.6, [128, 122, 118]),
+            ("cobblestone", 2.0, [110, 110, 110]),
+            ("snow", 0.2, [240, 244, 250]),
+            ("ice", 0.5, [150, 190, 240]),
+            ("snowy grass", 0.6, [200, 214, 206]),
+            ("sand", 0.5, [219, 206, 160]),
+            ("planks", 1.5, [162, 130, 78]),
+            ("dark planks", 1.5, [92, 66, 40]),
+            ("stone", 1.5, [125, 125, 125]),
+            ("leaves", 0.2, [58, 110, 38]),
+            ("water", 0.1, [48, 96, 200]),
+            ("lamp", 0.3, [245, 215, 150]),
+        ];
+        // name, opacity, distortion
+        let translucent = [("ice", 0.7, 0.0), ("water", 0.45, 0.004)];
+        let emissive = [("lamp", 4.0)];
+        // name, frames and seconds per frame, the frames in rows of
+        // cubemap.png after those of the materials
+        let animated = [("water", 4, 0.25)];
+        let flowing = [
+            ("sand", Flow::Powder),
+            ("gravel", Flow::Powder),
+            ("water", Flow::Liquid),
+        ];
+        let mut materials: Vec<_> = materials
+            .into_iter()
+            .enumerate()
+            .map(|(texture, (name, hardness, color))| {
+                let (opacity, distortion) = translucent
+                    .iter()
+                    .find(|t| t.0 == name)
+                    .map_or((1.0, 0.0), |t| (t.1, t.2));
+                let emission = emissive.iter().find(|e| e.0 == name).map_or(0.0, |e| e.1);
+                let flow = flowing
+                    .iter()
+                    .find(|f| f.0 == name)
+                    .map_or(Flow::Solid, |f| f.1);
+                let animation = animated.iter().find(|a| a.0 == name).map(|a| (a.1, a.2));
+                Material {
+                    name,
+                    hardness,
+                    opacity,
+                    distortion,
+                    color,
+                    emission,
+                    flow,
+                    level: if flow == Flow::Liquid { MAX_LEVEL } else { 0 },
+                    texture: texture as MaterialId,
+                    animation: animation.map(|(frames, frame_time)| TextureAnimation {
+                        first: 0,
+                        frames,
+                        frame_time,
+                    }),
+                    texture_file: None,
+                }
+            })
+            .collect();
+        let mut first = materials.len() as MaterialId;
+        for animation in materials.iter_mut().filter_map(|m| m.animation.as_mut()) {
+            animation.first = first;
+            first += animation.frames as MaterialId;
+        }
+        // the lower levels of liquids after the rows of cubemap.png, shallower
+        // water being clearer
+        let sources: Vec<_> = materials
+            .iter()
+            .filter(|m| m.level == MAX_LEVEL)
+            .cloned()
+            .collect();
+        for source in sources {
+            for level in (1..MAX_LEVEL).rev() {
+                let depth = level as f32 / MAX_LEVEL as f32;
+                materials.push(Material {
+                    opacity: source.opacity * (0.5 + 0.5 * depth),
+                    level,
+                    ..source.clone()
+                });
+            }
+        }
+        MaterialRegistry { materials }
+    }
+}
+
+impl MaterialRegistry {
+    pub fn get(&self, id: MaterialId) -> Option<&Material> {
+        if id <= 0 {
+            return None;
+        }
+        self.materials.get(id as usize)
+    }
+
+    pub fn id(&self, name: &str) -> Option<MaterialId> {
+        self.materials
+            .iter()
+            .skip(1)
+            .position(|m| m.name == name)
+            .map(|i| i as MaterialId + 1)
+    }
+
+    pub fn hardness(&self, id: MaterialId) -> f32 {
+        self.get(id).map(|m| m.hardness).unwrap_or(0.0)
+    }
+
+    pub fn flow(&self, id: MaterialId) -> Flow {
+        self.get(id).map_or(Flow::Solid, |m| m.flow)
+    }
+
+    /// Level of a liquid, 0 for other materials and empty space.
+    pub fn level(&self, id: MaterialId) -> u8 {
+        self.get(id).map_or(0, |m| m.level)
+    }
+
+    /// The material of the liquid `id` at another level, None for level 0.
+    pub fn with_level(&self, id: MaterialId, level: u8) -> Option<MaterialId> {
+        let texture = self.get(id)?.texture;
+        (1..self.materials.len())
+            .find(|&i| {
+                let m = &self.materials[i];
+                m.flow == Flow::Liquid && m.texture == texture && m.level == level
+            })
+            .map(|i| i as MaterialId)
+    }
+
+    /// Cube map index of `id`, or `id` itself for ids without a material.
+    pub fn texture(&self, id: MaterialId) -> MaterialId {
+        self.get(id).map_or(id, |m| m.texture)
+    }
+
+    /// Cube map index of every id `time` seconds in, following their
+    /// animations.
+    pub fn textures_at(&self, time: f32) -> Vec<MaterialId> {
+        self.materials
+            .iter()
+            .map(|m| m.animation.map_or(m.texture, |a| a.texture(time)))
+            .collect()
+    }
+
+    /// Names the PNG of a cube map for `id` and the materials sharing its
+    /// texture, like the levels of a liquid, which the renderer loads in
+    /// place of their row of cubemap.png. They stop being animated. False if
+    /// there's no material `id`.
+    pub fn set_texture_file(&mut self, id: MaterialId, path: &Path) -> bool {
+        let texture = match self.get(id) {
+            Some(material) => material.texture,
+            None => return false,
+        };
+        for material in self.materials.iter_mut().skip(1) {
+            if material.texture == texture {
+                material.texture_file = Some(path.to_path_buf());
+                material.animation = None;
+            }
+        }
+        true
+    }
+
+    pub fn texture_file(&self, id: MaterialId) -> Option<&Path> {
+        self.get(id)?.texture_file.as_deref()
+    }
+
+    pub fn get_mut(&mut self, id: MaterialId) -> Option<&mut Material> {
+        if id <= 0 {
+            return None;
+        }
+        self.materials.get_mut(id as usize)
+    }
+
+    /// Opacity, distortion, emission and texture of every id, in the layout
+    /// of the shader's material buffer.
+    pub fn serialize(&self) -> Vec<f32> {
+        self.materials
+            .iter()
+            .flat_map(|m| [m.opacity, m.distortion, m.emission, m.texture as f32])
+            .collect()
+    }
+
+    /// Returns the number of ids in use, including the reserved empty id.
+    pub fn len(&self) -> usize {
+        self.materials.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.materials.len() <= 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn air_is_not_a_material() {
+        let registry = MaterialRegistry::default();
+        assert!(registry.get(0).is_none());
+        assert!(registry.get(-1).is_none());
+    }
+
+    #[test]
+    fn unknown_material_has_no_hardness() {
+        let registry = MaterialRegistry::default();
+        assert_eq!(0.0, registry.hardness(registry.len() as MaterialId));
+    }
+
+    #[test]
+    fn all_materials_take_time_to_break() {
+        let registry = MaterialRegistry::default();
+        for id in 1..registry.len() as MaterialId {
+            assert!(registry.hardness(id) > 0.0);
+        }
+    }
+
+    #[test]
+    fn water_is_translucent() {
+        let registry = MaterialRegistry::default();
+        let water = registry.id("water").unwrap();
+        assert!(registry.get(water).unwrap().is_translucent());
+        assert!(!registry.get(1).unwrap().is_translucent());
+    }
+
+    #[test]
+    fn sand_falls_and_stone_stays() {
+        let registry = MaterialRegistry::default();
+        assert_eq!(Flow::Powder, registry.flow(registry.id("sand").unwrap()));
+        assert_eq!(Flow::Liquid, registry.flow(registry.id("water").unwrap()));
+        assert_eq!(Flow::Solid, registry.flow(registry.id("stone").unwrap()));
+        assert_eq!(Flow::Solid, registry.flow(0));
+    }
+
+    #[test]
+    fn water_has_a_material_per_level() {
+        let registry = MaterialRegistry::default();
+        let water = registry.id("water").unwrap();
+        assert_eq!(MAX_LEVEL, registry.level(water));
+        assert_eq!(Some(water), registry.with_level(water, MAX_LEVEL));
+        assert_eq!(None, registry.with_level(water, 0));
+        for level in 1..MAX_LEVEL {
+            let flowing = registry.with_level(water, level).unwrap();
+            assert_eq!(level, registry.level(flowing));
+            assert_eq!(water, registry.texture(flowing));
+            assert!(registry.get(flowing).unwrap().is_translucent());
+            assert_eq!(Some(flowing), registry.with_level(flowing, level));
+        }
+        assert_eq!(None, registry.with_level(registry.id("sand").unwrap(), 1));
+    }
+
+    #[test]
+    fn serialize_four_floats_per_id() {
+        let mut registry = MaterialRegistry::default();
+        registry.get_mut(2).unwrap().opacity = 0.25;
+        let data = registry.serialize();
+        assert_eq!(4 * registry.len(), data.len());
+        assert_eq!([0.25, 0.0, 0.0, 2.0], data[8..12]);
+        let lamp = registry.id("lamp").unwrap() as usize;
+        assert!(data[4 * lamp + 2] > 1.0);
+    }
+
+    #[test]
+    fn water_frames_loop() {
+        let registry = MaterialRegistry::default();
+        let water = registry.id("water").unwrap();
+        let animation = registry.get(water).unwrap().animation.unwrap();
+        assert_eq!(registry.id("lamp").unwrap() + 1, animation.first);
+        let frames = |time| registry.textures_at(time)[water as usize] - animation.first;
+        assert_eq!(0, frames(0.0));
+        assert_eq!(1, frames(animation.frame_time * 1.5));
+        let lap = animation.frame_time * animation.frames as f32;
+        assert_eq!(0, frames(lap + 0.01));
+        // flowing water moves like its source, and stone doesn't move
+        let flowing = registry.with_level(water, 1).unwrap() as usize;
+        assert_eq!(
+            registry.textures_at(0.3)[water as usize],
+            registry.textures_at(0.3)[flowing]
+        );
+        let stone = registry.id("stone").unwrap();
+        assert_eq!(
+            registry.texture(stone),
+            registry.textures_at(0.3)[stone as usize]
+        );
+    }
+
+    #[test]
+    fn texture_files_cover_liquid_levels() {
+        let mut registry = MaterialRegistry::default();
+        let water = registry.id("water").unwrap();
+        let path = Path::new("textures/water.png");
+        assert!(registry.set_texture_file(water, path));
+        let flowing = registry.with_level(water, 1).unwrap();
+        assert_eq!(Some(path), registry.texture_file(flowing));
+        assert_eq!(None, registry.texture_file(registry.id("stone").unwrap()));
+        assert_eq!(
+            registry.texture(water),
+            registry.textures_at(0.3)[water as usize]
+        );
+        assert!(!registry.set_texture_file(0, path));
+    }
+
+    #[test]
+    fn id_finds_name() {
+        let registry = MaterialRegistry::default();
+        assert_eq!(Some(1), registry.id("dirt"));
+        assert_eq!(None, registry.id("air"));
+        assert_eq!(None, registry.id("lava"));
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+
+use vecmath::Vector3;
+
+use crate::{
+    aabc::Aabc,
+    materials::{MaterialId, MaterialRegistry},
+    octree::Octree,
+};
+
+/// Edge length of the cubes the world is meshed and culled in by the raster
+/// renderer. Also outlined by graphics.comp in the chunk render mode.
+pub const CHUNK_SIZE: u32 = 32;
+
+/// A rectangle covering faces of the same material, with its corners counter
+/// clockwise when seen from the side its normal points to.
+#[derive(Debug, PartialEq)]
+pub struct Quad {
+    pub material: MaterialId,
+    pub normal: Vector3<i32>,
+    pub corners: [Vector3<i32>; 4],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vertex {
+    pub position: Vector3<f32>,
+    pub normal: Vector3<f32>,
+    pub material: MaterialId,
+}
+
+/// An indexed triangle list, counter clockwise triangles facing out.
+#[derive(Debug, Default)]
+pub struct Mesh {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
+impl Mesh {
+    /// Splits every quad into two triangles sharing its 4 vertices.
+    pub fn from_quads(quads: &[Quad]) -> Self {
+        let mut mesh = Mesh::default();
+        for quad in quads {
+            let first = mesh.vertices.len() as u32;
+            for corner in quad.corners {
+                mesh.vertices.push(Vertex {
+                    position: corner.map(|c| c as f32),
+                    normal: quad.normal.map(|n| n as f32),
+                    material: quad.material,
+                });
+            }
+            mesh.indices.extend([0, 1, 2, 0, 2, 3].map(|i| first + i));
+        }
+        mesh
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+}
+
+/// Meshes the voxels of the tree inside `chunk`. Faces against voxels in
+/// neighboring chunks are left out, so chunks can be meshed separately.
+pub fn mesh_chunk(tree: &Octree<MaterialId>, chunk: Aabc, materials: &MaterialRegistry) -> Mesh {
+    if tree.leaves_in(chunk).is_empty() {
+        return Mesh::default();
+    }
+    let size = chunk.size as i32;
+    let quads = greedy_quads(chunk.origin, [size; 3], materials, |pos| tree.get_leaf(pos));
+    Mesh::from_quads(&quads)
+}
+
+/// The voxels `mesh_chunk` looks at for `chunk`, those inside it and those
+/// against its faces, so it can be meshed away from the tree with
+/// `mesh_voxels`, on another thread.
+pub fn chunk_voxels(tree: &Octree<MaterialId>, chunk: Aabc) -> HashMap<Vector3<i32>, MaterialId> {
+    let mut voxels: HashMap<_, _> = tree.leaves_in(chunk).into_iter().collect();
+    if voxels.is_empty() {
+        return voxels;
+    }
+    let size = chunk.size as i32;
+    for d in 0..3 {
+        let (u, v) = ((d + 1) % 3, (d + 2) % 3);
+        for depth in [-1, size] {
+            for i in 0..size {
+                for j in 0..size {
+                    let mut pos = chunk.origin;
+                    pos[d] += depth;
+                    pos[u] += i;
+                    pos[v] += j;
+                    if let Some(material) = tree.get_leaf(pos) {
+                        voxels.insert(pos, material);
+                    }
+                }
+            }
+        }
+    }
+    voxels
+}
+
+/// Meshes `chunk` from the voxels `chunk_voxels` took out of the tree.
+pub fn mesh_voxels(
+    voxels: &HashMap<Vector3<i32>, MaterialId>,
+    chunk: Aabc,
+    materials: &MaterialRegistry,
+) -> Mesh {
+    if voxels.is_empty() {
+        return Mesh::default();
+    }
+    let size = chunk.size as i32;
+    let quads = greedy_quads(chunk.origin, [size; 3], materials, |pos| {
+        voxels.get(&pos).copied()
+    });
+    Mesh::from_quads(&quads)
+}
+
+/// Splits the tree into cubes of `chunk_size`, a power of two, and meshes the
+/// ones holding voxels.
+pub fn mesh_chunks(
+    tree: &Octree<MaterialId>,
+    chunk_size: u32,
+    materials: &MaterialRegistry,
+) -> Vec<(Aabc, Mesh)> {
+    let bounds = match tree.bounds() {
+        Some(bounds) => bounds,
+        None => return Vec::new(),
+    };
+    let size = chunk_size.min(bounds.size);
+    let count = (bounds.size / size) as i32;
+    let mut chunks = Vec::new();
+    for x in 0..count {
+        for y in 0..count {
+            for z in 0..count {
+                let offset = [x, y, z].map(|c| c * size as i32);
+                let chunk = Aabc::new(vecmath::vec3_add(bounds.origin, offset), size);
+                let mesh = mesh_chunk(tree, chunk, materials);
+                if !mesh.is_empty() {
+                    chunks.push((chunk, mesh));
+                }
+            }
+        }
+    }
+    chunks
+}
+
+/// Merges the visible faces of the voxels in the box from `origin` spanning
+/// `extent` into as few quads as it can, slice by slice. `get` looks up the
+/// voxel at a position, which may be outside the box. A face is visible
+/// unless it touches an opaque voxel or one of the same material, which
+/// includes the other levels of a liquid.
+pub fn greedy_quads<F>(
+    origin: Vector3<i32>,
+    extent: Vector3<i32>,
+    materials: &MaterialRegistry,
+    get: F,
+) -> Vec<Quad>
+where
+    F: Fn(Vector3<i32>) -> Option<MaterialId>,
+{
+    let hidden = |material: MaterialId, neighbor: Vector3<i32>| match get(neighbor) {
+        None => false,
+        Some(other) => {
+            materials.texture(other) == materials.texture(material)
+                || !matches!(materials.get(other), Some(m) if m.is_translucent())
+        }
+    };
+    let mut quads = Vec::new();
+    for d in 0..3 {
+        // u, v and d form a right handed basis
+        let (u, v) = ((d + 1) % 3, (d + 2) % 3);
+        let (width, height) = (extent[u], extent[v]);
+        let at = |i: i32, j: i32| (i + j * width) as usize;
+        let point = |depth: i32, i: i32, j: i32| {
+            let mut p = origin;
+            p[d] += depth;
+            p[u] += i;
+            p[v] += j;
+            p
+        };
+        for sign in [-1, 1] {
+            let mut normal = [0; 3];
+            normal[d] = sign;
+            for slice in 0..extent[d] {
+                // material of each visible face in the slice, 0 for none
+                let mut mask = vec![0; (width * height) as usize];
+                for j in 0..height {
+                    for i in 0..width {
+                        let pos = point(slice, i, j);
+                        if let Some(material) = get(pos) {
+                            if !hidden(material, vecmath::vec3_add(pos, normal)) {
+                                mask[at(i, j)] = material;
+                            }
+                        }
+                    }
+                }
+                let depth = slice + (sign + 1) / 2;
+                for j in 0..height {
+                    let mut i = 0;
+                    while i < width {
+                        let material = mask[at(i, j)];
+                        if material == 0 {
+                            i += 1;
+                            continue;
+                        }
+                        let mut w = 1;
+                        while i + w < width && mask[at(i + w, j)] == material {
+                            w += 1;
+                        }
+                        let mut h = 1;
+                        while j + h < height && (i..i + w).all(|k| mask[at(k, j + h)] == material) {
+                            h += 1;
+                        }
+                        for y in j..j + h {
+                            for x in i..i + w {
+                                mask[at(x, y)] = 0;
+                            }
+                        }
+                        let mut corners = [
+                            point(depth, i, j),
+                            point(depth, i + w, j),
+                            point(depth, i + w, j + h),
+                            point(depth, i, j + h),
+                        ];
+                        if sign < 0 {
+                            corners.reverse();
+                        }
+                        quads.push(Quad {
+                            material,
+                            normal,
+                            corners,
+                        });
+                        i += w;
+                    }
+                }
+            }
+        }
+    }
+    quads
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quads_of(
+        extent: Vector3<i32>,
+        voxels: impl IntoIterator<Item = (Vector3<i32>, MaterialId)>,
+    ) -> Vec<Quad> {
+        let voxels: HashMap<_, _> = voxels.into_iter().collect();
+        greedy_quads([0; 3], extent, &MaterialRegistry::default(), |pos| {
+            voxels.get(&pos).copied()
+        })
+    }
+
+    #[test]
+    fn single_voxel_has_six_faces() {
+        let quads = quads_of([1; 3], [([0, 0, 0], 1)]);
+        assert_eq!(6, quads.len());
+        let top = quads.iter().find(|q| q.normal == [0, 1, 0]).unwrap();
+        assert_eq!([[0, 1, 0], [0, 1, 1], [1, 1, 1], [1, 1, 0]], top.corners);
+    }
+
+    #[test]
+    fn block_faces_are_merged() {
+        let mut tree = Octree::new();
+        tree.fill_region(Aabc::new([0, 0, 0], 4), 1);
+        let quads = greedy_quads([0; 3], [4; 3], &MaterialRegistry::default(), |pos| {
+            tree.get_leaf(pos)
+        });
+        assert_eq!(6, quads.len());
+    }
+
+    #[test]
+    fn materials_are_not_merged() {
+        let quads = quads_of([2, 1, 1], [([0, 0, 0], 1), ([1, 0, 0], 2)]);
+        assert_eq!(10, quads.len());
+    }
+
+    #[test]
+    fn faces_behind_water_are_kept() {
+        let water = MaterialRegistry::default().id("water").unwrap();
+        let quads = quads_of([2, 1, 1], [([0, 0, 0], 1), ([1, 0, 0], water)]);
+        assert_eq!(11, quads.len());
+    }
+
+    #[test]
+    fn chunks_hide_shared_faces() {
+        let mut tree = Octree::new();
+        tree.insert_leaf(1, [1, 0, 0]);
+        tree.insert_leaf(1, [2, 0, 0]);
+        let materials = MaterialRegistry::default();
+        let left = mesh_chunk(&tree, Aabc::new([0, 0, 0], 2), &materials);
+        let right = mesh_chunk(&tree, Aabc::new([2, 0, 0], 2), &materials);
+        assert_eq!(10, left.triangle_count());
+        assert_eq!(10, right.triangle_count());
+        assert!(left.vertices.iter().all(|v| v.normal != [1.0, 0.0, 0.0]));
+        assert!(mesh_chunk(&tree, Aabc::new([8, 0, 0], 2), &materials).is_empty());
+    }
+
+    #[test]
+    fn chunk_voxels_mesh_like_the_tree() {
+        let mut tree = Octree::new();
+        tree.fill_region(Aabc::new([0, 0, 0], 4), 1);
+        tree.insert_leaf(2, [4, 1, 1]);
+        tree.insert_leaf(3, [1, -1, 2]);
+        tree.insert_leaf(3, [9, 9, 9]);
+        let materials = MaterialRegistry::default();
+        let chunk = Aabc::new([0, 0, 0], 4);
+        let voxels = chunk_voxels(&tree, chunk);
+        assert_eq!(66, voxels.len());
+        let mesh = mesh_voxels(&voxels, chunk, &materials);
+        let expected = mesh_chunk(&tree, chunk, &materials);
+        assert_eq!(expected.vertices, mesh.vertices);
+        assert_eq!(expected.indices, mesh.indices);
+        assert!(chunk_voxels(&tree, Aabc::new([16, 0, 0], 4)).is_empty());
+    }
+
+    #[test]
+    fn chunks_cover_tree() {
+        let mut tree = Octree::new();
+        tree.insert_leaf(1, [0, 0, 0]);
+        tree.insert_leaf(2, [9, 3, 0]);
+        let materials = MaterialRegistry::default();
+        let chunks = mesh_chunks(&tree, 4, &materials);
+        assert_eq!(2, chunks.len());
+        assert!(chunks.iter().all(|(chunk, _)| chunk.size == 4));
+        let triangles: usize = chunks.iter().map(|(_, m)| m.triangle_count()).sum();
+        assert_eq!(24, triangles);
+        assert!(mesh_chunks(&Octree::new(), 4, &materials).is_empty());
+    }
+
+    #[test]
+    fn quads_become_two_triangles() {
+        let quads = quads_of([1; 3], [([0, 0, 0], 3)]);
+        let mesh = Mesh::from_quads(&quads);
+        assert_eq!(24, mesh.vertices.len());
+        assert_eq!(12, mesh.triangle_count());
+        assert_eq!([4, 5, 6, 4, 6, 7], mesh.indices[6..12]);
+        assert!(mesh.vertices.iter().all(|v| v.material == 3));
+    }
+}
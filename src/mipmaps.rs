@@ -0,0 +1,86 @@
+/// Levels of a full mip chain of square images `size` texels across, down to
+/// a single texel.
+pub fn mip_levels(size: u32) -> u32 {
+    32 - size.max(1).leading_zeros()
+}
+
+/// Averages each 2x2 block of texels of `layers` square RGBA8 images `size`
+/// texels across. An odd last row or column is averaged into the block
+/// before it.
+pub fn downsample(size: u32, layers: u32, data: &[u8]) -> Vec<u8> {
+    assert_eq!((size * size * layers * 4) as usize, data.len());
+    let half = (size / 2).max(1);
+    let end = |c: u32| if c + 1 == half { size } else { 2 * c + 2 };
+    let mut out = Vec::with_capacity((half * half * layers * 4) as usize);
+    for layer in data.chunks((size * size * 4) as usize) {
+        for y in 0..half {
+            for x in 0..half {
+                let mut sum = [0u32; 4];
+                let mut count = 0;
+                for sy in 2 * y..end(y) {
+                    for sx in 2 * x..end(x) {
+                        let i = ((sy * size + sx) * 4) as usize;
+                        for (s, &c) in sum.iter_mut().zip(&layer[i..i + 4]) {
+                            *s += c as u32;
+                        }
+                        count += 1;
+                    }
+                }
+                out.extend(sum.map(|s| ((s + count / 2) / count) as u8));
+            }
+        }
+    }
+    out
+}
+
+/// The levels of the mip chain of `layers` square RGBA8 images, the first
+/// being `data` itself.
+pub fn mip_chain(size: u32, layers: u32, data: Vec<u8>) -> Vec<Vec<u8>> {
+    let mut levels = vec![data];
+    let mut size = size;
+    while size > 1 {
+        let next = downsample(size, layers, levels.last().unwrap());
+        levels.push(next);
+        size /= 2;
+    }
+    levels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chain_goes_down_to_one_texel() {
+        assert_eq!(1, mip_levels(1));
+        assert_eq!(5, mip_levels(16));
+        assert_eq!(5, mip_levels(17));
+        let levels = mip_chain(16, 2, vec![0; 16 * 16 * 2 * 4]);
+        let sizes: Vec<_> = levels.iter().map(|l| l.len() / 2 / 4).collect();
+        assert_eq!(vec![256, 64, 16, 4, 1], sizes);
+    }
+
+    #[test]
+    fn blocks_are_averaged_per_layer() {
+        // a checkerboard of black and white, then a plain red layer
+        let mut data: Vec<u8> = (0..16)
+            .flat_map(|i| {
+                let v = if (i % 4 + i / 4) % 2 == 0 { 255 } else { 0 };
+                [v, v, v, 255]
+            })
+            .collect();
+        data.extend([255, 0, 0, 255].repeat(16));
+        let half = downsample(4, 2, &data);
+        assert_eq!(2 * 2 * 2 * 4, half.len());
+        assert_eq!([128, 128, 128, 255], half[..4]);
+        assert_eq!([255, 0, 0, 255], half[16..20]);
+    }
+
+    #[test]
+    fn odd_sizes_keep_the_last_texels() {
+        let mut data = [0, 0, 0, 255].repeat(9);
+        // the corner would be dropped by 2x2 blocks alone
+        data[8 * 4..9 * 4].copy_from_slice(&[90, 90, 90, 255]);
+        assert_eq!(vec![10, 10, 10, 255], downsample(3, 1, &data));
+    }
+}
@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+
+use vecmath::Vector3;
+
+use crate::{octree::VoxelTree, voxel::VoxelData};
+
+// coordinates are offset by this so they fit in 21 unsigned bits per axis
+const OFFSET: i32 = 1 << 20;
+/// Coordinates of the leaves a `MortonOctree` holds are within
+/// `MIN_COORDINATE..=MAX_COORDINATE`.
+pub const MIN_COORDINATE: i32 = -OFFSET;
+pub const MAX_COORDINATE: i32 = OFFSET - 1;
+// serialized child slot of each octant, indexed by x | y << 1 | z << 2. This
+// matches the order used by `Octree`.
+const OCTANT_SLOTS: [usize; 8] = [6, 5, 2, 1, 7, 4, 3, 0];
+
+/// Octree backend that stores leaves in a hash map keyed by their Morton
+/// (Z-order) code. Edits are constant time and don't allocate nodes, the tree
+/// structure is only built when serializing by walking the sorted codes.
+/// Leaves can't be inserted outside [-2^20, 2^20), where there are none.
+pub struct MortonOctree<T: VoxelData> {
+    leaves: HashMap<u64, T>,
+}
+
+impl<T: VoxelData> Default for MortonOctree<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: VoxelData> MortonOctree<T> {
+    pub fn new() -> Self {
+        MortonOctree {
+            leaves: HashMap::new(),
+        }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        MortonOctree {
+            leaves: HashMap::with_capacity(capacity),
+        }
+    }
+
+    // appends the node covering `codes` at `level` (size 2^level) and returns
+    // its index
+    fn serialize_recurse(
+        arr: &mut Vec<i32>,
+        codes: &[(u64, T)],
+        level: u32,
+        parent: usize,
+    ) -> usize {
+        let idx = arr.len();
+        let words = if level == 1 { 1 + 8 * T::WORDS } else { 1 + 8 };
+        arr.resize(idx + words, 0);
+        arr[idx] = parent as i32;
+        let shift = 3 * (level - 1);
+        let mut rest = codes;
+        for octant in 0..8 {
+            let end = rest.partition_point(|(code, _)| (code >> shift) & 7 == octant);
+            let (inside, after) = rest.split_at(end);
+            rest = after;
+            if inside.is_empty() {
+                continue;
+            }
+            if level == 1 {
+                let slot = idx + 1 + OCTANT_SLOTS[octant as usize] * T::WORDS;
+                for (i, word) in inside[0].1.to_gpu_words().as_ref().iter().enumerate() {
+                    arr[slot + i] = *word as i32;
+                }
+            } else {
+                let slot = idx + 1 + OCTANT_SLOTS[octant as usize];
+                arr[slot] = Self::serialize_recurse(arr, inside, level - 1, idx) as i32;
+            }
+        }
+        idx
+    }
+}
+
+impl<T: VoxelData> VoxelTree<T> for MortonOctree<T> {
+    fn insert_leaf(&mut self, data: T, pos: Vector3<i32>) {
+        let code = encode(pos).unwrap_or_else(|| panic!("leaf at {:?} out of range", pos));
+        if self.leaves.insert(code, data).is_some() {
+            panic!("attempted to overwrite leaf at {:?}", pos)
+        }
+    }
+
+    fn remove_leaf(&mut self, pos: Vector3<i32>) {
+        if encode(pos)
+            .and_then(|code| self.leaves.remove(&code))
+            .is_none()
+        {
+            panic!("leaf not found at {:?}", pos)
+        }
+    }
+
+    fn get_leaf(&self, pos: Vector3<i32>) -> Option<T> {
+        encode(pos).and_then(|code| self.leaves.get(&code).copied())
+    }
+
+    fn count_leaves(&self) -> u64 {
+        self.leaves.len() as u64
+    }
+
+    fn serialize(&self) -> Vec<i32> {
+        if self.leaves.is_empty() {
+            return vec![0];
+        }
+        let mut codes: Vec<(u64, T)> = self.leaves.iter().map(|(&c, &d)| (c, d)).collect();
+        codes.sort_unstable_by_key(|&(code, _)| code);
+
+        // the root is the smallest aligned cube containing the first and last
+        // codes, and with them every code in between
+        let differing = 64 - (codes[0].0 ^ codes[codes.len() - 1].0).leading_zeros();
+        let level = ((differing + 2) / 3).max(1);
+        let origin = decode(codes[0].0 & !((1 << (3 * level)) - 1));
+
+        let mut arr = vec![1 << level, origin[0], origin[1], origin[2]];
+        Self::serialize_recurse(&mut arr, &codes, level, 0);
+        arr
+    }
+}
+
+fn spread(v: u64) -> u64 {
+    let mut v = v & 0x1f_ffff;
+    v = (v | v << 32) & 0x1f_0000_0000_ffff;
+    v = (v | v << 16) & 0x1f_0000_ff00_00ff;
+    v = (v | v << 8) & 0x100f_00f0_0f00_f00f;
+    v = (v | v << 4) & 0x10c3_0c30_c30c_30c3;
+    v = (v | v << 2) & 0x1249_2492_4924_9249;
+    v
+}
+
+fn compact(v: u64) -> u64 {
+    let mut v = v & 0x1249_2492_4924_9249;
+    v = (v ^ v >> 2) & 0x10c3_0c30_c30c_30c3;
+    v = (v ^ v >> 4) & 0x100f_00f0_0f00_f00f;
+    v = (v ^ v >> 8) & 0x1f_0000_ff00_00ff;
+    v = (v ^ v >> 16) & 0x1f_0000_0000_ffff;
+    v = (v ^ v >> 32) & 0x1f_ffff;
+    v
+}
+
+/// Interleaves the bits of the offset coordinates, x in the lowest bit. None
+/// for coordinates outside `MIN_COORDINATE..=MAX_COORDINATE`, which would
+/// share codes with others.
+pub fn encode(pos: Vector3<i32>) -> Option<u64> {
+    let mut code = 0;
+    for (i, &p) in pos.iter().enumerate() {
+        if !(MIN_COORDINATE..=MAX_COORDINATE).contains(&p) {
+            return None;
+        }
+        code |= spread((p + OFFSET) as u64) << i;
+    }
+    Some(code)
+}
+
+pub fn decode(code: u64) -> Vector3<i32> {
+    [
+        compact(code) as i32 - OFFSET,
+        compact(code >> 1) as i32 - OFFSET,
+        compact(code >> 2) as i32 - OFFSET,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{octree::Octree, voxel::Voxel, world::scattered_positions};
+
+    // walks a serialized tree and returns every leaf with its value
+    fn collect_leaves(arr: &[i32]) -> HashMap<Vector3<i32>, i32> {
+        fn recurse(
+            arr: &[i32],
+            idx: usize,
+            origin: Vector3<i32>,
+            size: i32,
+            leaves: &mut HashMap<Vector3<i32>, i32>,
+        ) {
+            let half = size / 2;
+            for (octant, &slot) in OCTANT_SLOTS.iter().enumerate() {
+                let value = arr[idx + 1 + slot];
+                if value == 0 {
+                    continue;
+                }
+                let child = [
+                    origin[0] + (octant as i32 & 1) * half,
+                    origin[1] + ((octant as i32 >> 1) & 1) * half,
+                    origin[2] + ((octant as i32 >> 2) & 1) * half,
+                ];
+                if size == 2 {
+                    leaves.insert(child, value);
+                } else {
+                    recurse(arr, value as usize, child, half, leaves);
+                }
+            }
+        }
+        let mut leaves = HashMap::new();
+        if arr[0] != 0 {
+            recurse(arr, 4, [arr[1], arr[2], arr[3]], arr[0], &mut leaves);
+        }
+        leaves
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        for pos in [[0, 0, 0], [-1, 2, -3], [OFFSET - 1, -OFFSET, 12345]] {
+            assert_eq!(pos, decode(encode(pos).unwrap()));
+        }
+    }
+
+    #[test]
+    fn encode_interleaves_axes() {
+        let code = |pos| encode(pos).unwrap();
+        let origin = code([0, 0, 0]);
+        assert_eq!(1, code([1, 0, 0]) - origin);
+        assert_eq!(2, code([0, 1, 0]) - origin);
+        assert_eq!(4, code([0, 0, 1]) - origin);
+    }
+
+    #[test]
+    fn coordinates_out_of_range_have_no_code() {
+        let max = [MAX_COORDINATE; 3];
+        assert_eq!(max, decode(encode(max).unwrap()));
+        assert_eq!(None, encode([MAX_COORDINATE + 1, 0, 0]));
+        assert_eq!(None, encode([0, MIN_COORDINATE - 1, 0]));
+        // past the end would wrap onto the lowest coordinate
+        let mut tree = MortonOctree::new();
+        tree.insert_leaf(1, [MIN_COORDINATE, 0, 0]);
+        assert_eq!(None, tree.get_leaf([MAX_COORDINATE + 1, 0, 0]));
+        assert_eq!(Some(1), tree.get_leaf([MIN_COORDINATE, 0, 0]));
+    }
+
+    #[test]
+    #[should_panic]
+    fn insert_out_of_range_panics() {
+        let mut tree = MortonOctree::new();
+        tree.insert_leaf(1, [0, 0, MAX_COORDINATE + 1]);
+    }
+
+    #[test]
+    fn serialize_empty_tree() {
+        let tree: MortonOctree<i32> = MortonOctree::new();
+        assert_eq!(vec![0], tree.serialize());
+    }
+
+    #[test]
+    fn serialize_size_2_tree() {
+        let mut tree = MortonOctree::new();
+        tree.insert_leaf(3, [0, 0, 0]);
+        tree.insert_leaf(4, [1, 1, 1]);
+        assert_eq!(
+            vec![2, 0, 0, 0, 0, 4, 0, 0, 0, 0, 0, 3, 0],
+            tree.serialize()
+        );
+    }
+
+    #[test]
+    fn serializes_same_leaves_as_octree() {
+        let mut octree = Octree::new();
+        let mut morton = MortonOctree::new();
+        for (i, pos) in scattered_positions(1, 500).into_iter().enumerate() {
+            octree.insert_leaf(i as i32 + 1, pos);
+            morton.insert_leaf(i as i32 + 1, pos);
+        }
+        assert_eq!(
+            collect_leaves(&octree.serialize()),
+            collect_leaves(&morton.serialize())
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn insert_duplicate_leaf_panics() {
+        let mut tree = MortonOctree::new();
+        tree.insert_leaf(1, [0, 0, 0]);
+        tree.insert_leaf(1, [0, 0, 0]);
+    }
+
+    #[test]
+    fn serializes_multi_word_leaves_like_octree() {
+        let mut morton = MortonOctree::new();
+        let mut octree = Octree::new();
+        for (i, pos) in [[0, 0, 0], [1, 0, 1], [0, 1, 1]].into_iter().enumerate() {
+            let voxel = Voxel {
+                orientation: i as u8,
+                ..Voxel::new(i as i32 + 1)
+            };
+            morton.insert_leaf(voxel, pos);
+            octree.insert_leaf(voxel, pos);
+        }
+        assert_eq!(octree.serialize(), morton.serialize());
+    }
+
+    #[test]
+    fn insert_and_remove_leaf() {
+        let mut tree = MortonOctree::new();
+        tree.insert_leaf(1, [4, -2, 7]);
+        assert_eq!(Some(1), tree.get_leaf([4, -2, 7]));
+        tree.remove_leaf([4, -2, 7]);
+        assert_eq!(None, tree.get_leaf([4, -2, 7]));
+        assert_eq!(0, tree.count_leaves());
+    }
+}
@@ -0,0 +1,11 @@
+//! Shared worlds: a server owns the world, generates its chunks and sends
+//! them to the connected viewers, which send their edits to the server to be
+//! passed on to everyone.
+
+pub mod avatars;
+pub mod client;
+pub mod protocol;
+pub mod server;
+
+/// Port the server listens on unless another is given.
+pub const DEFAULT_PORT: u16 = 7878;
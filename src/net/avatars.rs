@@ -0,0 +1,210 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use vecmath::Vector3;
+
+use crate::{
+    entity::{Entity, EntityId, EntityList},
+    labels::Label,
+    materials::MaterialId,
+};
+
+/// Identifies a client of a server for as long as it is connected.
+pub type PlayerId = u64;
+
+/// How often clients send their position. Avatars take this long to move to
+/// a new position, so they keep moving smoothly until the next one arrives.
+pub const UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+// size of an avatar box, with the camera a little below its top
+const AVATAR_SIZE: Vector3<f32> = [0.6, 1.8, 0.6];
+const EYE_HEIGHT: f32 = 1.6;
+// nametags float a little over the top of the avatar
+const NAMETAG_HEIGHT: f32 = 0.5;
+const NAMETAG_COLOR: [f32; 3] = [1.0, 1.0, 0.6];
+
+struct Track {
+    from: Vector3<f32>,
+    to: Vector3<f32>,
+    received: Instant,
+    entity: Option<EntityId>,
+}
+
+impl Track {
+    fn position(&self, now: Instant) -> Vector3<f32> {
+        let t = now.saturating_duration_since(self.received).as_secs_f32()
+            / UPDATE_INTERVAL.as_secs_f32();
+        let t = t.min(1.0);
+        [0, 1, 2].map(|i| self.from[i] + (self.to[i] - self.from[i]) * t)
+    }
+}
+
+/// The other players of a shared world, drawn as boxes with the entities.
+#[derive(Default)]
+pub struct RemotePlayers {
+    players: BTreeMap<PlayerId, Track>,
+    // entities of players that left, removed by `update_entities`
+    left: Vec<EntityId>,
+}
+
+impl RemotePlayers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.players.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.players.is_empty()
+    }
+
+    /// Starts moving a player from where it is now to `position`, or places a
+    /// player seen for the first time there.
+    pub fn moved(&mut self, id: PlayerId, position: Vector3<f32>, now: Instant) {
+        let track = self.players.entry(id).or_insert(Track {
+            from: position,
+            to: position,
+            received: now,
+            entity: None,
+        });
+        track.from = track.position(now);
+        track.to = position;
+        track.received = now;
+    }
+
+    pub fn left(&mut self, id: PlayerId) {
+        if let Some(track) = self.players.remove(&id) {
+            self.left.extend(track.entity);
+        }
+    }
+
+    /// Removes every player, as when the connection to the server is lost.
+    pub fn clear(&mut self) {
+        let ids: Vec<_> = self.players.keys().copied().collect();
+        for id in ids {
+            self.left(id);
+        }
+    }
+
+    /// Where the camera of a player is at `now`.
+    pub fn position(&self, id: PlayerId, now: Instant) -> Option<Vector3<f32>> {
+        self.players.get(&id).map(|track| track.position(now))
+    }
+
+    /// A label over the avatar of each player at `now`, naming it.
+    pub fn nametags(&self, now: Instant) -> Vec<Label> {
+        self.players
+            .iter()
+            .map(|(id, track)| {
+                let eye = track.position(now);
+                Label {
+                    position: [eye[0], eye[1] + NAMETAG_HEIGHT, eye[2]],
+                    text: format!("player {}", id),
+                    color: NAMETAG_COLOR,
+                }
+            })
+            .collect()
+    }
+
+    /// Moves the avatars to where their players are at `now`, adding and
+    /// removing them as players come and go. Each player gets one of
+    /// `materials` by id. Returns whether anything changed.
+    pub fn update_entities(
+        &mut self,
+        entities: &mut EntityList,
+        materials: &[MaterialId],
+        now: Instant,
+    ) -> bool {
+        let mut changed = false;
+        for id in self.left.drain(..) {
+            changed |= entities.remove(id).is_some();
+        }
+        for (id, track) in &mut self.players {
+            let eye = track.position(now);
+            let avatar = Entity {
+                position: [
+                    eye[0] - AVATAR_SIZE[0] / 2.0,
+                    eye[1] - EYE_HEIGHT,
+                    eye[2] - AVATAR_SIZE[2] / 2.0,
+                ],
+                size: AVATAR_SIZE,
+                material: materials[*id as usize % materials.len()],
+            };
+            match track.entity.and_then(|entity| entities.get_mut(entity)) {
+                Some(entity) if *entity == avatar => {}
+                Some(entity) => {
+                    *entity = avatar;
+                    changed = true;
+                }
+                // a full list is tried again on the next update
+                None => {
+                    track.entity = entities.spawn(avatar);
+                    changed |= track.entity.is_some();
+                }
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn players_move_smoothly_to_new_positions() {
+        let start = Instant::now();
+        let mut players = RemotePlayers::new();
+        players.moved(1, [0.0, 0.0, 0.0], start);
+        assert_eq!(Some([0.0; 3]), players.position(1, start + UPDATE_INTERVAL));
+        players.moved(1, [4.0, 0.0, 0.0], start);
+        let half = start + UPDATE_INTERVAL / 2;
+        assert_eq!(Some([2.0, 0.0, 0.0]), players.position(1, half));
+        // a new position midway continues from where the player was drawn
+        players.moved(1, [2.0, 2.0, 0.0], half);
+        assert_eq!(Some([2.0, 0.0, 0.0]), players.position(1, half));
+        assert_eq!(
+            Some([2.0, 2.0, 0.0]),
+            players.position(1, half + UPDATE_INTERVAL * 2)
+        );
+        assert_eq!(None, players.position(2, half));
+    }
+
+    #[test]
+    fn nametags_float_over_players() {
+        let now = Instant::now();
+        let mut players = RemotePlayers::new();
+        players.moved(7, [1.0, 2.0, 3.0], now);
+        let tags = players.nametags(now);
+        assert_eq!(1, tags.len());
+        assert_eq!("player 7", tags[0].text);
+        assert!(tags[0].position[1] > 2.0 + AVATAR_SIZE[1] - EYE_HEIGHT);
+    }
+
+    #[test]
+    fn avatars_follow_players() {
+        let now = Instant::now();
+        let mut players = RemotePlayers::new();
+        let mut entities = EntityList::new();
+        players.moved(0, [1.0, 2.0, 3.0], now);
+        players.moved(1, [0.0; 3], now);
+        assert!(players.update_entities(&mut entities, &[5, 6], now));
+        assert_eq!(2, entities.len());
+        let materials: Vec<_> = entities.iter().map(|(_, e)| e.material).collect();
+        assert_eq!(vec![5, 6], materials);
+        assert!(!players.update_entities(&mut entities, &[5, 6], now));
+
+        players.moved(0, [1.0, 5.0, 3.0], now);
+        players.update_entities(&mut entities, &[5, 6], now + UPDATE_INTERVAL);
+        let (_, moved) = entities.iter().next().unwrap();
+        assert_eq!([0.7, 5.0 - EYE_HEIGHT, 2.7], moved.position);
+
+        players.left(0);
+        assert!(players.update_entities(&mut entities, &[5, 6], now));
+        assert_eq!(1, entities.len());
+        assert_eq!(1, players.len());
+    }
+}
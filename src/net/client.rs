@@ -0,0 +1,192 @@
+use std::{
+    collections::HashSet,
+    io::{self, BufReader},
+    net::{Shutdown, TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+    time::Instant,
+};
+
+use tracing::warn;
+use vecmath::Vector3;
+
+use super::{
+    avatars::{PlayerId, RemotePlayers, UPDATE_INTERVAL},
+    protocol::{self, Message, VoxelEdit, PROTOCOL_VERSION},
+};
+use crate::{
+    io::region::invalid,
+    materials::{MaterialId, MaterialRegistry},
+    octree::Octree,
+    worldgen::ChunkPos,
+};
+
+/// A connection to the server of a shared world. Chunks are requested like
+/// from a `ChunkGenerator` and the edits of every player, this one included,
+/// arrive in the order the server applied them.
+pub struct Client {
+    stream: TcpStream,
+    seed: u64,
+    messages: Receiver<Message>,
+    requested: HashSet<ChunkPos>,
+    edits: Vec<VoxelEdit>,
+    // edits of materials it doesn't have are dropped
+    materials: MaterialRegistry,
+    chat: Vec<(PlayerId, String)>,
+    players: RemotePlayers,
+    last_position: Option<Instant>,
+    connected: bool,
+}
+
+impl Client {
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let mut stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        protocol::write_message(
+            &mut stream,
+            &Message::Hello {
+                version: PROTOCOL_VERSION,
+            },
+        )?;
+        let seed = match protocol::read_message(&mut stream)? {
+            Message::Welcome { seed } => seed,
+            message => {
+                return Err(invalid(&format!(
+                    "expected welcome, got {}",
+                    message.kind()
+                )))
+            }
+        };
+        let (sender, messages) = mpsc::channel();
+        let mut input = BufReader::new(stream.try_clone()?);
+        // ends when the connection is closed
+        thread::spawn(move || {
+            while let Ok(message) = protocol::read_message(&mut input) {
+                if sender.send(message).is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(Client {
+            stream,
+            seed,
+            messages,
+            requested: HashSet::new(),
+            edits: Vec::new(),
+            materials: MaterialRegistry::default(),
+            chat: Vec::new(),
+            players: RemotePlayers::new(),
+            last_position: None,
+            connected: true,
+        })
+    }
+
+    /// Seed the server generates the world from.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Asks the server for a chunk, unless it was asked for before.
+    pub fn request(&mut self, chunk: ChunkPos) {
+        if self.requested.insert(chunk) {
+            self.send(&Message::RequestChunk(chunk));
+        }
+    }
+
+    /// Sends edits made to the local world, which come back from `take_edits`
+    /// once the server applied them.
+    pub fn send_edits(&mut self, edits: Vec<VoxelEdit>) {
+        self.send(&Message::Edits(edits));
+    }
+
+    /// Sends a chat line to every player, this one included.
+    pub fn say(&mut self, text: &str) {
+        self.send(&Message::Say(text.to_string()));
+    }
+
+    /// Tells the other players where the camera is, at most once every
+    /// `UPDATE_INTERVAL`.
+    pub fn send_position(&mut self, position: Vector3<f32>) {
+        let now = Instant::now();
+        if self
+            .last_position
+            .iter()
+            .all(|&last| now - last >= UPDATE_INTERVAL)
+        {
+            self.last_position = Some(now);
+            self.send(&Message::Position(position));
+        }
+    }
+
+    /// The other players, as of the last `poll`.
+    pub fn players(&self) -> &RemotePlayers {
+        &self.players
+    }
+
+    pub fn players_mut(&mut self) -> &mut RemotePlayers {
+        &mut self.players
+    }
+
+    /// Returns the chunks received since the last call. Edits received along
+    /// with them are kept for `take_edits`, which should be applied after the
+    /// chunks.
+    pub fn poll(&mut self) -> Vec<(ChunkPos, Octree<MaterialId>)> {
+        let mut chunks = Vec::new();
+        loop {
+            match self.messages.try_recv() {
+                Ok(Message::Chunk(chunk, voxels)) => {
+                    let mut tree = Octree::new();
+                    for (pos, material) in voxels {
+                        tree.insert_leaf(material, pos);
+                    }
+                    chunks.push((chunk, tree));
+                }
+                Ok(Message::Edits(edits)) => self.edits.extend(edits),
+                Ok(Message::PlayerMoved(id, position)) => {
+                    self.players.moved(id, position, Instant::now())
+                }
+                Ok(Message::PlayerLeft(id)) => self.players.left(id),
+                Ok(Message::Chat(id, text)) => self.chat.push((id, text)),
+                Ok(message) => warn!(kind = message.kind(), "Unexpected message from server"),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.connected = false;
+                    break;
+                }
+            }
+        }
+        chunks
+    }
+
+    /// Returns the edits received by `poll` since the last call, oldest first.
+    pub fn take_edits(&mut self) -> Vec<VoxelEdit> {
+        let mut edits = std::mem::take(&mut self.edits);
+        edits.retain(|edit| edit.has_known_material(&self.materials));
+        edits
+    }
+
+    /// Returns the chat lines received by `poll` since the last call, with
+    /// who typed them.
+    pub fn take_chat(&mut self) -> Vec<(PlayerId, String)> {
+        std::mem::take(&mut self.chat)
+    }
+
+    // a failed send shows up as a disconnect in `poll`
+    fn send(&mut self, message: &Message) {
+        if protocol::write_message(&mut self.stream, message).is_err() {
+            self.connected = false;
+        }
+    }
+}
+
+// the reader thread holds a clone of the stream, which would keep the
+// connection open
+impl Drop for Client {
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
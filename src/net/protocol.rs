@@ -0,0 +1,388 @@
+use std::io::{self, Read, Write};
+
+use vecmath::Vector3;
+
+use super::avatars::PlayerId;
+use crate::{
+    io::region::{self, invalid, ChunkVoxels},
+    materials::{MaterialId, MaterialRegistry},
+    octree::VoxelChange,
+    worldgen::{ChunkPos, CHUNK_SIZE},
+};
+
+/// Bumped whenever a message changes, the server turns away other versions.
+pub const PROTOCOL_VERSION: u32 = 3;
+// larger messages are taken to be garbage
+const MAX_MESSAGE_LEN: u32 = 16 << 20;
+const COMPRESSION_LEVEL: i32 = 1;
+// most bytes a chunk decompresses to, a solid node for every voxel and a
+// split node with its mask above every 8 nodes, see region::encode_chunk
+const MAX_CHUNK_LEN: u64 = {
+    let voxels = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as u64;
+    5 * voxels + 2 * (voxels - 1) / 7
+};
+/// Longest chat line the server passes on, in characters.
+pub const MAX_CHAT_LEN: usize = 256;
+
+/// Sets a voxel to a material, or removes it for None.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VoxelEdit {
+    pub pos: Vector3<i32>,
+    pub material: Option<MaterialId>,
+}
+
+impl VoxelEdit {
+    /// The edit leaving the voxel the way the change did.
+    pub fn from_change(change: &VoxelChange<MaterialId>) -> Self {
+        let (pos, material) = change.result();
+        VoxelEdit { pos, material }
+    }
+
+    /// Whether the edit removes the voxel or sets it to a material of
+    /// `materials`, others would index past the tables built from it.
+    pub fn has_known_material(&self, materials: &MaterialRegistry) -> bool {
+        self.material.map_or(true, |id| materials.get(id).is_some())
+    }
+}
+
+/// Everything sent between a server and its clients. Each message is sent
+/// as its length, a tag and the fields in little endian.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    /// First message of a client.
+    Hello {
+        version: u32,
+    },
+    /// The server's answer to `Hello`.
+    Welcome {
+        seed: u64,
+    },
+    /// Asks the server for a chunk, which it sends when it is generated.
+    RequestChunk(ChunkPos),
+    /// All voxels of a chunk, with the edits made to it so far.
+    Chunk(ChunkPos, ChunkVoxels),
+    /// Edits made by a client, or by any client when sent by the server.
+    Edits(Vec<VoxelEdit>),
+    /// Where a client's camera is, sent a few times a second.
+    Position(Vector3<f32>),
+    /// Where another player is, sent by the server when it moves or when a
+    /// client connects.
+    PlayerMoved(PlayerId, Vector3<f32>),
+    PlayerLeft(PlayerId),
+    /// A chat line typed by a client.
+    Say(String),
+    /// A chat line and who typed it, sent by the server to every client.
+    Chat(PlayerId, String),
+}
+
+impl Message {
+    /// What kind of message this is, to log without its contents.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::Hello { .. } => "hello",
+            Message::Welcome { .. } => "welcome",
+            Message::RequestChunk(_) => "request chunk",
+            Message::Chunk(..) => "chunk",
+            Message::Edits(_) => "edits",
+            Message::Position(_) => "position",
+            Message::PlayerMoved(..) => "player moved",
+            Message::PlayerLeft(_) => "player left",
+            Message::Say(_) => "say",
+            Message::Chat(..) => "chat",
+        }
+    }
+}
+
+const HELLO: u8 = 0;
+const WELCOME: u8 = 1;
+const REQUEST_CHUNK: u8 = 2;
+const CHUNK: u8 = 3;
+const EDITS: u8 = 4;
+const POSITION: u8 = 5;
+const PLAYER_MOVED: u8 = 6;
+const PLAYER_LEFT: u8 = 7;
+const SAY: u8 = 8;
+const CHAT: u8 = 9;
+// the messages a client sends
+const CLIENT_TAGS: [u8; 5] = [HELLO, REQUEST_CHUNK, EDITS, POSITION, SAY];
+
+pub fn write_message<W: Write>(out: &mut W, message: &Message) -> io::Result<()> {
+    let mut body = Vec::new();
+    match message {
+        Message::Hello { version } => {
+            body.push(HELLO);
+            body.extend_from_slice(&version.to_le_bytes());
+        }
+        Message::Welcome { seed } => {
+            body.push(WELCOME);
+            body.extend_from_slice(&seed.to_le_bytes());
+        }
+        Message::RequestChunk(chunk) => {
+            body.push(REQUEST_CHUNK);
+            write_vec3(&mut body, *chunk);
+        }
+        Message::Chunk(chunk, voxels) => {
+            body.push(CHUNK);
+            write_vec3(&mut body, *chunk);
+            let encoded = region::encode_chunk(voxels, *chunk);
+            body.extend(zstd::encode_all(&encoded[..], COMPRESSION_LEVEL)?);
+        }
+        Message::Edits(edits) => {
+            body.push(EDITS);
+            body.extend_from_slice(&(edits.len() as u32).to_le_bytes());
+            for edit in edits {
+                write_vec3(&mut body, edit.pos);
+                match edit.material {
+                    Some(material) => {
+                        body.push(1);
+                        body.extend_from_slice(&material.to_le_bytes());
+                    }
+                    None => body.push(0),
+                }
+            }
+        }
+        Message::Position(position) => {
+            body.push(POSITION);
+            write_position(&mut body, *position);
+        }
+        Message::PlayerMoved(id, position) => {
+            body.push(PLAYER_MOVED);
+            body.extend_from_slice(&id.to_le_bytes());
+            write_position(&mut body, *position);
+        }
+        Message::PlayerLeft(id) => {
+            body.push(PLAYER_LEFT);
+            body.extend_from_slice(&id.to_le_bytes());
+        }
+        Message::Say(text) => {
+            body.push(SAY);
+            body.extend_from_slice(text.as_bytes());
+        }
+        Message::Chat(id, text) => {
+            body.push(CHAT);
+            body.extend_from_slice(&id.to_le_bytes());
+            body.extend_from_slice(text.as_bytes());
+        }
+    }
+    out.write_all(&(body.len() as u32).to_le_bytes())?;
+    out.write_all(&body)?;
+    out.flush()
+}
+
+/// Blocks until a whole message has been read.
+pub fn read_message<R: Read>(input: &mut R) -> io::Result<Message> {
+    let body = read_body(input)?;
+    parse(&body)
+}
+
+/// Like `read_message`, but turns away the messages only the server sends
+/// before decoding them.
+pub fn read_client_message<R: Read>(input: &mut R) -> io::Result<Message> {
+    let body = read_body(input)?;
+    if !CLIENT_TAGS.contains(&body[0]) {
+        return Err(invalid(&format!("tag {} isn't sent by clients", body[0])));
+    }
+    parse(&body)
+}
+
+fn read_body<R: Read>(input: &mut R) -> io::Result<Vec<u8>> {
+    let len = read_u32(input)?;
+    if len == 0 || len > MAX_MESSAGE_LEN {
+        return Err(invalid(&format!("bad message length {}", len)));
+    }
+    let mut body = vec![0; len as usize];
+    input.read_exact(&mut body)?;
+    Ok(body)
+}
+
+fn parse(body: &[u8]) -> io::Result<Message> {
+    let mut rest = &body[1..];
+    let message = match body[0] {
+        HELLO => Message::Hello {
+            version: read_u32(&mut rest)?,
+        },
+        WELCOME => Message::Welcome {
+            seed: read_u64(&mut rest)?,
+        },
+        REQUEST_CHUNK => Message::RequestChunk(read_vec3(&mut rest)?),
+        CHUNK => {
+            let chunk = read_vec3(&mut rest)?;
+            let encoded = decompress_chunk(rest)?;
+            rest = &[];
+            Message::Chunk(chunk, region::decode_chunk(&encoded, chunk)?)
+        }
+        EDITS => {
+            let count = read_u32(&mut rest)?;
+            // each edit takes at least 13 bytes
+            if count as usize > rest.len() / 13 {
+                return Err(invalid("too many edits"));
+            }
+            let mut edits = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                let pos = read_vec3(&mut rest)?;
+                let mut present = [0];
+                rest.read_exact(&mut present)?;
+                let material = match present[0] {
+                    0 => None,
+                    _ => Some(read_u32(&mut rest)? as MaterialId),
+                };
+                edits.push(VoxelEdit { pos, material });
+            }
+            Message::Edits(edits)
+        }
+        POSITION => Message::Position(read_position(&mut rest)?),
+        PLAYER_MOVED => Message::PlayerMoved(read_u64(&mut rest)?, read_position(&mut rest)?),
+        PLAYER_LEFT => Message::PlayerLeft(read_u64(&mut rest)?),
+        SAY => Message::Say(read_text(&mut rest)?),
+        CHAT => Message::Chat(read_u64(&mut rest)?, read_text(&mut rest)?),
+        tag => return Err(invalid(&format!("bad message tag {}", tag))),
+    };
+    if !rest.is_empty() {
+        return Err(invalid("trailing bytes after message"));
+    }
+    Ok(message)
+}
+
+// stops decompressing past the size of the largest chunk, rather than
+// filling memory with what a few bytes of garbage can expand to
+fn decompress_chunk(compressed: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoded = Vec::new();
+    zstd::stream::read::Decoder::new(compressed)?
+        .take(MAX_CHUNK_LEN + 1)
+        .read_to_end(&mut encoded)?;
+    if encoded.len() as u64 > MAX_CHUNK_LEN {
+        return Err(invalid("chunk decompresses to too much"));
+    }
+    Ok(encoded)
+}
+
+fn write_vec3(out: &mut Vec<u8>, v: Vector3<i32>) {
+    for c in v {
+        out.extend_from_slice(&c.to_le_bytes());
+    }
+}
+
+fn write_position(out: &mut Vec<u8>, v: Vector3<f32>) {
+    for c in v {
+        out.extend_from_slice(&c.to_le_bytes());
+    }
+}
+
+fn read_u64<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_position<R: Read>(input: &mut R) -> io::Result<Vector3<f32>> {
+    Ok([
+        f32::from_bits(read_u32(input)?),
+        f32::from_bits(read_u32(input)?),
+        f32::from_bits(read_u32(input)?),
+    ])
+}
+
+fn read_u32<R: Read>(input: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+// the text takes up the rest of the message
+fn read_text(input: &mut &[u8]) -> io::Result<String> {
+    let text = String::from_utf8(input.to_vec()).map_err(|_| invalid("text isn't UTF-8"))?;
+    *input = &[];
+    Ok(text)
+}
+
+fn read_vec3<R: Read>(input: &mut R) -> io::Result<Vector3<i32>> {
+    Ok([
+        read_u32(input)? as i32,
+        read_u32(input)? as i32,
+        read_u32(input)? as i32,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(message: Message) {
+        let mut bytes = Vec::new();
+        write_message(&mut bytes, &message).unwrap();
+        let mut input = &bytes[..];
+        assert_eq!(message, read_message(&mut input).unwrap());
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        round_trip(Message::Hello {
+            version: PROTOCOL_VERSION,
+        });
+        round_trip(Message::Welcome { seed: u64::MAX });
+        round_trip(Message::RequestChunk([-1, 2, -3]));
+        round_trip(Message::Chunk([0, -1, 0], vec![([3, -2, 1], 4)]));
+        round_trip(Message::Edits(vec![
+            VoxelEdit {
+                pos: [1, 2, 3],
+                material: Some(-5),
+            },
+            VoxelEdit {
+                pos: [-7, 0, 0],
+                material: None,
+            },
+        ]));
+        round_trip(Message::Position([0.5, -3.0, 1e6]));
+        round_trip(Message::PlayerMoved(3, [1.0, 2.0, 3.0]));
+        round_trip(Message::PlayerLeft(u64::MAX));
+        round_trip(Message::Say("hello there".to_string()));
+        round_trip(Message::Chat(2, String::new()));
+    }
+
+    #[test]
+    fn garbage_is_an_error() {
+        assert!(read_message(&mut &[0u8, 0, 0, 0][..]).is_err());
+        assert!(read_message(&mut &[1u8, 0, 0, 0, 9][..]).is_err());
+        // claims more edits than it holds
+        assert!(read_message(&mut &[5u8, 0, 0, 0, EDITS, 255, 255, 255, 255][..]).is_err());
+        // cut off
+        assert!(read_message(&mut &[5u8, 0, 0, 0, HELLO, 1][..]).is_err());
+        assert!(read_message(&mut &[2u8, 0, 0, 0, SAY, 0xff][..]).is_err());
+    }
+
+    #[test]
+    fn clients_cannot_send_chunks() {
+        let mut bytes = Vec::new();
+        write_message(&mut bytes, &Message::Chunk([0; 3], Vec::new())).unwrap();
+        assert!(read_client_message(&mut &bytes[..]).is_err());
+        let mut bytes = Vec::new();
+        write_message(&mut bytes, &Message::Say("hi".to_string())).unwrap();
+        assert!(read_client_message(&mut &bytes[..]).is_ok());
+    }
+
+    #[test]
+    fn chunks_decompress_to_a_bounded_size() {
+        let voxels: Vec<_> = (0..CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE)
+            .map(|i| ([i % 16, i / 16 % 16, i / 256], i % 2 + 1))
+            .collect();
+        let encoded = region::encode_chunk(&voxels, [0; 3]);
+        assert!(encoded.len() as u64 <= MAX_CHUNK_LEN);
+        let mut bytes = Vec::new();
+        write_message(&mut bytes, &Message::Chunk([0; 3], voxels.clone())).unwrap();
+        match read_message(&mut &bytes[..]).unwrap() {
+            Message::Chunk(_, read) => assert_eq!(voxels.len(), read.len()),
+            message => panic!("got {}", message.kind()),
+        }
+        let bomb = zstd::encode_all(&vec![0; MAX_CHUNK_LEN as usize + 1][..], 1).unwrap();
+        assert!(decompress_chunk(&bomb).is_err());
+    }
+
+    #[test]
+    fn edits_from_changes() {
+        let change = VoxelChange::Changed([1, 0, 0], 2, 3);
+        assert_eq!(Some(3), VoxelEdit::from_change(&change).material);
+        let change = VoxelChange::Removed([1, 0, 0], 2);
+        assert_eq!(None, VoxelEdit::from_change(&change).material);
+    }
+}
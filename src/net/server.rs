@@ -0,0 +1,477 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, BufReader},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError},
+    thread,
+    time::Duration,
+};
+
+use tracing::{info, warn};
+use vecmath::Vector3;
+
+use super::{
+    avatars::PlayerId,
+    protocol::{self, Message, MAX_CHAT_LEN, PROTOCOL_VERSION},
+    DEFAULT_PORT,
+};
+use crate::{
+    aabc::Aabc,
+    io::region::invalid,
+    materials::MaterialRegistry,
+    world::World,
+    worldgen::{ChunkGenerator, ChunkPos, CHUNK_SIZE, MAX_COORDINATE},
+};
+
+// how long a client has to say hello before it's turned away
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+// how long writing to a client can block before it's taken to be gone
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+// messages waiting to be written to a client, which is dropped when it falls
+// further behind than that
+const OUTBOX_LEN: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ServeConfig {
+    pub port: u16,
+    /// Seed of the generated world, random when not given.
+    pub seed: Option<u64>,
+}
+
+impl Default for ServeConfig {
+    fn default() -> Self {
+        ServeConfig {
+            port: DEFAULT_PORT,
+            seed: None,
+        }
+    }
+}
+
+enum Event {
+    Connected(PlayerId, Connection),
+    Received(PlayerId, Message),
+    Disconnected(PlayerId),
+}
+
+// a client's stream and the queue of its writer thread, so a slow client
+// doesn't hold up the others
+struct Connection {
+    stream: TcpStream,
+    outbox: SyncSender<Message>,
+}
+
+/// Owns the shared world. Chunks are generated when a client first asks for
+/// them, and edits are applied in the order they arrive and then sent to
+/// every client, the one that made them included, so all copies of the world
+/// end up the same.
+pub struct Server {
+    addr: SocketAddr,
+    world: World,
+    // what the clients use, edits with other materials are dropped
+    materials: MaterialRegistry,
+    generator: ChunkGenerator,
+    generated: HashSet<ChunkPos>,
+    // clients waiting for chunks being generated
+    waiting: HashMap<ChunkPos, Vec<PlayerId>>,
+    clients: HashMap<PlayerId, Connection>,
+    // last position each client sent
+    positions: HashMap<PlayerId, Vector3<f32>>,
+    events: Receiver<Event>,
+}
+
+impl Server {
+    /// Starts accepting clients on a background thread. Each client gets a
+    /// thread greeting it and then reading its messages, which are handled
+    /// by `step`, and one writing what's sent to it.
+    pub fn bind<A: ToSocketAddrs>(addr: A, generator: ChunkGenerator) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let (events, receiver) = mpsc::channel();
+        let seed = generator.seed();
+        thread::spawn(move || {
+            for (id, stream) in listener.incoming().enumerate() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("Could not accept client: {}", e);
+                        continue;
+                    }
+                };
+                let events = events.clone();
+                thread::spawn(move || {
+                    if let Err(e) = accept(id as PlayerId, stream, seed, events) {
+                        warn!(id, "Could not accept client: {}", e)
+                    }
+                });
+            }
+        });
+        Ok(Server {
+            addr,
+            world: World::new(),
+            materials: MaterialRegistry::default(),
+            generator,
+            generated: HashSet::new(),
+            waiting: HashMap::new(),
+            clients: HashMap::new(),
+            positions: HashMap::new(),
+            events: receiver,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn run(mut self) -> ! {
+        loop {
+            self.step(Duration::from_millis(5));
+        }
+    }
+
+    /// Handles what the clients sent, waiting up to `timeout` for something
+    /// to arrive, and sends the chunks finished since the last step.
+    pub fn step(&mut self, timeout: Duration) {
+        match self.events.recv_timeout(timeout) {
+            Ok(event) => self.handle(event),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => panic!("server stopped accepting clients"),
+        }
+        while let Ok(event) = self.events.try_recv() {
+            self.handle(event);
+        }
+        for (chunk, tree) in self.generator.poll() {
+            self.world.insert_chunk(&tree);
+            self.generated.insert(chunk);
+            for client in self.waiting.remove(&chunk).unwrap_or_default() {
+                self.send_chunk(client, chunk);
+            }
+        }
+    }
+
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::Connected(id, connection) => {
+                info!(id, addr = ?connection.stream.peer_addr(), "Client connected");
+                self.clients.insert(id, connection);
+                let others: Vec<_> = self.positions.iter().map(|(&o, &p)| (o, p)).collect();
+                for (other, position) in others {
+                    self.send(id, &Message::PlayerMoved(other, position));
+                }
+            }
+            Event::Received(id, Message::RequestChunk(chunk)) if in_range(chunk, CHUNK_SIZE) => {
+                if self.generated.contains(&chunk) {
+                    self.send_chunk(id, chunk);
+                } else {
+                    self.waiting.entry(chunk).or_default().push(id);
+                    self.generator.request(chunk);
+                }
+            }
+            Event::Received(_, Message::Edits(edits)) => {
+                let edits: Vec<_> = edits
+                    .into_iter()
+                    .filter(|edit| {
+                        in_range(edit.pos, 1) && edit.has_known_material(&self.materials)
+                    })
+                    .collect();
+                let changes: Vec<_> = edits.iter().map(|e| (e.pos, e.material)).collect();
+                self.world.apply_remote(&changes);
+                let ids: Vec<_> = self.clients.keys().copied().collect();
+                let message = Message::Edits(edits);
+                for id in ids {
+                    self.send(id, &message);
+                }
+            }
+            Event::Received(id, Message::Position(position)) => {
+                self.positions.insert(id, position);
+                self.send_to_others(id, &Message::PlayerMoved(id, position));
+            }
+            Event::Received(id, Message::Say(text)) => {
+                let text: String = text.chars().take(MAX_CHAT_LEN).collect();
+                info!(id, "Chat: {}", text);
+                let ids: Vec<_> = self.clients.keys().copied().collect();
+                let message = Message::Chat(id, text);
+                for other in ids {
+                    self.send(other, &message);
+                }
+            }
+            Event::Received(id, message) => {
+                let kind = message.kind();
+                warn!(id, kind, "Unexpected message, disconnecting the client");
+                self.disconnect(id);
+            }
+            Event::Disconnected(id) => self.disconnect(id),
+        }
+    }
+
+    fn send_chunk(&mut self, id: PlayerId, chunk: ChunkPos) {
+        let region = Aabc::new(chunk.map(|c| c * CHUNK_SIZE), CHUNK_SIZE as u32);
+        let voxels = self.world.tree().leaves_in(region);
+        self.send(id, &Message::Chunk(chunk, voxels));
+    }
+
+    // a client that can't be written to, or that's too far behind, is
+    // dropped
+    fn send(&mut self, id: PlayerId, message: &Message) {
+        let sent = match self.clients.get(&id) {
+            Some(connection) => connection.outbox.try_send(message.clone()),
+            None => return,
+        };
+        match sent {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                warn!(id, "Client fell too far behind, disconnecting it");
+                self.disconnect(id);
+            }
+            Err(TrySendError::Disconnected(_)) => self.disconnect(id),
+        }
+    }
+
+    fn send_to_others(&mut self, id: PlayerId, message: &Message) {
+        let others: Vec<_> = self.clients.keys().copied().filter(|&o| o != id).collect();
+        for other in others {
+            self.send(other, message);
+        }
+    }
+
+    fn disconnect(&mut self, id: PlayerId) {
+        if let Some(connection) = self.clients.remove(&id) {
+            let _ = connection.stream.shutdown(Shutdown::Both);
+            info!(id, "Client disconnected");
+            if self.positions.remove(&id).is_some() {
+                self.send_to_others(id, &Message::PlayerLeft(id));
+            }
+        }
+    }
+}
+
+// whether a position in units of `scale` voxels is inside the coordinates
+// the world is generated in
+fn in_range(pos: [i32; 3], scale: i32) -> bool {
+    let limit = MAX_COORDINATE / scale;
+    pos.iter().all(|c| (-limit..limit).contains(c))
+}
+
+// greets the client, then starts writing what's sent to it and goes on to
+// read its messages
+fn accept(id: PlayerId, mut stream: TcpStream, seed: u64, events: Sender<Event>) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    match protocol::read_client_message(&mut stream)? {
+        Message::Hello {
+            version: PROTOCOL_VERSION,
+        } => {}
+        Message::Hello { version } => {
+            return Err(invalid(&format!(
+                "client version {} isn't {}",
+                version, PROTOCOL_VERSION
+            )))
+        }
+        message => {
+            return Err(invalid(&format!("expected hello, got {}", message.kind())));
+        }
+    }
+    protocol::write_message(&mut stream, &Message::Welcome { seed })?;
+    stream.set_read_timeout(None)?;
+
+    let (outbox, queued) = mpsc::sync_channel::<Message>(OUTBOX_LEN);
+    let mut output = stream.try_clone()?;
+    thread::spawn(move || {
+        for message in queued {
+            if protocol::write_message(&mut output, &message).is_err() {
+                // the reader sees the connection closed and reports it
+                let _ = output.shutdown(Shutdown::Both);
+                return;
+            }
+        }
+    });
+    let mut input = BufReader::new(stream.try_clone()?);
+    let _ = events.send(Event::Connected(id, Connection { stream, outbox }));
+    while let Ok(message) = protocol::read_client_message(&mut input) {
+        if events.send(Event::Received(id, message)).is_err() {
+            return Ok(());
+        }
+    }
+    let _ = events.send(Event::Disconnected(id));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::{
+        materials::MaterialId,
+        net::{client::Client, protocol::VoxelEdit},
+        worldgen::{caves::CaveConfig, tests::PALETTE},
+    };
+
+    fn server() -> Server {
+        let generator = ChunkGenerator::new(5, PALETTE, CaveConfig::default(), None, 1);
+        Server::bind("127.0.0.1:0", generator).unwrap()
+    }
+
+    // steps the server until `done` or a few seconds passed
+    fn step_until(server: &mut Server, mut done: impl FnMut() -> bool) {
+        let start = Instant::now();
+        while !done() {
+            assert!(start.elapsed() < Duration::from_secs(10), "timed out");
+            server.step(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn client_receives_generated_chunks() {
+        let mut server = server();
+        let mut client = Client::connect(server.local_addr()).unwrap();
+        assert_eq!(5, client.seed());
+        client.request([0, -1, 0]);
+        let mut chunks = Vec::new();
+        step_until(&mut server, || {
+            chunks.extend(client.poll());
+            !chunks.is_empty()
+        });
+        let (chunk, tree) = &chunks[0];
+        assert_eq!([0, -1, 0], *chunk);
+        let region = Aabc::new([0, -CHUNK_SIZE, 0], CHUNK_SIZE as u32);
+        assert_eq!(server.world().tree().leaves_in(region), tree.leaves());
+        assert!(tree.count_leaves() > 0);
+    }
+
+    #[test]
+    fn edits_reach_every_client() {
+        let mut server = server();
+        let mut a = Client::connect(server.local_addr()).unwrap();
+        let mut b = Client::connect(server.local_addr()).unwrap();
+        // both are connected once the server has sent them a chunk
+        a.request([0, 0, 0]);
+        b.request([0, 0, 0]);
+        let (mut a_chunks, mut b_chunks) = (0, 0);
+        step_until(&mut server, || {
+            a_chunks += a.poll().len();
+            b_chunks += b.poll().len();
+            a_chunks + b_chunks == 2
+        });
+
+        let edit = VoxelEdit {
+            pos: [1, 40, 2],
+            material: Some(7),
+        };
+        a.send_edits(vec![edit]);
+        let (mut a_edits, mut b_edits) = (Vec::new(), Vec::new());
+        step_until(&mut server, || {
+            a.poll();
+            b.poll();
+            a_edits.extend(a.take_edits());
+            b_edits.extend(b.take_edits());
+            !a_edits.is_empty() && !b_edits.is_empty()
+        });
+        assert_eq!(vec![edit], a_edits);
+        assert_eq!(vec![edit], b_edits);
+        assert_eq!(Some(7), server.world().get([1, 40, 2]));
+    }
+
+    #[test]
+    fn edits_of_unknown_materials_are_dropped() {
+        let mut server = server();
+        let mut a = Client::connect(server.local_addr()).unwrap();
+        a.request([0, 0, 0]);
+        step_until(&mut server, || !a.poll().is_empty());
+
+        let len = MaterialRegistry::default().len() as MaterialId;
+        let bad = [-5, 0, len].map(|id| VoxelEdit {
+            pos: [id, 40, 0],
+            material: Some(id),
+        });
+        let good = VoxelEdit {
+            pos: [1, 40, 2],
+            material: Some(len - 1),
+        };
+        a.send_edits([&bad[..], &[good]].concat());
+        let mut edits = Vec::new();
+        step_until(&mut server, || {
+            a.poll();
+            edits.extend(a.take_edits());
+            !edits.is_empty()
+        });
+        assert_eq!(vec![good], edits);
+        for edit in bad {
+            assert_eq!(None, server.world().get(edit.pos));
+        }
+    }
+
+    #[test]
+    fn silent_clients_dont_hold_up_others() {
+        let mut server = server();
+        let _silent = TcpStream::connect(server.local_addr()).unwrap();
+        let mut client = Client::connect(server.local_addr()).unwrap();
+        client.request([0, 0, 0]);
+        step_until(&mut server, || !client.poll().is_empty());
+    }
+
+    #[test]
+    fn players_see_each_other() {
+        let mut server = server();
+        let mut a = Client::connect(server.local_addr()).unwrap();
+        a.send_position([1.0, 2.0, 3.0]);
+        let start = Instant::now();
+        while server.positions.is_empty() {
+            assert!(start.elapsed() < Duration::from_secs(10), "timed out");
+            server.step(Duration::from_millis(1));
+        }
+        // joining later still shows the players already there
+        let mut b = Client::connect(server.local_addr()).unwrap();
+        step_until(&mut server, || {
+            b.poll();
+            b.players().len() == 1
+        });
+        drop(a);
+        step_until(&mut server, || {
+            b.poll();
+            b.players().is_empty()
+        });
+    }
+
+    #[test]
+    fn chat_reaches_every_client() {
+        let mut server = server();
+        let mut a = Client::connect(server.local_addr()).unwrap();
+        let mut b = Client::connect(server.local_addr()).unwrap();
+        a.request([0, 0, 0]);
+        b.request([0, 0, 0]);
+        let (mut a_chunks, mut b_chunks) = (0, 0);
+        step_until(&mut server, || {
+            a_chunks += a.poll().len();
+            b_chunks += b.poll().len();
+            a_chunks + b_chunks == 2
+        });
+
+        a.say(&"a".repeat(MAX_CHAT_LEN + 1));
+        let (mut a_chat, mut b_chat) = (Vec::new(), Vec::new());
+        step_until(&mut server, || {
+            a.poll();
+            b.poll();
+            a_chat.extend(a.take_chat());
+            b_chat.extend(b.take_chat());
+            !a_chat.is_empty() && !b_chat.is_empty()
+        });
+        assert_eq!(a_chat, b_chat);
+        assert_eq!(MAX_CHAT_LEN, b_chat[0].1.len());
+    }
+
+    #[test]
+    fn out_of_range_requests_are_ignored() {
+        assert!(in_range([0, -1, 0], 1));
+        assert!(!in_range([MAX_COORDINATE, 0, 0], 1));
+        assert!(in_range(
+            [MAX_COORDINATE / CHUNK_SIZE - 1, 0, 0],
+            CHUNK_SIZE
+        ));
+        assert!(!in_range([0, 0, MAX_COORDINATE / CHUNK_SIZE], CHUNK_SIZE));
+    }
+}
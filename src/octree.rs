@@ -1,64 +1,129 @@
+use std::{collections::HashSet, mem};
+
 use vecmath::{vec3_add, Vector3};
 
-use crate::aabc::Aabc;
+use crate::{aabc::Aabc, camera::Frustum, voxel::VoxelData};
 
-pub struct Octree<T: Copy + Into<i32>> {
-    n_leaves: u32,
-    root: Option<Box<Node<T>>>,
+/// Common interface of the octree backends. Every backend serializes into the
+/// format traversed by the ray tracing shader: the root's size and origin
+/// followed by the nodes. Each node is the index of its parent, 0 for the root,
+/// and 8 slots which hold child indices or, in nodes of size 2, the
+/// `VoxelData::WORDS` words of each leaf.
+pub trait VoxelTree<T> {
+    fn insert_leaf(&mut self, data: T, pos: Vector3<i32>);
+    fn remove_leaf(&mut self, pos: Vector3<i32>);
+    fn get_leaf(&self, pos: Vector3<i32>) -> Option<T>;
+    fn count_leaves(&self) -> u64;
+    fn serialize(&self) -> Vec<i32>;
 }
 
-#[derive(PartialEq, Debug)]
-struct Node<T: Copy + Into<i32>> {
-    data: NodeData<T>,
-    aabc: Aabc,
-}
+// index of a node in the arena
+type NodeId = u32;
 
-#[derive(PartialEq, Debug)]
-enum NodeData<T: Copy + Into<i32>> {
-    Children([Option<Box<Node<T>>>; 8]),
-    Value(T),
+/// A single voxel edit, as produced by `Octree::diff`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VoxelChange<T> {
+    Added(Vector3<i32>, T),
+    Removed(Vector3<i32>, T),
+    /// A voxel whose value changed from the first to the second.
+    Changed(Vector3<i32>, T, T),
 }
 
-impl<T: Copy + Into<i32>> Clone for Box<Node<T>> {
-    fn clone(&self) -> Self {
-        match &self.data {
-            NodeData::Children(children) => {
-                let mut new_children = [None, None, None, None, None, None, None, None];
-                for i in 0..children.len() {
-                    match &children[i] {
-                        Some(child) => new_children[i] = Some(child.clone()),
-                        _ => (),
-                    }
-                }
-                Box::new(Node {
-                    data: NodeData::Children(new_children),
-                    aabc: self.aabc,
-                })
+impl<T: Copy> VoxelChange<T> {
+    /// The change that undoes this one.
+    pub fn inverse(&self) -> VoxelChange<T> {
+        match *self {
+            VoxelChange::Added(pos, value) => VoxelChange::Removed(pos, value),
+            VoxelChange::Removed(pos, value) => VoxelChange::Added(pos, value),
+            VoxelChange::Changed(pos, old, new) => VoxelChange::Changed(pos, new, old),
+        }
+    }
+
+    /// The position changed and the value left there, None if removed.
+    pub fn result(&self) -> (Vector3<i32>, Option<T>) {
+        match *self {
+            VoxelChange::Added(pos, value) | VoxelChange::Changed(pos, _, value) => {
+                (pos, Some(value))
             }
-            NodeData::Value(v) => Box::new(Node {
-                data: NodeData::Value(*v),
-                aabc: self.aabc,
-            }),
+            VoxelChange::Removed(pos, _) => (pos, None),
         }
     }
 }
 
-impl<T: Copy + Into<i32>> Node<T> {
-    fn empty(origin: Vector3<i32>, size: u32) -> Box<Node<T>> {
-        Box::new(Node {
-            data: NodeData::Children([None, None, None, None, None, None, None, None]),
+/// How `Octree::merge` resolves voxels present in both trees.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergePolicy {
+    KeepExisting,
+    Overwrite,
+}
+
+/// Where the words of a node's subtree are in a serialized tree, see
+/// `Octree::serialized_nodes`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SerializedNode {
+    pub aabc: Aabc,
+    pub start: usize,
+    pub len: usize,
+    /// Start of the parent, 0 for the root.
+    pub parent: usize,
+    /// The word of the parent pointing to the node, None for the root.
+    pub pointer: Option<usize>,
+}
+
+/// Shape and memory usage of an `Octree`, see `Octree::stats`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OctreeStats {
+    /// Nodes reachable from the root.
+    pub nodes: usize,
+    pub leaves: u64,
+    /// Number of levels, 0 for an empty tree.
+    pub depth: u32,
+    /// Fraction of the root's volume that holds voxels.
+    pub occupancy: f64,
+    /// Bytes allocated for the arena, including free slots.
+    pub memory_bytes: usize,
+}
+
+/// Pointer-based octree. The nodes live in an arena and refer to their
+/// children by index, so clones are a single copy and dropping a large tree
+/// doesn't recurse.
+#[derive(Clone)]
+pub struct Octree<T: VoxelData> {
+    n_leaves: u64,
+    root: Option<NodeId>,
+    nodes: Vec<Node<T>>,
+    // slots of removed nodes, reused before the arena grows
+    free: Vec<NodeId>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+struct Node<T: VoxelData> {
+    data: NodeData<T>,
+    aabc: Aabc,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+enum NodeData<T: VoxelData> {
+    Children([Option<NodeId>; 8]),
+    Value(T),
+}
+
+impl<T: VoxelData> Node<T> {
+    fn empty(origin: Vector3<i32>, size: u32) -> Node<T> {
+        Node {
+            data: NodeData::Children([None; 8]),
             aabc: Aabc { origin, size },
-        })
+        }
     }
 
-    pub fn new_leaf(data: T, pos: Vector3<i32>) -> Box<Node<T>> {
-        Box::new(Node {
+    pub fn new_leaf(data: T, pos: Vector3<i32>) -> Node<T> {
+        Node {
             data: NodeData::Value(data),
             aabc: Aabc {
                 origin: pos,
                 size: 1,
             },
-        })
+        }
     }
 
     fn get_octant_idx(&self, target: Aabc) -> usize {
@@ -97,6 +162,11 @@ impl<T: Copy + Into<i32>> Node<T> {
         }
     }
 
+    // inverse of get_octant_idx
+    fn child_aabc(&self, idx: usize) -> Aabc {
+        self.aabc.octant(idx)
+    }
+
     // returns the number of children, and if there was only 1, its index
     fn count_children(&self) -> (u32, Option<usize>) {
         let mut idx = None;
@@ -120,67 +190,107 @@ impl<T: Copy + Into<i32>> Node<T> {
             }
         }
     }
+}
 
-    fn remove_child(&mut self, target: Aabc) -> bool {
-        let idx = self.get_octant_idx(target);
-        match &mut self.data {
-            NodeData::Children(ref mut children) => match children[idx] {
-                Some(ref mut node) if node.aabc == target => {
-                    children[idx] = None;
-                    self.count_children().0 == 0
-                }
-                Some(ref mut node) => {
-                    let remove_node = node.remove_child(target);
-                    if remove_node {
-                        children[idx] = None;
-                    }
-                    self.count_children().0 == 0
-                }
+impl<T: VoxelData> Octree<T> {
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    /// Creates an empty tree with room for `capacity` nodes before the arena
+    /// reallocates. A tree of n scattered leaves needs a bit over n nodes.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Octree {
+            n_leaves: 0,
+            root: None,
+            nodes: Vec::with_capacity(capacity),
+            free: Vec::new(),
+        }
+    }
+
+    fn node(&self, id: NodeId) -> &Node<T> {
+        &self.nodes[id as usize]
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> &mut Node<T> {
+        &mut self.nodes[id as usize]
+    }
+
+    fn alloc(&mut self, node: Node<T>) -> NodeId {
+        match self.free.pop() {
+            Some(id) => {
+                self.nodes[id as usize] = node;
+                id
+            }
+            None => {
+                self.nodes.push(node);
+                (self.nodes.len() - 1) as NodeId
+            }
+        }
+    }
+
+    fn dealloc(&mut self, id: NodeId) {
+        self.free.push(id);
+    }
+
+    fn remove_child(&mut self, parent: NodeId, target: Aabc) -> bool {
+        self.split(parent);
+        let idx = self.node(parent).get_octant_idx(target);
+        let child = match &self.node(parent).data {
+            NodeData::Children(children) => match children[idx] {
+                Some(child) => child,
                 None => panic!("child not found"),
             },
             NodeData::Value(_) => panic!("????"),
+        };
+        let remove_node = self.node(child).aabc == target || self.remove_child(child, target);
+        if remove_node {
+            self.dealloc(child);
+            if let NodeData::Children(ref mut children) = self.node_mut(parent).data {
+                children[idx] = None;
+            }
         }
+        self.node(parent).count_children().0 == 0
     }
 
-    fn add_down(&mut self, target_leaf: Box<Node<T>>) {
-        if self.aabc.size > 2 {
-            let idx = self.get_octant_idx(target_leaf.aabc);
-            match &mut self.data {
-                NodeData::Children(ref mut children) => match children[idx] {
-                    Some(ref mut child) => Self::add_down(child, target_leaf),
-                    None => {
-                        let shrunken = self.aabc.shrink_towards(target_leaf.aabc.origin);
-                        let n = Node::empty(shrunken.origin, shrunken.size);
-                        let idx2 = self.add_child(n);
-                        // TODO how to do this in a smarter way
-                        match &mut self.data {
-                            NodeData::Children(ref mut children) => match children[idx2] {
-                                Some(ref mut child) => Self::add_down(child, target_leaf),
-                                None => unreachable!(),
-                            },
-                            NodeData::Value(_) => unreachable!(),
-                        }
-                    }
-                },
-                NodeData::Value(_) => unreachable!(),
-            }
-        } else {
-            self.add_child(target_leaf);
+    fn add_down(&mut self, mut parent: NodeId, target_leaf: NodeId) {
+        let target = self.node(target_leaf).aabc;
+        while self.node(parent).aabc.size > 2 {
+            let idx = self.node(parent).get_octant_idx(target);
+            let existing = match &self.node(parent).data {
+                NodeData::Children(children) => children[idx],
+                NodeData::Value(_) => panic!("attempted to overwrite solid node at {:?}", target),
+            };
+            parent = match existing {
+                Some(child) if matches!(self.node(child).data, NodeData::Value(_)) => {
+                    panic!("attempted to overwrite solid node at {:?}", target)
+                }
+                Some(child) => child,
+                None => {
+                    let shrunken = self.node(parent).aabc.shrink_towards(target.origin);
+                    let n = self.alloc(Node::empty(shrunken.origin, shrunken.size));
+                    self.add_child(parent, n);
+                    n
+                }
+            };
         }
+        self.add_child(parent, target_leaf);
     }
 
-    fn add_child(&mut self, child: Box<Node<T>>) -> usize {
-        if !self.aabc.contains(child.aabc.origin) {
+    fn add_child(&mut self, parent: NodeId, child: NodeId) -> usize {
+        let child_aabc = self.node(child).aabc;
+        let parent = self.node_mut(parent);
+        if !parent.aabc.contains(child_aabc.origin) {
             panic!("child outside parent");
         }
-        if self.aabc.size != child.aabc.size * 2 {
+        if parent.aabc.size != child_aabc.size * 2 {
             panic!("parent not twice as big as child");
         }
-        let idx = self.get_octant_idx(child.aabc);
-        match self.data {
+        let idx = parent.get_octant_idx(child_aabc);
+        match parent.data {
             NodeData::Children(ref mut children) => {
                 if children[idx].is_some() {
-                    panic!("attempted to overwrite child at {:?}", child.aabc)
+                    panic!("attempted to overwrite child at {:?}", child_aabc)
                 }
                 children[idx] = Some(child);
                 idx
@@ -188,104 +298,363 @@ impl<T: Copy + Into<i32>> Node<T> {
             NodeData::Value(_) => panic!("cannot add a child to a leaf node"),
         }
     }
-}
 
-impl<T: Copy + Into<i32>> Octree<T> {
-    pub fn new() -> Self {
-        Octree {
-            n_leaves: 0,
-            root: None,
+    // turns a solid node into a parent of 8 solid nodes of half its size
+    fn split(&mut self, id: NodeId) {
+        let node = self.node(id).clone();
+        let value = match node.data {
+            NodeData::Value(v) if node.aabc.size > 1 => v,
+            _ => return,
+        };
+        let mut children = [None; 8];
+        for (i, child) in children.iter_mut().enumerate() {
+            let aabc = node.child_aabc(i);
+            *child = Some(self.alloc(Node {
+                data: NodeData::Value(value),
+                aabc,
+            }));
+        }
+        self.node_mut(id).data = NodeData::Children(children);
+    }
+
+    // deallocates every node below `id`
+    fn free_descendants(&mut self, id: NodeId) {
+        let mut stack = vec![id];
+        while let Some(curr) = stack.pop() {
+            if let NodeData::Children(children) = self.node(curr).data {
+                for child in children.into_iter().flatten() {
+                    self.dealloc(child);
+                    stack.push(child);
+                }
+            }
         }
     }
 
-    fn get_size_recurse(node: &Box<Node<T>>) -> usize {
+    // number of voxels below `id`
+    fn count_subtree(&self, id: NodeId) -> u64 {
+        let node = self.node(id);
         match &node.data {
+            NodeData::Value(_) => (node.aabc.size as u64).pow(3),
+            NodeData::Children(children) => children
+                .iter()
+                .flatten()
+                .map(|&c| self.count_subtree(c))
+                .sum(),
+        }
+    }
+
+    // number of words a node of the given size takes up in the serialization
+    fn node_words(size: u32) -> usize {
+        if size == 2 {
+            1 + 8 * T::WORDS
+        } else {
+            1 + 8
+        }
+    }
+
+    // writes the words of a leaf into slot `slot` of the size 2 node at `idx`
+    fn write_leaf(arr: &mut [i32], idx: usize, slot: usize, data: T) {
+        let start = idx + 1 + slot * T::WORDS;
+        for (dst, word) in arr[start..start + T::WORDS]
+            .iter_mut()
+            .zip(data.to_gpu_words().as_ref())
+        {
+            *dst = *word as i32;
+        }
+    }
+
+    // serialized size of a solid node of the given size
+    fn solid_serialized_size(size: u32) -> usize {
+        if size <= 2 {
+            Self::node_words(2)
+        } else {
+            Self::node_words(size) + 8 * Self::solid_serialized_size(size / 2)
+        }
+    }
+
+    fn get_size_recurse<F: Fn(Aabc) -> bool>(&self, node: NodeId, visible: &F) -> usize {
+        match &self.node(node).data {
             NodeData::Children(children) => {
-                let mut count = 8;
-                for child in children {
-                    match child {
-                        Some(ref c) => count += Self::get_size_recurse(c),
-                        None => (),
+                let mut count = Self::node_words(self.node(node).aabc.size);
+                for child in children.iter().flatten() {
+                    if visible(self.node(*child).aabc) {
+                        count += self.get_size_recurse(*child, visible);
                     }
                 }
                 count
             }
-            NodeData::Value(_) => 0,
+            NodeData::Value(_) => match self.node(node).aabc.size {
+                1 => 0,
+                size => Self::solid_serialized_size(size),
+            },
         }
     }
 
     fn get_serialized_size(&self) -> usize {
-        match &self.root {
-            Some(r) => 4 + Self::get_size_recurse(r),
-            None => 1,
+        self.get_visible_size(&|_| true)
+    }
+
+    fn get_visible_size<F: Fn(Aabc) -> bool>(&self, visible: &F) -> usize {
+        match self.root {
+            Some(r) if visible(self.node(r).aabc) => {
+                match (&self.node(r).data, self.node(r).aabc.size) {
+                    // serialized with a size 2 parent, see serialize()
+                    (NodeData::Value(_), 1) => 4 + Self::node_words(2),
+                    _ => 4 + self.get_size_recurse(r, visible),
+                }
+            }
+            _ => 1,
         }
     }
 
-    pub fn count_leaves(&self) -> u32 {
+    pub fn count_leaves(&self) -> u64 {
         self.n_leaves
     }
 
-    fn serialize_recurse(idx: usize, arr: &mut Vec<i32>, curr: &Box<Node<T>>) -> usize {
+    /// The cube covered by the root, None for an empty tree.
+    pub fn bounds(&self) -> Option<Aabc> {
+        self.root.map(|r| self.node(r).aabc)
+    }
+
+    pub fn get_leaf(&self, pos: Vector3<i32>) -> Option<T> {
+        let mut node = self.node(self.root?);
+        loop {
+            if !node.aabc.contains(pos) {
+                return None;
+            }
+            match &node.data {
+                NodeData::Value(v) => return Some(*v),
+                NodeData::Children(children) => {
+                    node = self.node(children[node.get_octant_idx(Aabc::new(pos, 1))]?)
+                }
+            }
+        }
+    }
+
+    fn serialize_recurse<F: Fn(Aabc) -> bool>(
+        &self,
+        idx: usize,
+        parent: usize,
+        arr: &mut Vec<i32>,
+        curr: NodeId,
+        visible: &F,
+    ) -> usize {
+        let curr = self.node(curr);
+        arr[idx] = parent as i32;
         match &curr.data {
             NodeData::Children(children) => {
-                let mut start = idx + 8;
+                let mut start = idx + Self::node_words(curr.aabc.size);
                 if curr.aabc.size == 2 {
                     for i in 0..children.len() {
-                        match &children[i] {
-                            Some(c) => match c.data {
+                        match children[i] {
+                            Some(c) if visible(self.node(c).aabc) => match self.node(c).data {
                                 NodeData::Children(_) => unreachable!(),
-                                NodeData::Value(d) => arr[idx + i] = d.clone().into(),
+                                NodeData::Value(d) => Self::write_leaf(arr, idx, i, d),
                             },
-                            None => (),
+                            _ => (),
                         }
                     }
                 } else {
                     for i in 0..children.len() {
-                        match &children[i] {
-                            Some(c) => {
-                                arr[idx + i] = start as i32;
-                                start += Self::serialize_recurse(start, arr, c)
+                        match children[i] {
+                            Some(c) if visible(self.node(c).aabc) => {
+                                arr[idx + 1 + i] = start as i32;
+                                start += self.serialize_recurse(start, idx, arr, c, visible)
                             }
-                            None => (),
+                            _ => (),
                         }
                     }
                 }
                 start - idx
             }
+            NodeData::Value(v) if curr.aabc.size > 1 => {
+                Self::serialize_solid(idx, parent, arr, *v, curr.aabc.size)
+            }
             NodeData::Value(_) => panic!("single leaf tree not supported"),
         }
     }
 
+    // the format has no solid nodes, so they're written as full subtrees
+    fn serialize_solid(
+        idx: usize,
+        parent: usize,
+        arr: &mut Vec<i32>,
+        value: T,
+        size: u32,
+    ) -> usize {
+        arr[idx] = parent as i32;
+        if size == 2 {
+            for slot in 0..8 {
+                Self::write_leaf(arr, idx, slot, value);
+            }
+            return Self::node_words(2);
+        }
+        let mut start = idx + Self::node_words(size);
+        for i in 0..8 {
+            arr[idx + 1 + i] = start as i32;
+            start += Self::serialize_solid(start, idx, arr, value, size / 2);
+        }
+        start - idx
+    }
+
     pub fn serialize(&self) -> Vec<i32> {
-        let mut arr = vec![0 as i32; self.get_serialized_size()];
-        match &self.root {
-            Some(n) => {
-                arr[0] = n.aabc.size as i32;
+        self.serialize_where(&|_| true)
+    }
+
+    /// Same as `serialize` but leaves out subtrees entirely outside of
+    /// `frustum`. The result has to be serialized again when the camera moves.
+    pub fn serialize_visible(&self, frustum: &Frustum) -> Vec<i32> {
+        self.serialize_where(&|aabc| frustum.intersects_aabc(aabc))
+    }
+
+    // serializes the nodes for which `visible` holds along with their parents
+    fn serialize_where<F: Fn(Aabc) -> bool>(&self, visible: &F) -> Vec<i32> {
+        let mut arr = vec![0 as i32; self.get_visible_size(visible)];
+        match self.root.map(|r| self.node(r)) {
+            Some(n) if visible(n.aabc) => {
                 arr[1] = n.aabc.origin[0];
                 arr[2] = n.aabc.origin[1];
                 arr[3] = n.aabc.origin[2];
-                Self::serialize_recurse(4, &mut arr, n);
+                match n.data {
+                    NodeData::Value(v) if n.aabc.size == 1 => {
+                        // the format has no representation for a leaf root, so
+                        // wrap it in a size 2 parent
+                        arr[0] = 2;
+                        let idx = Node::<T>::empty(n.aabc.origin, 2).get_octant_idx(n.aabc);
+                        Self::write_leaf(&mut arr, 4, idx, v);
+                    }
+                    _ => {
+                        arr[0] = n.aabc.size as i32;
+                        self.serialize_recurse(4, 0, &mut arr, self.root.unwrap(), visible);
+                    }
+                }
                 arr
             }
-            None => arr,
+            _ => arr,
         }
     }
 
-    fn shrink_root(&mut self) {
-        match self.root {
-            Some(ref mut root_node) => {
-                let (n, i) = root_node.count_children();
-                match root_node.data {
-                    NodeData::Value(_) => (),
-                    NodeData::Children(ref mut children) => {
-                        if n == 1 {
-                            self.root = std::mem::replace(&mut children[i.unwrap()], None);
-                            self.shrink_root();
-                        }
-                    }
+    /// Serializes `leaves`, which must be inside `node`, as the subtree of a
+    /// node covering `node` the way `serialize` would, starting at index 0
+    /// with a parent of 0, see `relocate`. Empty when there are no leaves, as
+    /// the format has no empty nodes.
+    pub fn serialize_node(node: Aabc, leaves: Vec<(Vector3<i32>, T)>) -> Vec<i32> {
+        let mut arr = Vec::new();
+        if !leaves.is_empty() {
+            Self::serialize_leaves(&mut arr, 0, node, leaves);
+        }
+        arr
+    }
+
+    // appends the node covering `aabc` and below it those of its leaves
+    fn serialize_leaves(
+        arr: &mut Vec<i32>,
+        parent: usize,
+        aabc: Aabc,
+        leaves: Vec<(Vector3<i32>, T)>,
+    ) -> usize {
+        let idx = arr.len();
+        arr.resize(idx + Self::node_words(aabc.size), 0);
+        arr[idx] = parent as i32;
+        let node = Node::<T>::empty(aabc.origin, aabc.size);
+        if aabc.size == 2 {
+            for (pos, data) in leaves {
+                Self::write_leaf(arr, idx, node.get_octant_idx(Aabc::new(pos, 1)), data);
+            }
+            return idx;
+        }
+        let mut octants: [Vec<_>; 8] = Default::default();
+        for (pos, data) in leaves {
+            octants[node.get_octant_idx(Aabc::new(pos, 1))].push((pos, data));
+        }
+        for (i, octant) in octants.into_iter().enumerate() {
+            if !octant.is_empty() {
+                let child = Self::serialize_leaves(arr, idx, node.child_aabc(i), octant);
+                arr[idx + 1 + i] = child as i32;
+            }
+        }
+        idx
+    }
+
+    /// Moves the words of `serialize_node` for a node of `size` to start at
+    /// `start` under the parent at `parent`.
+    pub fn relocate(words: &mut [i32], size: u32, start: usize, parent: usize) {
+        Self::relocate_node(words, 0, size, start as i32);
+        words[0] = parent as i32;
+    }
+
+    fn relocate_node(words: &mut [i32], idx: usize, size: u32, delta: i32) {
+        words[idx] += delta;
+        if size > 2 {
+            for i in 0..8 {
+                let child = words[idx + 1 + i];
+                if child != 0 {
+                    words[idx + 1 + i] = child + delta;
+                    Self::relocate_node(words, child as usize, size / 2, delta);
                 }
             }
+        }
+    }
+
+    /// Returns the nodes of at least `min_size` in the subtree of `node`,
+    /// whose words start at `start` of a serialization held in `words` from
+    /// `base` on, deepest first. The subtree must be in one piece, as
+    /// `serialize` and `serialize_node` leave it.
+    pub fn serialized_nodes(
+        words: &[i32],
+        base: usize,
+        node: SerializedNode,
+        min_size: u32,
+    ) -> Vec<SerializedNode> {
+        let mut nodes = Vec::new();
+        Self::find_serialized(words, base, node, min_size, &mut nodes);
+        nodes
+    }
+
+    // adds the nodes of at least min_size below and including `node`, whose
+    // len is ignored, and returns its len
+    fn find_serialized(
+        words: &[i32],
+        base: usize,
+        node: SerializedNode,
+        min_size: u32,
+        nodes: &mut Vec<SerializedNode>,
+    ) -> usize {
+        let mut len = Self::node_words(node.aabc.size);
+        if node.aabc.size > 2 {
+            for i in 0..8 {
+                let pointer = node.start + 1 + i;
+                let child = words[pointer - base];
+                if child != 0 {
+                    let child = SerializedNode {
+                        aabc: node.aabc.octant(i),
+                        start: child as usize,
+                        len: 0,
+                        parent: node.start,
+                        pointer: Some(pointer),
+                    };
+                    len += Self::find_serialized(words, base, child, min_size, nodes);
+                }
+            }
+        }
+        if node.aabc.size >= min_size {
+            nodes.push(SerializedNode { len, ..node });
+        }
+        len
+    }
+
+    fn shrink_root(&mut self) {
+        let root = match self.root {
+            Some(root) => root,
             None => panic!("root is none"),
+        };
+        let (n, i) = self.node(root).count_children();
+        if let NodeData::Children(children) = self.node(root).data {
+            if n == 1 {
+                self.root = children[i.unwrap()];
+                self.dealloc(root);
+                self.shrink_root();
+            }
         }
     }
 
@@ -293,17 +662,12 @@ impl<T: Copy + Into<i32>> Octree<T> {
         self.n_leaves -= 1;
         match self.root {
             None => panic!("cannot remove from empty tree"),
-            Some(ref mut node) => {
+            Some(node) => {
                 let target = Aabc::new(target, 1);
-                if node.aabc == target {
-                    self.root = None
+                if self.node(node).aabc == target || self.remove_child(node, target) {
+                    self.clear()
                 } else {
-                    let remove_node = node.remove_child(target);
-                    if remove_node {
-                        self.root = None
-                    } else {
-                        self.shrink_root()
-                    }
+                    self.shrink_root()
                 }
             }
         }
@@ -311,36 +675,460 @@ impl<T: Copy + Into<i32>> Octree<T> {
 
     pub fn insert_leaf(&mut self, data: T, pos: Vector3<i32>) {
         self.n_leaves += 1;
-        let leaf = Node::new_leaf(data, pos);
-        let root = std::mem::replace(&mut self.root, None);
-        match root {
+        let leaf = self.alloc(Node::new_leaf(data, pos));
+        match self.root {
             None => self.root = Some(leaf),
             Some(mut node) => {
-                while !node.aabc.contains(leaf.aabc.origin) {
-                    let expanded = node.aabc.expand_towards(leaf.aabc.origin);
-                    let mut n = Node::empty(expanded.origin, expanded.size);
-                    n.add_child(node);
+                while !self.node(node).aabc.contains(pos) {
+                    let expanded = self.node(node).aabc.expand_towards(pos);
+                    let n = self.alloc(Node::empty(expanded.origin, expanded.size));
+                    self.add_child(n, node);
                     node = n;
                 }
-                node.add_down(leaf);
                 self.root = Some(node);
+                self.add_down(node, leaf);
+            }
+        }
+    }
+
+    /// Returns every leaf with its position, in depth first order.
+    pub fn leaves(&self) -> Vec<(Vector3<i32>, T)> {
+        self.collect_leaves(None)
+    }
+
+    /// Returns the leaves inside `region`, skipping subtrees outside of it.
+    pub fn leaves_in(&self, region: Aabc) -> Vec<(Vector3<i32>, T)> {
+        self.collect_leaves(Some(region))
+    }
+
+    fn collect_leaves(&self, region: Option<Aabc>) -> Vec<(Vector3<i32>, T)> {
+        let inside = |aabc: Aabc| region.map_or(true, |r| r.intersects(aabc));
+        let mut leaves = Vec::new();
+        let mut stack: Vec<NodeId> = self.root.into_iter().collect();
+        while let Some(id) = stack.pop() {
+            let node = self.node(id);
+            if !inside(node.aabc) {
+                continue;
+            }
+            match &node.data {
+                NodeData::Value(v) => {
+                    let size = node.aabc.size as i32;
+                    for x in 0..size {
+                        for y in 0..size {
+                            for z in 0..size {
+                                let pos = vec3_add(node.aabc.origin, [x, y, z]);
+                                if inside(Aabc::new(pos, 1)) {
+                                    leaves.push((pos, *v));
+                                }
+                            }
+                        }
+                    }
+                }
+                NodeData::Children(children) => stack.extend(children.iter().flatten()),
+            }
+        }
+        leaves
+    }
+
+    /// Returns the changes that turn this tree into `other`.
+    pub fn diff(&self, other: &Octree<T>) -> Vec<VoxelChange<T>>
+    where
+        T: PartialEq,
+    {
+        let mut changes = Vec::new();
+        for (pos, value) in self.leaves() {
+            match other.get_leaf(pos) {
+                None => changes.push(VoxelChange::Removed(pos, value)),
+                Some(new) if new != value => changes.push(VoxelChange::Changed(pos, value, new)),
+                Some(_) => (),
+            }
+        }
+        for (pos, value) in other.leaves() {
+            if self.get_leaf(pos).is_none() {
+                changes.push(VoxelChange::Added(pos, value));
+            }
+        }
+        changes
+    }
+
+    /// Applies changes produced by `diff`.
+    pub fn apply(&mut self, changes: &[VoxelChange<T>]) {
+        for change in changes {
+            match *change {
+                VoxelChange::Added(pos, value) => self.insert_leaf(value, pos),
+                VoxelChange::Removed(pos, _) => self.remove_leaf(pos),
+                VoxelChange::Changed(pos, _, value) => {
+                    self.remove_leaf(pos);
+                    self.insert_leaf(value, pos);
+                }
+            }
+        }
+    }
+
+    /// Copies every leaf of `other` into this tree. Voxels present in both are
+    /// resolved according to `policy`.
+    pub fn merge(&mut self, other: &Octree<T>, policy: MergePolicy) {
+        for (pos, value) in other.leaves() {
+            if self.get_leaf(pos).is_some() {
+                if policy == MergePolicy::KeepExisting {
+                    continue;
+                }
+                self.remove_leaf(pos);
+            }
+            self.insert_leaf(value, pos);
+        }
+    }
+
+    /// Sets every voxel in `region` to `value`. Parts of the tree fully inside
+    /// the region are replaced by a single solid node instead of individual
+    /// leaves.
+    pub fn fill_region(&mut self, region: Aabc, value: T) {
+        if region.size == 0 {
+            return;
+        }
+        let max = vec3_add(region.origin, [region.size as i32 - 1; 3]);
+        let root = match self.root {
+            Some(root) => root,
+            None => {
+                let size = region.size.next_power_of_two().max(2);
+                let root = self.alloc(Node::empty(region.origin, size));
+                self.root = Some(root);
+                root
             }
+        };
+        let mut root = root;
+        while !self.node(root).aabc.contains_aabc(region) {
+            let aabc = self.node(root).aabc;
+            let target = if aabc.contains(region.origin) {
+                max
+            } else {
+                region.origin
+            };
+            let expanded = aabc.expand_towards(target);
+            let n = self.alloc(Node::empty(expanded.origin, expanded.size));
+            self.add_child(n, root);
+            root = n;
+        }
+        self.root = Some(root);
+        self.fill_node(root, region, value);
+    }
+
+    fn fill_node(&mut self, id: NodeId, region: Aabc, value: T) {
+        let aabc = self.node(id).aabc;
+        if region.contains_aabc(aabc) {
+            let previous = self.count_subtree(id);
+            self.free_descendants(id);
+            self.node_mut(id).data = NodeData::Value(value);
+            self.n_leaves = self.n_leaves + (aabc.size as u64).pow(3) - previous;
+            return;
+        }
+        self.split(id);
+        for idx in 0..8 {
+            let child_aabc = self.node(id).child_aabc(idx);
+            if !region.intersects(child_aabc) {
+                continue;
+            }
+            let child = match self.node(id).data {
+                NodeData::Children(children) => children[idx],
+                NodeData::Value(_) => unreachable!(),
+            };
+            let child = match child {
+                Some(child) => child,
+                None => {
+                    let n = self.alloc(Node::empty(child_aabc.origin, child_aabc.size));
+                    self.add_child(id, n);
+                    n
+                }
+            };
+            self.fill_node(child, region, value);
+        }
+    }
+
+    /// Removes every voxel in `region`, dropping whole subtrees where they are
+    /// fully inside it.
+    pub fn clear_region(&mut self, region: Aabc) {
+        let root = match self.root {
+            Some(root) if self.node(root).aabc.intersects(region) => root,
+            _ => return,
+        };
+        if self.clear_node(root, region) {
+            self.clear();
+        } else {
+            self.shrink_root();
         }
     }
+
+    // returns whether the node is now empty and should be removed
+    fn clear_node(&mut self, id: NodeId, region: Aabc) -> bool {
+        let aabc = self.node(id).aabc;
+        if region.contains_aabc(aabc) {
+            self.n_leaves -= self.count_subtree(id);
+            self.free_descendants(id);
+            return true;
+        }
+        self.split(id);
+        for idx in 0..8 {
+            let child = match self.node(id).data {
+                NodeData::Children(children) => children[idx],
+                NodeData::Value(_) => unreachable!(),
+            };
+            let child = match child {
+                Some(child) if region.intersects(self.node(child).aabc) => child,
+                _ => continue,
+            };
+            if self.clear_node(child, region) {
+                self.dealloc(child);
+                if let NodeData::Children(ref mut children) = self.node_mut(id).data {
+                    children[idx] = None;
+                }
+            }
+        }
+        self.node(id).count_children().0 == 0
+    }
+
+    pub fn stats(&self) -> OctreeStats {
+        let mut nodes = 0;
+        let mut depth = 0;
+        let mut stack: Vec<(NodeId, u32)> = self.root.map(|r| (r, 1)).into_iter().collect();
+        while let Some((id, level)) = stack.pop() {
+            nodes += 1;
+            depth = depth.max(level);
+            if let NodeData::Children(children) = &self.node(id).data {
+                stack.extend(children.iter().flatten().map(|&c| (c, level + 1)));
+            }
+        }
+        let occupancy = match self.root {
+            Some(r) => self.n_leaves as f64 / (self.node(r).aabc.size as f64).powi(3),
+            None => 0.0,
+        };
+        OctreeStats {
+            nodes,
+            leaves: self.n_leaves,
+            depth,
+            occupancy,
+            memory_bytes: self.nodes.capacity() * mem::size_of::<Node<T>>()
+                + self.free.capacity() * mem::size_of::<NodeId>(),
+        }
+    }
+
+    /// Checks the structural invariants of the tree: every child sits in its
+    /// octant of the parent at half its size, branches aren't empty, voxels
+    /// are single leaves or power of two solid nodes, no node is reachable
+    /// twice or from the free list, and the leaf count matches the contents.
+    pub fn validate(&self) -> Result<(), String> {
+        let root = match self.root {
+            Some(root) => root,
+            None if self.n_leaves == 0 => return Ok(()),
+            None => return Err(format!("empty tree counts {} leaves", self.n_leaves)),
+        };
+        let free: HashSet<NodeId> = self.free.iter().copied().collect();
+        let mut seen = HashSet::new();
+        let mut voxels = 0u64;
+        let mut stack = vec![root];
+        while let Some(id) = stack.pop() {
+            if id as usize >= self.nodes.len() || free.contains(&id) {
+                return Err(format!("node {} is not allocated", id));
+            }
+            if !seen.insert(id) {
+                return Err(format!("node {} is reachable twice", id));
+            }
+            let node = self.node(id);
+            if !node.aabc.size.is_power_of_two() {
+                return Err(format!("node {:?} size is not a power of two", node.aabc));
+            }
+            match &node.data {
+                NodeData::Value(_) => voxels += (node.aabc.size as u64).pow(3),
+                NodeData::Children(children) => {
+                    if node.aabc.size < 2 {
+                        return Err(format!("branch {:?} is smaller than 2", node.aabc));
+                    }
+                    if node.count_children().0 == 0 {
+                        return Err(format!("branch {:?} has no children", node.aabc));
+                    }
+                    for (idx, child) in children.iter().enumerate() {
+                        let child = match child {
+                            Some(child) => *child,
+                            None => continue,
+                        };
+                        let expected = node.child_aabc(idx);
+                        if self.nodes.get(child as usize).map(|c| c.aabc) != Some(expected) {
+                            return Err(format!(
+                                "child {} of {:?} should be {:?}",
+                                idx, node.aabc, expected
+                            ));
+                        }
+                        stack.push(child);
+                    }
+                }
+            }
+        }
+        if voxels != self.n_leaves {
+            return Err(format!(
+                "tree holds {} voxels but counts {}",
+                voxels, self.n_leaves
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that `arr` is laid out the way `serialize` does it, so the
+    /// shader's walks down through children and up through parents stay in
+    /// the buffer and end: every child is inside it, reached once, and
+    /// points back to its parent.
+    pub fn check_serialized(arr: &[i32]) -> Result<(), String> {
+        match arr {
+            [] => return Err("no root size".to_string()),
+            [0, ..] => return Ok(()),
+            _ if arr.len() < 4 || arr.len() > i32::MAX as usize => {
+                return Err(format!("{} words can't hold a tree", arr.len()))
+            }
+            _ => (),
+        }
+        let size = arr[0];
+        if size < 2 || !(size as u32).is_power_of_two() {
+            return Err(format!("root size {} is not a power of two above 1", size));
+        }
+        if arr[1..4].iter().any(|c| c.checked_add(size).is_none()) {
+            return Err(format!(
+                "root at {:?} reaches past the coordinates",
+                &arr[1..4]
+            ));
+        }
+        let mut seen = HashSet::new();
+        let mut stack = vec![(4, 0, size as u32)];
+        while let Some((idx, parent, size)) = stack.pop() {
+            let end = idx + Self::node_words(size);
+            if end > arr.len() {
+                return Err(format!("node {} ends past the buffer", idx));
+            }
+            if !seen.insert(idx) {
+                return Err(format!("node {} is reachable twice", idx));
+            }
+            if arr[idx] != parent as i32 {
+                return Err(format!(
+                    "node {} points to parent {}, not {}",
+                    idx, arr[idx], parent
+                ));
+            }
+            if size == 2 {
+                continue;
+            }
+            for &child in &arr[idx + 1..end] {
+                match child {
+                    0 => (),
+                    child if child < 4 => {
+                        return Err(format!("child {} of node {} is in the header", child, idx))
+                    }
+                    child => stack.push((child as usize, idx, size / 2)),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // the tree is empty, so the whole arena can be reused
+    fn clear(&mut self) {
+        self.root = None;
+        self.nodes.clear();
+        self.free.clear();
+    }
+}
+
+impl<T: VoxelData> VoxelTree<T> for Octree<T> {
+    fn insert_leaf(&mut self, data: T, pos: Vector3<i32>) {
+        Octree::insert_leaf(self, data, pos)
+    }
+
+    fn remove_leaf(&mut self, pos: Vector3<i32>) {
+        Octree::remove_leaf(self, pos)
+    }
+
+    fn get_leaf(&self, pos: Vector3<i32>) -> Option<T> {
+        Octree::get_leaf(self, pos)
+    }
+
+    fn count_leaves(&self) -> u64 {
+        Octree::count_leaves(self)
+    }
+
+    fn serialize(&self) -> Vec<i32> {
+        Octree::serialize(self)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{aabc::Aabc, octree::Node};
+    use std::collections::HashMap;
+
+    use proptest::prelude::*;
+
+    use crate::{
+        aabc::Aabc,
+        camera::Projection,
+        octree::Node,
+        voxel::{Voxel, MAX_LIGHT},
+        world::scattered_positions,
+    };
 
     use super::*;
 
+    // owned copy of a subtree, for comparing tree structure
+    #[derive(PartialEq, Debug)]
+    enum Snapshot {
+        Leaf(i32, Vector3<i32>),
+        Branch(Aabc, [Option<Box<Snapshot>>; 8]),
+    }
+
+    fn leaf(data: i32, pos: Vector3<i32>) -> Option<Box<Snapshot>> {
+        Some(Box::new(Snapshot::Leaf(data, pos)))
+    }
+
+    fn snapshot<T: VoxelData>(tree: &Octree<T>, id: NodeId) -> Snapshot {
+        let node = tree.node(id);
+        match &node.data {
+            NodeData::Value(v) => {
+                Snapshot::Leaf(v.to_gpu_words().as_ref()[0] as i32, node.aabc.origin)
+            }
+            NodeData::Children(children) => Snapshot::Branch(
+                node.aabc,
+                children.map(|c| c.map(|c| Box::new(snapshot(tree, c)))),
+            ),
+        }
+    }
+
+    fn root_snapshot<T: VoxelData>(tree: &Octree<T>) -> Option<Snapshot> {
+        tree.root.map(|r| snapshot(tree, r))
+    }
+
     #[test]
     fn insert_leaf() {
         let mut tree = Octree::new();
-        let expected_root = Node::new_leaf(0, [0, 0, 0]);
         tree.insert_leaf(0, [0, 0, 0]);
-        assert_eq!(tree.root, Some(expected_root))
+        assert_eq!(root_snapshot(&tree), Some(Snapshot::Leaf(0, [0, 0, 0])))
+    }
+
+    #[test]
+    fn leaves_at_large_coordinates() {
+        let far = 1 << 20;
+        let corners: Vec<_> = Aabc::new([0; 3], 1)
+            .corners()
+            .iter()
+            .map(|c| c.map(|c| if c == 0 { -far } else { far - 1 }))
+            .collect();
+        let mut tree = Octree::new();
+        for (i, pos) in corners.iter().enumerate() {
+            tree.insert_leaf(i as i32 + 1, *pos);
+        }
+        tree.validate().unwrap();
+        assert_eq!(Some(Aabc::new([-far; 3], 2 * far as u32)), tree.bounds());
+        for (i, pos) in corners.iter().enumerate() {
+            assert_eq!(Some(i as i32 + 1), tree.get_leaf(*pos));
+        }
+        assert_eq!(2 * far, tree.serialize()[0]);
+        for pos in &corners {
+            tree.remove_leaf(*pos);
+        }
+        assert_eq!(0, tree.count_leaves());
     }
 
     #[test]
@@ -363,53 +1151,68 @@ mod tests {
     #[test]
     #[should_panic]
     fn add_leaf_outside_node_panics() {
-        let mut node = Node::empty([0, 0, 0], 2);
-        node.add_child(Node::new_leaf(0, [2, 2, 2]));
+        let mut tree = Octree::new();
+        let node = tree.alloc(Node::empty([0, 0, 0], 2));
+        let leaf = tree.alloc(Node::new_leaf(0, [2, 2, 2]));
+        tree.add_child(node, leaf);
     }
 
     #[test]
     #[should_panic]
     fn add_leaf_to_large_node_panics() {
-        let mut node = Node::empty([0, 0, 0], 4);
-        node.add_child(Node::new_leaf(0, [0, 0, 0]));
+        let mut tree = Octree::new();
+        let node = tree.alloc(Node::empty([0, 0, 0], 4));
+        let leaf = tree.alloc(Node::new_leaf(0, [0, 0, 0]));
+        tree.add_child(node, leaf);
     }
 
     #[test]
     #[should_panic]
     fn add_missized_child_panics() {
-        let mut node: Box<Node<i32>> = Node::empty([0, 0, 0], 8);
-        node.add_child(Node::empty([0, 0, 0], 2));
+        let mut tree: Octree<i32> = Octree::new();
+        let node = tree.alloc(Node::empty([0, 0, 0], 8));
+        let child = tree.alloc(Node::empty([0, 0, 0], 2));
+        tree.add_child(node, child);
     }
 
     #[test]
     #[should_panic]
     fn add_child_node_outside_node_panics() {
-        let mut node: Box<Node<i32>> = Node::empty([0, 0, 0], 4);
-        node.add_child(Node::empty([4, 4, 4], 2));
+        let mut tree: Octree<i32> = Octree::new();
+        let node = tree.alloc(Node::empty([0, 0, 0], 4));
+        let child = tree.alloc(Node::empty([4, 4, 4], 2));
+        tree.add_child(node, child);
     }
 
     #[test]
     fn add_children_leaves_to_node() {
-        let mut node = Node::empty([0, 0, 0], 2);
-        let expected_children = [
-            Some(Node::new_leaf(0, [1, 1, 1])),
-            Some(Node::new_leaf(0, [1, 1, 0])),
-            Some(Node::new_leaf(0, [0, 1, 0])),
-            Some(Node::new_leaf(0, [0, 1, 1])),
-            Some(Node::new_leaf(0, [1, 0, 1])),
-            Some(Node::new_leaf(0, [1, 0, 0])),
-            Some(Node::new_leaf(0, [0, 0, 0])),
-            Some(Node::new_leaf(0, [0, 0, 1])),
+        let mut tree = Octree::new();
+        let node = tree.alloc(Node::empty([0, 0, 0], 2));
+        let positions = [
+            [1, 1, 1],
+            [1, 1, 0],
+            [0, 1, 0],
+            [0, 1, 1],
+            [1, 0, 1],
+            [1, 0, 0],
+            [0, 0, 0],
+            [0, 0, 1],
         ];
-        for i in 0..expected_children.len() {
-            node.add_child(expected_children[i].clone().unwrap());
+        for pos in positions {
+            let leaf = tree.alloc(Node::new_leaf(0, pos));
+            tree.add_child(node, leaf);
         }
-        assert_eq!(NodeData::Children(expected_children), node.data)
+        let expected_children = positions.map(|pos| leaf(0, pos));
+        assert_eq!(
+            Snapshot::Branch(Aabc::new([0, 0, 0], 2), expected_children),
+            snapshot(&tree, node)
+        )
     }
 
     #[test]
     fn add_child_nodes_to_node() {
-        let mut node: Box<Node<i32>> = Node::empty([0, 0, 0], 4);
+        let mut tree: Octree<i32> = Octree::new();
+        let node = tree.alloc(Node::empty([0, 0, 0], 4));
         let expected_aabcs = [
             Aabc {
                 origin: [2, 2, 2],
@@ -445,12 +1248,13 @@ mod tests {
             },
         ];
         for i in 0..expected_aabcs.len() {
-            node.add_child(Node::empty(expected_aabcs[i].origin, 2));
+            let child = tree.alloc(Node::empty(expected_aabcs[i].origin, 2));
+            tree.add_child(node, child);
         }
-        match node.data {
+        match tree.node(node).data {
             NodeData::Children(arr) => {
                 for i in 0..expected_aabcs.len() {
-                    assert_eq!(expected_aabcs[i], arr[i].clone().unwrap().aabc)
+                    assert_eq!(expected_aabcs[i], tree.node(arr[i].unwrap()).aabc)
                 }
             }
             NodeData::Value(_) => unreachable!(),
@@ -460,15 +1264,23 @@ mod tests {
     #[test]
     fn insert_two_leaves() {
         let mut tree = Octree::new();
-        let leaf1 = Node::new_leaf(0, [0, 0, 0]);
-        let leaf2 = Node::new_leaf(1, [1, 0, 0]);
         tree.insert_leaf(0, [0, 0, 0]);
         tree.insert_leaf(1, [1, 0, 0]);
-        let mut expected_node = Node::empty([0, 0, 0], 2);
-        expected_node.data =
-            NodeData::Children([None, None, None, None, None, Some(leaf2), Some(leaf1), None]);
+        let expected_node = Snapshot::Branch(
+            Aabc::new([0, 0, 0], 2),
+            [
+                None,
+                None,
+                None,
+                None,
+                None,
+                leaf(1, [1, 0, 0]),
+                leaf(0, [0, 0, 0]),
+                None,
+            ],
+        );
 
-        assert_eq!(tree.root, Some(expected_node));
+        assert_eq!(root_snapshot(&tree), Some(expected_node));
     }
 
     #[test]
@@ -501,68 +1313,283 @@ mod tests {
         tree.insert_leaf(0, [0, 0, 0]);
         tree.insert_leaf(0, [1, 1, 1]);
         tree.remove_leaf([0, 0, 0]);
-        assert_eq!(tree.root, Some(Node::new_leaf(0, [1, 1, 1])));
+        assert_eq!(root_snapshot(&tree), Some(Snapshot::Leaf(0, [1, 1, 1])));
     }
 
     #[test]
     fn complex_insert_remove() {
         let mut tree = Octree::new();
-        let leaf3 = Node::new_leaf(0, [2, 2, 2]);
         tree.insert_leaf(0, [0, 0, 0]);
         tree.insert_leaf(0, [1, 1, 1]);
-        tree.insert_leaf(0, leaf3.aabc.origin);
+        tree.insert_leaf(0, [2, 2, 2]);
         tree.remove_leaf([0, 0, 0]);
-        let leaf4 = Node::new_leaf(5, [2, 2, 1]);
-        tree.insert_leaf(5, leaf4.aabc.origin);
+        tree.insert_leaf(5, [2, 2, 1]);
         tree.remove_leaf([1, 1, 1]);
 
-        let expected_root = Box::new(Node {
-            data: NodeData::Children([
-                Some(Box::new(Node {
-                    data: NodeData::Children([
-                        None,
-                        None,
-                        None,
-                        None,
-                        None,
-                        None,
-                        Some(leaf3),
-                        None,
-                    ]),
-                    aabc: Aabc {
+        let expected_root = Snapshot::Branch(
+            Aabc {
+                origin: [0, 0, 0],
+                size: 4,
+            },
+            [
+                Some(Box::new(Snapshot::Branch(
+                    Aabc {
                         origin: [2, 2, 2],
                         size: 2,
                     },
-                })),
-                Some(Box::new(Node {
-                    data: NodeData::Children([
-                        None,
-                        None,
-                        None,
-                        None,
-                        None,
-                        None,
-                        None,
-                        Some(leaf4),
-                    ]),
-                    aabc: Aabc {
+                    [None, None, None, None, None, None, leaf(0, [2, 2, 2]), None],
+                ))),
+                Some(Box::new(Snapshot::Branch(
+                    Aabc {
                         origin: [2, 2, 0],
                         size: 2,
                     },
-                })),
+                    [None, None, None, None, None, None, None, leaf(5, [2, 2, 1])],
+                ))),
                 None,
                 None,
                 None,
                 None,
                 None,
                 None,
-            ]),
-            aabc: Aabc {
-                origin: [0, 0, 0],
-                size: 4,
-            },
-        });
-        assert_eq!(tree.root, Some(expected_root));
+            ],
+        );
+        assert_eq!(root_snapshot(&tree), Some(expected_root));
+    }
+
+    #[test]
+    fn removed_nodes_are_reused() {
+        let mut tree = Octree::new();
+        tree.insert_leaf(0, [0, 0, 0]);
+        tree.insert_leaf(0, [5, 5, 5]);
+        let nodes = tree.nodes.len();
+        tree.remove_leaf([5, 5, 5]);
+        tree.insert_leaf(0, [5, 5, 5]);
+        assert_eq!(nodes, tree.nodes.len());
+    }
+
+    #[test]
+    fn clone_is_independent() {
+        let mut tree = Octree::new();
+        tree.insert_leaf(1, [0, 0, 0]);
+        tree.insert_leaf(2, [3, 3, 3]);
+        let copy = tree.clone();
+        tree.remove_leaf([3, 3, 3]);
+        assert_eq!(Some(2), copy.get_leaf([3, 3, 3]));
+        assert_eq!(None, tree.get_leaf([3, 3, 3]));
+    }
+
+    #[test]
+    fn diff_of_equal_trees_is_empty() {
+        let mut a = Octree::new();
+        a.insert_leaf(1, [0, 0, 0]);
+        a.insert_leaf(2, [3, -1, 4]);
+        assert!(a.diff(&a.clone()).is_empty());
+    }
+
+    #[test]
+    fn diff_finds_changes() {
+        let mut a = Octree::new();
+        a.insert_leaf(1, [0, 0, 0]);
+        a.insert_leaf(2, [1, 0, 0]);
+        let mut b = Octree::new();
+        b.insert_leaf(3, [1, 0, 0]);
+        b.insert_leaf(4, [5, 5, 5]);
+        let mut changes = a.diff(&b);
+        changes.sort_by_key(|c| format!("{:?}", c));
+        assert_eq!(
+            vec![
+                VoxelChange::Added([5, 5, 5], 4),
+                VoxelChange::Changed([1, 0, 0], 2, 3),
+                VoxelChange::Removed([0, 0, 0], 1),
+            ],
+            changes
+        );
+    }
+
+    #[test]
+    fn apply_diff_reproduces_target() {
+        let mut a = Octree::new();
+        a.insert_leaf(1, [0, 0, 0]);
+        a.insert_leaf(2, [-4, 2, 0]);
+        let mut b = Octree::new();
+        b.insert_leaf(3, [-4, 2, 0]);
+        b.insert_leaf(4, [8, 8, 8]);
+        let changes = a.diff(&b);
+        a.apply(&changes);
+        assert!(a.diff(&b).is_empty());
+        assert_eq!(2, a.count_leaves());
+    }
+
+    #[test]
+    fn merge_policies() {
+        let mut world = Octree::new();
+        world.insert_leaf(1, [0, 0, 0]);
+        let mut prefab = Octree::new();
+        prefab.insert_leaf(2, [0, 0, 0]);
+        prefab.insert_leaf(2, [1, 1, 1]);
+
+        let mut kept = world.clone();
+        kept.merge(&prefab, MergePolicy::KeepExisting);
+        assert_eq!(Some(1), kept.get_leaf([0, 0, 0]));
+        assert_eq!(Some(2), kept.get_leaf([1, 1, 1]));
+
+        world.merge(&prefab, MergePolicy::Overwrite);
+        assert_eq!(Some(2), world.get_leaf([0, 0, 0]));
+        assert_eq!(2, world.count_leaves());
+    }
+
+    #[test]
+    fn fill_region_uses_solid_nodes() {
+        let mut tree = Octree::new();
+        tree.fill_region(Aabc::new([0, 0, 0], 4), 1);
+        assert_eq!(64, tree.count_leaves());
+        assert_eq!(Some(1), tree.get_leaf([3, 3, 3]));
+        assert_eq!(None, tree.get_leaf([4, 0, 0]));
+        assert_eq!(1, tree.nodes.len() - tree.free.len());
+    }
+
+    #[test]
+    fn huge_fills_are_counted_whole() {
+        let mut tree = Octree::new();
+        tree.fill_region(Aabc::new([0, 0, 0], 2048), 1);
+        assert_eq!(1 << 33, tree.count_leaves());
+        assert_eq!(1 << 33, tree.stats().leaves);
+        tree.validate().unwrap();
+    }
+
+    #[test]
+    fn fill_unaligned_region() {
+        let mut tree = Octree::new();
+        tree.insert_leaf(5, [-4, 0, 0]);
+        tree.fill_region(Aabc::new([1, 1, 1], 3), 2);
+        assert_eq!(28, tree.count_leaves());
+        assert_eq!(Some(2), tree.get_leaf([3, 3, 3]));
+        assert_eq!(None, tree.get_leaf([0, 1, 1]));
+        assert_eq!(Some(5), tree.get_leaf([-4, 0, 0]));
+    }
+
+    #[test]
+    fn fill_overwrites_voxels() {
+        let mut tree = Octree::new();
+        tree.insert_leaf(5, [1, 1, 1]);
+        tree.insert_leaf(5, [9, 9, 9]);
+        tree.fill_region(Aabc::new([0, 0, 0], 2), 1);
+        assert_eq!(9, tree.count_leaves());
+        assert_eq!(Some(1), tree.get_leaf([1, 1, 1]));
+        assert_eq!(Some(5), tree.get_leaf([9, 9, 9]));
+    }
+
+    #[test]
+    fn solid_serializes_like_leaves() {
+        let mut solid = Octree::new();
+        solid.fill_region(Aabc::new([0, 0, 0], 4), 3);
+        let mut leaves = Octree::new();
+        for x in 0..4 {
+            for y in 0..4 {
+                for z in 0..4 {
+                    leaves.insert_leaf(3, [x, y, z]);
+                }
+            }
+        }
+        assert_eq!(leaves.serialize(), solid.serialize());
+        assert_eq!(leaves.get_serialized_size(), solid.get_serialized_size());
+    }
+
+    #[test]
+    fn clear_region_splits_solid_nodes() {
+        let mut tree = Octree::new();
+        tree.fill_region(Aabc::new([0, 0, 0], 4), 1);
+        tree.clear_region(Aabc::new([0, 0, 0], 2));
+        assert_eq!(56, tree.count_leaves());
+        assert_eq!(None, tree.get_leaf([1, 1, 1]));
+        assert_eq!(Some(1), tree.get_leaf([2, 0, 0]));
+        assert_eq!(56, tree.leaves().len());
+    }
+
+    #[test]
+    fn clear_everything_empties_tree() {
+        let mut tree = Octree::new();
+        tree.fill_region(Aabc::new([0, 0, 0], 4), 1);
+        tree.insert_leaf(2, [-3, 0, 0]);
+        tree.clear_region(Aabc::new([-4, -4, -4], 16));
+        assert_eq!(0, tree.count_leaves());
+        assert_eq!(None, tree.root);
+        assert_eq!(vec![0], tree.serialize());
+    }
+
+    #[test]
+    fn remove_leaf_from_solid_node() {
+        let mut tree = Octree::new();
+        tree.fill_region(Aabc::new([0, 0, 0], 2), 1);
+        tree.remove_leaf([0, 0, 0]);
+        assert_eq!(7, tree.count_leaves());
+        assert_eq!(None, tree.get_leaf([0, 0, 0]));
+        assert_eq!(Some(1), tree.get_leaf([1, 0, 0]));
+    }
+
+    #[test]
+    fn stats_of_small_tree() {
+        let mut tree = Octree::new();
+        assert_eq!(0, tree.stats().depth);
+        tree.insert_leaf(1, [0, 0, 0]);
+        tree.insert_leaf(2, [1, 1, 1]);
+        tree.insert_leaf(3, [2, 2, 2]);
+        let stats = tree.stats();
+        // size 4 root, two size 2 branches and three leaves
+        assert_eq!(6, stats.nodes);
+        assert_eq!(3, stats.leaves);
+        assert_eq!(3, stats.depth);
+        assert_eq!(3.0 / 64.0, stats.occupancy);
+        assert!(stats.memory_bytes > 0);
+    }
+
+    #[test]
+    fn edited_trees_are_valid() {
+        let mut tree = Octree::new();
+        assert_eq!(Ok(()), tree.validate());
+        for pos in scattered_positions(5, 200) {
+            tree.insert_leaf(1, pos);
+        }
+        tree.fill_region(Aabc::new([0, 0, 0], 4), 2);
+        tree.clear_region(Aabc::new([1, 1, 1], 2));
+        for pos in scattered_positions(5, 200).into_iter().step_by(3) {
+            if tree.get_leaf(pos).is_some() {
+                tree.remove_leaf(pos);
+            }
+        }
+        assert_eq!(Ok(()), tree.validate());
+    }
+
+    #[test]
+    fn validate_finds_misplaced_child() {
+        let mut tree = Octree::new();
+        tree.insert_leaf(1, [0, 0, 0]);
+        tree.insert_leaf(2, [1, 1, 1]);
+        let leaf = match tree.node(tree.root.unwrap()).data {
+            NodeData::Children(children) => children.into_iter().flatten().next().unwrap(),
+            NodeData::Value(_) => unreachable!(),
+        };
+        tree.node_mut(leaf).aabc.origin = [5, 5, 5];
+        assert!(tree.validate().is_err());
+    }
+
+    #[test]
+    fn validate_finds_wrong_leaf_count() {
+        let mut tree = Octree::new();
+        tree.insert_leaf(1, [0, 0, 0]);
+        tree.n_leaves = 2;
+        assert!(tree.validate().is_err());
+    }
+
+    #[test]
+    fn leaves_in_region() {
+        let mut tree = Octree::new();
+        tree.fill_region(Aabc::new([0, 0, 0], 4), 1);
+        tree.insert_leaf(2, [9, 9, 9]);
+        let mut leaves = tree.leaves_in(Aabc::new([3, 3, 3], 8));
+        leaves.sort_by_key(|l| l.0);
+        assert_eq!(vec![([3, 3, 3], 1), ([9, 9, 9], 2)], leaves);
     }
 
     #[test]
@@ -610,7 +1637,7 @@ mod tests {
         tree.insert_leaf(1, [0, 0, 0]);
         tree.insert_leaf(2, [1, 1, 1]);
 
-        let expected = vec![2, 0, 0, 0, 2, 0, 0, 0, 0, 0, 1, 0];
+        let expected = vec![2, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 1, 0];
         assert_eq!(expected, tree.serialize());
     }
 
@@ -620,7 +1647,7 @@ mod tests {
         tree.insert_leaf(1, [0, 0, -5]);
         tree.insert_leaf(2, [1, 0, -5]);
 
-        let expected = vec![2, 0, 0, -5, 0, 0, 0, 0, 0, 2, 1, 0];
+        let expected = vec![2, 0, 0, -5, 0, 0, 0, 0, 0, 0, 2, 1, 0];
         assert_eq!(expected, tree.serialize());
     }
 
@@ -634,9 +1661,9 @@ mod tests {
         let expected = vec![
             4, // root size
             -2, -2, -2, // xyz
-            12, 0, 0, 0, 0, 0, 20, 0, // size 4's children indices
-            2, 0, 0, 0, 0, 0, 1, 0, // size 2's children leaf block type
-            3, 0, 0, 0, 0, 0, 0, 0, // size 2's children leaf block type
+            0, 13, 0, 0, 0, 0, 0, 22, 0, // size 4's parent and children indices
+            4, 2, 0, 0, 0, 0, 0, 1, 0, // size 2's parent and children leaf block type
+            4, 3, 0, 0, 0, 0, 0, 0, 0, // size 2's parent and children leaf block type
         ];
         assert_eq!(expected, tree.serialize());
     }
@@ -652,16 +1679,188 @@ mod tests {
         let expected = vec![
             8, // root size
             0, 0, 0, // xyz
-            12, 0, 0, 0, 0, 0, 28, 0, // size 8's children indices
-            0, 0, 0, 0, 0, 0, 20, 0, // size 4's children indices
-            0, 0, 0, 0, 0, 0, 4, 0, // size 2's children leaf block type
-            36, 0, 0, 0, 0, 0, 44, 0, // size 4's children indices
-            0, 0, 0, 0, 0, 0, 3, 0, // size 2's children leaf block type
-            2, 0, 0, 0, 0, 0, 1, 0, // size 2's children leaf block type
+            0, 13, 0, 0, 0, 0, 0, 31, 0, // size 8's parent and children indices
+            4, 0, 0, 0, 0, 0, 0, 22, 0, // size 4's parent and children indices
+            13, 0, 0, 0, 0, 0, 0, 4, 0, // size 2's parent and children leaf block type
+            4, 40, 0, 0, 0, 0, 0, 49, 0, // size 4's parent and children indices
+            31, 0, 0, 0, 0, 0, 0, 3, 0, // size 2's parent and children leaf block type
+            31, 2, 0, 0, 0, 0, 0, 1, 0, // size 2's parent and children leaf block type
         ];
         assert_eq!(expected, tree.serialize());
     }
 
+    #[test]
+    fn serialize_single_leaf_tree() {
+        let mut tree: Octree<i32> = Octree::new();
+        tree.insert_leaf(3, [1, 2, 3]);
+
+        let expected = vec![2, 1, 2, 3, 0, 0, 0, 0, 0, 0, 0, 3, 0];
+        assert_eq!(expected, tree.serialize());
+    }
+
+    #[test]
+    fn serialize_multi_word_leaves() {
+        let mut tree = Octree::new();
+        let voxel = Voxel {
+            material: 3,
+            orientation: 1,
+            light: 2,
+        };
+        tree.insert_leaf(voxel, [0, 0, 0]);
+        tree.insert_leaf(Voxel::new(4), [1, 1, 1]);
+        let mut expected = vec![2, 0, 0, 0];
+        expected.extend_from_slice(&[0; 17]);
+        expected[5..7].copy_from_slice(&[4, (MAX_LIGHT as i32) << 8]);
+        expected[17..19].copy_from_slice(&[3, 1 | 2 << 8]);
+        assert_eq!(expected, tree.serialize());
+        assert_eq!(expected.len(), tree.get_serialized_size());
+    }
+
+    #[test]
+    fn serialized_children_point_back_to_parent() {
+        let mut tree = Octree::new();
+        for (i, pos) in scattered_positions(3, 200).into_iter().enumerate() {
+            tree.insert_leaf(i as i32 + 1, pos);
+        }
+        tree.fill_region(Aabc::new([64, 64, 64], 8), 7);
+        let arr = tree.serialize();
+        assert_eq!(0, arr[4]);
+        let mut stack = vec![(4, arr[0])];
+        while let Some((idx, size)) = stack.pop() {
+            if size == 2 {
+                continue;
+            }
+            for slot in 1..9 {
+                let child = arr[idx + slot] as usize;
+                if child != 0 {
+                    assert_eq!(idx as i32, arr[child]);
+                    stack.push((child, size / 2));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn serialized_trees_are_checked() {
+        let mut tree = Octree::new();
+        for (i, pos) in scattered_positions(3, 200).into_iter().enumerate() {
+            tree.insert_leaf(i as i32 + 1, pos);
+        }
+        let arr = tree.serialize();
+        Octree::<i32>::check_serialized(&arr).unwrap();
+        Octree::<i32>::check_serialized(&[0]).unwrap();
+        let child = arr[5..13].iter().copied().find(|&c| c != 0).unwrap() as usize;
+
+        let mut cycle = arr.clone();
+        cycle[child + 1..child + 9].fill(0);
+        cycle[child + 1] = 4;
+        assert!(Octree::<i32>::check_serialized(&cycle).is_err());
+        let mut past_end = arr.clone();
+        past_end[child + 1] = arr.len() as i32;
+        assert!(Octree::<i32>::check_serialized(&past_end).is_err());
+        let mut wrong_parent = arr.clone();
+        wrong_parent[child] = child as i32;
+        assert!(Octree::<i32>::check_serialized(&wrong_parent).is_err());
+        assert!(Octree::<i32>::check_serialized(&arr[..arr.len() - 1]).is_err());
+        assert!(Octree::<i32>::check_serialized(&[3, 0, 0, 0]).is_err());
+        assert!(Octree::<i32>::check_serialized(&[i32::MIN, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn nodes_serialize_like_the_tree() {
+        let mut tree = Octree::new();
+        for (i, pos) in scattered_positions(5, 200).into_iter().enumerate() {
+            tree.insert_leaf(i as i32 + 1, pos);
+        }
+        tree.fill_region(Aabc::new([64, 64, 64], 8), 7);
+        let arr = tree.serialize();
+        let root = tree.bounds().unwrap();
+        let mut words = Octree::serialize_node(root, tree.leaves());
+        Octree::<i32>::relocate(&mut words, root.size, 4, 0);
+        assert_eq!(&arr[4..], &words[..]);
+        assert!(Octree::<i32>::serialize_node(root, Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn serialized_nodes_cover_their_subtrees() {
+        let mut tree = Octree::new();
+        tree.fill_region(Aabc::new([0, 0, 0], 16), 1);
+        tree.insert_leaf(2, [40, 3, 3]);
+        let arr = tree.serialize();
+        let root = SerializedNode {
+            aabc: tree.bounds().unwrap(),
+            start: 4,
+            len: 0,
+            parent: 0,
+            pointer: None,
+        };
+        let nodes = Octree::<i32>::serialized_nodes(&arr, 0, root, 16);
+        let last = *nodes.last().unwrap();
+        assert_eq!((4, arr.len() - 4), (last.start, last.len));
+        let solid = nodes
+            .iter()
+            .find(|n| n.aabc == Aabc::new([0, 0, 0], 16))
+            .unwrap();
+        assert_eq!(solid.start as i32, arr[solid.pointer.unwrap()]);
+        assert_eq!(solid.parent as i32, arr[solid.start]);
+        assert_eq!(Octree::<i32>::solid_serialized_size(16), solid.len);
+        assert!(nodes.iter().all(|n| n.aabc.size >= 16));
+    }
+
+    #[test]
+    fn serialize_visible_culls_subtrees() {
+        let camera = Projection::Perspective {
+            fov: std::f32::consts::PI / 2.0,
+        }
+        .camera_info([0.0, 0.0, 0.0], [0.0, 0.0, -1.0]);
+        let frustum = Frustum::from_camera_info(&camera, 1.0);
+        let mut tree = Octree::new();
+        tree.insert_leaf(1, [0, 0, -8]);
+        tree.insert_leaf(2, [0, 0, 8]);
+        let visible = serialized_leaves(&tree.serialize_visible(&frustum));
+        assert_eq!(HashMap::from([([0, 0, -8], 1)]), visible);
+        assert!(tree.serialize_visible(&frustum).len() < tree.serialize().len());
+    }
+
+    #[test]
+    fn serialize_visible_culls_root() {
+        let camera = Projection::Perspective {
+            fov: std::f32::consts::PI / 2.0,
+        }
+        .camera_info([0.0, 0.0, 0.0], [0.0, 0.0, -1.0]);
+        let frustum = Frustum::from_camera_info(&camera, 1.0);
+        let mut tree = Octree::new();
+        tree.insert_leaf(1, [0, 0, 8]);
+        assert_eq!(vec![0], tree.serialize_visible(&frustum));
+    }
+
+    #[test]
+    fn get_leaf_empty_tree() {
+        let tree: Octree<i32> = Octree::new();
+        assert_eq!(None, tree.get_leaf([0, 0, 0]));
+    }
+
+    #[test]
+    fn get_leaf_single_leaf_tree() {
+        let mut tree = Octree::new();
+        tree.insert_leaf(7, [1, 2, 3]);
+        assert_eq!(Some(7), tree.get_leaf([1, 2, 3]));
+        assert_eq!(None, tree.get_leaf([1, 2, 4]));
+    }
+
+    #[test]
+    fn get_leaf_after_insert_and_remove() {
+        let mut tree = Octree::new();
+        tree.insert_leaf(1, [0, 0, 0]);
+        tree.insert_leaf(2, [5, -3, 2]);
+        tree.insert_leaf(3, [-8, 9, 1]);
+        tree.remove_leaf([0, 0, 0]);
+        assert_eq!(None, tree.get_leaf([0, 0, 0]));
+        assert_eq!(Some(2), tree.get_leaf([5, -3, 2]));
+        assert_eq!(Some(3), tree.get_leaf([-8, 9, 1]));
+        assert_eq!(None, tree.get_leaf([100, 100, 100]));
+    }
+
     #[test]
     fn get_size_serialize_empty_tree() {
         let tree: Octree<bool> = Octree::new();
@@ -673,7 +1872,7 @@ mod tests {
         let mut tree: Octree<i32> = Octree::new();
         tree.insert_leaf(1, [0, 0, 0]);
         tree.insert_leaf(2, [1, 1, 1]);
-        assert_eq!(12, tree.get_serialized_size());
+        assert_eq!(13, tree.get_serialized_size());
     }
 
     #[test]
@@ -682,7 +1881,7 @@ mod tests {
         tree.insert_leaf(1, [0, 0, 0]);
         tree.insert_leaf(2, [1, 1, 1]);
         tree.insert_leaf(3, [-1, -1, -1]);
-        assert_eq!(28, tree.get_serialized_size());
+        assert_eq!(31, tree.get_serialized_size());
     }
 
     #[test]
@@ -692,7 +1891,7 @@ mod tests {
         tree.insert_leaf(2, [1, 1, 1]);
         tree.insert_leaf(3, [2, 2, 2]);
         tree.insert_leaf(4, [4, 4, 4]);
-        assert_eq!(52, tree.get_serialized_size());
+        assert_eq!(58, tree.get_serialized_size());
     }
 
     #[test]
@@ -703,4 +1902,96 @@ mod tests {
         tree.insert_leaf(14, [2, 2, -3]);
         tree.insert_leaf(15, [3, 3, -2]);
     }
+
+    // walks a serialized tree and returns every leaf with its value
+    fn serialized_leaves(arr: &[i32]) -> HashMap<Vector3<i32>, i32> {
+        let mut leaves = HashMap::new();
+        if arr[0] == 0 {
+            return leaves;
+        }
+        let mut stack = vec![(4, [arr[1], arr[2], arr[3]], arr[0] as u32)];
+        while let Some((idx, origin, size)) = stack.pop() {
+            let node = Node::<i32>::empty(origin, size);
+            for slot in 0..8 {
+                let value = arr[idx + 1 + slot];
+                let child = node.child_aabc(slot);
+                if value == 0 {
+                    continue;
+                } else if size == 2 {
+                    leaves.insert(child.origin, value);
+                } else {
+                    stack.push((value as usize, child.origin, child.size));
+                }
+            }
+        }
+        leaves
+    }
+
+    fn check_tree(
+        tree: &Octree<i32>,
+        expected: &HashMap<Vector3<i32>, i32>,
+    ) -> Result<(), TestCaseError> {
+        prop_assert_eq!(Ok(()), tree.validate());
+        prop_assert_eq!(expected.len() as u64, tree.count_leaves());
+        for (pos, value) in expected {
+            prop_assert_eq!(Some(*value), tree.get_leaf(*pos));
+        }
+        prop_assert_eq!(expected, &serialized_leaves(&tree.serialize()));
+        if let Some(root) = tree.root {
+            // the root is shrunk as far as possible
+            prop_assert_ne!(1, tree.node(root).count_children().0);
+        }
+        Ok(())
+    }
+
+    fn voxels() -> impl Strategy<Value = HashMap<Vector3<i32>, i32>> {
+        prop::collection::hash_map(prop::array::uniform3(-16..16), 1..10, 0..64)
+    }
+
+    proptest! {
+        #[test]
+        fn inserted_voxels_are_found(voxels in voxels()) {
+            let mut tree = Octree::new();
+            for (pos, value) in &voxels {
+                tree.insert_leaf(*value, *pos);
+            }
+            check_tree(&tree, &voxels)?;
+        }
+
+        #[test]
+        fn removed_voxels_are_gone(
+            mut voxels in voxels(),
+            removed in prop::collection::vec(any::<prop::sample::Index>(), 0..64),
+        ) {
+            let mut tree = Octree::new();
+            for (pos, value) in &voxels {
+                tree.insert_leaf(*value, *pos);
+            }
+            let positions: Vec<_> = voxels.keys().copied().collect();
+            for idx in removed {
+                if positions.is_empty() {
+                    break;
+                }
+                let pos = *idx.get(&positions);
+                if voxels.remove(&pos).is_some() {
+                    tree.remove_leaf(pos);
+                    prop_assert_eq!(None, tree.get_leaf(pos));
+                }
+            }
+            check_tree(&tree, &voxels)?;
+        }
+
+        #[test]
+        fn removing_everything_empties_tree(voxels in voxels()) {
+            let mut tree = Octree::new();
+            for (pos, value) in &voxels {
+                tree.insert_leaf(*value, *pos);
+            }
+            for pos in voxels.keys() {
+                tree.remove_leaf(*pos);
+            }
+            prop_assert_eq!(vec![0], tree.serialize());
+            prop_assert_eq!(None, tree.root);
+        }
+    }
 }
@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+
+use crate::{
+    aabc::Aabc,
+    materials::MaterialId,
+    octree::{Octree, SerializedNode},
+};
+
+/// Nodes this size and larger are serialized again on their own after an
+/// edit, smaller ones with the smallest of them around them.
+pub const PATCH_SIZE: u32 = 16;
+
+/// Words to write into the octree's buffer from `start` on.
+#[derive(Debug, PartialEq)]
+pub struct Patch {
+    pub start: usize,
+    pub words: Vec<i32>,
+}
+
+/// Where the nodes of at least `PATCH_SIZE` of a serialized octree are in the
+/// buffer it was uploaded to, so an edit only has to serialize the nodes
+/// around it again. A node is written over its old words when it still fits
+/// in them, otherwise after the end of the tree, into the room the buffer
+/// was allocated with.
+pub struct OctreeLayout {
+    root: Aabc,
+    nodes: HashMap<Aabc, SerializedNode>,
+    len: usize,
+    capacity: usize,
+}
+
+impl OctreeLayout {
+    /// Indexes `words`, the output of `Octree::serialize`, uploaded into a
+    /// buffer of `capacity` words. None for trees smaller than `PATCH_SIZE`,
+    /// which are cheaper to upload whole.
+    pub fn new(words: &[i32], capacity: usize) -> Option<Self> {
+        if words[0] < PATCH_SIZE as i32 {
+            return None;
+        }
+        let root = Aabc::new([words[1], words[2], words[3]], words[0] as u32);
+        let root_node = SerializedNode {
+            aabc: root,
+            start: 4,
+            len: 0,
+            parent: 0,
+            pointer: None,
+        };
+        let nodes = Octree::<MaterialId>::serialized_nodes(words, 0, root_node, PATCH_SIZE)
+            .into_iter()
+            .map(|node| (node.aabc, node))
+            .collect();
+        Some(OctreeLayout {
+            root,
+            nodes,
+            len: words.len(),
+            capacity,
+        })
+    }
+
+    /// Words to allocate for a tree of `len` words, leaving room for nodes
+    /// that grow.
+    pub fn capacity_for(len: usize) -> usize {
+        len + len / 4
+    }
+
+    /// Whether edits inside `bounds`, those of the edited tree, can be
+    /// patched in, which they can't once the tree grew past the root.
+    pub fn covers(&self, bounds: Aabc) -> bool {
+        self.root.contains_aabc(bounds)
+    }
+
+    /// The nodes to serialize again for an edit inside `region`, the smallest
+    /// around each `PATCH_SIZE` cell of the root it touches.
+    pub fn patch_nodes(&self, region: Aabc) -> Vec<Aabc> {
+        let mut found: Vec<Aabc> = Vec::new();
+        let size = PATCH_SIZE as i32;
+        let cell = |c: i32, axis: usize| (c - self.root.origin[axis]).div_euclid(size);
+        let (first, last) = (
+            [0, 1, 2].map(|i| cell(region.origin[i], i).max(0)),
+            [0, 1, 2].map(|i| {
+                let end = self.root.size as i32 / size - 1;
+                cell(region.origin[i] + region.size as i32 - 1, i).min(end)
+            }),
+        );
+        for x in first[0]..=last[0] {
+            for y in first[1]..=last[1] {
+                for z in first[2]..=last[2] {
+                    let origin = [x, y, z].map(|c| c * size);
+                    let mut node = Aabc::new(
+                        [0, 1, 2].map(|i| self.root.origin[i] + origin[i]),
+                        PATCH_SIZE,
+                    );
+                    while !self.nodes.contains_key(&node) {
+                        node = self.parent_of(node);
+                    }
+                    if !found.iter().any(|f| f.contains_aabc(node)) {
+                        found.retain(|f| !node.contains_aabc(*f));
+                        found.push(node);
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    // the node of twice the size around `node`
+    fn parent_of(&self, node: Aabc) -> Aabc {
+        let size = node.size as i32 * 2;
+        let origin = [0, 1, 2].map(|i| {
+            let root = self.root.origin[i];
+            root + (node.origin[i] - root).div_euclid(size) * size
+        });
+        Aabc::new(origin, node.size * 2)
+    }
+
+    /// Places `words`, serialized by `Octree::serialize_node` for `node`, one
+    /// of `patch_nodes`, and returns what to write into the buffer. None when
+    /// the tree has to be uploaded whole instead: when the buffer is full, the
+    /// root doesn't fit its old words or the tree is empty.
+    pub fn place(&mut self, node: Aabc, mut words: Vec<i32>) -> Option<Vec<Patch>> {
+        let old = *self.nodes.get(&node)?;
+        if words.is_empty() {
+            // unlinked from its parent, the format has no empty nodes
+            let pointer = old.pointer?;
+            self.remove(node);
+            return Some(vec![Patch {
+                start: pointer,
+                words: vec![0],
+            }]);
+        }
+        let moved = words.len() > old.len;
+        let start = if !moved {
+            old.start
+        } else if old.pointer.is_some() && self.len + words.len() <= self.capacity {
+            self.len
+        } else {
+            return None;
+        };
+        self.remove(node);
+        Octree::<MaterialId>::relocate(&mut words, node.size, start, old.parent);
+        let placed = SerializedNode { start, ..old };
+        for found in Octree::<MaterialId>::serialized_nodes(&words, start, placed, PATCH_SIZE) {
+            self.nodes.insert(found.aabc, found);
+        }
+        let mut patches = Vec::new();
+        if moved {
+            self.len += words.len();
+            patches.push(Patch {
+                start: old.pointer.unwrap(),
+                words: vec![start as i32],
+            });
+        } else {
+            // keeps the words it had, which the next patch may need
+            self.nodes.get_mut(&node).unwrap().len = old.len;
+        }
+        patches.push(Patch { start, words });
+        Some(patches)
+    }
+
+    // forgets `node` and the nodes below it
+    fn remove(&mut self, node: Aabc) {
+        if self.nodes.remove(&node).is_some() && node.size > PATCH_SIZE {
+            for i in 0..8 {
+                self.remove(node.octant(i));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vecmath::Vector3;
+
+    use super::*;
+
+    // applies `patches` to `buffer` like the GPU would
+    fn apply(buffer: &mut [i32], patches: Vec<Patch>) {
+        for patch in patches {
+            buffer[patch.start..patch.start + patch.words.len()].copy_from_slice(&patch.words);
+        }
+    }
+
+    // the leaves a serialized tree holds, found by following its pointers
+    fn leaves(buffer: &[i32]) -> HashMap<Vector3<i32>, MaterialId> {
+        let mut leaves = HashMap::new();
+        let mut stack = vec![(
+            4,
+            Aabc::new([buffer[1], buffer[2], buffer[3]], buffer[0] as u32),
+        )];
+        while let Some((idx, aabc)) = stack.pop() {
+            for slot in 0..8 {
+                let value = buffer[idx + 1 + slot];
+                if value == 0 {
+                    continue;
+                } else if aabc.size == 2 {
+                    leaves.insert(aabc.octant(slot).origin, value);
+                } else {
+                    assert_eq!(idx as i32, buffer[value as usize]);
+                    stack.push((value as usize, aabc.octant(slot)));
+                }
+            }
+        }
+        leaves
+    }
+
+    fn upload(tree: &Octree<MaterialId>) -> (Vec<i32>, OctreeLayout) {
+        let words = tree.serialize();
+        let capacity = OctreeLayout::capacity_for(words.len());
+        let layout = OctreeLayout::new(&words, capacity).unwrap();
+        let mut buffer = words;
+        buffer.resize(capacity, 0);
+        (buffer, layout)
+    }
+
+    // serializes the nodes around `region` again and patches them in
+    fn patch(
+        tree: &Octree<MaterialId>,
+        buffer: &mut [i32],
+        layout: &mut OctreeLayout,
+        region: Aabc,
+    ) -> Option<()> {
+        for node in layout.patch_nodes(region) {
+            let words = Octree::serialize_node(node, tree.leaves_in(node));
+            apply(buffer, layout.place(node, words)?);
+        }
+        Some(())
+    }
+
+    fn tree_leaves(tree: &Octree<MaterialId>) -> HashMap<Vector3<i32>, MaterialId> {
+        tree.leaves().into_iter().collect()
+    }
+
+    #[test]
+    fn edits_are_patched_in() {
+        let mut tree = Octree::new();
+        tree.fill_region(Aabc::new([0, 0, 0], 64), 1);
+        let (mut buffer, mut layout) = upload(&tree);
+        // a hole fits where the solid node was
+        tree.remove_leaf([5, 5, 5]);
+        let region = Aabc::new([0, 0, 0], 16);
+        assert_eq!(vec![region], layout.patch_nodes(region));
+        patch(&tree, &mut buffer, &mut layout, region).unwrap();
+        assert_eq!(tree_leaves(&tree), leaves(&buffer));
+        // emptying a node unlinks it
+        tree.clear_region(Aabc::new([16, 0, 0], 16));
+        patch(&tree, &mut buffer, &mut layout, Aabc::new([16, 0, 0], 16)).unwrap();
+        assert_eq!(tree_leaves(&tree), leaves(&buffer));
+        // and filling it again patches its parent, which still has room
+        tree.fill_region(Aabc::new([16, 0, 0], 16), 2);
+        patch(&tree, &mut buffer, &mut layout, Aabc::new([16, 0, 0], 16)).unwrap();
+        assert_eq!(tree_leaves(&tree), leaves(&buffer));
+    }
+
+    #[test]
+    fn grown_nodes_move_after_the_end() {
+        let mut tree = Octree::new();
+        tree.insert_leaf(1, [0, 0, 0]);
+        // leaves room after the end
+        tree.fill_region(Aabc::new([32, 32, 32], 16), 1);
+        let (mut buffer, mut layout) = upload(&tree);
+        let len = tree.serialize().len();
+        tree.insert_leaf(2, [4, 0, 0]);
+        let region = Aabc::new([4, 0, 0], 1);
+        let node = Aabc::new([0, 0, 0], 16);
+        assert_eq!(vec![node], layout.patch_nodes(region));
+        let words = Octree::serialize_node(node, tree.leaves_in(node));
+        let patches = layout.place(node, words).unwrap();
+        assert_eq!(len, patches[1].start);
+        apply(&mut buffer, patches);
+        assert_eq!(tree_leaves(&tree), leaves(&buffer));
+    }
+
+    #[test]
+    fn regions_patch_the_smallest_nodes_around_them() {
+        let mut tree = Octree::new();
+        tree.insert_leaf(1, [0, 0, 0]);
+        tree.insert_leaf(1, [63, 63, 63]);
+        let (_, layout) = upload(&tree);
+        // the cells of an unaligned region and the nodes around the empty ones
+        let nodes = layout.patch_nodes(Aabc::new([8, 8, 8], 16));
+        assert_eq!(vec![Aabc::new([0, 0, 0], 32)], nodes);
+        let nodes = layout.patch_nodes(Aabc::new([0, 0, 0], 4));
+        assert_eq!(vec![Aabc::new([0, 0, 0], 16)], nodes);
+        assert!(layout.covers(Aabc::new([16, 16, 16], 32)));
+        assert!(!layout.covers(Aabc::new([0, 0, 0], 128)));
+        assert!(layout.patch_nodes(Aabc::new([64, 0, 0], 16)).is_empty());
+    }
+
+    #[test]
+    fn full_buffers_are_uploaded_whole() {
+        let mut tree = Octree::new();
+        tree.insert_leaf(1, [0, 0, 0]);
+        tree.insert_leaf(1, [63, 63, 63]);
+        let (mut buffer, mut layout) = upload(&tree);
+        let region = Aabc::new([32, 32, 32], 32);
+        tree.fill_region(region, 2);
+        assert_eq!(None, patch(&tree, &mut buffer, &mut layout, region));
+        assert!(OctreeLayout::new(&[0], 1).is_none());
+    }
+}
@@ -0,0 +1,371 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    time::Duration,
+};
+
+use vecmath::{vec3_add, Vector3};
+
+use crate::{
+    materials::{Flow, MaterialId, MaterialRegistry, MAX_LEVEL},
+    world::World,
+};
+
+/// Time between two steps of the simulation.
+pub const TICK: Duration = Duration::from_millis(50);
+
+// steps run at most per update, so slow frames slow the simulation down
+// instead of making the next frame slower still
+const MAX_TICKS_PER_UPDATE: u32 = 4;
+
+const DOWN: Vector3<i32> = [0, -1, 0];
+
+const UP: Vector3<i32> = [0, 1, 0];
+
+const SIDES: [Vector3<i32>; 4] = [[1, 0, 0], [0, 0, 1], [-1, 0, 0], [0, 0, -1]];
+
+/// Moves the voxels of powders and spreads liquids, see `materials::Flow`, a
+/// step every `TICK` as a cellular automaton. Only voxels next to something
+/// that changed are looked at, the rest of the world is taken to be at rest.
+/// The changes are made through `World::settle`, as part of the edit that set
+/// them off.
+///
+/// Liquids flow the way they do in Minecraft: below a liquid is liquid of
+/// level `MAX_LEVEL - 1`, and beside liquid that can't flow down is liquid a
+/// level lower. Sources stay put, and liquid nothing flows into dries up.
+pub struct Physics {
+    // voxels that may move on the next step, lowest first so those on top
+    // follow them down in the same step
+    awake: BTreeSet<(i32, Vector3<i32>)>,
+    // not stepped through yet
+    elapsed: Duration,
+    ticks: usize,
+    enabled: bool,
+}
+
+impl Default for Physics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Physics {
+    pub fn new() -> Self {
+        Physics {
+            awake: BTreeSet::new(),
+            elapsed: Duration::ZERO,
+            ticks: 0,
+            enabled: true,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Disabled, voxels stay where they are but are still woken, and move
+    /// once enabled again.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.elapsed = Duration::ZERO;
+    }
+
+    /// Whether no voxel may move.
+    pub fn is_at_rest(&self) -> bool {
+        self.awake.is_empty()
+    }
+
+    /// Looks at the voxels around positions that changed on the next step.
+    pub fn wake(&mut self, positions: impl IntoIterator<Item = Vector3<i32>>) {
+        for pos in positions {
+            for x in -1..=1 {
+                for y in -1..=1 {
+                    for z in -1..=1 {
+                        let neighbour = vec3_add(pos, [x, y, z]);
+                        self.awake.insert((neighbour[1], neighbour));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Steps through `dt`, changing only positions `loaded` accepts so
+    /// nothing falls out of the world. Returns how many voxels changed.
+    pub fn update(
+        &mut self,
+        dt: Duration,
+        world: &mut World,
+        materials: &MaterialRegistry,
+        loaded: impl Fn(Vector3<i32>) -> bool,
+    ) -> usize {
+        if !self.enabled {
+            return 0;
+        }
+        self.elapsed += dt;
+        let mut moved = 0;
+        let mut ticks = 0;
+        while self.elapsed >= TICK {
+            if ticks == MAX_TICKS_PER_UPDATE {
+                self.elapsed = Duration::ZERO;
+                break;
+            }
+            self.elapsed -= TICK;
+            ticks += 1;
+            moved += self.tick(world, materials, &loaded);
+        }
+        moved
+    }
+
+    /// Moves every awake powder voxel that can move by one position, then
+    /// spreads liquids by one voxel.
+    pub fn tick(
+        &mut self,
+        world: &mut World,
+        materials: &MaterialRegistry,
+        loaded: impl Fn(Vector3<i32>) -> bool,
+    ) -> usize {
+        self.ticks += 1;
+        let awake = std::mem::take(&mut self.awake);
+        // moved there this step, so not moved again
+        let mut arrived = HashSet::new();
+        for &(_, pos) in &awake {
+            if arrived.contains(&pos) {
+                continue;
+            }
+            let material = match world.get(pos) {
+                Some(material) if materials.flow(material) == Flow::Powder => material,
+                _ => continue,
+            };
+            let free = |p: Vector3<i32>| loaded(p) && world.get(p).is_none();
+            if let Some(to) = self.destination(pos, free) {
+                world.settle(&[(pos, None), (to, Some(material))]);
+                arrived.insert(to);
+                self.wake([pos, to]);
+            }
+        }
+        // every level from the liquids before any changes, as they all
+        // change at once
+        let levels: Vec<_> = awake
+            .iter()
+            .filter(|(_, pos)| loaded(*pos))
+            .filter_map(|&(_, pos)| {
+                let current = world.get(pos);
+                let next = liquid_at(pos, world, materials, &loaded)?;
+                (next != current).then_some((pos, next))
+            })
+            .collect();
+        world.settle(&levels);
+        self.wake(levels.iter().map(|(pos, _)| *pos));
+        arrived.len() + levels.len()
+    }
+
+    // where the powder voxel at `pos` moves, straight down if it can, else
+    // down the side of what it's on
+    fn destination(
+        &self,
+        pos: Vector3<i32>,
+        free: impl Fn(Vector3<i32>) -> bool,
+    ) -> Option<Vector3<i32>> {
+        let below = vec3_add(pos, DOWN);
+        if free(below) {
+            return Some(below);
+        }
+        // starting from another side every step, so piles spread evenly
+        (0..SIDES.len())
+            .map(|i| SIDES[(i + self.ticks) % SIDES.len()])
+            .find(|side| free(vec3_add(pos, *side)) && free(vec3_add(below, *side)))
+            .map(|side| vec3_add(below, side))
+    }
+}
+
+// what flowing liquid `pos` should hold, None if nothing can flow there.
+// Sources and other materials stay, and only loaded voxels are flowed into
+// or count as somewhere to flow down to.
+fn liquid_at(
+    pos: Vector3<i32>,
+    world: &World,
+    materials: &MaterialRegistry,
+    loaded: impl Fn(Vector3<i32>) -> bool,
+) -> Option<Option<MaterialId>> {
+    let current = world.get(pos);
+    if let Some(material) = current {
+        if !(1..MAX_LEVEL).contains(&materials.level(material)) {
+            return None;
+        }
+    }
+    let liquid = |p: Vector3<i32>| world.get(p).filter(|m| materials.level(*m) > 0);
+    if let Some(above) = liquid(vec3_add(pos, UP)) {
+        return Some(materials.with_level(above, MAX_LEVEL - 1));
+    }
+    // into empty space or liquid that isn't full
+    let flows_down = |p: Vector3<i32>| {
+        let below = vec3_add(p, DOWN);
+        loaded(below)
+            && world
+                .get(below)
+                .map_or(true, |m| (1..MAX_LEVEL).contains(&materials.level(m)))
+    };
+    let next = SIDES
+        .iter()
+        .map(|side| vec3_add(pos, *side))
+        .filter_map(|side| liquid(side).filter(|_| !flows_down(side)))
+        .max_by_key(|m| materials.level(*m))
+        .and_then(|m| materials.with_level(m, materials.level(m) - 1));
+    // empty space that stays empty isn't a change, see tick
+    Some(next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settle(physics: &mut Physics, world: &mut World, loaded: impl Fn(Vector3<i32>) -> bool) {
+        let materials = MaterialRegistry::default();
+        for _ in 0..100 {
+            physics.tick(world, &materials, &loaded);
+        }
+        assert!(physics.is_at_rest());
+    }
+
+    fn above_floor(pos: Vector3<i32>) -> bool {
+        pos[1] >= 0
+    }
+
+    fn id(name: &str) -> MaterialId {
+        MaterialRegistry::default().id(name).unwrap()
+    }
+
+    #[test]
+    fn sand_falls_and_stone_stays() {
+        let mut world = World::new();
+        world.set([0, 5, 0], id("sand"));
+        world.set([2, 5, 0], id("stone"));
+        let mut physics = Physics::new();
+        physics.wake([[0, 5, 0], [2, 5, 0]]);
+        settle(&mut physics, &mut world, above_floor);
+        assert_eq!(Some(id("sand")), world.get([0, 0, 0]));
+        assert_eq!(None, world.get([0, 5, 0]));
+        assert_eq!(Some(id("stone")), world.get([2, 5, 0]));
+    }
+
+    #[test]
+    fn sand_column_becomes_a_pile() {
+        let mut world = World::new();
+        for y in 0..3 {
+            world.set([0, y, 0], id("sand"));
+        }
+        let mut physics = Physics::new();
+        physics.wake([[0, 1, 0]]);
+        settle(&mut physics, &mut world, above_floor);
+        assert_eq!(3, world.tree().count_leaves());
+        // nothing is left on top of the others
+        assert!(world.tree().leaves().iter().all(|(pos, _)| pos[1] == 0));
+    }
+
+    fn level(world: &World, pos: Vector3<i32>) -> u8 {
+        let materials = MaterialRegistry::default();
+        world.get(pos).map_or(0, |m| materials.level(m))
+    }
+
+    #[test]
+    fn water_spreads_a_level_lower_each_voxel() {
+        let mut world = World::new();
+        world.set([0, 0, 0], id("water"));
+        let mut physics = Physics::new();
+        physics.wake([[0, 0, 0]]);
+        settle(&mut physics, &mut world, above_floor);
+        assert_eq!(MAX_LEVEL, level(&world, [0, 0, 0]));
+        for x in 1..MAX_LEVEL as i32 {
+            assert_eq!(MAX_LEVEL - x as u8, level(&world, [x, 0, 0]));
+            assert_eq!(MAX_LEVEL - x as u8, level(&world, [0, 0, -x]));
+        }
+        assert_eq!(0, level(&world, [MAX_LEVEL as i32, 0, 0]));
+        assert_eq!(MAX_LEVEL - 2, level(&world, [1, 0, 1]));
+        assert_eq!(0, level(&world, [0, 1, 0]));
+    }
+
+    #[test]
+    fn water_falls_then_spreads() {
+        let mut world = World::new();
+        // a floor at y 3 with a hole next to the source
+        world.fill_box([-8, 3, -8], [8, 3, 8], Some(id("stone")));
+        world.remove([1, 3, 0]);
+        world.set([0, 4, 0], id("water"));
+        let mut physics = Physics::new();
+        physics.wake([[0, 4, 0]]);
+        settle(&mut physics, &mut world, above_floor);
+        assert_eq!(MAX_LEVEL - 1, level(&world, [1, 4, 0]));
+        for y in 0..=3 {
+            assert_eq!(MAX_LEVEL - 1, level(&world, [1, y, 0]));
+        }
+        assert_eq!(MAX_LEVEL - 2, level(&world, [2, 0, 0]));
+    }
+
+    #[test]
+    fn water_dries_up_without_its_source() {
+        let mut world = World::new();
+        world.set([0, 0, 0], id("water"));
+        let mut physics = Physics::new();
+        physics.wake([[0, 0, 0]]);
+        settle(&mut physics, &mut world, above_floor);
+        world.remove([0, 0, 0]);
+        physics.wake([[0, 0, 0]]);
+        settle(&mut physics, &mut world, above_floor);
+        assert_eq!(0, world.tree().count_leaves());
+    }
+
+    #[test]
+    fn flooding_undoes_with_the_source() {
+        let mut world = World::new();
+        world.set([0, 0, 0], id("water"));
+        let mut physics = Physics::new();
+        physics.wake([[0, 0, 0]]);
+        settle(&mut physics, &mut world, above_floor);
+        assert!(world.tree().count_leaves() > 1);
+        assert!(world.undo());
+        assert_eq!(0, world.tree().count_leaves());
+    }
+
+    #[test]
+    fn nothing_falls_out_of_loaded_chunks() {
+        let mut world = World::new();
+        world.set([0, 5, 0], id("sand"));
+        let mut physics = Physics::new();
+        physics.wake([[0, 5, 0]]);
+        settle(&mut physics, &mut world, |pos| pos[1] >= 3);
+        assert_eq!(Some(id("sand")), world.get([0, 3, 0]));
+    }
+
+    #[test]
+    fn falling_undoes_with_the_edit() {
+        let mut world = World::new();
+        world.set([0, 4, 0], id("sand"));
+        let mut physics = Physics::new();
+        physics.wake([[0, 4, 0]]);
+        settle(&mut physics, &mut world, above_floor);
+        assert!(world.undo());
+        assert_eq!(0, world.tree().count_leaves());
+    }
+
+    #[test]
+    fn update_steps_every_tick() {
+        let mut world = World::new();
+        world.set([0, 20, 0], id("sand"));
+        let materials = MaterialRegistry::default();
+        let mut physics = Physics::new();
+        physics.wake([[0, 20, 0]]);
+        assert_eq!(
+            0,
+            physics.update(TICK / 2, &mut world, &materials, above_floor)
+        );
+        assert_eq!(
+            2,
+            physics.update(TICK * 3 / 2, &mut world, &materials, above_floor)
+        );
+        assert_eq!(Some(id("sand")), world.get([0, 18, 0]));
+        // a long frame isn't caught up on
+        physics.update(TICK * 100, &mut world, &materials, above_floor);
+        assert_eq!(Some(id("sand")), world.get([0, 14, 0]));
+        physics.set_enabled(false);
+        assert_eq!(0, physics.update(TICK, &mut world, &materials, above_floor));
+    }
+}
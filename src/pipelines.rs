@@ -0,0 +1,204 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+
+use vulkano::{
+    device::Device,
+    pipeline::{cache::PipelineCache, ComputePipeline},
+    shader::ShaderModule,
+};
+
+use crate::{graphics::cs, materials::MaterialId, voxel::VoxelData};
+
+/// Shader features baked into a compute pipeline through specialization
+/// constants. Each distinct combination requires its own pipeline.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ShaderFeatures {
+    pub debug_octree: bool,
+    /// Traces the sun's shadows at all. Off, surfaces are lit by the sky
+    /// light alone whatever the lighting asks for.
+    pub shadows: bool,
+    /// Octree nodes a ray visits before it gives up and counts as a miss,
+    /// `i32::MAX` for no limit.
+    pub max_steps: i32,
+    pub fog: Fog,
+    pub workgroup_size: [u32; 2],
+}
+
+/// How surfaces fade into the sky with distance.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Fog {
+    Off,
+    /// Fades from a quarter of the view distance to all of it.
+    Linear,
+    Exponential,
+}
+
+impl Fog {
+    pub fn next(self) -> Self {
+        match self {
+            Fog::Off => Fog::Linear,
+            Fog::Linear => Fog::Exponential,
+            Fog::Exponential => Fog::Off,
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "off" => Some(Fog::Off),
+            "linear" => Some(Fog::Linear),
+            "exp" | "exponential" => Some(Fog::Exponential),
+            _ => None,
+        }
+    }
+}
+
+impl Default for ShaderFeatures {
+    fn default() -> Self {
+        ShaderFeatures {
+            debug_octree: true,
+            shadows: true,
+            max_steps: i32::MAX,
+            fog: Fog::Off,
+            workgroup_size: [8, 8],
+        }
+    }
+}
+
+impl ShaderFeatures {
+    fn specialization_constants(&self) -> cs::SpecializationConstants {
+        cs::SpecializationConstants {
+            DEBUG_OCTREE: self.debug_octree as u32,
+            GROUP_SIZE_X: self.workgroup_size[0],
+            GROUP_SIZE_Y: self.workgroup_size[1],
+            LEAF_WORDS: MaterialId::WORDS as i32,
+            SHADOWS: self.shadows as u32,
+            MAX_STEPS: self.max_steps,
+            FOG: self.fog as i32,
+        }
+    }
+
+    /// Returns every feature set that differs from this one by a single toggle,
+    /// i.e. the permutations the user is most likely to switch to next.
+    pub fn neighbors(&self) -> Vec<ShaderFeatures> {
+        vec![
+            ShaderFeatures {
+                debug_octree: !self.debug_octree,
+                ..*self
+            },
+            ShaderFeatures {
+                shadows: !self.shadows,
+                ..*self
+            },
+            ShaderFeatures {
+                fog: self.fog.next(),
+                ..*self
+            },
+        ]
+    }
+}
+
+/// Caches one compute pipeline per active `ShaderFeatures` permutation and
+/// compiles likely-needed permutations on background threads so switching
+/// features doesn't stall a frame.
+pub struct PermutationCache {
+    device: Arc<Device>,
+    shader: Arc<ShaderModule>,
+    vk_cache: Arc<PipelineCache>,
+    pipelines: HashMap<ShaderFeatures, Arc<ComputePipeline>>,
+    // compiling in the background
+    pending: HashMap<ShaderFeatures, JoinHandle<Arc<ComputePipeline>>>,
+}
+
+impl PermutationCache {
+    pub fn new(device: Arc<Device>) -> Self {
+        PermutationCache {
+            shader: cs::load(device.clone()).unwrap(),
+            vk_cache: PipelineCache::empty(device.clone()).unwrap(),
+            device,
+            pipelines: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Returns the pipeline for `features`, waiting for its background
+    /// compilation if one was started, or else compiling it on the calling
+    /// thread.
+    pub fn get(&mut self, features: ShaderFeatures) -> Arc<ComputePipeline> {
+        self.receive_finished();
+        if let Some(pipeline) = self.pipelines.get(&features) {
+            return pipeline.clone();
+        }
+        // what's left of a compilation under way is shorter than a new one
+        let pipeline = match self.pending.remove(&features) {
+            Some(compiling) => compiling.join().unwrap(),
+            None => compile(
+                self.device.clone(),
+                self.shader.clone(),
+                self.vk_cache.clone(),
+                features,
+            ),
+        };
+        self.pipelines.insert(features, pipeline.clone());
+        pipeline
+    }
+
+    /// Starts compiling `features` in the background if it isn't cached yet.
+    pub fn prefetch(&mut self, features: ShaderFeatures) {
+        self.receive_finished();
+        if self.pipelines.contains_key(&features) || self.pending.contains_key(&features) {
+            return;
+        }
+        let device = self.device.clone();
+        let shader = self.shader.clone();
+        let vk_cache = self.vk_cache.clone();
+        let compiling = thread::spawn(move || compile(device, shader, vk_cache, features));
+        self.pending.insert(features, compiling);
+    }
+
+    pub fn prefetch_neighbors(&mut self, features: ShaderFeatures) {
+        for neighbor in features.neighbors() {
+            self.prefetch(neighbor);
+        }
+    }
+
+    /// Compiles every permutation from `shader` from now on, dropping those
+    /// of the shader before, see `ShaderReloader`.
+    pub fn replace_shader(&mut self, shader: Arc<ShaderModule>) {
+        self.shader = shader;
+        self.pipelines.clear();
+        // compilations of the shader before finish unheard of
+        self.pending.clear();
+    }
+
+    fn receive_finished(&mut self) {
+        let finished: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, compiling)| compiling.is_finished())
+            .map(|(&features, _)| features)
+            .collect();
+        for features in finished {
+            let pipeline = self.pending.remove(&features).unwrap().join().unwrap();
+            self.pipelines.entry(features).or_insert(pipeline);
+        }
+    }
+}
+
+fn compile(
+    device: Arc<Device>,
+    shader: Arc<ShaderModule>,
+    vk_cache: Arc<PipelineCache>,
+    features: ShaderFeatures,
+) -> Arc<ComputePipeline> {
+    ComputePipeline::new(
+        device,
+        shader.entry_point("main").unwrap(),
+        &features.specialization_constants(),
+        Some(vk_cache),
+        |_| {},
+    )
+    .unwrap()
+}
@@ -0,0 +1,62 @@
+use vecmath::Vector3;
+
+use crate::raycast::RaycastHit;
+
+/// How the next placed block is turned. The block is previewed as a
+/// translucent ghost in the cell it would go in, its material is the one
+/// selected in the hotbar.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Placement {
+    /// Quarter turns around the y axis, from 0 to 3.
+    pub rotation: u32,
+}
+
+impl Placement {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rotate(&mut self) {
+        self.rotation = (self.rotation + 1) % 4
+    }
+}
+
+/// The cell a block placed against the hit face goes in, or None when the ray
+/// started inside the voxel and there is no face.
+pub fn target(hit: &RaycastHit) -> Option<Vector3<i32>> {
+    if hit.normal == [0; 3] {
+        return None;
+    }
+    Some(vecmath::vec3_add(hit.pos, hit.normal))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_is_in_front_of_face() {
+        let hit = RaycastHit {
+            pos: [1, 2, 3],
+            normal: [0, -1, 0],
+            distance: 2.0,
+        };
+        assert_eq!(Some([1, 1, 3]), target(&hit));
+        assert_eq!(
+            None,
+            target(&RaycastHit {
+                normal: [0; 3],
+                ..hit
+            })
+        );
+    }
+
+    #[test]
+    fn rotation_wraps() {
+        let mut placement = Placement::new();
+        for expected in [1, 2, 3, 0] {
+            placement.rotate();
+            assert_eq!(expected, placement.rotation);
+        }
+    }
+}
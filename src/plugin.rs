@@ -0,0 +1,153 @@
+use std::{collections::VecDeque, time::Duration};
+
+use winit::event::VirtualKeyCode;
+
+use crate::{materials::MaterialId, octree::VoxelChange, worldgen::ChunkPos};
+
+/// Something that happened in the app, which plugins can react to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AppEvent {
+    /// Voxels changed by edits, undo or redo, oldest first.
+    VoxelChanged(Vec<VoxelChange<MaterialId>>),
+    /// A chunk was generated or received and added to the world.
+    ChunkLoaded(ChunkPos),
+    /// A frame was drawn, this long after the one before.
+    FrameRendered(Duration),
+    /// A key was pressed while the world can be edited.
+    KeyAction(VirtualKeyCode),
+    /// The renderer was created again after the device was lost, without
+    /// anything sent to the one before.
+    RendererRecreated,
+}
+
+/// A feature that works on the app `C` when events happen, instead of the
+/// app calling it.
+pub trait Plugin<C> {
+    fn handle(&mut self, context: &mut C, event: &AppEvent);
+}
+
+/// Queues events until they are dispatched to every plugin.
+pub struct EventBus<C> {
+    plugins: Vec<Box<dyn Plugin<C>>>,
+    queue: VecDeque<AppEvent>,
+}
+
+impl<C> Default for EventBus<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C> EventBus<C> {
+    pub fn new() -> Self {
+        EventBus {
+            plugins: Vec::new(),
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Adds a plugin, which gets events after those added before it.
+    pub fn add(&mut self, plugin: impl Plugin<C> + 'static) {
+        self.plugins.push(Box::new(plugin));
+    }
+
+    pub fn publish(&mut self, event: AppEvent) {
+        self.queue.push_back(event);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+impl<C: AsMut<EventBus<C>>> EventBus<C> {
+    /// Hands the queued events to the plugins of the context's bus in order,
+    /// including those the plugins publish meanwhile.
+    pub fn dispatch(context: &mut C) {
+        while let Some(event) = context.as_mut().queue.pop_front() {
+            // taken out so the plugins can work on the context
+            let mut plugins = std::mem::take(&mut context.as_mut().plugins);
+            for plugin in &mut plugins {
+                plugin.handle(context, &event);
+            }
+            let bus = context.as_mut();
+            plugins.append(&mut bus.plugins);
+            bus.plugins = plugins;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct App {
+        bus: EventBus<App>,
+        log: Vec<String>,
+    }
+
+    impl AsMut<EventBus<App>> for App {
+        fn as_mut(&mut self) -> &mut EventBus<App> {
+            &mut self.bus
+        }
+    }
+
+    // logs every event, loading the next chunk over after each one
+    struct Loader(&'static str);
+
+    impl Plugin<App> for Loader {
+        fn handle(&mut self, app: &mut App, event: &AppEvent) {
+            app.log.push(format!("{} {:?}", self.0, event));
+            match event {
+                AppEvent::ChunkLoaded([x, 0, 0]) if *x < 1 => {
+                    app.bus.publish(AppEvent::ChunkLoaded([x + 1, 0, 0]))
+                }
+                AppEvent::KeyAction(_) => app.bus.add(Loader("late")),
+                _ => (),
+            }
+        }
+    }
+
+    #[test]
+    fn plugins_get_events_in_order() {
+        let mut app = App::default();
+        app.bus.add(Loader("a"));
+        app.bus.add(Loader("b"));
+        app.bus.publish(AppEvent::ChunkLoaded([0, 0, 0]));
+        app.bus
+            .publish(AppEvent::FrameRendered(Duration::from_millis(5)));
+        EventBus::dispatch(&mut app);
+        assert!(app.bus.is_empty());
+        assert_eq!(
+            vec![
+                "a ChunkLoaded([0, 0, 0])",
+                "b ChunkLoaded([0, 0, 0])",
+                "a FrameRendered(5ms)",
+                "b FrameRendered(5ms)",
+                "a ChunkLoaded([1, 0, 0])",
+                "b ChunkLoaded([1, 0, 0])",
+                "a ChunkLoaded([1, 0, 0])",
+                "b ChunkLoaded([1, 0, 0])",
+            ],
+            app.log
+        );
+    }
+
+    #[test]
+    fn plugins_added_while_dispatching_get_later_events() {
+        let mut app = App::default();
+        app.bus.add(Loader("a"));
+        app.bus.publish(AppEvent::KeyAction(VirtualKeyCode::F5));
+        app.bus.publish(AppEvent::FrameRendered(Duration::ZERO));
+        EventBus::dispatch(&mut app);
+        assert_eq!(
+            vec![
+                "a KeyAction(F5)",
+                "a FrameRendered(0ns)",
+                "late FrameRendered(0ns)",
+            ],
+            app.log
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use vecmath::Vector3;
+
+use crate::materials::MaterialId;
+
+/// A copied block of voxels, positioned relative to its minimum corner.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoxelPrefab {
+    /// Size of the copied box along each axis.
+    pub extent: Vector3<i32>,
+    pub voxels: Vec<(Vector3<i32>, MaterialId)>,
+}
+
+impl VoxelPrefab {
+    /// Keeps the voxels inside the box from `origin` spanning `extent`.
+    pub fn new(
+        origin: Vector3<i32>,
+        extent: Vector3<i32>,
+        voxels: impl IntoIterator<Item = (Vector3<i32>, MaterialId)>,
+    ) -> Self {
+        VoxelPrefab {
+            extent,
+            voxels: voxels
+                .into_iter()
+                .map(|(pos, m)| ([0, 1, 2].map(|i| pos[i] - origin[i]), m))
+                .filter(|(pos, _)| (0..3).all(|i| (0..extent[i]).contains(&pos[i])))
+                .collect(),
+        }
+    }
+
+    /// Rotates by `quarter_turns` of 90 degrees around the y axis, counter
+    /// clockwise when seen from above. The result still starts at 0.
+    pub fn rotated(&self, quarter_turns: i32) -> Self {
+        let mut prefab = self.clone();
+        for _ in 0..quarter_turns.rem_euclid(4) {
+            let [w, h, d] = prefab.extent;
+            prefab.extent = [d, h, w];
+            for (pos, _) in prefab.voxels.iter_mut() {
+                *pos = [pos[2], pos[1], w - 1 - pos[0]];
+            }
+        }
+        prefab
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefab() -> VoxelPrefab {
+        VoxelPrefab::new(
+            [10, 0, 0],
+            [3, 1, 2],
+            [([10, 0, 0], 1), ([12, 0, 1], 2), ([13, 0, 0], 3)],
+        )
+    }
+
+    #[test]
+    fn new_keeps_relative_voxels_in_box() {
+        let prefab = prefab();
+        assert_eq!(vec![([0, 0, 0], 1), ([2, 0, 1], 2)], prefab.voxels);
+    }
+
+    #[test]
+    fn rotate_quarter_turn() {
+        let rotated = prefab().rotated(1);
+        assert_eq!([2, 1, 3], rotated.extent);
+        assert_eq!(vec![([0, 0, 2], 1), ([1, 0, 0], 2)], rotated.voxels);
+    }
+
+    #[test]
+    fn four_turns_is_identity() {
+        assert_eq!(prefab(), prefab().rotated(4));
+        assert_eq!(prefab().rotated(-1), prefab().rotated(3));
+    }
+}
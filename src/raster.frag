@@ -0,0 +1,29 @@
+#version 450
+
+layout(location = 0) in vec3 v_normal;
+layout(location = 1) flat in int v_material;
+
+layout(location = 0) out vec4 f_color;
+
+// same layout as in graphics.comp
+layout(set = 0, binding = 0) uniform Lighting {
+    vec3 sun_dir;
+    float ambient;
+    vec3 sun_color;
+    float sun_cos_radius;
+    vec3 sky_color;
+    // only the ray tracer draws shadows
+    int sun_shadow_samples;
+    float sun_shadow_cone;
+} lighting;
+
+// color of every material id
+layout(set = 0, binding = 1) readonly buffer Colors {
+    vec4 data[];
+} colors;
+
+void main() {
+    vec3 albedo = colors.data[v_material].rgb;
+    float sun = max(dot(normalize(v_normal), lighting.sun_dir), 0.0);
+    f_color = vec4(albedo * (lighting.ambient + sun * lighting.sun_color), 1.0);
+}
@@ -0,0 +1,667 @@
+use std::{collections::HashMap, mem, sync::Arc};
+
+use bytemuck::{Pod, Zeroable};
+use tracing::{debug, error, info};
+use vecmath::Vector3;
+use vulkano::{
+    buffer::{BufferAccess, BufferUsage, CpuAccessibleBuffer, TypedBufferAccess},
+    command_buffer::{
+        AutoCommandBufferBuilder, CommandBufferUsage, RenderPassBeginInfo, SubpassContents,
+    },
+    descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet},
+    device::{Device, DeviceCreateInfo, DeviceExtensions, Features, Queue, QueueCreateInfo},
+    format::Format,
+    image::{view::ImageView, AttachmentImage, ImageAccess, SwapchainImage},
+    impl_vertex,
+    pipeline::{
+        graphics::{
+            depth_stencil::DepthStencilState,
+            input_assembly::InputAssemblyState,
+            rasterization::{CullMode, RasterizationState},
+            vertex_input::BuffersDefinition,
+            viewport::{Viewport, ViewportState},
+        },
+        GraphicsPipeline, Pipeline, PipelineBindPoint,
+    },
+    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
+    swapchain::{
+        acquire_next_image, AcquireError, PresentMode, Surface, Swapchain, SwapchainCreateInfo,
+        SwapchainCreationError,
+    },
+    sync::{self, FlushError, GpuFuture},
+};
+use winit::window::Window;
+
+use crate::{
+    aabc::Aabc,
+    camera::{self, Frustum},
+    debug_draw::Line,
+    decals::DecalList,
+    entity::EntityList,
+    frames_in_flight::DEFAULT_FRAMES_IN_FLIGHT,
+    gpu_memory::{self, MemoryKind, MemoryUsage, Resident},
+    graphics::{
+        self,
+        cs::ty::{CameraInfo, HudInfo, Lighting},
+        Graphics, GraphicsCreationError,
+    },
+    materials::{MaterialId, MaterialRegistry},
+    mesh,
+    octree::Octree,
+    renderer::Renderer,
+    scheduler::{DirtyChunks, Workers},
+    status::Status,
+    view_distance::ViewDistance,
+};
+
+const NEAR: f32 = 0.05;
+// dirty chunks taken out of the tree each frame, so a burst of edits is
+// remeshed over a few frames instead of hitching one
+const MAX_CHUNKS_PER_FRAME: usize = 2;
+const REMESH_THREADS: usize = 2;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+struct RasterVertex {
+    position: [f32; 3],
+    normal: [f32; 3],
+    material: i32,
+}
+
+impl_vertex!(RasterVertex, position, normal, material);
+
+struct Chunk {
+    bounds: Aabc,
+    // kept to upload the chunk again after it was evicted
+    mesh: mesh::Mesh,
+    // None while evicted
+    buffers: Option<ChunkBuffers>,
+    last_visible: u64,
+}
+
+struct ChunkBuffers {
+    vertices: Arc<CpuAccessibleBuffer<[RasterVertex]>>,
+    indices: Arc<CpuAccessibleBuffer<[u32]>>,
+}
+
+impl ChunkBuffers {
+    fn bytes(&self) -> u64 {
+        self.vertices.size() + self.indices.size()
+    }
+}
+
+// the voxels of a chunk to mesh on a worker thread
+struct RemeshJob {
+    id: u64,
+    chunk: Aabc,
+    voxels: HashMap<Vector3<i32>, MaterialId>,
+    materials: Arc<MaterialRegistry>,
+}
+
+/// Fallback renderer drawing greedy meshed chunks of the world with a
+/// graphics pipeline, for devices where the ray tracer is too slow. Voxels
+/// get the flat color of their material, translucent ones are drawn opaque,
+/// and decals, debug lines, entities and the HUD aren't drawn.
+pub struct RasterRenderer {
+    surface: Arc<Surface<Window>>,
+    recreate_swapchain: bool,
+    // see Renderer::is_lost
+    lost: bool,
+    previous_frame_end: Option<Box<dyn GpuFuture>>,
+    swapchain: Arc<Swapchain<Window>>,
+    render_pass: Arc<RenderPass>,
+    framebuffers: Vec<Arc<Framebuffer>>,
+    pipeline: Arc<GraphicsPipeline>,
+    queue: Arc<Queue>,
+    camera: CameraInfo,
+    // culled against instead of camera while frozen
+    cull_camera: Option<CameraInfo>,
+    // kept to remesh the world, translucent voxels don't hide faces
+    materials: Arc<MaterialRegistry>,
+    color_buffer: Arc<CpuAccessibleBuffer<[[f32; 4]]>>,
+    lighting: Lighting,
+    lighting_buffer: Arc<CpuAccessibleBuffer<fs::ty::Lighting>>,
+    chunks: Vec<Chunk>,
+    remesh: Workers<RemeshJob, (u64, Aabc, mesh::Mesh)>,
+    // the latest job of each chunk being remeshed, by origin
+    remeshing: HashMap<Vector3<i32>, u64>,
+    next_remesh: u64,
+    // the far plane, see ViewDistance
+    view_distance: f32,
+    // counts the frames drawn, to tell which chunks were seen last
+    frame: u64,
+    memory_budget: Option<u64>,
+}
+
+impl RasterRenderer {
+    pub fn new(
+        surface: Arc<Surface<Window>>,
+        camera_info: CameraInfo,
+        tree: &Octree<MaterialId>,
+        materials: &MaterialRegistry,
+        lighting: Lighting,
+    ) -> Result<Self, GraphicsCreationError> {
+        let device_extensions = DeviceExtensions {
+            khr_swapchain: true,
+            ..DeviceExtensions::none()
+        };
+        let (physical_device, queue_family) =
+            graphics::select_physical_device(&surface, &device_extensions, &Features::none())
+                .ok_or(GraphicsCreationError::NoSuitableDevice)?;
+
+        info!(
+            device = %physical_device.properties().device_name,
+            kind = ?physical_device.properties().device_type,
+            "Rasterizing"
+        );
+
+        let (device, mut queues) = Device::new(
+            physical_device,
+            DeviceCreateInfo {
+                enabled_extensions: device_extensions,
+                queue_create_infos: vec![QueueCreateInfo::family(queue_family)],
+                ..DeviceCreateInfo::default()
+            },
+        )
+        .map_err(GraphicsCreationError::Device)?;
+        let queue = queues.next().unwrap();
+
+        let (swapchain, swapchain_images) = graphics::create_swapchain(
+            device.clone(),
+            surface.clone(),
+            PresentMode::Fifo,
+            DEFAULT_FRAMES_IN_FLIGHT,
+        );
+
+        let render_pass = vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                color: {
+                    load: Clear,
+                    store: Store,
+                    format: swapchain.image_format(),
+                    samples: 1,
+                },
+                depth: {
+                    load: Clear,
+                    store: DontCare,
+                    format: Format::D16_UNORM,
+                    samples: 1,
+                }
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {depth}
+            }
+        )
+        .unwrap();
+
+        let vs = vs::load(device.clone()).unwrap();
+        let fs = fs::load(device.clone()).unwrap();
+        let pipeline = GraphicsPipeline::start()
+            .vertex_input_state(BuffersDefinition::new().vertex::<RasterVertex>())
+            .vertex_shader(vs.entry_point("main").unwrap(), ())
+            .input_assembly_state(InputAssemblyState::new())
+            .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+            .fragment_shader(fs.entry_point("main").unwrap(), ())
+            .rasterization_state(RasterizationState::new().cull_mode(CullMode::Back))
+            .depth_stencil_state(DepthStencilState::simple_depth_test())
+            .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+            .build(device.clone())
+            .unwrap();
+
+        let mut renderer = RasterRenderer {
+            surface,
+            recreate_swapchain: false,
+            lost: false,
+            previous_frame_end: Some(sync::now(device.clone()).boxed()),
+            swapchain,
+            framebuffers: Self::create_framebuffers(&swapchain_images, &render_pass),
+            render_pass,
+            pipeline,
+            queue,
+            camera: camera_info,
+            cull_camera: None,
+            materials: Arc::new(materials.clone()),
+            color_buffer: Self::create_color_buffer(device.clone(), materials),
+            lighting,
+            lighting_buffer: Self::create_lighting_buffer(device, lighting),
+            chunks: Vec::new(),
+            remesh: Workers::new(REMESH_THREADS, |job: RemeshJob| {
+                let mesh = mesh::mesh_voxels(&job.voxels, job.chunk, &job.materials);
+                (job.id, job.chunk, mesh)
+            }),
+            remeshing: HashMap::new(),
+            next_remesh: 0,
+            view_distance: ViewDistance::default().voxels(),
+            frame: 0,
+            memory_budget: None,
+        };
+        renderer.update_octree(tree);
+        Ok(renderer)
+    }
+
+    fn set_lost(&mut self) {
+        self.lost = true;
+        // the last frame never finishes on a lost device, and dropping its
+        // fence would wait for it
+        if let Some(end) = self.previous_frame_end.take() {
+            mem::forget(end);
+        }
+    }
+
+    fn create_framebuffers(
+        images: &[Arc<SwapchainImage<Window>>],
+        render_pass: &Arc<RenderPass>,
+    ) -> Vec<Arc<Framebuffer>> {
+        let size = images[0].dimensions().width_height();
+        let depth = ImageView::new_default(
+            AttachmentImage::transient(render_pass.device().clone(), size, Format::D16_UNORM)
+                .unwrap(),
+        )
+        .unwrap();
+        images
+            .iter()
+            .map(|image| {
+                Framebuffer::new(
+                    render_pass.clone(),
+                    FramebufferCreateInfo {
+                        attachments: vec![
+                            ImageView::new_default(image.clone()).unwrap(),
+                            depth.clone(),
+                        ],
+                        ..Default::default()
+                    },
+                )
+                .unwrap()
+            })
+            .collect()
+    }
+
+    fn create_color_buffer(
+        device: Arc<Device>,
+        materials: &MaterialRegistry,
+    ) -> Arc<CpuAccessibleBuffer<[[f32; 4]]>> {
+        let colors = (0..materials.len() as MaterialId).map(|id| {
+            materials.get(id).map_or([0.0; 4], |m| {
+                let [r, g, b] = m.color.map(|c| c as f32 / 255.0);
+                [r, g, b, m.opacity]
+            })
+        });
+        CpuAccessibleBuffer::from_iter(
+            device,
+            BufferUsage {
+                storage_buffer: true,
+                ..BufferUsage::none()
+            },
+            false,
+            colors,
+        )
+        .unwrap()
+    }
+
+    fn create_lighting_buffer(
+        device: Arc<Device>,
+        lighting: Lighting,
+    ) -> Arc<CpuAccessibleBuffer<fs::ty::Lighting>> {
+        CpuAccessibleBuffer::from_data(
+            device,
+            BufferUsage {
+                uniform_buffer: true,
+                ..BufferUsage::none()
+            },
+            false,
+            // both shaders declare the same block
+            bytemuck::cast(lighting),
+        )
+        .unwrap()
+    }
+
+    // swaps in the meshes of chunks done remeshing, unless the chunk was sent
+    // again since or the whole world was remeshed
+    fn poll_chunks(&mut self) {
+        for (id, bounds, mesh) in self.remesh.poll() {
+            if self.remeshing.get(&bounds.origin) != Some(&id) {
+                continue;
+            }
+            self.remeshing.remove(&bounds.origin);
+            // chunks of a world smaller than a chunk are smaller too
+            self.chunks.retain(|c| !bounds.contains_aabc(c.bounds));
+            if !mesh.is_empty() {
+                let chunk = self.create_chunk(bounds, mesh);
+                self.chunks.push(chunk);
+            }
+        }
+    }
+
+    fn create_chunk(&self, bounds: Aabc, mesh: mesh::Mesh) -> Chunk {
+        Chunk {
+            bounds,
+            buffers: Some(Self::create_buffers(self.queue.device(), &mesh)),
+            mesh,
+            last_visible: self.frame,
+        }
+    }
+
+    fn create_buffers(device: &Arc<Device>, mesh: &mesh::Mesh) -> ChunkBuffers {
+        let vertices = mesh.vertices.iter().map(|v| RasterVertex {
+            position: v.position,
+            normal: v.normal,
+            material: v.material,
+        });
+        ChunkBuffers {
+            vertices: CpuAccessibleBuffer::from_iter(
+                device.clone(),
+                BufferUsage {
+                    vertex_buffer: true,
+                    ..BufferUsage::none()
+                },
+                false,
+                vertices,
+            )
+            .unwrap(),
+            indices: CpuAccessibleBuffer::from_iter(
+                device.clone(),
+                BufferUsage {
+                    index_buffer: true,
+                    ..BufferUsage::none()
+                },
+                false,
+                mesh.indices.iter().copied(),
+            )
+            .unwrap(),
+        }
+    }
+
+    // drops the buffers of the least recently visible chunks while over the
+    // budget, they're uploaded again from their mesh once visible
+    fn evict(&mut self) {
+        let budget = match self.memory_budget {
+            Some(budget) => budget,
+            None => return,
+        };
+        let used = self.memory_usage().total();
+        if used <= budget {
+            return;
+        }
+        let resident: Vec<_> = self
+            .chunks
+            .iter()
+            .enumerate()
+            .filter_map(|(i, chunk)| {
+                chunk.buffers.as_ref().map(|buffers| Resident {
+                    key: i,
+                    bytes: buffers.bytes(),
+                    last_visible: chunk.last_visible,
+                })
+            })
+            .collect();
+        let evicted = gpu_memory::evictions(&resident, used - budget, self.frame);
+        if evicted.is_empty() {
+            return;
+        }
+        for &i in &evicted {
+            self.chunks[i].buffers = None;
+        }
+        debug!(
+            chunks = evicted.len(),
+            "Evicted chunks over the memory budget"
+        );
+    }
+}
+
+impl Renderer for RasterRenderer {
+    fn redraw(&mut self) {
+        self.poll_chunks();
+        let dimensions = self.surface.window().inner_size();
+        if self.lost || dimensions.width == 0 || dimensions.height == 0 {
+            return;
+        }
+
+        self.previous_frame_end.as_mut().unwrap().cleanup_finished();
+
+        if self.recreate_swapchain {
+            let (new_swapchain, new_images) = match self.swapchain.recreate(SwapchainCreateInfo {
+                image_extent: dimensions.into(),
+                ..self.swapchain.create_info()
+            }) {
+                Ok(r) => r,
+                Err(SwapchainCreationError::ImageExtentNotSupported { .. }) => return,
+                Err(SwapchainCreationError::DeviceLost | SwapchainCreationError::SurfaceLost) => {
+                    return self.set_lost()
+                }
+                Err(e) => panic!("Failed to recreate swapchain: {:?}", e),
+            };
+            self.swapchain = new_swapchain;
+            self.framebuffers = Self::create_framebuffers(&new_images, &self.render_pass);
+            self.recreate_swapchain = false;
+        }
+
+        let (next_image_idx, suboptimal, acquire_future) =
+            match acquire_next_image(self.swapchain.clone(), None) {
+                Ok(r) => r,
+                Err(AcquireError::OutOfDate) => {
+                    self.recreate_swapchain = true;
+                    return;
+                }
+                Err(AcquireError::DeviceLost | AcquireError::SurfaceLost) => {
+                    return self.set_lost()
+                }
+                Err(e) => panic!("Failed to acquire next image: {:?}", e),
+            };
+
+        if suboptimal {
+            self.recreate_swapchain = true;
+        }
+
+        let framebuffer = self.framebuffers[next_image_idx].clone();
+        let [width, height] = framebuffer.extent();
+        let aspect = width as f32 / height as f32;
+        let frustum =
+            Frustum::from_camera_info(self.cull_camera.as_ref().unwrap_or(&self.camera), aspect);
+        let desc_set = PersistentDescriptorSet::new(
+            self.pipeline.layout().set_layouts().get(0).unwrap().clone(),
+            [
+                WriteDescriptorSet::buffer(0, self.lighting_buffer.clone()),
+                WriteDescriptorSet::buffer(1, self.color_buffer.clone()),
+            ],
+        )
+        .unwrap();
+        let [r, g, b] = self.lighting.sky_color;
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.queue.device().clone(),
+            self.queue.family(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values: vec![Some([r, g, b, 1.0].into()), Some(1f32.into())],
+                    ..RenderPassBeginInfo::framebuffer(framebuffer)
+                },
+                SubpassContents::Inline,
+            )
+            .unwrap()
+            .set_viewport(
+                0,
+                [Viewport {
+                    origin: [0.0, 0.0],
+                    dimensions: [width as f32, height as f32],
+                    depth_range: 0.0..1.0,
+                }],
+            )
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.pipeline.layout().clone(),
+                0,
+                desc_set,
+            )
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                vs::ty::PushConstants {
+                    view_projection: camera::view_projection(
+                        &self.camera,
+                        aspect,
+                        NEAR,
+                        self.view_distance,
+                    ),
+                },
+            );
+        self.frame += 1;
+        let device = self.queue.device().clone();
+        for chunk in self
+            .chunks
+            .iter_mut()
+            .filter(|c| frustum.intersects_aabc(c.bounds))
+        {
+            chunk.last_visible = self.frame;
+            let buffers = chunk
+                .buffers
+                .get_or_insert_with(|| Self::create_buffers(&device, &chunk.mesh));
+            builder
+                .bind_vertex_buffers(0, buffers.vertices.clone())
+                .bind_index_buffer(buffers.indices.clone())
+                .draw_indexed(buffers.indices.len() as u32, 1, 0, 0, 0)
+                .unwrap();
+        }
+        self.evict();
+        builder.end_render_pass().unwrap();
+        let command_buffer = builder.build().unwrap();
+
+        let render_future = self
+            .previous_frame_end
+            .take()
+            .unwrap()
+            .join(acquire_future)
+            .then_execute(self.queue.clone(), command_buffer)
+            .unwrap()
+            .then_swapchain_present(self.queue.clone(), self.swapchain.clone(), next_image_idx)
+            .then_signal_fence_and_flush();
+
+        match render_future {
+            Ok(future) => {
+                self.previous_frame_end = Some(future.boxed());
+            }
+            Err(FlushError::OutOfDate) => {
+                self.recreate_swapchain = true;
+                self.previous_frame_end = Some(sync::now(self.queue.device().clone()).boxed());
+            }
+            Err(FlushError::DeviceLost | FlushError::SurfaceLost) => self.set_lost(),
+            Err(e) => {
+                error!(error = ?e, "Failed to flush future");
+                self.previous_frame_end = Some(sync::now(self.queue.device().clone()).boxed());
+            }
+        }
+    }
+
+    fn resize(&mut self) {
+        self.recreate_swapchain = true
+    }
+
+    fn is_lost(&self) -> bool {
+        self.lost
+    }
+
+    fn update_camera(&mut self, camera_info: CameraInfo) {
+        self.camera = camera_info
+    }
+
+    fn update_cull_camera(&mut self, camera: Option<CameraInfo>) {
+        self.cull_camera = camera
+    }
+
+    /// Remeshes every chunk of the tree.
+    fn update_octree(&mut self, tree: &Octree<MaterialId>) {
+        self.remeshing.clear();
+        self.chunks = mesh::mesh_chunks(tree, mesh::CHUNK_SIZE, &self.materials)
+            .into_iter()
+            .map(|(bounds, mesh)| self.create_chunk(bounds, mesh))
+            .collect();
+    }
+
+    /// Remeshes the first few dirty chunks on worker threads, the old meshes
+    /// are drawn until the new ones are done.
+    fn update_chunks(&mut self, tree: &Octree<MaterialId>, dirty: &mut DirtyChunks) {
+        for chunk in dirty.take(MAX_CHUNKS_PER_FRAME) {
+            let id = self.next_remesh;
+            self.next_remesh += 1;
+            self.remeshing.insert(chunk.origin, id);
+            self.remesh.submit(RemeshJob {
+                id,
+                chunk,
+                voxels: mesh::chunk_voxels(tree, chunk),
+                materials: self.materials.clone(),
+            });
+        }
+    }
+
+    // decals, debug lines, entities and the HUD are only drawn by the ray
+    // tracer
+    fn update_decals(&mut self, _decals: &DecalList) {}
+
+    fn update_debug_lines(&mut self, _lines: Vec<Line>) {}
+
+    fn update_entities(&mut self, _entities: &EntityList) {}
+
+    fn update_hud(&mut self, _hud_info: HudInfo) {}
+
+    fn update_lighting(&mut self, lighting: Lighting) {
+        self.lighting = lighting;
+        self.lighting_buffer = Self::create_lighting_buffer(self.queue.device().clone(), lighting)
+    }
+
+    fn update_view_distance(&mut self, voxels: f32) {
+        self.view_distance = voxels
+    }
+
+    fn update_materials(&mut self, materials: &MaterialRegistry) {
+        self.materials = Arc::new(materials.clone());
+        self.color_buffer = Self::create_color_buffer(self.queue.device().clone(), materials)
+    }
+
+    fn update_status(&mut self, status: &Status) {
+        self.surface.window().set_title(&status.title())
+    }
+
+    /// Chunks count while uploaded, evicted ones only hold their mesh in
+    /// host memory.
+    fn memory_usage(&self) -> MemoryUsage {
+        let mut usage = MemoryUsage::default();
+        for buffers in self.chunks.iter().filter_map(|c| c.buffers.as_ref()) {
+            usage.add(MemoryKind::Chunks, buffers.bytes());
+        }
+        usage.add(MemoryKind::Textures, self.color_buffer.size());
+        // the depth image all framebuffers share
+        if let Some(framebuffer) = self.framebuffers.first() {
+            let depth = framebuffer.attachments()[1].image();
+            usage.add(MemoryKind::Images, Graphics::image_bytes(&*depth));
+        }
+        usage
+    }
+
+    fn set_memory_budget(&mut self, budget: Option<u64>) {
+        self.memory_budget = budget;
+    }
+}
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/raster.vert",
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/raster.frag",
+        types_meta: {
+            use bytemuck::{Pod, Zeroable};
+            #[derive(Clone, Debug, Copy, Zeroable, Pod)]
+        }
+    }
+}
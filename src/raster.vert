@@ -0,0 +1,18 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in int material;
+
+layout(push_constant) uniform PushConstants {
+    mat4 view_projection;
+} pc;
+
+layout(location = 0) out vec3 v_normal;
+layout(location = 1) flat out int v_material;
+
+void main() {
+    gl_Position = pc.view_projection * vec4(position, 1.0);
+    v_normal = normal;
+    v_material = material;
+}
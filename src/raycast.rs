@@ -0,0 +1,111 @@
+use vecmath::{vec3_len, vec3_normalized, Vector3};
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct RaycastHit {
+    pub pos: Vector3<i32>,
+    /// Normal of the face the ray entered through. Zero if the ray started
+    /// inside the voxel.
+    pub normal: Vector3<i32>,
+    pub distance: f32,
+}
+
+/// Walks the voxel grid along a ray (Amanatides & Woo) and returns the first
+/// voxel for which `is_solid` is true, up to `max_distance` away.
+pub fn raycast<F: Fn(Vector3<i32>) -> bool>(
+    origin: Vector3<f32>,
+    dir: Vector3<f32>,
+    max_distance: f32,
+    is_solid: F,
+) -> Option<RaycastHit> {
+    if vec3_len(dir) == 0.0 {
+        return None;
+    }
+    let dir = vec3_normalized(dir);
+    let mut voxel = origin.map(|c| c.floor() as i32);
+    let mut step = [0; 3];
+    let mut t_max = [f32::INFINITY; 3];
+    let mut t_delta = [f32::INFINITY; 3];
+    for i in 0..3 {
+        if dir[i] > 0.0 {
+            step[i] = 1;
+            t_max[i] = (origin[i].floor() + 1.0 - origin[i]) / dir[i];
+            t_delta[i] = 1.0 / dir[i];
+        } else if dir[i] < 0.0 {
+            step[i] = -1;
+            t_max[i] = (origin[i] - origin[i].floor()) / -dir[i];
+            t_delta[i] = -1.0 / dir[i];
+        }
+    }
+    let mut normal = [0; 3];
+    let mut distance = 0.0;
+    loop {
+        if is_solid(voxel) {
+            return Some(RaycastHit {
+                pos: voxel,
+                normal,
+                distance,
+            });
+        }
+        let mut axis = 0;
+        for i in 1..3 {
+            if t_max[i] < t_max[axis] {
+                axis = i;
+            }
+        }
+        distance = t_max[axis];
+        if distance > max_distance {
+            return None;
+        }
+        voxel[axis] += step[axis];
+        t_max[axis] += t_delta[axis];
+        normal = [0; 3];
+        normal[axis] = -step[axis];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_direction_misses() {
+        assert_eq!(None, raycast([0.0; 3], [0.0; 3], 10.0, |_| true));
+    }
+
+    #[test]
+    fn starting_inside_solid() {
+        let hit = raycast([0.5, 0.5, 0.5], [1.0, 0.0, 0.0], 10.0, |_| true).unwrap();
+        assert_eq!([0, 0, 0], hit.pos);
+        assert_eq!([0, 0, 0], hit.normal);
+    }
+
+    #[test]
+    fn hits_along_positive_axis() {
+        let hit = raycast([0.5, 0.5, 0.5], [1.0, 0.0, 0.0], 10.0, |p| p == [3, 0, 0]).unwrap();
+        assert_eq!([3, 0, 0], hit.pos);
+        assert_eq!([-1, 0, 0], hit.normal);
+        assert_eq!(2.5, hit.distance);
+    }
+
+    #[test]
+    fn hits_along_negative_axis() {
+        let hit = raycast([0.5, 0.5, 0.5], [0.0, 0.0, -1.0], 10.0, |p| p == [0, 0, -2]).unwrap();
+        assert_eq!([0, 0, -2], hit.pos);
+        assert_eq!([0, 0, 1], hit.normal);
+        assert_eq!(1.5, hit.distance);
+    }
+
+    #[test]
+    fn hits_diagonally() {
+        let hit = raycast([0.5, 0.5, 0.5], [1.0, 1.0, 1.0], 10.0, |p| p == [2, 2, 2]).unwrap();
+        assert_eq!([2, 2, 2], hit.pos);
+    }
+
+    #[test]
+    fn respects_max_distance() {
+        assert_eq!(
+            None,
+            raycast([0.5, 0.5, 0.5], [1.0, 0.0, 0.0], 2.0, |p| p == [3, 0, 0])
+        );
+    }
+}
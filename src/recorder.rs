@@ -0,0 +1,153 @@
+use std::{path::PathBuf, sync::Arc};
+
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer},
+    command_buffer::{AutoCommandBufferBuilder, CopyImageToBufferInfo, PrimaryAutoCommandBuffer},
+    device::Device,
+    image::{ImageAccess, StorageImage},
+    memory::pool::StdMemoryPool,
+};
+
+use crate::io::frames::{Frame, FrameWriter};
+
+// frames being copied to the host at once, the copy of a frame is read back
+// when its slot comes around again
+const RING_SIZE: usize = 3;
+// frames read back but not written yet
+const WRITE_QUEUE: usize = 8;
+
+struct Slot {
+    buffer: Arc<CpuAccessibleBuffer<[u8]>>,
+    size: [u32; 2],
+    // whether a frame is being copied into the buffer
+    pending: bool,
+}
+
+/// Frame counts of a finished recording.
+#[derive(Debug)]
+pub struct RecordingSummary {
+    pub written: u32,
+    pub dropped: u32,
+}
+
+/// Records rendered frames as numbered PNGs. Each frame is copied into one of
+/// a ring of host visible buffers by the frame's own command buffer and read
+/// back once the GPU is done with it, so recording never waits on the GPU.
+/// Frames are dropped rather than stalling when the ring or the writer is
+/// full.
+pub struct FrameRecorder {
+    device: Arc<Device>,
+    slots: Vec<Slot>,
+    next_slot: usize,
+    // index of the next frame the writer takes, so dropped frames leave no
+    // gaps
+    next_frame: u32,
+    dropped: u32,
+    writer: FrameWriter,
+}
+
+impl FrameRecorder {
+    pub fn new(device: Arc<Device>, dir: PathBuf) -> std::io::Result<Self> {
+        Ok(FrameRecorder {
+            device,
+            slots: Vec::new(),
+            next_slot: 0,
+            next_frame: 0,
+            dropped: 0,
+            writer: FrameWriter::new(dir, WRITE_QUEUE)?,
+        })
+    }
+
+    fn create_buffer(device: Arc<Device>, size: [u32; 2]) -> Arc<CpuAccessibleBuffer<[u8]>> {
+        let len = size[0] as usize * size[1] as usize * 4;
+        CpuAccessibleBuffer::from_iter(
+            device,
+            BufferUsage::transfer_dst(),
+            true,
+            (0..len).map(|_| 0u8),
+        )
+        .unwrap()
+    }
+
+    /// Hands the frames whose copies finished to the writer, oldest first.
+    /// Buffers the GPU still uses can't be read yet, nor the ones after them.
+    pub fn collect(&mut self) {
+        let len = self.slots.len();
+        for i in 0..len {
+            let slot = &mut self.slots[(self.next_slot + i) % len];
+            if !slot.pending {
+                continue;
+            }
+            let pixels = match slot.buffer.read() {
+                Ok(data) => data.to_vec(),
+                Err(_) => break,
+            };
+            slot.pending = false;
+            let frame = Frame {
+                index: self.next_frame,
+                size: slot.size,
+                pixels,
+            };
+            if self.writer.send(frame) {
+                self.next_frame += 1;
+            } else {
+                self.dropped += 1;
+            }
+        }
+    }
+
+    /// Moves the recording to another device, e.g. after the old one was
+    /// lost, dropping the frames still being copied on the old one.
+    pub fn move_to(&mut self, device: Arc<Device>) {
+        self.dropped += self.slots.iter().filter(|s| s.pending).count() as u32;
+        self.slots.clear();
+        self.next_slot = 0;
+        self.device = device;
+    }
+
+    /// Records the copy of `image`, which must be RGBA8, into the next slot of
+    /// the ring.
+    pub fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        image: Arc<StorageImage<Arc<StdMemoryPool>>>,
+    ) {
+        self.collect();
+        let size = image.dimensions().width_height();
+        if self.slots.len() < RING_SIZE {
+            self.slots.push(Slot {
+                buffer: Self::create_buffer(self.device.clone(), size),
+                size,
+                pending: false,
+            });
+        }
+        let slot = &mut self.slots[self.next_slot];
+        if slot.pending {
+            self.dropped += 1;
+            return;
+        }
+        if slot.size != size {
+            slot.buffer = Self::create_buffer(self.device.clone(), size);
+            slot.size = size;
+        }
+        builder
+            .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+                image,
+                slot.buffer.clone(),
+            ))
+            .unwrap();
+        slot.pending = true;
+        self.next_slot = (self.next_slot + 1) % RING_SIZE;
+    }
+
+    /// Writes the remaining frames, which must not be in use by the GPU
+    /// anymore.
+    pub fn finish(mut self) -> RecordingSummary {
+        self.collect();
+        let unread = self.slots.iter().filter(|s| s.pending).count() as u32;
+        RecordingSummary {
+            written: self.writer.finish(),
+            dropped: self.dropped + unread,
+        }
+    }
+}
@@ -0,0 +1,59 @@
+/// What the ray tracer draws. Everything but `Shaded` and `PathTraced` is
+/// for debugging.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderMode {
+    Shaded,
+    /// Heatmap of the octree traversal steps of the primary ray.
+    Steps,
+    Depth,
+    Normals,
+    /// A distinct color per material id.
+    Material,
+    /// Shaded, with the edges of the meshing chunks outlined.
+    Chunks,
+    /// Progressive path tracing, which converges while nothing moves.
+    PathTraced,
+}
+
+impl RenderMode {
+    pub const ALL: [RenderMode; 7] = [
+        RenderMode::Shaded,
+        RenderMode::Steps,
+        RenderMode::Depth,
+        RenderMode::Normals,
+        RenderMode::Material,
+        RenderMode::Chunks,
+        RenderMode::PathTraced,
+    ];
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self.index() as usize + 1) % Self::ALL.len()]
+    }
+
+    /// The value graphics.comp switches on.
+    pub fn index(self) -> i32 {
+        self as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_cycles_through_all() {
+        let mut mode = RenderMode::Shaded;
+        for expected in RenderMode::ALL.iter().skip(1) {
+            mode = mode.next();
+            assert_eq!(*expected, mode);
+        }
+        assert_eq!(RenderMode::Shaded, mode.next());
+    }
+
+    #[test]
+    fn index_matches_position() {
+        for (i, mode) in RenderMode::ALL.iter().enumerate() {
+            assert_eq!(i as i32, mode.index());
+        }
+    }
+}
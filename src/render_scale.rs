@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+pub const MIN_RENDER_SCALE: f32 = 0.5;
+pub const MAX_RENDER_SCALE: f32 = 2.0;
+
+// automatic scaling moves in steps of this size so the render target isn't
+// recreated every frame
+const SCALE_STEP: f32 = 0.05;
+// frame times within this fraction of the target don't change the scale
+const DEAD_BAND: f32 = 0.1;
+// weight of the newest frame time in the moving average
+const SMOOTHING: f32 = 0.1;
+
+/// Ratio between the ray tracing resolution and the swapchain resolution,
+/// optionally adjusted automatically to hold a frame time budget.
+pub struct RenderScale {
+    scale: f32,
+    target_frame_time: Option<Duration>,
+    smoothed_frame_time: Option<f32>,
+}
+
+impl RenderScale {
+    pub fn new(scale: f32) -> Self {
+        RenderScale {
+            scale: scale.clamp(MIN_RENDER_SCALE, MAX_RENDER_SCALE),
+            target_frame_time: None,
+            smoothed_frame_time: None,
+        }
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale.clamp(MIN_RENDER_SCALE, MAX_RENDER_SCALE);
+    }
+
+    pub fn target_frame_time(&self) -> Option<Duration> {
+        self.target_frame_time
+    }
+
+    /// Enables automatic scaling towards `target`, or disables it with `None`.
+    pub fn set_target_frame_time(&mut self, target: Option<Duration>) {
+        self.target_frame_time = target;
+        self.smoothed_frame_time = None;
+    }
+
+    /// Feeds a measured frame time into the automatic scaling. Returns true if
+    /// the scale changed.
+    pub fn update(&mut self, frame_time: Duration) -> bool {
+        let target = match self.target_frame_time {
+            Some(t) => t.as_secs_f32(),
+            None => return false,
+        };
+        let frame_time = frame_time.as_secs_f32();
+        let smoothed = match self.smoothed_frame_time {
+            Some(s) => s + SMOOTHING * (frame_time - s),
+            None => frame_time,
+        };
+        self.smoothed_frame_time = Some(smoothed);
+        if smoothed <= 0.0 || (smoothed - target).abs() <= DEAD_BAND * target {
+            return false;
+        }
+        let step = if smoothed > target {
+            -SCALE_STEP
+        } else {
+            SCALE_STEP
+        };
+        let scale = ((self.scale + step) / SCALE_STEP).round() * SCALE_STEP;
+        let scale = scale.clamp(MIN_RENDER_SCALE, MAX_RENDER_SCALE);
+        if scale == self.scale {
+            return false;
+        }
+        self.scale = scale;
+        true
+    }
+
+    /// Returns the render resolution for a given swapchain resolution.
+    pub fn apply(&self, size: [u32; 2]) -> [u32; 2] {
+        [
+            ((size[0] as f32 * self.scale).round() as u32).max(1),
+            ((size[1] as f32 * self.scale).round() as u32).max(1),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale_is_clamped() {
+        let mut scale = RenderScale::new(5.0);
+        assert_eq!(MAX_RENDER_SCALE, scale.scale());
+        scale.set_scale(0.0);
+        assert_eq!(MIN_RENDER_SCALE, scale.scale());
+    }
+
+    #[test]
+    fn apply_scales_size() {
+        let scale = RenderScale::new(0.5);
+        assert_eq!([400, 300], scale.apply([800, 600]));
+    }
+
+    #[test]
+    fn no_target_never_changes() {
+        let mut scale = RenderScale::new(1.0);
+        assert!(!scale.update(Duration::from_secs(1)));
+        assert_eq!(1.0, scale.scale());
+    }
+
+    #[test]
+    fn slow_frames_lower_scale() {
+        let mut scale = RenderScale::new(1.0);
+        scale.set_target_frame_time(Some(Duration::from_millis(16)));
+        assert!(scale.update(Duration::from_millis(40)));
+        assert!(scale.scale() < 1.0);
+    }
+
+    #[test]
+    fn fast_frames_raise_scale() {
+        let mut scale = RenderScale::new(1.0);
+        scale.set_target_frame_time(Some(Duration::from_millis(16)));
+        assert!(scale.update(Duration::from_millis(4)));
+        assert!(scale.scale() > 1.0);
+    }
+
+    #[test]
+    fn frames_within_budget_keep_scale() {
+        let mut scale = RenderScale::new(1.0);
+        scale.set_target_frame_time(Some(Duration::from_millis(16)));
+        assert!(!scale.update(Duration::from_millis(17)));
+        assert_eq!(1.0, scale.scale());
+    }
+
+    #[test]
+    fn scale_stays_in_range() {
+        let mut scale = RenderScale::new(1.0);
+        scale.set_target_frame_time(Some(Duration::from_millis(16)));
+        for _ in 0..100 {
+            scale.update(Duration::from_secs(1));
+        }
+        assert_eq!(MIN_RENDER_SCALE, scale.scale());
+    }
+}
@@ -0,0 +1,211 @@
+use std::{io, path::Path};
+
+use crate::{
+    debug_draw::Line,
+    decals::DecalList,
+    entity::EntityList,
+    gpu_memory::MemoryUsage,
+    graphics::{
+        cs::ty::{CameraInfo, HudInfo, Lighting},
+        Graphics,
+    },
+    materials::{MaterialId, MaterialRegistry},
+    octree::Octree,
+    scheduler::DirtyChunks,
+    status::Status,
+};
+
+/// What the game loop needs from a renderer. Settings that only make sense for
+/// the ray tracer are reached through `ray_tracer`.
+pub trait Renderer {
+    fn redraw(&mut self);
+
+    /// Recreates the swapchain on the next redraw, after the window changed.
+    fn resize(&mut self);
+
+    fn update_camera(&mut self, camera_info: CameraInfo);
+
+    /// Freezes the camera the world is culled against at `camera` while the
+    /// one drawn from keeps moving, to see what's culled. None follows the
+    /// drawn camera again.
+    fn update_cull_camera(&mut self, camera: Option<CameraInfo>);
+
+    fn update_octree(&mut self, tree: &Octree<MaterialId>);
+
+    /// Sends the chunks of `dirty` that fit in this frame, removing them from
+    /// it. Called every frame, so work on earlier chunks can be picked up.
+    /// Renderers that can't update part of the world send all of it.
+    fn update_chunks(&mut self, tree: &Octree<MaterialId>, dirty: &mut DirtyChunks) {
+        if !dirty.is_empty() {
+            dirty.clear();
+            self.update_octree(tree)
+        }
+    }
+
+    fn update_decals(&mut self, decals: &DecalList);
+
+    /// Lines to draw over the next frame, see `debug_draw`.
+    fn update_debug_lines(&mut self, lines: Vec<Line>);
+
+    fn update_entities(&mut self, entities: &EntityList);
+
+    fn update_hud(&mut self, hud_info: HudInfo);
+
+    fn update_lighting(&mut self, lighting: Lighting);
+
+    /// How far the world is drawn, in voxels, see `ViewDistance`.
+    fn update_view_distance(&mut self, voxels: f32);
+
+    fn update_materials(&mut self, materials: &MaterialRegistry);
+
+    fn update_status(&mut self, status: &Status);
+
+    /// Device memory held for the world and the frames, see `gpu_memory`.
+    fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage::default()
+    }
+
+    /// Evicts the least recently visible chunks while more than `budget`
+    /// bytes are held, None for no limit. Renderers holding the whole world
+    /// have nothing to evict, the game loop streams less of it instead.
+    fn set_memory_budget(&mut self, _: Option<u64>) {}
+
+    /// Whether the device or the surface was lost, after which the renderer
+    /// draws nothing and has to be created again.
+    fn is_lost(&self) -> bool;
+
+    /// Renders the view at `width` by `height` pixels into a PNG at `path`, in
+    /// tiles at most `tile_size` pixels across, see `tiles`.
+    fn render_tiled(
+        &mut self,
+        _width: u32,
+        _height: u32,
+        _tile_size: u32,
+        _path: &Path,
+    ) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Only the ray tracer renders tiles",
+        ))
+    }
+
+    fn ray_tracer(&mut self) -> Option<&mut Graphics> {
+        None
+    }
+}
+
+impl Renderer for Graphics {
+    fn redraw(&mut self) {
+        Graphics::redraw(self)
+    }
+
+    fn resize(&mut self) {
+        self.recreate_swapchain = true
+    }
+
+    fn update_camera(&mut self, camera_info: CameraInfo) {
+        Graphics::update_camera(self, camera_info)
+    }
+
+    fn update_cull_camera(&mut self, camera: Option<CameraInfo>) {
+        Graphics::update_cull_camera(self, camera)
+    }
+
+    fn update_octree(&mut self, tree: &Octree<MaterialId>) {
+        Graphics::update_octree(self, tree)
+    }
+
+    fn update_chunks(&mut self, tree: &Octree<MaterialId>, dirty: &mut DirtyChunks) {
+        Graphics::update_chunks(self, tree, dirty)
+    }
+
+    fn update_decals(&mut self, decals: &DecalList) {
+        Graphics::update_decals(self, decals)
+    }
+
+    fn update_debug_lines(&mut self, lines: Vec<Line>) {
+        Graphics::update_debug_lines(self, lines)
+    }
+
+    fn update_entities(&mut self, entities: &EntityList) {
+        Graphics::update_entities(self, entities)
+    }
+
+    fn update_hud(&mut self, hud_info: HudInfo) {
+        Graphics::update_hud(self, hud_info)
+    }
+
+    fn update_lighting(&mut self, lighting: Lighting) {
+        Graphics::update_lighting(self, lighting)
+    }
+
+    fn update_view_distance(&mut self, voxels: f32) {
+        Graphics::update_view_distance(self, voxels)
+    }
+
+    fn update_materials(&mut self, materials: &MaterialRegistry) {
+        Graphics::update_materials(self, materials)
+    }
+
+    fn update_status(&mut self, status: &Status) {
+        Graphics::update_status(self, status)
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        Graphics::memory_usage(self)
+    }
+
+    fn is_lost(&self) -> bool {
+        Graphics::is_lost(self)
+    }
+
+    fn render_tiled(
+        &mut self,
+        width: u32,
+        height: u32,
+        tile_size: u32,
+        path: &Path,
+    ) -> io::Result<()> {
+        Graphics::render_tiled(self, [width, height], tile_size, path)
+    }
+
+    fn ray_tracer(&mut self) -> Option<&mut Graphics> {
+        Some(self)
+    }
+}
+
+/// Draws nothing, standing in while a lost renderer is replaced: the surface
+/// only takes the new one's swapchain once the old one is dropped.
+pub struct NoRenderer;
+
+impl Renderer for NoRenderer {
+    fn redraw(&mut self) {}
+
+    fn resize(&mut self) {}
+
+    fn update_camera(&mut self, _: CameraInfo) {}
+
+    fn update_cull_camera(&mut self, _: Option<CameraInfo>) {}
+
+    fn update_octree(&mut self, _: &Octree<MaterialId>) {}
+
+    fn update_decals(&mut self, _: &DecalList) {}
+
+    fn update_debug_lines(&mut self, _: Vec<Line>) {}
+
+    fn update_entities(&mut self, _: &EntityList) {}
+
+    fn update_hud(&mut self, _: HudInfo) {}
+
+    fn update_lighting(&mut self, _: Lighting) {}
+
+    fn update_view_distance(&mut self, _: f32) {}
+
+    fn update_materials(&mut self, _: &MaterialRegistry) {}
+
+    fn update_status(&mut self, _: &Status) {}
+
+    fn is_lost(&self) -> bool {
+        false
+    }
+}
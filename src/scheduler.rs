@@ -0,0 +1,238 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use vecmath::Vector3;
+
+use crate::aabc::Aabc;
+
+/// The chunks of the world edited since the renderer last got them, oldest
+/// first and each once, so bursts of edits can be sent a few chunks a frame.
+pub struct DirtyChunks {
+    size: u32,
+    queue: VecDeque<Vector3<i32>>,
+    queued: HashSet<Vector3<i32>>,
+}
+
+impl DirtyChunks {
+    /// Tracks cubes of `size` aligned to multiples of it.
+    pub fn new(size: u32) -> Self {
+        DirtyChunks {
+            size,
+            queue: VecDeque::new(),
+            queued: HashSet::new(),
+        }
+    }
+
+    /// Marks the chunk of `pos` and those next to it that `pos` touches, whose
+    /// faces against it may have changed too.
+    pub fn mark(&mut self, pos: Vector3<i32>) {
+        self.mark_chunk(pos);
+        for d in 0..3 {
+            for sign in [-1, 1] {
+                let mut neighbor = pos;
+                neighbor[d] += sign;
+                self.mark_chunk(neighbor);
+            }
+        }
+    }
+
+    /// Marks every chunk the box from `min` to `max`, inclusive, or its
+    /// faces touch.
+    pub fn mark_box(&mut self, min: Vector3<i32>, max: Vector3<i32>) {
+        let (lo, hi) = (min.map(|c| c - 1), max.map(|c| c + 1));
+        let size = self.size as i32;
+        let (first, last) = (
+            lo.map(|c| c.div_euclid(size)),
+            hi.map(|c| c.div_euclid(size)),
+        );
+        for x in first[0]..=last[0] {
+            for y in first[1]..=last[1] {
+                for z in first[2]..=last[2] {
+                    self.push([x, y, z]);
+                }
+            }
+        }
+    }
+
+    fn mark_chunk(&mut self, pos: Vector3<i32>) {
+        self.push(pos.map(|c| c.div_euclid(self.size as i32)));
+    }
+
+    fn push(&mut self, chunk: Vector3<i32>) {
+        if self.queued.insert(chunk) {
+            self.queue.push_back(chunk);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Removes and returns the `budget` chunks marked first.
+    pub fn take(&mut self, budget: usize) -> Vec<Aabc> {
+        let count = budget.min(self.queue.len());
+        self.queue
+            .drain(..count)
+            .map(|chunk| {
+                self.queued.remove(&chunk);
+                Aabc::new(chunk.map(|c| c * self.size as i32), self.size)
+            })
+            .collect()
+    }
+
+    /// Marks `chunk`, one of those `take` returned, again, for a chunk that
+    /// can't be sent yet.
+    pub fn requeue(&mut self, chunk: Aabc) {
+        self.push(chunk.origin.map(|c| c.div_euclid(self.size as i32)));
+    }
+
+    pub fn clear(&mut self) {
+        self.queue.clear();
+        self.queued.clear();
+    }
+}
+
+/// Runs jobs on a pool of worker threads. Results are picked up with `poll`
+/// in whatever order they finish.
+pub struct Workers<J, R> {
+    jobs: Option<Sender<J>>,
+    results: Receiver<R>,
+    pending: usize,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<J: Send + 'static, R: Send + 'static> Workers<J, R> {
+    /// Starts `threads` workers running `work` on the jobs submitted.
+    pub fn new(threads: usize, work: impl Fn(J) -> R + Send + Sync + 'static) -> Self {
+        let (jobs, job_receiver) = mpsc::channel::<J>();
+        let (result_sender, results) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let work = Arc::new(work);
+        let workers = (0..threads.max(1))
+            .map(|_| {
+                let jobs = job_receiver.clone();
+                let results = result_sender.clone();
+                let work = work.clone();
+                thread::spawn(move || loop {
+                    // the lock is released before working
+                    let job = match jobs.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        Err(_) => return,
+                    };
+                    if results.send(work(job)).is_err() {
+                        return;
+                    }
+                })
+            })
+            .collect();
+        Workers {
+            jobs: Some(jobs),
+            results,
+            pending: 0,
+            workers,
+        }
+    }
+
+    pub fn submit(&mut self, job: J) {
+        self.pending += 1;
+        self.jobs.as_ref().unwrap().send(job).unwrap();
+    }
+
+    /// The jobs submitted whose results weren't picked up yet.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Returns the results finished since the last call.
+    pub fn poll(&mut self) -> Vec<R> {
+        let results: Vec<_> = self.results.try_iter().collect();
+        self.pending -= results.len();
+        results
+    }
+}
+
+impl<J, R> Drop for Workers<J, R> {
+    fn drop(&mut self) {
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    fn origins(chunks: &[Aabc]) -> Vec<Vector3<i32>> {
+        chunks.iter().map(|c| c.origin).collect()
+    }
+
+    #[test]
+    fn chunks_are_taken_once_oldest_first() {
+        let mut dirty = DirtyChunks::new(16);
+        dirty.mark([40, 8, 8]);
+        dirty.mark([-8, 8, 8]);
+        dirty.mark([41, 9, 9]);
+        assert_eq!(2, dirty.len());
+        assert_eq!(vec![[32, 0, 0]], origins(&dirty.take(1)));
+        assert_eq!(vec![[-16, 0, 0]], origins(&dirty.take(4)));
+        assert!(dirty.is_empty());
+        dirty.mark([40, 8, 8]);
+        assert_eq!(vec![Aabc::new([32, 0, 0], 16)], dirty.take(1));
+        dirty.mark([-8, 8, 8]);
+        dirty.requeue(Aabc::new([32, 0, 0], 16));
+        assert_eq!(vec![[-16, 0, 0], [32, 0, 0]], origins(&dirty.take(4)));
+    }
+
+    #[test]
+    fn neighbors_touched_are_marked() {
+        let mut dirty = DirtyChunks::new(16);
+        dirty.mark([15, 0, 8]);
+        assert_eq!(
+            vec![[0, 0, 0], [16, 0, 0], [0, -16, 0]],
+            origins(&dirty.take(8))
+        );
+    }
+
+    #[test]
+    fn box_marks_its_chunks_and_their_neighbors() {
+        let mut dirty = DirtyChunks::new(16);
+        dirty.mark_box([1, 1, 1], [20, 14, 14]);
+        assert_eq!(2, dirty.len());
+        dirty.mark_box([0, 1, 1], [0, 1, 1]);
+        assert_eq!(3, dirty.len());
+        dirty.clear();
+        assert!(dirty.take(1).is_empty());
+    }
+
+    #[test]
+    fn workers_return_every_result() {
+        let mut workers = Workers::new(3, |n: u32| n * 2);
+        for n in 0..10 {
+            workers.submit(n);
+        }
+        assert_eq!(10, workers.pending());
+        let mut results = Vec::new();
+        let start = Instant::now();
+        while results.len() < 10 && start.elapsed() < Duration::from_secs(5) {
+            results.extend(workers.poll());
+            thread::sleep(Duration::from_millis(1));
+        }
+        results.sort();
+        assert_eq!((0..10).map(|n| n * 2).collect::<Vec<_>>(), results);
+        assert_eq!(0, workers.pending());
+    }
+}
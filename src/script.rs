@@ -0,0 +1,425 @@
+use std::{cell::RefCell, collections::HashMap, fs, mem, path::Path, rc::Rc};
+
+use rhai::{Array, CallFnOptions, Dynamic, Engine, EvalAltResult, Map, Scope, AST, FLOAT, INT};
+use vecmath::Vector3;
+
+use crate::{
+    materials::{MaterialId, MaterialRegistry},
+    world::World,
+};
+
+// operations a script may take per run or tick before it is stopped, which
+// catches endless loops
+const MAX_OPERATIONS: u64 = 10_000_000;
+// called every frame when a script defines it, with the seconds since the
+// script was loaded
+const TICK: &str = "tick";
+// longest ray a script may cast, which is walked a voxel at a time
+const MAX_RAY_LENGTH: f32 = 1024.0;
+// farthest from the origin a ray may start, past it voxel coordinates lose
+// precision and rays could leave the range of i32
+const MAX_RAY_ORIGIN: f32 = (1 << 24) as f32;
+
+/// What a script asks for. Scripts only queue commands, which are applied
+/// by whoever runs the script once it returns.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScriptCommand {
+    /// Sets a voxel to a material, or removes it for None.
+    SetVoxel(Vector3<i32>, Option<MaterialId>),
+    /// Sets every voxel between two corners, both included.
+    Fill(Vector3<i32>, Vector3<i32>, Option<MaterialId>),
+    MoveCamera(Vector3<f32>),
+}
+
+// what the functions given to scripts share with the script
+#[derive(Default)]
+struct State {
+    commands: Vec<ScriptCommand>,
+    camera: Vector3<f32>,
+    // lent by whoever runs the script, for its queries
+    world: World,
+}
+
+type Shared = Rc<RefCell<State>>;
+
+/// A Rhai script that edits the world and moves the camera. Besides its
+/// top level statements, which `run` runs once, it can define
+/// `fn tick(time)` to animate things every frame.
+///
+/// Materials are given by name, "air" removing voxels. Queries see the world
+/// as it was when the script was run, without the edits it queued:
+///
+/// ```text
+/// set_voxel(x, y, z, material)
+/// remove_voxel(x, y, z)
+/// fill(x1, y1, z1, x2, y2, z2, material)
+/// voxel(x, y, z) // material
+/// raycast(x, y, z, dx, dy, dz, max_distance) // #{position, normal, distance, material} or (), at most 1024 voxels
+/// camera_position() // [x, y, z]
+/// set_camera(x, y, z)
+/// ```
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    state: Shared,
+}
+
+impl Script {
+    pub fn load(path: &Path, materials: &MaterialRegistry) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::compile(&source, materials)
+    }
+
+    pub fn compile(source: &str, materials: &MaterialRegistry) -> Result<Self, String> {
+        let state = Shared::default();
+        let engine = engine(&state, materials);
+        let ast = engine.compile(source).map_err(|e| e.to_string())?;
+        Ok(Script {
+            engine,
+            ast,
+            scope: Scope::new(),
+            state,
+        })
+    }
+
+    /// Whether the script defines a tick function.
+    pub fn ticks(&self) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|f| f.name == TICK && f.params.len() == 1)
+    }
+
+    /// Runs the top level statements, returning the commands they queued.
+    pub fn run(
+        &mut self,
+        camera: Vector3<f32>,
+        world: &mut World,
+    ) -> Result<Vec<ScriptCommand>, String> {
+        self.lend(camera, world);
+        let result = self.engine.run_ast_with_scope(&mut self.scope, &self.ast);
+        self.finish(result, world)
+    }
+
+    /// Calls the tick function with the seconds since the script was loaded,
+    /// returning the commands it queued.
+    pub fn tick(
+        &mut self,
+        time: f32,
+        camera: Vector3<f32>,
+        world: &mut World,
+    ) -> Result<Vec<ScriptCommand>, String> {
+        self.lend(camera, world);
+        let options = CallFnOptions::new().eval_ast(false);
+        let result = self
+            .engine
+            .call_fn_with_options::<Dynamic>(
+                options,
+                &mut self.scope,
+                &self.ast,
+                TICK,
+                (time as FLOAT,),
+            )
+            .map(|_| ());
+        self.finish(result, world)
+    }
+
+    // the world is moved into the state while the script runs, since the
+    // functions given to it can't borrow
+    fn lend(&mut self, camera: Vector3<f32>, world: &mut World) {
+        let mut state = self.state.borrow_mut();
+        state.camera = camera;
+        mem::swap(&mut state.world, world);
+    }
+
+    // gives the world back, the commands being dropped when the script
+    // failed part way
+    fn finish(
+        &mut self,
+        result: Result<(), Box<EvalAltResult>>,
+        world: &mut World,
+    ) -> Result<Vec<ScriptCommand>, String> {
+        let mut state = self.state.borrow_mut();
+        mem::swap(&mut state.world, world);
+        let commands = mem::take(&mut state.commands);
+        drop(state);
+        result.map_err(|e| e.to_string())?;
+        Ok(commands)
+    }
+}
+
+fn engine(state: &Shared, materials: &MaterialRegistry) -> Engine {
+    let mut names = HashMap::new();
+    for id in 1..materials.len() as MaterialId {
+        // the levels of a liquid share its name, which is its source's
+        names.entry(materials.get(id).unwrap().name).or_insert(id);
+    }
+    let material = move |name: &str| -> Result<Option<MaterialId>, Box<EvalAltResult>> {
+        match name {
+            "air" => Ok(None),
+            name => match names.get(name) {
+                Some(&id) => Ok(Some(id)),
+                None => Err(format!("unknown material {}", name).into()),
+            },
+        }
+    };
+    let material = Rc::new(material);
+    let material_names: Rc<Vec<&'static str>> = Rc::new(
+        (0..materials.len() as MaterialId)
+            .map(|id| materials.get(id).map_or("air", |m| m.name))
+            .collect(),
+    );
+
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    let (s, m) = (state.clone(), material.clone());
+    engine.register_fn(
+        "set_voxel",
+        move |x: INT, y: INT, z: INT, name: &str| -> Result<(), Box<EvalAltResult>> {
+            let command = ScriptCommand::SetVoxel(position(x, y, z)?, m(name)?);
+            s.borrow_mut().commands.push(command);
+            Ok(())
+        },
+    );
+    let s = state.clone();
+    engine.register_fn(
+        "remove_voxel",
+        move |x: INT, y: INT, z: INT| -> Result<(), Box<EvalAltResult>> {
+            let command = ScriptCommand::SetVoxel(position(x, y, z)?, None);
+            s.borrow_mut().commands.push(command);
+            Ok(())
+        },
+    );
+    let (s, m) = (state.clone(), material);
+    engine.register_fn(
+        "fill",
+        move |x1: INT,
+              y1: INT,
+              z1: INT,
+              x2: INT,
+              y2: INT,
+              z2: INT,
+              name: &str|
+              -> Result<(), Box<EvalAltResult>> {
+            let command =
+                ScriptCommand::Fill(position(x1, y1, z1)?, position(x2, y2, z2)?, m(name)?);
+            s.borrow_mut().commands.push(command);
+            Ok(())
+        },
+    );
+    let (s, n) = (state.clone(), material_names.clone());
+    engine.register_fn(
+        "voxel",
+        move |x: INT, y: INT, z: INT| -> Result<String, Box<EvalAltResult>> {
+            let material = s.borrow().world.get(position(x, y, z)?);
+            Ok(material_name(&n, material).to_string())
+        },
+    );
+    let (s, n) = (state.clone(), material_names);
+    engine.register_fn(
+        "raycast",
+        move |x: FLOAT,
+              y: FLOAT,
+              z: FLOAT,
+              dx: FLOAT,
+              dy: FLOAT,
+              dz: FLOAT,
+              max: FLOAT|
+              -> Result<Dynamic, Box<EvalAltResult>> {
+            let state = s.borrow();
+            let (origin, dir) = ray([x, y, z], [dx, dy, dz], max)?;
+            let max = (max as f32).clamp(0.0, MAX_RAY_LENGTH);
+            let hit = match state.world.raycast(origin, dir, max) {
+                Some(hit) => hit,
+                None => return Ok(Dynamic::UNIT),
+            };
+            let vector = |v: Vector3<i32>| -> Array { v.map(|c| Dynamic::from(c as INT)).into() };
+            let mut map = Map::new();
+            map.insert("position".into(), vector(hit.pos).into());
+            map.insert("normal".into(), vector(hit.normal).into());
+            map.insert("distance".into(), (hit.distance as FLOAT).into());
+            let material = material_name(&n, state.world.get(hit.pos));
+            map.insert("material".into(), material.into());
+            Ok(map.into())
+        },
+    );
+    let s = state.clone();
+    engine.register_fn("camera_position", move || -> Array {
+        s.borrow()
+            .camera
+            .iter()
+            .map(|&c| Dynamic::from(c as FLOAT))
+            .collect()
+    });
+    let s = state.clone();
+    engine.register_fn("set_camera", move |x: FLOAT, y: FLOAT, z: FLOAT| {
+        let command = ScriptCommand::MoveCamera([x as f32, y as f32, z as f32]);
+        s.borrow_mut().commands.push(command);
+    });
+    // so whole numbers work too
+    let s = state.clone();
+    engine.register_fn("set_camera", move |x: INT, y: INT, z: INT| {
+        let command = ScriptCommand::MoveCamera([x as f32, y as f32, z as f32]);
+        s.borrow_mut().commands.push(command);
+    });
+    engine
+}
+
+fn material_name(names: &[&'static str], material: Option<MaterialId>) -> &'static str {
+    material
+        .and_then(|id| names.get(id as usize).copied())
+        .unwrap_or("air")
+}
+
+// the origin and direction of a ray, refusing values that aren't finite and
+// origins too far out
+fn ray(
+    origin: [FLOAT; 3],
+    dir: [FLOAT; 3],
+    max: FLOAT,
+) -> Result<(Vector3<f32>, Vector3<f32>), Box<EvalAltResult>> {
+    let (origin, dir) = (origin.map(|c| c as f32), dir.map(|c| c as f32));
+    if !origin
+        .iter()
+        .chain(&dir)
+        .chain(&[max as f32])
+        .all(|c| c.is_finite())
+    {
+        return Err("raycast needs finite numbers".into());
+    }
+    if let Some(c) = origin.iter().find(|c| c.abs() > MAX_RAY_ORIGIN) {
+        return Err(format!("{} is out of range", c).into());
+    }
+    Ok((origin, dir))
+}
+
+fn position(x: INT, y: INT, z: INT) -> Result<Vector3<i32>, Box<EvalAltResult>> {
+    let coordinate = |c: INT| {
+        i32::try_from(c).map_err(|_| Box::<EvalAltResult>::from(format!("{} is out of range", c)))
+    };
+    Ok([coordinate(x)?, coordinate(y)?, coordinate(z)?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(source: &str) -> Script {
+        Script::compile(source, &MaterialRegistry::default()).unwrap()
+    }
+
+    #[test]
+    fn statements_queue_commands() {
+        let materials = MaterialRegistry::default();
+        let stone = materials.id("stone").unwrap();
+        let mut script = compile(
+            r#"
+            for x in 0..2 {
+                set_voxel(x, -1, 3, "stone");
+            }
+            remove_voxel(0, 0, 0);
+            fill(0, 0, 0, 1, 1, 1, "air");
+            let pos = camera_position();
+            set_camera(pos[0], pos[1] + 1.0, 2.0);
+            "#,
+        );
+        assert!(!script.ticks());
+        assert_eq!(
+            Ok(vec![
+                ScriptCommand::SetVoxel([0, -1, 3], Some(stone)),
+                ScriptCommand::SetVoxel([1, -1, 3], Some(stone)),
+                ScriptCommand::SetVoxel([0, 0, 0], None),
+                ScriptCommand::Fill([0, 0, 0], [1, 1, 1], None),
+                ScriptCommand::MoveCamera([2.0, 4.0, 2.0]),
+            ]),
+            script.run([2.0, 3.0, 4.0], &mut World::new())
+        );
+    }
+
+    #[test]
+    fn tick_gets_the_time() {
+        let mut script = compile(
+            r#"
+            set_camera(0, 0, 0);
+            fn tick(time) {
+                set_camera(time, 0.0, 0.0);
+            }
+            "#,
+        );
+        assert!(script.ticks());
+        assert_eq!(1, script.run([0.0; 3], &mut World::new()).unwrap().len());
+        // the top level statements don't run again
+        assert_eq!(
+            Ok(vec![ScriptCommand::MoveCamera([1.5, 0.0, 0.0])]),
+            script.tick(1.5, [0.0; 3], &mut World::new())
+        );
+    }
+
+    #[test]
+    fn errors_drop_the_commands() {
+        let mut script = compile(r#"set_voxel(0, 0, 0, "stone"); set_voxel(0, 0, 0, "cheese");"#);
+        assert!(script
+            .run([0.0; 3], &mut World::new())
+            .unwrap_err()
+            .contains("cheese"));
+        assert!(compile("set_voxel(1 << 40, 0, 0, \"stone\");")
+            .run([0.0; 3], &mut World::new())
+            .is_err());
+        assert!(compile("loop {}").run([0.0; 3], &mut World::new()).is_err());
+        assert!(Script::compile("fn (", &MaterialRegistry::default()).is_err());
+    }
+
+    #[test]
+    fn queries_see_the_world() {
+        let materials = MaterialRegistry::default();
+        let mut world = World::new();
+        world.set([3, 0, 0], materials.id("stone").unwrap());
+        let mut script = compile(
+            r#"
+            let hit = raycast(0.5, 0.5, 0.5, 1.0, 0.0, 0.0, 10.0);
+            set_camera(hit.position[0], hit.normal[0], 0);
+            if hit.distance == 2.5 && hit.material == "stone" && voxel(0, 0, 0) == "air" {
+                remove_voxel(3, 0, 0);
+            }
+            if raycast(0.5, 0.5, 0.5, -1.0, 0.0, 0.0, 10.0) == () {
+                remove_voxel(-1, 0, 0);
+            }
+            "#,
+        );
+        assert_eq!(
+            Ok(vec![
+                ScriptCommand::MoveCamera([3.0, -1.0, 0.0]),
+                ScriptCommand::SetVoxel([3, 0, 0], None),
+                ScriptCommand::SetVoxel([-1, 0, 0], None),
+            ]),
+            script.run([0.0; 3], &mut world)
+        );
+        // the world is given back
+        assert!(world.get([3, 0, 0]).is_some());
+    }
+
+    #[test]
+    fn rays_are_bounded() {
+        let materials = MaterialRegistry::default();
+        let mut world = World::new();
+        world.set([2000, 0, 0], materials.id("stone").unwrap());
+        let mut script = compile(
+            r#"
+            if raycast(0.5, 0.5, 0.5, 1.0, 0.0, 0.0, 1e30) == () {
+                remove_voxel(0, 0, 0);
+            }
+            "#,
+        );
+        assert_eq!(
+            Ok(vec![ScriptCommand::SetVoxel([0, 0, 0], None)]),
+            script.run([0.0; 3], &mut world)
+        );
+        for ray in [
+            "raycast(0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0 / 0.0)",
+            "raycast(0.0 / 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 10.0)",
+            "raycast(1e30, 0.0, 0.0, 1.0, 0.0, 0.0, 10.0)",
+        ] {
+            assert!(compile(ray).run([0.0; 3], &mut world).is_err());
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use vecmath::Vector3;
+
+use crate::debug_draw;
+
+/// A box of voxels picked for editing, from `min` to `max` with both
+/// included, which can be nudged and resized a voxel at a time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SelectionBox {
+    pub min: Vector3<i32>,
+    pub max: Vector3<i32>,
+}
+
+/// A way the selection can be nudged, relative to where the camera looks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Back,
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl SelectionBox {
+    /// The box with `a` and `b` as opposite corners.
+    pub fn new(a: Vector3<i32>, b: Vector3<i32>) -> Self {
+        SelectionBox {
+            min: [0, 1, 2].map(|i| a[i].min(b[i])),
+            max: [0, 1, 2].map(|i| a[i].max(b[i])),
+        }
+    }
+
+    /// Voxels along each axis.
+    pub fn extent(&self) -> Vector3<i32> {
+        [0, 1, 2].map(|i| self.max[i] - self.min[i] + 1)
+    }
+
+    pub fn volume(&self) -> i64 {
+        self.extent().iter().map(|&e| e as i64).product()
+    }
+
+    pub fn nudge(&mut self, step: Vector3<i32>) {
+        self.min = vecmath::vec3_add(self.min, step);
+        self.max = vecmath::vec3_add(self.max, step);
+    }
+
+    /// Moves the face `step` points to outwards by it to grow the box, or
+    /// inwards to shrink it, leaving at least a voxel.
+    pub fn resize(&mut self, step: Vector3<i32>, grow: bool) {
+        for i in 0..3 {
+            let step = step[i].signum();
+            match (step, grow) {
+                (0, _) => (),
+                (1, true) => self.max[i] += 1,
+                (_, true) => self.min[i] -= 1,
+                (1, false) => self.max[i] = (self.max[i] - 1).max(self.min[i]),
+                (_, false) => self.min[i] = (self.min[i] + 1).min(self.max[i]),
+            }
+        }
+    }
+
+    /// Outlines the box over the next frame, see `debug_draw`.
+    pub fn draw(&self, color: [f32; 3]) {
+        debug_draw::cuboid(
+            self.min.map(|c| c as f32),
+            self.max.map(|c| c as f32 + 1.0),
+            color,
+        )
+    }
+}
+
+/// The voxel step `direction` makes for a camera looking along `look`, the
+/// horizontal ones along the axis closest to where it looks.
+pub fn step(direction: Direction, look: Vector3<f32>) -> Vector3<i32> {
+    let forward = if look[0].abs() > look[2].abs() {
+        [look[0].signum() as i32, 0, 0]
+    } else {
+        [0, 0, look[2].signum() as i32]
+    };
+    let right = [-forward[2], 0, forward[0]];
+    match direction {
+        Direction::Forward => forward,
+        Direction::Back => forward.map(|c| -c),
+        Direction::Right => right,
+        Direction::Left => right.map(|c| -c),
+        Direction::Up => [0, 1, 0],
+        Direction::Down => [0, -1, 0],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corners_in_any_order() {
+        let selection = SelectionBox::new([3, 0, -2], [1, 4, 2]);
+        assert_eq!([1, 0, -2], selection.min);
+        assert_eq!([3, 4, 2], selection.max);
+        assert_eq!([3, 5, 5], selection.extent());
+        assert_eq!(75, selection.volume());
+    }
+
+    #[test]
+    fn resize_moves_one_face() {
+        let mut selection = SelectionBox::new([0; 3], [1, 1, 1]);
+        selection.resize([0, 0, -1], true);
+        assert_eq!([0, 0, -1], selection.min);
+        selection.resize([1, 0, 0], true);
+        assert_eq!([2, 1, 1], selection.max);
+        selection.resize([0, 1, 0], false);
+        selection.resize([0, 1, 0], false);
+        // down to a voxel and no further
+        assert_eq!([3, 1, 3], selection.extent());
+    }
+
+    #[test]
+    fn nudge_keeps_the_size() {
+        let mut selection = SelectionBox::new([0; 3], [1, 2, 3]);
+        selection.nudge([-1, 5, 0]);
+        assert_eq!(SelectionBox::new([-1, 5, 0], [0, 7, 3]), selection);
+    }
+
+    #[test]
+    fn steps_follow_the_camera() {
+        // looking along -z, mostly
+        let look = [0.3, -0.5, -1.0];
+        assert_eq!([0, 0, -1], step(Direction::Forward, look));
+        assert_eq!([1, 0, 0], step(Direction::Right, look));
+        assert_eq!([-1, 0, 0], step(Direction::Left, look));
+        assert_eq!([0, 1, 0], step(Direction::Up, look));
+        // and along +x
+        assert_eq!([1, 0, 0], step(Direction::Forward, [1.0, 0.0, 0.2]));
+        assert_eq!([0, 0, 1], step(Direction::Right, [1.0, 0.0, 0.2]));
+    }
+}
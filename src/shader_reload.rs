@@ -0,0 +1,81 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use shaderc::{CompileOptions, Compiler, EnvVersion, ShaderKind, TargetEnv};
+use tracing::{error, info};
+use vulkano::{device::Device, shader::ShaderModule};
+
+/// Compiles `graphics.comp` again whenever it's saved, so the ray tracer can
+/// be worked on without rebuilding. Only the code of the shader can change
+/// this way: the uniform blocks and specialization constants are still those
+/// the Rust side was built with, and changing them needs a rebuild.
+pub struct ShaderReloader {
+    path: PathBuf,
+    // of the source last compiled
+    modified: Option<SystemTime>,
+    compiler: Compiler,
+}
+
+impl Default for ShaderReloader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShaderReloader {
+    pub fn new() -> Self {
+        let path = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/src/graphics.comp"));
+        ShaderReloader {
+            modified: modified(&path),
+            path,
+            compiler: Compiler::new().unwrap(),
+        }
+    }
+
+    /// Returns the shader compiled again if the file changed since the last
+    /// call. What went wrong is printed instead when it doesn't compile.
+    pub fn poll(&mut self, device: &Arc<Device>) -> Option<Arc<ShaderModule>> {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        match self.compile(device) {
+            Ok(shader) => {
+                info!(path = %self.path.display(), "Reloaded the shader");
+                Some(shader)
+            }
+            Err(e) => {
+                error!(path = %self.path.display(), "Could not reload the shader: {}", e);
+                None
+            }
+        }
+    }
+
+    fn compile(&self, device: &Arc<Device>) -> Result<Arc<ShaderModule>, String> {
+        let source = fs::read_to_string(&self.path).map_err(|e| e.to_string())?;
+        let mut options = CompileOptions::new().unwrap();
+        options.set_target_env(TargetEnv::Vulkan, EnvVersion::Vulkan1_0 as u32);
+        let artifact = self
+            .compiler
+            .compile_into_spirv(
+                &source,
+                ShaderKind::Compute,
+                "graphics.comp",
+                "main",
+                Some(&options),
+            )
+            .map_err(|e| e.to_string())?;
+        // shaderc only produces valid SPIR-V
+        unsafe { ShaderModule::from_words(device.clone(), artifact.as_binary()) }
+            .map_err(|e| e.to_string())
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,89 @@
+use crate::graphics::cs::ty::Lighting;
+
+/// How the shadows of a light are traced: `samples` rays towards it per
+/// pixel, all but the first jittered within `cone` radians for soft edges.
+/// Each sample costs about as much as the primary ray.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Shadows {
+    pub samples: u32,
+    pub cone: f32,
+}
+
+impl Shadows {
+    pub const OFF: Shadows = Shadows {
+        samples: 0,
+        cone: 0.0,
+    };
+    pub const HARD: Shadows = Shadows {
+        samples: 1,
+        cone: 0.0,
+    };
+    pub const SOFT: Shadows = Shadows {
+        samples: 4,
+        cone: 0.03,
+    };
+    pub const SOFTER: Shadows = Shadows {
+        samples: 8,
+        cone: 0.08,
+    };
+    /// From cheapest to most expensive.
+    pub const PRESETS: [Shadows; 4] = [Self::OFF, Self::HARD, Self::SOFT, Self::SOFTER];
+
+    pub fn is_enabled(self) -> bool {
+        self.samples > 0
+    }
+
+    /// The next preset, going back to off after the softest. Other settings
+    /// go to off.
+    pub fn next(self) -> Self {
+        match Self::PRESETS.iter().position(|&p| p == self) {
+            Some(i) => Self::PRESETS[(i + 1) % Self::PRESETS.len()],
+            None => Self::OFF,
+        }
+    }
+
+    /// Sets the shadows of the sun, the only light the shader traces
+    /// shadows for.
+    pub fn apply_to_sun(self, lighting: &mut Lighting) {
+        lighting.sun_shadow_samples = self.samples as i32;
+        lighting.sun_shadow_cone = self.cone;
+    }
+}
+
+impl Default for Shadows {
+    fn default() -> Self {
+        Shadows::HARD
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time_of_day::TimeOfDay;
+    use std::time::Duration;
+
+    #[test]
+    fn next_cycles_through_presets() {
+        let mut shadows = Shadows::OFF;
+        for expected in Shadows::PRESETS.iter().skip(1) {
+            shadows = shadows.next();
+            assert_eq!(*expected, shadows);
+        }
+        assert_eq!(Shadows::OFF, shadows.next());
+        let custom = Shadows {
+            samples: 3,
+            cone: 0.1,
+        };
+        assert_eq!(Shadows::OFF, custom.next());
+    }
+
+    #[test]
+    fn off_has_no_samples() {
+        assert!(!Shadows::OFF.is_enabled());
+        let mut lighting = TimeOfDay::new(Duration::from_secs(60)).lighting();
+        Shadows::SOFT.apply_to_sun(&mut lighting);
+        assert_eq!(4, lighting.sun_shadow_samples);
+        Shadows::OFF.apply_to_sun(&mut lighting);
+        assert_eq!(0, lighting.sun_shadow_samples);
+    }
+}
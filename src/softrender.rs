@@ -0,0 +1,364 @@
+use std::{f32::consts::PI, path::PathBuf};
+
+use vecmath::{vec3_add, vec3_dot, vec3_normalized, vec3_scale, vec3_sub, Vector3};
+
+use crate::{
+    benchmark,
+    camera::{self, Projection},
+    demo_world::DemoWorld,
+    graphics::cs::ty::CameraInfo,
+    materials::{MaterialId, MaterialRegistry},
+    octree::Octree,
+    voxel::VoxelData,
+    world::World,
+};
+
+const SKY_COLOR: [u8; 3] = [135, 190, 235];
+const SUN_DIR: Vector3<f32> = [0.42, 0.82, 0.38];
+const AMBIENT: f32 = 0.35;
+
+// origin of each child slot in halves of its parent, like get_child_origin of
+// graphics.comp
+const CHILD_OFFSETS: [[i32; 3]; 8] = [
+    [1, 1, 1],
+    [1, 1, 0],
+    [0, 1, 0],
+    [0, 1, 1],
+    [1, 0, 1],
+    [1, 0, 0],
+    [0, 0, 0],
+    [0, 0, 1],
+];
+
+/// The first voxel a traced ray hit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
+    pub pos: Vector3<i32>,
+    /// First word of the voxel's leaf.
+    pub material: u32,
+    /// Normal of the face the ray entered through. Zero if the ray started
+    /// inside the voxel.
+    pub normal: Vector3<i32>,
+    pub distance: f32,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SnapshotConfig {
+    pub out: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    /// Seed of the benchmark world drawn.
+    pub seed: u64,
+    /// Drawn from its spawn instead of the benchmark world.
+    pub demo_world: Option<DemoWorld>,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        SnapshotConfig {
+            out: PathBuf::from("snapshot.png"),
+            width: 320,
+            height: 240,
+            seed: 0,
+            demo_world: None,
+        }
+    }
+}
+
+impl SnapshotConfig {
+    /// Size of the image, rays need at least 2 pixels each way.
+    pub fn size(&self) -> [u32; 2] {
+        [self.width.max(2), self.height.max(2)]
+    }
+}
+
+// Where a ray enters the box from min of the given size: the axis of the face,
+// the point and its squared distance, like hit_aabc_from of graphics.comp.
+fn hit_box(
+    origin: Vector3<f32>,
+    ray: Vector3<f32>,
+    min: Vector3<f32>,
+    size: f32,
+) -> Option<(usize, f32)> {
+    let max = min.map(|c| c + size);
+    let mut candidate = [None; 3];
+    for i in 0..3 {
+        if origin[i] < min[i] {
+            candidate[i] = Some(min[i]);
+        } else if origin[i] > max[i] {
+            candidate[i] = Some(max[i]);
+        }
+    }
+    if candidate.iter().all(Option::is_none) {
+        return Some((0, 0.0));
+    }
+    let t = [0, 1, 2].map(|i| match candidate[i] {
+        Some(plane) if ray[i] != 0.0 => (plane - origin[i]) / ray[i],
+        _ => -1.0,
+    });
+    let mut axis = 0;
+    for i in 1..3 {
+        if t[axis] < t[i] {
+            axis = i;
+        }
+    }
+    if t[axis] < 0.0 {
+        return None;
+    }
+    let mut coord = [0.0; 3];
+    for i in 0..3 {
+        coord[i] = match candidate[i] {
+            Some(plane) if i == axis => plane,
+            _ => origin[i] + t[axis] * ray[i],
+        };
+        if coord[i] < min[i] || coord[i] > max[i] {
+            return None;
+        }
+    }
+    let d = vec3_sub(coord, origin);
+    Some((axis, vec3_dot(d, d)))
+}
+
+/// Follows `ray` from `origin` through a tree serialized by
+/// `Octree::serialize` the way trace_octree of graphics.comp does, giving up
+/// on voxels further than `max_distance`. `ray` has to be normalized.
+pub fn trace<T: VoxelData>(
+    data: &[i32],
+    origin: Vector3<f32>,
+    ray: Vector3<f32>,
+    max_distance: f32,
+) -> Option<Hit> {
+    let mut size = *data.first()?;
+    if size == 0 {
+        return None;
+    }
+    let root = [data[1], data[2], data[3]];
+    let mut node = root;
+    let mut idx = 4;
+    // entry distances are squared, as in the shader
+    let max_dist = max_distance * max_distance;
+    let mut best = -1.0;
+    // the root's parent pointer is 0
+    while idx != 0 {
+        let half = size / 2;
+        let stride = if size == 2 { T::WORDS } else { 1 };
+        // entry distance, index, origin and face axis of the nearest child
+        // entered after the last one visited
+        let mut next: Option<(f32, usize, Vector3<i32>, usize)> = None;
+        for (i, offset) in CHILD_OFFSETS.iter().enumerate() {
+            let slot = idx + 1 + i * stride;
+            let child = match data[slot] {
+                0 => continue,
+                _ if size == 2 => slot,
+                child => child as usize,
+            };
+            let child_origin = [0, 1, 2].map(|d| node[d] + offset[d] * half);
+            let hit = hit_box(origin, ray, child_origin.map(|c| c as f32), half as f32);
+            if let Some((axis, dist)) = hit {
+                if dist > best && dist <= max_dist && dist < next.map_or(f32::MAX, |n| n.0) {
+                    next = Some((dist, child, child_origin, axis));
+                }
+            }
+        }
+        match next {
+            Some((dist, leaf, pos, axis)) if size == 2 => {
+                let mut normal = [0; 3];
+                if dist > 0.0 {
+                    normal[axis] = if ray[axis] > 0.0 { -1 } else { 1 };
+                }
+                return Some(Hit {
+                    pos,
+                    material: data[leaf] as u32,
+                    normal,
+                    distance: dist.sqrt(),
+                });
+            }
+            Some((_, child, child_origin, _)) => {
+                node = child_origin;
+                size = half;
+                idx = child;
+                best = -1.0;
+            }
+            None => {
+                // carry on in the parent after the node just left
+                best = hit_box(origin, ray, node.map(|c| c as f32), size as f32)
+                    .map_or(0.0, |(_, dist)| dist);
+                size *= 2;
+                node = [0, 1, 2].map(|d| root[d] + (node[d] - root[d]).div_euclid(size) * size);
+                idx = data[idx] as usize;
+            }
+        }
+    }
+    None
+}
+
+/// Origin and direction of the ray through `pixel` of an image of `size`, at
+/// least 2 by 2, like calculate_ray of graphics.comp without the jitter.
+pub fn camera_ray(
+    camera: &CameraInfo,
+    pixel: [u32; 2],
+    size: [u32; 2],
+) -> (Vector3<f32>, Vector3<f32>) {
+    let [t_n, b_n, v_n] = camera::basis(camera);
+    let (k, m) = (size[0] as f32, size[1] as f32);
+    let [left, top, right, bottom] = camera.window;
+    let g_x = if camera.orthographic != 0 {
+        camera.fov / 2.0
+    } else {
+        (camera.fov / 2.0).tan()
+    };
+    let g_y = g_x * (m - 1.0) / (k - 1.0) * (right - left) / (bottom - top);
+    let mix = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let p_x = mix(left, right, (pixel[0] as f32 - 1.0) / (k - 1.0));
+    let p_y = mix(top, bottom, (pixel[1] as f32 - 1.0) / (m - 1.0));
+    let across = vec3_add(vec3_scale(b_n, g_x * p_x), vec3_scale(v_n, g_y * p_y));
+    if camera.orthographic != 0 {
+        (vec3_add(camera.eye, across), t_n)
+    } else {
+        (camera.eye, vec3_normalized(vec3_add(t_n, across)))
+    }
+}
+
+/// Traces a ray for each pixel of an image of `size`, row by row.
+pub fn trace_image<T: VoxelData>(
+    tree: &Octree<T>,
+    camera: &CameraInfo,
+    size: [u32; 2],
+    max_distance: f32,
+) -> Vec<Option<Hit>> {
+    let data = tree.serialize();
+    (0..size[1])
+        .flat_map(|y| (0..size[0]).map(move |x| [x, y]))
+        .map(|pixel| {
+            let (origin, ray) = camera_ray(camera, pixel, size);
+            trace::<T>(&data, origin, ray, max_distance)
+        })
+        .collect()
+}
+
+/// RGBA8 image of `tree` seen from `camera`, each voxel the average color of
+/// its material lit by a fixed sun. Needs no GPU, but is far too slow for more
+/// than small images.
+pub fn render(
+    tree: &Octree<MaterialId>,
+    materials: &MaterialRegistry,
+    camera: &CameraInfo,
+    size: [u32; 2],
+) -> Vec<u8> {
+    let sun = vec3_normalized(SUN_DIR);
+    trace_image(tree, camera, size, f32::MAX)
+        .into_iter()
+        .flat_map(|hit| {
+            let [r, g, b] = match hit {
+                None => SKY_COLOR,
+                Some(hit) => {
+                    let color = materials
+                        .get(hit.material as MaterialId)
+                        .map_or([255; 3], |m| m.color);
+                    let normal = hit.normal.map(|c| c as f32);
+                    let light = AMBIENT + (1.0 - AMBIENT) * vec3_dot(normal, sun).max(0.0);
+                    color.map(|c| (c as f32 * light) as u8)
+                }
+            };
+            [r, g, b, 255]
+        })
+        .collect()
+}
+
+/// Draws the first view of the benchmark's flythrough, or a demo world as
+/// seen when starting in it, on the CPU for machines without Vulkan.
+pub fn snapshot(config: &SnapshotConfig) -> Vec<u8> {
+    let materials = MaterialRegistry::default();
+    let (world, camera) = match config.demo_world {
+        Some(demo) => {
+            let [x, y, z] = demo.spawn();
+            let camera =
+                Projection::Perspective { fov: PI / 2.0 }.camera_info([x, y, z], [x, y, z - 1.0]);
+            (demo.build(&materials), camera)
+        }
+        None => (
+            World::seeded(config.seed, benchmark::WORLD_EXTENT, 5),
+            benchmark::camera_path(0, 1),
+        ),
+    };
+    render(world.tree(), &materials, &camera, config.size())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{camera::Projection, raycast::raycast, voxel::Voxel};
+
+    #[test]
+    fn empty_tree_misses() {
+        let tree = Octree::<MaterialId>::new();
+        assert_eq!(
+            None,
+            trace::<MaterialId>(&tree.serialize(), [0.0; 3], [1.0, 0.0, 0.0], 100.0)
+        );
+    }
+
+    #[test]
+    fn hits_match_the_grid_walk() {
+        let world = World::seeded(3, 5, 5);
+        let data = world.tree().serialize();
+        let origins = [[-12.3, 7.1, 9.4], [14.2, -9.7, -3.3], [0.4, 0.3, 0.2]];
+        let targets = [[0.7, 0.2, 0.1], [-2.6, 1.9, 3.3], [4.1, -3.7, 1.2]];
+        for origin in origins {
+            for target in targets {
+                let ray = vec3_normalized(vec3_sub(target, origin));
+                let expected = raycast(origin, ray, 100.0, |p| world.get(p).is_some());
+                let hit = trace::<MaterialId>(&data, origin, ray, 100.0);
+                assert_eq!(expected.map(|h| h.pos), hit.map(|h| h.pos));
+                if let (Some(expected), Some(hit)) = (expected, hit) {
+                    assert_eq!(expected.normal, hit.normal);
+                    assert!((expected.distance - hit.distance).abs() < 1e-3);
+                    assert_eq!(5, hit.material);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn leaves_of_several_words_are_read() {
+        let mut tree = Octree::new();
+        let mut voxel = Voxel::new(7);
+        voxel.orientation = 2;
+        tree.insert_leaf(Voxel::new(3), [1, 0, 0]);
+        tree.insert_leaf(voxel, [4, 0, 0]);
+        let data = tree.serialize();
+        let hit = trace::<Voxel>(&data, [10.5, 0.5, 0.5], [-1.0, 0.0, 0.0], 100.0).unwrap();
+        assert_eq!(([4, 0, 0], 7), (hit.pos, hit.material));
+        assert_eq!([1, 0, 0], hit.normal);
+        assert!(trace::<Voxel>(&data, [10.5, 0.5, 0.5], [-1.0, 0.0, 0.0], 5.0).is_none());
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let camera =
+            Projection::Orthographic { width: 8.0 }.camera_info([0.0; 3], [0.0, 0.0, -1.0]);
+        let (a, ray_a) = camera_ray(&camera, [0, 0], [4, 4]);
+        let (b, ray_b) = camera_ray(&camera, [3, 2], [4, 4]);
+        assert_eq!(ray_a, ray_b);
+        assert_ne!(a, b);
+        let camera =
+            Projection::Perspective { fov: PI / 2.0 }.camera_info([0.0; 3], [0.0, 0.0, -1.0]);
+        let (a, ray_a) = camera_ray(&camera, [0, 0], [4, 4]);
+        let (b, ray_b) = camera_ray(&camera, [3, 2], [4, 4]);
+        assert_eq!(a, b);
+        assert_ne!(ray_a, ray_b);
+    }
+
+    #[test]
+    fn snapshot_shows_the_world_against_the_sky() {
+        let config = SnapshotConfig {
+            width: 24,
+            height: 16,
+            ..SnapshotConfig::default()
+        };
+        let pixels = snapshot(&config);
+        assert_eq!(24 * 16 * 4, pixels.len());
+        let sky = pixels.chunks(4).filter(|p| p[..3] == SKY_COLOR).count();
+        assert!(sky > 0 && sky < 24 * 16);
+    }
+}
@@ -0,0 +1,101 @@
+use std::{collections::VecDeque, time::Duration};
+
+/// Rolling window of frame times.
+pub struct FrameStats {
+    frame_times: VecDeque<Duration>,
+    capacity: usize,
+}
+
+impl FrameStats {
+    pub fn new(capacity: usize) -> Self {
+        FrameStats {
+            frame_times: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn record(&mut self, frame_time: Duration) {
+        if self.frame_times.len() == self.capacity {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_time);
+    }
+
+    pub fn len(&self) -> usize {
+        self.frame_times.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frame_times.is_empty()
+    }
+
+    pub fn average(&self) -> Duration {
+        if self.frame_times.is_empty() {
+            return Duration::ZERO;
+        }
+        self.frame_times.iter().sum::<Duration>() / self.frame_times.len() as u32
+    }
+
+    pub fn min(&self) -> Duration {
+        self.frame_times.iter().min().copied().unwrap_or_default()
+    }
+
+    pub fn max(&self) -> Duration {
+        self.frame_times.iter().max().copied().unwrap_or_default()
+    }
+
+    // p is in the range [0, 100]
+    pub fn percentile(&self, p: f32) -> Duration {
+        if self.frame_times.is_empty() {
+            return Duration::ZERO;
+        }
+        let mut sorted: Vec<Duration> = self.frame_times.iter().copied().collect();
+        sorted.sort();
+        let rank = (p.clamp(0.0, 100.0) / 100.0 * (sorted.len() - 1) as f32).round() as usize;
+        sorted[rank]
+    }
+
+    pub fn fps(&self) -> f32 {
+        let avg = self.average().as_secs_f32();
+        if avg == 0.0 {
+            0.0
+        } else {
+            1.0 / avg
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_stats_are_zero() {
+        let stats = FrameStats::new(4);
+        assert_eq!(Duration::ZERO, stats.average());
+        assert_eq!(Duration::ZERO, stats.percentile(99.0));
+        assert_eq!(0.0, stats.fps());
+    }
+
+    #[test]
+    fn oldest_sample_is_evicted() {
+        let mut stats = FrameStats::new(2);
+        stats.record(Duration::from_millis(100));
+        stats.record(Duration::from_millis(10));
+        stats.record(Duration::from_millis(20));
+        assert_eq!(2, stats.len());
+        assert_eq!(Duration::from_millis(20), stats.max());
+        assert_eq!(Duration::from_millis(15), stats.average());
+    }
+
+    #[test]
+    fn percentiles() {
+        let mut stats = FrameStats::new(100);
+        for i in 1..=100 {
+            stats.record(Duration::from_millis(i));
+        }
+        assert_eq!(Duration::from_millis(1), stats.percentile(0.0));
+        assert_eq!(Duration::from_millis(100), stats.percentile(100.0));
+        assert_eq!(Duration::from_millis(51), stats.percentile(50.0));
+    }
+}
@@ -0,0 +1,76 @@
+use vecmath::Vector3;
+
+use crate::gpu_memory;
+
+/// What the window title shows, so the basics are visible with the overlay
+/// off.
+pub struct Status {
+    pub fps: f32,
+    pub position: Vector3<f32>,
+    pub world: String,
+    /// Samples in each pixel so far while path tracing.
+    pub samples: Option<u32>,
+    /// Bytes of device memory the renderer holds, see `gpu_memory`.
+    pub memory: u64,
+}
+
+impl Status {
+    pub fn title(&self) -> String {
+        let [x, y, z] = self.position;
+        let title = format!(
+            "{} - {:.0} fps - {:.1}, {:.1}, {:.1} - {} - {}",
+            env!("CARGO_PKG_NAME"),
+            self.fps,
+            x,
+            y,
+            z,
+            self.world,
+            gpu_memory::format_bytes(self.memory)
+        );
+        match self.samples {
+            Some(samples) => format!("{} - {} samples", title, samples),
+            None => title,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn title_lists_status() {
+        let status = Status {
+            fps: 59.6,
+            position: [1.0, -2.25, 30.0],
+            world: "seed 3".to_string(),
+            samples: None,
+            memory: 96 << 20,
+        };
+        assert_eq!(
+            format!(
+                "{} - 60 fps - 1.0, -2.2, 30.0 - seed 3 - 96.0 MiB",
+                env!("CARGO_PKG_NAME")
+            ),
+            status.title()
+        );
+    }
+
+    #[test]
+    fn title_counts_samples() {
+        let status = Status {
+            fps: 10.0,
+            position: [0.0; 3],
+            world: "world".to_string(),
+            samples: Some(128),
+            memory: 0,
+        };
+        assert_eq!(
+            format!(
+                "{} - 10 fps - 0.0, 0.0, 0.0 - world - 0 B - 128 samples",
+                env!("CARGO_PKG_NAME")
+            ),
+            status.title()
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use std::{collections::HashSet, f32::consts::PI, time::Instant};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use vecmath::Vector3;
+
+use crate::{
+    camera::{Camera, LookEvent},
+    octree::Octree,
+    stats::FrameStats,
+};
+
+const WORLD_EXTENT: i32 = 64;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct StressConfig {
+    pub iterations: u32,
+    pub edits_per_iteration: u32,
+    pub fill_size: i32,
+    pub seed: u64,
+}
+
+impl Default for StressConfig {
+    fn default() -> Self {
+        StressConfig {
+            iterations: 1000,
+            edits_per_iteration: 256,
+            fill_size: 16,
+            seed: 0,
+        }
+    }
+}
+
+pub struct StressReport {
+    pub frame_stats: FrameStats,
+    pub final_leaves: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Workload {
+    RandomEdits,
+    TeleportCamera,
+    RegionChurn,
+    BrushFill,
+}
+
+/// Runs randomized workloads against the octree and camera, checking
+/// invariants after every iteration. Panics as soon as an invariant is broken.
+pub fn run(config: &StressConfig) -> StressReport {
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut tree: Octree<i32> = Octree::new();
+    let mut voxels: HashSet<Vector3<i32>> = HashSet::new();
+    let mut frame_stats = FrameStats::new(config.iterations.max(1) as usize);
+
+    for _ in 0..config.iterations {
+        let start = Instant::now();
+        let workload = match rng.gen_range(0..8) {
+            0..=4 => Workload::RandomEdits,
+            5 => Workload::TeleportCamera,
+            6 => Workload::RegionChurn,
+            _ => Workload::BrushFill,
+        };
+        match workload {
+            Workload::RandomEdits => {
+                for _ in 0..config.edits_per_iteration {
+                    toggle_voxel(&mut tree, &mut voxels, random_pos(&mut rng), &mut rng);
+                }
+            }
+            Workload::TeleportCamera => {
+                let mut camera = Camera::new(random_pos(&mut rng).map(|c| c as f32), PI / 2.0);
+                camera.apply_look_event(LookEvent {
+                    right: rng.gen_range(-PI..PI),
+                    down: rng.gen_range(-PI / 2.0..PI / 2.0),
+                });
+                let info = camera.get_camera_info();
+                assert!(info
+                    .eye
+                    .iter()
+                    .chain(info.target.iter())
+                    .all(|c| c.is_finite()));
+            }
+            Workload::RegionChurn => {
+                let origin = random_pos(&mut rng);
+                for_each_in_cube(origin, config.fill_size, |pos| {
+                    if voxels.remove(&pos) {
+                        tree.remove_leaf(pos);
+                    }
+                });
+                for_each_in_cube(origin, config.fill_size, |pos| {
+                    if rng.gen_range(0..12) == 0 && voxels.insert(pos) {
+                        tree.insert_leaf(rng.gen_range(0..6), pos);
+                    }
+                });
+            }
+            Workload::BrushFill => {
+                let material = rng.gen_range(0..6);
+                for_each_in_cube(random_pos(&mut rng), config.fill_size, |pos| {
+                    if voxels.insert(pos) {
+                        tree.insert_leaf(material, pos);
+                    }
+                });
+            }
+        }
+        check_invariants(&tree, &voxels);
+        frame_stats.record(start.elapsed());
+    }
+
+    StressReport {
+        frame_stats,
+        final_leaves: tree.count_leaves(),
+    }
+}
+
+pub fn print_report(report: &StressReport) {
+    let stats = &report.frame_stats;
+    println!("iterations: {}", stats.len());
+    println!("final leaves: {}", report.final_leaves);
+    println!(
+        "iteration time: min {:?}, avg {:?}, p99 {:?}, max {:?}",
+        stats.min(),
+        stats.average(),
+        stats.percentile(99.0),
+        stats.max(),
+    );
+}
+
+fn random_pos(rng: &mut StdRng) -> Vector3<i32> {
+    [
+        rng.gen_range(-WORLD_EXTENT..WORLD_EXTENT),
+        rng.gen_range(-WORLD_EXTENT..WORLD_EXTENT),
+        rng.gen_range(-WORLD_EXTENT..WORLD_EXTENT),
+    ]
+}
+
+fn toggle_voxel(
+    tree: &mut Octree<i32>,
+    voxels: &mut HashSet<Vector3<i32>>,
+    pos: Vector3<i32>,
+    rng: &mut StdRng,
+) {
+    if voxels.remove(&pos) {
+        tree.remove_leaf(pos);
+    } else {
+        voxels.insert(pos);
+        tree.insert_leaf(rng.gen_range(0..6), pos);
+    }
+}
+
+fn for_each_in_cube<F: FnMut(Vector3<i32>)>(origin: Vector3<i32>, size: i32, mut f: F) {
+    for x in origin[0]..origin[0] + size {
+        for y in origin[1]..origin[1] + size {
+            for z in origin[2]..origin[2] + size {
+                f([x, y, z]);
+            }
+        }
+    }
+}
+
+fn check_invariants(tree: &Octree<i32>, voxels: &HashSet<Vector3<i32>>) {
+    if let Err(e) = tree.validate() {
+        panic!("invalid tree: {}", e)
+    }
+    assert_eq!(
+        voxels.len() as u64,
+        tree.count_leaves(),
+        "leaf count drifted"
+    );
+    if voxels.is_empty() {
+        return;
+    }
+    let serialized = tree.serialize();
+    let size = serialized[0];
+    assert!(
+        size >= 2 && (size as u32).is_power_of_two(),
+        "root size {} is not a power of two",
+        size
+    );
+    let origin = [serialized[1], serialized[2], serialized[3]];
+    for v in voxels {
+        for i in 0..3 {
+            assert!(
+                v[i] >= origin[i] && v[i] < origin[i] + size,
+                "voxel {:?} outside root at {:?} with size {}",
+                v,
+                origin,
+                size
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn small_config() -> StressConfig {
+        StressConfig {
+            iterations: 20,
+            edits_per_iteration: 32,
+            fill_size: 4,
+            seed: 7,
+        }
+    }
+
+    #[test]
+    fn small_run_holds_invariants() {
+        let report = run(&small_config());
+        assert_eq!(20, report.frame_stats.len());
+    }
+
+    #[test]
+    fn same_seed_is_deterministic() {
+        let a = run(&small_config());
+        let b = run(&small_config());
+        assert_eq!(a.final_leaves, b.final_leaves);
+    }
+}
@@ -0,0 +1,188 @@
+use std::sync::Arc;
+
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer},
+    command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
+    descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet},
+    device::Queue,
+    image::{view::ImageView, ImageAccess, StorageImage},
+    memory::pool::StdMemoryPool,
+    pipeline::{ComputePipeline, Pipeline, PipelineBindPoint},
+};
+
+use crate::{
+    camera,
+    graphics::{cs::ty::CameraInfo, Graphics, COMPUTE_GROUP_SIZE},
+    workgroups::group_count,
+};
+
+use self::taa_cs::ty::Reprojection;
+
+// weight of the newest frame in the accumulated history
+const BLEND: f32 = 0.1;
+// length of the jitter sequence before it repeats
+const JITTER_PERIOD: u32 = 8;
+
+/// Temporal anti-aliasing: jitters the primary rays each frame and blends the
+/// result with the reprojected previous frame.
+pub struct Taa {
+    pipeline: Arc<ComputePipeline>,
+    // ping-ponged between being the previous frame and the output
+    history: [Arc<StorageImage<Arc<StdMemoryPool>>>; 2],
+    output_idx: usize,
+    prev_camera: Option<CameraInfo>,
+    frame: u32,
+}
+
+impl Taa {
+    pub fn new(queue: &Arc<Queue>, size: [u32; 2]) -> Self {
+        let shader = taa_cs::load(queue.device().clone()).unwrap();
+        let pipeline = ComputePipeline::new(
+            queue.device().clone(),
+            shader.entry_point("main").unwrap(),
+            &(),
+            None,
+            |_| {},
+        )
+        .unwrap();
+        Taa {
+            pipeline,
+            history: [
+                Graphics::create_storage_image(queue, size),
+                Graphics::create_storage_image(queue, size),
+            ],
+            output_idx: 0,
+            prev_camera: None,
+            frame: 0,
+        }
+    }
+
+    /// Subpixel offset to apply to the primary rays of the next frame.
+    pub fn jitter(&self) -> [f32; 2] {
+        let idx = self.frame % JITTER_PERIOD + 1;
+        [halton(idx, 2) - 0.5, halton(idx, 3) - 0.5]
+    }
+
+    /// Discards the history, resizing it if the render resolution changed.
+    /// Must be called before `record` whenever the input size changes.
+    /// Bytes of device memory its images hold.
+    pub fn memory(&self) -> u64 {
+        self.history
+            .iter()
+            .map(|image| Graphics::image_bytes(&**image))
+            .sum()
+    }
+
+    pub fn reset(&mut self, queue: &Arc<Queue>, size: [u32; 2]) {
+        if self.history[0].dimensions().width_height() != size {
+            self.history = [
+                Graphics::create_storage_image(queue, size),
+                Graphics::create_storage_image(queue, size),
+            ];
+        }
+        self.prev_camera = None;
+    }
+
+    /// Records the resolve of `input` against the history and returns the
+    /// image holding the anti-aliased result.
+    pub fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        input: Arc<StorageImage<Arc<StdMemoryPool>>>,
+        camera: CameraInfo,
+    ) -> Arc<StorageImage<Arc<StdMemoryPool>>> {
+        let size = input.dimensions().width_height();
+        let prev_camera = self.prev_camera.unwrap_or(camera);
+        let reprojection = CpuAccessibleBuffer::from_data(
+            self.pipeline.device().clone(),
+            BufferUsage {
+                uniform_buffer: true,
+                ..BufferUsage::none()
+            },
+            false,
+            Reprojection {
+                prev_eye: prev_camera.eye,
+                prev_fov: prev_camera.fov,
+                prev_target: prev_camera.target,
+                blend: BLEND,
+                eye: camera.eye,
+                fov: camera.fov,
+                target: camera.target,
+                // the reprojection only knows the whole perspective view
+                history_valid: (self.prev_camera.is_some() && camera::is_full_perspective(&camera))
+                    as i32,
+            },
+        )
+        .unwrap();
+
+        let history = self.history[1 - self.output_idx].clone();
+        let output = self.history[self.output_idx].clone();
+        let desc_set = PersistentDescriptorSet::new(
+            self.pipeline.layout().set_layouts().get(0).unwrap().clone(),
+            [
+                WriteDescriptorSet::image_view(0, ImageView::new_default(input).unwrap()),
+                WriteDescriptorSet::image_view(1, ImageView::new_default(history).unwrap()),
+                WriteDescriptorSet::image_view(2, ImageView::new_default(output.clone()).unwrap()),
+                WriteDescriptorSet::buffer(3, reprojection),
+            ],
+        )
+        .unwrap();
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipeline.layout().clone(),
+                0,
+                desc_set,
+            )
+            .dispatch(group_count(size, [COMPUTE_GROUP_SIZE; 2]))
+            .unwrap();
+
+        self.prev_camera = Some(camera);
+        self.output_idx = 1 - self.output_idx;
+        self.frame = self.frame.wrapping_add(1);
+        output
+    }
+}
+
+// radical inverse of `idx` in the given base, in [0, 1)
+fn halton(mut idx: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut f = 1.0;
+    while idx > 0 {
+        f /= base as f32;
+        result += f * (idx % base) as f32;
+        idx /= base;
+    }
+    result
+}
+
+pub mod taa_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/taa.comp",
+        types_meta: {
+            use bytemuck::{Pod, Zeroable};
+            #[derive(Clone, Debug, Copy, Zeroable, Pod)]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn halton_base_2() {
+        assert_eq!(0.5, halton(1, 2));
+        assert_eq!(0.25, halton(2, 2));
+        assert_eq!(0.75, halton(3, 2));
+    }
+
+    #[test]
+    fn halton_base_3() {
+        assert!((halton(1, 3) - 1.0 / 3.0).abs() < 1e-6);
+        assert!((halton(2, 3) - 2.0 / 3.0).abs() < 1e-6);
+        assert!((halton(3, 3) - 1.0 / 9.0).abs() < 1e-6);
+    }
+}
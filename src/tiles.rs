@@ -0,0 +1,154 @@
+/// Tile size of `Renderer::render_tiled` when none is given.
+pub const DEFAULT_TILE_SIZE: u32 = 2048;
+
+/// Tiles are at least half this across, the rays of a tile being spread
+/// between its first and last pixel.
+pub const MIN_TILE_SIZE: u32 = 16;
+
+/// A rectangle of a large image, rendered on its own.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tile {
+    pub offset: [u32; 2],
+    pub size: [u32; 2],
+}
+
+/// Splits an image of `size` into tiles at most `tile_size` across, row by
+/// row from the top left. The tiles of a row or column are about the same
+/// size, so none is left thinner than the others.
+pub fn tiles(size: [u32; 2], tile_size: u32) -> Vec<Tile> {
+    let tile_size = tile_size.max(MIN_TILE_SIZE);
+    let edges = |len: u32| {
+        let count = ((len + tile_size - 1) / tile_size).max(1);
+        (0..=count)
+            .map(|i| (len as u64 * i as u64 / count as u64) as u32)
+            .collect::<Vec<_>>()
+    };
+    let (xs, ys) = (edges(size[0]), edges(size[1]));
+    ys.windows(2)
+        .flat_map(|y| {
+            xs.windows(2).map(move |x| Tile {
+                offset: [x[0], y[0]],
+                size: [x[1] - x[0], y[1] - y[0]],
+            })
+        })
+        .collect()
+}
+
+impl Tile {
+    /// The part of `window` the tile covers, `window` being the part of the
+    /// view the whole image of `image_size` covers, see `camera::Projection`.
+    /// The rays of graphics.comp go from the first to the last pixel of the
+    /// image, so the tile's edges are at its outer pixels for the rays of the
+    /// tiles to be those of the whole image.
+    pub fn window(&self, image_size: [u32; 2], window: [f32; 4]) -> [f32; 4] {
+        let edge = |axis: usize, pixel: u32| {
+            let t = pixel as f32 / (image_size[axis] - 1) as f32;
+            window[axis] + (window[axis + 2] - window[axis]) * t
+        };
+        [
+            edge(0, self.offset[0]),
+            edge(1, self.offset[1]),
+            edge(0, self.offset[0] + self.size[0] - 1),
+            edge(1, self.offset[1] + self.size[1] - 1),
+        ]
+    }
+
+    /// Copies the RGBA8 `pixels` of the tile to its place in `image`.
+    pub fn copy_into(&self, pixels: &[u8], image: &mut [u8], image_size: [u32; 2]) {
+        let row_len = self.size[0] as usize * 4;
+        for (y, row) in pixels.chunks_exact(row_len).enumerate() {
+            let start = ((self.offset[1] as usize + y) * image_size[0] as usize
+                + self.offset[0] as usize)
+                * 4;
+            image[start..start + row_len].copy_from_slice(row);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::FULL_WINDOW;
+
+    #[test]
+    fn tiles_cover_the_image() {
+        let tiles = tiles([100, 40], 32);
+        // 4 columns of 25 and 2 rows of 20
+        assert_eq!(8, tiles.len());
+        assert_eq!(
+            Tile {
+                offset: [0, 0],
+                size: [25, 20]
+            },
+            tiles[0]
+        );
+        assert_eq!(
+            Tile {
+                offset: [75, 20],
+                size: [25, 20]
+            },
+            tiles[7]
+        );
+        let area: u32 = tiles.iter().map(|t| t.size[0] * t.size[1]).sum();
+        assert_eq!(100 * 40, area);
+    }
+
+    #[test]
+    fn small_image_is_one_tile() {
+        assert_eq!(
+            vec![Tile {
+                offset: [0, 0],
+                size: [8, 8]
+            }],
+            tiles([8, 8], 1024)
+        );
+    }
+
+    #[test]
+    fn tile_windows_share_the_rays() {
+        let close = |a: [f32; 4], b: [f32; 4]| (0..4).all(|i| (a[i] - b[i]).abs() < 1e-6);
+        let size = [11, 5];
+        let whole = Tile {
+            offset: [0, 0],
+            size,
+        };
+        assert!(close(FULL_WINDOW, whole.window(size, FULL_WINDOW)));
+        // pixels 0 to 4 and 5 to 10 of 0 to 10, 5 being the middle of the view
+        let left = Tile {
+            offset: [0, 0],
+            size: [5, 5],
+        };
+        let right = Tile {
+            offset: [5, 0],
+            size: [6, 5],
+        };
+        assert!(close(
+            [-1.0, -1.0, -0.2, 1.0],
+            left.window(size, FULL_WINDOW)
+        ));
+        assert!(close(
+            [0.0, -1.0, 1.0, 1.0],
+            right.window(size, FULL_WINDOW)
+        ));
+        // within a window that is itself part of the view
+        assert!(close(
+            [0.5, 0.0, 1.0, 1.0],
+            right.window(size, [0.0, 0.0, 1.0, 1.0])
+        ));
+    }
+
+    #[test]
+    fn copy_into_places_rows() {
+        let mut image = vec![0; 3 * 2 * 4];
+        let tile = Tile {
+            offset: [1, 0],
+            size: [2, 2],
+        };
+        let pixels: Vec<u8> = (1..=16).collect();
+        tile.copy_into(&pixels, &mut image, [3, 2]);
+        assert_eq!([0; 4], image[0..4]);
+        assert_eq!(pixels[0..8], image[4..12]);
+        assert_eq!([0; 4], image[12..16]);
+        assert_eq!(pixels[8..16], image[16..24]);
+    }
+}
@@ -0,0 +1,129 @@
+use std::{f32::consts::PI, time::Duration};
+
+use vecmath::Vector3;
+
+use crate::graphics::cs::ty::Lighting;
+
+const DAY_SKY: Vector3<f32> = [0.45, 0.65, 0.95];
+const NIGHT_SKY: Vector3<f32> = [0.01, 0.01, 0.04];
+const SUNSET: Vector3<f32> = [0.9, 0.45, 0.2];
+const DAY_AMBIENT: f32 = 0.35;
+const NIGHT_AMBIENT: f32 = 0.08;
+// cosine of the angular radius of the sun disc
+const SUN_COS_RADIUS: f32 = 0.9995;
+
+/// Position of the sun over a day that lasts `day_length` of real time.
+pub struct TimeOfDay {
+    // fraction of the day, 0 is midnight and 0.5 noon
+    time: f32,
+    day_length: Duration,
+}
+
+impl TimeOfDay {
+    /// Starts at noon.
+    pub fn new(day_length: Duration) -> Self {
+        TimeOfDay {
+            time: 0.5,
+            day_length,
+        }
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn set_time(&mut self, time: f32) {
+        self.time = time.rem_euclid(1.0);
+    }
+
+    pub fn day_length(&self) -> Duration {
+        self.day_length
+    }
+
+    pub fn set_day_length(&mut self, day_length: Duration) {
+        self.day_length = day_length;
+    }
+
+    pub fn advance(&mut self, dt: Duration) {
+        if self.day_length.is_zero() {
+            return;
+        }
+        self.set_time(self.time + dt.as_secs_f32() / self.day_length.as_secs_f32());
+    }
+
+    /// Unit vector pointing towards the sun. It rises in +x and sets in -x.
+    pub fn sun_direction(&self) -> Vector3<f32> {
+        let angle = 2.0 * PI * (self.time - 0.25);
+        vecmath::vec3_normalized([angle.cos(), angle.sin(), 0.25])
+    }
+
+    pub fn lighting(&self) -> Lighting {
+        let sun_dir = self.sun_direction();
+        let height = sun_dir[1];
+        let daylight = smoothstep(-0.1, 0.2, height);
+        // strongest with the sun at the horizon
+        let dusk = (1.0 - height.abs() * 5.0).max(0.0);
+        let sky = vecmath::vec3_add(
+            mix(NIGHT_SKY, DAY_SKY, daylight),
+            vecmath::vec3_scale(SUNSET, 0.3 * dusk),
+        );
+        let sun = mix(SUNSET, [1.0, 0.97, 0.9], (height * 4.0).clamp(0.0, 1.0));
+        Lighting {
+            sun_dir,
+            ambient: NIGHT_AMBIENT + (DAY_AMBIENT - NIGHT_AMBIENT) * daylight,
+            sun_color: vecmath::vec3_scale(sun, daylight),
+            sun_cos_radius: SUN_COS_RADIUS,
+            sky_color: sky,
+            // up to the renderer, see Shadows
+            sun_shadow_samples: 0,
+            sun_shadow_cone: 0.0,
+        }
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn mix(a: Vector3<f32>, b: Vector3<f32>, t: f32) -> Vector3<f32> {
+    [0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sun_is_up_at_noon_and_down_at_midnight() {
+        let mut tod = TimeOfDay::new(Duration::from_secs(60));
+        assert!(tod.sun_direction()[1] > 0.9);
+        tod.set_time(0.0);
+        assert!(tod.sun_direction()[1] < -0.9);
+    }
+
+    #[test]
+    fn advance_wraps_around() {
+        let mut tod = TimeOfDay::new(Duration::from_secs(60));
+        tod.advance(Duration::from_secs(45));
+        assert!((tod.time() - 0.25).abs() < 1e-5);
+    }
+
+    #[test]
+    fn zero_day_length_stops_time() {
+        let mut tod = TimeOfDay::new(Duration::ZERO);
+        tod.advance(Duration::from_secs(1));
+        assert_eq!(0.5, tod.time());
+    }
+
+    #[test]
+    fn night_is_darker() {
+        let mut tod = TimeOfDay::new(Duration::from_secs(60));
+        let day = tod.lighting();
+        tod.set_time(0.0);
+        let night = tod.lighting();
+        assert!(night.ambient < day.ambient);
+        assert!(night.sky_color[2] < day.sky_color[2]);
+        assert_eq!([0.0; 3], night.sun_color);
+    }
+}
@@ -0,0 +1,171 @@
+use std::sync::Arc;
+
+use bytemuck::Pod;
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer, DeviceLocalBuffer},
+    command_buffer::{
+        AutoCommandBufferBuilder, BufferImageCopy, CommandBufferUsage, CopyBufferInfo,
+        CopyBufferToImageInfo, PrimaryAutoCommandBuffer, PrimaryCommandBuffer,
+    },
+    device::{physical::QueueFamily, Queue},
+    image::{ImageAccess, StorageImage},
+    sync::GpuFuture,
+};
+
+/// Uploads data into device local memory. Copies are submitted to a dedicated
+/// transfer queue when the device has one so they don't wait behind rendering.
+pub struct Uploader {
+    graphics_queue: Arc<Queue>,
+    transfer_queue: Arc<Queue>,
+    // uploads the graphics queue hasn't waited on yet
+    pending: Option<Box<dyn GpuFuture>>,
+}
+
+impl Uploader {
+    /// `transfer_queue` may be the graphics queue itself if the device has no
+    /// separate transfer queue.
+    pub fn new(graphics_queue: Arc<Queue>, transfer_queue: Arc<Queue>) -> Self {
+        Uploader {
+            graphics_queue,
+            transfer_queue,
+            pending: None,
+        }
+    }
+
+    pub fn is_async(&self) -> bool {
+        self.graphics_queue.family() != self.transfer_queue.family()
+    }
+
+    /// Queue families that resources written by the uploader must be shared
+    /// between.
+    pub fn queue_families(&self) -> Vec<QueueFamily> {
+        let mut families = vec![self.graphics_queue.family()];
+        if self.is_async() {
+            families.push(self.transfer_queue.family());
+        }
+        families
+    }
+
+    /// Copies `data` into a new device local buffer with the given usage.
+    pub fn upload_buffer<T>(
+        &mut self,
+        data: Vec<T>,
+        usage: BufferUsage,
+    ) -> Arc<DeviceLocalBuffer<[T]>>
+    where
+        T: Pod + Send + Sync,
+    {
+        let device = self.transfer_queue.device().clone();
+        let buffer = DeviceLocalBuffer::array(
+            device.clone(),
+            data.len() as u64,
+            BufferUsage {
+                transfer_dst: true,
+                ..usage
+            },
+            self.queue_families(),
+        )
+        .unwrap();
+        let staging =
+            CpuAccessibleBuffer::from_iter(device, BufferUsage::transfer_src(), false, data)
+                .unwrap();
+        let mut builder = self.builder();
+        builder
+            .copy_buffer(CopyBufferInfo::buffers(staging, buffer.clone()))
+            .unwrap();
+        self.submit(builder);
+        buffer
+    }
+
+    /// Copies `data` into every layer of `image`, which must be shared with
+    /// `queue_families`.
+    pub fn upload_image(&mut self, data: Vec<u8>, image: Arc<StorageImage>) {
+        let staging = CpuAccessibleBuffer::from_iter(
+            self.transfer_queue.device().clone(),
+            BufferUsage::transfer_src(),
+            false,
+            data,
+        )
+        .unwrap();
+        let mut builder = self.builder();
+        builder
+            .copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(staging, image))
+            .unwrap();
+        self.submit(builder);
+    }
+
+    /// Copies each of `levels` into every layer of the mip level of `image`
+    /// it's at, the first being the full size one.
+    pub fn upload_image_levels(&mut self, levels: Vec<Vec<u8>>, image: Arc<dyn ImageAccess>) {
+        let offsets: Vec<u64> = levels
+            .iter()
+            .scan(0, |offset, level| {
+                let start = *offset;
+                *offset += level.len() as u64;
+                Some(start)
+            })
+            .collect();
+        let staging = CpuAccessibleBuffer::from_iter(
+            self.transfer_queue.device().clone(),
+            BufferUsage::transfer_src(),
+            false,
+            levels.concat(),
+        )
+        .unwrap();
+        let dimensions = image.dimensions();
+        let mut info = CopyBufferToImageInfo::buffer_image(staging, image);
+        let first = info.regions[0].clone();
+        info.regions = offsets
+            .into_iter()
+            .enumerate()
+            .map(|(level, buffer_offset)| {
+                let level = level as u32;
+                let mut region = BufferImageCopy {
+                    buffer_offset,
+                    image_extent: dimensions
+                        .mip_level_dimensions(level)
+                        .unwrap()
+                        .width_height_depth(),
+                    ..first.clone()
+                };
+                region.image_subresource.mip_level = level;
+                region
+            })
+            .collect();
+        let mut builder = self.builder();
+        builder.copy_buffer_to_image(info).unwrap();
+        self.submit(builder);
+    }
+
+    /// Takes the uploads submitted since the last call. The graphics queue
+    /// must wait on the returned future before using the uploaded resources.
+    pub fn take_pending(&mut self) -> Option<Box<dyn GpuFuture>> {
+        self.pending.take()
+    }
+
+    fn builder(&self) -> AutoCommandBufferBuilder<PrimaryAutoCommandBuffer> {
+        AutoCommandBufferBuilder::primary(
+            self.transfer_queue.device().clone(),
+            self.transfer_queue.family(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap()
+    }
+
+    fn submit(&mut self, builder: AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) {
+        // flushing right away starts the copy now instead of at the next frame,
+        // the semaphore lets the graphics queue wait for it without a fence
+        let future = builder
+            .build()
+            .unwrap()
+            .execute(self.transfer_queue.clone())
+            .unwrap()
+            .then_signal_semaphore_and_flush()
+            .unwrap()
+            .boxed();
+        self.pending = Some(match self.pending.take() {
+            Some(pending) => pending.join(future).boxed(),
+            None => future,
+        });
+    }
+}
@@ -0,0 +1,94 @@
+use std::sync::Arc;
+
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
+    descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet},
+    device::Queue,
+    image::{view::ImageView, ImageAccess, StorageImage},
+    memory::pool::StdMemoryPool,
+    pipeline::{ComputePipeline, Pipeline, PipelineBindPoint},
+};
+
+use crate::{
+    graphics::{Graphics, COMPUTE_GROUP_SIZE},
+    workgroups::group_count,
+};
+
+/// Resamples the ray traced image to the swapchain resolution with a
+/// Lanczos filter, which stays sharp where a linear blit would blur.
+pub struct Upscaler {
+    pipeline: Arc<ComputePipeline>,
+    output: Arc<StorageImage<Arc<StdMemoryPool>>>,
+}
+
+impl Upscaler {
+    pub fn new(queue: &Arc<Queue>, size: [u32; 2]) -> Self {
+        let shader = upscale_cs::load(queue.device().clone()).unwrap();
+        let pipeline = ComputePipeline::new(
+            queue.device().clone(),
+            shader.entry_point("main").unwrap(),
+            &(),
+            None,
+            |_| {},
+        )
+        .unwrap();
+        Upscaler {
+            pipeline,
+            output: Graphics::create_storage_image(queue, size),
+        }
+    }
+
+    /// Resizes the output image, e.g. after the swapchain was recreated.
+    /// Bytes of device memory its image holds.
+    pub fn memory(&self) -> u64 {
+        Graphics::image_bytes(&*self.output)
+    }
+
+    pub fn resize(&mut self, queue: &Arc<Queue>, size: [u32; 2]) {
+        if self.output.dimensions().width_height() != size {
+            self.output = Graphics::create_storage_image(queue, size);
+        }
+    }
+
+    /// Records the upscale of `input` and returns the output image. When the
+    /// sizes already match `input` is returned without recording anything.
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        input: Arc<StorageImage<Arc<StdMemoryPool>>>,
+    ) -> Arc<StorageImage<Arc<StdMemoryPool>>> {
+        let size = self.output.dimensions().width_height();
+        if input.dimensions().width_height() == size {
+            return input;
+        }
+        let desc_set = PersistentDescriptorSet::new(
+            self.pipeline.layout().set_layouts().get(0).unwrap().clone(),
+            [
+                WriteDescriptorSet::image_view(0, ImageView::new_default(input).unwrap()),
+                WriteDescriptorSet::image_view(
+                    1,
+                    ImageView::new_default(self.output.clone()).unwrap(),
+                ),
+            ],
+        )
+        .unwrap();
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipeline.layout().clone(),
+                0,
+                desc_set,
+            )
+            .dispatch(group_count(size, [COMPUTE_GROUP_SIZE; 2]))
+            .unwrap();
+        self.output.clone()
+    }
+}
+
+pub mod upscale_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/upscale.comp",
+    }
+}
@@ -0,0 +1,69 @@
+use std::sync::Arc;
+
+use log::Level;
+use vulkano::instance::{
+    debug::{
+        DebugUtilsMessageSeverity, DebugUtilsMessageType, DebugUtilsMessenger,
+        DebugUtilsMessengerCreateInfo, Message,
+    },
+    Instance, InstanceExtensions,
+};
+
+/// Checks every Vulkan call for misuse, at a large cost to the frame time.
+pub const LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+/// What the instance needs on top of `extensions` for `messenger`.
+pub fn extensions(extensions: InstanceExtensions) -> InstanceExtensions {
+    InstanceExtensions {
+        ext_debug_utils: true,
+        ..extensions
+    }
+}
+
+/// Logs what the validation layer and the driver report, under the `vulkan`
+/// target. Messages stop once the messenger is dropped.
+pub fn messenger(instance: &Arc<Instance>) -> DebugUtilsMessenger {
+    // the callback only logs, which is safe to do from any thread
+    unsafe {
+        DebugUtilsMessenger::new(
+            instance.clone(),
+            DebugUtilsMessengerCreateInfo {
+                message_severity: DebugUtilsMessageSeverity::all(),
+                message_type: DebugUtilsMessageType::all(),
+                ..DebugUtilsMessengerCreateInfo::user_callback(Arc::new(log_message))
+            },
+        )
+        .unwrap()
+    }
+}
+
+fn log_message(message: &Message) {
+    let kind = if message.ty.validation {
+        "validation"
+    } else if message.ty.performance {
+        "performance"
+    } else {
+        "general"
+    };
+    log::log!(
+        target: "vulkan",
+        level(message.severity),
+        "[{}] {}: {}",
+        kind,
+        message.layer_prefix.unwrap_or("driver"),
+        message.description
+    );
+}
+
+// informational messages are mostly the loader listing what it found
+fn level(severity: DebugUtilsMessageSeverity) -> Level {
+    if severity.error {
+        Level::Error
+    } else if severity.warning {
+        Level::Warn
+    } else if severity.information {
+        Level::Debug
+    } else {
+        Level::Trace
+    }
+}
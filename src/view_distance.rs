@@ -0,0 +1,88 @@
+use crate::worldgen::CHUNK_SIZE;
+
+pub const MIN_VIEW_DISTANCE: i32 = 1;
+pub const MAX_VIEW_DISTANCE: i32 = 24;
+
+/// How far the world reaches around the camera, in chunks of
+/// `worldgen::CHUNK_SIZE`. Chunks are generated that far, rays give up there
+/// and the fog thickens up to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ViewDistance(i32);
+
+impl ViewDistance {
+    /// Clamped between `MIN_VIEW_DISTANCE` and `MAX_VIEW_DISTANCE`.
+    pub fn new(chunks: i32) -> Self {
+        ViewDistance(chunks.clamp(MIN_VIEW_DISTANCE, MAX_VIEW_DISTANCE))
+    }
+
+    pub fn chunks(self) -> i32 {
+        self.0
+    }
+
+    /// How far rays go before they give up and show the sky.
+    pub fn voxels(self) -> f32 {
+        (self.0 * CHUNK_SIZE) as f32
+    }
+
+    pub fn farther(self) -> Self {
+        Self::new(self.0 + 1)
+    }
+
+    pub fn nearer(self) -> Self {
+        Self::new(self.0 - 1)
+    }
+
+    /// The farthest distance nearer than this one where the world should fit
+    /// in `allowance` bytes, when holding it out to this distance takes
+    /// `bytes`. The chunks held grow with the cube of the distance.
+    pub fn within(self, bytes: u64, allowance: u64) -> Self {
+        let held = |distance: Self| (2 * distance.0 as u128 + 1).pow(3);
+        let mut distance = self.nearer();
+        while distance.0 > MIN_VIEW_DISTANCE
+            && bytes as u128 * held(distance) / held(self) > allowance as u128
+        {
+            distance = distance.nearer();
+        }
+        distance
+    }
+}
+
+impl Default for ViewDistance {
+    /// 256 voxels, with fog from 64 on, as far as the world was drawn before
+    /// the view distance could be changed.
+    fn default() -> Self {
+        ViewDistance(256 / CHUNK_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distance_is_clamped() {
+        assert_eq!(MIN_VIEW_DISTANCE, ViewDistance::new(-4).chunks());
+        assert_eq!(MAX_VIEW_DISTANCE, ViewDistance::new(100).chunks());
+        let farthest = ViewDistance::new(MAX_VIEW_DISTANCE);
+        assert_eq!(farthest, farthest.farther());
+        assert_eq!(MAX_VIEW_DISTANCE - 1, farthest.nearer().chunks());
+        assert_eq!(ViewDistance::new(1), ViewDistance::new(1).nearer());
+    }
+
+    #[test]
+    fn budget_cuts_by_the_chunks_held() {
+        let distance = ViewDistance::new(4);
+        // 729 chunks held, 125 of them out to 2
+        assert_eq!(2, distance.within(729, 125).chunks());
+        assert_eq!(1, distance.within(729, 124).chunks());
+        assert_eq!(3, distance.within(729, 728).chunks());
+        assert_eq!(MIN_VIEW_DISTANCE, distance.within(u64::MAX, 0).chunks());
+    }
+
+    #[test]
+    fn rays_reach_the_chunks_streamed() {
+        let distance = ViewDistance::new(4);
+        assert_eq!(4.0 * CHUNK_SIZE as f32, distance.voxels());
+        assert_eq!(256.0, ViewDistance::default().voxels());
+    }
+}
@@ -0,0 +1,89 @@
+use crate::materials::MaterialId;
+
+/// Payload stored in the leaves of an octree. Leaves are serialized as `WORDS`
+/// consecutive words in the slots of their size 2 parent, so a present voxel
+/// must have a non-zero first word, 0 marks an empty slot.
+pub trait VoxelData: Copy {
+    const WORDS: usize;
+    /// Always `[u32; Self::WORDS]`.
+    type Words: AsRef<[u32]>;
+
+    fn to_gpu_words(&self) -> Self::Words;
+}
+
+impl VoxelData for i32 {
+    const WORDS: usize = 1;
+    type Words = [u32; 1];
+
+    fn to_gpu_words(&self) -> [u32; 1] {
+        [*self as u32]
+    }
+}
+
+impl VoxelData for bool {
+    const WORDS: usize = 1;
+    type Words = [u32; 1];
+
+    fn to_gpu_words(&self) -> [u32; 1] {
+        [*self as u32]
+    }
+}
+
+pub const MAX_LIGHT: u8 = 15;
+
+/// A voxel carrying an orientation and light level along with its material.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Voxel {
+    pub material: MaterialId,
+    /// Quarter turns around the y axis, 0 to 3.
+    pub orientation: u8,
+    /// 0 to `MAX_LIGHT`.
+    pub light: u8,
+}
+
+impl Voxel {
+    pub fn new(material: MaterialId) -> Self {
+        Voxel {
+            material,
+            orientation: 0,
+            light: MAX_LIGHT,
+        }
+    }
+}
+
+// the material, then the orientation and light packed into the low two bytes
+impl VoxelData for Voxel {
+    const WORDS: usize = 2;
+    type Words = [u32; 2];
+
+    fn to_gpu_words(&self) -> [u32; 2] {
+        [
+            self.material as u32,
+            (self.orientation & 3) as u32 | (self.light.min(MAX_LIGHT) as u32) << 8,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn voxel_words() {
+        let voxel = Voxel {
+            material: 3,
+            orientation: 2,
+            light: 7,
+        };
+        assert_eq!([3, 2 | 7 << 8], voxel.to_gpu_words());
+    }
+
+    #[test]
+    fn light_is_clamped() {
+        let voxel = Voxel {
+            light: 200,
+            ..Voxel::new(1)
+        };
+        assert_eq!((MAX_LIGHT as u32) << 8, voxel.to_gpu_words()[1]);
+    }
+}
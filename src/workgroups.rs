@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+/// Workgroup sizes of the ray tracing shader tried by the startup autotune.
+pub const WORKGROUP_SIZE_CANDIDATES: [[u32; 2]; 5] = [[8, 8], [16, 8], [8, 16], [16, 16], [32, 8]];
+
+/// Returns the number of workgroups needed to cover `size`, rounding up so
+/// sizes that aren't a multiple of the workgroup size are fully covered.
+pub fn group_count(size: [u32; 2], group_size: [u32; 2]) -> [u32; 3] {
+    [
+        (size[0] + group_size[0] - 1) / group_size[0],
+        (size[1] + group_size[1] - 1) / group_size[1],
+        1,
+    ]
+}
+
+/// Returns the candidates within the device's compute limits.
+pub fn supported_candidates(max_invocations: u32, max_size: [u32; 3]) -> Vec<[u32; 2]> {
+    WORKGROUP_SIZE_CANDIDATES
+        .into_iter()
+        .filter(|[x, y]| x * y <= max_invocations && *x <= max_size[0] && *y <= max_size[1])
+        .collect()
+}
+
+/// Picks the workgroup size with the lowest measured time.
+pub fn fastest(timings: &[([u32; 2], Duration)]) -> Option<[u32; 2]> {
+    timings
+        .iter()
+        .min_by_key(|(_, time)| *time)
+        .map(|(size, _)| *size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn group_count_rounds_up() {
+        assert_eq!([2, 1, 1], group_count([16, 8], [8, 8]));
+        assert_eq!([3, 2, 1], group_count([17, 9], [8, 8]));
+        assert_eq!([1, 1, 1], group_count([1, 1], [16, 16]));
+    }
+
+    #[test]
+    fn candidates_respect_limits() {
+        assert_eq!(vec![[8, 8]], supported_candidates(64, [64, 64, 64]));
+        assert_eq!(
+            vec![[8, 8], [16, 8], [32, 8]],
+            supported_candidates(256, [32, 8, 1])
+        );
+    }
+
+    #[test]
+    fn fastest_picks_lowest_time() {
+        let timings = [
+            ([8, 8], Duration::from_micros(300)),
+            ([16, 8], Duration::from_micros(200)),
+            ([16, 16], Duration::from_micros(250)),
+        ];
+        assert_eq!(Some([16, 8]), fastest(&timings));
+        assert_eq!(None, fastest(&[]));
+    }
+}
@@ -0,0 +1,695 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use vecmath::Vector3;
+
+use crate::{
+    aabc::Aabc,
+    journal::{Edit, EditJournal},
+    materials::MaterialId,
+    octree::{MergePolicy, Octree, VoxelChange},
+    prefab::VoxelPrefab,
+    raycast::{raycast, RaycastHit},
+    worldgen::{self, ChunkPos},
+};
+
+// voxel changes kept for undo, about 20 bytes each
+const MAX_JOURNAL_CHANGES: usize = 1 << 20;
+
+/// The editable voxel world. All voxel edits go through `World` so the octree
+/// stays consistent with what is uploaded to the GPU, and so every edit can be
+/// undone.
+pub struct World {
+    tree: Octree<MaterialId>,
+    journal: EditJournal,
+    // chunks edited since they were last saved
+    dirty: BTreeSet<ChunkPos>,
+    // changes not taken yet, kept once the world is shared
+    outbox: Option<Vec<VoxelChange<MaterialId>>>,
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl World {
+    pub fn new() -> Self {
+        World {
+            tree: Octree::new(),
+            journal: EditJournal::new(MAX_JOURNAL_CHANGES),
+            dirty: BTreeSet::new(),
+            outbox: None,
+        }
+    }
+
+    /// Fills the cube from -extent to extent with randomly placed voxels of a
+    /// single material.
+    pub fn random<R: Rng>(rng: &mut R, extent: i32, material: MaterialId) -> Self {
+        let mut world = World::new();
+        for i in -extent..extent {
+            for j in -extent..extent {
+                for k in -extent..extent {
+                    if rng.gen_range(0..12) == 0 {
+                        world.set_voxel([i, j, k], material);
+                    }
+                }
+            }
+        }
+        world
+    }
+
+    /// Same as `random` but reproducible for a given seed.
+    pub fn seeded(seed: u64, extent: i32, material: MaterialId) -> Self {
+        Self::random(&mut StdRng::seed_from_u64(seed), extent, material)
+    }
+
+    pub fn tree(&self) -> &Octree<MaterialId> {
+        &self.tree
+    }
+
+    pub fn get(&self, pos: Vector3<i32>) -> Option<MaterialId> {
+        self.tree.get_leaf(pos)
+    }
+
+    /// Places a voxel, returning the material it replaced.
+    pub fn set(&mut self, pos: Vector3<i32>, material: MaterialId) -> Option<MaterialId> {
+        let change = self.set_voxel(pos, material);
+        self.record(vec![change]);
+        match change {
+            VoxelChange::Changed(_, previous, _) => Some(previous),
+            _ => None,
+        }
+    }
+
+    /// Reverts the latest edit. Returns false if there was nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        match self.journal.undo() {
+            Some(changes) => {
+                // other players may have edited the same voxels since
+                for change in &changes {
+                    let (pos, material) = change.result();
+                    self.set_or_remove(pos, material);
+                }
+                self.changed(&changes);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reapplies the latest undone edit. Returns false if there was nothing to
+    /// redo.
+    pub fn redo(&mut self) -> bool {
+        match self.journal.redo() {
+            Some(changes) => {
+                // other players may have edited the same voxels since
+                for change in &changes {
+                    let (pos, material) = change.result();
+                    self.set_or_remove(pos, material);
+                }
+                self.changed(&changes);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the first voxel hit by a ray, see `raycast::raycast`.
+    pub fn raycast(
+        &self,
+        origin: Vector3<f32>,
+        dir: Vector3<f32>,
+        max_distance: f32,
+    ) -> Option<RaycastHit> {
+        raycast(origin, dir, max_distance, |pos| {
+            self.tree.get_leaf(pos).is_some()
+        })
+    }
+
+    /// Removes every voxel intersecting the sphere, returning how many were
+    /// removed.
+    pub fn carve_sphere(&mut self, center: Vector3<f32>, radius: f32) -> u32 {
+        let mut edit = Edit::new();
+        for cube in sphere_cubes(center, radius) {
+            for (pos, material) in self.tree.leaves_in(cube) {
+                edit.push(VoxelChange::Removed(pos, material));
+            }
+            self.tree.clear_region(cube);
+        }
+        let removed = edit.len() as u32;
+        self.record(edit);
+        removed
+    }
+
+    /// Sets every voxel intersecting the sphere to `material`.
+    pub fn paint_sphere(&mut self, center: Vector3<f32>, radius: f32, material: MaterialId) {
+        let mut edit = Edit::new();
+        for cube in sphere_cubes(center, radius) {
+            let previous: HashMap<_, _> = self.tree.leaves_in(cube).into_iter().collect();
+            self.tree.fill_region(cube, material);
+            let size = cube.size as i32;
+            for x in 0..size {
+                for y in 0..size {
+                    for z in 0..size {
+                        let pos = vecmath::vec3_add(cube.origin, [x, y, z]);
+                        match previous.get(&pos) {
+                            None => edit.push(VoxelChange::Added(pos, material)),
+                            Some(&old) if old != material => {
+                                edit.push(VoxelChange::Changed(pos, old, material))
+                            }
+                            Some(_) => (),
+                        }
+                    }
+                }
+            }
+        }
+        self.record(edit);
+    }
+
+    /// Sets every voxel from `min` to `max`, both included, to `material`, or
+    /// removes them for None, as a single edit. Returns how many changed.
+    pub fn fill_box(
+        &mut self,
+        min: Vector3<i32>,
+        max: Vector3<i32>,
+        material: Option<MaterialId>,
+    ) -> usize {
+        let mut edit = Edit::new();
+        for x in min[0]..=max[0] {
+            for y in min[1]..=max[1] {
+                for z in min[2]..=max[2] {
+                    edit.extend(self.replace_voxel([x, y, z], material));
+                }
+            }
+        }
+        let changed = edit.len();
+        self.record(edit);
+        changed
+    }
+
+    /// Sets each position to the material or removes its voxel for None, as
+    /// what followed from the latest edit, like voxels falling after it.
+    /// Undoing that edit undoes them too.
+    pub fn settle(&mut self, edits: &[(Vector3<i32>, Option<MaterialId>)]) {
+        let mut changes = Edit::new();
+        for &(pos, material) in edits {
+            changes.extend(self.replace_voxel(pos, material));
+        }
+        self.changed(&changes);
+        self.journal.amend(changes);
+    }
+
+    pub fn copy_region(&self, region: Aabc) -> VoxelPrefab {
+        let size = region.size as i32;
+        VoxelPrefab::new(region.origin, [size; 3], self.tree.leaves_in(region))
+    }
+
+    /// Copies the voxels from `min` to `max`, both included.
+    pub fn copy_box(&self, min: Vector3<i32>, max: Vector3<i32>) -> VoxelPrefab {
+        let extent = [0, 1, 2].map(|i| max[i] - min[i] + 1);
+        // the prefab keeps what's in the box of the cube around it
+        let size = extent.into_iter().max().unwrap().max(1) as u32;
+        VoxelPrefab::new(min, extent, self.tree.leaves_in(Aabc::new(min, size)))
+    }
+
+    /// Copies every voxel, from the minimum corner of their bounding box.
+    pub fn copy_all(&self) -> VoxelPrefab {
+        let leaves = self.tree.leaves();
+        if leaves.is_empty() {
+            return VoxelPrefab::new([0; 3], [0; 3], leaves);
+        }
+        let mut min = leaves[0].0;
+        let mut max = leaves[0].0;
+        for (pos, _) in &leaves {
+            for i in 0..3 {
+                min[i] = min[i].min(pos[i]);
+                max[i] = max[i].max(pos[i]);
+            }
+        }
+        let extent = [0, 1, 2].map(|i| max[i] - min[i] + 1);
+        VoxelPrefab::new(min, extent, leaves)
+    }
+
+    /// Places the voxels of `prefab` with its minimum corner at `offset`,
+    /// replacing what's there. Empty space in the prefab is left untouched.
+    pub fn paste(&mut self, prefab: &VoxelPrefab, offset: Vector3<i32>) {
+        let edit = prefab
+            .voxels
+            .iter()
+            .map(|(pos, material)| self.set_voxel(vecmath::vec3_add(offset, *pos), *material))
+            .collect();
+        self.record(edit);
+    }
+
+    /// Adds the voxels of a generated chunk. Voxels already placed are kept,
+    /// and since generating isn't an edit it can't be undone and doesn't
+    /// need saving.
+    pub fn insert_chunk(&mut self, chunk: &Octree<MaterialId>) {
+        self.tree.merge(chunk, MergePolicy::KeepExisting);
+    }
+
+    /// Drops the voxels of a chunk to free their memory, unless it has edits
+    /// that weren't saved yet and couldn't be generated or loaded again.
+    /// Returns whether it was dropped. Like `insert_chunk` this isn't an edit.
+    pub fn unload_chunk(&mut self, chunk: ChunkPos) -> bool {
+        if self.dirty.contains(&chunk) {
+            return false;
+        }
+        let origin = chunk.map(|c| c * worldgen::CHUNK_SIZE);
+        self.tree
+            .clear_region(Aabc::new(origin, worldgen::CHUNK_SIZE as u32));
+        true
+    }
+
+    /// Removes a voxel, returning its material if there was one.
+    pub fn remove(&mut self, pos: Vector3<i32>) -> Option<MaterialId> {
+        let previous = self.tree.get_leaf(pos);
+        if let Some(material) = previous {
+            self.tree.remove_leaf(pos);
+            self.record(vec![VoxelChange::Removed(pos, material)]);
+        }
+        previous
+    }
+
+    /// Returns the chunks edited since they were last taken that `ready`
+    /// accepts, which need saving. The others stay dirty.
+    pub fn take_dirty_chunks(&mut self, mut ready: impl FnMut(ChunkPos) -> bool) -> Vec<ChunkPos> {
+        let taken: Vec<_> = self.dirty.iter().copied().filter(|c| ready(*c)).collect();
+        for chunk in &taken {
+            self.dirty.remove(chunk);
+        }
+        taken
+    }
+
+    /// Marks chunks as needing saving again, like those that failed to save.
+    pub fn mark_dirty(&mut self, chunks: impl IntoIterator<Item = ChunkPos>) {
+        self.dirty.extend(chunks);
+    }
+
+    /// Starts keeping the changes made by edits, undo and redo for
+    /// `take_changes`, so they can be sent to other players or announced.
+    pub fn share_changes(&mut self) {
+        self.outbox.get_or_insert_with(Vec::new);
+    }
+
+    /// Returns the changes made since the last call, oldest first.
+    pub fn take_changes(&mut self) -> Vec<VoxelChange<MaterialId>> {
+        self.outbox.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Applies edits made elsewhere, setting each position to the material or
+    /// removing its voxel for None. They can't be undone and aren't saved or
+    /// shared, as wherever they were made does that.
+    pub fn apply_remote(&mut self, edits: &[(Vector3<i32>, Option<MaterialId>)]) {
+        for &(pos, material) in edits {
+            self.set_or_remove(pos, material);
+        }
+    }
+
+    fn set_or_remove(&mut self, pos: Vector3<i32>, material: Option<MaterialId>) {
+        match material {
+            Some(material) => {
+                self.set_voxel(pos, material);
+            }
+            None if self.tree.get_leaf(pos).is_some() => self.tree.remove_leaf(pos),
+            None => {}
+        }
+    }
+
+    fn record(&mut self, edit: Edit) {
+        self.changed(&edit);
+        self.journal.record(edit);
+    }
+
+    fn changed(&mut self, changes: &[VoxelChange<MaterialId>]) {
+        if let Some(outbox) = &mut self.outbox {
+            outbox.extend_from_slice(changes);
+        }
+        for change in changes {
+            self.dirty
+                .insert(worldgen::chunk_containing(change.result().0));
+        }
+    }
+
+    // sets or removes a voxel without recording the change, None if it was
+    // already so
+    fn replace_voxel(
+        &mut self,
+        pos: Vector3<i32>,
+        material: Option<MaterialId>,
+    ) -> Option<VoxelChange<MaterialId>> {
+        match (self.tree.get_leaf(pos), material) {
+            (Some(old), Some(new)) if old == new => None,
+            (_, Some(new)) => Some(self.set_voxel(pos, new)),
+            (Some(old), None) => {
+                self.tree.remove_leaf(pos);
+                Some(VoxelChange::Removed(pos, old))
+            }
+            (None, None) => None,
+        }
+    }
+
+    // edits the tree without recording the change
+    fn set_voxel(&mut self, pos: Vector3<i32>, material: MaterialId) -> VoxelChange<MaterialId> {
+        let change = match self.tree.get_leaf(pos) {
+            Some(previous) => {
+                self.tree.remove_leaf(pos);
+                VoxelChange::Changed(pos, previous, material)
+            }
+            None => VoxelChange::Added(pos, material),
+        };
+        self.tree.insert_leaf(material, pos);
+        change
+    }
+}
+
+// Covers the voxels intersecting a sphere with as few cubes as possible, so
+// the octree can edit whole subtrees at once.
+fn sphere_cubes(center: Vector3<f32>, radius: f32) -> Vec<Aabc> {
+    let min = center.map(|c| (c - radius).floor() as i32);
+    let extent = (0..3)
+        .map(|i| (center[i] + radius).ceil() as i32 - min[i])
+        .max()
+        .unwrap();
+    let mut cubes = Vec::new();
+    let mut stack = vec![Aabc::new(min, (extent.max(1) as u32).next_power_of_two())];
+    while let Some(cube) = stack.pop() {
+        let lo = cube.origin.map(|c| c as f32);
+        let hi = lo.map(|c| c + cube.size as f32);
+        let mut nearest = 0.0;
+        let mut farthest = 0.0;
+        for i in 0..3 {
+            let near = center[i].clamp(lo[i], hi[i]) - center[i];
+            let far = (lo[i] - center[i]).abs().max((hi[i] - center[i]).abs());
+            nearest += near * near;
+            farthest += far * far;
+        }
+        if nearest >= radius * radius {
+            continue;
+        }
+        if farthest <= radius * radius || cube.size == 1 {
+            cubes.push(cube);
+            continue;
+        }
+        let half = cube.size / 2;
+        for offset in Aabc::new([0, 0, 0], 1).corners() {
+            let origin = [0, 1, 2].map(|i| cube.origin[i] + offset[i] * half as i32);
+            stack.push(Aabc::new(origin, half));
+        }
+    }
+    cubes
+}
+
+/// Returns `count` distinct voxel positions scattered through a cube centered
+/// on the origin about 8 times the volume of the voxels. The positions only
+/// depend on `seed`, which makes them suitable as benchmark input.
+pub fn scattered_positions(seed: u64, count: usize) -> Vec<Vector3<i32>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let extent = ((count as f64).cbrt().ceil() as i32).max(1);
+    let mut seen = HashSet::with_capacity(count);
+    let mut positions = Vec::with_capacity(count);
+    while positions.len() < count {
+        let pos = [
+            rng.gen_range(-extent..extent),
+            rng.gen_range(-extent..extent),
+            rng.gen_range(-extent..extent),
+        ];
+        if seen.insert(pos) {
+            positions.push(pos);
+        }
+    }
+    positions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_and_get() {
+        let mut world = World::new();
+        assert_eq!(None, world.set([1, 2, 3], 4));
+        assert_eq!(Some(4), world.get([1, 2, 3]));
+    }
+
+    #[test]
+    fn set_replaces() {
+        let mut world = World::new();
+        world.set([1, 2, 3], 4);
+        assert_eq!(Some(4), world.set([1, 2, 3], 5));
+        assert_eq!(Some(5), world.get([1, 2, 3]));
+        assert_eq!(1, world.tree().count_leaves());
+    }
+
+    #[test]
+    fn seeded_is_reproducible() {
+        let a = World::seeded(7, 4, 1);
+        let b = World::seeded(7, 4, 1);
+        assert_eq!(a.tree().serialize(), b.tree().serialize());
+    }
+
+    #[test]
+    fn scattered_positions_are_distinct() {
+        let positions = scattered_positions(3, 1000);
+        let unique: HashSet<_> = positions.iter().collect();
+        assert_eq!(1000, unique.len());
+        assert_eq!(positions, scattered_positions(3, 1000));
+    }
+
+    // voxels intersecting the sphere, the slow way
+    fn sphere_voxels(center: Vector3<f32>, radius: f32) -> HashSet<Vector3<i32>> {
+        let r = radius.ceil() as i32 + 1;
+        let c = center.map(|c| c.floor() as i32);
+        let mut voxels = HashSet::new();
+        for x in c[0] - r..=c[0] + r {
+            for y in c[1] - r..=c[1] + r {
+                for z in c[2] - r..=c[2] + r {
+                    let pos = [x, y, z];
+                    let d: f32 = (0..3)
+                        .map(|i| {
+                            let near = center[i].clamp(pos[i] as f32, pos[i] as f32 + 1.0);
+                            (near - center[i]).powi(2)
+                        })
+                        .sum();
+                    if d < radius * radius {
+                        voxels.insert(pos);
+                    }
+                }
+            }
+        }
+        voxels
+    }
+
+    #[test]
+    fn paint_sphere_matches_voxels() {
+        let mut world = World::new();
+        let center = [0.5, 2.3, -1.0];
+        world.paint_sphere(center, 3.5, 2);
+        let expected = sphere_voxels(center, 3.5);
+        assert_eq!(expected.len() as u64, world.tree().count_leaves());
+        for pos in expected {
+            assert_eq!(Some(2), world.get(pos));
+        }
+        assert_eq!(Ok(()), world.tree().validate());
+    }
+
+    #[test]
+    fn carve_sphere_removes_voxels() {
+        let mut world = World::seeded(3, 6, 1);
+        world.set([0, 0, 0], 1);
+        let before = world.tree().count_leaves();
+        let inside: Vec<_> = sphere_voxels([0.5, 0.5, 0.5], 2.5)
+            .into_iter()
+            .filter(|&pos| world.get(pos).is_some())
+            .collect();
+        let removed = world.carve_sphere([0.5, 0.5, 0.5], 2.5);
+        assert_eq!(inside.len() as u32, removed);
+        assert_eq!(before - removed as u64, world.tree().count_leaves());
+        for pos in inside {
+            assert_eq!(None, world.get(pos));
+        }
+    }
+
+    #[test]
+    fn copy_and_paste_rotated() {
+        let mut world = World::new();
+        world.set([0, 0, 0], 1);
+        world.set([1, 0, 0], 2);
+        world.set([5, 5, 5], 3);
+        let prefab = world.copy_region(Aabc::new([0, 0, 0], 2));
+        assert_eq!(2, prefab.voxels.len());
+        world.paste(&prefab.rotated(1), [10, 0, 0]);
+        assert_eq!(Some(1), world.get([10, 0, 1]));
+        assert_eq!(Some(2), world.get([10, 0, 0]));
+        assert_eq!(5, world.tree().count_leaves());
+    }
+
+    #[test]
+    fn copy_box_keeps_to_the_box() {
+        let mut world = World::new();
+        world.fill_box([0, 0, 0], [4, 4, 4], Some(1));
+        let prefab = world.copy_box([1, 1, 1], [3, 1, 2]);
+        assert_eq!([3, 1, 2], prefab.extent);
+        assert_eq!(6, prefab.voxels.len());
+    }
+
+    #[test]
+    fn copy_all_fits_bounds() {
+        let mut world = World::new();
+        world.set([-2, 0, 1], 1);
+        world.set([3, 4, 1], 2);
+        let prefab = world.copy_all();
+        assert_eq!([6, 5, 1], prefab.extent);
+        assert_eq!(2, prefab.voxels.len());
+        assert!(World::new().copy_all().voxels.is_empty());
+    }
+
+    #[test]
+    fn undo_and_redo_edits() {
+        let mut world = World::seeded(1, 4, 1);
+        let original = world.tree().serialize();
+        assert!(!world.undo());
+        world.set([0, 0, 0], 2);
+        world.carve_sphere([0.5, 0.5, 0.5], 2.0);
+        world.paint_sphere([3.0, 3.0, 3.0], 1.5, 3);
+        let edited = world.tree().leaves().len();
+        assert!(world.undo());
+        assert!(world.undo());
+        assert!(world.undo());
+        assert!(!world.undo());
+        assert_eq!(original, world.tree().serialize());
+        assert!(world.redo());
+        assert!(world.redo());
+        assert!(world.redo());
+        assert!(!world.redo());
+        assert_eq!(edited, world.tree().leaves().len());
+        assert_eq!(Some(3), world.get([3, 3, 3]));
+    }
+
+    #[test]
+    fn settled_voxels_undo_with_the_edit() {
+        let mut world = World::new();
+        world.set([0, 5, 0], 1);
+        world.settle(&[([0, 5, 0], None), ([0, 0, 0], Some(1))]);
+        assert_eq!(Some(1), world.get([0, 0, 0]));
+        assert!(world.undo());
+        assert_eq!(0, world.tree().count_leaves());
+        assert!(!world.undo());
+        assert!(world.redo());
+        assert_eq!(Some(1), world.get([0, 0, 0]));
+        assert_eq!(None, world.get([0, 5, 0]));
+    }
+
+    #[test]
+    fn fill_box_is_one_edit() {
+        let mut world = World::new();
+        world.set([1, 1, 1], 2);
+        assert_eq!(7, world.fill_box([0, 0, 0], [1, 1, 1], Some(2)));
+        assert_eq!(Some(2), world.get([0, 1, 0]));
+        assert_eq!(8, world.fill_box([0, 0, 0], [1, 1, 1], None));
+        assert_eq!(None, world.get([1, 1, 1]));
+        assert!(world.undo());
+        assert_eq!(Some(2), world.get([0, 0, 0]));
+        assert!(world.undo());
+        assert_eq!(None, world.get([0, 0, 0]));
+        assert_eq!(Some(2), world.get([1, 1, 1]));
+    }
+
+    #[test]
+    fn remove_missing_is_none() {
+        let mut world = World::new();
+        world.set([0, 0, 0], 1);
+        assert_eq!(None, world.remove([5, 5, 5]));
+        assert_eq!(Some(1), world.remove([0, 0, 0]));
+        assert_eq!(0, world.tree().count_leaves());
+    }
+
+    #[test]
+    fn inserted_chunk_keeps_placed_voxels_and_isnt_undone() {
+        let mut world = World::new();
+        world.set([0, 0, 0], 1);
+        let mut chunk = Octree::new();
+        chunk.insert_leaf(2, [0, 0, 0]);
+        chunk.insert_leaf(2, [1, 0, 0]);
+        world.insert_chunk(&chunk);
+        assert_eq!(Some(1), world.get([0, 0, 0]));
+        assert_eq!(Some(2), world.get([1, 0, 0]));
+        assert!(world.undo());
+        assert_eq!(Some(2), world.get([1, 0, 0]));
+    }
+
+    #[test]
+    fn edits_mark_chunks_dirty() {
+        let mut world = World::new();
+        let mut chunk = Octree::new();
+        chunk.insert_leaf(1, [0, 0, 0]);
+        world.insert_chunk(&chunk);
+        assert!(world.take_dirty_chunks(|_| true).is_empty());
+        world.set([1, 2, 3], 2);
+        world.remove([0, 0, 0]);
+        world.set([-1, 40, 0], 2);
+        assert_eq!(vec![[0, 0, 0]], world.take_dirty_chunks(|c| c[0] == 0));
+        assert_eq!(vec![[-1, 2, 0]], world.take_dirty_chunks(|_| true));
+        assert!(world.take_dirty_chunks(|_| true).is_empty());
+        world.undo();
+        assert_eq!(vec![[-1, 2, 0]], world.take_dirty_chunks(|_| true));
+        // chunks that failed to save are taken again
+        world.mark_dirty([[-1, 2, 0]]);
+        assert_eq!(vec![[-1, 2, 0]], world.take_dirty_chunks(|_| true));
+    }
+
+    #[test]
+    fn only_saved_chunks_are_unloaded() {
+        let mut world = World::new();
+        let mut chunk = Octree::new();
+        chunk.insert_leaf(1, [0, 0, 0]);
+        chunk.insert_leaf(1, [16, 0, 0]);
+        world.insert_chunk(&chunk);
+        world.set([17, 0, 0], 2);
+        assert!(world.unload_chunk([0, 0, 0]));
+        assert!(!world.unload_chunk([1, 0, 0]));
+        assert_eq!(None, world.get([0, 0, 0]));
+        assert_eq!(Some(1), world.get([16, 0, 0]));
+        world.take_dirty_chunks(|_| true);
+        assert!(world.unload_chunk([1, 0, 0]));
+        assert_eq!(0, world.tree().count_leaves());
+    }
+
+    #[test]
+    fn shared_changes_include_undo() {
+        let mut world = World::new();
+        world.set([0, 0, 0], 1);
+        assert!(world.take_changes().is_empty());
+        world.share_changes();
+        world.set([1, 0, 0], 2);
+        world.undo();
+        assert_eq!(
+            vec![
+                VoxelChange::Added([1, 0, 0], 2),
+                VoxelChange::Removed([1, 0, 0], 2)
+            ],
+            world.take_changes()
+        );
+    }
+
+    #[test]
+    fn remote_edits_are_not_recorded() {
+        let mut world = World::new();
+        world.share_changes();
+        world.set([0, 0, 0], 1);
+        world.take_changes();
+        world.take_dirty_chunks(|_| true);
+        world.apply_remote(&[([0, 0, 0], None), ([5, 0, 0], Some(3))]);
+        assert_eq!(None, world.get([0, 0, 0]));
+        assert_eq!(Some(3), world.get([5, 0, 0]));
+        assert!(world.take_changes().is_empty());
+        assert!(world.take_dirty_chunks(|_| true).is_empty());
+        // undoing an edit to a voxel removed since leaves it removed
+        assert!(world.undo());
+        assert_eq!(None, world.get([0, 0, 0]));
+        assert_eq!(Some(3), world.get([5, 0, 0]));
+        assert!(!world.undo());
+    }
+}
@@ -0,0 +1,484 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use tracing::{debug_span, error};
+use vecmath::Vector3;
+
+use crate::{
+    aabc::Aabc,
+    io::region::RegionStore,
+    materials::{MaterialId, MaterialRegistry},
+    octree::Octree,
+};
+
+pub mod caves;
+pub mod features;
+
+use self::caves::CaveConfig;
+
+/// Edge length of a generated chunk in voxels.
+pub const CHUNK_SIZE: i32 = 16;
+/// Name saved with worlds, changed whenever a seed would generate different
+/// chunks so saves know their unsaved chunks won't match.
+pub const GENERATOR: &str = "terrain-1";
+// the ground is filled up to between these heights
+const GROUND_HEIGHT: i32 = -4;
+const GROUND_VARIATION: i32 = 3;
+
+/// Materials the generated world is made of.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Palette {
+    pub ground: MaterialId,
+    pub trunk: MaterialId,
+    pub leaves: MaterialId,
+    pub rock: MaterialId,
+}
+
+impl Palette {
+    pub fn from_registry(materials: &MaterialRegistry) -> Self {
+        let id = |name| materials.id(name).unwrap();
+        Palette {
+            ground: id("grass"),
+            trunk: id("dark planks"),
+            leaves: id("leaves"),
+            rock: id("cobblestone"),
+        }
+    }
+}
+
+/// Furthest voxel coordinate generated on any axis. The octree is tested up
+/// to it, and past it the f32 positions of the renderer lose precision.
+pub const MAX_COORDINATE: i32 = 1 << 20;
+
+/// Which chunks may be generated, in chunks. Unset limits leave the world
+/// unbounded up to `MAX_COORDINATE`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WorldBounds {
+    /// Chunks from the origin along x and z.
+    pub radius: Option<i32>,
+    /// Lowest chunk layer.
+    pub bottom: Option<i32>,
+    /// Highest chunk layer.
+    pub top: Option<i32>,
+}
+
+impl WorldBounds {
+    pub fn contains(&self, chunk: ChunkPos) -> bool {
+        let limit = MAX_COORDINATE / CHUNK_SIZE;
+        chunk.iter().all(|c| (-limit..limit).contains(c))
+            && self
+                .radius
+                .iter()
+                .all(|r| chunk[0].abs() <= *r && chunk[2].abs() <= *r)
+            && self.bottom.iter().all(|bottom| chunk[1] >= *bottom)
+            && self.top.iter().all(|top| chunk[1] <= *top)
+    }
+}
+
+/// Position of a chunk in chunks, the chunk covers the voxels from
+/// `CHUNK_SIZE` times it.
+pub type ChunkPos = Vector3<i32>;
+
+pub fn chunk_of(pos: Vector3<f32>) -> ChunkPos {
+    pos.map(|c| (c / CHUNK_SIZE as f32).floor() as i32)
+}
+
+pub fn chunk_containing(pos: Vector3<i32>) -> ChunkPos {
+    pos.map(|c| c.div_euclid(CHUNK_SIZE))
+}
+
+/// The chunks within `radius` chunks of the one containing `pos` on every
+/// axis, nearest first.
+pub fn chunks_around(pos: Vector3<f32>, radius: i32) -> Vec<ChunkPos> {
+    let center = chunk_of(pos);
+    let mut chunks = Vec::new();
+    for x in -radius..=radius {
+        for y in -radius..=radius {
+            for z in -radius..=radius {
+                chunks.push([center[0] + x, center[1] + y, center[2] + z]);
+            }
+        }
+    }
+    chunks.sort_by_key(|chunk| (0..3).map(|i| (chunk[i] - center[i]).pow(2)).sum::<i32>());
+    chunks
+}
+
+// SplitMix64's finalizer, spreads every input bit over the whole output
+pub(crate) fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Hash of a voxel column. The world only depends on the seed and these
+/// hashes, not on a random number generator whose output could change between
+/// versions or platforms, nor on the order chunks are generated in.
+pub fn column_hash(seed: u64, x: i32, z: i32) -> u64 {
+    // offset by the golden ratio so seed 0 doesn't hash 0 to 0
+    let seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    mix(mix(seed ^ x as u32 as u64) ^ ((z as u32 as u64) << 32))
+}
+
+/// Height of the first empty voxel above the ground in a column.
+pub fn ground_height(seed: u64, x: i32, z: i32) -> i32 {
+    GROUND_HEIGHT + (column_hash(seed, x, z) % GROUND_VARIATION as u64) as i32
+}
+
+/// Generates the voxels of a chunk: bumpy ground with caves carved out of it
+/// and structures placed on top, see `caves` and `features`. Returns None if
+/// `cancelled` was set before it finished.
+pub fn generate_chunk(
+    seed: u64,
+    chunk: ChunkPos,
+    palette: &Palette,
+    caves: &CaveConfig,
+    cancelled: &AtomicBool,
+) -> Option<Octree<MaterialId>> {
+    let origin = chunk.map(|c| c * CHUNK_SIZE);
+    let mut tree = Octree::new();
+    for x in 0..CHUNK_SIZE {
+        if cancelled.load(Ordering::Relaxed) {
+            return None;
+        }
+        for z in 0..CHUNK_SIZE {
+            let height = ground_height(seed, origin[0] + x, origin[2] + z);
+            for y in 0..(height - origin[1]).clamp(0, CHUNK_SIZE) {
+                let pos = vecmath::vec3_add(origin, [x, y, z]);
+                if !caves.carves(seed, pos, height - pos[1]) {
+                    tree.insert_leaf(palette.ground, pos);
+                }
+            }
+        }
+    }
+    let region = Aabc::new(origin, CHUNK_SIZE as u32);
+    features::place(seed, palette, region, &mut tree);
+    Some(tree)
+}
+
+struct Job {
+    id: u64,
+    chunk: ChunkPos,
+    cancelled: Arc<AtomicBool>,
+}
+
+/// Generates chunks on a pool of worker threads. Finished chunks are picked
+/// up with `poll` on the thread that requested them, which uploads them, so
+/// generating never holds up the event loop. Chunks saved in the store are
+/// loaded instead of generated.
+pub struct ChunkGenerator {
+    seed: u64,
+    jobs: Option<Sender<Job>>,
+    results: Receiver<(u64, ChunkPos, Octree<MaterialId>)>,
+    // requested chunks not picked up yet, with the id of their job
+    pending: HashMap<ChunkPos, (u64, Arc<AtomicBool>)>,
+    next_id: u64,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ChunkGenerator {
+    /// Starts `threads` workers generating chunks for `seed`.
+    pub fn new(
+        seed: u64,
+        palette: Palette,
+        caves: CaveConfig,
+        store: Option<Arc<RegionStore>>,
+        threads: usize,
+    ) -> Self {
+        let (jobs, job_receiver) = mpsc::channel::<Job>();
+        let (result_sender, results) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let workers = (0..threads.max(1))
+            .map(|_| {
+                let jobs = job_receiver.clone();
+                let results = result_sender.clone();
+                let store = store.clone();
+                thread::spawn(move || loop {
+                    // the lock is released before generating
+                    let job = match jobs.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        Err(_) => return,
+                    };
+                    let _span = debug_span!("chunk", chunk = ?job.chunk).entered();
+                    let saved = store
+                        .as_ref()
+                        .and_then(|store| load_chunk(store, job.chunk));
+                    let tree = saved.or_else(|| {
+                        generate_chunk(seed, job.chunk, &palette, &caves, &job.cancelled)
+                    });
+                    if let Some(tree) = tree {
+                        if results.send((job.id, job.chunk, tree)).is_err() {
+                            return;
+                        }
+                    }
+                })
+            })
+            .collect();
+        ChunkGenerator {
+            seed,
+            jobs: Some(jobs),
+            results,
+            pending: HashMap::new(),
+            next_id: 0,
+            workers,
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn is_pending(&self, chunk: ChunkPos) -> bool {
+        self.pending.contains_key(&chunk)
+    }
+
+    /// Queues a chunk, unless it is already pending.
+    pub fn request(&mut self, chunk: ChunkPos) {
+        if self.is_pending(chunk) {
+            return;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.pending.insert(chunk, (id, cancelled.clone()));
+        self.jobs
+            .as_ref()
+            .unwrap()
+            .send(Job {
+                id,
+                chunk,
+                cancelled,
+            })
+            .unwrap();
+    }
+
+    /// Cancels the pending chunks `keep` returns false for. Workers skip or
+    /// abandon them and they are never returned by `poll`.
+    pub fn retain(&mut self, mut keep: impl FnMut(ChunkPos) -> bool) {
+        self.pending.retain(|chunk, (_, cancelled)| {
+            let kept = keep(*chunk);
+            if !kept {
+                cancelled.store(true, Ordering::Relaxed);
+            }
+            kept
+        });
+    }
+
+    /// Returns the chunks finished since the last call.
+    pub fn poll(&mut self) -> Vec<(ChunkPos, Octree<MaterialId>)> {
+        let mut finished = Vec::new();
+        for (id, chunk, tree) in self.results.try_iter() {
+            // results of cancelled jobs can still arrive
+            if self.pending.get(&chunk).map(|(pending, _)| *pending) == Some(id) {
+                self.pending.remove(&chunk);
+                finished.push((chunk, tree));
+            }
+        }
+        finished
+    }
+}
+
+// a chunk that can't be read is generated again
+fn load_chunk(store: &RegionStore, chunk: ChunkPos) -> Option<Octree<MaterialId>> {
+    match store.read_chunk(chunk) {
+        Ok(voxels) => voxels.map(|voxels| {
+            let mut tree = Octree::new();
+            for (pos, material) in voxels {
+                tree.insert_leaf(material, pos);
+            }
+            tree
+        }),
+        Err(e) => {
+            error!(?chunk, "Could not load chunk: {}", e);
+            None
+        }
+    }
+}
+
+impl Drop for ChunkGenerator {
+    fn drop(&mut self) {
+        self.retain(|_| false);
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        fs, thread,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+
+    pub const PALETTE: Palette = Palette {
+        ground: 1,
+        trunk: 2,
+        leaves: 3,
+        rock: 4,
+    };
+
+    fn poll_until(generator: &mut ChunkGenerator, count: usize) -> Vec<ChunkPos> {
+        let start = Instant::now();
+        let mut chunks = Vec::new();
+        while chunks.len() < count && start.elapsed() < Duration::from_secs(10) {
+            chunks.extend(generator.poll().into_iter().map(|(chunk, _)| chunk));
+            thread::sleep(Duration::from_millis(1));
+        }
+        chunks
+    }
+
+    #[test]
+    fn chunk_is_deterministic_and_inside_bounds() {
+        let cancelled = AtomicBool::new(false);
+        let caves = CaveConfig::default();
+        let a = generate_chunk(7, [1, -1, 0], &PALETTE, &caves, &cancelled).unwrap();
+        let b = generate_chunk(7, [1, -1, 0], &PALETTE, &caves, &cancelled).unwrap();
+        assert_eq!(a.leaves(), b.leaves());
+        assert!(a.count_leaves() > 0);
+        for (pos, material) in a.leaves() {
+            assert_eq!([1, -1, 0], chunk_of(pos.map(|c| c as f32)));
+            assert!((1..=4).contains(&material));
+        }
+    }
+
+    #[test]
+    fn column_hash_is_stable() {
+        // changing these changes every generated world
+        assert_eq!(0x48218226ff3cd4bf, column_hash(0, 0, 0));
+        assert_eq!(0x4801cee63232411a, column_hash(7, -3, 5));
+        assert_eq!(0xa57643a456fc53bd, column_hash(u64::MAX, 100, -100));
+    }
+
+    #[test]
+    fn ground_is_solid_below_and_empty_above() {
+        let cancelled = AtomicBool::new(false);
+        let no_caves = CaveConfig {
+            density: 0.0,
+            ..Default::default()
+        };
+        let below = generate_chunk(7, [0, -2, 0], &PALETTE, &no_caves, &cancelled).unwrap();
+        assert_eq!((CHUNK_SIZE as u64).pow(3), below.count_leaves());
+        let above = generate_chunk(7, [0, 1, 0], &PALETTE, &no_caves, &cancelled).unwrap();
+        assert_eq!(0, above.count_leaves());
+    }
+
+    #[test]
+    fn caves_leave_a_valid_sparse_tree() {
+        let cancelled = AtomicBool::new(false);
+        let caves = CaveConfig::default();
+        let below = generate_chunk(7, [0, -2, 0], &PALETTE, &caves, &cancelled).unwrap();
+        let full = (CHUNK_SIZE as u64).pow(3);
+        assert!(below.count_leaves() > full / 4 && below.count_leaves() < full);
+        below.validate().unwrap();
+    }
+
+    #[test]
+    fn cancelled_chunk_is_not_generated() {
+        assert!(generate_chunk(
+            0,
+            [0; 3],
+            &PALETTE,
+            &CaveConfig::default(),
+            &AtomicBool::new(true)
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn bounds_limit_chunks() {
+        let unbounded = WorldBounds::default();
+        let limit = MAX_COORDINATE / CHUNK_SIZE;
+        assert!(unbounded.contains([limit - 1, -limit, 0]));
+        assert!(!unbounded.contains([limit, 0, 0]));
+        let bounds = WorldBounds {
+            radius: Some(2),
+            bottom: Some(-1),
+            top: Some(0),
+        };
+        assert!(bounds.contains([-2, -1, 2]));
+        assert!(!bounds.contains([3, 0, 0]));
+        assert!(!bounds.contains([0, -2, 0]));
+        assert!(!bounds.contains([0, 1, 0]));
+    }
+
+    #[test]
+    fn chunk_at_max_coordinate() {
+        let limit = MAX_COORDINATE / CHUNK_SIZE;
+        let chunk = [limit - 1, -1, -limit];
+        let tree = generate_chunk(
+            1,
+            chunk,
+            &PALETTE,
+            &CaveConfig::default(),
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        tree.validate().unwrap();
+        for (pos, _) in tree.leaves() {
+            assert_eq!(chunk, chunk_of(pos.map(|c| c as f32)));
+        }
+    }
+
+    #[test]
+    fn chunks_around_nearest_first() {
+        let chunks = chunks_around([20.0, -1.0, 0.0], 1);
+        assert_eq!(27, chunks.len());
+        assert_eq!([1, -1, 0], chunks[0]);
+        assert!(chunks.contains(&[2, 0, 1]));
+    }
+
+    #[test]
+    fn generator_delivers_requested_chunks() {
+        let mut generator = ChunkGenerator::new(3, PALETTE, CaveConfig::default(), None, 2);
+        for x in 0..4 {
+            generator.request([x, 0, 0]);
+        }
+        let mut chunks = poll_until(&mut generator, 4);
+        chunks.sort();
+        assert_eq!(vec![[0, 0, 0], [1, 0, 0], [2, 0, 0], [3, 0, 0]], chunks);
+        assert!(!generator.is_pending([0, 0, 0]));
+    }
+
+    #[test]
+    fn cancelled_chunks_are_not_delivered() {
+        let mut generator = ChunkGenerator::new(3, PALETTE, CaveConfig::default(), None, 1);
+        for x in 0..8 {
+            generator.request([x, 0, 0]);
+        }
+        generator.retain(|chunk| chunk[0] % 2 == 0);
+        let mut chunks = poll_until(&mut generator, 4);
+        chunks.sort();
+        assert_eq!(vec![[0, 0, 0], [2, 0, 0], [4, 0, 0], [6, 0, 0]], chunks);
+        thread::sleep(Duration::from_millis(20));
+        assert!(generator.poll().is_empty());
+    }
+
+    #[test]
+    fn generator_loads_saved_chunks() {
+        let dir = std::env::temp_dir().join(format!("rtvox_saved_{}", std::process::id()));
+        let store = Arc::new(RegionStore::open(dir.clone()).unwrap());
+        store
+            .write_chunks(&[([0, -1, 0], vec![([1, -2, 3], 9)])])
+            .unwrap();
+        let mut generator = ChunkGenerator::new(3, PALETTE, CaveConfig::default(), Some(store), 1);
+        generator.request([0, -1, 0]);
+        let start = Instant::now();
+        let mut finished = Vec::new();
+        while finished.is_empty() {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            finished = generator.poll();
+        }
+        assert_eq!(vec![([1, -2, 3], 9)], finished[0].1.leaves());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,119 @@
+use vecmath::Vector3;
+
+use super::{column_hash, mix};
+
+// keeps the cave noise independent of the other hashes of the seed
+const SALT: u64 = 0xca7e_5a17;
+
+/// Shape of the caves carved out of the ground.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CaveConfig {
+    /// Noise value from 0 to 1 below which the ground is carved out. Higher
+    /// values carve more, 0 turns caves off.
+    pub density: f32,
+    /// Size of the caves in voxels.
+    pub scale: f32,
+    /// Voxels of ground kept solid below the surface.
+    pub crust: i32,
+}
+
+impl Default for CaveConfig {
+    fn default() -> Self {
+        CaveConfig {
+            density: 0.3,
+            scale: 8.0,
+            crust: 3,
+        }
+    }
+}
+
+impl CaveConfig {
+    /// Whether the voxel `depth` below the surface is carved out.
+    pub fn carves(&self, seed: u64, pos: Vector3<i32>, depth: i32) -> bool {
+        if self.density <= 0.0 || depth <= self.crust {
+            return false;
+        }
+        value_noise(seed ^ SALT, pos.map(|c| c as f32 / self.scale)) < self.density
+    }
+}
+
+// random value from 0 to 1 at a lattice point
+fn lattice(seed: u64, p: Vector3<i32>) -> f32 {
+    let hash = mix(column_hash(seed, p[0], p[2]) ^ p[1] as u32 as u64);
+    (hash >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Smoothly interpolated random values from 0 to 1 at the integer points.
+pub fn value_noise(seed: u64, p: Vector3<f32>) -> f32 {
+    let cell = p.map(|c| c.floor() as i32);
+    // smoothstep so there are no creases along the cell faces
+    let t = [0, 1, 2].map(|i| {
+        let f = p[i] - cell[i] as f32;
+        f * f * (3.0 - 2.0 * f)
+    });
+    let mut value = 0.0;
+    for corner in 0..8 {
+        let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+        let weight: f32 = (0..3)
+            .map(|i| if offset[i] == 1 { t[i] } else { 1.0 - t[i] })
+            .product();
+        value += weight * lattice(seed, vecmath::vec3_add(cell, offset));
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noise_matches_lattice_at_integers() {
+        for p in [[0, 0, 0], [3, -2, 7]] {
+            let value = value_noise(1, p.map(|c| c as f32));
+            assert!((value - lattice(1, p)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn noise_stays_in_range() {
+        for i in 0..1000 {
+            let p = [i as f32 * 0.37, i as f32 * -0.11, i as f32 * 0.05];
+            let value = value_noise(9, p);
+            assert!((0.0..=1.0).contains(&value));
+        }
+    }
+
+    #[test]
+    fn crust_and_zero_density_are_never_carved() {
+        let caves = CaveConfig::default();
+        let off = CaveConfig {
+            density: 0.0,
+            ..caves
+        };
+        for x in 0..64 {
+            assert!(!caves.carves(2, [x, -10, 0], caves.crust));
+            assert!(!off.carves(2, [x, -10, 0], 20));
+        }
+    }
+
+    #[test]
+    fn density_carves_more() {
+        let carved = |density| {
+            let caves = CaveConfig {
+                density,
+                ..Default::default()
+            };
+            (0..32768)
+                .filter(|i| caves.carves(4, [i % 32, -(i / 1024), (i / 32) % 32], 10))
+                .count()
+        };
+        let sparse = carved(0.3);
+        let dense = carved(0.5);
+        assert!(
+            sparse > 0 && sparse < dense && dense < 32768,
+            "{} {}",
+            sparse,
+            dense
+        );
+    }
+}
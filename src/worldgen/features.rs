@@ -0,0 +1,205 @@
+use vecmath::Vector3;
+
+use crate::{aabc::Aabc, materials::MaterialId, octree::Octree, prefab::VoxelPrefab};
+
+use super::{column_hash, ground_height, Palette};
+
+// one in this many columns has a structure on it
+const CHANCE: u64 = 160;
+// keeps the placement hashes independent of the ground heights
+const SALT: u64 = 0x5eed_f00d;
+/// Furthest a structure reaches from its column along x and z.
+pub const MAX_REACH: i32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Feature {
+    Tree,
+    Boulder,
+}
+
+/// A structure anchored on a column, standing on the ground.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Placed {
+    pub feature: Feature,
+    /// Picks the size of the structure.
+    pub variant: u32,
+    /// Quarter turns around the y axis.
+    pub rotation: i32,
+}
+
+/// The structure anchored on the column, if any. Only depends on the seed and
+/// the column.
+pub fn feature_at(seed: u64, x: i32, z: i32) -> Option<Placed> {
+    let hash = column_hash(seed ^ SALT, x, z);
+    match hash % CHANCE {
+        0 => Some(Placed {
+            // twice as many trees as boulders
+            feature: match (hash >> 16) % 3 {
+                0 => Feature::Boulder,
+                _ => Feature::Tree,
+            },
+            variant: ((hash >> 24) % 3) as u32,
+            rotation: ((hash >> 32) % 4) as i32,
+        }),
+        _ => None,
+    }
+}
+
+/// A trunk with a ball of leaves on top, or a lumpy rock. The prefab is
+/// centered on its column along x and z.
+pub fn prefab(placed: Placed, palette: &Palette) -> VoxelPrefab {
+    let size = 2 * MAX_REACH + 1;
+    let mut voxels = Vec::new();
+    let height = match placed.feature {
+        Feature::Tree => {
+            let trunk = 4 + placed.variant as i32;
+            for y in 0..trunk {
+                voxels.push(([MAX_REACH, y, MAX_REACH], palette.trunk));
+            }
+            // leaves around the top of the trunk with the corners cut off
+            for x in 0..size {
+                for y in trunk - 2..trunk + 2 {
+                    for z in 0..size {
+                        let d = [x - MAX_REACH, y - trunk, z - MAX_REACH];
+                        let corner = d[0].abs() == MAX_REACH && d[2].abs() == MAX_REACH;
+                        let cap =
+                            y == trunk + 1 && (d[0].abs() == MAX_REACH || d[2].abs() == MAX_REACH);
+                        let inside_trunk = d[0] == 0 && d[2] == 0 && y < trunk;
+                        if !corner && !cap && !inside_trunk {
+                            voxels.push(([x, y, z], palette.leaves));
+                        }
+                    }
+                }
+            }
+            trunk + 2
+        }
+        Feature::Boulder => {
+            let radius = 1.0 + placed.variant as f32 * 0.5;
+            for x in 0..size {
+                for y in 0..size {
+                    for z in 0..size {
+                        let d = [x - MAX_REACH, y, z - MAX_REACH];
+                        // flattened and off center so it doesn't look like a ball
+                        let r2 = (d[0] as f32 + 0.5).powi(2)
+                            + (d[1] as f32 * 1.5).powi(2)
+                            + (d[2] as f32).powi(2);
+                        if r2 <= radius * radius {
+                            voxels.push(([x, y, z], palette.rock));
+                        }
+                    }
+                }
+            }
+            size
+        }
+    };
+    VoxelPrefab::new([0; 3], [size, height, size], voxels).rotated(placed.rotation)
+}
+
+/// Stamps the parts of the structures that fall inside `region` into `tree`,
+/// keeping the voxels already there. Structures anchored outside of the
+/// region are included, so a structure crossing chunks comes out whole when
+/// the chunks are generated separately.
+pub fn place(seed: u64, palette: &Palette, region: Aabc, tree: &mut Octree<MaterialId>) {
+    let size = region.size as i32;
+    let [ox, _, oz] = region.origin;
+    for x in ox - MAX_REACH..ox + size + MAX_REACH {
+        for z in oz - MAX_REACH..oz + size + MAX_REACH {
+            let placed = match feature_at(seed, x, z) {
+                Some(placed) => placed,
+                None => continue,
+            };
+            let corner: Vector3<i32> = [x - MAX_REACH, ground_height(seed, x, z), z - MAX_REACH];
+            for (pos, material) in prefab(placed, palette).voxels {
+                let pos = vecmath::vec3_add(corner, pos);
+                if region.contains(pos) && tree.get_leaf(pos).is_none() {
+                    tree.insert_leaf(material, pos);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::worldgen::tests::PALETTE;
+
+    fn tree(variant: u32) -> Placed {
+        Placed {
+            feature: Feature::Tree,
+            variant,
+            rotation: 0,
+        }
+    }
+
+    #[test]
+    fn tree_has_trunk_under_leaves() {
+        let prefab = prefab(tree(0), &PALETTE);
+        assert!(prefab
+            .voxels
+            .contains(&([MAX_REACH, 0, MAX_REACH], PALETTE.trunk)));
+        assert!(prefab
+            .voxels
+            .iter()
+            .any(|(pos, m)| *m == PALETTE.leaves && pos[1] == prefab.extent[1] - 1));
+        assert!(prefab
+            .voxels
+            .iter()
+            .all(|(pos, _)| pos[1] < prefab.extent[1]));
+    }
+
+    #[test]
+    fn rotation_keeps_center() {
+        let placed = Placed {
+            feature: Feature::Boulder,
+            variant: 2,
+            rotation: 1,
+        };
+        let rotated = prefab(placed, &PALETTE);
+        assert_eq!(
+            prefab(
+                Placed {
+                    rotation: 0,
+                    ..placed
+                },
+                &PALETTE
+            )
+            .voxels
+            .len(),
+            rotated.voxels.len()
+        );
+        assert_eq!(2 * MAX_REACH + 1, rotated.extent[0]);
+    }
+
+    #[test]
+    fn features_are_rare_and_deterministic() {
+        let count = (0..100)
+            .flat_map(|x| (0..100).map(move |z| (x, z)))
+            .filter(|&(x, z)| feature_at(3, x, z).is_some())
+            .count();
+        assert!(count > 10 && count < 200, "{} features", count);
+        assert_eq!(feature_at(3, 12, 34), feature_at(3, 12, 34));
+    }
+
+    #[test]
+    fn structures_match_across_regions() {
+        let seed = 5;
+        let mut whole = Octree::new();
+        place(seed, &PALETTE, Aabc::new([0, -8, 0], 64), &mut whole);
+        assert!(whole.count_leaves() > 0);
+        let mut parts = Octree::new();
+        for x in 0..4 {
+            for y in 0..4 {
+                for z in 0..4 {
+                    let origin = [x * 16, y * 16 - 8, z * 16];
+                    place(seed, &PALETTE, Aabc::new(origin, 16), &mut parts);
+                }
+            }
+        }
+        let mut expected = whole.leaves();
+        let mut actual = parts.leaves();
+        expected.sort();
+        actual.sort();
+        assert_eq!(expected, actual);
+    }
+}
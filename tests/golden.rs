@@ -0,0 +1,105 @@
+//! Renders fixed scenes on the CPU and compares them with the reference
+//! images in tests/golden. Run with UPDATE_GOLDEN=1 to write them again after
+//! an intended change, and look at what changed before committing them.
+
+use std::{f32::consts::PI, path::Path};
+
+use rtvox::{
+    benchmark,
+    camera::Projection,
+    demo_world::DemoWorld,
+    golden::{self, Tolerance},
+    materials::{MaterialId, MaterialRegistry},
+    octree::Octree,
+    softrender::{self, SnapshotConfig},
+    world::World,
+};
+
+const SIZE: [u32; 2] = [96, 64];
+
+fn check(name: &str, pixels: &[u8]) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let failures = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    if let Err(e) = golden::check(&dir, &failures, name, SIZE, pixels, Tolerance::default()) {
+        panic!("{}", e);
+    }
+}
+
+#[test]
+fn benchmark_world() {
+    let config = SnapshotConfig {
+        width: SIZE[0],
+        height: SIZE[1],
+        ..SnapshotConfig::default()
+    };
+    check("benchmark_world", &softrender::snapshot(&config));
+}
+
+#[test]
+fn carved_block() {
+    // carving splits solid nodes, leaving partly filled ones the serialization
+    // has to get right
+    let mut tree: Octree<MaterialId> = Octree::new();
+    for x in -8..8 {
+        for y in -8..8 {
+            for z in -8..8 {
+                tree.insert_leaf(if y < 4 { 1 } else { 2 }, [x, y, z]);
+            }
+        }
+    }
+    for x in -8..8 {
+        for y in -3..1 {
+            for w in 0..3 {
+                tree.remove_leaf([x, y, w]);
+                tree.remove_leaf([w - 4, y + 6, x]);
+            }
+        }
+    }
+    let camera =
+        Projection::Perspective { fov: PI / 3.0 }.camera_info([24.0, 16.0, 30.0], [0.0; 3]);
+    let pixels = softrender::render(&tree, &MaterialRegistry::default(), &camera, SIZE);
+    check("carved_block", &pixels);
+}
+
+#[test]
+fn materials_orthographic() {
+    let materials = MaterialRegistry::default();
+    let mut tree: Octree<MaterialId> = Octree::new();
+    // a staircase with a step of each material
+    for (step, id) in (1..materials.len() as MaterialId).enumerate() {
+        let step = step as i32;
+        for z in 0..4 {
+            for y in 0..=step {
+                tree.insert_leaf(id, [step, y, z]);
+            }
+        }
+    }
+    let camera =
+        Projection::Orthographic { width: 24.0 }.camera_info([-6.0, 20.0, 18.0], [6.0, 4.0, 2.0]);
+    check(
+        "materials_orthographic",
+        &softrender::render(&tree, &materials, &camera, SIZE),
+    );
+}
+
+#[test]
+fn off_center_tile() {
+    let world = World::seeded(3, benchmark::WORLD_EXTENT, 5);
+    let camera =
+        Projection::tile(PI / 2.0, [1, 0], [2, 2]).camera_info([12.0, 6.0, 12.0], [0.0; 3]);
+    check(
+        "off_center_tile",
+        &softrender::render(world.tree(), &MaterialRegistry::default(), &camera, SIZE),
+    );
+}
+
+#[test]
+fn demo_sphere() {
+    let config = SnapshotConfig {
+        width: SIZE[0],
+        height: SIZE[1],
+        demo_world: Some(DemoWorld::Sphere),
+        ..SnapshotConfig::default()
+    };
+    check("demo_sphere", &softrender::snapshot(&config));
+}